@@ -0,0 +1,29 @@
+#![no_main]
+
+use std::net::SocketAddr;
+
+use libfuzzer_sys::fuzz_target;
+use localsend_lib::scanner::{handle_packet, DeviceRegistry};
+use localsend_proto::{
+    dto::{MulticastDto, RegisterDto},
+    Validate,
+};
+
+fuzz_target!(|packet: &[u8]| {
+    let addr: SocketAddr = ([192, 168, 1, 20], 53317).into();
+    if let Ok(dto) = serde_json::from_slice::<MulticastDto>(packet) {
+        if dto.validate().is_ok() {
+            dto.to_device(addr.ip(), addr.port(), false);
+        }
+    }
+    if let Ok(dto) = serde_json::from_slice::<RegisterDto>(packet) {
+        if dto.validate().is_ok() {
+            dto.to_device(addr.ip(), addr.port(), false);
+        }
+    }
+    let mut registry = DeviceRegistry::new(4);
+    handle_packet(&mut registry, packet, addr);
+    // a repeated packet only refreshes the device it announced
+    handle_packet(&mut registry, packet, addr);
+    assert!(registry.len() <= 1);
+});
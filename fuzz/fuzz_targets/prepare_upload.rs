@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use localsend_proto::{dto::PrepareUploadRequestDto, Validate};
+
+fuzz_target!(|body: &[u8]| {
+    let Ok(request) = serde_json::from_slice::<PrepareUploadRequestDto>(body) else {
+        return;
+    };
+    if request.validate().is_ok() {
+        for (id, file) in &request.files {
+            assert_eq!(id, &file.id);
+        }
+    }
+});
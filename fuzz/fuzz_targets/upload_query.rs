@@ -0,0 +1,27 @@
+#![no_main]
+
+use std::path::{Component, Path};
+
+use libfuzzer_sys::fuzz_target;
+use localsend_lib::{
+    server::{StrictQuery, UploadQuery},
+    util::fs::{normalize_file_name, NameRules},
+};
+
+fuzz_target!(|input: &[u8]| {
+    let Ok(input) = std::str::from_utf8(input) else {
+        return;
+    };
+    if let Ok(StrictQuery(params)) = StrictQuery::parse(input) {
+        UploadQuery::from_params(&params, false).ok();
+        UploadQuery::from_params(&params, true).ok();
+    }
+    // whatever the sender names a file, it is saved below the destination
+    for rules in [NameRules::Unix, NameRules::Windows] {
+        let name = normalize_file_name(input, rules, '_');
+        assert!(!name.is_empty());
+        assert!(Path::new(&name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_))));
+    }
+});
@@ -0,0 +1,82 @@
+/*
+ * Starts a device, optionally sends files to another one and stops again.
+ *
+ *   cc examples/lifecycle.c -Iinclude ../target/debug/liblocalsend_ffi.a \
+ *       -lssl -lcrypto -lpthread -ldl -lm -o lifecycle
+ *   ./lifecycle PORT HTTP_PORT [FINGERPRINT PATH...]
+ */
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+#include "localsend.h"
+
+static int fail(const char *what) {
+    char *error = localsend_last_error();
+    fprintf(stderr, "%s: %s\n", what, error ? error : "unknown error");
+    localsend_string_free(error);
+    return 1;
+}
+
+/* Prints the events of the send until it ends, returns whether it finished. */
+static int wait_for_send(LocalSendContext *ctx) {
+    for (;;) {
+        char *event = localsend_poll_event(ctx);
+        if (!event) {
+            usleep(100 * 1000);
+            continue;
+        }
+        printf("%s\n", event);
+        int finished = strstr(event, "\"type\":\"sendFinished\"") != NULL;
+        int ended = finished || strstr(event, "\"type\":\"sessionFailed\"") != NULL ||
+                    strstr(event, "\"type\":\"sessionCancelled\"") != NULL;
+        localsend_string_free(event);
+        if (ended) {
+            return finished;
+        }
+    }
+}
+
+int main(int argc, char **argv) {
+    if (argc < 3 || argc == 4) {
+        fprintf(stderr, "usage: %s PORT HTTP_PORT [FINGERPRINT PATH...]\n", argv[0]);
+        return 2;
+    }
+    int status = 0;
+    LocalSendContext *ctx =
+        localsend_context_new("lifecycle", atoi(argv[1]), atoi(argv[2]), NULL, false);
+    if (!ctx) {
+        return fail("context");
+    }
+    if (localsend_server_start(ctx) != 0) {
+        status = fail("start");
+        goto out;
+    }
+
+    if (argc > 4) {
+        char *devices = localsend_scan(ctx);
+        if (!devices) {
+            status = fail("scan");
+            goto out;
+        }
+        printf("devices: %s\n", devices);
+        localsend_string_free(devices);
+
+        char *session_id =
+            localsend_send(ctx, argv[3], (const char *const *)&argv[4], (size_t)(argc - 4));
+        if (!session_id) {
+            status = fail("send");
+            goto out;
+        }
+        printf("sending: %s\n", session_id);
+        localsend_string_free(session_id);
+        status = !wait_for_send(ctx);
+    }
+
+    if (localsend_server_stop(ctx) != 0) {
+        status = fail("stop");
+    }
+out:
+    localsend_context_free(ctx);
+    return status;
+}
@@ -0,0 +1,97 @@
+#ifndef LOCALSEND_H
+#define LOCALSEND_H
+
+/* Generated by cbindgen from localsend-ffi, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A local device with its server, scanner and the events of its sessions.
+typedef struct LocalSendContext LocalSendContext;
+
+// Creates a context announcing `alias` on the multicast `port` and serving on
+// `http_port`, returns null on failure.
+//
+// A null `alias` uses the host name, a null `destination` the working directory.
+// Offers are declined unless `quick_save` is set, then every file is saved.
+//
+// # Safety
+//
+// `alias` and `destination` must be null or nul-terminated strings.
+struct LocalSendContext *localsend_context_new(const char *alias,
+                                               uint16_t port,
+                                               uint16_t http_port,
+                                               const char *destination,
+                                               bool quick_save);
+
+// Stops the server and releases the context, null is ignored.
+//
+// # Safety
+//
+// `ctx` must be null or returned by [`localsend_context_new`] and not freed before.
+void localsend_context_free(struct LocalSendContext *ctx);
+
+// Starts serving and announcing the device, returns 0 or -1.
+//
+// # Safety
+//
+// `ctx` must be a live context.
+int localsend_server_start(const struct LocalSendContext *ctx);
+
+// Stops the server, cancelling running receives, returns 0 or -1.
+//
+// # Safety
+//
+// `ctx` must be a live context.
+int localsend_server_stop(const struct LocalSendContext *ctx);
+
+// Scans for devices, blocking until the scan settles, and returns them as a
+// JSON array, null on failure.
+//
+// The devices found are the targets of [`localsend_send`] until the next scan.
+//
+// # Safety
+//
+// `ctx` must be a live context.
+char *localsend_scan(const struct LocalSendContext *ctx);
+
+// Starts sending `count` files or directories of `paths` to the device of
+// `fingerprint` found by the last scan.
+//
+// Returns the id of the send session at once, its progress and end arrive as
+// events. Null on failure.
+//
+// # Safety
+//
+// `ctx` must be a live context, `fingerprint` a nul-terminated string and
+// `paths` point to `count` nul-terminated strings.
+char *localsend_send(const struct LocalSendContext *ctx,
+                     const char *fingerprint,
+                     const char *const *paths,
+                     size_t count);
+
+// Returns the next session event as JSON, null when there is none.
+//
+// The events are those of the server's event bus, e.g.
+// `{"type": "fileFinished", "sessionId": "…", "fileId": "…", "status": "finished"}`.
+// Events not polled in time are dropped, the oldest first.
+//
+// # Safety
+//
+// `ctx` must be a live context.
+char *localsend_poll_event(const struct LocalSendContext *ctx);
+
+// Returns the message of the last failure on this thread, null if nothing failed.
+char *localsend_last_error(void);
+
+// Releases a string returned by this library, null is ignored.
+//
+// # Safety
+//
+// `s` must be null or returned by this library and not freed before.
+void localsend_string_free(char *s);
+
+#endif /* LOCALSEND_H */
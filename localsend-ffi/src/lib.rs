@@ -0,0 +1,520 @@
+//! C bindings of localsend-rs, declared in `include/localsend.h`.
+//!
+//! A [`LocalSendContext`] owns its runtime, the api server and the multicast
+//! scanner. Strings returned by the functions are owned by the caller and
+//! released with [`localsend_string_free`]. A failing function returns null or
+//! `-1` and keeps a message for [`localsend_last_error`] on the calling thread,
+//! panics are caught and reported the same way.
+//!
+//! Build with `--profile release-ffi` for release libraries, the release
+//! profile aborts on panics.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    net::{IpAddr, Ipv4Addr},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use localsend_lib::{
+    scanner::MulticastDeviceScanner,
+    send::{SendSession, SendingFiles, Target},
+    server::{
+        start_api_server, ClientMessage, MutexServerState, ServerHandle, ServerMessage,
+        ServerState, SessionEvent,
+    },
+    util::device,
+};
+use localsend_proto::{Device, DeviceType, DEFAULT_MULTICAST, PROTOCOL_VERSION_2};
+use tokio::{
+    runtime::Runtime,
+    sync::{broadcast, mpsc},
+};
+use tokio_util::sync::CancellationToken;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+type FfiResult<T> = Result<T, String>;
+
+/// A local device with its server, scanner and the events of its sessions.
+pub struct LocalSendContext {
+    runtime: Runtime,
+    device: Mutex<Device>,
+    multicast_port: u16,
+    state: MutexServerState,
+    events: Mutex<broadcast::Receiver<SessionEvent>>,
+    server: Mutex<Option<(ServerHandle, CancellationToken)>>,
+    scanner: Mutex<Option<Arc<MulticastDeviceScanner>>>,
+    /// Found by the last scan, the targets of [`localsend_send`]
+    devices: Mutex<Vec<Device>>,
+    cancel: CancellationToken,
+}
+
+impl LocalSendContext {
+    fn new(
+        alias: Option<&str>,
+        port: u16,
+        http_port: u16,
+        destination: Option<&str>,
+        quick_save: bool,
+    ) -> FfiResult<Self> {
+        if port == 0 || http_port == 0 {
+            return Err("Ports must not be 0".to_owned());
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Failed to start the runtime: {}", e))?;
+
+        let ip = device::local_addr()
+            .map(|addr| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let device = Device {
+            ip: ip.to_string(),
+            alias: alias.map(str::to_owned).unwrap_or_else(device::alias),
+            fingerprint: device::fingerprint(),
+            version: PROTOCOL_VERSION_2.to_string(),
+            device_model: Some(device::device_model()),
+            device_type: DeviceType::Desktop,
+            download: false,
+            https: false,
+            port: http_port,
+        };
+
+        let (server_tx, server_rx) = mpsc::channel(1);
+        let (client_tx, client_rx) = mpsc::channel(1);
+        let mut state = ServerState::new(server_tx, client_rx);
+        state.settings.quick_save = quick_save;
+        if let Some(destination) = destination {
+            state.settings.destination = PathBuf::from(destination);
+        }
+        let events = state.events.subscribe();
+        runtime.spawn(answer_offers(server_rx, client_tx));
+
+        Ok(Self {
+            runtime,
+            device: Mutex::new(device),
+            multicast_port: port,
+            state: Arc::new(tokio::sync::Mutex::new(state)),
+            events: Mutex::new(events),
+            server: Mutex::default(),
+            scanner: Mutex::default(),
+            devices: Mutex::default(),
+            cancel: CancellationToken::new(),
+        })
+    }
+
+    /// The multicast scanner, bound on first use.
+    fn scanner(&self) -> FfiResult<Arc<MulticastDeviceScanner>> {
+        let mut scanner = self.scanner.lock().unwrap();
+        if let Some(scanner) = scanner.as_ref() {
+            return Ok(scanner.clone());
+        }
+        let device = self.device.lock().unwrap().clone();
+        let created = self
+            .runtime
+            .block_on(MulticastDeviceScanner::new(
+                &device,
+                DEFAULT_MULTICAST.parse().unwrap(),
+                self.multicast_port,
+                self.multicast_port,
+            ))
+            .map_err(|e| format!("Failed to bind port {}: {}", self.multicast_port, e))?;
+        Ok(scanner.insert(Arc::new(created)).clone())
+    }
+
+    fn start_server(&self) -> FfiResult<()> {
+        let mut server = self.server.lock().unwrap();
+        if server.is_some() {
+            return Err("The server is already running".to_owned());
+        }
+        let port = self.device.lock().unwrap().port;
+        let cancel = self.cancel.child_token();
+        let handle = self.runtime.block_on(async {
+            let handle = start_api_server(port, self.state.clone(), &cancel).await?;
+            handle.ready().await;
+            Ok::<_, localsend_lib::server::ServerError>(handle)
+        });
+        let handle = handle.map_err(|e| e.to_string())?;
+
+        // announcing is best effort, the server is reachable by ip either way
+        match self.scanner() {
+            Ok(scanner) => {
+                let cancel = cancel.clone();
+                self.runtime.spawn(async move {
+                    loop {
+                        for ms in [100, 500, 2000] {
+                            scanner.send_announcement().await;
+                            tokio::select! {
+                                _ = tokio::time::sleep(Duration::from_millis(ms)) => {}
+                                _ = cancel.cancelled() => return,
+                            }
+                        }
+                    }
+                });
+            }
+            Err(e) => log::warn!("Not announcing the server: {}", e),
+        }
+        *server = Some((handle, cancel));
+        Ok(())
+    }
+
+    fn stop_server(&self) -> FfiResult<()> {
+        let Some((handle, cancel)) = self.server.lock().unwrap().take() else {
+            return Ok(());
+        };
+        cancel.cancel();
+        self.runtime
+            .block_on(handle.wait())
+            .map_err(|e| format!("Failed to stop the server: {}", e))
+    }
+
+    fn scan(&self) -> FfiResult<String> {
+        let scanner = self.scanner()?;
+        let devices = self
+            .runtime
+            .block_on(scanner.scan(&self.cancel))
+            .map_err(|e| format!("Failed to scan: {}", e))?;
+        let json = serde_json::to_string(&devices).map_err(|e| e.to_string())?;
+        *self.devices.lock().unwrap() = devices;
+        Ok(json)
+    }
+
+    fn send(&self, fingerprint: &str, paths: &[&str]) -> FfiResult<String> {
+        let target = Target::Fingerprint(fingerprint.to_owned())
+            .resolve(&self.devices.lock().unwrap())
+            .map_err(|e| e.to_string())?;
+        let mut files = SendingFiles::default();
+        for path in paths {
+            let added = if Path::new(path).is_dir() {
+                files.add_dir(path)
+            } else {
+                files.add_file(path, None)
+            };
+            added.map_err(|e| format!("Failed to add {}: {}", path, e))?;
+        }
+        if files.is_empty() {
+            return Err("No files to send".to_owned());
+        }
+
+        let device = self.device.lock().unwrap().clone();
+        let session = SendSession::new(&device, target, &files);
+        let session_id = session.session_id.clone();
+        let state = self.state.clone();
+        let cancel = self.cancel.clone();
+        self.runtime.spawn(async move {
+            // progress is published as events, the stream is not taken
+            let session_id = session.session_id.clone();
+            if let Err(e) = session.upload(Some(state.clone()), &cancel).await {
+                state.lock().await.events.emit(SessionEvent::SessionFailed {
+                    session_id,
+                    reason: e.to_string(),
+                });
+            }
+        });
+        Ok(session_id)
+    }
+
+    fn poll_event(&self) -> Option<String> {
+        let mut events = self.events.lock().unwrap();
+        loop {
+            match events.try_recv() {
+                Ok(event) => return serde_json::to_string(&event).ok(),
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    log::warn!("Dropped {} events nobody polled", skipped);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for LocalSendContext {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        if let Err(e) = self.stop_server() {
+            log::warn!("{}", e);
+        }
+    }
+}
+
+/// Declines every offer that needs a selection, only `quick_save` receives files.
+async fn answer_offers(
+    mut server_rx: mpsc::Receiver<ServerMessage>,
+    client_tx: mpsc::Sender<ClientMessage>,
+) {
+    while let Some(message) = server_rx.recv().await {
+        if let ServerMessage::SelectedFiles(_) = message {
+            client_tx.send(ClientMessage::Declined).await.ok();
+        }
+    }
+}
+
+fn set_last_error(message: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    format!("Panicked: {}", message)
+}
+
+/// Runs `f` at the boundary, turning errors and panics into `fallback`.
+fn guard<T>(fallback: T, f: impl FnOnce() -> FfiResult<T>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e);
+            fallback
+        }
+        Err(panic) => {
+            set_last_error(panic_message(panic));
+            fallback
+        }
+    }
+}
+
+fn status(result: FfiResult<()>) -> FfiResult<c_int> {
+    result.map(|_| 0)
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    // JSON and messages never contain a nul, it is dropped if one slips in
+    CString::new(s.replace('\0', ""))
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+unsafe fn optional_str<'a>(ptr: *const c_char, name: &str) -> FfiResult<Option<&'a str>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map(Some)
+        .map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+unsafe fn required_str<'a>(ptr: *const c_char, name: &str) -> FfiResult<&'a str> {
+    optional_str(ptr, name)?.ok_or_else(|| format!("{} is null", name))
+}
+
+unsafe fn context<'a>(ctx: *const LocalSendContext) -> FfiResult<&'a LocalSendContext> {
+    ctx.as_ref().ok_or_else(|| "The context is null".to_owned())
+}
+
+/// Creates a context announcing `alias` on the multicast `port` and serving on
+/// `http_port`, returns null on failure.
+///
+/// A null `alias` uses the host name, a null `destination` the working directory.
+/// Offers are declined unless `quick_save` is set, then every file is saved.
+///
+/// # Safety
+///
+/// `alias` and `destination` must be null or nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_context_new(
+    alias: *const c_char,
+    port: u16,
+    http_port: u16,
+    destination: *const c_char,
+    quick_save: bool,
+) -> *mut LocalSendContext {
+    guard(ptr::null_mut(), || {
+        let alias = optional_str(alias, "alias")?;
+        let destination = optional_str(destination, "destination")?;
+        let ctx = LocalSendContext::new(alias, port, http_port, destination, quick_save)?;
+        Ok(Box::into_raw(Box::new(ctx)))
+    })
+}
+
+/// Stops the server and releases the context, null is ignored.
+///
+/// # Safety
+///
+/// `ctx` must be null or returned by [`localsend_context_new`] and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_context_free(ctx: *mut LocalSendContext) {
+    guard((), || {
+        if !ctx.is_null() {
+            drop(Box::from_raw(ctx));
+        }
+        Ok(())
+    })
+}
+
+/// Starts serving and announcing the device, returns 0 or -1.
+///
+/// # Safety
+///
+/// `ctx` must be a live context.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_server_start(ctx: *const LocalSendContext) -> c_int {
+    guard(-1, || status(context(ctx)?.start_server()))
+}
+
+/// Stops the server, cancelling running receives, returns 0 or -1.
+///
+/// # Safety
+///
+/// `ctx` must be a live context.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_server_stop(ctx: *const LocalSendContext) -> c_int {
+    guard(-1, || status(context(ctx)?.stop_server()))
+}
+
+/// Scans for devices, blocking until the scan settles, and returns them as a
+/// JSON array, null on failure.
+///
+/// The devices found are the targets of [`localsend_send`] until the next scan.
+///
+/// # Safety
+///
+/// `ctx` must be a live context.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_scan(ctx: *const LocalSendContext) -> *mut c_char {
+    guard(ptr::null_mut(), || context(ctx)?.scan().map(into_c_string))
+}
+
+/// Starts sending `count` files or directories of `paths` to the device of
+/// `fingerprint` found by the last scan.
+///
+/// Returns the id of the send session at once, its progress and end arrive as
+/// events. Null on failure.
+///
+/// # Safety
+///
+/// `ctx` must be a live context, `fingerprint` a nul-terminated string and
+/// `paths` point to `count` nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_send(
+    ctx: *const LocalSendContext,
+    fingerprint: *const c_char,
+    paths: *const *const c_char,
+    count: usize,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let ctx = context(ctx)?;
+        let fingerprint = required_str(fingerprint, "fingerprint")?;
+        if paths.is_null() && count > 0 {
+            return Err("paths is null".to_owned());
+        }
+        let paths = (0..count)
+            .map(|i| required_str(*paths.add(i), "path"))
+            .collect::<FfiResult<Vec<_>>>()?;
+        ctx.send(fingerprint, &paths).map(into_c_string)
+    })
+}
+
+/// Returns the next session event as JSON, null when there is none.
+///
+/// The events are those of the server's event bus, e.g.
+/// `{"type": "fileFinished", "sessionId": "…", "fileId": "…", "status": "finished"}`.
+/// Events not polled in time are dropped, the oldest first.
+///
+/// # Safety
+///
+/// `ctx` must be a live context.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_poll_event(ctx: *const LocalSendContext) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        Ok(context(ctx)?
+            .poll_event()
+            .map(into_c_string)
+            .unwrap_or(ptr::null_mut()))
+    })
+}
+
+/// Returns the message of the last failure on this thread, null if nothing failed.
+#[no_mangle]
+pub extern "C" fn localsend_last_error() -> *mut c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .clone()
+            .map(into_c_string)
+            .unwrap_or(ptr::null_mut())
+    })
+}
+
+/// Releases a string returned by this library, null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or returned by this library and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn localsend_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{CStr, CString},
+        net::{TcpListener, UdpSocket},
+        ptr,
+    };
+
+    use super::{
+        localsend_context_free, localsend_context_new, localsend_last_error, localsend_poll_event,
+        localsend_send, localsend_server_start, localsend_server_stop, localsend_string_free,
+    };
+
+    unsafe fn last_error() -> String {
+        let error = localsend_last_error();
+        assert!(!error.is_null());
+        let message = CStr::from_ptr(error).to_str().unwrap().to_owned();
+        localsend_string_free(error);
+        message
+    }
+
+    #[test]
+    fn test_context_lifecycle() {
+        let port = UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let http_port = TcpListener::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let alias = CString::new("ffi").unwrap();
+        let destination = CString::new(std::env::temp_dir().to_str().unwrap()).unwrap();
+        unsafe {
+            assert!(localsend_context_new(alias.as_ptr(), 0, 0, ptr::null(), false).is_null());
+            assert_eq!(last_error(), "Ports must not be 0");
+
+            let ctx =
+                localsend_context_new(alias.as_ptr(), port, http_port, destination.as_ptr(), false);
+            assert!(!ctx.is_null());
+            assert_eq!(localsend_server_start(ctx), 0);
+            assert_eq!(localsend_server_start(ctx), -1);
+            assert_eq!(last_error(), "The server is already running");
+            assert!(localsend_poll_event(ctx).is_null());
+
+            // nothing was scanned, so no device matches
+            let fingerprint = CString::new("unknown").unwrap();
+            assert!(localsend_send(ctx, fingerprint.as_ptr(), ptr::null(), 0).is_null());
+            assert!(last_error().contains("unknown"));
+
+            assert_eq!(localsend_server_stop(ctx), 0);
+            assert_eq!(localsend_server_stop(ctx), 0);
+            localsend_context_free(ctx);
+            localsend_context_free(ptr::null_mut());
+        }
+    }
+}
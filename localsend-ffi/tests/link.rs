@@ -0,0 +1,56 @@
+//! Links the example against the static library, like a C program would.
+#![cfg(target_os = "linux")]
+
+use std::{
+    net::{TcpListener, UdpSocket},
+    path::PathBuf,
+    process::Command,
+};
+
+fn static_lib() -> PathBuf {
+    // the library is built next to the test binaries, in target/<profile>/deps
+    let exe = std::env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    [deps, deps.parent().unwrap()]
+        .iter()
+        .map(|dir| dir.join("liblocalsend_ffi.a"))
+        .find(|path| path.exists())
+        .expect("liblocalsend_ffi.a is built with the tests")
+}
+
+#[test]
+fn test_link_lifecycle() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let out = std::env::temp_dir().join(format!("localsend-lifecycle-{}", std::process::id()));
+    let compiled = Command::new("cc")
+        .arg(crate_dir.join("examples/lifecycle.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg(static_lib())
+        .args(["-lssl", "-lcrypto", "-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&out)
+        .status()
+        .expect("cc is installed");
+    assert!(compiled.success());
+
+    let port = UdpSocket::bind("0.0.0.0:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let http_port = TcpListener::bind("0.0.0.0:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let output = Command::new(&out)
+        .args([port.to_string(), http_port.to_string()])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&out).ok();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
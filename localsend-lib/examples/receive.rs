@@ -0,0 +1,67 @@
+//! Receives everything offered into a directory, announcing itself so that it shows up in
+//! the device lists of others.
+//!
+//! ```sh
+//! cargo run -p localsend-lib --example receive -- [DESTINATION]
+//! ```
+//!
+//! Files are saved to the current directory unless another destination is given.
+
+use std::sync::Arc;
+
+use localsend_lib::{
+    scanner::MulticastDeviceScanner,
+    server::{start_api_server, ServerMessage, ServerState},
+    util::device,
+};
+use localsend_proto::{
+    Device, DeviceType, DEFAULT_HTTP_PORT, DEFAULT_MULTICAST, DEFAULT_PORT, PROTOCOL_VERSION_2,
+};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::main]
+async fn main() -> localsend_lib::Result<()> {
+    let destination = std::env::args().nth(1).unwrap_or_else(|| ".".to_owned());
+
+    let (server_tx, mut server_rx) = tokio::sync::mpsc::channel(4);
+    let (_client_tx, client_rx) = tokio::sync::mpsc::channel(1);
+    let mut state = ServerState::new(server_tx, client_rx);
+    state.settings.quick_save = true;
+    state.settings.destination = destination.into();
+    let state = Arc::new(tokio::sync::Mutex::new(state));
+    let cancel = CancellationToken::new();
+    let server = start_api_server(DEFAULT_HTTP_PORT, state, &cancel).await?;
+    server.ready().await;
+
+    let local = Device {
+        ip: device::local_addr()?.ip().to_string(),
+        version: PROTOCOL_VERSION_2.to_owned(),
+        port: server.local_addr().port(),
+        https: false,
+        fingerprint: device::fingerprint(),
+        alias: device::alias(),
+        device_model: Some(device::device_model()),
+        device_type: DeviceType::Headless,
+        download: false,
+    };
+    let multiaddr = DEFAULT_MULTICAST.parse().expect("multicast address");
+    let scanner =
+        MulticastDeviceScanner::new(&local, multiaddr, DEFAULT_PORT, DEFAULT_PORT).await?;
+    // announces now and then and answers the scans of others while the events are received
+    let _events = Arc::new(scanner).subscribe();
+    println!("Receiving as {} on {}", local.alias, server.local_addr());
+
+    while let Some(message) = server_rx.recv().await {
+        match message {
+            ServerMessage::TextReceived(text) => println!("Text: {}", text),
+            ServerMessage::SessionFinished(report) => println!(
+                "{} sent {} files to {:?}",
+                report.sender,
+                report.files.len(),
+                report.destination
+            ),
+            _ => {}
+        }
+    }
+    Ok(())
+}
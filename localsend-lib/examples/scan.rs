@@ -0,0 +1,42 @@
+//! Lists the devices answering a multicast scan, the way senders find receivers.
+//!
+//! ```sh
+//! cargo run -p localsend-lib --example scan
+//! ```
+
+use localsend_lib::{scanner::MulticastDeviceScanner, util::device};
+use localsend_proto::{Device, DeviceType, DEFAULT_MULTICAST, DEFAULT_PORT, PROTOCOL_VERSION_2};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::main]
+async fn main() -> localsend_lib::Result<()> {
+    let local = Device {
+        ip: device::local_addr()?.ip().to_string(),
+        version: PROTOCOL_VERSION_2.to_owned(),
+        port: DEFAULT_PORT,
+        https: false,
+        fingerprint: device::fingerprint(),
+        alias: device::alias(),
+        device_model: Some(device::device_model()),
+        device_type: DeviceType::Headless,
+        download: false,
+    };
+    let multiaddr = DEFAULT_MULTICAST.parse().expect("multicast address");
+    let scanner =
+        MulticastDeviceScanner::new(&local, multiaddr, DEFAULT_PORT, DEFAULT_PORT).await?;
+
+    let devices = scanner.scan(&CancellationToken::new()).await?;
+    for device in &devices {
+        println!(
+            "{:<24} {:<10} {}:{} v{} {}",
+            device.alias,
+            device.device_type,
+            device.ip,
+            device.port,
+            device.version,
+            device.fingerprint
+        );
+    }
+    println!("{} devices", devices.len());
+    Ok(())
+}
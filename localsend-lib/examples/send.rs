@@ -0,0 +1,51 @@
+//! Sends files to a device at a known address, without scanning for it.
+//!
+//! ```sh
+//! cargo run -p localsend-lib --example send -- 192.168.1.23 53317 FILE...
+//! ```
+
+use localsend_lib::{send::SendSession, send::SendingFiles, util::device};
+use localsend_proto::{Device, DeviceType, DEFAULT_HTTP_PORT, PROTOCOL_VERSION_2};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::main]
+async fn main() -> localsend_lib::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(ip), Some(port)) = (args.next(), args.next()) else {
+        eprintln!("usage: send IP PORT FILE...");
+        std::process::exit(2);
+    };
+    let port = port.parse().expect("port");
+
+    let mut files = SendingFiles::default();
+    for path in args {
+        files.add_file(&path, None)?;
+    }
+    let local = Device {
+        ip: device::local_addr()?.ip().to_string(),
+        version: PROTOCOL_VERSION_2.to_owned(),
+        port: DEFAULT_HTTP_PORT,
+        https: false,
+        fingerprint: device::fingerprint(),
+        alias: device::alias(),
+        device_model: Some(device::device_model()),
+        device_type: DeviceType::Headless,
+        download: false,
+    };
+    // only what a prepare-upload request needs, a scan would fill in the rest
+    let target = Device {
+        ip,
+        port,
+        alias: "target".to_owned(),
+        fingerprint: String::new(),
+        ..local.clone()
+    };
+
+    let sent = SendSession::new(&local, target, &files)
+        .upload(None, &CancellationToken::new())
+        .await?;
+    for file in sent.values() {
+        println!("{:?} {}", file.status, file.file.file_name);
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Measures serving a file over loopback with the default read size of
+//! `ReaderStream` and with `SERVE_BUFFER_SIZE`.
+//!
+//! ```sh
+//! cargo run --release -p localsend-lib --example serve_bench -- [SIZE_MB]
+//! ```
+//!
+//! The file is 2048 MB unless another size is given.
+
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{HeaderMap, Method},
+    routing::get,
+    Router,
+};
+use futures_util::StreamExt;
+use localsend_lib::server::{serve_file_buffered, SERVE_BUFFER_SIZE};
+use tokio::io::AsyncWriteExt;
+
+/// What `ReaderStream::new` reads at once, the size served files used before.
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+#[tokio::main]
+async fn main() {
+    let size_mb: u64 = match std::env::args().nth(1) {
+        Some(arg) => arg.parse().expect("size in MB"),
+        None => 2048,
+    };
+    let path = std::env::temp_dir().join(format!("serve-bench-{}.bin", uuid::Uuid::new_v4()));
+    write_file(&path, size_mb << 20).await;
+    let path = Arc::new(path);
+
+    for buffer_size in [DEFAULT_BUFFER_SIZE, SERVE_BUFFER_SIZE] {
+        let (elapsed, cpu) = serve_once(path.clone(), buffer_size).await;
+        let throughput = size_mb as f64 / elapsed.as_secs_f64();
+        match cpu {
+            Some(cpu) => println!(
+                "buffer {:>7} B: {:>8.1} MB/s, {:.2}s cpu for {:.2}s",
+                buffer_size,
+                throughput,
+                cpu.as_secs_f64(),
+                elapsed.as_secs_f64()
+            ),
+            None => println!("buffer {:>7} B: {:>8.1} MB/s", buffer_size, throughput),
+        }
+    }
+    tokio::fs::remove_file(path.as_ref()).await.ok();
+}
+
+async fn write_file(path: &PathBuf, size: u64) {
+    let mut file = tokio::fs::File::create(path).await.unwrap();
+    let chunk: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+    let mut written = 0;
+    while written < size {
+        let len = chunk.len().min((size - written) as usize);
+        file.write_all(&chunk[..len]).await.unwrap();
+        written += len as u64;
+    }
+    file.flush().await.unwrap();
+}
+
+/// Downloads the file once from a server reading `buffer_size` bytes at a time.
+async fn serve_once(path: Arc<PathBuf>, buffer_size: usize) -> (Duration, Option<Duration>) {
+    let router = Router::new().route(
+        "/file",
+        get(move |method: Method, headers: HeaderMap| async move {
+            serve_file_buffered(&path, &method, &headers, buffer_size)
+                .await
+                .unwrap()
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/file", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let cpu_before = cpu_time();
+    let started = Instant::now();
+    let response = reqwest::get(&url).await.unwrap();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        chunk.unwrap();
+    }
+    let elapsed = started.elapsed();
+    let cpu = cpu_time()
+        .zip(cpu_before)
+        .map(|(after, before)| after - before);
+
+    server.abort();
+    (elapsed, cpu)
+}
+
+/// CPU time of this process, client and server together.
+#[cfg(target_os = "linux")]
+fn cpu_time() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the fields after the parenthesized command name, utime and stime are 14 and 15
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    // USER_HZ, 100 on every common Linux configuration
+    Some(Duration::from_millis(ticks * 10))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
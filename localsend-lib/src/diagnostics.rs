@@ -0,0 +1,740 @@
+//! Checks for the network problems behind most "nothing works" reports.
+
+use std::{
+    fmt, io,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::RangeInclusive,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::send::CLIENT;
+
+/// How long a single network probe may take.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+const PROXY_VARS: [&str; 3] = ["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but likely causes problems
+    Warning,
+    Failed,
+}
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn ok(name: &str, detail: impl ToString) -> Self {
+        Self {
+            name: name.to_owned(),
+            status: CheckStatus::Ok,
+            detail: detail.to_string(),
+            hint: None,
+        }
+    }
+
+    fn warning(name: &str, detail: impl ToString, hint: impl ToString) -> Self {
+        Self {
+            status: CheckStatus::Warning,
+            hint: Some(hint.to_string()),
+            ..Self::ok(name, detail)
+        }
+    }
+
+    fn failed(name: &str, detail: impl ToString, hint: impl ToString) -> Self {
+        Self {
+            status: CheckStatus::Failed,
+            hint: Some(hint.to_string()),
+            ..Self::ok(name, detail)
+        }
+    }
+}
+
+/// What to check, the ports of this device are expected to be free.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsOptions {
+    pub multiaddr: Ipv4Addr,
+    pub port: u16,
+    pub http_port: u16,
+    /// Address of this device in the local network
+    pub local_ip: IpAddr,
+    /// Http address of a device that can not be reached
+    pub peer: Option<SocketAddr>,
+}
+
+/// Runs all checks in order.
+pub async fn run_diagnostics(options: &DiagnosticsOptions) -> Vec<CheckResult> {
+    let mut results = vec![
+        check_udp_bind(options.port),
+        check_multicast_join(options.multiaddr),
+        check_multicast_loopback(options.multiaddr).await,
+        check_inbound(options.local_ip, options.http_port).await,
+        check_proxy(
+            |name| std::env::var(name).ok(),
+            options.peer.map_or(options.local_ip, |peer| peer.ip()),
+        ),
+    ];
+    if let Some(peer) = options.peer {
+        results.push(check_peer(peer).await);
+    }
+    results
+}
+
+/// Can the discovery port be bound.
+pub fn check_udp_bind(port: u16) -> CheckResult {
+    const NAME: &str = "UDP port";
+    match std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)) {
+        Ok(_) => CheckResult::ok(NAME, format!("port {} can be bound", port)),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => CheckResult::failed(
+            NAME,
+            format!("port {} is already in use", port),
+            "Stop other localsend instances or choose another port with --port",
+        ),
+        Err(e) => CheckResult::failed(
+            NAME,
+            format!("binding port {} failed: {}", port, e),
+            "Ports below 1024 need root, choose another port with --port",
+        ),
+    }
+}
+
+/// Does joining the multicast group succeed.
+pub fn check_multicast_join(multiaddr: Ipv4Addr) -> CheckResult {
+    const NAME: &str = "Multicast group";
+    let result = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.join_multicast_v4(&multiaddr, &Ipv4Addr::UNSPECIFIED));
+    match result {
+        Ok(_) => CheckResult::ok(NAME, format!("joined {}", multiaddr)),
+        Err(e) => CheckResult::failed(
+            NAME,
+            format!("joining {} failed: {}", multiaddr, e),
+            "Connect to a network, a VPN or a missing multicast route can also cause this",
+        ),
+    }
+}
+
+/// Do we receive our own announcement through the multicast group.
+///
+/// Uses an ephemeral port, the discovery port may be taken by a running instance.
+pub async fn check_multicast_loopback(multiaddr: Ipv4Addr) -> CheckResult {
+    const NAME: &str = "Multicast loopback";
+    let hint =
+        "Devices will not find each other, check that the active interface supports multicast";
+    let socket = match bind_multicast(multiaddr).await {
+        Ok(socket) => socket,
+        Err(e) => return CheckResult::failed(NAME, format!("no socket: {}", e), hint),
+    };
+    let local_port = match socket.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(e) => return CheckResult::failed(NAME, format!("no socket: {}", e), hint),
+    };
+    let probe = format!("localsend-doctor-{}", uuid::Uuid::new_v4());
+    if let Err(e) = socket
+        .send_to(probe.as_bytes(), (multiaddr, local_port))
+        .await
+    {
+        return CheckResult::failed(NAME, format!("sending failed: {}", e), hint);
+    }
+    let received = tokio::time::timeout(PROBE_TIMEOUT, async {
+        let mut buf = [0u8; 128];
+        loop {
+            match socket.recv_from(&mut buf).await {
+                Ok((size, _)) if &buf[..size] == probe.as_bytes() => return true,
+                Ok(_) => continue,
+                Err(_) => return false,
+            }
+        }
+    })
+    .await;
+    match received {
+        Ok(true) => CheckResult::ok(NAME, "own announcement received"),
+        _ => CheckResult::failed(NAME, "own announcement not received", hint),
+    }
+}
+
+async fn bind_multicast(multiaddr: Ipv4Addr) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.join_multicast_v4(multiaddr, Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+    Ok(socket)
+}
+
+/// Can we connect to the http port through the local network address.
+///
+/// Connecting to ourselves does not pass every firewall rule, a success only
+/// means inbound connections are likely accepted.
+pub async fn check_inbound(local_ip: IpAddr, port: u16) -> CheckResult {
+    const NAME: &str = "Inbound TCP";
+    let hint = format!(
+        "Allow inbound TCP connections to port {} in the firewall",
+        port
+    );
+    // a running instance answers itself
+    let _listener = match TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await {
+        Ok(listener) => Some(listener),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => None,
+        Err(e) => {
+            return CheckResult::failed(NAME, format!("binding port {} failed: {}", port, e), hint)
+        }
+    };
+    let addr = SocketAddr::new(local_ip, port);
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => CheckResult::ok(NAME, format!("{} accepts connections", addr)),
+        Ok(Err(e)) => {
+            CheckResult::failed(NAME, format!("connecting to {} failed: {}", addr, e), hint)
+        }
+        Err(_) => CheckResult::failed(NAME, format!("connecting to {} timed out", addr), hint),
+    }
+}
+
+/// Are requests to `lan_ip` routed through a proxy from the environment.
+pub fn check_proxy(env: impl Fn(&str) -> Option<String>, lan_ip: IpAddr) -> CheckResult {
+    const NAME: &str = "Proxy";
+    let var = |name: &str| {
+        env(name)
+            .or_else(|| env(&name.to_lowercase()))
+            .filter(|value| !value.trim().is_empty())
+    };
+    let proxies: Vec<&str> = PROXY_VARS
+        .iter()
+        .copied()
+        .filter(|name| var(name).is_some())
+        .collect();
+    if proxies.is_empty() {
+        return CheckResult::ok(NAME, "no proxy configured");
+    }
+    let no_proxy = var("NO_PROXY").unwrap_or_default();
+    if is_excluded(&no_proxy, lan_ip) {
+        return CheckResult::ok(
+            NAME,
+            format!("{} set, {} is excluded", proxies.join(", "), lan_ip),
+        );
+    }
+    CheckResult::warning(
+        NAME,
+        format!(
+            "{} set, requests to {} use the proxy",
+            proxies.join(", "),
+            lan_ip
+        ),
+        format!(
+            "Add the local network to NO_PROXY, e.g. NO_PROXY={}",
+            suggested_no_proxy(lan_ip)
+        ),
+    )
+}
+
+/// Whether an entry of a `NO_PROXY` list matches `ip`.
+///
+/// Entries are `*`, ip addresses or IPv4 networks like `192.168.0.0/16`, host names never match.
+fn is_excluded(no_proxy: &str, ip: IpAddr) -> bool {
+    no_proxy.split(',').map(str::trim).any(|entry| {
+        if entry == "*" {
+            return true;
+        }
+        if let Ok(excluded) = entry.parse::<IpAddr>() {
+            return excluded == ip;
+        }
+        let (IpAddr::V4(ip), Some((network, prefix))) = (ip, entry.split_once('/')) else {
+            return false;
+        };
+        match (network.parse::<Ipv4Addr>(), prefix.parse::<u32>()) {
+            (Ok(network), Ok(prefix)) if prefix <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            _ => false,
+        }
+    })
+}
+
+fn suggested_no_proxy(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) if ip.is_private() => {
+            let [a, b, ..] = ip.octets();
+            match a {
+                10 => "10.0.0.0/8".to_owned(),
+                172 => "172.16.0.0/12".to_owned(),
+                _ => format!("{}.{}.0.0/16", a, b),
+            }
+        }
+        ip => ip.to_string(),
+    }
+}
+
+/// Operating systems with their own reasons for refusing a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Windows,
+    MacOs,
+    Linux,
+    Other,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Platform::Windows
+        } else if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else if cfg!(target_os = "linux") {
+            Platform::Linux
+        } else {
+            Platform::Other
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Udp => f.write_str("UDP"),
+            Transport::Tcp => f.write_str("TCP"),
+        }
+    }
+}
+
+/// A port that could not be bound.
+#[derive(Debug)]
+pub struct BindFailure {
+    pub transport: Transport,
+    pub port: u16,
+    pub error: io::Error,
+}
+
+/// Binds the discovery and http ports like starting the servers does and releases them.
+pub fn probe_binds(port: u16, http_port: u16) -> Result<(), BindFailure> {
+    std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|error| BindFailure {
+        transport: Transport::Udp,
+        port,
+        error,
+    })?;
+    std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, http_port)).map_err(|error| {
+        BindFailure {
+            transport: Transport::Tcp,
+            port: http_port,
+            error,
+        }
+    })?;
+    Ok(())
+}
+
+/// What went wrong when binding a port and what to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindAdvice {
+    pub explanation: String,
+    /// The next port that is not known to be reserved
+    pub suggested_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindProblem {
+    InUse,
+    Denied,
+    Other,
+}
+
+fn classify(error: &io::Error) -> BindProblem {
+    // WSAEADDRINUSE and WSAEACCES, not mapped to error kinds on other platforms
+    match error.raw_os_error() {
+        Some(10048) => return BindProblem::InUse,
+        Some(10013) => return BindProblem::Denied,
+        _ => {}
+    }
+    match error.kind() {
+        ErrorKind::AddrInUse => BindProblem::InUse,
+        ErrorKind::PermissionDenied => BindProblem::Denied,
+        _ => BindProblem::Other,
+    }
+}
+
+/// Explains `failure` for `platform`.
+///
+/// `excluded` are the port ranges reserved by the system, only known on windows.
+pub fn bind_advice(
+    failure: &BindFailure,
+    platform: Platform,
+    excluded: &[RangeInclusive<u16>],
+) -> BindAdvice {
+    let port = failure.port;
+    let transport = failure.transport;
+    let problem = classify(&failure.error);
+    let explanation = match (problem, platform) {
+        (BindProblem::InUse, _) => format!(
+            "{} port {} is already in use. Another localsend or the official LocalSend app \
+             is probably running, close it or choose another port.",
+            transport, port
+        ),
+        (BindProblem::Denied, Platform::Windows) => {
+            match excluded.iter().find(|range| range.contains(&port)) {
+                Some(range) => format!(
+                    "Windows reserved {} port {} in the range {}-{}, usually for Hyper-V, \
+                     WSL or Docker. Choose a port outside the reserved ranges.",
+                    transport,
+                    port,
+                    range.start(),
+                    range.end()
+                ),
+                None => format!(
+                    "Windows refused {} port {} (WSAEACCES). Another LocalSend instance may \
+                     hold it exclusively, or Hyper-V reserved it, see \
+                     `netsh interface ipv4 show excludedportrange protocol=tcp`.",
+                    transport, port
+                ),
+            }
+        }
+        (BindProblem::Denied, _) if port < 1024 => format!(
+            "{} port {} is privileged, only root may bind ports below 1024. \
+             Choose a port above 1023.",
+            transport, port
+        ),
+        (BindProblem::Denied, Platform::MacOs) => format!(
+            "macOS refused {} port {}. Allow incoming connections when macOS asks, \
+             or in System Settings > Network > Firewall.",
+            transport, port
+        ),
+        (BindProblem::Denied, Platform::Linux) => format!(
+            "Binding {} port {} was denied, likely by SELinux or AppArmor. If devices \
+             do not find each other afterwards, check the firewalld zone of the network, \
+             e.g. `firewall-cmd --add-port={}/{}`.",
+            transport,
+            port,
+            port,
+            transport.to_string().to_lowercase()
+        ),
+        _ => format!("Binding {} port {} failed.", transport, port),
+    };
+    let suggested_port = match problem {
+        BindProblem::Other => None,
+        _ => suggest_port(port, excluded),
+    };
+    BindAdvice {
+        explanation,
+        suggested_port,
+    }
+}
+
+/// The first port after `port` outside of `excluded`, staying clear of privileged ports.
+fn suggest_port(port: u16, excluded: &[RangeInclusive<u16>]) -> Option<u16> {
+    (port.max(1023).checked_add(1)?..=u16::MAX)
+        .find(|candidate| !excluded.iter().any(|range| range.contains(candidate)))
+}
+
+/// Port ranges windows reserved for `transport`, empty on other platforms.
+pub fn excluded_port_ranges(transport: Transport) -> Vec<RangeInclusive<u16>> {
+    if !cfg!(windows) {
+        return vec![];
+    }
+    let protocol = format!("protocol={}", transport.to_string().to_lowercase());
+    let output = std::process::Command::new("netsh")
+        .args(["interface", "ipv4", "show", "excludedportrange", &protocol])
+        .output();
+    match output {
+        Ok(output) => parse_excluded_port_ranges(&String::from_utf8_lossy(&output.stdout)),
+        Err(e) => {
+            log::debug!("netsh failed: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// Reads the table printed by `netsh interface ipv4 show excludedportrange`.
+fn parse_excluded_port_ranges(output: &str) -> Vec<RangeInclusive<u16>> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let start = columns.next()?.parse().ok()?;
+            let end = columns.next()?.parse().ok()?;
+            Some(start..=end)
+        })
+        .collect()
+}
+
+/// Can a device be reached, first by TCP and then with an http request.
+pub async fn check_peer(peer: SocketAddr) -> CheckResult {
+    const NAME: &str = "Peer";
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(peer)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => {
+            return CheckResult::failed(
+                NAME,
+                format!("{} refused the connection", peer),
+                "Check that localsend is running on the device and listens on this port",
+            )
+        }
+        Ok(Err(e)) => {
+            return CheckResult::failed(
+                NAME,
+                format!("connecting to {} failed: {}", peer, e),
+                "Check that both devices are in the same network",
+            )
+        }
+        Err(_) => {
+            return CheckResult::failed(
+                NAME,
+                format!("connecting to {} timed out", peer),
+                "Guest networks often isolate devices (AP isolation), \
+                 use another network or check the firewall of the device",
+            )
+        }
+    }
+    // any response proves the http server is reachable, old versions lack the info route
+    let url = format!("http://{}/api/localsend/v2/info", peer);
+    match CLIENT.get(url).timeout(PROBE_TIMEOUT).send().await {
+        Ok(response) => CheckResult::ok(
+            NAME,
+            format!("{} answered with {}", peer, response.status()),
+        ),
+        Err(e) => CheckResult::failed(
+            NAME,
+            format!(
+                "{} accepted the connection, but the request failed: {}",
+                peer, e
+            ),
+            "A proxy or captive portal may intercept requests, check the Proxy result",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+    };
+
+    use crate::test_util::TestReceiver;
+
+    use super::{
+        bind_advice, check_inbound, check_peer, check_proxy, check_udp_bind, is_excluded,
+        parse_excluded_port_ranges, probe_binds, BindFailure, CheckStatus, Platform, Transport,
+    };
+
+    const LAN_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+
+    #[test]
+    fn test_check_udp_bind() {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let result = check_udp_bind(port);
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(result.hint.unwrap().contains("--port"));
+        drop(socket);
+        assert_eq!(check_udp_bind(port).status, CheckStatus::Ok);
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        assert!(is_excluded("*", LAN_IP));
+        assert!(is_excluded("localhost, 192.168.1.20", LAN_IP));
+        assert!(is_excluded("example.com,192.168.0.0/16", LAN_IP));
+        assert!(!is_excluded("192.168.2.0/24", LAN_IP));
+        assert!(!is_excluded("localhost,.local", LAN_IP));
+        assert!(!is_excluded("", LAN_IP));
+        assert!(is_excluded("0.0.0.0/0", LAN_IP));
+    }
+
+    #[test]
+    fn test_check_proxy() {
+        let env = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            move |name: &str| vars.get(name).cloned()
+        };
+
+        assert_eq!(check_proxy(env(&[]), LAN_IP).status, CheckStatus::Ok);
+        let result = check_proxy(env(&[("https_proxy", "http://proxy:3128")]), LAN_IP);
+        assert_eq!(result.status, CheckStatus::Warning);
+        assert_eq!(
+            result.hint.as_deref(),
+            Some("Add the local network to NO_PROXY, e.g. NO_PROXY=192.168.0.0/16")
+        );
+        let result = check_proxy(
+            env(&[
+                ("HTTP_PROXY", "http://proxy:3128"),
+                ("no_proxy", "192.168.0.0/16"),
+            ]),
+            LAN_IP,
+        );
+        assert_eq!(result.status, CheckStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_check_peer() {
+        let receiver = TestReceiver::start().await;
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, receiver.port()));
+        assert_eq!(check_peer(peer).await.status, CheckStatus::Ok);
+        receiver.stop().await;
+
+        let result = check_peer(peer).await;
+        assert_eq!(result.status, CheckStatus::Failed);
+        assert!(result.detail.contains("refused"));
+    }
+
+    #[tokio::test]
+    async fn test_check_inbound() {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let result = check_inbound(IpAddr::V4(Ipv4Addr::LOCALHOST), port).await;
+        assert_eq!(result.status, CheckStatus::Ok);
+    }
+
+    fn failure(transport: Transport, port: u16, error: std::io::Error) -> BindFailure {
+        BindFailure {
+            transport,
+            port,
+            error,
+        }
+    }
+
+    #[test]
+    fn test_probe_binds() {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let failure = probe_binds(port, 0).unwrap_err();
+        assert_eq!(failure.transport, Transport::Udp);
+        assert_eq!(failure.port, port);
+        assert_eq!(failure.error.kind(), std::io::ErrorKind::AddrInUse);
+        drop(socket);
+        probe_binds(port, 0).unwrap();
+    }
+
+    #[test]
+    fn test_in_use_advice() {
+        let in_use = || std::io::Error::from(std::io::ErrorKind::AddrInUse);
+        for platform in [Platform::Windows, Platform::Linux, Platform::MacOs] {
+            let advice = bind_advice(&failure(Transport::Tcp, 53317, in_use()), platform, &[]);
+            assert!(
+                advice.explanation.contains("already in use"),
+                "{}",
+                advice.explanation
+            );
+            assert_eq!(advice.suggested_port, Some(53318));
+        }
+        // WSAEADDRINUSE
+        let error = std::io::Error::from_raw_os_error(10048);
+        let advice = bind_advice(
+            &failure(Transport::Udp, 53317, error),
+            Platform::Windows,
+            &[],
+        );
+        assert!(advice
+            .explanation
+            .starts_with("UDP port 53317 is already in use"));
+    }
+
+    #[test]
+    fn test_windows_denied_advice() {
+        // WSAEACCES
+        let error = || std::io::Error::from_raw_os_error(10013);
+        let reserved = [53300..=53399, 53400..=53410];
+        let advice = bind_advice(
+            &failure(Transport::Tcp, 53317, error()),
+            Platform::Windows,
+            &reserved,
+        );
+        assert!(
+            advice.explanation.contains("in the range 53300-53399"),
+            "{}",
+            advice.explanation
+        );
+        assert_eq!(advice.suggested_port, Some(53411));
+
+        let advice = bind_advice(
+            &failure(Transport::Tcp, 53317, error()),
+            Platform::Windows,
+            &[],
+        );
+        assert!(
+            advice.explanation.contains("excludedportrange"),
+            "{}",
+            advice.explanation
+        );
+        assert_eq!(advice.suggested_port, Some(53318));
+    }
+
+    #[test]
+    fn test_unix_denied_advice() {
+        let denied = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let advice = bind_advice(&failure(Transport::Tcp, 80, denied()), Platform::Linux, &[]);
+        assert!(
+            advice.explanation.contains("below 1024"),
+            "{}",
+            advice.explanation
+        );
+        assert_eq!(advice.suggested_port, Some(1024));
+
+        let advice = bind_advice(
+            &failure(Transport::Udp, 53317, denied()),
+            Platform::Linux,
+            &[],
+        );
+        assert!(
+            advice
+                .explanation
+                .contains("firewall-cmd --add-port=53317/udp"),
+            "{}",
+            advice.explanation
+        );
+
+        let advice = bind_advice(
+            &failure(Transport::Tcp, 53317, denied()),
+            Platform::MacOs,
+            &[],
+        );
+        assert!(
+            advice.explanation.contains("Allow incoming connections"),
+            "{}",
+            advice.explanation
+        );
+
+        let other = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+        let advice = bind_advice(&failure(Transport::Tcp, 53317, other), Platform::Linux, &[]);
+        assert_eq!(advice.explanation, "Binding TCP port 53317 failed.");
+        assert_eq!(advice.suggested_port, None);
+    }
+
+    #[test]
+    fn test_parse_excluded_port_ranges() {
+        let output = "
+Protocol tcp Port Exclusion Ranges
+
+Start Port    End Port
+----------    --------
+      5357        5357
+     50000       50059     *
+     53300       53399
+
+* - Administered port exclusions.
+";
+        assert_eq!(
+            parse_excluded_port_ranges(output),
+            vec![5357..=5357, 50000..=50059, 53300..=53399]
+        );
+    }
+}
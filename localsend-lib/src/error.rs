@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::{receive::ReceiveError, send::SendError, server::ServerError};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error(transparent)]
@@ -12,4 +15,379 @@ pub enum Error {
     Send(#[from] crate::send::SendError),
     #[error(transparent)]
     WalkDir(#[from] walkdir::Error),
+    #[error(transparent)]
+    Server(#[from] crate::server::ServerError),
+    #[error(transparent)]
+    Route(#[from] localsend_proto::RouteError),
+}
+
+/// Stable machine readable error code, the serialized names must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    ReceiverBusy,
+    Rejected,
+    Cancelled,
+    CancelledByReceiver,
+    Timeout,
+    NothingSelected,
+    InvalidParameters,
+    InvalidSession,
+    InvalidState,
+    InvalidToken,
+    Forbidden,
+    DeviceNotFound,
+    AmbiguousTarget,
+    TargetUnreachable,
+    CertificateMismatch,
+    StructureLimitExceeded,
+    DestinationLost,
+    DownloadUnsupported,
+    SaveFailed,
+    NotDelivered,
+    AddressInUse,
+    UnexpectedStatus,
+    UnsupportedProtocol,
+    Io,
+    Network,
+    Internal,
+}
+
+/// Serializable form of [`Error`], used as the body of error responses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorDto {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub file_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub status_code: Option<u16>,
+}
+
+impl ErrorDto {
+    pub fn with_file_id(mut self, file_id: impl ToString) -> Self {
+        self.file_id = Some(file_id.to_string());
+        self
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) => ErrorCode::Io,
+            Error::Reqwest(_) => ErrorCode::Network,
+            Error::Receive(e) => e.code(),
+            Error::Send(e) => e.code(),
+            Error::WalkDir(_) => ErrorCode::Io,
+            Error::Server(ServerError::AddrInUse(_)) => ErrorCode::AddressInUse,
+            Error::Server(ServerError::Io(_)) => ErrorCode::Io,
+            Error::Route(_) => ErrorCode::UnsupportedProtocol,
+        }
+    }
+
+    /// Status code of the http response that caused this error, if any.
+    pub fn remote_status_code(&self) -> Option<u16> {
+        match self {
+            Error::Reqwest(e) => e.status().map(|s| s.as_u16()),
+            Error::Send(SendError::Unknown(status)) => Some(status.as_u16()),
+            _ => None,
+        }
+    }
+
+    pub fn to_dto(&self) -> ErrorDto {
+        ErrorDto {
+            code: self.code(),
+            message: self.to_string(),
+            file_id: None,
+            status_code: self.remote_status_code(),
+        }
+    }
+}
+
+impl ReceiveError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ReceiveError::EmptyFiles => ErrorCode::InvalidParameters,
+            ReceiveError::InvalidIp(_) => ErrorCode::Forbidden,
+            ReceiveError::InvalidParameters => ErrorCode::InvalidParameters,
+            ReceiveError::InvalidRecipient => ErrorCode::InvalidState,
+            ReceiveError::InvalidSessionId => ErrorCode::InvalidSession,
+            ReceiveError::InvalidServerState => ErrorCode::InvalidState,
+            ReceiveError::InvalidToken => ErrorCode::InvalidToken,
+            ReceiveError::NothingSelected => ErrorCode::NothingSelected,
+            ReceiveError::SaveFileFailed => ErrorCode::SaveFailed,
+            ReceiveError::SessionBlocked => ErrorCode::ReceiverBusy,
+            ReceiveError::QueueFull(_) => ErrorCode::ReceiverBusy,
+            ReceiveError::Closed => ErrorCode::ReceiverBusy,
+            ReceiveError::SessionDeclined => ErrorCode::Rejected,
+            ReceiveError::SessionNotExists => ErrorCode::InvalidSession,
+            ReceiveError::Cancelled => ErrorCode::Cancelled,
+            ReceiveError::DownloadUnsupported => ErrorCode::DownloadUnsupported,
+            ReceiveError::DecisionTimeout => ErrorCode::Timeout,
+            ReceiveError::UploadInProgress => ErrorCode::InvalidState,
+            ReceiveError::InvalidDto(_) => ErrorCode::InvalidParameters,
+            ReceiveError::StructureLimitExceeded { .. } => ErrorCode::StructureLimitExceeded,
+            ReceiveError::DestinationLost(_) => ErrorCode::DestinationLost,
+            ReceiveError::DestinationUnavailable(_) => ErrorCode::DestinationLost,
+        }
+    }
+}
+
+impl SendError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SendError::NothingSelected => ErrorCode::NothingSelected,
+            SendError::Rejected => ErrorCode::Rejected,
+            SendError::Busy => ErrorCode::ReceiverBusy,
+            SendError::Cancelled => ErrorCode::CancelledByReceiver,
+            SendError::NoPermission => ErrorCode::Forbidden,
+            SendError::DeviceNotFound(_) => ErrorCode::DeviceNotFound,
+            SendError::AmbiguousTarget(_) => ErrorCode::AmbiguousTarget,
+            SendError::TargetUnreachable { .. } => ErrorCode::TargetUnreachable,
+            SendError::HostNotFound { .. } => ErrorCode::DeviceNotFound,
+            SendError::HostUnreachable { .. } => ErrorCode::TargetUnreachable,
+            SendError::MissingFiles(_) => ErrorCode::InvalidParameters,
+            SendError::BrokenSymlink(_) => ErrorCode::InvalidParameters,
+            SendError::Aborted => ErrorCode::Cancelled,
+            SendError::NotDelivered(_) => ErrorCode::NotDelivered,
+            SendError::CertificateMismatch { .. } => ErrorCode::CertificateMismatch,
+            SendError::Unknown(_) => ErrorCode::UnexpectedStatus,
+        }
+    }
+}
+
+impl From<&Error> for ErrorDto {
+    fn from(value: &Error) -> Self {
+        value.to_dto()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use localsend_proto::{fixtures, Problem, ProtocolVersion, RouteError, ValidationError};
+    use reqwest::StatusCode;
+
+    use crate::{
+        receive::{DestinationError, ReceiveError, StructureLimit},
+        send::SendError,
+        server::ServerError,
+    };
+
+    use super::{Error, ErrorCode, ErrorDto};
+
+    // adding a variant breaks these matches, so every new variant gets a code and a test entry
+    fn receive_errors() -> Vec<ReceiveError> {
+        let errors = vec![
+            ReceiveError::EmptyFiles,
+            ReceiveError::InvalidIp(String::default()),
+            ReceiveError::InvalidParameters,
+            ReceiveError::InvalidRecipient,
+            ReceiveError::InvalidSessionId,
+            ReceiveError::InvalidServerState,
+            ReceiveError::InvalidToken,
+            ReceiveError::NothingSelected,
+            ReceiveError::SaveFileFailed,
+            ReceiveError::SessionBlocked,
+            ReceiveError::QueueFull(Duration::from_secs(60)),
+            ReceiveError::Closed,
+            ReceiveError::SessionDeclined,
+            ReceiveError::SessionNotExists,
+            ReceiveError::Cancelled,
+            ReceiveError::DownloadUnsupported,
+            ReceiveError::DecisionTimeout,
+            ReceiveError::UploadInProgress,
+            ReceiveError::InvalidDto(ValidationError::new("id", Problem::Empty)),
+            ReceiveError::StructureLimitExceeded {
+                file_name: String::default(),
+                limit: StructureLimit::Depth(16),
+            },
+            ReceiveError::DestinationLost(String::default()),
+            ReceiveError::DestinationUnavailable(DestinationError::Missing(PathBuf::default())),
+        ];
+        for e in &errors {
+            match e {
+                ReceiveError::EmptyFiles
+                | ReceiveError::InvalidIp(_)
+                | ReceiveError::InvalidParameters
+                | ReceiveError::InvalidRecipient
+                | ReceiveError::InvalidSessionId
+                | ReceiveError::InvalidServerState
+                | ReceiveError::InvalidToken
+                | ReceiveError::NothingSelected
+                | ReceiveError::SaveFileFailed
+                | ReceiveError::SessionBlocked
+                | ReceiveError::QueueFull(_)
+                | ReceiveError::Closed
+                | ReceiveError::SessionDeclined
+                | ReceiveError::SessionNotExists
+                | ReceiveError::Cancelled
+                | ReceiveError::DownloadUnsupported
+                | ReceiveError::DecisionTimeout
+                | ReceiveError::UploadInProgress
+                | ReceiveError::InvalidDto(_)
+                | ReceiveError::StructureLimitExceeded { .. }
+                | ReceiveError::DestinationLost(_)
+                | ReceiveError::DestinationUnavailable(_) => {}
+            }
+        }
+        errors
+    }
+
+    fn send_errors() -> Vec<SendError> {
+        let errors = vec![
+            SendError::NothingSelected,
+            SendError::Rejected,
+            SendError::Busy,
+            SendError::Cancelled,
+            SendError::NoPermission,
+            SendError::DeviceNotFound(String::default()),
+            SendError::AmbiguousTarget(vec![]),
+            SendError::TargetUnreachable {
+                device: Box::new(fixtures::device("phone", 53317)),
+            },
+            SendError::HostNotFound {
+                host: String::default(),
+                reason: String::default(),
+            },
+            SendError::HostUnreachable {
+                host: String::default(),
+                port: 53317,
+                tried: vec![],
+            },
+            SendError::MissingFiles(vec![]),
+            SendError::BrokenSymlink(Default::default()),
+            SendError::Aborted,
+            SendError::NotDelivered(vec![]),
+            SendError::CertificateMismatch {
+                device: Box::new(fixtures::device("phone", 53317)),
+            },
+            SendError::Unknown(StatusCode::IM_A_TEAPOT),
+        ];
+        for e in &errors {
+            match e {
+                SendError::NothingSelected
+                | SendError::Rejected
+                | SendError::Busy
+                | SendError::Cancelled
+                | SendError::NoPermission
+                | SendError::DeviceNotFound(_)
+                | SendError::AmbiguousTarget(_)
+                | SendError::TargetUnreachable { .. }
+                | SendError::HostNotFound { .. }
+                | SendError::HostUnreachable { .. }
+                | SendError::MissingFiles(_)
+                | SendError::BrokenSymlink(_)
+                | SendError::Aborted
+                | SendError::NotDelivered(_)
+                | SendError::CertificateMismatch { .. }
+                | SendError::Unknown(_) => {}
+            }
+        }
+        errors
+    }
+
+    fn code_name(code: ErrorCode) -> String {
+        serde_json::to_value(code)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let codes: Vec<String> = receive_errors()
+            .iter()
+            .map(|e| code_name(e.code()))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                "INVALID_PARAMETERS",
+                "FORBIDDEN",
+                "INVALID_PARAMETERS",
+                "INVALID_STATE",
+                "INVALID_SESSION",
+                "INVALID_STATE",
+                "INVALID_TOKEN",
+                "NOTHING_SELECTED",
+                "SAVE_FAILED",
+                "RECEIVER_BUSY",
+                "RECEIVER_BUSY",
+                "RECEIVER_BUSY",
+                "REJECTED",
+                "INVALID_SESSION",
+                "CANCELLED",
+                "DOWNLOAD_UNSUPPORTED",
+                "TIMEOUT",
+                "INVALID_STATE",
+                "INVALID_PARAMETERS",
+                "STRUCTURE_LIMIT_EXCEEDED",
+                "DESTINATION_LOST",
+                "DESTINATION_LOST",
+            ]
+        );
+
+        let codes: Vec<String> = send_errors().iter().map(|e| code_name(e.code())).collect();
+        assert_eq!(
+            codes,
+            vec![
+                "NOTHING_SELECTED",
+                "REJECTED",
+                "RECEIVER_BUSY",
+                "CANCELLED_BY_RECEIVER",
+                "FORBIDDEN",
+                "DEVICE_NOT_FOUND",
+                "AMBIGUOUS_TARGET",
+                "TARGET_UNREACHABLE",
+                "DEVICE_NOT_FOUND",
+                "TARGET_UNREACHABLE",
+                "INVALID_PARAMETERS",
+                "INVALID_PARAMETERS",
+                "CANCELLED",
+                "NOT_DELIVERED",
+                "CERTIFICATE_MISMATCH",
+                "UNEXPECTED_STATUS",
+            ]
+        );
+
+        let io = Error::from(std::io::Error::other("io"));
+        assert_eq!(io.code(), ErrorCode::Io);
+        let in_use = Error::from(ServerError::AddrInUse(([0, 0, 0, 0], 53317).into()));
+        assert_eq!(code_name(in_use.code()), "ADDRESS_IN_USE");
+        let route = Error::from(RouteError::UnsupportedVersion(ProtocolVersion::new(3, 0)));
+        assert_eq!(code_name(route.code()), "UNSUPPORTED_PROTOCOL");
+    }
+
+    #[test]
+    fn test_error_dto() {
+        let error = Error::from(SendError::Unknown(StatusCode::IM_A_TEAPOT));
+        let dto = serde_json::to_value(error.to_dto().with_file_id("file")).unwrap();
+        assert_eq!(
+            dto,
+            serde_json::json!({
+                "code": "UNEXPECTED_STATUS",
+                "message": "Unknown response status code: 418 I'm a teapot",
+                "fileId": "file",
+                "statusCode": 418,
+            })
+        );
+
+        let error = Error::from(ReceiveError::InvalidToken);
+        let dto = serde_json::to_value(error.to_dto()).unwrap();
+        assert_eq!(
+            dto,
+            serde_json::json!({"code": "INVALID_TOKEN", "message": "Invalid token"})
+        );
+
+        // the error body of the protocol fixtures
+        let dto = error.to_dto().with_file_id("a1");
+        let fixture: serde_json::Value = serde_json::from_str(fixtures::ERROR).unwrap();
+        assert_eq!(serde_json::to_value(dto).unwrap(), fixture);
+        let parsed: ErrorDto = serde_json::from_str(fixtures::ERROR).unwrap();
+        assert_eq!(parsed.code, ErrorCode::InvalidToken);
+    }
 }
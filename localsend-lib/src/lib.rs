@@ -1,9 +1,13 @@
+pub mod diagnostics;
 mod error;
+pub mod progress;
 pub mod receive;
 pub mod scanner;
 pub mod send;
 pub mod server;
 mod settings;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 pub mod util;
 
 pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,323 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::Stream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::send::{throughput, FileStatus};
+
+/// Events waiting for the consumer before positions are coalesced.
+const CAPACITY: usize = 100;
+
+/// What happened to a file of a transfer, in either direction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// Bytes of the file transferred so far, more are to come
+    Position {
+        file_id: String,
+        position: u64,
+        /// Since the transfer of the file started
+        elapsed: Duration,
+    },
+    /// The last byte of the file arrived, files without bytes end with position 0
+    Finished {
+        file_id: String,
+        position: u64,
+        elapsed: Duration,
+    },
+    Failed {
+        file_id: String,
+    },
+    /// The file was not transferred, e.g. the receiver did not select it
+    Skipped {
+        file_id: String,
+        /// Why the receiver's filters dropped the file, `None` when it was not selected
+        reason: Option<String>,
+    },
+    /// A session reporting to the stream ended, none of its files report anymore
+    SessionEnded,
+}
+
+impl ProgressEvent {
+    pub fn file_id(&self) -> Option<&str> {
+        match self {
+            ProgressEvent::Position { file_id, .. }
+            | ProgressEvent::Finished { file_id, .. }
+            | ProgressEvent::Failed { file_id }
+            | ProgressEvent::Skipped { file_id, .. } => Some(file_id),
+            ProgressEvent::SessionEnded => None,
+        }
+    }
+
+    /// Bytes transferred so far, 0 for events without a position.
+    pub fn position(&self) -> u64 {
+        match self {
+            ProgressEvent::Position { position, .. } | ProgressEvent::Finished { position, .. } => {
+                *position
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            ProgressEvent::Position { elapsed, .. } | ProgressEvent::Finished { elapsed, .. } => {
+                *elapsed
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// How the file ended, `None` while it is transferred and for the end of a session.
+    pub fn outcome(&self) -> Option<FileStatus> {
+        match self {
+            ProgressEvent::Finished { .. } => Some(FileStatus::Finished),
+            ProgressEvent::Failed { .. } => Some(FileStatus::Failed),
+            ProgressEvent::Skipped { .. } => Some(FileStatus::Skipped),
+            ProgressEvent::Position { .. } | ProgressEvent::SessionEnded => None,
+        }
+    }
+
+    /// Average bytes per second of the file so far.
+    pub fn speed(&self) -> f64 {
+        throughput(self.position(), self.elapsed())
+    }
+}
+
+/// Reports the progress of transfers to a [`ProgressStream`].
+///
+/// Sessions hold a clone while they run, see [`ProgressStream`] for what the
+/// consumer gets.
+#[derive(Debug, Clone)]
+pub struct ProgressSender {
+    tx: mpsc::Sender<ProgressEvent>,
+}
+
+impl ProgressSender {
+    /// Reports `position` of `size` bytes, the file finishes once all arrived.
+    pub(crate) async fn position(
+        &self,
+        file_id: &str,
+        position: u64,
+        size: u64,
+        elapsed: Duration,
+    ) {
+        let file_id = file_id.to_owned();
+        if position >= size {
+            let event = ProgressEvent::Finished {
+                file_id,
+                position,
+                elapsed,
+            };
+            self.tx.send(event).await.ok();
+            return;
+        }
+        let event = ProgressEvent::Position {
+            file_id,
+            position,
+            elapsed,
+        };
+        // a consumer falling behind gets the next position instead of slowing the transfer
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(event) {
+            log::trace!("Progress consumer is behind, coalescing positions");
+        }
+    }
+
+    /// Reports the end of a file that transferred no bytes, or failed.
+    pub(crate) async fn done(&self, file_id: &str, status: FileStatus) {
+        let file_id = file_id.to_owned();
+        let event = match status {
+            FileStatus::Finished => ProgressEvent::Finished {
+                file_id,
+                position: 0,
+                elapsed: Duration::ZERO,
+            },
+            FileStatus::Skipped => ProgressEvent::Skipped {
+                file_id,
+                reason: None,
+            },
+            _ => ProgressEvent::Failed { file_id },
+        };
+        self.tx.send(event).await.ok();
+    }
+
+    /// Reports a file the receiver's filters dropped, see [`ProgressEvent::Skipped`].
+    pub(crate) async fn filtered(&self, file_id: &str, reason: &str) {
+        let event = ProgressEvent::Skipped {
+            file_id: file_id.to_owned(),
+            reason: Some(reason.to_owned()),
+        };
+        self.tx.send(event).await.ok();
+    }
+
+    pub(crate) async fn session_ended(&self) {
+        self.tx.send(ProgressEvent::SessionEnded).await.ok();
+    }
+
+    /// A handle that does not keep the stream open, see [`WeakProgressSender::upgrade`].
+    pub fn downgrade(&self) -> WeakProgressSender {
+        WeakProgressSender(self.tx.downgrade())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WeakProgressSender(mpsc::WeakSender<ProgressEvent>);
+
+impl WeakProgressSender {
+    /// `None` once the stream has ended.
+    pub fn upgrade(&self) -> Option<ProgressSender> {
+        self.0.upgrade().map(|tx| ProgressSender { tx })
+    }
+}
+
+/// The progress of one or more sessions, in the order it happened.
+///
+/// For every file the stream yields any number of [`ProgressEvent::Position`]s
+/// with growing positions, followed by exactly one of `Finished`, `Failed` or
+/// `Skipped`. Positions are coalesced while the consumer falls behind, events
+/// ending a file never are. After the last event of its files, a session that
+/// ran to its end reports [`ProgressEvent::SessionEnded`]; sessions dropped
+/// without a result, e.g. a receive that expired, go without it. The stream
+/// ends once every session reporting to it was dropped.
+///
+/// Dropping the stream does not affect the transfers. A stream that is kept has
+/// to be read, files end only once their final event was taken.
+#[derive(Debug)]
+pub struct ProgressStream {
+    rx: mpsc::Receiver<ProgressEvent>,
+}
+
+impl ProgressStream {
+    /// A stream and the sender for it, e.g. to report several sessions to one
+    /// stream or to hand to the receive flow.
+    pub fn channel() -> (ProgressSender, ProgressStream) {
+        let (tx, rx) = mpsc::channel(CAPACITY);
+        (ProgressSender { tx }, ProgressStream { rx })
+    }
+
+    /// The next event, `None` once the stream ended.
+    pub async fn recv(&mut self) -> Option<ProgressEvent> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for ProgressStream {
+    type Item = ProgressEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// The progress of a file, replaced by [`ProgressEvent`].
+#[deprecated(note = "consume a `ProgressStream` of `ProgressEvent`s instead")]
+#[derive(Debug)]
+pub struct UploadProgress {
+    pub file_id: String,
+    pub position: u64,
+    /// `Sending` until the last chunk arrived, which is `Finished`. Failed and skipped
+    /// files get a single event without a position
+    pub status: FileStatus,
+    /// Time since the transfer of this file started
+    pub elapsed: Duration,
+}
+
+#[allow(deprecated)]
+impl UploadProgress {
+    /// The final event of a file that sent no chunks, e.g. an empty or a failed one.
+    pub fn done(file_id: impl ToString, status: FileStatus) -> Self {
+        Self {
+            file_id: file_id.to_string(),
+            position: 0,
+            status,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status == FileStatus::Finished
+    }
+
+    /// Average bytes per second so far.
+    pub fn speed(&self) -> f64 {
+        throughput(self.position, self.elapsed)
+    }
+
+    /// The progress of a file event, `None` for [`ProgressEvent::SessionEnded`].
+    pub fn from_event(event: &ProgressEvent) -> Option<Self> {
+        let file_id = event.file_id()?.to_owned();
+        Some(Self {
+            file_id,
+            position: event.position(),
+            status: event.outcome().unwrap_or(FileStatus::Sending),
+            elapsed: event.elapsed(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+
+    use crate::send::FileStatus;
+
+    use super::{ProgressEvent, ProgressStream, CAPACITY};
+
+    #[tokio::test]
+    async fn test_progress_stream() {
+        let (tx, mut stream) = ProgressStream::channel();
+        let elapsed = Duration::from_secs(1);
+        tx.done("skipped", FileStatus::Skipped).await;
+        // more positions than fit while nobody reads, the file still ends
+        for position in 1..=(CAPACITY as u64 * 2) {
+            tx.position("a", position, 1000, elapsed).await;
+        }
+        let weak = tx.downgrade();
+        assert!(weak.upgrade().is_some());
+        // the stream is full, ending the file waits for the consumer
+        let producer = tokio::spawn(async move {
+            tx.position("a", 1000, 1000, elapsed).await;
+            tx.done("b", FileStatus::Failed).await;
+            tx.session_ended().await;
+        });
+
+        assert_eq!(
+            stream.next().await,
+            Some(ProgressEvent::Skipped {
+                file_id: "skipped".to_owned(),
+                reason: None,
+            })
+        );
+        let mut last = 0;
+        let mut positions = 0;
+        let finished = loop {
+            match stream.next().await.unwrap() {
+                ProgressEvent::Position { position, .. } => {
+                    assert!(position > last);
+                    last = position;
+                    positions += 1;
+                }
+                event => break event,
+            }
+        };
+        assert!(positions < CAPACITY * 2);
+        assert_eq!(finished.outcome(), Some(FileStatus::Finished));
+        assert_eq!(finished.position(), 1000);
+        assert_eq!(finished.speed(), 1000.0);
+        assert_eq!(
+            stream.next().await,
+            Some(ProgressEvent::Failed {
+                file_id: "b".to_owned()
+            })
+        );
+        assert_eq!(stream.next().await, Some(ProgressEvent::SessionEnded));
+        assert_eq!(stream.next().await, None);
+        producer.await.unwrap();
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufWriter},
+};
+
+use crate::util::fs::{normalize_file_name, NameRules};
+
+const TAR_BLOCK_SIZE: u64 = 512;
+const ZIP_LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP_CRC_OFFSET: u64 = 14;
+const ZIP_FLAG_UTF8: u16 = 0x0800;
+const ZIP_VERSION: u16 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "tar" => Some(ArchiveFormat::Tar),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ArchiveEntry {
+    name: String,
+    size: u64,
+    written: u64,
+    offset: u64,
+    hasher: crc32fast::Hasher,
+}
+
+#[derive(Debug)]
+struct ZipRecord {
+    name: String,
+    size: u32,
+    crc: u32,
+    offset: u32,
+}
+
+/// Writes received files into a single tar or zip (stored) archive.
+///
+/// Entries are streamed straight to disk, so only the zip central directory
+/// is kept in memory until the archive is finished.
+#[derive(Debug)]
+pub struct ArchiveWriter {
+    format: ArchiveFormat,
+    path: PathBuf,
+    file: BufWriter<File>,
+    position: u64,
+    entry: Option<ArchiveEntry>,
+    records: Vec<ZipRecord>,
+    mtime: u64,
+}
+
+impl ArchiveWriter {
+    pub async fn create(path: impl AsRef<Path>, format: ArchiveFormat) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::create(&path).await?;
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(Self {
+            format,
+            path,
+            file: BufWriter::new(file),
+            position: 0,
+            entry: None,
+            records: vec![],
+            mtime,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts an entry for a received file, `name` is made relative like the names of
+    /// files saved to disk, with `\` taken as a separator too.
+    pub async fn start_entry(&mut self, name: &str, size: u64) -> io::Result<()> {
+        let name = &entry_name(name);
+        if self.entry.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "previous archive entry is not finished",
+            ));
+        }
+        let offset = self.position;
+        let header = match self.format {
+            ArchiveFormat::Tar => tar_header(name, size, self.mtime),
+            ArchiveFormat::Zip => {
+                check_zip_limit(size)?;
+                check_zip_limit(offset)?;
+                zip_local_header(name, size, self.mtime)
+            }
+        };
+        self.write_raw(&header).await?;
+        self.entry = Some(ArchiveEntry {
+            name: name.to_string(),
+            size,
+            written: 0,
+            offset,
+            hasher: crc32fast::Hasher::new(),
+        });
+        Ok(())
+    }
+
+    /// Returns a writer for the data of the current entry.
+    pub fn entry_writer(&mut self) -> EntryWriter<'_> {
+        EntryWriter { archive: self }
+    }
+
+    pub async fn finish_entry(&mut self) -> io::Result<()> {
+        let entry = self
+            .entry
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no archive entry started"))?;
+        if entry.written != entry.size {
+            let offset = entry.offset;
+            self.truncate(offset).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "archive entry {} has {} bytes (expected: {})",
+                    entry.name, entry.written, entry.size
+                ),
+            ));
+        }
+
+        match self.format {
+            ArchiveFormat::Tar => {
+                let padding = (TAR_BLOCK_SIZE - entry.size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+                self.write_raw(&vec![0u8; padding as usize]).await?;
+            }
+            ArchiveFormat::Zip => {
+                let crc = entry.hasher.finalize();
+                self.file
+                    .seek(SeekFrom::Start(entry.offset + ZIP_CRC_OFFSET))
+                    .await?;
+                self.file.write_all(&crc.to_le_bytes()).await?;
+                self.file.seek(SeekFrom::Start(self.position)).await?;
+                self.records.push(ZipRecord {
+                    name: entry.name,
+                    size: entry.size as u32,
+                    crc,
+                    offset: entry.offset as u32,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the current entry, rewinding the archive to where it started.
+    pub async fn abort_entry(&mut self) -> io::Result<()> {
+        if let Some(entry) = self.entry.take() {
+            self.truncate(entry.offset).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the tar trailer or the zip central directory.
+    pub async fn finish(mut self) -> io::Result<PathBuf> {
+        self.abort_entry().await?;
+        match self.format {
+            ArchiveFormat::Tar => {
+                self.write_raw(&[0u8; 2 * TAR_BLOCK_SIZE as usize]).await?;
+            }
+            ArchiveFormat::Zip => {
+                let directory_offset = self.position;
+                check_zip_limit(directory_offset)?;
+                let records = std::mem::take(&mut self.records);
+                if records.len() > u16::MAX as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "too many entries for a zip archive, use tar instead",
+                    ));
+                }
+                for record in &records {
+                    let header = zip_central_header(record, self.mtime);
+                    self.write_raw(&header).await?;
+                }
+                let directory_size = self.position - directory_offset;
+                check_zip_limit(directory_size)?;
+
+                let mut eocd = Vec::with_capacity(22);
+                eocd.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+                eocd.extend_from_slice(&0u16.to_le_bytes()); // number of this disk
+                eocd.extend_from_slice(&0u16.to_le_bytes()); // disk with central directory
+                eocd.extend_from_slice(&(records.len() as u16).to_le_bytes());
+                eocd.extend_from_slice(&(records.len() as u16).to_le_bytes());
+                eocd.extend_from_slice(&(directory_size as u32).to_le_bytes());
+                eocd.extend_from_slice(&(directory_offset as u32).to_le_bytes());
+                eocd.extend_from_slice(&0u16.to_le_bytes()); // comment length
+                self.write_raw(&eocd).await?;
+            }
+        }
+        self.file.flush().await?;
+        Ok(self.path)
+    }
+
+    /// Discards the archive and removes it from disk.
+    pub async fn abort(self) -> io::Result<()> {
+        let path = self.path;
+        drop(self.file);
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn write_raw(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all(buf).await?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    async fn truncate(&mut self, offset: u64) -> io::Result<()> {
+        self.file.flush().await?;
+        self.file.get_ref().set_len(offset).await?;
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        self.position = offset;
+        Ok(())
+    }
+}
+
+/// Data writer of the entry currently being written to an [`ArchiveWriter`].
+pub struct EntryWriter<'a> {
+    archive: &'a mut ArchiveWriter,
+}
+
+impl<'a> AsyncWrite for EntryWriter<'a> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let archive = &mut *self.get_mut().archive;
+        let Some(entry) = archive.entry.as_mut() else {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "no archive entry started",
+            )));
+        };
+        if entry.written + buf.len() as u64 > entry.size {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("archive entry {} exceeds its size", entry.name),
+            )));
+        }
+        let result = Pin::new(&mut archive.file).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            entry.hasher.update(&buf[..len]);
+            entry.written += len as u64;
+            archive.position += len as u64;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().archive.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().archive.file).poll_flush(cx)
+    }
+}
+
+/// The name of an entry for a received file, it never leaves the directory the
+/// archive is extracted to, wherever that happens.
+fn entry_name(name: &str) -> String {
+    let entry = normalize_file_name(&name.replace('\\', "/"), NameRules::Unix, '_');
+    if entry != name {
+        log::warn!("Archiving {:?} as {:?}", name, entry);
+    }
+    entry
+}
+
+fn check_zip_limit(value: u64) -> io::Result<()> {
+    if value > u32::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "zip archives are limited to 4 GiB, use tar instead",
+        ));
+    }
+    Ok(())
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&octal.as_bytes()[octal.len() - digits..]);
+    field[digits] = 0;
+}
+
+fn tar_header_block(name: &[u8], prefix: &[u8], size: u64, mtime: u64, kind: u8) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name);
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime);
+    header[148..156].fill(b' ');
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix);
+
+    let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+    let checksum = format!("{:06o}\0 ", checksum);
+    header[148..156].copy_from_slice(checksum.as_bytes());
+    header
+}
+
+/// Splits `name` into the ustar prefix and name fields when it fits.
+fn split_ustar_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.char_indices()
+        .filter(|(_, c)| *c == '/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && !name.is_empty() && name.len() <= 100)
+}
+
+fn pax_record(key: &str, value: &str) -> String {
+    let record = format!(" {}={}\n", key, value);
+    let mut len = record.len();
+    loop {
+        let total = len.to_string().len() + record.len();
+        if total == len {
+            return format!("{}{}", len, record);
+        }
+        len = total;
+    }
+}
+
+fn tar_header(name: &str, size: u64, mtime: u64) -> Vec<u8> {
+    const MAX_USTAR_SIZE: u64 = 0o77777777777;
+
+    let mut header = vec![];
+    let ustar_name = split_ustar_name(name);
+    let mut pax = String::new();
+    if ustar_name.is_none() {
+        pax.push_str(&pax_record("path", name));
+    }
+    if size > MAX_USTAR_SIZE {
+        pax.push_str(&pax_record("size", &size.to_string()));
+    }
+    if !pax.is_empty() {
+        let pax_size = pax.len() as u64;
+        header.extend_from_slice(&tar_header_block(b"pax_header", b"", pax_size, mtime, b'x'));
+        header.extend_from_slice(pax.as_bytes());
+        let padding = (TAR_BLOCK_SIZE - pax_size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        header.resize(header.len() + padding as usize, 0);
+    }
+
+    let (prefix, name) = ustar_name.unwrap_or(("", name));
+    let name = &name.as_bytes()[..name.len().min(100)];
+    header.extend_from_slice(&tar_header_block(
+        name,
+        prefix.as_bytes(),
+        size.min(MAX_USTAR_SIZE),
+        mtime,
+        b'0',
+    ));
+    header
+}
+
+/// Converts unix seconds into the MS-DOS (time, date) pair used by zip.
+fn dos_date_time(unix_secs: u64) -> (u16, u16) {
+    let days = (unix_secs / 86400) as i64;
+    let secs = unix_secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((secs / 3600) << 11) | (((secs % 3600) / 60) << 5) | ((secs % 60) / 2);
+    let date = (((year - 1980) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+fn zip_local_header(name: &str, size: u64, mtime: u64) -> Vec<u8> {
+    let (time, date) = dos_date_time(mtime);
+    let mut header = Vec::with_capacity(30 + name.len());
+    header.extend_from_slice(&ZIP_LOCAL_HEADER_SIGNATURE.to_le_bytes());
+    header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+    header.extend_from_slice(&ZIP_FLAG_UTF8.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // stored
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&date.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // crc32, patched in finish_entry
+    header.extend_from_slice(&(size as u32).to_le_bytes());
+    header.extend_from_slice(&(size as u32).to_le_bytes());
+    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+    header.extend_from_slice(name.as_bytes());
+    header
+}
+
+fn zip_central_header(record: &ZipRecord, mtime: u64) -> Vec<u8> {
+    let (time, date) = dos_date_time(mtime);
+    let mut header = Vec::with_capacity(46 + record.name.len());
+    header.extend_from_slice(&ZIP_CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+    header.extend_from_slice(&ZIP_VERSION.to_le_bytes()); // version made by
+    header.extend_from_slice(&ZIP_VERSION.to_le_bytes()); // version needed
+    header.extend_from_slice(&ZIP_FLAG_UTF8.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // stored
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&date.to_le_bytes());
+    header.extend_from_slice(&record.crc.to_le_bytes());
+    header.extend_from_slice(&record.size.to_le_bytes());
+    header.extend_from_slice(&record.size.to_le_bytes());
+    header.extend_from_slice(&(record.name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+    header.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    header.extend_from_slice(&0u16.to_le_bytes()); // disk number start
+    header.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+    header.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+    header.extend_from_slice(&record.offset.to_le_bytes());
+    header.extend_from_slice(record.name.as_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names and contents of archived files.
+    type Entries = Vec<(String, Vec<u8>)>;
+
+    fn le16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn le32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// The text of a NUL padded header field.
+    fn field(bytes: &[u8]) -> &str {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        std::str::from_utf8(&bytes[..end]).unwrap()
+    }
+
+    fn octal(bytes: &[u8]) -> u64 {
+        u64::from_str_radix(field(bytes).trim(), 8).unwrap()
+    }
+
+    /// The entries of a tar archive, checking the header checksums and the trailer.
+    fn read_tar(bytes: &[u8]) -> Entries {
+        assert_eq!(bytes.len() as u64 % TAR_BLOCK_SIZE, 0);
+        let mut entries = vec![];
+        let mut pax_path = None;
+        let mut offset = 0;
+        loop {
+            let header = &bytes[offset..offset + 512];
+            if header.iter().all(|b| *b == 0) {
+                assert_eq!(&bytes[offset..], &[0u8; 1024]);
+                return entries;
+            }
+            let mut unsigned = header.to_vec();
+            unsigned[148..156].fill(b' ');
+            let checksum: u32 = unsigned.iter().map(|b| *b as u32).sum();
+            assert_eq!(octal(&header[148..155]), checksum as u64);
+            assert_eq!(&header[257..263], b"ustar\0");
+
+            let size = octal(&header[124..136]) as usize;
+            let data = &bytes[offset + 512..offset + 512 + size];
+            offset += 512 + size.div_ceil(512) * 512;
+            match header[156] {
+                b'x' => {
+                    let records = std::str::from_utf8(data).unwrap();
+                    pax_path = records.lines().find_map(|record| {
+                        let (_, record) = record.split_once(' ')?;
+                        record.strip_prefix("path=").map(str::to_owned)
+                    });
+                }
+                b'0' => {
+                    let name = pax_path.take().unwrap_or_else(|| {
+                        match (field(&header[345..500]), field(&header[..100])) {
+                            ("", name) => name.to_owned(),
+                            (prefix, name) => format!("{}/{}", prefix, name),
+                        }
+                    });
+                    entries.push((name, data.to_vec()));
+                }
+                kind => panic!("unexpected entry type {}", kind),
+            }
+        }
+    }
+
+    /// The entries of a zip archive by its central directory, checking the local
+    /// headers and the CRCs of the data.
+    fn read_zip(bytes: &[u8]) -> Entries {
+        let eocd = &bytes[bytes.len() - 22..];
+        assert_eq!(le32(eocd, 0), ZIP_END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        let count = le16(eocd, 10) as usize;
+        assert_eq!(le16(eocd, 8) as usize, count);
+        let mut offset = le32(eocd, 16) as usize;
+        assert_eq!(offset + le32(eocd, 12) as usize, bytes.len() - 22);
+
+        let mut entries = vec![];
+        for _ in 0..count {
+            let central = &bytes[offset..];
+            assert_eq!(le32(central, 0), ZIP_CENTRAL_HEADER_SIGNATURE);
+            let (crc, size) = (le32(central, 16), le32(central, 20) as usize);
+            let name_len = le16(central, 28) as usize;
+            let name = &central[46..46 + name_len];
+            offset += 46 + name_len;
+
+            let local = &bytes[le32(central, 42) as usize..];
+            assert_eq!(le32(local, 0), ZIP_LOCAL_HEADER_SIGNATURE);
+            assert_eq!(le32(local, 14), crc);
+            assert_eq!(&local[30..30 + name_len], name);
+            let data = &local[30 + name_len..30 + name_len + size];
+            assert_eq!(crc32fast::hash(data), crc);
+            entries.push((String::from_utf8(name.to_vec()).unwrap(), data.to_vec()));
+        }
+        entries
+    }
+
+    /// Archives these files and an aborted one, returning the archive's bytes.
+    async fn archive(format: ArchiveFormat, files: &Entries) -> Vec<u8> {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let mut archive = ArchiveWriter::create(&path, format).await.unwrap();
+        for (index, (name, content)) in files.iter().enumerate() {
+            archive
+                .start_entry(name, content.len() as u64)
+                .await
+                .unwrap();
+            archive.entry_writer().write_all(content).await.unwrap();
+            archive.finish_entry().await.unwrap();
+            if index == 0 {
+                archive.start_entry("partial.bin", 100).await.unwrap();
+                archive.entry_writer().write_all(b"cut").await.unwrap();
+                archive.abort_entry().await.unwrap();
+            }
+        }
+        assert_eq!(archive.finish().await.unwrap(), path);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(path).ok();
+        bytes
+    }
+
+    /// Files to archive and the entries expected for them, in order.
+    fn round_trip_files() -> (Entries, Entries) {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(110));
+        let files = [
+            ("a.txt", "a.txt", b"hello".to_vec()),
+            (
+                "dir/b.bin",
+                "dir/b.bin",
+                (0..1000).map(|i| i as u8).collect(),
+            ),
+            ("empty", "empty", vec![]),
+            (&long, &long, b"long".to_vec()),
+            ("../evil.txt", "evil.txt", b"evil".to_vec()),
+            ("/etc/cron.d/x", "etc/cron.d/x", b"cron".to_vec()),
+            ("..\\..\\win.txt", "win.txt", b"win".to_vec()),
+        ];
+        files
+            .into_iter()
+            .map(|(name, entry, content)| {
+                (
+                    (name.to_owned(), content.clone()),
+                    (entry.to_owned(), content),
+                )
+            })
+            .unzip()
+    }
+
+    #[tokio::test]
+    async fn test_tar_round_trip() {
+        let (files, expected) = round_trip_files();
+        let bytes = archive(ArchiveFormat::Tar, &files).await;
+        assert_eq!(read_tar(&bytes), expected);
+    }
+
+    #[tokio::test]
+    async fn test_zip_round_trip() {
+        let (files, expected) = round_trip_files();
+        let bytes = archive(ArchiveFormat::Zip, &files).await;
+        assert_eq!(read_zip(&bytes), expected);
+    }
+
+    #[tokio::test]
+    async fn test_abort_removes_archive() {
+        let path = std::env::temp_dir().join(format!("{}.zip", uuid::Uuid::new_v4()));
+        let mut archive = ArchiveWriter::create(&path, ArchiveFormat::Zip)
+            .await
+            .unwrap();
+        archive.start_entry("a.txt", 5).await.unwrap();
+        archive.entry_writer().write_all(b"hel").await.unwrap();
+        archive.abort().await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_pax_record_length() {
+        assert_eq!(pax_record("path", "a"), "9 path=a\n");
+        let record = pax_record("path", &"x".repeat(95));
+        assert_eq!(record.len().to_string(), record.split(' ').next().unwrap());
+    }
+
+    #[test]
+    fn test_split_ustar_name() {
+        assert_eq!(split_ustar_name("a/b.txt"), Some(("", "a/b.txt")));
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(20));
+        assert_eq!(
+            split_ustar_name(&long),
+            Some(("d".repeat(120).as_str(), "f".repeat(20).as_str()))
+        );
+        assert_eq!(split_ustar_name(&"f".repeat(120)), None);
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2024-02-29 12:34:56 UTC
+        let (time, date) = dos_date_time(1709210096);
+        assert_eq!(date, ((2024 - 1980) << 9) | (2 << 5) | 29);
+        assert_eq!(time, (12 << 11) | (34 << 5) | 28);
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use localsend_proto::{dto::FileDto, Device};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    send::FileStatus,
+    util::hash::{HashAlgorithm, Hasher},
+};
+
+use super::{ReceiveSink, SinkWriter};
+
+/// What an audited session received for one file, a line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    /// When the body ended, RFC 3339 in UTC
+    pub time: String,
+    pub session_id: String,
+    pub sender_alias: String,
+    pub sender_fingerprint: String,
+    pub sender_ip: String,
+    pub file_name: String,
+    /// Size announced by the sender
+    pub size: u64,
+    /// Bytes actually received
+    pub bytes: u64,
+    /// Of the received bytes, only for finished files
+    pub sha256: Option<String>,
+    pub duration_secs: f64,
+    pub status: FileStatus,
+}
+
+#[derive(Debug)]
+struct Audit {
+    hasher: Hasher,
+    bytes: u64,
+    started: Instant,
+}
+
+/// Receives bodies like any other sink but keeps nothing, only what arrived.
+///
+/// Every file is counted and hashed, then logged and appended to the audit log
+/// as an [`AuditRecord`], so the whole protocol runs without touching the disk.
+#[derive(Debug)]
+pub struct AuditSink {
+    session_id: String,
+    sender: Device,
+    log: Option<PathBuf>,
+    files: Mutex<HashMap<String, Arc<Mutex<Audit>>>>,
+}
+
+impl AuditSink {
+    pub fn new(session_id: impl ToString, sender: &Device, log: Option<PathBuf>) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            sender: sender.clone(),
+            log,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn record(&self, file: &FileDto, status: FileStatus) -> io::Result<()> {
+        let Some(audit) = self.files.lock().unwrap().remove(&file.id) else {
+            return Ok(());
+        };
+        let (hasher, bytes, started) = {
+            let mut audit = audit.lock().unwrap();
+            let hasher = std::mem::replace(&mut audit.hasher, HashAlgorithm::Sha256.hasher());
+            (hasher, audit.bytes, audit.started)
+        };
+        let sha256 = (status == FileStatus::Finished).then(|| hasher.finalize().into());
+        let record = AuditRecord {
+            time: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            session_id: self.session_id.clone(),
+            sender_alias: self.sender.alias.clone(),
+            sender_fingerprint: self.sender.fingerprint.clone(),
+            sender_ip: self.sender.ip.clone(),
+            file_name: file.file_name.clone(),
+            size: file.size,
+            bytes,
+            sha256,
+            duration_secs: started.elapsed().as_secs_f64(),
+            status,
+        };
+        log::info!(
+            "Audited {:?} from {} ({}): {} of {} bytes in {:.2}s, sha256 {}",
+            record.file_name,
+            record.sender_alias,
+            record.sender_ip,
+            record.bytes,
+            record.size,
+            record.duration_secs,
+            record.sha256.as_deref().unwrap_or("-"),
+        );
+        let Some(path) = &self.log else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(&record).map_err(io::Error::from)?;
+        line.push(b'\n');
+        let mut log = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        log.write_all(&line).await
+    }
+}
+
+#[async_trait]
+impl ReceiveSink for AuditSink {
+    async fn open(&self, file: &FileDto) -> io::Result<SinkWriter> {
+        let audit = Arc::new(Mutex::new(Audit {
+            hasher: HashAlgorithm::Sha256.hasher(),
+            bytes: 0,
+            started: Instant::now(),
+        }));
+        self.files
+            .lock()
+            .unwrap()
+            .insert(file.id.clone(), audit.clone());
+        Ok(Box::pin(AuditWriter(audit)))
+    }
+
+    async fn finish(&self, file: &FileDto) -> io::Result<Option<PathBuf>> {
+        self.record(file, FileStatus::Finished).await?;
+        Ok(None)
+    }
+
+    async fn abort(&self, file: &FileDto) {
+        if let Err(e) = self.record(file, FileStatus::Failed).await {
+            log::warn!("Failed to audit {:?}: {}", file.file_name, e);
+        }
+    }
+}
+
+struct AuditWriter(Arc<Mutex<Audit>>);
+
+impl AsyncWrite for AuditWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut audit = self.0.lock().unwrap();
+        audit.hasher.update(buf);
+        audit.bytes += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
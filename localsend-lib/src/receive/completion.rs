@@ -0,0 +1,288 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::io::AsyncWriteExt;
+
+use crate::util::hash::FileHash;
+
+use super::{ReceiveHook, ReceivedFileInfo};
+
+/// Appended to the name of a saved file to name its marker.
+pub const MARKER_SUFFIX: &str = ".localsend-complete";
+
+/// What tools waiting for received files learn about one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionRecord {
+    pub path: PathBuf,
+    /// Size of the saved file
+    pub size: u64,
+    /// The digest the sender announced, the body matched it
+    pub hash: Option<String>,
+    pub sender_alias: String,
+    pub session_id: String,
+    /// When the file was complete, RFC 3339 in UTC
+    pub time: String,
+}
+
+impl CompletionRecord {
+    /// Describes the file of `info`, `None` when the sink kept none.
+    async fn new(info: &ReceivedFileInfo) -> io::Result<Option<Self>> {
+        let Some(path) = &info.path else {
+            return Ok(None);
+        };
+        let size = tokio::fs::metadata(path).await?.len();
+        Ok(Some(Self {
+            path: path.clone(),
+            size,
+            hash: info
+                .file
+                .hash
+                .as_deref()
+                .and_then(FileHash::parse)
+                .map(String::from),
+            sender_alias: info.sender.alias.clone(),
+            session_id: info.session_id.clone(),
+            time: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+/// Where the marker of the file at `path` is written.
+pub fn marker_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(MARKER_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Writes a [`CompletionRecord`] to `<name>.localsend-complete` next to every saved file.
+///
+/// Hooks only run for files saved completely, so a marker never describes a failed
+/// or cancelled file. The marker is renamed into place, it is never seen half written.
+#[derive(Debug, Default)]
+pub struct CompletionMarker;
+
+#[async_trait]
+impl ReceiveHook for CompletionMarker {
+    async fn on_file_received(&self, info: &ReceivedFileInfo) -> Result<(), String> {
+        let write = async {
+            let Some(record) = CompletionRecord::new(info).await? else {
+                return Ok(());
+            };
+            let marker = marker_path(&record.path);
+            let mut partial = marker.clone().into_os_string();
+            partial.push(".part");
+            let json = serde_json::to_vec(&record).map_err(io::Error::from)?;
+            tokio::fs::write(&partial, json).await?;
+            tokio::fs::rename(&partial, &marker).await
+        };
+        write
+            .await
+            .map_err(|e: io::Error| format!("completion marker: {}", e))
+    }
+}
+
+/// Writes a [`CompletionRecord`] per saved file as a JSON line to a named pipe.
+///
+/// Records are dropped while no consumer has the pipe open, other files are
+/// appended to.
+#[derive(Debug)]
+pub struct CompletionFifo {
+    path: PathBuf,
+}
+
+impl CompletionFifo {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    #[cfg(unix)]
+    async fn write_line(&self, line: &[u8]) -> io::Result<()> {
+        let is_fifo = {
+            use std::os::unix::fs::FileTypeExt;
+            tokio::fs::metadata(&self.path).await?.file_type().is_fifo()
+        };
+        if !is_fifo {
+            return self.append_line(line).await;
+        }
+        let mut sender = match tokio::net::unix::pipe::OpenOptions::new().open_sender(&self.path) {
+            Ok(sender) => sender,
+            // ENXIO, nobody reads the pipe
+            Err(e) if e.raw_os_error() == Some(6) => {
+                log::debug!("No reader on {:?}, dropping the record", self.path);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        sender.write_all(line).await
+    }
+
+    #[cfg(not(unix))]
+    async fn write_line(&self, line: &[u8]) -> io::Result<()> {
+        self.append_line(line).await
+    }
+
+    async fn append_line(&self, line: &[u8]) -> io::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line).await?;
+        // tokio finishes the write in the background otherwise
+        file.flush().await
+    }
+}
+
+#[async_trait]
+impl ReceiveHook for CompletionFifo {
+    async fn on_file_received(&self, info: &ReceivedFileInfo) -> Result<(), String> {
+        let write = async {
+            let Some(record) = CompletionRecord::new(info).await? else {
+                return Ok(());
+            };
+            let mut line = serde_json::to_vec(&record).map_err(io::Error::from)?;
+            line.push(b'\n');
+            self.write_line(&line).await
+        };
+        write
+            .await
+            .map_err(|e: io::Error| format!("completion fifo {:?}: {}", self.path, e))
+    }
+}
+
+/// Removes the markers below `dir` last modified more than `max_age` ago, returns
+/// how many were removed.
+pub async fn sweep_markers(dir: &Path, max_age: Duration) -> usize {
+    let dir = dir.to_path_buf();
+    let sweep = tokio::task::spawn_blocking(move || {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        let entries = walkdir::WalkDir::new(&dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok());
+        for entry in entries {
+            let is_marker = entry.file_type().is_file()
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.ends_with(MARKER_SUFFIX));
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            let stale = modified
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > max_age);
+            if !is_marker || !stale {
+                continue;
+            }
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(e) => log::warn!("Failed to remove marker {:?}: {}", entry.path(), e),
+            }
+        }
+        removed
+    });
+    sweep.await.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use localsend_proto::{
+        dto::{FileDto, FileType},
+        fixtures::device,
+    };
+
+    use crate::receive::{ReceiveHook, ReceivedFileInfo};
+
+    use super::{marker_path, sweep_markers, CompletionFifo, CompletionMarker, CompletionRecord};
+
+    fn info(path: Option<std::path::PathBuf>) -> ReceivedFileInfo {
+        ReceivedFileInfo {
+            session_id: "session".to_owned(),
+            sender: device("phone", 53317),
+            file: FileDto {
+                id: "1".to_owned(),
+                file_name: "photo.jpg".to_owned(),
+                size: 3,
+                file_type: FileType::Image,
+                hash: Some("5D41402ABC4B2A76B9719D911017C592".to_owned()),
+                preview: None,
+            },
+            path,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_completion_marker() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("photo.jpg");
+        std::fs::write(&path, b"jpg").unwrap();
+
+        CompletionMarker
+            .on_file_received(&info(Some(path.clone())))
+            .await
+            .unwrap();
+        let marker = marker_path(&path);
+        assert_eq!(marker, dir.join("photo.jpg.localsend-complete"));
+        let record: CompletionRecord =
+            serde_json::from_slice(&std::fs::read(&marker).unwrap()).unwrap();
+        assert_eq!(record.path, path);
+        assert_eq!(record.size, 3);
+        assert_eq!(
+            record.hash.as_deref(),
+            Some("5d41402abc4b2a76b9719d911017c592")
+        );
+        assert_eq!(record.sender_alias, "phone");
+
+        // files not kept by the sink have no marker
+        CompletionMarker
+            .on_file_received(&info(None))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // fresh markers stay
+        assert_eq!(sweep_markers(&dir, Duration::from_secs(60)).await, 0);
+        assert_eq!(sweep_markers(&dir, Duration::ZERO).await, 1);
+        assert!(!marker.exists());
+        assert!(path.exists());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_completion_fifo_file() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("photo.jpg");
+        std::fs::write(&path, b"jpg").unwrap();
+        let log = dir.join("completed.jsonl");
+        std::fs::write(&log, b"").unwrap();
+
+        let fifo = CompletionFifo::new(&log);
+        for _ in 0..2 {
+            fifo.on_file_received(&info(Some(path.clone())))
+                .await
+                .unwrap();
+        }
+        let content = std::fs::read_to_string(&log).unwrap();
+        let records: Vec<CompletionRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].path, path);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,199 @@
+use std::{path::PathBuf, sync::Mutex};
+
+use async_trait::async_trait;
+use localsend_proto::{dto::FileDto, Device};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    oneshot,
+};
+
+use crate::{
+    progress::ProgressSender,
+    server::{ClientMessage, ServerMessage},
+};
+
+use super::PreviewFile;
+
+/// The answer to a prepare-upload request.
+#[derive(Debug, Clone)]
+pub enum Decision {
+    /// Receive these files, the others offered are skipped
+    Accept(Vec<FileDto>),
+    Decline,
+    /// Nobody answered, e.g. a prompt that was left open
+    Timeout,
+}
+
+/// Decides which of the offered files are received.
+///
+/// [`ReceiveDecider::decide`] is called once per prepare-upload, without holding
+/// the server state, and is given up on after `Settings::decision_timeout`.
+#[async_trait]
+pub trait ReceiveDecider: Send + Sync {
+    async fn decide(&self, sender: Device, files: Vec<FileDto>) -> Decision;
+
+    /// Returns the ids of the quarantined files to keep once their session finished,
+    /// see `Settings::preview_dir`. Everything is kept by default and when no answer
+    /// arrives within `Settings::decision_timeout`.
+    async fn review(&self, _sender: Device, files: Vec<PreviewFile>) -> Vec<String> {
+        files.into_iter().map(|preview| preview.file.id).collect()
+    }
+
+    /// Receives the progress of the files accepted by the last decision.
+    fn progress_tx(&self) -> Option<ProgressSender> {
+        None
+    }
+
+    /// Where the files accepted by the last decision are saved instead of the
+    /// destination of the settings, e.g. a directory the user picked for them.
+    fn destination(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Accepts every file without asking, used for quick save.
+#[derive(Debug, Default)]
+pub struct AcceptAll;
+
+#[async_trait]
+impl ReceiveDecider for AcceptAll {
+    async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+        Decision::Accept(files)
+    }
+}
+
+/// Asks through [`ServerMessage::SelectedFiles`] and waits for the [`ClientMessage`]
+/// answering it, the default decider.
+#[derive(Debug)]
+pub struct ChannelDecider {
+    server_tx: Sender<ServerMessage>,
+    client_rx: tokio::sync::Mutex<Receiver<ClientMessage>>,
+    progress_tx: Mutex<Option<ProgressSender>>,
+    destination: Mutex<Option<PathBuf>>,
+}
+
+impl ChannelDecider {
+    pub fn new(server_tx: Sender<ServerMessage>, client_rx: Receiver<ClientMessage>) -> Self {
+        Self {
+            server_tx,
+            client_rx: tokio::sync::Mutex::new(client_rx),
+            progress_tx: Mutex::new(None),
+            destination: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl ReceiveDecider for ChannelDecider {
+    async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+        let mut client_rx = self.client_rx.lock().await;
+        // answers arriving after an earlier decision timed out belong to no request
+        while client_rx.try_recv().is_ok() {}
+        if self
+            .server_tx
+            .send(ServerMessage::SelectedFiles(files))
+            .await
+            .is_err()
+        {
+            return Decision::Decline;
+        }
+        match client_rx.recv().await {
+            Some(ClientMessage::FilesSelected(progress_tx, files, destination)) => {
+                *self.progress_tx.lock().unwrap() = Some(progress_tx);
+                *self.destination.lock().unwrap() = destination;
+                Decision::Accept(files)
+            }
+            Some(ClientMessage::Declined) | None => Decision::Decline,
+            Some(ClientMessage::FilesReviewed(_)) => {
+                log::warn!("Unexpected review answer to a file selection");
+                Decision::Decline
+            }
+        }
+    }
+
+    async fn review(&self, _sender: Device, files: Vec<PreviewFile>) -> Vec<String> {
+        let ids = files
+            .iter()
+            .map(|preview| preview.file.id.clone())
+            .collect();
+        let mut client_rx = self.client_rx.lock().await;
+        while client_rx.try_recv().is_ok() {}
+        if self
+            .server_tx
+            .send(ServerMessage::ReviewFiles(files))
+            .await
+            .is_err()
+        {
+            return ids;
+        }
+        match client_rx.recv().await {
+            Some(ClientMessage::FilesReviewed(kept)) => kept,
+            Some(ClientMessage::Declined) => vec![],
+            // received files are not lost to a client that went away
+            Some(ClientMessage::FilesSelected(..)) | None => ids,
+        }
+    }
+
+    fn progress_tx(&self) -> Option<ProgressSender> {
+        self.progress_tx.lock().unwrap().take()
+    }
+
+    fn destination(&self) -> Option<PathBuf> {
+        self.destination.lock().unwrap().take()
+    }
+}
+
+/// Files offered by a sender, waiting for an answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingOffer {
+    pub sender: Device,
+    pub files: Vec<FileDto>,
+}
+
+/// Keeps offers pending until [`PendingDecider::respond`] answers them, e.g. from
+/// the control socket of a daemon.
+#[derive(Debug, Default)]
+pub struct PendingDecider {
+    pending: Mutex<Option<(PendingOffer, oneshot::Sender<bool>)>>,
+}
+
+impl PendingDecider {
+    /// The offer waiting for an answer, if any.
+    pub fn pending(&self) -> Option<PendingOffer> {
+        let pending = self.pending.lock().unwrap();
+        // given up on after the decision timeout
+        let (offer, _) = pending.as_ref().filter(|(_, tx)| !tx.is_closed())?;
+        Some(offer.clone())
+    }
+
+    /// Accepts all files of the pending offer or declines it, `false` when none is pending.
+    pub fn respond(&self, accept: bool) -> bool {
+        match self.pending.lock().unwrap().take() {
+            Some((_, tx)) => tx.send(accept).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl ReceiveDecider for PendingDecider {
+    async fn decide(&self, sender: Device, files: Vec<FileDto>) -> Decision {
+        log::info!(
+            "{:?} offers {} files, waiting for an answer",
+            sender.alias,
+            files.len()
+        );
+        let (tx, rx) = oneshot::channel();
+        let offer = PendingOffer {
+            sender,
+            files: files.clone(),
+        };
+        *self.pending.lock().unwrap() = Some((offer, tx));
+        match rx.await {
+            Ok(true) => Decision::Accept(files),
+            Ok(false) | Err(_) => Decision::Decline,
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::hash::FileHash;
+
+/// Kept in the data directory by the CLI.
+pub const DEDUP_INDEX_FILE: &str = "dedup-index.json";
+
+/// What happens to an offered file whose content was received before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupAction {
+    /// Do not receive it, the report points to the file already there
+    #[default]
+    Skip,
+    /// Hard link the file already there to the offered name, copying across file systems
+    Link,
+    /// Copy the file already there to the offered name
+    Copy,
+}
+
+impl FromStr for DedupAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(DedupAction::Skip),
+            "link" => Ok(DedupAction::Link),
+            "copy" => Ok(DedupAction::Copy),
+            _ => Err(format!("unknown dedup action: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct IndexEntry {
+    path: PathBuf,
+    size: u64,
+}
+
+/// Where the files received before are, by the digest their sender announced.
+///
+/// Only files whose body matched their digest are added, files sent without one
+/// are always received.
+#[derive(Debug)]
+pub struct DedupIndex {
+    path: PathBuf,
+    entries: HashMap<String, IndexEntry>,
+}
+
+impl DedupIndex {
+    /// Reads the index kept at `path`, a missing or unreadable index starts empty.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+                log::warn!("Ignoring invalid dedup index {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                log::warn!("Failed to read dedup index {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+        Self { path, entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The file received before with this digest, if it is still there with `size` bytes.
+    pub fn lookup(&self, hash: &str, size: u64) -> Option<&Path> {
+        let hash = FileHash::parse(hash)?;
+        let entry = self.entries.get(hash.hex())?;
+        (entry.size == size && is_intact(entry)).then_some(entry.path.as_path())
+    }
+
+    pub fn insert(&mut self, hash: &str, path: impl AsRef<Path>, size: u64) {
+        let Some(hash) = FileHash::parse(hash) else {
+            return;
+        };
+        // the working directory of the next run may differ
+        let path = path.as_ref();
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let entry = IndexEntry { path, size };
+        self.entries.insert(hash.into(), entry);
+    }
+
+    /// Drops the entries whose files were deleted or changed, returns how many.
+    pub fn prune(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| is_intact(entry));
+        before - self.entries.len()
+    }
+
+    /// Writes the index next to its path first, so a crash keeps the previous one.
+    pub async fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let json = serde_json::to_vec(&self.entries).map_err(io::Error::from)?;
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &self.path).await
+    }
+}
+
+fn is_intact(entry: &IndexEntry) -> bool {
+    std::fs::metadata(&entry.path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.len() == entry.size)
+}
+
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Puts the content of `existing` at `target` according to `action`.
+///
+/// Nothing is written when `target` already is `existing`.
+pub(crate) async fn place_duplicate(
+    existing: &Path,
+    target: &Path,
+    action: DedupAction,
+) -> io::Result<()> {
+    if is_same_file(existing, target) {
+        return Ok(());
+    }
+    if let Some(dir) = target.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    match action {
+        DedupAction::Skip => Ok(()),
+        DedupAction::Link => {
+            tokio::fs::remove_file(target).await.ok();
+            if let Err(e) = tokio::fs::hard_link(existing, target).await {
+                log::warn!("Failed to link {:?}, copying it: {}", existing, e);
+                tokio::fs::copy(existing, target).await?;
+            }
+            Ok(())
+        }
+        DedupAction::Copy => tokio::fs::copy(existing, target).await.map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{place_duplicate, DedupAction, DedupIndex};
+    use crate::util::hash::FileHash;
+
+    #[tokio::test]
+    async fn test_dedup_index() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let photo = dir.join("photo.jpg");
+        std::fs::write(&photo, "photo").unwrap();
+        let hash = FileHash::sha256("photo").to_string();
+
+        let mut index = DedupIndex::load(dir.join("index.json"));
+        assert!(index.is_empty());
+        index.insert(&hash.to_ascii_uppercase(), &photo, 5);
+        index.save().await.unwrap();
+
+        let mut index = DedupIndex::load(dir.join("index.json"));
+        assert_eq!(index.lookup(&hash, 5), Some(photo.as_path()));
+        assert_eq!(index.lookup(&hash, 6), None);
+
+        let copy = dir.join("copy/photo.jpg");
+        place_duplicate(&photo, &copy, DedupAction::Link)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "photo");
+        // the file already at the offered name is kept as it is
+        place_duplicate(&photo, &photo, DedupAction::Copy)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&photo).unwrap(), "photo");
+
+        std::fs::remove_file(&photo).unwrap();
+        assert_eq!(index.lookup(&hash, 5), None);
+        assert_eq!(index.prune(), 1);
+        assert_eq!(index.len(), 0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
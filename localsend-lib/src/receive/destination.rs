@@ -0,0 +1,333 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use localsend_proto::Device;
+use thiserror::Error;
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::util::fs::{available_space, normalize_file_name, NameRules};
+
+/// Placeholders a destination may contain, e.g. `~/incoming/{alias}/{date}`.
+pub const DESTINATION_PLACEHOLDERS: [&str; 5] =
+    ["alias", "fingerprint", "date", "time", "sessionId"];
+
+/// Fails on placeholders of `destination` that are not in [`DESTINATION_PLACEHOLDERS`].
+pub fn validate_destination(destination: &Path) -> Result<(), String> {
+    let Some(template) = destination.to_str() else {
+        return Ok(());
+    };
+    for name in placeholders(template) {
+        if !DESTINATION_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{}}} in destination, expected one of {}",
+                name,
+                DESTINATION_PLACEHOLDERS
+                    .map(|name| format!("{{{}}}", name))
+                    .join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Why files can not be saved to a destination.
+#[derive(Error, Debug)]
+pub enum DestinationError {
+    #[error("{} does not exist", .0.display())]
+    Missing(PathBuf),
+    #[error("{} can not be created: {1}", .0.display())]
+    CreateFailed(PathBuf, io::Error),
+    #[error("{} is not a directory", .0.display())]
+    NotADirectory(PathBuf),
+    #[error("{} is not writable: {1}", .0.display())]
+    NotWritable(PathBuf, io::Error),
+    #[error("{} has no space left", .0.display())]
+    NoSpace(PathBuf),
+}
+
+/// Checks that files can be saved in `dir`, creating it with `create`, and returns
+/// its canonical path.
+///
+/// A probe file is created and removed, so a read-only or missing mount fails here
+/// rather than with the first file received.
+pub fn check_destination(dir: &Path, create: bool) -> Result<PathBuf, DestinationError> {
+    if create && !dir.exists() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| DestinationError::CreateFailed(dir.to_path_buf(), e))?;
+    }
+    let dir = match std::fs::canonicalize(dir) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(DestinationError::Missing(dir.to_path_buf()))
+        }
+        Err(e) => return Err(DestinationError::NotWritable(dir.to_path_buf(), e)),
+    };
+    if !dir.is_dir() {
+        return Err(DestinationError::NotADirectory(dir));
+    }
+    let probe = dir.join(format!(
+        ".localsend-probe-{}",
+        uuid::Uuid::new_v4().simple()
+    ));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            std::fs::remove_file(&probe).ok();
+        }
+        Err(e) => return Err(DestinationError::NotWritable(dir, e)),
+    }
+    if available_space(&dir) == Some(0) {
+        return Err(DestinationError::NoSpace(dir));
+    }
+    Ok(dir)
+}
+
+/// [`check_destination`] for a directory created by the first file saved, without
+/// creating anything: the directory or else its closest existing parent is checked.
+pub fn check_destination_parent(dir: &Path) -> Result<(), DestinationError> {
+    for ancestor in dir.ancestors() {
+        let ancestor = match ancestor.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => ancestor,
+        };
+        match std::fs::metadata(ancestor) {
+            Ok(_) => return check_destination(ancestor, false).map(|_| ()),
+            // missing, or below a file which the parents tell
+            Err(_) => continue,
+        }
+    }
+    Err(DestinationError::Missing(dir.to_path_buf()))
+}
+
+/// The directory a session of `sender` saves to.
+///
+/// Values sent by the sender are made valid names for `rules`, so they never add
+/// path components. Dates and times are local when the offset is known, UTC otherwise.
+pub fn resolve_destination(
+    destination: &Path,
+    sender: &Device,
+    session_id: &str,
+    rules: NameRules,
+    replacement: char,
+) -> PathBuf {
+    let Some(template) = destination.to_str() else {
+        return destination.to_path_buf();
+    };
+    if placeholders(template).next().is_none() {
+        return destination.to_path_buf();
+    }
+
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let mut resolved = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some((start, end)) = next_placeholder(rest) {
+        resolved.push_str(&rest[..start]);
+        let value = match &rest[start + 1..end] {
+            "alias" => sanitize(&sender.alias, rules, replacement),
+            "fingerprint" => sanitize(&sender.fingerprint, rules, replacement),
+            "date" => now
+                .format(format_description!("[year]-[month]-[day]"))
+                .unwrap_or_default(),
+            "time" => now
+                .format(format_description!("[hour]-[minute]-[second]"))
+                .unwrap_or_default(),
+            "sessionId" => session_id.to_owned(),
+            // rejected by validate_destination
+            _ => rest[start..=end].to_owned(),
+        };
+        resolved.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    resolved.push_str(rest);
+    PathBuf::from(resolved)
+}
+
+/// The directories of `destination` before its first placeholder, where all sessions save below.
+pub fn destination_root(destination: &Path) -> PathBuf {
+    let Some(template) = destination.to_str() else {
+        return destination.to_path_buf();
+    };
+    let Some((start, _)) = next_placeholder(template) else {
+        return destination.to_path_buf();
+    };
+    let prefix = &template[..start];
+    let root = match prefix.ends_with(std::path::is_separator) {
+        true => Path::new(prefix),
+        // the placeholder is part of a name
+        false => Path::new(prefix).parent().unwrap_or(Path::new("")),
+    };
+    match root.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => root.to_path_buf(),
+    }
+}
+
+/// Turns a value into a single path component.
+fn sanitize(value: &str, rules: NameRules, replacement: char) -> String {
+    let value = value.replace(['/', '\\'], &replacement.to_string());
+    normalize_file_name(&value, rules, replacement)
+}
+
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    let mut rest = template;
+    std::iter::from_fn(move || {
+        let (start, end) = next_placeholder(rest)?;
+        let name = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        Some(name)
+    })
+}
+
+/// Byte offsets of the braces of the next `{name}` in `template`.
+fn next_placeholder(template: &str) -> Option<(usize, usize)> {
+    let start = template.find('{')?;
+    let end = start + template[start..].find('}')?;
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use localsend_proto::{fixtures::device, Device};
+
+    use crate::util::fs::NameRules;
+
+    use super::{
+        check_destination, check_destination_parent, destination_root, resolve_destination,
+        validate_destination, DestinationError,
+    };
+
+    fn resolve(template: &str, alias: &str, rules: NameRules) -> PathBuf {
+        let sender = Device {
+            fingerprint: "abc".to_owned(),
+            ..device(alias, 53317)
+        };
+        resolve_destination(Path::new(template), &sender, "id", rules, '_')
+    }
+
+    #[test]
+    fn test_validate_destination() {
+        assert!(validate_destination(Path::new("/tmp/{alias}/{date}-{time}")).is_ok());
+        assert!(validate_destination(Path::new("/tmp/{fingerprint}/{sessionId}")).is_ok());
+        assert!(validate_destination(Path::new("/tmp/plain")).is_ok());
+        let error = validate_destination(Path::new("/tmp/{alais}")).unwrap_err();
+        assert!(
+            error.starts_with("unknown placeholder {alais}"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_resolve_destination() {
+        assert_eq!(
+            resolve(
+                "in/{alias}/{fingerprint}/{sessionId}",
+                "Pixel 7",
+                NameRules::Unix
+            ),
+            PathBuf::from("in/Pixel 7/abc/id")
+        );
+        assert_eq!(resolve("in", "Pixel", NameRules::Unix), PathBuf::from("in"));
+
+        let resolved = resolve("{date}_{time}", "Pixel", NameRules::Unix);
+        let resolved = resolved.to_str().unwrap();
+        // e.g. 2024-01-31_13-05-09
+        assert_eq!(resolved.len(), 19, "{}", resolved);
+        assert!(resolved
+            .chars()
+            .all(|ch| ch.is_ascii_digit() || ch == '-' || ch == '_'));
+    }
+
+    #[test]
+    fn test_sanitize_alias() {
+        assert_eq!(
+            resolve("in/{alias}", "../../etc", NameRules::Unix),
+            PathBuf::from("in/.._.._etc")
+        );
+        assert_eq!(
+            resolve("in/{alias}", "..", NameRules::Unix),
+            PathBuf::from("in/_")
+        );
+        assert_eq!(
+            resolve("in/{alias}", "a\\b", NameRules::Unix),
+            PathBuf::from("in/a_b")
+        );
+        assert_eq!(
+            resolve("in/{alias}", "Joe's: PC?", NameRules::Windows),
+            PathBuf::from("in/Joe's_ PC_")
+        );
+    }
+
+    #[test]
+    fn test_destination_root() {
+        let root = |template: &str| destination_root(Path::new(template));
+        assert_eq!(root("/tmp/in"), PathBuf::from("/tmp/in"));
+        assert_eq!(root("/tmp/in/{alias}/{date}"), PathBuf::from("/tmp/in/"));
+        assert_eq!(root("/tmp/in/from-{alias}"), PathBuf::from("/tmp/in"));
+        assert_eq!(root("{alias}"), PathBuf::from("."));
+    }
+
+    #[test]
+    fn test_check_destination() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file"), "").unwrap();
+
+        let checked = check_destination(&dir.join("."), false).unwrap();
+        assert_eq!(checked, std::fs::canonicalize(&dir).unwrap());
+        // the probe file is gone again
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let missing = dir.join("mnt/backup");
+        assert!(matches!(
+            check_destination(&missing, false),
+            Err(DestinationError::Missing(_))
+        ));
+        assert!(check_destination_parent(&missing).is_ok());
+        assert!(!missing.exists());
+        assert!(check_destination(&missing, true).is_ok());
+        assert!(missing.is_dir());
+
+        let file = dir.join("file");
+        assert!(matches!(
+            check_destination(&file, false),
+            Err(DestinationError::NotADirectory(_))
+        ));
+        assert!(matches!(
+            check_destination_parent(&file.join("below")),
+            Err(DestinationError::NotADirectory(_))
+        ));
+        assert!(matches!(
+            check_destination(&file.join("below"), true),
+            Err(DestinationError::CreateFailed(..))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_read_only_destination() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // root writes anyway
+        let writable = std::fs::File::create(dir.join("probe")).is_ok();
+        if !writable {
+            assert!(matches!(
+                check_destination(&dir, false),
+                Err(DestinationError::NotWritable(..))
+            ));
+            assert!(matches!(
+                check_destination_parent(&dir.join("new")),
+                Err(DestinationError::NotWritable(..))
+            ));
+        }
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures_util::TryStreamExt;
+use localsend_proto::{
+    dto::{FileDto, PrepareDownloadResponseDto},
+    ApiRoute, Device,
+};
+use reqwest::{header, Response, StatusCode};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, BufWriter},
+};
+use tokio_util::io::StreamReader;
+
+use crate::{
+    progress::ProgressSender,
+    send::{SendError, CLIENT},
+    util::{
+        fs::{resolve_collision, DangerousExtensions, NameRules},
+        hash::FileHash,
+        trace,
+    },
+    CollisionPolicy, Result,
+};
+
+use super::{copy_body_from, fs_path, ReceiveError};
+
+/// Fetches files offered by a device through the download API.
+#[derive(Debug)]
+pub struct DownloadSession {
+    target: Device,
+    session_id: String,
+    files: HashMap<String, FileDto>,
+    name_rules: NameRules,
+    name_replacement: char,
+    dangerous_extensions: DangerousExtensions,
+}
+
+impl DownloadSession {
+    pub async fn prepare(target: &Device) -> Result<Self> {
+        if !target.download {
+            return Err(ReceiveError::DownloadUnsupported)?;
+        }
+
+        let response = trace::send(CLIENT.post(ApiRoute::PrepareDownload.target(target)?)).await?;
+        match response.status() {
+            // 200
+            StatusCode::OK => {}
+            // 403
+            StatusCode::FORBIDDEN => return Err(ReceiveError::SessionDeclined)?,
+            // 409, 429
+            StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS => {
+                return Err(ReceiveError::SessionBlocked)?
+            }
+            status => return Err(SendError::Unknown(status))?,
+        }
+
+        let dto = response.json::<PrepareDownloadResponseDto>().await?;
+        Ok(Self {
+            target: target.clone(),
+            session_id: dto.session_id,
+            files: dto.files,
+            name_rules: NameRules::native(),
+            name_replacement: '_',
+            dangerous_extensions: DangerousExtensions::native(),
+        })
+    }
+
+    /// Replaces characters the destination does not accept with `replacement`.
+    pub fn with_name_rules(mut self, rules: NameRules, replacement: char) -> Self {
+        self.name_rules = rules;
+        self.name_replacement = replacement;
+        self
+    }
+
+    /// Appends `.received` to the names of files with one of these extensions.
+    pub fn with_dangerous_extensions(mut self, extensions: DangerousExtensions) -> Self {
+        self.dangerous_extensions = extensions;
+        self
+    }
+
+    pub fn files(&self) -> Vec<FileDto> {
+        self.files.values().cloned().collect()
+    }
+
+    pub async fn download(
+        &self,
+        files: &[FileDto],
+        destination: &Path,
+        collision_policy: CollisionPolicy,
+        progress_tx: Option<ProgressSender>,
+    ) -> Result<()> {
+        let mut result = Ok(());
+        for file in files {
+            if let Err(e) = self
+                .download_file(file, destination, collision_policy, &progress_tx)
+                .await
+            {
+                log::error!("Failed to download file {}: {}", file.file_name, e);
+                result = Err(e);
+            }
+        }
+        if let Some(progress_tx) = &progress_tx {
+            progress_tx.session_ended().await;
+        }
+        result
+    }
+
+    async fn download_file(
+        &self,
+        file: &FileDto,
+        destination: &Path,
+        collision_policy: CollisionPolicy,
+        progress_tx: &Option<ProgressSender>,
+    ) -> Result<()> {
+        // named by the serving device, saved like pushed files below `destination` only
+        let path = fs_path(
+            destination,
+            &file.file_name,
+            self.name_rules,
+            self.name_replacement,
+            &self.dangerous_extensions,
+        );
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // a partial file of a previous attempt is resumed with a range request, it is
+        // named after the sanitized path and so never found outside `destination`
+        let mut partial_path = path.clone().into_os_string();
+        partial_path.push(".part");
+        let partial_path = PathBuf::from(partial_path);
+        let expected = file.hash.as_deref().and_then(FileHash::parse);
+        let mut offset = match tokio::fs::metadata(&partial_path).await {
+            Ok(metadata) if metadata.len() < file.size => metadata.len(),
+            // a complete one is only trusted with a digest to check it against
+            Ok(metadata) if metadata.len() == file.size && expected.is_some() => file.size,
+            Ok(_) => 0,
+            Err(_) => 0,
+        };
+
+        loop {
+            if offset < file.size || offset == 0 {
+                offset = self.fetch(file, &partial_path, offset, progress_tx).await?;
+            }
+            if is_complete(&partial_path, file.size, expected.as_ref()).await? {
+                break;
+            }
+            tokio::fs::remove_file(&partial_path).await.ok();
+            if offset == 0 {
+                return Err(ReceiveError::SaveFileFailed)?;
+            }
+            // the kept bytes differ from what is served now, they are fetched once more
+            log::warn!(
+                "Partial file {:?} does not match, downloading it again",
+                partial_path
+            );
+            offset = 0;
+        }
+        let path = resolve_collision(path, collision_policy);
+        tokio::fs::rename(&partial_path, &path).await?;
+        log::info!("File {:?} has been saved to {:?}", file.file_name, path);
+        Ok(())
+    }
+
+    /// Writes the body of `file` to `partial_path` from `offset` on, returns the offset
+    /// the device served from.
+    async fn fetch(
+        &self,
+        file: &FileDto,
+        partial_path: &Path,
+        offset: u64,
+        progress_tx: &Option<ProgressSender>,
+    ) -> Result<u64> {
+        let mut request = CLIENT
+            .get(ApiRoute::Download.target(&self.target)?)
+            .query(&[("sessionId", &self.session_id), ("fileId", &file.id)]);
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", offset));
+        }
+        let response = trace::send(request).await?;
+        let offset = match response.status() {
+            StatusCode::OK => 0,
+            StatusCode::PARTIAL_CONTENT if content_range_start(&response) == Some(offset) => {
+                log::info!("Resume downloading {:?} at {}", file.file_name, offset);
+                offset
+            }
+            status => return Err(SendError::Unknown(status))?,
+        };
+
+        let file_handle = if offset > 0 {
+            OpenOptions::new().append(true).open(partial_path).await?
+        } else {
+            File::create(partial_path).await?
+        };
+        let mut writer = BufWriter::new(file_handle);
+        let stream = response
+            .bytes_stream()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        let mut reader = StreamReader::new(stream);
+        // the partial file is kept on errors, so the next attempt can resume
+        copy_body_from(
+            &mut reader,
+            &mut writer,
+            file,
+            offset,
+            progress_tx,
+            None,
+            None,
+        )
+        .await?;
+        Ok(offset)
+    }
+}
+
+/// Whether the file at `path` has `size` bytes and, when given, the digest `expected`.
+async fn is_complete(path: &Path, size: u64, expected: Option<&FileHash>) -> io::Result<bool> {
+    if tokio::fs::metadata(path).await?.len() != size {
+        return Ok(false);
+    }
+    let Some(expected) = expected else {
+        return Ok(true);
+    };
+    let mut reader = File::open(path).await?;
+    let mut hasher = expected.algorithm().hasher();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf).await? {
+            0 => break,
+            len => hasher.update(&buf[..len]),
+        }
+    }
+    Ok(hasher.finalize() == *expected)
+}
+
+/// Returns the first byte position of a `Content-Range: bytes first-last/size` header.
+fn content_range_start(response: &Response) -> Option<u64> {
+    let value = response
+        .headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (first, _) = value.strip_prefix("bytes ")?.split_once('-')?;
+    first.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::Query,
+        http::{header, HeaderMap, HeaderValue, Method},
+        routing::{get, post},
+        Json, Router,
+    };
+    use localsend_proto::{
+        dto::{FileDto, FileType, PrepareDownloadResponseDto},
+        fixtures::device,
+        ApiRoute, Device,
+    };
+
+    use crate::{server::serve_file, util::hash::FileHash, CollisionPolicy};
+
+    use super::DownloadSession;
+
+    const CONTENT: &[u8] = b"0123456789abcdef";
+
+    type Ranges = Arc<Mutex<Vec<Option<HeaderValue>>>>;
+
+    /// Serves `CONTENT` under each of `names` through the download API.
+    async fn serve(dir: &Path, names: &[&str]) -> (Device, Ranges) {
+        let source = dir.join("source.bin");
+        std::fs::write(&source, CONTENT).unwrap();
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let device = Device {
+            download: true,
+            ..device("download", listener.local_addr().unwrap().port())
+        };
+        let files = names.iter().enumerate().map(|(index, name)| {
+            let file = FileDto {
+                id: index.to_string(),
+                file_name: name.to_string(),
+                size: CONTENT.len() as u64,
+                file_type: FileType::Other,
+                hash: Some(FileHash::sha256(CONTENT).into()),
+                preview: None,
+            };
+            (file.id.clone(), file)
+        });
+        let dto = PrepareDownloadResponseDto {
+            info: device.clone().into(),
+            session_id: "session".to_owned(),
+            files: files.collect(),
+        };
+
+        let ranges = Ranges::default();
+        let router =
+            Router::new()
+                .route(
+                    &ApiRoute::PrepareDownload.v2(),
+                    post(move || async move { Json(dto) }),
+                )
+                .route(
+                    &ApiRoute::Download.v2(),
+                    get({
+                        let ranges = ranges.clone();
+                        move |_: Query<HashMap<String, String>>,
+                              method: Method,
+                              headers: HeaderMap| async move {
+                            ranges
+                                .lock()
+                                .unwrap()
+                                .push(headers.get(header::RANGE).cloned());
+                            serve_file(&source, &method, &headers).await.unwrap()
+                        }
+                    }),
+                );
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (device, ranges)
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(dir.join("destination")).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_resume_download() {
+        let dir = temp_dir();
+        let destination = dir.join("destination");
+        // left over from an interrupted attempt
+        std::fs::write(destination.join("file.bin.part"), &CONTENT[..6]).unwrap();
+        let (device, ranges) = serve(&dir, &["file.bin"]).await;
+
+        let session = DownloadSession::prepare(&device).await.unwrap();
+        session
+            .download(
+                &session.files(),
+                &destination,
+                CollisionPolicy::Overwrite,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read(destination.join("file.bin")).unwrap(),
+            CONTENT
+        );
+        assert!(!destination.join("file.bin.part").exists());
+        assert_eq!(
+            ranges.lock().unwrap().as_slice(),
+            &[Some(HeaderValue::from_static("bytes=6-"))]
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_resume_mismatched_part() {
+        let dir = temp_dir();
+        let destination = dir.join("destination");
+        // bytes of another version of the file, and a complete download never renamed
+        std::fs::write(destination.join("file.bin.part"), b"abcdef").unwrap();
+        std::fs::write(destination.join("whole.bin.part"), CONTENT).unwrap();
+        let (device, ranges) = serve(&dir, &["file.bin", "whole.bin"]).await;
+
+        let session = DownloadSession::prepare(&device).await.unwrap();
+        session
+            .download(
+                &session.files(),
+                &destination,
+                CollisionPolicy::Overwrite,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read(destination.join("file.bin")).unwrap(),
+            CONTENT
+        );
+        assert_eq!(
+            std::fs::read(destination.join("whole.bin")).unwrap(),
+            CONTENT
+        );
+        assert!(!destination.join("file.bin.part").exists());
+        assert!(!destination.join("whole.bin.part").exists());
+        // the resumed one is fetched again in full, the complete one not at all
+        assert_eq!(
+            ranges.lock().unwrap().as_slice(),
+            &[Some(HeaderValue::from_static("bytes=6-")), None]
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_download_stays_in_destination() {
+        let dir = temp_dir();
+        let destination = dir.join("destination");
+        let outside = dir.join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        // only the partial file below the destination is resumed, the one above is
+        // neither appended to nor moved
+        std::fs::write(dir.join("x.bin.part"), b"outside").unwrap();
+        std::fs::write(destination.join("x.bin.part"), &CONTENT[..6]).unwrap();
+        let absolute = outside.join("y.bin");
+        let absolute = absolute.to_str().unwrap();
+        let (device, ranges) = serve(&dir, &["../x.bin", absolute]).await;
+
+        let session = DownloadSession::prepare(&device).await.unwrap();
+        session
+            .download(
+                &session.files(),
+                &destination,
+                CollisionPolicy::Overwrite,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(destination.join("x.bin")).unwrap(), CONTENT);
+        let relative = absolute.trim_start_matches('/');
+        assert_eq!(std::fs::read(destination.join(relative)).unwrap(), CONTENT);
+        assert!(!dir.join("x.bin").exists());
+        assert!(!outside.join("y.bin").exists());
+        assert_eq!(std::fs::read(dir.join("x.bin.part")).unwrap(), b"outside");
+        assert!(!destination.join("x.bin.part").exists());
+        let mut ranges = ranges.lock().unwrap().clone();
+        ranges.sort();
+        assert_eq!(ranges, [None, Some(HeaderValue::from_static("bytes=6-"))]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use localsend_proto::{dto::FileDto, Device};
+use tokio::task::JoinHandle;
+
+use super::ReceiveReport;
+
+/// A file that was received completely.
+#[derive(Debug, Clone)]
+pub struct ReceivedFileInfo {
+    pub session_id: String,
+    pub sender: Device,
+    pub file: FileDto,
+    /// Where the sink saved the file, if it keeps one
+    pub path: Option<PathBuf>,
+}
+
+/// Runs for every file saved by the sink of a session, e.g. to open or move it.
+///
+/// Hooks run in the background after the file finished and are given up on after
+/// `Settings::hook_timeout`. A failure is noted in the session report, the file
+/// stays finished.
+#[async_trait]
+pub trait ReceiveHook: Send + Sync + fmt::Debug {
+    async fn on_file_received(&self, info: &ReceivedFileInfo) -> Result<(), String>;
+}
+
+/// Runs several hooks one after the other, e.g. a completion marker and a command.
+///
+/// Every hook runs even when an earlier one failed, the failures are joined.
+#[derive(Debug)]
+pub struct HookChain(pub Vec<Arc<dyn ReceiveHook>>);
+
+#[async_trait]
+impl ReceiveHook for HookChain {
+    async fn on_file_received(&self, info: &ReceivedFileInfo) -> Result<(), String> {
+        let mut errors = vec![];
+        for hook in &self.0 {
+            if let Err(e) = hook.on_file_received(info).await {
+                errors.push(e);
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("; ")),
+        }
+    }
+}
+
+/// The hooks started for the files of a session, keyed by file name.
+#[derive(Debug, Default)]
+pub struct HookRuns(Vec<(String, JoinHandle<Result<(), String>>)>);
+
+impl HookRuns {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn spawn(
+        &mut self,
+        hook: Arc<dyn ReceiveHook>,
+        info: ReceivedFileInfo,
+        timeout: Duration,
+    ) {
+        let file_name = info.file.file_name.clone();
+        let task = tokio::spawn(async move {
+            let result = match tokio::time::timeout(timeout, hook.on_file_received(&info)).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("timed out after {:?}", timeout)),
+            };
+            if let Err(e) = &result {
+                log::warn!("Hook failed for {:?}: {}", info.file.file_name, e);
+            }
+            result
+        });
+        self.0.push((file_name, task));
+    }
+
+    /// Waits for the hooks and notes their failures in `report`.
+    pub async fn finish(self, report: &mut ReceiveReport) {
+        for (file_name, task) in self.0 {
+            let error = match task.await {
+                Ok(result) => result.err(),
+                Err(e) => Some(format!("hook panicked: {}", e)),
+            };
+            if let Some(file) = report.files.iter_mut().find(|f| f.file_name == file_name) {
+                file.hook_error = error;
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    io::{self, Read},
+    net::{Ipv4Addr, TcpListener},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use localsend_proto::{dto::FileDto, Device};
+use serde::{Deserialize, Serialize};
+
+use crate::util::hash::FileHash;
+
+/// Kept in the data directory by the CLI, with a directory per server port below it.
+pub const JOURNAL_DIR: &str = "sessions";
+
+/// What a receive session expects and where it writes, kept on disk while it runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub session_id: String,
+    pub sender_alias: String,
+    pub sender_fingerprint: String,
+    pub sender_ip: String,
+    pub destination: PathBuf,
+    pub files: Vec<JournalFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalFile {
+    pub id: String,
+    pub file_name: String,
+    pub size: u64,
+    pub hash: Option<String>,
+    /// Where the body is written, set once it started
+    pub path: Option<PathBuf>,
+    pub finished: bool,
+}
+
+/// The journal of a running session, the file is removed once the last clone is
+/// dropped.
+///
+/// A journal that is still there when a server starts belongs to a receiver that
+/// crashed, [`clean_journals`] removes the files it left half written.
+#[derive(Debug, Clone)]
+pub struct SessionJournal(Arc<JournalFileHandle>);
+
+#[derive(Debug)]
+struct JournalFileHandle {
+    path: PathBuf,
+    entry: Mutex<JournalEntry>,
+}
+
+impl Drop for JournalFileHandle {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+impl SessionJournal {
+    /// Nothing is written before the first files are added.
+    pub fn new(dir: &Path, session_id: &str, sender: &Device, destination: &Path) -> Self {
+        let entry = JournalEntry {
+            session_id: session_id.to_owned(),
+            sender_alias: sender.alias.clone(),
+            sender_fingerprint: sender.fingerprint.clone(),
+            sender_ip: sender.ip.clone(),
+            destination: destination.to_path_buf(),
+            files: vec![],
+        };
+        Self(Arc::new(JournalFileHandle {
+            path: dir.join(format!("{}.json", session_id)),
+            entry: Mutex::new(entry),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0.path
+    }
+
+    pub fn entry(&self) -> JournalEntry {
+        self.0.entry.lock().unwrap().clone()
+    }
+
+    /// Records the files accepted for the session.
+    pub fn add_files<'a>(&self, files: impl IntoIterator<Item = &'a FileDto>) {
+        self.update(|entry| {
+            entry
+                .files
+                .extend(files.into_iter().map(|file| JournalFile {
+                    id: file.id.clone(),
+                    file_name: file.file_name.clone(),
+                    size: file.size,
+                    hash: file.hash.clone(),
+                    path: None,
+                    finished: false,
+                }))
+        });
+    }
+
+    /// Records where the body of a file is written, before the first byte.
+    pub fn opened(&self, file_id: &str, path: &Path) {
+        self.update_file(file_id, |file| {
+            file.path = Some(path.to_path_buf());
+            file.finished = false;
+        });
+    }
+
+    pub fn finished(&self, file_id: &str) {
+        self.update_file(file_id, |file| file.finished = true);
+    }
+
+    /// The body was removed again, e.g. after a failed upload.
+    pub fn aborted(&self, file_id: &str) {
+        self.update_file(file_id, |file| file.path = None);
+    }
+
+    fn update_file(&self, file_id: &str, update: impl FnOnce(&mut JournalFile)) {
+        self.update(|entry| {
+            if let Some(file) = entry.files.iter_mut().find(|file| file.id == file_id) {
+                update(file);
+            }
+        });
+    }
+
+    fn update(&self, update: impl FnOnce(&mut JournalEntry)) {
+        let mut entry = self.0.entry.lock().unwrap();
+        update(&mut entry);
+        if let Err(e) = write_journal(&self.0.path, &entry) {
+            log::warn!("Failed to write session journal {:?}: {}", self.0.path, e);
+        }
+    }
+}
+
+/// Replaces the journal at once, a crash keeps the previous one.
+fn write_journal(path: &Path, entry: &JournalEntry) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_vec(entry).map_err(io::Error::from)?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// What was found in the journals of crashed sessions.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JournalCleanup {
+    pub sessions: Vec<JournalEntry>,
+    /// Half written files that were removed
+    pub removed: Vec<PathBuf>,
+    /// Files whose whole body arrived before the crash, they are kept
+    pub completed: Vec<PathBuf>,
+    /// Journals that could not be read, removed only when forced
+    pub invalid: Vec<PathBuf>,
+}
+
+impl JournalCleanup {
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty() && self.invalid.is_empty()
+    }
+
+    fn merge(&mut self, other: JournalCleanup) {
+        self.sessions.extend(other.sessions);
+        self.removed.extend(other.removed);
+        self.completed.extend(other.completed);
+        self.invalid.extend(other.invalid);
+    }
+}
+
+/// Removes the journals in `dir` and the files their sessions left half written.
+///
+/// Only call this while no session may use `dir`, e.g. when the server owning it
+/// starts. A file is kept when its size and announced digest show that it arrived
+/// completely. Unreadable journals are only removed with `force`.
+pub fn clean_journals(dir: &Path, force: bool) -> JournalCleanup {
+    let mut cleanup = JournalCleanup::default();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return cleanup;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "tmp") {
+            std::fs::remove_file(&path).ok();
+            continue;
+        }
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+        let journal = std::fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<JournalEntry>(&json).ok());
+        let Some(journal) = journal else {
+            if force {
+                std::fs::remove_file(&path).ok();
+            }
+            cleanup.invalid.push(path);
+            continue;
+        };
+        for file in &journal.files {
+            let Some(written) = file.path.as_ref().filter(|_| !file.finished) else {
+                continue;
+            };
+            if !written.exists() {
+                continue;
+            }
+            if is_complete(written, file) {
+                cleanup.completed.push(written.clone());
+                continue;
+            }
+            match std::fs::remove_file(written) {
+                Ok(()) => cleanup.removed.push(written.clone()),
+                Err(e) => log::warn!("Failed to remove {:?}: {}", written, e),
+            }
+        }
+        std::fs::remove_file(&path).ok();
+        cleanup.sessions.push(journal);
+    }
+    cleanup
+}
+
+/// Cleans the journals of every port no receiver listens on anymore, see [`clean_journals`].
+pub fn clean_stale_journals(root: &Path, force: bool) -> JournalCleanup {
+    let mut cleanup = JournalCleanup::default();
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return cleanup;
+    };
+    for entry in entries.flatten() {
+        let Some(port) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u16>().ok())
+        else {
+            continue;
+        };
+        // a running receiver holds its port, nobody else can bind it
+        if TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_err() {
+            log::debug!("Keeping the journals of port {}, it is in use", port);
+            continue;
+        }
+        cleanup.merge(clean_journals(&entry.path(), force));
+        std::fs::remove_dir(entry.path()).ok();
+    }
+    cleanup
+}
+
+fn is_complete(path: &Path, file: &JournalFile) -> bool {
+    if !std::fs::metadata(path).is_ok_and(|m| m.len() == file.size) {
+        return false;
+    }
+    let Some(expected) = file.hash.as_deref().and_then(FileHash::parse) else {
+        // without a digest the size is all there is to go on
+        return true;
+    };
+    let Ok(mut reader) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut hasher = expected.algorithm().hasher();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(_) => return false,
+        }
+    }
+    hasher.finalize() == expected
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::{
+        dto::{FileDto, FileType},
+        fixtures::device,
+    };
+
+    use crate::util::hash::FileHash;
+
+    use super::{clean_journals, SessionJournal};
+
+    fn file(id: &str, content: &str, hash: bool) -> FileDto {
+        FileDto {
+            id: id.to_owned(),
+            file_name: format!("{}.bin", id),
+            size: content.len() as u64,
+            file_type: FileType::Other,
+            hash: hash.then(|| FileHash::sha256(content).into()),
+            preview: None,
+        }
+    }
+
+    #[test]
+    fn test_clean_after_crash() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let (dir, destination) = (root.join("journals"), root.join("dest"));
+        std::fs::create_dir_all(&destination).unwrap();
+        let sender = device("sender", 53317);
+        let journal = SessionJournal::new(&dir, "session", &sender, &destination);
+        assert!(!journal.path().exists());
+        let files = [
+            file("finished", "0000", false),
+            file("partial", "1111", true),
+            file("arrived", "2222", true),
+            file("corrupt", "3333", true),
+            file("waiting", "4444", false),
+        ];
+        journal.add_files(&files);
+        assert!(journal.path().exists());
+        for (id, content) in [
+            ("finished", "0000"),
+            ("partial", "11"),
+            ("arrived", "2222"),
+            ("corrupt", "3330"),
+        ] {
+            let path = destination.join(format!("{}.bin", id));
+            journal.opened(id, &path);
+            std::fs::write(&path, content).unwrap();
+        }
+        journal.finished("finished");
+
+        // a crash never drops the journal
+        let journal_path = journal.path().to_path_buf();
+        std::mem::forget(journal);
+        let cleanup = clean_journals(&dir, false);
+        assert_eq!(cleanup.sessions.len(), 1);
+        assert_eq!(cleanup.sessions[0].session_id, "session");
+        let mut removed = cleanup.removed.clone();
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                destination.join("corrupt.bin"),
+                destination.join("partial.bin")
+            ]
+        );
+        assert_eq!(cleanup.completed, vec![destination.join("arrived.bin")]);
+        assert!(destination.join("finished.bin").exists());
+        assert!(destination.join("arrived.bin").exists());
+        assert!(!journal_path.exists());
+
+        // unreadable journals stay unless forced
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        assert_eq!(clean_journals(&dir, false).invalid.len(), 1);
+        assert!(dir.join("broken.json").exists());
+        assert_eq!(clean_journals(&dir, true).invalid.len(), 1);
+        assert!(!dir.join("broken.json").exists());
+
+        // a session that ends removes its journal
+        let journal = SessionJournal::new(&dir, "ended", &sender, &destination);
+        journal.add_files(&files[..1]);
+        let path = journal.path().to_path_buf();
+        assert!(path.exists());
+        drop(journal);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -1,5 +1,39 @@
+mod archive;
+mod audit;
+mod completion;
+mod decider;
+mod dedup;
+mod destination;
+mod download;
+mod hook;
+mod journal;
+mod quarantine;
+mod rate_limit;
 mod receive_session;
 mod receiving_file;
+mod report;
+mod save;
+mod sink;
+mod status;
+mod structure;
+mod tokens;
 
+pub use archive::*;
+pub use audit::*;
+pub use completion::*;
+pub use decider::*;
+pub use dedup::*;
+pub use destination::*;
+pub use download::*;
+pub use hook::*;
+pub use journal::*;
+pub use quarantine::*;
+pub use rate_limit::*;
 pub use receive_session::*;
 pub use receiving_file::*;
+pub use report::*;
+pub(crate) use save::*;
+pub use sink::*;
+pub use status::*;
+pub use structure::*;
+pub use tokens::*;
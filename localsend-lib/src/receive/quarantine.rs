@@ -0,0 +1,296 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use localsend_proto::dto::FileDto;
+
+use crate::{
+    send::FileStatus,
+    util::fs::{resolve_collision, saved_name, DangerousExtensions, NameRules},
+    CollisionPolicy,
+};
+
+use super::{FsSink, ReceiveSink, ReceivingFile};
+
+/// Starts the names of the quarantine directories below `Settings::preview_dir`.
+pub const QUARANTINE_PREFIX: &str = "localsend-preview-";
+
+/// A quarantined file that was received completely, waiting to be kept or discarded.
+#[derive(Debug, Clone)]
+pub struct PreviewFile {
+    pub file: FileDto,
+    /// Where the file can be opened until it is reviewed
+    pub path: PathBuf,
+}
+
+/// Holds the small files of a session until the user reviewed them, see
+/// `Settings::preview_dir`.
+///
+/// Kept files are moved to the destination of the session, the others are
+/// deleted with the quarantine directory.
+#[derive(Debug)]
+pub struct Quarantine {
+    dir: PathBuf,
+    file_ids: HashSet<String>,
+    sink: Arc<dyn ReceiveSink>,
+}
+
+impl Quarantine {
+    pub fn new(
+        preview_dir: &Path,
+        session_id: &str,
+        file_ids: HashSet<String>,
+        name_rules: NameRules,
+        name_replacement: char,
+        dangerous_extensions: DangerousExtensions,
+    ) -> Self {
+        let dir = preview_dir.join(format!("{}{}", QUARANTINE_PREFIX, session_id));
+        // names may repeat within a session, nothing in the quarantine is overwritten
+        let sink = FsSink::new(&dir, CollisionPolicy::Rename)
+            .with_name_rules(name_rules, name_replacement)
+            .with_dangerous_extensions(dangerous_extensions);
+        Self {
+            dir,
+            file_ids,
+            sink: Arc::new(sink),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the file with this id is received into the quarantine.
+    pub fn contains(&self, file_id: &str) -> bool {
+        self.file_ids.contains(file_id)
+    }
+
+    pub fn sink(&self) -> Arc<dyn ReceiveSink> {
+        self.sink.clone()
+    }
+
+    /// The quarantined files among `files` that were received completely.
+    pub fn files(&self, files: &HashMap<String, ReceivingFile>) -> Vec<PreviewFile> {
+        let mut previews: Vec<PreviewFile> = files
+            .values()
+            .filter(|file| self.contains(&file.file.id) && file.status == FileStatus::Finished)
+            .filter_map(|file| {
+                Some(PreviewFile {
+                    file: file.file.clone(),
+                    path: file.path.clone()?,
+                })
+            })
+            .collect();
+        previews.sort_by(|a, b| a.file.file_name.cmp(&b.file.file_name));
+        previews
+    }
+
+    /// Moves the quarantined files with an id in `keep` below `destination`, deletes
+    /// the others and returns the ids of the files that were moved.
+    ///
+    /// Files that could not be moved fail, discarded files are skipped.
+    pub async fn release(
+        self,
+        files: &mut HashMap<String, ReceivingFile>,
+        keep: &[String],
+        destination: &Path,
+        collision_policy: CollisionPolicy,
+    ) -> Vec<String> {
+        let mut kept = vec![];
+        for file in files.values_mut() {
+            if !self.contains(&file.file.id) || file.status != FileStatus::Finished {
+                continue;
+            }
+            let Some(path) = file.path.take() else {
+                continue;
+            };
+            if !keep.contains(&file.file.id) {
+                tokio::fs::remove_file(&path).await.ok();
+                file.status = FileStatus::Skipped;
+                file.reason = Some("Discarded after preview".to_owned());
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&self.dir) else {
+                continue;
+            };
+            let target = resolve_collision(destination.join(relative), collision_policy);
+            match move_file(&path, &target).await {
+                Ok(()) => {
+                    file.saved_name = saved_name(&target, destination)
+                        .filter(|name| name != &file.file.file_name);
+                    file.path = Some(target);
+                    kept.push(file.file.id.clone());
+                }
+                Err(e) => {
+                    log::error!("Failed to move {:?} to {:?}: {:?}", path, target, e);
+                    file.status = FileStatus::Failed;
+                    file.reason = Some(format!("Failed to keep after preview: {}", e));
+                }
+            }
+        }
+        self.remove().await;
+        kept
+    }
+
+    /// Deletes the quarantine directory with the files left in it.
+    pub async fn remove(self) {
+        if let Err(e) = tokio::fs::remove_dir_all(&self.dir).await {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("Failed to remove quarantine {:?}: {}", self.dir, e);
+            }
+        }
+    }
+}
+
+/// Renames `from` to `to`, copying when they are on different filesystems.
+async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(from, to).await?;
+    tokio::fs::remove_file(from).await
+}
+
+/// Removes the quarantine directories below `preview_dir` left behind by cancelled
+/// or crashed runs, returns how many were removed.
+pub async fn sweep_quarantines(preview_dir: &Path) -> usize {
+    let Ok(mut entries) = tokio::fs::read_dir(preview_dir).await else {
+        return 0;
+    };
+    let mut removed = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let is_quarantine = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(QUARANTINE_PREFIX));
+        if !is_quarantine || !entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        match tokio::fs::remove_dir_all(entry.path()).await {
+            Ok(()) => removed += 1,
+            Err(e) => log::warn!("Failed to remove quarantine {:?}: {}", entry.path(), e),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use localsend_proto::dto::{FileDto, FileType};
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{
+        receive::ReceivingFile,
+        send::FileStatus,
+        util::fs::{DangerousExtensions, NameRules},
+        CollisionPolicy,
+    };
+
+    use super::{sweep_quarantines, Quarantine, QUARANTINE_PREFIX};
+
+    fn file(id: &str, name: &str) -> FileDto {
+        FileDto {
+            id: id.to_owned(),
+            file_name: name.to_owned(),
+            size: 4,
+            file_type: FileType::Image,
+            hash: None,
+            preview: None,
+        }
+    }
+
+    async fn receive(quarantine: &Quarantine, file: FileDto) -> ReceivingFile {
+        let sink = quarantine.sink();
+        let mut writer = sink.open(&file).await.unwrap();
+        writer.write_all(b"data").await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+        let mut receiving = ReceivingFile::new(file.clone(), None);
+        receiving.path = sink.finish(&file).await.unwrap();
+        receiving.status = FileStatus::Finished;
+        receiving.bytes = 4;
+        receiving
+    }
+
+    #[tokio::test]
+    async fn test_release() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let destination = dir.join("destination");
+        let ids = ["1", "2", "3"].map(String::from).into();
+        let quarantine = Quarantine::new(
+            &dir,
+            "session",
+            ids,
+            NameRules::native(),
+            '_',
+            DangerousExtensions::default(),
+        );
+        assert!(quarantine.dir().starts_with(&dir));
+
+        let mut files = HashMap::new();
+        for file in [
+            file("1", "keep.jpg"),
+            file("2", "photos/discard.jpg"),
+            file("3", "taken.jpg"),
+        ] {
+            let id = file.id.clone();
+            files.insert(id, receive(&quarantine, file).await);
+        }
+        let previews = quarantine.files(&files);
+        assert_eq!(previews.len(), 3);
+        assert!(previews
+            .iter()
+            .all(|preview| preview.path.starts_with(quarantine.dir())));
+
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("taken.jpg"), b"old").unwrap();
+        let quarantine_dir = quarantine.dir().to_path_buf();
+        let keep = ["1".to_owned(), "3".to_owned()];
+        let mut kept = quarantine
+            .release(&mut files, &keep, &destination, CollisionPolicy::Rename)
+            .await;
+        kept.sort();
+        assert_eq!(kept, keep);
+
+        assert_eq!(
+            std::fs::read(destination.join("keep.jpg")).unwrap(),
+            b"data"
+        );
+        assert_eq!(files["1"].path, Some(destination.join("keep.jpg")));
+        assert_eq!(files["3"].saved_name.as_deref(), Some("taken (1).jpg"));
+        assert_eq!(
+            std::fs::read(destination.join("taken.jpg")).unwrap(),
+            b"old"
+        );
+        assert_eq!(files["2"].status, FileStatus::Skipped);
+        assert_eq!(files["2"].path, None);
+        assert!(!destination.join("photos").exists());
+        assert!(!quarantine_dir.exists());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_sweep_quarantines() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let stale = dir.join(format!("{}old-session", QUARANTINE_PREFIX));
+        std::fs::create_dir_all(stale.join("photos")).unwrap();
+        std::fs::write(stale.join("photos/a.jpg"), b"data").unwrap();
+        std::fs::create_dir_all(dir.join("unrelated")).unwrap();
+
+        assert_eq!(sweep_quarantines(&dir).await, 1);
+        assert!(!stale.exists());
+        assert!(dir.join("unrelated").exists());
+        assert_eq!(sweep_quarantines(&dir.join("missing")).await, 0);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
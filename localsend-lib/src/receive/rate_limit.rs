@@ -0,0 +1,86 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+
+/// Paces the bodies of a session to a rate in bytes per second, shared by its
+/// concurrent uploads.
+///
+/// Reads are held back rather than writes, so TCP slows the sender down too.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may be read right away, negative while reads are owed
+    available: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Bytes per second, at least 1.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1) as f64,
+            bucket: Arc::new(Mutex::new(Bucket {
+                available: 0.0,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    /// Unused allowance kept after a pause, a quarter second of the rate
+    fn burst(&self) -> f64 {
+        self.rate / 4.0
+    }
+
+    /// Waits until `bytes` that were just read fit into the rate.
+    ///
+    /// Each call takes its bytes at once, concurrent callers wait in turn for
+    /// the bytes taken before them.
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * self.rate;
+            bucket.available = (bucket.available + refill).min(self.burst()) - bytes as f64;
+            bucket.updated = now;
+            if bucket.available < 0.0 {
+                Duration::from_secs_f64(-bucket.available / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[tokio::test]
+    async fn test_shared_rate() {
+        let limiter = RateLimiter::new(100_000);
+        let start = Instant::now();
+        // two uploads of 10 KB chunks share the rate
+        let upload = |limiter: RateLimiter| async move {
+            for _ in 0..5 {
+                limiter.consume(10_000).await;
+            }
+        };
+        tokio::join!(upload(limiter.clone()), upload(limiter.clone()));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(950), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1300), "{:?}", elapsed);
+    }
+}
@@ -1,12 +1,28 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use localsend_proto::Device;
+use localsend_proto::{Device, ValidationError};
 use thiserror::Error;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 
-use crate::send::UploadProgress;
+use crate::{
+    progress::ProgressSender,
+    send::FileStatus,
+    util::{compression::Compression, fs::SessionNames, inhibit::TransferGuard},
+    Settings,
+};
 
-use super::ReceivingFile;
+use super::{
+    ArchiveWriter, DedupIndex, DestinationError, HookRuns, Quarantine, RateLimiter, ReceiveSink,
+    ReceivingFile, SessionJournal, StatusTracker, StructureLimit,
+};
+
+pub type SharedArchive = Arc<Mutex<Option<ArchiveWriter>>>;
 
 #[derive(Error, Debug)]
 pub enum ReceiveError {
@@ -30,12 +46,35 @@ pub enum ReceiveError {
     SaveFileFailed,
     #[error("Blocked by another session")]
     SessionBlocked,
+    /// Too many requests wait for the running session already, retry after this long
+    #[error("Too many senders waiting")]
+    QueueFull(Duration),
+    /// The receiver took the only session it was started for
+    #[error("Receiver takes no more sessions")]
+    Closed,
     #[error("File request declined by recipient")]
     SessionDeclined,
     #[error("No session")]
     SessionNotExists,
     #[error("Cancelled")]
     Cancelled,
+    #[error("Device does not offer downloads")]
+    DownloadUnsupported,
+    #[error("Recipient did not answer in time")]
+    DecisionTimeout,
+    #[error("File is still being received")]
+    UploadInProgress,
+    #[error("Invalid request: {0}")]
+    InvalidDto(#[from] ValidationError),
+    #[error("{file_name:?} exceeds the limit of {limit}")]
+    StructureLimitExceeded {
+        file_name: String,
+        limit: StructureLimit,
+    },
+    #[error("Destination lost: {0}")]
+    DestinationLost(String),
+    #[error("Destination unavailable: {0}")]
+    DestinationUnavailable(#[from] DestinationError),
 }
 
 #[derive(Debug)]
@@ -45,7 +84,130 @@ pub struct ReceiveSession {
     pub sender: Device,
     pub files: HashMap<String, ReceivingFile>,
     pub destination_directory: PathBuf,
-    pub progress_tx: Option<Sender<UploadProgress>>,
+    pub progress_tx: Option<ProgressSender>,
+    pub archive: Option<SharedArchive>,
+    pub print_texts: bool,
+    /// When the files were accepted
+    pub started: Option<Instant>,
+    /// Upload encoding agreed on with a localsend-rs sender
+    pub compression: Option<Compression>,
+    /// Refreshed on every request and body chunk of the sender
+    pub last_activity: Activity,
+    /// Interrupts running uploads once the session is dropped
+    pub cancel: CancellationToken,
+    /// Receives the files that are not archived or printed
+    pub sink: Arc<dyn ReceiveSink>,
+    /// Whether `sink` only audits the files, see `Settings::audit`
+    pub audited: bool,
+    /// Kept on disk while files are saved to `destination`, see `Settings::journal_dir`
+    pub journal: Option<SessionJournal>,
+    pub status_tracker: StatusTracker,
+    /// Receive hooks started for the finished files
+    pub hooks: HookRuns,
+    /// Receives the files previewed before they are kept
+    pub quarantine: Option<Quarantine>,
+    /// Gets the digests of the files saved to the sink when dedup is on
+    pub dedup: Option<DedupIndex>,
+    /// Names in the directories the session saves to, by how the filesystem compares them
+    pub names: SessionNames,
+    /// Why files can no longer be saved to `destination_directory`, the session was aborted
+    pub destination_lost: Option<String>,
+    /// Paces the uploads when `Settings::receive_rate_limit` is set
+    pub rate_limiter: Option<RateLimiter>,
+    /// Keeps the system awake from the moment files are received until the session ends
+    pub transfer_guard: Option<TransferGuard>,
+    /// The settings of the server when the session started, reloads do not change them
+    pub settings: Arc<Settings>,
+    /// See `ServerState::settings_version`
+    pub settings_version: u64,
+}
+
+/// What is kept of the last finished session to answer retried uploads, the sender
+/// may have missed the response to the last one.
+#[derive(Debug)]
+pub struct FinishedSession {
+    pub session_id: String,
+    pub sender_ip: String,
+    pub files: HashMap<String, ReceivingFile>,
+    /// Whether the files were saved into an archive
+    pub archived: bool,
+    /// Its destination was lost, see [`ReceiveSession::destination_lost`]
+    pub destination_lost: Option<String>,
+}
+
+/// Last time a session saw activity, shared with its running uploads.
+#[derive(Debug, Clone)]
+pub struct Activity(Arc<std::sync::Mutex<Instant>>);
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self(Arc::new(std::sync::Mutex::new(Instant::now())))
+    }
+}
+
+impl Activity {
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    /// Time since the last activity.
+    pub fn idle(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+impl ReceiveSession {
+    /// Finalizes the archive of this session, if any.
+    pub async fn finish_archive(&mut self) -> std::io::Result<Option<PathBuf>> {
+        let Some(archive) = self.archive.take() else {
+            return Ok(None);
+        };
+        let archive = archive.lock().await.take();
+        match archive {
+            Some(archive) => archive.finish().await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Stops running uploads, which remove their partial files, the archive and the
+    /// quarantine.
+    pub async fn abort(&mut self) {
+        self.cancel.cancel();
+        self.status_tracker.clear();
+        self.abort_archive().await;
+        if let Some(quarantine) = self.quarantine.take() {
+            quarantine.remove().await;
+        }
+    }
+
+    /// Fails the files not received yet and stops the running uploads, nothing can be
+    /// saved to the destination any more. Returns the ids of the failed files.
+    pub fn lose_destination(&mut self, reason: String) -> Vec<String> {
+        log::error!("Aborting session {}: {}", self.session_id, reason);
+        let mut failed = vec![];
+        for (id, file) in self.files.iter_mut() {
+            if matches!(file.status, FileStatus::Queue | FileStatus::Sending) {
+                file.status = FileStatus::Failed;
+                file.reason = Some(format!("Destination lost: {}", reason));
+                failed.push(id.clone());
+            }
+        }
+        self.destination_lost = Some(reason);
+        self.cancel.cancel();
+        failed
+    }
+
+    /// Removes the partially written archive of this session, if any.
+    pub async fn abort_archive(&mut self) {
+        let Some(archive) = self.archive.take() else {
+            return;
+        };
+        let archive = archive.lock().await.take();
+        if let Some(archive) = archive {
+            log::info!("Removing unfinished archive {:?}", archive.path());
+            archive.abort().await.ok();
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -1,10 +1,72 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
 use localsend_proto::dto::FileDto;
 
-use crate::send::FileStatus;
+use crate::{
+    receive::FileToken,
+    send::{transfer_duration, FileStatus},
+};
 
 #[derive(Debug, Clone)]
 pub struct ReceivingFile {
     pub file: FileDto,
     pub status: FileStatus,
-    pub token: Option<String>,
+    /// Kept after the upload, a retry of a finished file is answered without receiving it again
+    pub token: Option<FileToken>,
+    /// Where the file was saved, the archive when saving into one
+    pub path: Option<PathBuf>,
+    /// Set when the file was saved under another name than the one sent
+    pub saved_name: Option<String>,
+    pub bytes: u64,
+    pub started: Option<Instant>,
+    pub finished: Option<Instant>,
+    /// When the file was received successfully
+    pub completed_at: Option<Instant>,
+    /// Why the file was skipped or failed
+    pub reason: Option<String>,
+    /// The file with the same content received before, set instead of receiving it
+    pub duplicate_of: Option<PathBuf>,
+    /// Size of the existing file the body was appended to, after appending
+    pub appended_to_size: Option<u64>,
+}
+
+impl ReceivingFile {
+    pub fn new(file: FileDto, token: Option<FileToken>) -> Self {
+        Self {
+            file,
+            status: FileStatus::Queue,
+            token,
+            path: None,
+            saved_name: None,
+            bytes: 0,
+            started: None,
+            finished: None,
+            completed_at: None,
+            reason: None,
+            duplicate_of: None,
+            appended_to_size: None,
+        }
+    }
+
+    /// How long receiving took, `None` unless it was started and ended.
+    pub fn duration(&self) -> Option<Duration> {
+        transfer_duration(self.started, self.finished)
+    }
+
+    /// Whether the file was received and, unless saved into an archive, is still
+    /// there with the size it was received with.
+    pub async fn is_complete(&self, archived: bool) -> bool {
+        if self.status != FileStatus::Finished || self.completed_at.is_none() {
+            return false;
+        }
+        match &self.path {
+            Some(path) if !archived => tokio::fs::metadata(path)
+                .await
+                .is_ok_and(|metadata| metadata.len() == self.bytes),
+            _ => true,
+        }
+    }
 }
@@ -0,0 +1,134 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    send::{throughput, FileStatus},
+    util::note::is_note,
+};
+
+use super::ReceiveSession;
+
+/// Outcome of a single file of a receive session.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivedFileReport {
+    pub file_name: String,
+    pub path: Option<PathBuf>,
+    /// Name the file was saved as when it differs from `file_name`
+    pub saved_name: Option<String>,
+    pub bytes: u64,
+    pub status: FileStatus,
+    pub duration_secs: Option<f64>,
+    /// Bytes per second of a finished file
+    pub speed: Option<f64>,
+    pub reason: Option<String>,
+    /// Why the receive hook failed for this file
+    pub hook_error: Option<String>,
+    /// The file with the same content received before, the file was not transferred
+    pub duplicate_of: Option<PathBuf>,
+    /// Size of the existing file `bytes` were appended to, after appending
+    pub appended_to_size: Option<u64>,
+}
+
+/// Summary of a finished receive session.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiveReport {
+    pub session_id: String,
+    pub sender: String,
+    /// The directory of the session with its placeholders resolved
+    pub destination: PathBuf,
+    pub files: Vec<ReceivedFileReport>,
+    /// Bytes transferred, files received before are not counted
+    pub total_bytes: u64,
+    pub duration_secs: f64,
+    /// Bytes per second over the whole session
+    pub average_speed: f64,
+    /// The files were audited, not saved
+    pub audited: bool,
+    /// Why the session was aborted when its destination could no longer be written to
+    pub destination_lost: Option<String>,
+    /// Version of the settings the session used, counted up by every reload
+    pub settings_version: u64,
+}
+
+impl ReceiveReport {
+    /// Files transferred successfully, files received before are not counted.
+    pub fn finished(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.status == FileStatus::Finished && f.duplicate_of.is_none())
+            .count()
+    }
+
+    /// Files accepted but not received, neither finished nor skipped nor received before.
+    pub fn failed(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.duplicate_of.is_none())
+            .filter(|f| !matches!(f.status, FileStatus::Finished | FileStatus::Skipped))
+            .count()
+    }
+
+    /// Files not transferred because their content was received before.
+    pub fn deduplicated(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.duplicate_of.is_some())
+            .count()
+    }
+}
+
+impl ReceiveSession {
+    pub fn report(&self) -> ReceiveReport {
+        let mut files: Vec<ReceivedFileReport> = self
+            .files
+            .values()
+            .filter(|file| !is_note(&file.file))
+            .map(|file| ReceivedFileReport {
+                file_name: file.file.file_name.clone(),
+                path: file.path.clone(),
+                saved_name: file.saved_name.clone(),
+                bytes: file.bytes,
+                status: file.status.clone(),
+                duration_secs: file.duration().map(|d| d.as_secs_f64()),
+                speed: file
+                    .duration()
+                    .filter(|_| file.status == FileStatus::Finished)
+                    .map(|d| throughput(file.bytes, d)),
+                reason: file.reason.clone(),
+                hook_error: None,
+                duplicate_of: file.duplicate_of.clone(),
+                appended_to_size: file.appended_to_size,
+            })
+            .collect();
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+        let total_bytes = files
+            .iter()
+            .filter(|f| f.duplicate_of.is_none())
+            .map(|f| f.bytes)
+            .sum();
+        let duration_secs = self
+            .started
+            .map(|started| (Instant::now() - started).as_secs_f64())
+            .unwrap_or_default();
+        let average_speed = throughput(total_bytes, Duration::from_secs_f64(duration_secs));
+        ReceiveReport {
+            session_id: self.session_id.clone(),
+            sender: self.sender.alias.clone(),
+            destination: self.destination_directory.clone(),
+            files,
+            total_bytes,
+            duration_secs,
+            average_speed,
+            audited: self.audited,
+            destination_lost: self.destination_lost.clone(),
+            settings_version: self.settings_version,
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::{io, time::Instant};
+
+use localsend_proto::dto::FileDto;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    progress::ProgressSender, send::FileStatus, server::ProgressEvents, util::hash::FileHash,
+    Result,
+};
+
+use super::{ReceiveError, StatusTracker};
+
+const BUF_SIZE: usize = 1024 * 8;
+
+/// Copies a file body to `writer`, reporting progress for `file`.
+///
+/// Returns the number of bytes copied. Bodies not matching the md5 or sha256
+/// digest announced for `file` fail after the last byte was written.
+pub(crate) async fn copy_body<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    file: &FileDto,
+    progress_tx: &Option<ProgressSender>,
+    status_tracker: Option<&StatusTracker>,
+    events: Option<&mut ProgressEvents>,
+) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    copy_body_from(reader, writer, file, 0, progress_tx, status_tracker, events).await
+}
+
+/// Like [`copy_body`], for a body starting at `offset` of `file`.
+///
+/// Resumed bodies are not verified, their start was not read.
+pub(crate) async fn copy_body_from<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    file: &FileDto,
+    offset: u64,
+    progress_tx: &Option<ProgressSender>,
+    status_tracker: Option<&StatusTracker>,
+    mut events: Option<&mut ProgressEvents>,
+) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; BUF_SIZE];
+    let mut position: u64 = offset;
+    let started = Instant::now();
+    let expected = file
+        .hash
+        .as_deref()
+        .and_then(FileHash::parse)
+        .filter(|_| offset == 0);
+    let mut hasher = expected.as_ref().map(|hash| hash.algorithm().hasher());
+
+    loop {
+        match reader.read(&mut buf[..]).await {
+            Ok(0) => break,
+            Ok(len) => {
+                position += len as u64;
+                writer.write_all(&buf[0..len]).await?;
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&buf[0..len]);
+                }
+                if let Some(status_tracker) = status_tracker {
+                    status_tracker.progress(&file.id, position);
+                }
+                if let Some(events) = &mut events {
+                    events.update(position, position >= file.size);
+                }
+                if let Some(progress_tx) = progress_tx {
+                    progress_tx
+                        .position(&file.id, position, file.size, started.elapsed())
+                        .await;
+                }
+            }
+            Err(e) => {
+                log::warn!("Error: {:?}", e);
+                if let Some(progress_tx) = progress_tx {
+                    progress_tx.done(&file.id, FileStatus::Failed).await;
+                }
+                return Err(ReceiveError::Cancelled)?;
+            }
+        }
+    }
+
+    writer.flush().await?;
+    if let (Some(expected), Some(hasher)) = (expected, hasher) {
+        let actual = hasher.finalize();
+        if actual != expected {
+            let message = format!("expected digest {} but got {}", expected, actual);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message))?;
+        }
+    }
+    Ok(position - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::dto::{FileDto, FileType};
+
+    use crate::{util::hash::FileHash, Error};
+
+    use super::{copy_body, copy_body_from};
+
+    fn file(hash: Option<String>) -> FileDto {
+        FileDto {
+            id: "a".to_owned(),
+            file_name: "a.txt".to_owned(),
+            size: 5,
+            file_type: FileType::Text,
+            hash,
+            preview: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_body() {
+        for hash in [
+            FileHash::sha256("hello").to_string(),
+            "5d41402abc4b2a76b9719d911017c592".to_owned(),
+        ] {
+            let file = file(Some(hash));
+            let mut written = Vec::new();
+            let bytes = copy_body(&mut &b"hello"[..], &mut written, &file, &None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(bytes, 5);
+            assert_eq!(written, b"hello");
+
+            let result = copy_body(
+                &mut &b"hallo"[..],
+                &mut Vec::new(),
+                &file,
+                &None,
+                None,
+                None,
+            )
+            .await;
+            assert!(
+                matches!(&result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData),
+                "{:?}",
+                result
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unverified_body() {
+        // unknown digests and resumed bodies are taken as they are
+        for file in [file(None), file(Some("abc".to_owned()))] {
+            copy_body(
+                &mut &b"hallo"[..],
+                &mut Vec::new(),
+                &file,
+                &None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        let file = file(Some(FileHash::sha256("hello").to_string()));
+        copy_body_from(
+            &mut &b"lo"[..],
+            &mut Vec::new(),
+            &file,
+            3,
+            &None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+}
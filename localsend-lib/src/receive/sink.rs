@@ -0,0 +1,639 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use async_trait::async_trait;
+use localsend_proto::dto::{FileDto, FileType};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWrite, BufWriter},
+    sync::mpsc::Sender,
+};
+use tokio_util::sync::PollSender;
+
+use crate::{
+    util::fs::{
+        normalize_file_name, resolve_collision, resolve_session_collision, DangerousExtensions,
+        NameRules, SessionNames,
+    },
+    CollisionPolicy,
+};
+
+use super::SessionJournal;
+
+pub type SinkWriter = Pin<Box<dyn AsyncWrite + Send>>;
+type SinkFn = dyn Fn(&str) -> Arc<dyn ReceiveSink> + Send + Sync;
+
+/// Where the bodies of received files are written to.
+///
+/// For every file [`ReceiveSink::open`] is called first, followed by
+/// [`ReceiveSink::finish`] once the whole body was written or
+/// [`ReceiveSink::abort`] when receiving it failed.
+#[async_trait]
+pub trait ReceiveSink: Send + Sync + fmt::Debug {
+    async fn open(&self, file: &FileDto) -> io::Result<SinkWriter>;
+
+    /// Returns where the file was saved, if it was saved to disk.
+    async fn finish(&self, file: &FileDto) -> io::Result<Option<PathBuf>>;
+
+    async fn abort(&self, file: &FileDto);
+
+    /// Size of the existing file [`ReceiveSink::finish`] appended the body of `file` to,
+    /// asked once after it.
+    fn appended(&self, _file: &FileDto) -> Option<u64> {
+        None
+    }
+}
+
+/// Creates the sink of an accepted session from its session id.
+#[derive(Clone)]
+pub struct SinkFactory(Arc<SinkFn>);
+
+impl SinkFactory {
+    pub fn new(factory: impl Fn(&str) -> Arc<dyn ReceiveSink> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(factory))
+    }
+
+    pub fn create(&self, session_id: &str) -> Arc<dyn ReceiveSink> {
+        (self.0)(session_id)
+    }
+}
+
+impl fmt::Debug for SinkFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SinkFactory")
+    }
+}
+
+/// Saves files below a destination directory, the default sink.
+#[derive(Debug)]
+pub struct FsSink {
+    destination: PathBuf,
+    collision_policy: CollisionPolicy,
+    name_rules: NameRules,
+    name_replacement: char,
+    dangerous_extensions: DangerousExtensions,
+    /// Shared with the session, `None` only compares names as the filesystem does
+    names: Option<SessionNames>,
+    journal: Option<SessionJournal>,
+    append_any_type: bool,
+    // file id to the path it is written to
+    paths: Mutex<HashMap<String, PathBuf>>,
+    // file id to the existing file its body is appended to once finished
+    appends: Mutex<HashMap<String, PathBuf>>,
+    // file id to the size of the file it was appended to
+    appended: Mutex<HashMap<String, u64>>,
+}
+
+impl FsSink {
+    pub fn new(destination: impl AsRef<Path>, collision_policy: CollisionPolicy) -> Self {
+        Self {
+            destination: destination.as_ref().to_path_buf(),
+            collision_policy,
+            name_rules: NameRules::native(),
+            name_replacement: '_',
+            dangerous_extensions: DangerousExtensions::default(),
+            names: None,
+            journal: None,
+            append_any_type: false,
+            paths: Mutex::new(HashMap::new()),
+            appends: Mutex::new(HashMap::new()),
+            appended: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces characters the destination does not accept with `replacement`.
+    pub fn with_name_rules(mut self, rules: NameRules, replacement: char) -> Self {
+        self.name_rules = rules;
+        self.name_replacement = replacement;
+        self
+    }
+
+    /// Appends `.received` to the names of files with one of these extensions.
+    pub fn with_dangerous_extensions(mut self, extensions: DangerousExtensions) -> Self {
+        self.dangerous_extensions = extensions;
+        self
+    }
+
+    /// Also renames files whose names only differ in case or Unicode normalization
+    /// from others in `names`, when the collision policy renames.
+    pub fn with_session_names(mut self, names: SessionNames) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    /// Records in `journal` where each file is written and whether it is complete.
+    pub fn with_journal(mut self, journal: SessionJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Also appends images, videos, PDFs and APKs with [`CollisionPolicy::Append`].
+    pub fn with_append_any_type(mut self, append_any_type: bool) -> Self {
+        self.append_any_type = append_any_type;
+        self
+    }
+
+    /// Whether the body of `file` is appended to the existing file at `path`, not
+    /// when the session writes to it itself.
+    fn appends_to(&self, file: &FileDto, path: &Path) -> bool {
+        self.collision_policy == CollisionPolicy::Append
+            && (self.append_any_type || matches!(file.file_type, FileType::Text | FileType::Other))
+            && path.is_file()
+            && !self.paths.lock().unwrap().values().any(|p| p == path)
+            && !self.appends.lock().unwrap().values().any(|p| p == path)
+    }
+}
+
+/// Appends `source` to `target` and returns the size of `target`, which is truncated
+/// back to its size before when appending fails.
+async fn append_file(source: &Path, target: &Path) -> io::Result<u64> {
+    let mut source = File::open(source).await?;
+    let mut target = OpenOptions::new().append(true).open(target).await?;
+    let size = target.metadata().await?.len();
+    match tokio::io::copy(&mut source, &mut target).await {
+        Ok(appended) => Ok(size + appended),
+        Err(e) => {
+            target.set_len(size).await.ok();
+            Err(e)
+        }
+    }
+}
+
+/// Where [`FsSink`] saves a file, before resolving collisions.
+pub(crate) fn fs_path(
+    destination: &Path,
+    file_name: &str,
+    rules: NameRules,
+    replacement: char,
+    dangerous: &DangerousExtensions,
+) -> PathBuf {
+    let name = normalize_file_name(file_name, rules, replacement);
+    if let Some(defused) = dangerous.defuse(&name) {
+        log::warn!(
+            "Saving {:?} as {:?}, files of this type may run when opened",
+            file_name,
+            defused
+        );
+        return destination.join(defused);
+    }
+    if name != file_name {
+        log::warn!("Saving {:?} as {:?}", file_name, name);
+    }
+    destination.join(name)
+}
+
+#[async_trait]
+impl ReceiveSink for FsSink {
+    async fn open(&self, file: &FileDto) -> io::Result<SinkWriter> {
+        let path = fs_path(
+            &self.destination,
+            &file.file_name,
+            self.name_rules,
+            self.name_replacement,
+            &self.dangerous_extensions,
+        );
+        if let Some(path) = path.parent() {
+            if !path.exists() {
+                tokio::fs::create_dir_all(path).await?;
+            }
+        }
+        // the body is written next to the file first, a failed upload leaves it unchanged
+        if self.appends_to(file, &path) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let temp = path.with_file_name(format!(
+                ".{}.{}.localsend-append",
+                name,
+                uuid::Uuid::new_v4().simple()
+            ));
+            if let Some(journal) = &self.journal {
+                journal.opened(&file.id, &temp);
+            }
+            let file_handle = File::create(&temp).await?;
+            self.paths.lock().unwrap().insert(file.id.clone(), temp);
+            self.appends.lock().unwrap().insert(file.id.clone(), path);
+            return Ok(Box::pin(BufWriter::new(file_handle)));
+        }
+        let path = match &self.names {
+            Some(names) => resolve_session_collision(path, self.collision_policy, names),
+            None => resolve_collision(path, self.collision_policy),
+        };
+
+        // journaled first, a crash must not leave a file nobody knows about
+        if let Some(journal) = &self.journal {
+            journal.opened(&file.id, &path);
+        }
+        let file_handle = File::create(&path).await?;
+        self.paths.lock().unwrap().insert(file.id.clone(), path);
+        Ok(Box::pin(BufWriter::new(file_handle)))
+    }
+
+    async fn finish(&self, file: &FileDto) -> io::Result<Option<PathBuf>> {
+        let path = self.paths.lock().unwrap().remove(&file.id);
+        let target = self.appends.lock().unwrap().remove(&file.id);
+        if let (Some(temp), Some(target)) = (&path, target) {
+            let appended = append_file(temp, &target).await;
+            tokio::fs::remove_file(temp).await.ok();
+            if let Some(journal) = &self.journal {
+                match appended {
+                    Ok(_) => journal.finished(&file.id),
+                    Err(_) => journal.aborted(&file.id),
+                }
+            }
+            let size = appended?;
+            log::info!(
+                "Appended {} bytes to {:?}, now {} bytes",
+                file.size,
+                target,
+                size
+            );
+            self.appended.lock().unwrap().insert(file.id.clone(), size);
+            return Ok(Some(target));
+        }
+        if let Some(journal) = &self.journal {
+            journal.finished(&file.id);
+        }
+        Ok(path)
+    }
+
+    async fn abort(&self, file: &FileDto) {
+        let path = self.paths.lock().unwrap().remove(&file.id);
+        self.appends.lock().unwrap().remove(&file.id);
+        if let Some(path) = path {
+            tokio::fs::remove_file(path).await.ok();
+        }
+        if let Some(journal) = &self.journal {
+            journal.aborted(&file.id);
+        }
+    }
+
+    fn appended(&self, file: &FileDto) -> Option<u64> {
+        self.appended.lock().unwrap().remove(&file.id)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SinkEvent {
+    Opened(FileDto),
+    Chunk { file_id: String, data: Vec<u8> },
+    Finished(String),
+    Aborted(String),
+}
+
+/// Streams received files over a channel instead of saving them.
+///
+/// Writing waits while the channel is full, so a slow consumer slows down the
+/// sender instead of buffering in memory. Receiving fails once the consumer
+/// drops its receiver.
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    tx: Sender<SinkEvent>,
+}
+
+impl ChannelSink {
+    pub fn new(tx: Sender<SinkEvent>) -> Self {
+        Self { tx }
+    }
+
+    async fn send(&self, event: SinkEvent) -> io::Result<()> {
+        self.tx.send(event).await.map_err(|_| closed())
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Sink receiver dropped")
+}
+
+#[async_trait]
+impl ReceiveSink for ChannelSink {
+    async fn open(&self, file: &FileDto) -> io::Result<SinkWriter> {
+        self.send(SinkEvent::Opened(file.clone())).await?;
+        Ok(Box::pin(ChannelWriter {
+            file_id: file.id.clone(),
+            tx: PollSender::new(self.tx.clone()),
+        }))
+    }
+
+    async fn finish(&self, file: &FileDto) -> io::Result<Option<PathBuf>> {
+        self.send(SinkEvent::Finished(file.id.clone())).await?;
+        Ok(None)
+    }
+
+    async fn abort(&self, file: &FileDto) {
+        self.send(SinkEvent::Aborted(file.id.clone())).await.ok();
+    }
+}
+
+struct ChannelWriter {
+    file_id: String,
+    tx: PollSender<SinkEvent>,
+}
+
+impl AsyncWrite for ChannelWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.tx.poll_reserve(cx)).map_err(|_| closed())?;
+        let event = SinkEvent::Chunk {
+            file_id: self.file_id.clone(),
+            data: buf.to_vec(),
+        };
+        self.tx.send_item(event).map_err(|_| closed())?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use async_trait::async_trait;
+    use localsend_proto::{
+        dto::{FileDto, FileType},
+        fixtures::device,
+    };
+    use tokio::{io::AsyncWriteExt, sync::mpsc::Receiver};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        receive::ReceiveReport,
+        send::{FileStatus, SendSession, SendingFiles},
+        server::ServerMessage,
+        test_util::TestReceiver,
+        util::fs::{DangerousExtensions, NameRules},
+        CollisionPolicy,
+    };
+
+    use super::{ChannelSink, FsSink, ReceiveSink, SinkEvent, SinkFactory, SinkWriter};
+
+    /// Sends `files` to ourselves with the sink created by `factory`.
+    async fn send_to_sink(
+        files: &[(&str, Vec<u8>)],
+        factory: SinkFactory,
+    ) -> (SendingFiles, ReceiveReport) {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut sending = SendingFiles::default();
+        for (name, data) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            sending.add_file(&path, None).unwrap();
+        }
+
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.sink_factory = Some(factory);
+        })
+        .await;
+        let sent = SendSession::new(&device("sender", 0), receiver.device(), &sending)
+            .upload(Some(receiver.state.clone()), &CancellationToken::new())
+            .await
+            .unwrap();
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert!(!receiver.destination.exists());
+        receiver.stop().await;
+
+        std::fs::remove_dir_all(dir).ok();
+        (sent, report)
+    }
+
+    async fn collect(mut rx: Receiver<SinkEvent>) -> HashMap<String, (FileDto, Vec<u8>, bool)> {
+        let mut files = HashMap::new();
+        let mut ids = HashMap::new();
+        while let Some(event) = rx.recv().await {
+            match event {
+                SinkEvent::Opened(file) => {
+                    ids.insert(file.id.clone(), file.file_name.clone());
+                    files.insert(file.file_name.clone(), (file, vec![], false));
+                }
+                SinkEvent::Chunk { file_id, data } => {
+                    files.get_mut(&ids[&file_id]).unwrap().1.extend(data)
+                }
+                SinkEvent::Finished(file_id) => files.get_mut(&ids[&file_id]).unwrap().2 = true,
+                SinkEvent::Aborted(file_id) => panic!("aborted {}", file_id),
+            }
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_channel_sink() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let consumer = tokio::spawn(collect(rx));
+        let factory = SinkFactory::new(move |_| Arc::new(ChannelSink::new(tx.clone())));
+
+        let big: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let files = [("big.bin", big.clone()), ("small.txt", b"hello".to_vec())];
+        let (sent, report) = send_to_sink(&files, factory).await;
+        assert!(sent.values().all(|f| f.status == FileStatus::Finished));
+        assert_eq!(report.finished(), 2);
+        assert!(report.files.iter().all(|f| f.path.is_none()));
+        assert_eq!(report.total_bytes, big.len() as u64 + 5);
+
+        let received = consumer.await.unwrap();
+        assert_eq!(received["big.bin"].1, big);
+        assert_eq!(received["small.txt"].1, b"hello");
+        assert!(received.values().all(|(_, _, finished)| *finished));
+    }
+
+    /// Fails every write, like a full disk or a rejecting upstream.
+    #[derive(Debug, Default)]
+    struct FailingSink {
+        aborted: AtomicBool,
+    }
+
+    struct FailingWriter;
+
+    impl tokio::io::AsyncWrite for FailingWriter {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::task::Poll::Ready(Err(io::Error::other("no space left")))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_trait]
+    impl ReceiveSink for FailingSink {
+        async fn open(&self, _file: &FileDto) -> io::Result<SinkWriter> {
+            Ok(Box::pin(FailingWriter))
+        }
+
+        async fn finish(&self, _file: &FileDto) -> io::Result<Option<PathBuf>> {
+            panic!("failed files are never finished")
+        }
+
+        async fn abort(&self, _file: &FileDto) {
+            self.aborted.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_sink() {
+        let sink = Arc::new(FailingSink::default());
+        let factory = {
+            let sink = sink.clone();
+            SinkFactory::new(move |_| sink.clone())
+        };
+
+        let (sent, report) = send_to_sink(&[("a.bin", vec![1; 1024])], factory).await;
+        assert!(sent.values().all(|f| f.status == FileStatus::Failed));
+        assert_eq!(report.finished(), 0);
+        assert_eq!(report.files[0].status, FileStatus::Failed);
+        assert!(report.files[0].reason.is_some());
+        assert!(sink.aborted.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_fs_sink_normalizes_names() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let sink =
+            FsSink::new(&dir, CollisionPolicy::Overwrite).with_name_rules(NameRules::Windows, '_');
+        let file = FileDto {
+            id: "1".to_owned(),
+            file_name: "../photos/photo:2024?.jpg".to_owned(),
+            size: 3,
+            file_type: FileType::Image,
+            hash: None,
+            preview: None,
+        };
+
+        let mut writer = sink.open(&file).await.unwrap();
+        writer.write_all(b"jpg").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let path = sink.finish(&file).await.unwrap().unwrap();
+        assert_eq!(path, dir.join("photos").join("photo_2024_.jpg"));
+        assert_eq!(std::fs::read(path).unwrap(), b"jpg");
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_fs_sink_defuses_dangerous_names() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let sink = FsSink::new(&dir, CollisionPolicy::Rename)
+            .with_dangerous_extensions(DangerousExtensions::new(["exe"]));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("invoice.pdf.EXE.received"), b"old").unwrap();
+        let file = FileDto {
+            id: "1".to_owned(),
+            file_name: "invoice.pdf.EXE.".to_owned(),
+            size: 2,
+            file_type: FileType::Other,
+            hash: None,
+            preview: None,
+        };
+
+        let mut writer = sink.open(&file).await.unwrap();
+        writer.write_all(b"MZ").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let path = sink.finish(&file).await.unwrap().unwrap();
+        // collisions are resolved on the name saved
+        assert_eq!(path, dir.join("invoice.pdf.EXE (1).received"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_fs_sink_appends() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("device.log"), b"one\n").unwrap();
+        std::fs::write(dir.join("photo.jpg"), b"jpg").unwrap();
+        let sink = FsSink::new(&dir, CollisionPolicy::Append);
+        let file = |id: &str, file_name: &str, file_type| FileDto {
+            id: id.to_owned(),
+            file_name: file_name.to_owned(),
+            size: 4,
+            file_type,
+            hash: None,
+            preview: None,
+        };
+
+        let log = file("1", "device.log", FileType::Other);
+        let mut writer = sink.open(&log).await.unwrap();
+        writer.write_all(b"two\n").await.unwrap();
+        writer.shutdown().await.unwrap();
+        // nothing is appended before the body is complete
+        assert_eq!(std::fs::read(dir.join("device.log")).unwrap(), b"one\n");
+        drop(writer);
+        assert_eq!(
+            sink.finish(&log).await.unwrap(),
+            Some(dir.join("device.log"))
+        );
+        assert_eq!(sink.appended(&log), Some(8));
+        assert_eq!(
+            std::fs::read(dir.join("device.log")).unwrap(),
+            b"one\ntwo\n"
+        );
+
+        let failed = file("2", "device.log", FileType::Other);
+        let mut writer = sink.open(&failed).await.unwrap();
+        writer.write_all(b"bro").await.unwrap();
+        drop(writer);
+        sink.abort(&failed).await;
+        assert_eq!(sink.appended(&failed), None);
+        assert_eq!(
+            std::fs::read(dir.join("device.log")).unwrap(),
+            b"one\ntwo\n"
+        );
+
+        // images are renamed
+        let photo = file("3", "photo.jpg", FileType::Image);
+        let mut writer = sink.open(&photo).await.unwrap();
+        writer.write_all(b"jpeg").await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+        assert_eq!(
+            sink.finish(&photo).await.unwrap(),
+            Some(dir.join("photo (1).jpg"))
+        );
+        assert_eq!(sink.appended(&photo), None);
+
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 3);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
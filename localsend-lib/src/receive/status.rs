@@ -0,0 +1,314 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::send::FileStatus;
+
+use super::{ReceiveSession, ReceivingFile};
+
+/// The status file is rewritten at most once in this interval.
+pub const STATUS_WRITE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What the receiver is doing, as written to the status file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum ReceiverStatus {
+    #[default]
+    Idle,
+    Receiving(SessionProgress),
+    /// The last session, until the next one starts
+    Finished(SessionProgress),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionProgress {
+    pub session_id: String,
+    pub sender: String,
+    pub files: Vec<FileProgress>,
+    pub total_size: u64,
+    pub total_position: u64,
+    /// Seconds since the unix epoch
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileProgress {
+    pub id: String,
+    pub file_name: String,
+    pub size: u64,
+    pub position: u64,
+    pub status: FileStatus,
+}
+
+impl FileProgress {
+    fn new(file: &ReceivingFile) -> Self {
+        Self {
+            id: file.file.id.clone(),
+            file_name: file.file.file_name.clone(),
+            size: file.file.size,
+            position: 0,
+            status: file.status.clone(),
+        }
+    }
+}
+
+impl SessionProgress {
+    fn file_mut(&mut self, file_id: &str) -> Option<&mut FileProgress> {
+        self.files.iter_mut().find(|file| file.id == file_id)
+    }
+
+    fn update_total(&mut self) {
+        self.total_position = self.files.iter().map(|file| file.position).sum();
+    }
+}
+
+/// Latest [`ReceiverStatus`], updated without ever waiting for the status file.
+#[derive(Debug, Clone)]
+pub struct StatusTracker(Arc<watch::Sender<ReceiverStatus>>);
+
+impl Default for StatusTracker {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(ReceiverStatus::Idle).0))
+    }
+}
+
+impl StatusTracker {
+    pub fn subscribe(&self) -> watch::Receiver<ReceiverStatus> {
+        self.0.subscribe()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.0.receiver_count()
+    }
+
+    pub fn current(&self) -> ReceiverStatus {
+        self.0.borrow().clone()
+    }
+
+    /// Starts tracking an accepted session, skipped files are left out.
+    pub fn start(&self, session: &ReceiveSession) {
+        let mut files: Vec<FileProgress> = session
+            .files
+            .values()
+            .filter(|file| file.status != FileStatus::Skipped)
+            .map(FileProgress::new)
+            .collect();
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        self.0
+            .send_replace(ReceiverStatus::Receiving(SessionProgress {
+                session_id: session.session_id.clone(),
+                sender: session.sender.alias.clone(),
+                total_size: files.iter().map(|file| file.size).sum(),
+                total_position: 0,
+                files,
+                finished_at: None,
+            }));
+    }
+
+    /// Adds the files of an extended session.
+    pub fn add_files<'a>(&self, files: impl IntoIterator<Item = &'a ReceivingFile>) {
+        let added: Vec<FileProgress> = files
+            .into_iter()
+            .filter(|file| file.status != FileStatus::Skipped)
+            .map(FileProgress::new)
+            .collect();
+        self.0.send_if_modified(|status| {
+            let ReceiverStatus::Receiving(session) = status else {
+                return false;
+            };
+            session.total_size += added.iter().map(|file| file.size).sum::<u64>();
+            session.files.extend(added);
+            true
+        });
+    }
+
+    pub fn progress(&self, file_id: &str, position: u64) {
+        self.update_file(file_id, |file| {
+            file.position = position;
+            file.status = FileStatus::Sending;
+        });
+    }
+
+    pub fn file_finished(&self, file_id: &str, status: FileStatus) {
+        self.update_file(file_id, |file| {
+            if status == FileStatus::Finished {
+                file.position = file.size;
+            }
+            file.status = status;
+        });
+    }
+
+    /// Keeps the last session with the time it finished.
+    pub fn finish(&self) {
+        self.0.send_if_modified(|status| match status {
+            ReceiverStatus::Receiving(session) => {
+                let mut session = session.clone();
+                session.finished_at = Some(unix_time());
+                *status = ReceiverStatus::Finished(session);
+                true
+            }
+            _ => false,
+        });
+    }
+
+    /// Forgets a cancelled or expired session.
+    pub fn clear(&self) {
+        self.0.send_if_modified(|status| match status {
+            ReceiverStatus::Receiving(_) => {
+                *status = ReceiverStatus::Idle;
+                true
+            }
+            _ => false,
+        });
+    }
+
+    fn update_file(&self, file_id: &str, update: impl FnOnce(&mut FileProgress)) {
+        self.0.send_if_modified(|status| {
+            let ReceiverStatus::Receiving(session) = status else {
+                return false;
+            };
+            let Some(file) = session.file_mut(file_id) else {
+                return false;
+            };
+            update(file);
+            session.update_total();
+            true
+        });
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Writes every change of `tracker` to `path`, at most once per [`STATUS_WRITE_INTERVAL`].
+///
+/// The file is replaced atomically, readers never see a partial document.
+/// The writer stops after the first failed write, e.g. on a read-only filesystem.
+pub(crate) fn spawn_status_writer(path: PathBuf, tracker: &StatusTracker) -> JoinHandle<()> {
+    let mut status_rx = tracker.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let status = status_rx.borrow_and_update().clone();
+            if let Err(e) = write_status(&path, &status).await {
+                log::error!(
+                    "Failed to write status file {:?}, disabling it: {}",
+                    path,
+                    e
+                );
+                return;
+            }
+            tokio::time::sleep(STATUS_WRITE_INTERVAL).await;
+            if status_rx.changed().await.is_err() {
+                return;
+            }
+        }
+    })
+}
+
+async fn write_status(path: &Path, status: &ReceiverStatus) -> std::io::Result<()> {
+    let json = serde_json::to_vec_pretty(status)?;
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    let temp_path = path.with_file_name(file_name);
+    tokio::fs::write(&temp_path, json).await?;
+    if let Err(e) = tokio::fs::rename(&temp_path, path).await {
+        tokio::fs::remove_file(&temp_path).await.ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use localsend_proto::dto::PrepareUploadResponseDto;
+    use reqwest::Body;
+    use serde_json::Value;
+
+    use crate::test_util::TestReceiver;
+
+    use super::{spawn_status_writer, StatusTracker};
+
+    fn read_status(path: &PathBuf) -> Option<Value> {
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
+
+    #[tokio::test]
+    async fn test_mid_transfer_snapshot() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let status_file = dir.join("status.json");
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.status_file = Some(status_file.clone());
+        })
+        .await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare_sized(&[("0", 8)])
+            .await
+            .json()
+            .await
+            .unwrap();
+
+        // half of the file arrives, then the sender stalls
+        let stalled = async_stream::stream! {
+            yield std::io::Result::Ok(b"0000".to_vec());
+            std::future::pending::<()>().await;
+        };
+        let upload = tokio::spawn(
+            receiver
+                .upload(&session, "0", Body::wrap_stream(stalled))
+                .send(),
+        );
+
+        let mut snapshot = None;
+        for _ in 0..100 {
+            match read_status(&status_file) {
+                Some(status) if status["state"] == "receiving" && status["totalPosition"] == 4 => {
+                    snapshot = Some(status);
+                    break;
+                }
+                _ => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        let snapshot = snapshot.expect("no mid-transfer snapshot");
+        assert_eq!(snapshot["sessionId"], session.session_id.as_str());
+        assert_eq!(snapshot["sender"], "sender");
+        assert_eq!(snapshot["totalSize"], 8);
+        assert_eq!(snapshot["finishedAt"], Value::Null);
+        let files = snapshot["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0]["fileName"], "0.bin");
+        assert_eq!(files[0]["size"], 8);
+        assert_eq!(files[0]["position"], 4);
+        assert_eq!(files[0]["status"], "sending");
+
+        upload.abort();
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_path_disables_writer() {
+        let path = std::env::temp_dir()
+            .join(uuid::Uuid::new_v4().to_string())
+            .join("status.json");
+        let writer = spawn_status_writer(path, &StatusTracker::default());
+        tokio::time::timeout(Duration::from_secs(1), writer)
+            .await
+            .expect("writer still running")
+            .unwrap();
+    }
+}
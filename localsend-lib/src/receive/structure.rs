@@ -0,0 +1,196 @@
+use std::{collections::HashSet, fmt};
+
+use localsend_proto::dto::FileDto;
+
+pub const DEFAULT_MAX_PATH_DEPTH: usize = 16;
+/// Longer names are cut to fit the file system anyway, far longer ones are refused.
+pub const DEFAULT_MAX_COMPONENT_LEN: usize = 1024;
+pub const DEFAULT_MAX_DIRECTORIES: usize = 4096;
+pub const DEFAULT_MAX_FILES: usize = 10_000;
+
+/// Which limit of [`StructureLimits`] a file broke, with its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureLimit {
+    Depth(usize),
+    ComponentLength(usize),
+    Directories(usize),
+    Files(usize),
+}
+
+impl fmt::Display for StructureLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StructureLimit::Depth(max) => write!(f, "{} nested directories", max),
+            StructureLimit::ComponentLength(max) => write!(f, "{} bytes per name", max),
+            StructureLimit::Directories(max) => write!(f, "{} directories per session", max),
+            StructureLimit::Files(max) => write!(f, "{} files per session", max),
+        }
+    }
+}
+
+/// Bounds the directories a sender creates through the `/` in file names.
+///
+/// Without them a sender could offer thousands of files, each in its own deep
+/// directory, and leave the destination full of empty directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructureLimits {
+    /// Directories above a file
+    pub max_depth: usize,
+    /// Bytes of each directory and file name
+    pub max_component_len: usize,
+    /// Distinct directories of a session
+    pub max_directories: usize,
+    /// Files of a session, texts included
+    pub max_files: usize,
+}
+
+impl Default for StructureLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_PATH_DEPTH,
+            max_component_len: DEFAULT_MAX_COMPONENT_LEN,
+            max_directories: DEFAULT_MAX_DIRECTORIES,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+}
+
+impl StructureLimits {
+    /// Splits `files` into those within the limits and those breaking one, going
+    /// through them by name so that the first files of a directory are kept.
+    ///
+    /// `known` are the names of the files the session has already, their
+    /// directories and number count towards the limits.
+    pub fn check<'a>(
+        &self,
+        known: impl IntoIterator<Item = &'a str>,
+        mut files: Vec<FileDto>,
+    ) -> (Vec<FileDto>, Vec<(FileDto, StructureLimit)>) {
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        let mut directories = HashSet::new();
+        let mut count = 0;
+        for name in known {
+            directories.extend(directories_of(name));
+            count += 1;
+        }
+        let mut accepted = Vec::with_capacity(files.len());
+        let mut rejected = vec![];
+        for file in files {
+            match self.check_file(&file.file_name, &directories, count) {
+                Some(limit) => rejected.push((file, limit)),
+                None => {
+                    directories.extend(directories_of(&file.file_name));
+                    count += 1;
+                    accepted.push(file);
+                }
+            }
+        }
+        (accepted, rejected)
+    }
+
+    fn check_file(
+        &self,
+        name: &str,
+        directories: &HashSet<String>,
+        count: usize,
+    ) -> Option<StructureLimit> {
+        if count >= self.max_files {
+            return Some(StructureLimit::Files(self.max_files));
+        }
+        let components: Vec<&str> = components(name).collect();
+        if components.len().saturating_sub(1) > self.max_depth {
+            return Some(StructureLimit::Depth(self.max_depth));
+        }
+        if components.iter().any(|c| c.len() > self.max_component_len) {
+            return Some(StructureLimit::ComponentLength(self.max_component_len));
+        }
+        let new = directories_of(name)
+            .filter(|dir| !directories.contains(dir))
+            .count();
+        if directories.len() + new > self.max_directories {
+            return Some(StructureLimit::Directories(self.max_directories));
+        }
+        None
+    }
+}
+
+/// The parts of a file name that end up on disk, like `normalize_file_name` splits them.
+fn components(name: &str) -> impl Iterator<Item = &str> {
+    name.split('/').filter(|c| !matches!(*c, "" | "." | ".."))
+}
+
+/// Every directory a file creates, `a/b/c.txt` creates `a` and `a/b`.
+fn directories_of(name: &str) -> impl Iterator<Item = String> + '_ {
+    let components: Vec<&str> = components(name).collect();
+    let depth = components.len().saturating_sub(1);
+    (1..=depth).map(move |end| components[..end].join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::dto::{FileDto, FileType};
+
+    use super::{StructureLimit, StructureLimits};
+
+    fn file(name: &str) -> FileDto {
+        FileDto {
+            id: name.to_owned(),
+            file_name: name.to_owned(),
+            size: 1,
+            file_type: FileType::Other,
+            hash: None,
+            preview: None,
+        }
+    }
+
+    fn check(limits: &StructureLimits, names: &[String]) -> Vec<(String, StructureLimit)> {
+        let files = names.iter().map(|name| file(name)).collect();
+        let (_, rejected) = limits.check([], files);
+        rejected
+            .into_iter()
+            .map(|(file, limit)| (file.file_name, limit))
+            .collect()
+    }
+
+    #[test]
+    fn test_structure_limits() {
+        let limits = StructureLimits::default();
+        let deep = "d/".repeat(17) + "bomb.txt";
+        let names = vec![
+            "src/main.rs".to_owned(),
+            "d/".repeat(16) + "deepest.txt",
+            deep.clone(),
+            format!("{}/a.txt", "n".repeat(1025)),
+        ];
+        assert_eq!(
+            check(&limits, &names),
+            [
+                (deep.clone(), StructureLimit::Depth(16)),
+                (names[3].clone(), StructureLimit::ComponentLength(1024)),
+            ]
+        );
+        // raised for a deep source tree
+        let raised = StructureLimits {
+            max_depth: 64,
+            ..limits
+        };
+        assert!(check(&raised, &[deep]).is_empty());
+
+        // every file in its own directory
+        let bomb: Vec<String> = (0..5000).map(|i| format!("{:04}/f", i)).collect();
+        let rejected = check(&limits, &bomb);
+        assert_eq!(rejected.len(), 5000 - 4096);
+        assert_eq!(
+            rejected[0],
+            ("4096/f".to_owned(), StructureLimit::Directories(4096))
+        );
+
+        let few = StructureLimits {
+            max_files: 2,
+            ..limits
+        };
+        let (accepted, rejected) = few.check(["a/x"], vec![file("a/y"), file("a/z")]);
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(rejected[0].1, StructureLimit::Files(2));
+    }
+}
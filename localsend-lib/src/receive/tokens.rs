@@ -0,0 +1,205 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use subtle::ConstantTimeEq;
+
+/// Creates the upload tokens handed out by prepare-upload, one per accepted file.
+pub trait TokenIssuer: Send + Sync + fmt::Debug {
+    fn issue(&self) -> String;
+}
+
+/// UUID v4 tokens like the official apps use, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidTokens;
+
+impl TokenIssuer for UuidTokens {
+    fn issue(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// 32 random bytes in unpadded base64url, 43 characters, for peers expecting opaque tokens.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomTokens;
+
+impl TokenIssuer for RandomTokens {
+    fn issue(&self) -> String {
+        let mut bytes = [0u8; 32];
+        getrandom::getrandom(&mut bytes).expect("No source of randomness");
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+}
+
+/// The token of a receiving file and when it was issued.
+#[derive(Debug, Clone)]
+pub struct FileToken {
+    value: String,
+    issued: Instant,
+    /// Set once an upload started with the token
+    used: bool,
+}
+
+impl FileToken {
+    pub fn new(value: String) -> Self {
+        Self {
+            value,
+            issued: Instant::now(),
+            used: false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+
+    pub fn mark_used(&mut self) {
+        self.used = true;
+    }
+
+    /// Compares in constant time, only the length of `presented` shows in the timing.
+    pub fn matches(&self, presented: &str) -> bool {
+        self.value.as_bytes().ct_eq(presented.as_bytes()).into()
+    }
+}
+
+/// Why a token was refused, only logged, senders see an invalid token either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenRejection {
+    /// The file has no token, e.g. it was not selected
+    Missing,
+    Mismatch,
+    Expired,
+    /// Already used under [`TokenPolicy::single_use`]
+    Used,
+}
+
+impl fmt::Display for TokenRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TokenRejection::Missing => "no token issued",
+            TokenRejection::Mismatch => "wrong token",
+            TokenRejection::Expired => "token expired",
+            TokenRejection::Used => "token already used",
+        })
+    }
+}
+
+/// When the token of a file stops being accepted, besides the end of its session.
+///
+/// By default a token stays valid for the whole session, so that a sender
+/// retrying a finished file is answered without sending it again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenPolicy {
+    /// Refuse tokens issued longer ago than this, even within a session
+    pub ttl: Option<Duration>,
+    /// Accept each token for a single upload, retries of it are refused
+    pub single_use: bool,
+}
+
+impl TokenPolicy {
+    pub fn validate(
+        &self,
+        token: Option<&FileToken>,
+        presented: &str,
+        now: Instant,
+    ) -> Result<(), TokenRejection> {
+        let token = token.ok_or(TokenRejection::Missing)?;
+        if !token.matches(presented) {
+            return Err(TokenRejection::Mismatch);
+        }
+        if self
+            .ttl
+            .is_some_and(|ttl| now.saturating_duration_since(token.issued) > ttl)
+        {
+            return Err(TokenRejection::Expired);
+        }
+        if self.single_use && token.used {
+            return Err(TokenRejection::Used);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FileToken, RandomTokens, TokenIssuer, TokenPolicy, TokenRejection, UuidTokens};
+
+    #[test]
+    fn test_issue_tokens() {
+        let uuid = UuidTokens.issue();
+        assert!(uuid::Uuid::parse_str(&uuid).is_ok());
+        assert_ne!(uuid, UuidTokens.issue());
+
+        let random = RandomTokens.issue();
+        assert_eq!(random.len(), 43);
+        assert!(random
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+        assert_ne!(random, RandomTokens.issue());
+    }
+
+    #[test]
+    fn test_validate_token() {
+        let mut token = FileToken::new("abc".to_owned());
+        let now = Instant::now();
+        let policy = TokenPolicy::default();
+        assert_eq!(policy.validate(Some(&token), "abc", now), Ok(()));
+        assert_eq!(
+            policy.validate(Some(&token), "abd", now),
+            Err(TokenRejection::Mismatch)
+        );
+        // a prefix or an extension of the token is as wrong as any other
+        assert_eq!(
+            policy.validate(Some(&token), "ab", now),
+            Err(TokenRejection::Mismatch)
+        );
+        assert_eq!(
+            policy.validate(Some(&token), "abcd", now),
+            Err(TokenRejection::Mismatch)
+        );
+        assert_eq!(
+            policy.validate(None, "abc", now),
+            Err(TokenRejection::Missing)
+        );
+
+        token.mark_used();
+        assert_eq!(policy.validate(Some(&token), "abc", now), Ok(()));
+        let single_use = TokenPolicy {
+            single_use: true,
+            ..policy
+        };
+        assert_eq!(
+            single_use.validate(Some(&token), "abc", now),
+            Err(TokenRejection::Used)
+        );
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let token = FileToken::new("abc".to_owned());
+        let policy = TokenPolicy {
+            ttl: Some(Duration::from_secs(60)),
+            ..TokenPolicy::default()
+        };
+        let issued = Instant::now();
+        assert_eq!(policy.validate(Some(&token), "abc", issued), Ok(()));
+        let later = issued + Duration::from_secs(61);
+        assert_eq!(
+            policy.validate(Some(&token), "abc", later),
+            Err(TokenRejection::Expired)
+        );
+        assert_eq!(
+            policy.validate(Some(&token), "abd", later),
+            Err(TokenRejection::Mismatch)
+        );
+        assert_eq!(
+            TokenPolicy::default().validate(Some(&token), "abc", later),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::{io, sync::Arc};
+
+use async_trait::async_trait;
+use localsend_proto::Device;
+use tokio::sync::mpsc::Receiver;
+use tokio_util::sync::CancellationToken;
+
+use super::{DeviceEvent, MulticastDeviceScanner, StaticDeviceProvider};
+
+/// Finds the devices to send to, what pickers and the CLI depend on rather than
+/// on a concrete scanner.
+#[async_trait]
+pub trait DeviceScanner: Send + Sync {
+    /// Scans until the answers settled, returns the devices found so far once
+    /// `cancel` is cancelled.
+    async fn scan(&self, cancel: &CancellationToken) -> io::Result<Vec<Device>>;
+
+    /// Reports devices as they appear, change and disappear until the receiver is
+    /// dropped.
+    fn subscribe(self: Arc<Self>) -> Receiver<DeviceEvent>;
+
+    /// Tells the devices around that this one is there.
+    async fn announce(&self);
+
+    /// Where the configured devices come from, if any.
+    fn static_devices(&self) -> Option<&StaticDeviceProvider> {
+        None
+    }
+
+    /// The configured devices, reported whether they answer or not.
+    fn configured_devices(&self) -> Vec<Device> {
+        self.static_devices()
+            .map(StaticDeviceProvider::devices)
+            .unwrap_or_default()
+    }
+
+    /// Forgets a device that did not answer until it is seen again.
+    fn mark_stale(&self, _device: &Device) {}
+}
+
+#[async_trait]
+impl DeviceScanner for MulticastDeviceScanner {
+    async fn scan(&self, cancel: &CancellationToken) -> io::Result<Vec<Device>> {
+        MulticastDeviceScanner::scan(self, cancel).await
+    }
+
+    fn subscribe(self: Arc<Self>) -> Receiver<DeviceEvent> {
+        MulticastDeviceScanner::subscribe(&self)
+    }
+
+    async fn announce(&self) {
+        self.send_announcement().await
+    }
+
+    fn static_devices(&self) -> Option<&StaticDeviceProvider> {
+        MulticastDeviceScanner::static_devices(self)
+    }
+
+    fn configured_devices(&self) -> Vec<Device> {
+        MulticastDeviceScanner::configured_devices(self)
+    }
+
+    fn mark_stale(&self, device: &Device) {
+        MulticastDeviceScanner::mark_stale(self, device)
+    }
+}
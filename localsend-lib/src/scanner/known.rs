@@ -0,0 +1,49 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use localsend_proto::Device;
+
+use super::{DeviceEvent, DeviceScanner};
+
+/// Devices currently online, kept up to date by a subscription of the scanner.
+#[derive(Debug, Clone, Default)]
+pub struct KnownDevices(Arc<Mutex<HashMap<String, Device>>>);
+
+impl KnownDevices {
+    /// Subscribes to `scanner` for as long as the returned devices are used.
+    pub fn track(scanner: Arc<dyn DeviceScanner>) -> Self {
+        let known = Self::default();
+        let devices = Arc::downgrade(&known.0);
+        let mut events = scanner.subscribe();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let Some(devices) = devices.upgrade() else {
+                    break;
+                };
+                KnownDevices(devices).apply(event);
+            }
+        });
+        known
+    }
+
+    pub fn apply(&self, event: DeviceEvent) {
+        let mut devices = self.0.lock().unwrap();
+        match event {
+            DeviceEvent::Found(device) => {
+                devices.insert(device.fingerprint.clone(), device);
+            }
+            DeviceEvent::Lost(device) => {
+                devices.remove(&device.fingerprint);
+            }
+        }
+    }
+
+    /// The devices sorted by alias.
+    pub fn devices(&self) -> Vec<Device> {
+        let mut devices: Vec<Device> = self.0.lock().unwrap().values().cloned().collect();
+        devices.sort_by(|a, b| a.alias.cmp(&b.alias));
+        devices
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use localsend_proto::Device;
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+use super::{parse_announcement, DeviceEvent, DeviceScanner};
+
+/// What a [`MockScanner`] does at a programmed instant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockStep {
+    /// The device appears, or changes when it was there
+    Add(Device),
+    /// The device with this fingerprint disappears
+    Remove(String),
+    /// A packet arrives from an address, parsed like the multicast scanner does, so
+    /// malformed ones are dropped
+    Packet(Vec<u8>, SocketAddr),
+}
+
+/// A scanner without sockets whose devices change as scripted, for tests and demos.
+///
+/// Instants are counted from the creation of the scanner. Subscriptions report the
+/// devices there when they start, then every step as its instant passes, and scans
+/// return the devices there at the time of the scan.
+#[derive(Debug)]
+pub struct MockScanner {
+    devices: Vec<Device>,
+    script: Vec<(Duration, MockStep)>,
+    started: Instant,
+    announcements: AtomicUsize,
+    stale: Mutex<Vec<String>>,
+}
+
+impl MockScanner {
+    pub fn new(devices: impl IntoIterator<Item = Device>) -> Self {
+        Self {
+            devices: devices.into_iter().collect(),
+            script: vec![],
+            started: Instant::now(),
+            announcements: AtomicUsize::default(),
+            stale: Mutex::default(),
+        }
+    }
+
+    /// Runs `step` once `at` passed, steps of the same instant run in the order added.
+    pub fn with_step(mut self, at: Duration, step: MockStep) -> Self {
+        let index = self.script.partition_point(|(other, _)| *other <= at);
+        self.script.insert(index, (at, step));
+        self
+    }
+
+    pub fn add_at(self, at: Duration, device: Device) -> Self {
+        self.with_step(at, MockStep::Add(device))
+    }
+
+    pub fn remove_at(self, at: Duration, device: &Device) -> Self {
+        self.with_step(at, MockStep::Remove(device.fingerprint.clone()))
+    }
+
+    pub fn packet_at(self, at: Duration, packet: impl Into<Vec<u8>>, addr: SocketAddr) -> Self {
+        self.with_step(at, MockStep::Packet(packet.into(), addr))
+    }
+
+    /// How often [`DeviceScanner::announce`] was called.
+    pub fn announcements(&self) -> usize {
+        self.announcements.load(Ordering::Relaxed)
+    }
+
+    /// Fingerprints passed to [`DeviceScanner::mark_stale`].
+    pub fn stale(&self) -> Vec<String> {
+        self.stale.lock().unwrap().clone()
+    }
+
+    /// The devices there once the steps up to `elapsed` ran.
+    pub fn devices_at(&self, elapsed: Duration) -> Vec<Device> {
+        let mut devices = self.devices.clone();
+        for (_, step) in self.script.iter().take_while(|(at, _)| *at <= elapsed) {
+            step.run(&mut devices);
+        }
+        devices
+    }
+}
+
+impl MockStep {
+    /// Applies the step to `devices`, returns what a subscription reports of it.
+    fn run(&self, devices: &mut Vec<Device>) -> Option<DeviceEvent> {
+        let device = match self {
+            Self::Add(device) => device.clone(),
+            Self::Remove(fingerprint) => {
+                let index = devices.iter().position(|d| &d.fingerprint == fingerprint)?;
+                return Some(DeviceEvent::Lost(devices.remove(index)));
+            }
+            Self::Packet(packet, addr) => parse_announcement(packet, *addr)?.0,
+        };
+        match devices
+            .iter_mut()
+            .find(|d| d.fingerprint == device.fingerprint)
+        {
+            Some(existing) => *existing = device.clone(),
+            None => devices.push(device.clone()),
+        }
+        Some(DeviceEvent::Found(device))
+    }
+}
+
+#[async_trait]
+impl DeviceScanner for MockScanner {
+    async fn scan(&self, _cancel: &CancellationToken) -> io::Result<Vec<Device>> {
+        Ok(self.devices_at(self.started.elapsed()))
+    }
+
+    fn subscribe(self: Arc<Self>) -> Receiver<DeviceEvent> {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let elapsed = self.started.elapsed();
+            let mut devices = self.devices_at(elapsed);
+            for device in devices.clone() {
+                if tx.send(DeviceEvent::Found(device)).await.is_err() {
+                    return;
+                }
+            }
+            let pending = self.script.iter().skip_while(|(at, _)| *at <= elapsed);
+            for (at, step) in pending {
+                tokio::time::sleep_until(self.started + *at).await;
+                let Some(event) = step.run(&mut devices) else {
+                    continue;
+                };
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            // the subscription ends with the receiver, like the real one
+            tx.closed().await;
+        });
+        rx
+    }
+
+    async fn announce(&self) {
+        self.announcements.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn mark_stale(&self, device: &Device) {
+        self.stale.lock().unwrap().push(device.fingerprint.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use localsend_proto::{dto::MulticastDto, fixtures::device, DeviceType};
+    use tokio_util::sync::CancellationToken;
+
+    use super::MockScanner;
+    use crate::scanner::{DeviceEvent, DeviceScanner};
+
+    #[tokio::test]
+    async fn test_mock_scanner() {
+        let addr = "192.168.1.9:53317".parse().unwrap();
+        let valid = MulticastDto::v2(
+            "phone".to_owned(),
+            None,
+            DeviceType::Mobile,
+            "phone".to_owned(),
+            53317,
+            false,
+        );
+        let scanner = Arc::new(
+            MockScanner::new([device("a", 53317)])
+                .remove_at(Duration::from_millis(60), &device("a", 53317))
+                .packet_at(
+                    Duration::from_millis(40),
+                    serde_json::to_vec(&valid).unwrap(),
+                    addr,
+                )
+                .packet_at(Duration::from_millis(20), &b"{\"alias\": 1"[..], addr)
+                .add_at(Duration::from_millis(20), device("b", 53317)),
+        );
+        let mut events = scanner.clone().subscribe();
+        let mut received = vec![];
+        while received.len() < 4 {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv());
+            received.push(event.await.unwrap().unwrap());
+        }
+        // the malformed packet is dropped
+        let phone = match &received[2] {
+            DeviceEvent::Found(device) => device.clone(),
+            event => panic!("unexpected {:?}", event),
+        };
+        assert_eq!(phone.ip, "192.168.1.9");
+        assert_eq!(
+            received,
+            vec![
+                DeviceEvent::Found(device("a", 53317)),
+                DeviceEvent::Found(device("b", 53317)),
+                DeviceEvent::Found(phone.clone()),
+                DeviceEvent::Lost(device("a", 53317)),
+            ]
+        );
+
+        let devices = scanner.scan(&CancellationToken::new()).await.unwrap();
+        assert_eq!(devices, vec![device("b", 53317), phone]);
+        assert_eq!(scanner.devices_at(Duration::ZERO), vec![device("a", 53317)]);
+
+        scanner.announce().await;
+        scanner.mark_stale(&device("b", 53317));
+        assert_eq!(scanner.announcements(), 1);
+        assert_eq!(scanner.stale(), vec!["b".to_owned()]);
+    }
+}
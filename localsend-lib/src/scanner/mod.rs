@@ -1,3 +1,15 @@
+mod device_scanner;
+mod known;
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
 mod multicast;
+mod registry;
+mod static_devices;
 
+pub use device_scanner::*;
+pub use known::*;
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::*;
 pub use multicast::*;
+pub use registry::*;
+pub use static_devices::*;
@@ -1,79 +1,895 @@
 use std::{
-    net::{Ipv4Addr, SocketAddr},
+    collections::HashSet,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use localsend_proto::{
-    dto::{MulticastDto, RegisterDto},
-    Device, DeviceType,
+use localsend_proto::{dto::MulticastDto, Device, Validate};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, Receiver},
 };
-use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+use super::{DeviceRegistry, Discovery, StaticDeviceProvider};
+
+/// Interval between announcements while subscribed.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+/// A device is lost when it did not answer this long.
+const LOST_TIMEOUT: Duration = Duration::from_secs(7);
+/// [`MulticastDeviceScanner::scan`] gives up waiting for a first device after this long.
+pub const MAX_SCAN_DURATION: Duration = Duration::from_secs(10);
+/// Scans listen at most this long once a device answered.
+pub const SCAN_WINDOW: Duration = Duration::from_secs(2);
+/// Scans end once no new device answered this long.
+pub const DEFAULT_SCAN_SETTLE: Duration = Duration::from_millis(300);
+/// Announcements are kept this small by default, so that they fit into a single
+/// datagram below the usual MTU.
+pub const DEFAULT_ANNOUNCE_LIMIT: usize = 1400;
+/// The largest UDP payload over IPv4, peers do not limit their announcements and a
+/// long device model must not cut them.
+const RECV_BUFFER_SIZE: usize = 65_507;
+
+/// Decides which devices a scanner reports, see [`MulticastDeviceScanner::set_filter`].
+pub type DeviceFilter = Arc<dyn Fn(&Device) -> bool + Send + Sync>;
+
+/// How long [`MulticastDeviceScanner::scan`] listens for answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// End once no new device answered this long, `None` listens the whole `window`
+    pub settle: Option<Duration>,
+    /// Listen at most this long once a device answered, at least without `settle`
+    pub window: Duration,
+    /// Give up waiting for a first device after this long
+    pub timeout: Duration,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            settle: Some(DEFAULT_SCAN_SETTLE),
+            window: SCAN_WINDOW,
+            timeout: MAX_SCAN_DURATION,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Listens the whole window even when every device answered right away.
+    pub fn fixed() -> Self {
+        Self {
+            settle: None,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a scan running for `elapsed` is over, `last_found` is when the
+    /// latest new device answered.
+    pub fn is_done(&self, elapsed: Duration, last_found: Option<Duration>) -> bool {
+        if elapsed >= self.timeout {
+            return true;
+        }
+        let Some(last_found) = last_found else {
+            return false;
+        };
+        elapsed >= self.window
+            || self
+                .settle
+                .is_some_and(|settle| elapsed.saturating_sub(last_found) >= settle)
+    }
+}
+
+/// What a [`MulticastDeviceScanner`] sends and keeps, see
+/// [`MulticastDeviceScanner::with_roles`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScannerRoles {
+    /// Announce this device when scanning and every few seconds while subscribed
+    pub announce: bool,
+    /// Reply to the announcements of others
+    pub respond: bool,
+    /// Record the devices that announce themselves, scans and subscriptions only
+    /// report the configured devices without
+    pub register: bool,
+}
+
+impl Default for ScannerRoles {
+    fn default() -> Self {
+        Self {
+            announce: true,
+            respond: true,
+            register: true,
+        }
+    }
+}
+
+impl ScannerRoles {
+    /// Hears the announcements of others without ever sending a packet, nobody
+    /// learns about this device.
+    pub fn listen_only() -> Self {
+        Self {
+            announce: false,
+            respond: false,
+            register: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceEvent {
+    Found(Device),
+    Lost(Device),
+}
 
-#[derive(Debug)]
 pub struct MulticastDeviceScanner {
     socket: UdpSocket,
     device: MulticastDto,
     addr: SocketAddr,
+    multiaddr: Ipv4Addr,
+    /// Interface the multicast group was joined on
+    interface: Mutex<Ipv4Addr>,
+    /// Bumped on every network change, subscriptions then forget the devices they found
+    network_epoch: AtomicU64,
+    /// Fingerprints of devices that did not answer, dropped by subscriptions until seen again
+    stale: Mutex<HashSet<String>>,
+    filter: Mutex<Option<DeviceFilter>>,
+    scan_options: ScanOptions,
+    static_devices: Option<StaticDeviceProvider>,
+    discovery: Discovery,
+    roles: ScannerRoles,
     announce_msg: String,
+    reply_msg: String,
+}
+
+/// Serializes the announcement of `device` into at most `limit` bytes, see
+/// [`MulticastDeviceScanner::with_announce_limit`].
+pub fn announcement(device: &Device, limit: usize) -> io::Result<String> {
+    payloads(&announce_dto(device), limit).map(|(announce_msg, _)| announce_msg)
+}
+
+fn announce_dto(device: &Device) -> MulticastDto {
+    MulticastDto::v2(
+        device.alias.clone(),
+        device.device_model.clone(),
+        device.device_type.clone(),
+        device.fingerprint.clone(),
+        device.port,
+        true,
+    )
+}
+
+/// The announcement and reply of `device` within `limit` bytes each.
+fn payloads(device: &MulticastDto, limit: usize) -> io::Result<(String, String)> {
+    let mut announce = device.clone();
+    let announce_msg = fit_payload(&mut announce, limit)?;
+    if announce.alias != device.alias || announce.device_model != device.device_model {
+        log::warn!(
+            "Announcement cut to {} bytes, alias {:?}, model {:?}",
+            limit,
+            announce.alias,
+            announce.device_model
+        );
+    }
+    let mut reply = announce.into_response();
+    Ok((announce_msg, fit_payload(&mut reply, limit)?))
+}
+
+/// Serializes `dto` into at most `limit` bytes, shortening the device model and then
+/// the alias at character boundaries when it does not fit.
+fn fit_payload(dto: &mut MulticastDto, limit: usize) -> io::Result<String> {
+    loop {
+        let payload = serde_json::to_string(dto)?;
+        if payload.len() <= limit {
+            return Ok(payload);
+        }
+        let excess = payload.len() - limit;
+        // others still recognize a device by its alias without the model
+        match &mut dto.device_model {
+            Some(model) if !model.is_empty() => cut_end(model, excess, 0),
+            _ if dto.alias.chars().nth(1).is_some() => {
+                let first = dto.alias.chars().next().map_or(0, char::len_utf8);
+                cut_end(&mut dto.alias, excess, first);
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Announcement does not fit into {} bytes", limit),
+                ))
+            }
+        }
+        if dto.device_model.as_deref() == Some("") {
+            dto.device_model = None;
+        }
+    }
+}
+
+/// Removes at least `bytes` bytes from the end of `s` at a character boundary,
+/// keeping the first `min` bytes.
+fn cut_end(s: &mut String, bytes: usize, min: usize) {
+    let mut end = s.len().saturating_sub(bytes).max(min);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
 }
 
 impl MulticastDeviceScanner {
-    pub async fn new(device: &Device, multiaddr: Ipv4Addr, port: u16, http_port: u16) -> std::io::Result<Self> {
+    /// Binds the multicast socket on `port` and sends announcements to `announce_port`.
+    ///
+    /// The announced http port is taken from `device`, so it may differ from the
+    /// locally bound one when running behind NAT or port mapping.
+    pub async fn new(
+        device: &Device,
+        multiaddr: Ipv4Addr,
+        port: u16,
+        announce_port: u16,
+    ) -> std::io::Result<Self> {
         let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).await?;
         socket.join_multicast_v4(multiaddr, Ipv4Addr::UNSPECIFIED)?;
 
-        let device = MulticastDto::v2(
-            device.alias.clone(),
-            device.device_model.clone(),
-            DeviceType::Headless,
-            device.fingerprint.clone(),
-            http_port,
-            true,
-        );
-        let announce_msg = serde_json::to_string(&device)?;
+        let device = announce_dto(device);
+        let (announce_msg, reply_msg) = payloads(&device, DEFAULT_ANNOUNCE_LIMIT)?;
 
         Ok(Self {
             socket,
             device,
-            addr: (multiaddr, port).into(),
+            addr: (multiaddr, announce_port).into(),
+            multiaddr,
+            interface: Mutex::new(Ipv4Addr::UNSPECIFIED),
+            network_epoch: AtomicU64::new(0),
+            stale: Mutex::default(),
+            filter: Mutex::default(),
+            scan_options: ScanOptions::default(),
+            static_devices: None,
+            discovery: Discovery::Multicast,
+            roles: ScannerRoles::default(),
             announce_msg,
+            reply_msg,
         })
     }
 }
 
+impl fmt::Debug for MulticastDeviceScanner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MulticastDeviceScanner")
+            .field("socket", &self.socket)
+            .field("device", &self.device)
+            .field("addr", &self.addr)
+            .field("filtered", &self.filter.lock().unwrap().is_some())
+            .finish_non_exhaustive()
+    }
+}
+
 impl MulticastDeviceScanner {
+    /// Keeps announcements and replies within `limit` bytes instead of
+    /// [`DEFAULT_ANNOUNCE_LIMIT`], the model and then the alias are cut to fit.
+    pub fn with_announce_limit(mut self, limit: usize) -> io::Result<Self> {
+        (self.announce_msg, self.reply_msg) = payloads(&self.device, limit)?;
+        Ok(self)
+    }
+
+    /// Listens as long as `options` tell in [`Self::scan`].
+    pub fn with_scan_options(mut self, options: ScanOptions) -> Self {
+        self.scan_options = options;
+        self
+    }
+
+    /// Reports the devices of `provider` as always present, alone or next to
+    /// those announcing themselves as `discovery` tells.
+    ///
+    /// A configured device wins over an announcement with the same fingerprint.
+    pub fn with_static_devices(
+        mut self,
+        provider: StaticDeviceProvider,
+        discovery: Discovery,
+    ) -> Self {
+        self.static_devices = Some(provider);
+        self.discovery = discovery;
+        self
+    }
+
+    pub fn static_devices(&self) -> Option<&StaticDeviceProvider> {
+        self.static_devices
+            .as_ref()
+            .filter(|_| self.discovery.static_devices())
+    }
+
+    pub fn discovery(&self) -> Discovery {
+        self.discovery
+    }
+
+    /// Only announces, replies and records devices as `roles` tell.
+    pub fn with_roles(mut self, roles: ScannerRoles) -> Self {
+        self.roles = roles;
+        self
+    }
+
+    pub fn roles(&self) -> ScannerRoles {
+        self.roles
+    }
+
+    /// The configured devices passing the filter, without scanning.
+    pub fn configured_devices(&self) -> Vec<Device> {
+        let Some(provider) = self.static_devices() else {
+            return vec![];
+        };
+        let filter = self.filter.lock().unwrap().clone();
+        provider
+            .devices()
+            .into_iter()
+            .filter(|device| filter.as_ref().map_or(true, |filter| filter(device)))
+            .collect()
+    }
+
+    /// What is sent with every announcement.
+    pub fn announcement(&self) -> &str {
+        &self.announce_msg
+    }
+
+    /// Announces this device, unless its roles leave that out.
     pub async fn send_announcement(&self) {
-        let size = self
-            .socket
-            .send_to(self.announce_msg.as_bytes(), self.addr)
-            .await
+        if self.sends(false) {
+            self.send(&self.announce_msg).await;
+        }
+    }
+
+    /// Answers announcements of other devices, unless its roles leave that out.
+    pub async fn send_reply(&self) {
+        if self.sends(true) {
+            self.send(&self.reply_msg).await;
+        }
+    }
+
+    /// Whether a reply or an announcement goes out, nothing is sent with static discovery.
+    fn sends(&self, reply: bool) -> bool {
+        let role = if reply {
+            self.roles.respond
+        } else {
+            self.roles.announce
+        };
+        role && self.discovery.multicast()
+    }
+
+    /// Sends `msg` to the multicast group, failures are retried with the next announcement.
+    async fn send(&self, msg: &str) {
+        match self.socket.send_to(msg.as_bytes(), self.addr).await {
+            Ok(size) if size == msg.len() => {}
+            Ok(size) => log::warn!("Sent only {} of {} announcement bytes", size, msg.len()),
+            Err(e) => log::warn!("Failed to send announcement to {}: {}", self.addr, e),
+        }
+    }
+
+    /// Rejoins the multicast group on the interface of `ip` after the local address changed.
+    ///
+    /// Announcements carry no address, peers take it from the packets, so they stay
+    /// valid. Devices found on the old network are reported lost by running subscriptions.
+    pub fn network_changed(&self, ip: IpAddr) -> std::io::Result<()> {
+        self.network_epoch.fetch_add(1, Ordering::Relaxed);
+        let IpAddr::V4(ip) = ip else {
+            return Ok(());
+        };
+        let mut interface = self.interface.lock().unwrap();
+        if *interface == ip {
+            return Ok(());
+        }
+        // the old interface may be gone already
+        self.socket
+            .leave_multicast_v4(self.multiaddr, *interface)
             .ok();
-        assert!(size == Some(self.announce_msg.len()));
+        self.socket.join_multicast_v4(self.multiaddr, ip)?;
+        *interface = ip;
+        Ok(())
+    }
+
+    /// Reports `device` lost to running subscriptions, e.g. after it did not answer a
+    /// connection. It is found again with its next announcement.
+    pub fn mark_stale(&self, device: &Device) {
+        // configured devices stay, they are only shown as unreachable
+        if let Some(provider) = self.static_devices() {
+            if provider.contains(&device.fingerprint) {
+                provider.mark_stale(&device.fingerprint);
+                return;
+            }
+        }
+        self.stale
+            .lock()
+            .unwrap()
+            .insert(device.fingerprint.clone());
+    }
+
+    /// Only reports devices for which `filter` returns true.
+    ///
+    /// Other devices never show up in scans or subscriptions, they are not answered either.
+    pub fn set_filter(&self, filter: impl Fn(&Device) -> bool + Send + Sync + 'static) {
+        *self.filter.lock().unwrap() = Some(Arc::new(filter));
     }
 
-    pub async fn scan(&self) -> std::io::Result<Vec<Device>> {
-        let mut devices = vec![];
-        let mut buf = [0u8; 2048 as usize];
+    /// Parses a packet into the device it came from, ignoring our own packets
+    /// and those of filtered devices.
+    ///
+    /// Returns the device and whether it asked for a reply.
+    fn parse_packet(&self, packet: &[u8], addr: SocketAddr) -> Option<(Device, bool)> {
+        let (device, announce) = parse_announcement(packet, addr)?;
+        if device.fingerprint == self.device.fingerprint {
+            return None;
+        }
+        // anything sent by a stale device means it is back
+        self.stale.lock().unwrap().remove(&device.fingerprint);
+        if let Some(provider) = self.static_devices() {
+            if provider.contains(&device.fingerprint) {
+                provider.mark_reachable(&device.fingerprint);
+                return None;
+            }
+        }
+        let filter = self.filter.lock().unwrap().clone();
+        if filter.is_some_and(|filter| !filter(&device)) {
+            log::trace!("filtered device: {:?}", device);
+            return None;
+        }
+        Some((device, announce))
+    }
+
+    /// Scans until the answers settled, see [`ScanOptions`] and [`Self::with_scan_options`].
+    ///
+    /// Returns the devices found so far once `cancel` is cancelled.
+    pub async fn scan(&self, cancel: &CancellationToken) -> std::io::Result<Vec<Device>> {
+        self.scan_with(self.scan_options, cancel).await
+    }
+
+    /// Like [`Self::scan`], listening as long as `options` tell.
+    pub async fn scan_with(
+        &self,
+        options: ScanOptions,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<Vec<Device>> {
+        let mut devices = self.configured_devices();
+        if !self.discovery.multicast() || !self.roles.register {
+            return Ok(devices);
+        }
+        let mut registry = DeviceRegistry::default();
+        let mut buf = vec![0u8; RECV_BUFFER_SIZE];
+        let mut last_found = None;
 
         self.send_announcement().await;
 
         let instant = Instant::now();
-        while instant.elapsed() < Duration::from_secs(2) || devices.is_empty() {
-            if let Ok((size, addr)) = self.socket.try_recv_from(&mut buf) {
-                let register_dto: RegisterDto = serde_json::from_slice(&buf[..size])?;
-                if register_dto.fingerprint == self.device.fingerprint {
+        while !options.is_done(instant.elapsed(), last_found) && !cancel.is_cancelled() {
+            let received =
+                tokio::time::timeout(Duration::from_millis(50), self.socket.recv_from(&mut buf))
+                    .await;
+            if let Ok(Ok((size, addr))) = received {
+                if let Some((device, announce)) = self.parse_packet(&buf[..size], addr) {
+                    let known = registry.len();
+                    registry.observe(device, announce, Instant::now());
+                    if registry.len() > known {
+                        last_found = Some(instant.elapsed());
+                    }
+                }
+            }
+            if registry.take_reply(Instant::now()) {
+                self.send_reply().await;
+            }
+        }
+        devices.extend(registry.devices());
+        Ok(devices)
+    }
+
+    /// Scans continuously and reports devices as they appear, change and disappear.
+    ///
+    /// Scanning stops once the receiver is dropped. Avoid calling [`Self::scan`]
+    /// meanwhile, both read from the same socket.
+    pub fn subscribe(self: &Arc<Self>) -> Receiver<DeviceEvent> {
+        let (tx, rx) = mpsc::channel(16);
+        let scanner = self.clone();
+        tokio::spawn(async move {
+            let mut registry = DeviceRegistry::default();
+            let mut buf = vec![0u8; RECV_BUFFER_SIZE];
+            let mut announced: Option<Instant> = None;
+            let mut epoch = scanner.network_epoch.load(Ordering::Relaxed);
+            let mut configured: Vec<Device> = vec![];
+            let mut generation = None;
+
+            while !tx.is_closed() {
+                let mut events = vec![];
+                let current = scanner.static_devices().map(|p| p.generation());
+                if current != generation {
+                    generation = current;
+                    let devices = scanner.configured_devices();
+                    events.extend(
+                        configured
+                            .iter()
+                            .filter(|old| !devices.iter().any(|d| d.fingerprint == old.fingerprint))
+                            .map(|old| DeviceEvent::Lost(old.clone())),
+                    );
+                    events.extend(devices.iter().cloned().map(DeviceEvent::Found));
+                    configured = devices;
+                }
+                if !scanner.discovery.multicast() {
+                    for event in events {
+                        tx.send(event).await.ok();
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
                     continue;
                 }
+                // devices are still observed without registering them, replies depend on it
+                let mut heard = vec![];
+                let current = scanner.network_epoch.load(Ordering::Relaxed);
+                if current != epoch {
+                    epoch = current;
+                    heard = registry.clear();
+                    announced = None;
+                }
+                let stale = scanner.stale.lock().unwrap().clone();
+                heard.extend(stale.iter().filter_map(|f| registry.remove(f)));
+                if announced.map_or(true, |i| i.elapsed() >= ANNOUNCE_INTERVAL) {
+                    scanner.send_announcement().await;
+                    announced = Some(Instant::now());
+                }
+
+                let received = tokio::time::timeout(
+                    Duration::from_millis(100),
+                    scanner.socket.recv_from(&mut buf),
+                )
+                .await;
+                if let Ok(Ok((size, addr))) = received {
+                    if let Some((device, announce)) = scanner.parse_packet(&buf[..size], addr) {
+                        heard.extend(registry.observe(device, announce, Instant::now()));
+                    }
+                }
+                heard.extend(registry.expire(LOST_TIMEOUT, Instant::now()));
+                if scanner.roles.register {
+                    events.extend(heard);
+                }
+                for event in events {
+                    tx.send(event).await.ok();
+                }
 
-                let device = register_dto.to_device(addr.ip().to_string(), addr.port(), false);
-                if !devices.contains(&device) {
-                    log::trace!("found device: {:?}", device);
-                    devices.push(device);
+                if registry.take_reply(Instant::now()) {
+                    scanner.send_reply().await;
                 }
-            } else {
-                tokio::time::sleep(Duration::from_millis(100)).await;
             }
+        });
+        rx
+    }
+}
+
+/// Parses an announcement or reply sent from `addr`, invalid packets are `None`.
+///
+/// Returns the device and whether it asked for a reply.
+pub fn parse_announcement(packet: &[u8], addr: SocketAddr) -> Option<(Device, bool)> {
+    let dto: MulticastDto = match serde_json::from_slice(packet) {
+        Ok(dto) => dto,
+        Err(e) => {
+            log::debug!("invalid announcement from {}: {}", addr, e);
+            return None;
         }
-        return Ok(devices);
+    };
+    if let Err(e) = dto.validate() {
+        log::debug!("invalid announcement from {}: {}", addr, e);
+        return None;
+    }
+    let announce = dto.is_announce();
+    Some((dto.to_device(addr.ip(), addr.port(), false), announce))
+}
+
+/// Records the device a packet came from, what scanners do with every packet
+/// before their own filters. Needs no socket, so it can be fuzzed.
+pub fn handle_packet(
+    registry: &mut DeviceRegistry,
+    packet: &[u8],
+    addr: SocketAddr,
+) -> Vec<DeviceEvent> {
+    match parse_announcement(packet, addr) {
+        Some((device, announce)) => registry.observe(device, announce, Instant::now()),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use localsend_proto::{
+        dto::MulticastDto, fixtures::device, DeviceType, MAX_ALIAS_LEN, MAX_ID_LEN,
+    };
+    use tokio::{net::UdpSocket, task::JoinHandle};
+    use tokio_util::sync::CancellationToken;
+
+    use super::{
+        announce_dto, announcement, handle_packet, parse_announcement, payloads, DeviceEvent,
+        MulticastDeviceScanner, ScanOptions, ScannerRoles, DEFAULT_ANNOUNCE_LIMIT,
+    };
+    use crate::scanner::DeviceRegistry;
+
+    async fn scanner(port: u16) -> MulticastDeviceScanner {
+        let multiaddr = Ipv4Addr::new(224, 0, 0, 199);
+        MulticastDeviceScanner::new(&device("scanner", 53317), multiaddr, port, 9)
+            .await
+            .unwrap()
+    }
+
+    /// Announces `aliases` to `port` until aborted.
+    fn announce(port: u16, aliases: &[&str]) -> JoinHandle<()> {
+        let packets: Vec<String> = aliases
+            .iter()
+            .map(|alias| {
+                let dto = MulticastDto::v2(
+                    alias.to_string(),
+                    None,
+                    DeviceType::Mobile,
+                    alias.to_string(),
+                    53317,
+                    false,
+                );
+                serde_json::to_string(&dto).unwrap()
+            })
+            .collect();
+        tokio::spawn(async move {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            loop {
+                for packet in &packets {
+                    socket
+                        .send_to(packet.as_bytes(), ("127.0.0.1", port))
+                        .await
+                        .ok();
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+    }
+
+    async fn free_port() -> u16 {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+        socket.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_cancel_scan() {
+        let scanner = scanner(0).await;
+
+        // nobody answers, so the scan only ends when cancelled
+        let cancel = CancellationToken::new();
+        let canceller = {
+            let cancel = cancel.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                cancel.cancel();
+            })
+        };
+        let devices = tokio::time::timeout(Duration::from_secs(2), scanner.scan(&cancel))
+            .await
+            .expect("scan not cancelled")
+            .unwrap();
+        assert!(devices.is_empty());
+        canceller.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filter_scan() {
+        let port = free_port().await;
+        let scanner = scanner(port).await;
+        scanner.set_filter(|device| device.alias.starts_with("KIOSK-"));
+
+        // the only responder is filtered, so the scan gives up instead of waiting for it
+        let announcer = announce(port, &["Phone"]);
+        let devices = tokio::time::timeout(
+            Duration::from_secs(5),
+            scanner.scan_with(
+                ScanOptions {
+                    timeout: Duration::from_millis(2500),
+                    ..ScanOptions::default()
+                },
+                &CancellationToken::new(),
+            ),
+        )
+        .await
+        .expect("scan did not give up")
+        .unwrap();
+        assert!(devices.is_empty());
+        announcer.abort();
+
+        let announcer = announce(port, &["Phone", "KIOSK-1"]);
+        let devices = scanner.scan(&CancellationToken::new()).await.unwrap();
+        let aliases: Vec<_> = devices.iter().map(|device| device.alias.as_str()).collect();
+        assert_eq!(aliases, vec!["KIOSK-1"]);
+        announcer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_listen_only() {
+        let port = free_port().await;
+        let listening = scanner(port).await.with_roles(ScannerRoles::listen_only());
+        assert!(!listening.sends(false) && !listening.sends(true));
+
+        // others are still heard
+        let announcer = announce(port, &["Phone"]);
+        let devices = listening.scan(&CancellationToken::new()).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        announcer.abort();
+
+        let announcing = scanner(0).await.with_roles(ScannerRoles {
+            register: false,
+            ..ScannerRoles::default()
+        });
+        assert!(announcing.sends(false) && announcing.sends(true));
+        let devices = announcing.scan(&CancellationToken::new()).await.unwrap();
+        assert!(devices.is_empty());
+    }
+
+    #[test]
+    fn test_handle_packet() {
+        let mut registry = DeviceRegistry::default();
+        let addr = "192.168.1.20:53317".parse().unwrap();
+        let malformed: [&[u8]; 5] = [
+            b"",
+            b"\xff\xfe",
+            b"{\"alias\": 1}",
+            b"{\"alias\": \"Phone\", \"fingerprint\": \"\", \"port\": 70000}",
+            b"[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[",
+        ];
+        for packet in malformed {
+            assert!(handle_packet(&mut registry, packet, addr).is_empty());
+        }
+        assert!(registry.is_empty());
+
+        let dto = MulticastDto::v2(
+            "Phone".to_owned(),
+            None,
+            DeviceType::Mobile,
+            "phone".to_owned(),
+            53317,
+            true,
+        );
+        let packet = serde_json::to_vec(&dto).unwrap();
+        let events = handle_packet(&mut registry, &packet, addr);
+        assert!(matches!(&events[..], [DeviceEvent::Found(device)] if device.ip == "192.168.1.20"));
+    }
+
+    #[test]
+    fn test_scan_options() {
+        let ms = Duration::from_millis;
+        let options = ScanOptions::default();
+        // an empty network is given up on at the timeout only
+        assert!(!options.is_done(ms(9_999), None));
+        assert!(options.is_done(ms(10_000), None));
+        // a single device answering right away ends the scan once settled
+        assert!(!options.is_done(ms(300), Some(ms(40))));
+        assert!(options.is_done(ms(340), Some(ms(40))));
+        // devices answering one after another keep it going, up to the window
+        assert!(!options.is_done(ms(1_500), Some(ms(1_300))));
+        assert!(options.is_done(ms(2_000), Some(ms(1_900))));
+
+        let fixed = ScanOptions::fixed();
+        assert!(!fixed.is_done(ms(1_999), Some(ms(40))));
+        assert!(fixed.is_done(ms(2_000), Some(ms(40))));
+        assert!(!fixed.is_done(ms(5_000), None));
+    }
+
+    #[tokio::test]
+    async fn test_scan_settles() {
+        let port = free_port().await;
+        let scanner = scanner(port).await;
+        let announcer = announce(port, &["Phone"]);
+
+        let instant = Instant::now();
+        let devices = scanner.scan(&CancellationToken::new()).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert!(instant.elapsed() < Duration::from_millis(1500));
+
+        let instant = Instant::now();
+        let devices = scanner
+            .scan_with(ScanOptions::fixed(), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(devices.len(), 1);
+        assert!(instant.elapsed() >= Duration::from_secs(2));
+        announcer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_filter_subscription() {
+        let port = free_port().await;
+        let scanner = Arc::new(scanner(port).await);
+        scanner.set_filter(|device| device.fingerprint != "Phone");
+
+        let announcer = announce(port, &["Phone", "Tablet"]);
+        let mut events = scanner.subscribe();
+        let mut found = vec![];
+        let _ = tokio::time::timeout(Duration::from_secs(1), async {
+            while let Some(DeviceEvent::Found(device)) = events.recv().await {
+                found.push(device.alias);
+            }
+        })
+        .await;
+        assert_eq!(found, vec!["Tablet"]);
+        announcer.abort();
+    }
+
+    #[test]
+    fn test_announce_and_reply() {
+        let addr = (Ipv4Addr::LOCALHOST, 53317).into();
+        let (announce_msg, reply_msg) = payloads(
+            &announce_dto(&device("Laptop", 53317)),
+            DEFAULT_ANNOUNCE_LIMIT,
+        )
+        .unwrap();
+        assert!(announce_msg.contains(r#""announcement":true,"announce":true"#));
+        assert!(reply_msg.contains(r#""announcement":false,"announce":false"#));
+        let (_, announce) = parse_announcement(announce_msg.as_bytes(), addr).unwrap();
+        assert!(announce);
+        // others must not answer a reply, or every reply starts another round
+        let (device, announce) = parse_announcement(reply_msg.as_bytes(), addr).unwrap();
+        assert!(!announce);
+        assert_eq!(device.alias, "Laptop");
+    }
+
+    #[test]
+    fn test_announce_limit() {
+        let mut long = device(&"🚀".repeat(MAX_ALIAS_LEN), 53317);
+        long.device_model = Some("é".repeat(600));
+        "fingerprint".clone_into(&mut long.fingerprint);
+        assert!(announcement(&long, usize::MAX).unwrap().len() > DEFAULT_ANNOUNCE_LIMIT);
+
+        let payload = announcement(&long, 900).unwrap();
+        assert!(payload.len() <= 900);
+        // the model goes first, cut at a character boundary
+        let dto: MulticastDto = serde_json::from_str(&payload).unwrap();
+        assert_eq!(dto.alias, long.alias);
+        let model = dto.device_model.unwrap();
+        assert!(!model.is_empty() && long.device_model.as_ref().unwrap().starts_with(&model));
+
+        let payload = announcement(&long, 500).unwrap();
+        assert!(payload.len() <= 500);
+        let dto: MulticastDto = serde_json::from_str(&payload).unwrap();
+        assert_eq!(dto.device_model, None);
+        assert!(!dto.alias.is_empty() && long.alias.starts_with(&dto.alias));
+
+        // the fingerprint and fields never shrink
+        assert!(announcement(&long, 50).is_err());
+        let short = device("Laptop", 53317);
+        let dto: MulticastDto =
+            serde_json::from_str(&announcement(&short, DEFAULT_ANNOUNCE_LIMIT).unwrap()).unwrap();
+        assert_eq!(dto.alias, "Laptop");
+    }
+
+    #[tokio::test]
+    async fn test_receive_large_announcement() {
+        let port = free_port().await;
+        let scanner = scanner(port).await;
+        // as long as validation allows, with a model other implementations do not limit
+        let dto = MulticastDto::v2(
+            "🚀".repeat(MAX_ALIAS_LEN),
+            Some("\u{1}".repeat(2000)),
+            DeviceType::Mobile,
+            "f".repeat(MAX_ID_LEN),
+            53317,
+            false,
+        );
+        let packet = serde_json::to_string(&dto).unwrap();
+        assert!(packet.len() > 8192);
+        let announcer = tokio::spawn(async move {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            loop {
+                socket
+                    .send_to(packet.as_bytes(), ("127.0.0.1", port))
+                    .await
+                    .ok();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+
+        let devices = scanner.scan(&CancellationToken::new()).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].alias, dto.alias);
+        assert_eq!(devices[0].device_model, dto.device_model);
+        announcer.abort();
     }
 }
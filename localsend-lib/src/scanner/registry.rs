@@ -0,0 +1,297 @@
+use std::time::{Duration, Instant};
+
+use linked_hash_map::LinkedHashMap;
+use localsend_proto::Device;
+
+use super::DeviceEvent;
+
+/// Maximum number of remembered devices, the least recently seen one is evicted first.
+pub const REGISTRY_CAPACITY: usize = 256;
+/// We reply to the announcements of a device at most once in this interval.
+pub const REPLY_INTERVAL: Duration = Duration::from_secs(5);
+/// Announcements arriving within this delay are answered with a single reply.
+pub const REPLY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+struct Entry {
+    device: Device,
+    seen: Instant,
+    replied: Option<Instant>,
+}
+
+/// Devices found by multicast, keyed by fingerprint.
+#[derive(Debug)]
+pub struct DeviceRegistry {
+    // ordered from the least to the most recently seen
+    devices: LinkedHashMap<String, Entry>,
+    capacity: usize,
+    reply_pending: Option<Instant>,
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self::new(REGISTRY_CAPACITY)
+    }
+}
+
+impl DeviceRegistry {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            devices: LinkedHashMap::new(),
+            capacity,
+            reply_pending: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn devices(&self) -> Vec<Device> {
+        self.devices.values().map(|e| e.device.clone()).collect()
+    }
+
+    /// Records a device seen at `now`, the latest alias, ip and port win.
+    ///
+    /// `announce` tells whether the device asked for a reply.
+    pub fn observe(&mut self, device: Device, announce: bool, now: Instant) -> Vec<DeviceEvent> {
+        let mut events = vec![];
+        let entry = match self.devices.get_refresh(&device.fingerprint) {
+            Some(entry) => {
+                if entry.device != device {
+                    log::trace!("update device: {:?}", device);
+                    entry.device = device.clone();
+                    events.push(DeviceEvent::Found(device));
+                }
+                entry.seen = now;
+                entry
+            }
+            None => {
+                log::trace!("found device: {:?}", device);
+                let fingerprint = device.fingerprint.clone();
+                events.push(DeviceEvent::Found(device.clone()));
+                self.devices.insert(
+                    fingerprint.clone(),
+                    Entry {
+                        device,
+                        seen: now,
+                        replied: None,
+                    },
+                );
+                while self.devices.len() > self.capacity {
+                    if let Some((_, evicted)) = self.devices.pop_front() {
+                        events.push(DeviceEvent::Lost(evicted.device));
+                    }
+                }
+                match self.devices.get_mut(&fingerprint) {
+                    Some(entry) => entry,
+                    None => return events,
+                }
+            }
+        };
+
+        if announce && entry.replied.map_or(true, |r| now - r >= REPLY_INTERVAL) {
+            entry.replied = Some(now);
+            self.reply_pending.get_or_insert(now);
+        }
+        events
+    }
+
+    /// Returns whether a reply should be sent now, coalescing bursts of announcements.
+    pub fn take_reply(&mut self, now: Instant) -> bool {
+        match self.reply_pending {
+            Some(since) if now - since >= REPLY_DELAY => {
+                self.reply_pending = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets every device, e.g. after the network changed.
+    pub fn clear(&mut self) -> Vec<DeviceEvent> {
+        self.reply_pending = None;
+        let devices = std::mem::take(&mut self.devices);
+        devices
+            .into_iter()
+            .map(|(_, entry)| DeviceEvent::Lost(entry.device))
+            .collect()
+    }
+
+    /// Forgets a device until it is seen again, e.g. after it did not answer.
+    pub fn remove(&mut self, fingerprint: &str) -> Option<DeviceEvent> {
+        let entry = self.devices.remove(fingerprint)?;
+        Some(DeviceEvent::Lost(entry.device))
+    }
+
+    /// Removes devices not seen for `timeout`.
+    pub fn expire(&mut self, timeout: Duration, now: Instant) -> Vec<DeviceEvent> {
+        let mut events = vec![];
+        while let Some((_, entry)) = self.devices.front() {
+            if now - entry.seen < timeout {
+                break;
+            }
+            if let Some((_, entry)) = self.devices.pop_front() {
+                log::trace!("lost device: {:?}", entry.device);
+                events.push(DeviceEvent::Lost(entry.device));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use localsend_proto::{fixtures, Device};
+
+    use crate::scanner::DeviceEvent;
+
+    use super::{DeviceRegistry, REPLY_DELAY, REPLY_INTERVAL};
+
+    fn device(index: usize) -> Device {
+        Device {
+            ip: format!("192.168.1.{}", index),
+            fingerprint: format!("fingerprint-{}", index),
+            ..fixtures::device(&format!("device {}", index), 53317)
+        }
+    }
+
+    // what the picker ends up showing, keyed by fingerprint
+    fn apply(list: &mut HashMap<String, Device>, events: Vec<DeviceEvent>) -> usize {
+        let count = events.len();
+        for event in events {
+            match event {
+                DeviceEvent::Found(device) => {
+                    list.insert(device.fingerprint.clone(), device);
+                }
+                DeviceEvent::Lost(device) => {
+                    list.remove(&device.fingerprint);
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn test_announcement_storm() {
+        let mut registry = DeviceRegistry::default();
+        let mut list = HashMap::new();
+        let mut replies = 0;
+        let start = Instant::now();
+
+        // 40 devices, each re-announcing 5 times per burst, one burst every second
+        for burst in 0..10u32 {
+            let burst_start = start + Duration::from_secs(burst as u64);
+            let mut events = 0;
+            for round in 0..5u32 {
+                for i in 0..40 {
+                    let now = burst_start + Duration::from_millis((round * 10) as u64);
+                    events += apply(&mut list, registry.observe(device(i), true, now));
+                    if registry.take_reply(now) {
+                        replies += 1;
+                    }
+                }
+            }
+            if registry.take_reply(burst_start + REPLY_DELAY) {
+                replies += 1;
+            }
+            assert_eq!(events, if burst == 0 { 40 } else { 0 });
+            assert_eq!(registry.len(), 40);
+            assert_eq!(list.len(), 40);
+        }
+        // one reply per reply interval, not one per announcement
+        let expected = 10 / REPLY_INTERVAL.as_secs() as usize;
+        assert_eq!(replies, expected);
+    }
+
+    #[test]
+    fn test_merge_updates() {
+        let mut registry = DeviceRegistry::default();
+        let now = Instant::now();
+        let mut list = HashMap::new();
+
+        apply(&mut list, registry.observe(device(1), false, now));
+        let renamed = Device {
+            alias: "renamed".to_owned(),
+            port: 53318,
+            ..device(1)
+        };
+        let events = registry.observe(renamed.clone(), false, now);
+        assert_eq!(events, vec![DeviceEvent::Found(renamed.clone())]);
+        apply(&mut list, events);
+
+        assert_eq!(registry.devices(), vec![renamed.clone()]);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list["fingerprint-1"], renamed);
+        assert!(!registry.take_reply(now + REPLY_DELAY));
+    }
+
+    #[test]
+    fn test_eviction_and_expiry() {
+        let mut registry = DeviceRegistry::new(3);
+        let now = Instant::now();
+        for i in 0..3 {
+            registry.observe(device(i), false, now + Duration::from_secs(i as u64));
+        }
+        // seeing device 0 again makes device 1 the least recently seen
+        registry.observe(device(0), false, now + Duration::from_secs(3));
+        let events = registry.observe(device(3), false, now + Duration::from_secs(4));
+        assert_eq!(
+            events,
+            vec![DeviceEvent::Found(device(3)), DeviceEvent::Lost(device(1))]
+        );
+        assert_eq!(registry.len(), 3);
+
+        let events = registry.expire(Duration::from_secs(3), now + Duration::from_millis(5500));
+        assert_eq!(events, vec![DeviceEvent::Lost(device(2))]);
+        assert_eq!(registry.devices(), vec![device(0), device(3)]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut registry = DeviceRegistry::default();
+        let now = Instant::now();
+        registry.observe(device(0), true, now);
+        registry.observe(device(1), false, now);
+
+        let events = registry.clear();
+        assert_eq!(
+            events,
+            vec![DeviceEvent::Lost(device(0)), DeviceEvent::Lost(device(1))]
+        );
+        assert!(registry.is_empty());
+        assert!(!registry.take_reply(now + REPLY_DELAY));
+
+        // seen again on the new network
+        let events = registry.observe(device(0), false, now);
+        assert_eq!(events, vec![DeviceEvent::Found(device(0))]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut registry = DeviceRegistry::default();
+        let now = Instant::now();
+        registry.observe(device(0), false, now);
+        registry.observe(device(1), false, now);
+
+        let fingerprint = device(0).fingerprint;
+        assert_eq!(
+            registry.remove(&fingerprint),
+            Some(DeviceEvent::Lost(device(0)))
+        );
+        assert_eq!(registry.remove(&fingerprint), None);
+        assert_eq!(registry.devices(), vec![device(1)]);
+
+        let events = registry.observe(device(0), false, now);
+        assert_eq!(events, vec![DeviceEvent::Found(device(0))]);
+    }
+}
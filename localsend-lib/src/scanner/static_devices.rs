@@ -0,0 +1,351 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use localsend_proto::{
+    ApiRoute, Device, DeviceType, ProtocolVersion, DEFAULT_HTTP_PORT, MAX_ALIAS_LEN,
+    PROTOCOL_VERSION_2,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::send::check_reachable;
+
+/// Where a scanner finds devices, see [`super::MulticastDeviceScanner::with_static_devices`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Discovery {
+    /// Only devices announcing themselves
+    Multicast,
+    /// Only the configured devices, nothing is announced
+    Static,
+    /// The configured devices and those announcing themselves
+    #[default]
+    Both,
+}
+
+impl Discovery {
+    pub fn multicast(&self) -> bool {
+        *self != Discovery::Static
+    }
+
+    pub fn static_devices(&self) -> bool {
+        *self != Discovery::Multicast
+    }
+}
+
+impl fmt::Display for Discovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Discovery::Multicast => "multicast",
+            Discovery::Static => "static",
+            Discovery::Both => "both",
+        })
+    }
+}
+
+impl FromStr for Discovery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "multicast" => Ok(Discovery::Multicast),
+            "static" => Ok(Discovery::Static),
+            "both" => Ok(Discovery::Both),
+            _ => Err(format!(
+                "unknown discovery: {}, expected one of static, multicast, both",
+                s
+            )),
+        }
+    }
+}
+
+/// A device with a fixed address, as configured in a `[[devices]]` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StaticDevice {
+    #[serde(default)]
+    pub alias: String,
+    #[serde(default)]
+    pub ip: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub https: bool,
+    #[serde(default)]
+    pub fingerprint: String,
+    /// Protocol version, "2.0" when left out
+    #[serde(default = "default_version")]
+    pub version: String,
+    #[serde(default)]
+    pub device_type: DeviceType,
+    pub device_model: Option<String>,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_HTTP_PORT
+}
+
+fn default_version() -> String {
+    PROTOCOL_VERSION_2.to_owned()
+}
+
+/// A configured device that cannot be used, naming the entry.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Static device {entry}: {problem}")]
+pub struct StaticDeviceError {
+    /// Like `#2 "nas"`, counted from 1 in the order of the configuration
+    pub entry: String,
+    pub problem: String,
+}
+
+impl StaticDevice {
+    /// The device to send to, or what is wrong with the entry.
+    pub fn to_device(&self) -> Result<Device, String> {
+        if self.alias.trim().is_empty() {
+            return Err("missing alias".to_owned());
+        }
+        if self.alias.len() > MAX_ALIAS_LEN {
+            return Err(format!("alias longer than {} bytes", MAX_ALIAS_LEN));
+        }
+        if self.ip.is_empty() {
+            return Err("missing ip".to_owned());
+        }
+        self.ip
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid ip {:?}", self.ip))?;
+        if self.port == 0 {
+            return Err("invalid port 0".to_owned());
+        }
+        if self.fingerprint.trim().is_empty() {
+            return Err("missing fingerprint".to_owned());
+        }
+        let version = self
+            .version
+            .parse::<ProtocolVersion>()
+            .map_err(|e| e.to_string())?;
+        ApiRoute::Info.route(version).map_err(|e| e.to_string())?;
+        Ok(Device {
+            ip: self.ip.clone(),
+            version: self.version.clone(),
+            port: self.port,
+            https: self.https,
+            fingerprint: self.fingerprint.clone(),
+            alias: self.alias.clone(),
+            device_model: self.device_model.clone(),
+            device_type: self.device_type.clone(),
+            download: false,
+        })
+    }
+}
+
+/// Checks every entry, the errors name all broken ones rather than the first.
+pub fn parse_static_devices(
+    entries: &[StaticDevice],
+) -> Result<Vec<Device>, Vec<StaticDeviceError>> {
+    let mut devices: Vec<Device> = vec![];
+    let mut errors = vec![];
+    for (index, entry) in entries.iter().enumerate() {
+        let name = match entry.alias.trim() {
+            "" => format!("#{}", index + 1),
+            alias => format!("#{} {:?}", index + 1, alias),
+        };
+        let device = entry.to_device().and_then(|device| {
+            match devices.iter().find(|d| d.fingerprint == device.fingerprint) {
+                Some(other) => Err(format!("fingerprint also used by {:?}", other.alias)),
+                None => Ok(device),
+            }
+        });
+        match device {
+            Ok(device) => devices.push(device),
+            Err(problem) => errors.push(StaticDeviceError {
+                entry: name,
+                problem,
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(devices)
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug, Default)]
+struct StaticDevices {
+    devices: Vec<Device>,
+    /// Fingerprints of devices that did not answer, they are still listed
+    stale: HashSet<String>,
+    /// Bumped on every reload, subscriptions then report the changes
+    generation: u64,
+}
+
+/// Devices that are always present, whether they announce themselves or not.
+///
+/// Shared by clones, so that a reload reaches the scanner holding one.
+#[derive(Debug, Clone, Default)]
+pub struct StaticDeviceProvider(Arc<Mutex<StaticDevices>>);
+
+impl StaticDeviceProvider {
+    pub fn new(entries: &[StaticDevice]) -> Result<Self, Vec<StaticDeviceError>> {
+        let provider = Self::default();
+        provider.reload(entries)?;
+        Ok(provider)
+    }
+
+    /// Replaces the devices, keeping the previous ones when an entry is broken.
+    pub fn reload(&self, entries: &[StaticDevice]) -> Result<(), Vec<StaticDeviceError>> {
+        let devices = parse_static_devices(entries)?;
+        let mut inner = self.0.lock().unwrap();
+        inner
+            .stale
+            .retain(|fingerprint| devices.iter().any(|d| &d.fingerprint == fingerprint));
+        inner.devices = devices;
+        inner.generation += 1;
+        Ok(())
+    }
+
+    pub fn devices(&self) -> Vec<Device> {
+        self.0.lock().unwrap().devices.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().devices.is_empty()
+    }
+
+    pub fn contains(&self, fingerprint: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .devices
+            .iter()
+            .any(|d| d.fingerprint == fingerprint)
+    }
+
+    /// Whether the device did not answer the last time it was tried.
+    pub fn is_stale(&self, fingerprint: &str) -> bool {
+        self.0.lock().unwrap().stale.contains(fingerprint)
+    }
+
+    pub fn mark_stale(&self, fingerprint: &str) {
+        let mut inner = self.0.lock().unwrap();
+        if inner.devices.iter().any(|d| d.fingerprint == fingerprint) {
+            inner.stale.insert(fingerprint.to_owned());
+        }
+    }
+
+    pub fn mark_reachable(&self, fingerprint: &str) {
+        self.0.lock().unwrap().stale.remove(fingerprint);
+    }
+
+    /// Connects to every device, those not answering are marked stale but kept.
+    ///
+    /// Returns the unreachable ones.
+    pub async fn probe(&self) -> Vec<Device> {
+        let checks = self.devices().into_iter().map(|device| async move {
+            let reachable = check_reachable(&device).await.is_ok();
+            (device, reachable)
+        });
+        let mut unreachable = vec![];
+        for (device, reachable) in futures_util::future::join_all(checks).await {
+            if reachable {
+                self.mark_reachable(&device.fingerprint);
+            } else {
+                log::warn!(
+                    "Static device {:?} at {}:{} is not reachable",
+                    device.alias,
+                    device.ip,
+                    device.port
+                );
+                self.mark_stale(&device.fingerprint);
+                unreachable.push(device);
+            }
+        }
+        unreachable
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.0.lock().unwrap().generation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_static_devices, Discovery, StaticDevice, StaticDeviceProvider};
+
+    fn entry(alias: &str, ip: &str, fingerprint: &str) -> StaticDevice {
+        StaticDevice {
+            alias: alias.to_owned(),
+            ip: ip.to_owned(),
+            port: 53317,
+            https: false,
+            fingerprint: fingerprint.to_owned(),
+            version: "2.0".to_owned(),
+            device_type: Default::default(),
+            device_model: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_static_devices() {
+        let devices = parse_static_devices(&[entry("nas", "10.0.0.2", "a")]).unwrap();
+        assert_eq!(devices[0].alias, "nas");
+        assert_eq!(devices[0].port, 53317);
+
+        let mut old = entry("old", "10.0.0.4", "d");
+        "3.0".clone_into(&mut old.version);
+        let errors = parse_static_devices(&[
+            entry("nas", "10.0.0.2", "a"),
+            entry("printer", "10.0.0.300", "b"),
+            entry("", "10.0.0.3", "c"),
+            entry("backup", "10.0.0.5", ""),
+            entry("twin", "10.0.0.6", "a"),
+            old,
+        ])
+        .unwrap_err();
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "Static device #2 \"printer\": invalid ip \"10.0.0.300\"",
+                "Static device #3: missing alias",
+                "Static device #4 \"backup\": missing fingerprint",
+                "Static device #5 \"twin\": fingerprint also used by \"nas\"",
+                "Static device #6 \"old\": Protocol version 3.0 is not supported",
+            ]
+        );
+
+        assert_eq!("STATIC".parse(), Ok(Discovery::Static));
+        assert!("broadcast".parse::<Discovery>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_provider() {
+        let provider = StaticDeviceProvider::new(&[entry("nas", "127.0.0.1", "a")]).unwrap();
+        let generation = provider.generation();
+        // nothing listens on the port, the device stays but is stale
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let mut closed = entry("nas", "127.0.0.1", "a");
+        closed.port = port;
+        provider.reload(&[closed]).unwrap();
+        assert_eq!(provider.probe().await.len(), 1);
+        assert!(provider.is_stale("a"));
+        assert_eq!(provider.devices().len(), 1);
+
+        // a broken reload keeps what was there
+        assert!(provider.reload(&[entry("nas", "nas.local", "a")]).is_err());
+        assert_eq!(provider.devices()[0].port, port);
+        assert!(provider.generation() > generation);
+
+        provider
+            .reload(&[entry("backup", "127.0.0.1", "b")])
+            .unwrap();
+        assert!(!provider.contains("a"));
+        assert!(!provider.is_stale("a"));
+    }
+}
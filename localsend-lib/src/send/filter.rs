@@ -0,0 +1,172 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Files and directories skipped unless hidden files are included.
+pub const DEFAULT_EXCLUDES: [&str; 4] = [".git", ".svn", ".DS_Store", "Thumbs.db"];
+
+/// Decides which files of a directory are sent.
+#[derive(Debug, Default, Clone)]
+pub struct DirFilter {
+    /// Do not apply [`DEFAULT_EXCLUDES`]
+    pub include_hidden: bool,
+    /// Skip files ignored by `.gitignore` files inside the directory
+    pub respect_gitignore: bool,
+    /// Gitignore style globs of files to skip
+    pub exclude: Vec<String>,
+    /// What to do with symlinks found inside the directory
+    pub symlinks: SymlinkPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Send the targets under the names of the links, directories included
+    Follow,
+    /// Leave symlinks out, they are listed in [`FilterReport::symlinks`]
+    #[default]
+    Skip,
+    /// Send links to files as their targets, fail on links that can not be resolved
+    Error,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "skip" => Ok(SymlinkPolicy::Skip),
+            "error" => Ok(SymlinkPolicy::Error),
+            _ => Err(format!("unknown symlink policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExcludeRule {
+    Default(String),
+    Gitignore,
+    Exclude,
+}
+
+impl Display for ExcludeRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExcludeRule::Default(name) => write!(f, "{}", name),
+            ExcludeRule::Gitignore => f.write_str(".gitignore"),
+            ExcludeRule::Exclude => f.write_str("--exclude"),
+        }
+    }
+}
+
+/// Number of files skipped by each rule.
+#[derive(Debug, Default, Clone)]
+pub struct FilterReport {
+    pub excluded: BTreeMap<ExcludeRule, usize>,
+    /// Symlinks left out by [`SymlinkPolicy`], including broken and looping ones
+    pub symlinks: Vec<PathBuf>,
+}
+
+impl FilterReport {
+    /// Number of files excluded by rules, skipped symlinks are not counted.
+    pub fn total(&self) -> usize {
+        self.excluded.values().sum()
+    }
+
+    pub fn merge(&mut self, other: FilterReport) {
+        for (rule, count) in other.excluded {
+            *self.excluded.entry(rule).or_default() += count;
+        }
+        self.symlinks.extend(other.symlinks);
+    }
+
+    pub(crate) fn add(&mut self, rule: ExcludeRule) {
+        *self.excluded.entry(rule).or_default() += 1;
+    }
+}
+
+/// [`DirFilter`] prepared for a single directory.
+pub(crate) struct DirMatcher<'a> {
+    filter: &'a DirFilter,
+    root: PathBuf,
+    exclude: Gitignore,
+    // ordered from the deepest directory to the root
+    gitignores: Vec<Gitignore>,
+}
+
+impl<'a> DirMatcher<'a> {
+    pub fn new(filter: &'a DirFilter, root: &Path) -> Self {
+        let mut builder = GitignoreBuilder::new(root);
+        for glob in &filter.exclude {
+            if let Err(e) = builder.add_line(None, glob) {
+                log::warn!("invalid exclude glob {}: {}", glob, e);
+            }
+        }
+        let exclude = builder.build().unwrap_or_else(|_| Gitignore::empty());
+
+        let mut gitignores = vec![];
+        if filter.respect_gitignore {
+            let mut paths: Vec<PathBuf> = walkdir::WalkDir::new(root)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_file() && entry.file_name() == ".gitignore")
+                .map(|entry| entry.into_path())
+                .collect();
+            paths.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+            for path in paths {
+                let (gitignore, error) = Gitignore::new(&path);
+                if let Some(e) = error {
+                    log::warn!("failed to parse {:?}: {}", path, e);
+                }
+                gitignores.push(gitignore);
+            }
+        }
+
+        Self {
+            filter,
+            root: root.to_path_buf(),
+            exclude,
+            gitignores,
+        }
+    }
+
+    /// Returns the rule excluding the file at `path`, if any.
+    pub fn excluded_by(&self, path: &Path) -> Option<ExcludeRule> {
+        if !self.filter.include_hidden {
+            let relative = path.strip_prefix(&self.root).unwrap_or(path);
+            for component in relative.components() {
+                let name = component.as_os_str();
+                if let Some(name) = DEFAULT_EXCLUDES.iter().find(|n| name == **n) {
+                    return Some(ExcludeRule::Default(name.to_string()));
+                }
+            }
+        }
+
+        if self
+            .exclude
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+        {
+            return Some(ExcludeRule::Exclude);
+        }
+
+        for gitignore in &self.gitignores {
+            if !path.starts_with(gitignore.path()) {
+                continue;
+            }
+            let matched = gitignore.matched_path_or_any_parents(path, false);
+            if matched.is_ignore() {
+                return Some(ExcludeRule::Gitignore);
+            }
+            if matched.is_whitelist() {
+                break;
+            }
+        }
+        None
+    }
+}
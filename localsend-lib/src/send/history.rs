@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use localsend_proto::Device;
+use serde::{Deserialize, Serialize};
+
+use super::{FileStatus, SendingFiles};
+
+/// Kept in the data directory by the CLI.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// One send to one device, a line of the history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub fingerprint: String,
+    pub alias: String,
+    /// Seconds since the Unix epoch
+    pub finished_at: u64,
+    /// Files the device received
+    pub files: usize,
+    /// Bytes of the files the device received
+    pub bytes: u64,
+    pub success: bool,
+}
+
+impl HistoryEntry {
+    /// Records the outcome of a send to `target`, finished now.
+    pub fn new(target: &Device, result: &crate::Result<SendingFiles>) -> Self {
+        let finished = match result {
+            Ok(files) => files
+                .values()
+                .filter(|f| f.status == FileStatus::Finished)
+                .collect(),
+            Err(_) => vec![],
+        };
+        Self {
+            fingerprint: target.fingerprint.clone(),
+            alias: target.alias.clone(),
+            finished_at: unix_now(),
+            files: finished.len(),
+            bytes: finished.iter().map(|f| f.file.size).sum(),
+            success: result.is_ok(),
+        }
+    }
+}
+
+/// What the history knows about sends to one device.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerStats {
+    pub transfers: usize,
+    pub failures: usize,
+    /// Bytes received by the device over all transfers
+    pub bytes: u64,
+    /// Seconds since the Unix epoch of the last successful transfer
+    pub last_success: Option<u64>,
+}
+
+impl PeerStats {
+    /// Share of the transfers that failed, zero without any.
+    pub fn failure_rate(&self) -> f64 {
+        match self.transfers {
+            0 => 0.0,
+            transfers => self.failures as f64 / transfers as f64,
+        }
+    }
+
+    fn add(&mut self, entry: &HistoryEntry) {
+        self.transfers += 1;
+        self.bytes += entry.bytes;
+        if entry.success {
+            self.last_success = self.last_success.max(Some(entry.finished_at));
+        } else {
+            self.failures += 1;
+        }
+    }
+}
+
+/// Sends of earlier invocations, appended to a JSON lines file.
+///
+/// The file is only read once, the stats of an instance do not change when
+/// other processes append to it.
+#[derive(Debug)]
+pub struct TransferHistory {
+    path: PathBuf,
+    stats: OnceLock<HashMap<String, PeerStats>>,
+}
+
+impl TransferHistory {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            stats: OnceLock::new(),
+        }
+    }
+
+    /// Reads the entries, skipping lines that are not valid, a missing history is empty.
+    pub fn entries(&self) -> io::Result<Vec<HistoryEntry>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        Ok(content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    log::debug!("Skipping invalid history line {:?}: {}", line, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    pub fn record(&self, entry: &HistoryEntry) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::from)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)
+    }
+
+    /// The stats of every device sent to before by fingerprint, an unreadable history
+    /// has none.
+    pub fn stats_by_peer(&self) -> &HashMap<String, PeerStats> {
+        self.stats.get_or_init(|| {
+            let entries = self.entries().unwrap_or_else(|e| {
+                log::warn!("Failed to read history {:?}: {}", self.path, e);
+                vec![]
+            });
+            let mut stats: HashMap<String, PeerStats> = HashMap::new();
+            for entry in &entries {
+                stats
+                    .entry(entry.fingerprint.clone())
+                    .or_default()
+                    .add(entry);
+            }
+            stats
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(fingerprint: &str, finished_at: u64, bytes: u64, success: bool) -> HistoryEntry {
+        HistoryEntry {
+            fingerprint: fingerprint.to_owned(),
+            alias: fingerprint.to_uppercase(),
+            finished_at,
+            files: 1,
+            bytes,
+            success,
+        }
+    }
+
+    #[test]
+    fn test_stats_by_peer() {
+        let path = std::env::temp_dir().join(format!("{}.jsonl", uuid::Uuid::new_v4()));
+        let history = TransferHistory::new(&path);
+        history.record(&entry("a", 100, 10, true)).unwrap();
+        history.record(&entry("a", 300, 0, false)).unwrap();
+        history.record(&entry("a", 200, 20, true)).unwrap();
+        history.record(&entry("b", 400, 5, false)).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"not json\n").unwrap();
+
+        assert_eq!(history.entries().unwrap().len(), 4);
+        let stats = history.stats_by_peer();
+        assert_eq!(
+            stats["a"],
+            PeerStats {
+                transfers: 3,
+                failures: 1,
+                bytes: 30,
+                last_success: Some(200),
+            }
+        );
+        assert!((stats["a"].failure_rate() - 1.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(stats["b"].last_success, None);
+        assert_eq!(stats["b"].failure_rate(), 1.0);
+
+        // computed once per instance
+        history.record(&entry("c", 500, 1, true)).unwrap();
+        assert!(!history.stats_by_peer().contains_key("c"));
+        assert!(TransferHistory::new(&path)
+            .stats_by_peer()
+            .contains_key("c"));
+        std::fs::remove_file(path).unwrap();
+
+        let missing = TransferHistory::new(std::env::temp_dir().join("missing-history.jsonl"));
+        assert!(missing.stats_by_peer().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::{io::BufRead, path::PathBuf};
+
+use crate::Result;
+
+use super::{SendError, SendingFiles};
+
+/// A line of a manifest, the file to send and the name the receiver sees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub file_name: Option<String>,
+}
+
+/// Reads a list of files to send, one path per line.
+///
+/// Blank lines and lines starting with `#` are ignored. A tab separates the
+/// path from the file name announced to the receiver, e.g. `/data/a.bin\tbackups/a.bin`.
+pub fn read_manifest(reader: impl BufRead) -> std::io::Result<Vec<ManifestEntry>> {
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let entry = match line.split_once('\t') {
+            Some((path, file_name)) if !file_name.trim().is_empty() => ManifestEntry {
+                path: PathBuf::from(path.trim()),
+                file_name: Some(file_name.trim().to_owned()),
+            },
+            Some((path, _)) => ManifestEntry {
+                path: PathBuf::from(path.trim()),
+                file_name: None,
+            },
+            None => ManifestEntry {
+                path: PathBuf::from(line.trim()),
+                file_name: None,
+            },
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+impl SendingFiles {
+    /// Adds all files of a manifest.
+    ///
+    /// Every path is checked before anything is added, entries that are not
+    /// existing files are reported together.
+    pub fn add_manifest(&mut self, entries: &[ManifestEntry]) -> Result<()> {
+        let missing: Vec<PathBuf> = entries
+            .iter()
+            .filter(|entry| !entry.path.is_file())
+            .map(|entry| entry.path.clone())
+            .collect();
+        if !missing.is_empty() {
+            return Err(SendError::MissingFiles(missing).into());
+        }
+        for entry in entries {
+            self.add_file(&entry.path, entry.file_name.clone())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        send::{SendError, SendingFiles},
+        Error,
+    };
+
+    use super::{read_manifest, ManifestEntry};
+
+    #[test]
+    fn test_read_manifest() {
+        let manifest =
+            "# backups\n\n/data/a.bin\tbackups/a.bin\r\n  /data/b.bin  \n/data/c.bin\t\n";
+        let entries = read_manifest(manifest.as_bytes()).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ManifestEntry {
+                    path: PathBuf::from("/data/a.bin"),
+                    file_name: Some("backups/a.bin".to_owned()),
+                },
+                ManifestEntry {
+                    path: PathBuf::from("/data/b.bin"),
+                    file_name: None,
+                },
+                ManifestEntry {
+                    path: PathBuf::from("/data/c.bin"),
+                    file_name: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_manifest() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.bin"), "a").unwrap();
+        let manifest = format!(
+            "{0}/a.bin\tbackups/a.bin\n{0}/missing.bin\n{0}\n{0}/gone.bin\n",
+            dir.display()
+        );
+        let entries = read_manifest(manifest.as_bytes()).unwrap();
+
+        let mut files = SendingFiles::default();
+        match files.add_manifest(&entries) {
+            Err(Error::Send(SendError::MissingFiles(missing))) => assert_eq!(
+                missing,
+                vec![dir.join("missing.bin"), dir.clone(), dir.join("gone.bin")]
+            ),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(files.is_empty());
+
+        files.add_manifest(&entries[..1]).unwrap();
+        let file = files.values().next().unwrap();
+        assert_eq!(file.file.file_name, "backups/a.bin");
+        assert_eq!(file.path.as_deref(), Some(dir.join("a.bin").as_path()));
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,5 +1,19 @@
+mod filter;
+mod history;
+mod manifest;
+mod oneshot;
+mod pacing;
 mod send_file;
 mod send_session;
+mod target;
+mod tls;
 
+pub use filter::*;
+pub use history::*;
+pub use manifest::*;
+pub use oneshot::*;
+pub use pacing::*;
 pub use send_file::*;
 pub use send_session::*;
+pub use target::*;
+pub use tls::*;
@@ -0,0 +1,201 @@
+use std::path::Path;
+
+use localsend_proto::Device;
+use tokio_util::sync::CancellationToken;
+
+use crate::{progress::ProgressSender, server::MutexServerState, Result};
+
+use super::{FileStatus, SendError, SendSession, SendingFiles};
+
+/// Sends `text` to `target` and returns once the receiver got it.
+///
+/// `local` describes this device to the receiver, its ip and port are not used.
+///
+/// ```no_run
+/// # async fn example(target: localsend_proto::Device, local: localsend_proto::Device) -> localsend_lib::Result<()> {
+/// localsend_lib::send::send_text_to(&target, &local, "Hello from Rust").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn send_text_to(target: &Device, local: &Device, text: &str) -> Result<()> {
+    let mut files = SendingFiles::default();
+    files.add_text(text, true);
+    send_to(target, local, &files, None, None, &CancellationToken::new()).await
+}
+
+/// Sends the files at `paths` to `target` and returns once the receiver saved all of them.
+///
+/// Directories are sent with their files. Files declined by the receiver fail
+/// with [`SendError::NotDelivered`].
+///
+/// ```no_run
+/// # async fn example(target: localsend_proto::Device, local: localsend_proto::Device) -> localsend_lib::Result<()> {
+/// localsend_lib::send::send_files_to(&target, &local, &["photo.jpg", "notes/"]).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn send_files_to(
+    target: &Device,
+    local: &Device,
+    paths: &[impl AsRef<Path>],
+) -> Result<()> {
+    let mut files = SendingFiles::default();
+    for path in paths {
+        let path = path.as_ref();
+        if path.is_dir() {
+            files.add_dir(path)?;
+        } else {
+            files.add_file(path, None)?;
+        }
+    }
+    send_to(target, local, &files, None, None, &CancellationToken::new()).await
+}
+
+/// Uploads `files` to `target`, failing unless all of them were saved.
+pub(crate) async fn send_to(
+    target: &Device,
+    local: &Device,
+    files: &SendingFiles,
+    state: Option<MutexServerState>,
+    progress: Option<ProgressSender>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let mut session = SendSession::new(local, target.clone(), files);
+    if let Some(progress) = progress {
+        session = session.with_progress(progress);
+    }
+    let sent = session.upload(state, cancel).await?;
+
+    let mut missing: Vec<_> = sent
+        .values()
+        .filter(|file| file.status != FileStatus::Finished)
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    missing.sort_by_key(|file| file.index);
+    let names = missing
+        .into_iter()
+        .map(|file| file.file.file_name.clone())
+        .collect();
+    Err(SendError::NotDelivered(names).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use localsend_proto::{dto::FileDto, fixtures::device, Device, MAX_ALIAS_LEN};
+
+    use crate::{
+        receive::{Decision, ReceiveDecider},
+        send::SendError,
+        server::ServerMessage,
+        test_util::TestReceiver,
+        util::device::with_alias,
+        Error,
+    };
+
+    use super::{send_files_to, send_text_to};
+
+    /// Accepts only the first file offered.
+    #[derive(Debug)]
+    struct FirstOnly;
+
+    #[async_trait::async_trait]
+    impl ReceiveDecider for FirstOnly {
+        async fn decide(&self, _sender: Device, mut files: Vec<FileDto>) -> Decision {
+            files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+            files.truncate(1);
+            Decision::Accept(files)
+        }
+    }
+
+    /// Accepts everything and remembers who sent it.
+    #[derive(Debug, Default)]
+    struct RecordSender(Mutex<Option<Device>>);
+
+    #[async_trait::async_trait]
+    impl ReceiveDecider for RecordSender {
+        async fn decide(&self, sender: Device, files: Vec<FileDto>) -> Decision {
+            *self.0.lock().unwrap() = Some(sender);
+            Decision::Accept(files)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_text_to() {
+        let mut receiver = TestReceiver::start().await;
+        let target = receiver.device();
+
+        send_text_to(&target, &device("sender", 0), "hello")
+            .await
+            .unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv()).await;
+        match message {
+            Ok(Some(ServerMessage::SessionFinished(report))) => assert_eq!(report.finished(), 1),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_files_to() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.join("a.txt"), dir.join("b.txt")];
+        for path in &paths {
+            std::fs::write(path, b"data").unwrap();
+        }
+        let receiver = TestReceiver::start_with(|state| state.decider = Arc::new(FirstOnly)).await;
+        let target = receiver.device();
+
+        let result = send_files_to(&target, &device("sender", 0), &paths).await;
+        match result {
+            Err(Error::Send(SendError::NotDelivered(names))) => assert_eq!(names, vec!["b.txt"]),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(
+            std::fs::read(receiver.destination.join("a.txt")).unwrap(),
+            b"data"
+        );
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_alias_override() {
+        let recorder = Arc::new(RecordSender::default());
+        let receiver = {
+            let recorder = recorder.clone();
+            TestReceiver::start_with(move |state| state.decider = recorder).await
+        };
+        let target = receiver.device();
+        let local = device("hostname", 0);
+
+        send_text_to(
+            &target,
+            &with_alias(&local, "Work Laptop (urgent)"),
+            "hello",
+        )
+        .await
+        .unwrap();
+        let sender = recorder.0.lock().unwrap().take().unwrap();
+        assert_eq!(sender.alias, "Work Laptop (urgent)");
+        assert_eq!(sender.fingerprint, local.fingerprint);
+
+        // too long for receivers, cut at a character boundary instead of being rejected
+        let alias = "🚀".repeat(MAX_ALIAS_LEN + 10);
+        send_text_to(&target, &with_alias(&local, &alias), "hello")
+            .await
+            .unwrap();
+        let sender = recorder.0.lock().unwrap().take().unwrap();
+        assert_eq!(sender.alias, "🚀".repeat(MAX_ALIAS_LEN));
+
+        receiver.stop().await;
+    }
+}
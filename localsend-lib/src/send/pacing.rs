@@ -0,0 +1,460 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use localsend_proto::Device;
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::receive::RateLimiter;
+
+/// Queueing delay the adaptive pacing lets uploads add on top of the base RTT.
+pub const TARGET_DELAY: Duration = Duration::from_millis(50);
+
+/// How often the RTT to the receiver is measured while pacing adaptively.
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A probe taking longer counts as this RTT, the link is congested anyway.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The base RTT is the smallest one of this many intervals of [`BASE_INTERVAL`],
+/// so that it follows route changes within a minute.
+const BASE_HISTORY: usize = 10;
+const BASE_INTERVAL: Duration = Duration::from_secs(6);
+
+/// The queueing delay is the smallest one of the last samples, one late probe is noise.
+const CURRENT_FILTER: usize = 3;
+
+/// Throughput is measured over windows of this length.
+const THROUGHPUT_WINDOW: Duration = Duration::from_millis(500);
+
+/// Share of the measured throughput kept when the queueing delay exceeds the target.
+const BACKOFF: f64 = 0.85;
+
+/// Growth of the rate per sample without any queueing delay, less the closer the
+/// delay is to the target.
+const GAIN: f64 = 0.05;
+
+/// The rate never drops below this many bytes per second.
+const MIN_RATE: f64 = 64.0 * 1024.0;
+
+/// How `send` paces its uploads besides `--limit-rate`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pace {
+    /// As fast as the connection takes them
+    #[default]
+    Off,
+    /// Back off once the uploads make the RTT to the receiver grow
+    Adaptive,
+}
+
+impl fmt::Display for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Pace::Off => "off",
+            Pace::Adaptive => "adaptive",
+        })
+    }
+}
+
+impl FromStr for Pace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "off" => Ok(Pace::Off),
+            "adaptive" => Ok(Pace::Adaptive),
+            _ => Err(format!(
+                "unknown pace: {}, expected one of off, adaptive",
+                s
+            )),
+        }
+    }
+}
+
+/// How fast the files of a session are uploaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pacing {
+    /// Bytes per second the uploads never exceed, whatever `pace` measures
+    pub limit: Option<u64>,
+    pub pace: Pace,
+}
+
+/// Adapts the upload rate to the queueing delay it causes, similar to LEDBAT.
+///
+/// Uploads go unpaced until the RTT to the receiver grows more than
+/// [`TARGET_DELAY`] above the smallest one seen recently. The rate then drops to
+/// [`BACKOFF`] of the measured throughput, and grows again while the delay stays
+/// below the target, so that it settles at about 85-90% of what the link takes
+/// and other traffic keeps a short queue.
+///
+/// It is driven by the samples alone, the caller passes the time of each one.
+#[derive(Debug)]
+pub struct PaceController {
+    /// Smallest RTT of each recent interval, the oldest first
+    base: VecDeque<(Instant, Duration)>,
+    /// Queueing delays of the last samples, the oldest first
+    current: VecDeque<Duration>,
+    /// Bytes per second, `None` while unpaced
+    rate: Option<f64>,
+    /// Upper bound of `rate`, the hard limit
+    ceiling: Option<f64>,
+    window_start: Instant,
+    window_bytes: u64,
+    /// Bytes per second of the last full window
+    throughput: Option<f64>,
+    /// When the next bytes may be sent at `rate`
+    next_send: Instant,
+    last_backoff: Option<Instant>,
+}
+
+impl PaceController {
+    pub fn new(ceiling: Option<u64>, now: Instant) -> Self {
+        Self {
+            base: VecDeque::new(),
+            current: VecDeque::new(),
+            rate: None,
+            ceiling: ceiling.map(|ceiling| ceiling as f64),
+            window_start: now,
+            window_bytes: 0,
+            throughput: None,
+            next_send: now,
+            last_backoff: None,
+        }
+    }
+
+    /// Bytes per second the uploads are paced to, `None` while unpaced.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Bytes per second achieved over the last window.
+    pub fn throughput(&self) -> Option<f64> {
+        self.throughput
+    }
+
+    /// The smallest RTT seen recently.
+    pub fn base_delay(&self) -> Option<Duration> {
+        self.base.iter().map(|(_, rtt)| *rtt).min()
+    }
+
+    /// How much the RTT currently exceeds the base one.
+    pub fn queueing_delay(&self) -> Option<Duration> {
+        self.current.iter().min().copied()
+    }
+
+    /// Takes `bytes` about to be sent, returning how long to wait before sending them.
+    pub fn schedule(&mut self, bytes: usize, now: Instant) -> Duration {
+        self.window_bytes += bytes as u64;
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= THROUGHPUT_WINDOW {
+            self.throughput = Some(self.window_bytes as f64 / elapsed.as_secs_f64());
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+        let start = self.next_send.max(now);
+        self.next_send = start + Duration::from_secs_f64(bytes as f64 / rate);
+        start - now
+    }
+
+    /// Takes an RTT measured to the receiver while uploading.
+    pub fn on_rtt(&mut self, rtt: Duration, now: Instant) {
+        match self.base.back_mut() {
+            Some((started, base)) if now.saturating_duration_since(*started) < BASE_INTERVAL => {
+                *base = (*base).min(rtt);
+            }
+            _ => {
+                self.base.push_back((now, rtt));
+                if self.base.len() > BASE_HISTORY {
+                    self.base.pop_front();
+                }
+            }
+        }
+        let queueing = rtt.saturating_sub(self.base_delay().unwrap_or(rtt));
+        self.current.push_back(queueing);
+        if self.current.len() > CURRENT_FILTER {
+            self.current.pop_front();
+        }
+
+        let queueing = self.queueing_delay().unwrap_or_default();
+        if queueing > TARGET_DELAY {
+            self.back_off(rtt, now);
+        } else if let Some(rate) = self.rate {
+            let off_target = 1.0 - queueing.as_secs_f64() / TARGET_DELAY.as_secs_f64();
+            let mut grown = rate * (1.0 + GAIN * off_target);
+            // paced below the rate, growing further would not tell anything
+            if let Some(throughput) = self.throughput {
+                grown = grown.min(rate.max(throughput * 1.5));
+            }
+            self.rate = Some(self.capped(grown));
+        }
+    }
+
+    /// Lowers the rate below the throughput, at most once per RTT.
+    fn back_off(&mut self, rtt: Duration, now: Instant) {
+        let interval = rtt.max(PROBE_INTERVAL);
+        if matches!(self.last_backoff, Some(last) if now.saturating_duration_since(last) < interval)
+        {
+            return;
+        }
+        let sending = match (self.rate, self.throughput) {
+            (Some(rate), Some(throughput)) => rate.min(throughput),
+            (rate, throughput) => match rate.or(throughput) {
+                Some(sending) => sending,
+                // nothing measured yet, the queue is not ours to judge
+                None => return,
+            },
+        };
+        self.rate = Some(self.capped(sending * BACKOFF));
+        self.last_backoff = Some(now);
+    }
+
+    fn capped(&self, rate: f64) -> f64 {
+        let rate = rate.max(MIN_RATE);
+        match self.ceiling {
+            Some(ceiling) => rate.min(ceiling),
+            None => rate,
+        }
+    }
+}
+
+/// Paces the chunks of the uploads of a session, see [`Pacing`].
+#[derive(Debug, Clone)]
+pub(crate) struct UploadPacer {
+    limiter: Option<RateLimiter>,
+    controller: Option<Arc<Mutex<PaceController>>>,
+}
+
+impl UploadPacer {
+    pub(crate) fn new(pacing: Pacing) -> Self {
+        Self {
+            limiter: pacing.limit.map(RateLimiter::new),
+            controller: (pacing.pace == Pace::Adaptive).then(|| {
+                Arc::new(Mutex::new(PaceController::new(
+                    pacing.limit,
+                    Instant::now(),
+                )))
+            }),
+        }
+    }
+
+    /// Waits until `bytes` about to be sent fit into the pacing, the hard limit first.
+    pub(crate) async fn pace(&self, bytes: usize) {
+        if let Some(limiter) = &self.limiter {
+            limiter.consume(bytes).await;
+        }
+        let wait = match &self.controller {
+            Some(controller) => controller.lock().unwrap().schedule(bytes, Instant::now()),
+            None => Duration::ZERO,
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Measures the RTT to `target` by connecting to it until `cancel`, when pacing
+    /// adaptively. The handshake queues behind the uploads on the way there.
+    pub(crate) async fn probe(self, target: Device, cancel: CancellationToken) {
+        let Some(controller) = self.controller else {
+            return;
+        };
+        let addr = (target.ip.as_str(), target.port);
+        let mut interval = tokio::time::interval(PROBE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+            let started = Instant::now();
+            let rtt = match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await {
+                Ok(Ok(_)) => started.elapsed(),
+                Ok(Err(e)) => {
+                    log::debug!("RTT probe to {:?} failed: {}", target.alias, e);
+                    continue;
+                }
+                Err(_) => PROBE_TIMEOUT,
+            };
+            let mut controller = controller.lock().unwrap();
+            controller.on_rtt(rtt, Instant::now());
+            log::trace!(
+                "RTT to {:?}: {:?}, queueing {:?}, rate {:?}",
+                target.alias,
+                rtt,
+                controller.queueing_delay(),
+                controller.rate()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Pace, PaceController, BASE_HISTORY, BASE_INTERVAL, MIN_RATE, PROBE_INTERVAL};
+
+    const MB: f64 = 1_000_000.0;
+
+    /// Sends 10 MB/s in chunks of 64 KB for `duration` from `now`, probing on
+    /// every [`PROBE_INTERVAL`] with `rtt`, returns the end.
+    fn run(
+        controller: &mut PaceController,
+        mut now: Instant,
+        duration: Duration,
+        rtt: impl Fn(Instant) -> Duration,
+    ) -> Instant {
+        let end = now + duration;
+        let chunk = 64_000;
+        let mut next_probe = now;
+        while now < end {
+            let wait = controller.schedule(chunk, now);
+            now += wait.max(Duration::from_secs_f64(chunk as f64 / (10.0 * MB)));
+            if now >= next_probe {
+                controller.on_rtt(rtt(now), now);
+                next_probe = now + PROBE_INTERVAL;
+            }
+        }
+        now
+    }
+
+    #[test]
+    fn test_unpaced_without_queueing() {
+        let start = Instant::now();
+        let mut controller = PaceController::new(None, start);
+        let jitter =
+            |now: Instant| Duration::from_millis(2 + (now - start).as_millis() as u64 % 7 * 3);
+        run(&mut controller, start, Duration::from_secs(5), jitter);
+        assert_eq!(controller.rate(), None);
+        let throughput = controller.throughput().unwrap();
+        assert!((throughput - 10.0 * MB).abs() < 0.1 * MB, "{}", throughput);
+        assert_eq!(controller.base_delay(), Some(Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn test_backs_off_when_queueing() {
+        let start = Instant::now();
+        let mut controller = PaceController::new(None, start);
+        let now = run(&mut controller, start, Duration::from_secs(2), |_| {
+            Duration::from_millis(2)
+        });
+        // the queue builds up
+        controller.on_rtt(Duration::from_millis(80), now);
+        controller.on_rtt(Duration::from_millis(90), now);
+        assert_eq!(controller.rate(), None);
+        controller.on_rtt(Duration::from_millis(100), now);
+        let rate = controller.rate().unwrap();
+        assert!((rate - 8.5 * MB).abs() < 0.1 * MB, "{}", rate);
+        // once per RTT
+        controller.on_rtt(Duration::from_millis(100), now + Duration::from_millis(10));
+        assert_eq!(controller.rate(), Some(rate));
+
+        // paced, chunks are spread out
+        let waits: Vec<_> = (0..3).map(|_| controller.schedule(85_000, now)).collect();
+        assert_eq!(waits[0], Duration::ZERO);
+        assert!(waits[1] >= Duration::from_millis(9), "{:?}", waits);
+        assert!(waits[2] > waits[1], "{:?}", waits);
+
+        // keeps backing off while the queue stays
+        let now = run(&mut controller, now, Duration::from_secs(30), |_| {
+            Duration::from_millis(300)
+        });
+        assert_eq!(controller.rate(), Some(MIN_RATE));
+        let throughput = controller.throughput().unwrap();
+        assert!(throughput < MIN_RATE * 1.1, "{}", throughput);
+
+        // and recovers once it drains
+        run(&mut controller, now, Duration::from_secs(120), |_| {
+            Duration::from_millis(2)
+        });
+        let rate = controller.rate().unwrap();
+        assert!(rate > 9.0 * MB, "{}", rate);
+    }
+
+    #[test]
+    fn test_settles_below_capacity() {
+        // a 4 MB/s link, its queue grows by what is sent beyond that until the
+        // buffers are full and the sender is held back
+        let start = Instant::now();
+        let mut controller = PaceController::new(None, start);
+        let capacity = 4.0 * MB;
+        let mut now = start;
+        let mut queued = 0.0;
+        let mut sent = 0.0;
+        let mut measured_from = None;
+        let mut next_probe = now;
+        while now < start + Duration::from_secs(60) {
+            let chunk = 64_000;
+            let wait = controller.schedule(chunk, now);
+            let mut step = wait.max(Duration::from_secs_f64(chunk as f64 / (10.0 * MB)));
+            if queued >= MB {
+                step = step.max(Duration::from_secs_f64(chunk as f64 / capacity));
+            }
+            now += step;
+            queued = (queued + chunk as f64 - capacity * step.as_secs_f64()).max(0.0);
+            if now >= next_probe {
+                let rtt = Duration::from_millis(2) + Duration::from_secs_f64(queued / capacity);
+                controller.on_rtt(rtt, now);
+                next_probe = now + PROBE_INTERVAL;
+            }
+            if now >= start + Duration::from_secs(20) {
+                measured_from.get_or_insert(now);
+                sent += chunk as f64;
+            }
+        }
+        let achieved = sent / (now - measured_from.unwrap()).as_secs_f64();
+        assert!(
+            achieved > 0.8 * capacity && achieved < capacity,
+            "{}",
+            achieved
+        );
+        // the queue stays short
+        assert!(queued / capacity < 0.2, "{}", queued / capacity);
+    }
+
+    #[test]
+    fn test_ceiling() {
+        let start = Instant::now();
+        let mut controller = PaceController::new(Some(2_000_000), start);
+        let now = run(&mut controller, start, Duration::from_secs(2), |_| {
+            Duration::from_millis(2)
+        });
+        for rtt in [150, 150, 150] {
+            controller.on_rtt(Duration::from_millis(rtt), now);
+        }
+        assert_eq!(controller.rate(), Some(2.0 * MB));
+        run(&mut controller, now, Duration::from_secs(30), |_| {
+            Duration::from_millis(2)
+        });
+        assert_eq!(controller.rate(), Some(2.0 * MB));
+    }
+
+    #[test]
+    fn test_base_delay_follows_route() {
+        let start = Instant::now();
+        let mut controller = PaceController::new(None, start);
+        controller.on_rtt(Duration::from_millis(2), start);
+        // the new route is slower, not congested
+        let mut now = start;
+        for _ in 0..BASE_HISTORY * 2 {
+            now += BASE_INTERVAL;
+            controller.on_rtt(Duration::from_millis(40), now);
+        }
+        assert_eq!(controller.base_delay(), Some(Duration::from_millis(40)));
+        assert_eq!(controller.queueing_delay(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_parse_pace() {
+        assert_eq!("adaptive".parse(), Ok(Pace::Adaptive));
+        assert_eq!(" Off".parse(), Ok(Pace::Off));
+        assert!("fast".parse::<Pace>().is_err());
+        assert_eq!(Pace::Adaptive.to_string(), "adaptive");
+    }
+}
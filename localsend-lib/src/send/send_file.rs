@@ -1,15 +1,28 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use linked_hash_map::LinkedHashMap;
 use localsend_proto::dto::{FileDto, FileType};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::Result;
+use crate::{
+    util::{
+        hash::FileHash,
+        mime::{guess_mime, ContentTypes},
+        note::{is_note, note_file},
+        preview::PreviewPolicy,
+    },
+    Result,
+};
+
+use super::{filter::DirMatcher, DirFilter, FilterReport, SymlinkPolicy};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileStatus {
     Queue,
     Skipped,
@@ -25,66 +38,296 @@ pub struct SendingFile {
     pub status: FileStatus,
     pub path: Option<PathBuf>,
     pub token: Option<String>,
+    /// Why the receiver's filters skipped the file, when it told
+    pub reason: Option<String>,
+    /// Name the receiver saves the file under when it differs from the offered one,
+    /// only localsend-rs receivers tell
+    pub final_name: Option<String>,
+    /// Content type of the upload when the file name does not tell, the offered
+    /// file type stays as it is
+    pub mime: Option<String>,
+    pub started: Option<Instant>,
+    pub finished: Option<Instant>,
 }
 
 impl SendingFile {
-    pub fn new(index: usize, file: FileDto, path: Option<PathBuf>) -> Self {
+    /// A queued file, [`SendingFiles::push`] numbers it.
+    pub fn new(file: FileDto, path: Option<PathBuf>) -> Self {
         Self {
-            index,
+            index: 0,
             file,
             status: FileStatus::Queue,
             path,
             token: None,
+            reason: None,
+            final_name: None,
+            mime: None,
+            started: None,
+            finished: None,
+        }
+    }
+
+    pub fn with_mime(mut self, mime: impl ToString) -> Self {
+        self.mime = Some(mime.to_string());
+        self
+    }
+
+    /// The Content-Type of the upload, guessed from the file name unless set.
+    pub fn content_type(&self) -> String {
+        match &self.mime {
+            Some(mime) => mime.clone(),
+            None => guess_mime(&self.file.file_name),
+        }
+    }
+
+    /// The message of a text added with [`SendingFiles::add_text`], `None` for files,
+    /// notes and texts too long for a preview.
+    pub fn text(&self) -> Option<&str> {
+        match (&self.path, &self.file.file_type) {
+            (None, FileType::Text) if !is_note(&self.file) => self.file.preview.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// How long the upload took, `None` unless it was started and ended.
+    pub fn duration(&self) -> Option<Duration> {
+        transfer_duration(self.started, self.finished)
+    }
+
+    /// Average bytes per second of a finished upload.
+    pub fn speed(&self) -> Option<f64> {
+        match self.status {
+            FileStatus::Finished => self.duration().map(|d| throughput(self.file.size, d)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether an input that is not an existing file or directory still looks like a path,
+/// e.g. a mistyped one, so sending it as a text may not be what was meant.
+///
+/// Paths contain a separator or end with an extension of up to 5 letters or digits,
+/// links like `https://…` are texts.
+pub fn looks_like_path(input: &str) -> bool {
+    if input.contains("://") {
+        return false;
+    }
+    if input.contains(['/', '\\']) {
+        return true;
+    }
+    match input.trim().rsplit_once('.') {
+        Some((stem, extension)) => {
+            !stem.is_empty()
+                && !stem.ends_with(char::is_whitespace)
+                && (1..=5).contains(&extension.len())
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+                && extension.chars().any(|c| c.is_ascii_alphabetic())
         }
+        None => false,
+    }
+}
+
+/// Whether walking failed on a symlink, e.g. a broken one or a loop.
+fn is_link_error(e: &walkdir::Error) -> bool {
+    e.loop_ancestor().is_some()
+        || e.path()
+            .and_then(|path| std::fs::symlink_metadata(path).ok())
+            .is_some_and(|metadata| metadata.file_type().is_symlink())
+}
+
+pub(crate) fn transfer_duration(
+    started: Option<Instant>,
+    finished: Option<Instant>,
+) -> Option<Duration> {
+    started
+        .zip(finished)
+        .map(|(started, finished)| finished.saturating_duration_since(started))
+}
+
+/// Files offered in one session by default, larger offers make a prepare-upload request
+/// too big for receivers to handle well.
+pub const DEFAULT_CHUNK_FILES: usize = 2000;
+
+/// Bytes per second, zero for transfers too short to measure.
+pub fn throughput(bytes: u64, duration: Duration) -> f64 {
+    match duration.as_secs_f64() {
+        secs if secs > 0.0 => bytes as f64 / secs,
+        _ => 0.0,
     }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct SendingFiles {
-    pub files: LinkedHashMap<String, SendingFile>,
+    /// In display order, the index of a file is its position here
+    files: LinkedHashMap<String, SendingFile>,
+    /// Decides the previews of added texts and files
+    pub preview_policy: PreviewPolicy,
+    /// Decides the content types of added files
+    pub content_types: ContentTypes,
 }
 
 impl SendingFiles {
+    pub fn with_preview_policy(mut self, policy: PreviewPolicy) -> Self {
+        self.preview_policy = policy;
+        self
+    }
+
+    pub fn with_content_types(mut self, content_types: ContentTypes) -> Self {
+        self.content_types = content_types;
+        self
+    }
+
     pub fn get(&self, file_id: &String) -> Option<&SendingFile> {
         self.files.get(file_id)
     }
 
+    /// Changes a file in place, [`Self::push`] is the way to add one.
+    pub fn get_mut(&mut self, file_id: &str) -> Option<&mut SendingFile> {
+        self.files.get_mut(file_id)
+    }
+
+    /// The ids of the files in display order, which numbers them in progress and
+    /// summaries.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.files.keys()
+    }
+
+    /// The files in display order.
+    pub fn values(&self) -> impl Iterator<Item = &SendingFile> {
+        self.files.values()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut SendingFile> {
+        self.files.iter_mut().map(|(_, file)| file)
+    }
+
     pub fn len(&self) -> usize {
         self.files.len()
     }
 
+    /// Appends a file after the others, its index becomes its position. A file with
+    /// the same id is replaced where it is.
+    pub fn push(&mut self, mut file: SendingFile) {
+        match self.files.get_mut(&file.file.id) {
+            Some(existing) => {
+                file.index = existing.index;
+                *existing = file;
+            }
+            None => {
+                file.index = self.files.len();
+                self.files.insert(file.file.id.clone(), file);
+            }
+        }
+    }
+
+    /// Appends the files of `other` in its order, numbering them after these.
+    pub fn extend(&mut self, other: SendingFiles) {
+        for (_, file) in other.files {
+            self.push(file);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The text messages, in display order.
+    pub fn texts(&self) -> impl Iterator<Item = &SendingFile> {
+        self.values().filter(|file| file.text().is_some())
+    }
+
+    /// The files read from disk, in display order.
+    pub fn real_files(&self) -> impl Iterator<Item = &SendingFile> {
+        self.values().filter(|file| file.path.is_some())
+    }
+
+    /// Adds a text message, with a preview when `preview` is set and the policy allows one.
     pub fn add_text(&mut self, text: impl ToString, preview: bool) {
         let text = text.to_string();
+        let preview = preview
+            .then(|| self.preview_policy.preview(text.as_bytes()))
+            .flatten();
         let id = Uuid::new_v4().to_string();
-        let text_hash = format!("{:x}", md5::compute(&text));
+        let text_hash = FileHash::sha256(&text).to_string();
         let file = FileDto {
             id: id.clone(),
             file_name: format!("{}.txt", text_hash),
             size: text.len() as u64,
             file_type: localsend_proto::dto::FileType::Text,
             hash: Some(text_hash),
-            preview: if preview { Some(text) } else { None },
+            preview,
         };
-        self.files
-            .insert(id.clone(), SendingFile::new(self.files.len(), file, None));
+        self.push(SendingFile::new(file, None));
+    }
+
+    /// Attaches a note shown by localsend-rs receivers before accepting, see [`note_file`].
+    pub fn add_note(&mut self, note: &str) {
+        let file = note_file(note);
+        self.push(SendingFile::new(file, None));
     }
 
     pub fn add_dir(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.add_dir_with_filter(path, &DirFilter::default())?;
+        Ok(())
+    }
+
+    /// Adds all files of a directory accepted by `filter`.
+    pub fn add_dir_with_filter(
+        &mut self,
+        path: impl AsRef<Path>,
+        filter: &DirFilter,
+    ) -> Result<FilterReport> {
         use super::SendError;
 
         let base = path.as_ref().parent().ok_or(SendError::NoPermission)?;
+        let matcher = DirMatcher::new(filter, path.as_ref());
+        let mut report = FilterReport::default();
 
-        for entry in walkdir::WalkDir::new(&path) {
-            let entry = entry?;
+        // followed links keep the path below `path`, names never depend on the targets
+        let walker =
+            walkdir::WalkDir::new(&path).follow_links(filter.symlinks == SymlinkPolicy::Follow);
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                // a loop or a broken link, it must not stop the other files
+                Err(e) if filter.symlinks == SymlinkPolicy::Follow && is_link_error(&e) => {
+                    let link = e.path().unwrap_or(path.as_ref()).to_path_buf();
+                    log::warn!("skip symlink {:?}: {}", link, e);
+                    report.symlinks.push(link);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
             let entry_path = entry.path();
+            if entry.path_is_symlink() && entry.depth() > 0 {
+                match filter.symlinks {
+                    SymlinkPolicy::Follow => {}
+                    SymlinkPolicy::Skip => {
+                        log::debug!("skip symlink {:?}", entry_path);
+                        report.symlinks.push(entry_path.to_path_buf());
+                        continue;
+                    }
+                    SymlinkPolicy::Error => {
+                        if std::fs::metadata(entry_path).is_err() {
+                            return Err(SendError::BrokenSymlink(entry_path.to_path_buf()))?;
+                        }
+                    }
+                }
+            }
             if !entry_path.is_file() {
                 continue;
             }
 
+            if let Some(rule) = matcher.excluded_by(entry_path) {
+                log::debug!("exclude file {:?} by {}", entry_path, rule);
+                report.add(rule);
+                continue;
+            }
+
             let diff_path =
-                pathdiff::diff_paths(entry_path, &base).ok_or(SendError::NoPermission)?;
+                pathdiff::diff_paths(entry_path, base).ok_or(SendError::NoPermission)?;
             let file_name = match diff_path.to_str() {
-                Some(name) => name.replace("\\", "/"),
+                Some(name) => name.replace('\\', "/"),
                 None => {
                     log::error!("ignore file: {:?}", entry_path);
                     continue;
@@ -95,7 +338,7 @@ impl SendingFiles {
             self.add_file(entry_path, Some(file_name))?;
         }
 
-        Ok(())
+        Ok(report)
     }
 
     pub fn add_file(&mut self, path: impl AsRef<Path>, file_name: Option<String>) -> Result<()> {
@@ -125,6 +368,8 @@ impl SendingFiles {
         let size = std::fs::metadata(path)?.len();
         let file_name = file_name.unwrap_or(get_file_name(path).unwrap_or(id.clone()));
         let file_type = file_type(&file_name);
+        let preview = self.preview_policy.file_preview(path, &file_name, size);
+        let mime = self.content_types.resolve(&file_name, path);
 
         let file = FileDto {
             id: id.clone(),
@@ -132,12 +377,11 @@ impl SendingFiles {
             size,
             file_type,
             hash: None,
-            preview: None,
+            preview,
         };
-        self.files.insert(
-            id.clone(),
-            SendingFile::new(self.files.len(), file, Some(path.to_path_buf())),
-        );
+        let mut sending_file = SendingFile::new(file, Some(path.to_path_buf()));
+        sending_file.mime = mime;
+        self.push(sending_file);
         Ok(())
     }
 
@@ -155,14 +399,73 @@ impl SendingFiles {
         }
     }
 
+    /// Records why the receiver's filters skipped files, by file id.
+    pub fn update_reasons(&mut self, reasons: HashMap<String, String>) {
+        for (file_id, reason) in reasons {
+            if let Some(file) = self.files.get_mut(&file_id) {
+                file.reason = Some(reason);
+            }
+        }
+    }
+
+    /// Records the names the receiver expects to save files under, by file id.
+    pub fn update_final_names(&mut self, names: HashMap<String, String>) {
+        for (file_id, name) in names {
+            if let Some(file) = self.files.get_mut(&file_id) {
+                file.final_name = Some(name);
+            }
+        }
+    }
+
+    /// Keeps the files with the given ids, renumbering their indices.
+    pub fn retain(&mut self, file_ids: &[String]) {
+        for (id, file) in std::mem::take(&mut self.files) {
+            if file_ids.contains(&id) {
+                self.push(file);
+            }
+        }
+    }
+
+    /// Splits the files in order into offers of at most `max` files each, indices
+    /// are renumbered per offer.
+    pub fn chunks(&self, max: usize) -> Vec<SendingFiles> {
+        let mut chunks: Vec<SendingFiles> = vec![];
+        for file in self.values() {
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() < max.max(1) => chunk.push(file.clone()),
+                _ => {
+                    let mut chunk = SendingFiles::default()
+                        .with_preview_policy(self.preview_policy.clone())
+                        .with_content_types(self.content_types.clone());
+                    chunk.push(file.clone());
+                    chunks.push(chunk);
+                }
+            }
+        }
+        chunks
+    }
+
+    pub fn to_sending_status(&mut self, file_id: &str) {
+        if let Some(file) = self.files.get_mut(file_id) {
+            file.status = FileStatus::Sending;
+            file.started = Some(Instant::now());
+        }
+    }
+
     pub fn to_finish_status(&mut self, file_id: String, success: bool) {
-        self.files.get_mut(&file_id).map(|file| {
+        if let Some(file) = self.files.get_mut(&file_id) {
+            file.finished = Some(Instant::now());
             if success {
                 file.status = FileStatus::Finished;
             } else {
                 file.status = FileStatus::Failed;
             }
-        });
+        }
+    }
+
+    /// The offered files in display order.
+    pub fn to_dto_list(&self) -> Vec<FileDto> {
+        self.values().map(|file| file.file.clone()).collect()
     }
 
     pub fn to_dto_map(&self) -> HashMap<String, FileDto> {
@@ -172,3 +475,336 @@ impl SendingFiles {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use crate::{
+        send::{DirFilter, ExcludeRule, FileStatus},
+        util::mime::ContentTypes,
+    };
+
+    use super::{looks_like_path, throughput, SendingFiles};
+
+    fn write(root: &Path, name: &str, content: &str) {
+        let path = root.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn file_names(files: &SendingFiles) -> Vec<String> {
+        let mut names: Vec<String> = files
+            .values()
+            .map(|file| file.file.file_name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_add_dir_with_filter() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let root = dir.join("project");
+        write(&root, ".git/HEAD", "ref");
+        write(&root, ".DS_Store", "");
+        write(&root, ".gitignore", "*.log\ntarget/\n");
+        write(&root, "main.rs", "fn main() {}");
+        write(&root, "debug.log", "log");
+        write(&root, "target/app", "bin");
+        write(&root, "docs/.gitignore", "!keep.log\ndraft.md\n");
+        write(&root, "docs/keep.log", "keep");
+        write(&root, "docs/other.log", "other");
+        write(&root, "docs/draft.md", "draft");
+        write(&root, "docs/readme.md", "readme");
+
+        let mut files = SendingFiles::default();
+        let filter = DirFilter {
+            respect_gitignore: true,
+            exclude: vec!["readme.md".to_owned()],
+            ..Default::default()
+        };
+        let report = files.add_dir_with_filter(&root, &filter).unwrap();
+        assert_eq!(
+            file_names(&files),
+            vec![
+                "project/.gitignore",
+                "project/docs/.gitignore",
+                "project/docs/keep.log",
+                "project/main.rs",
+            ]
+        );
+        assert_eq!(report.excluded[&ExcludeRule::Default(".git".to_owned())], 1);
+        assert_eq!(
+            report.excluded[&ExcludeRule::Default(".DS_Store".to_owned())],
+            1
+        );
+        assert_eq!(report.excluded[&ExcludeRule::Gitignore], 4);
+        assert_eq!(report.excluded[&ExcludeRule::Exclude], 1);
+        assert_eq!(report.total(), 7);
+
+        let mut files = SendingFiles::default();
+        let filter = DirFilter {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let report = files.add_dir_with_filter(&root, &filter).unwrap();
+        assert_eq!(files.len(), 11);
+        assert_eq!(report.total(), 0);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policy() {
+        use std::os::unix::fs::symlink;
+
+        use crate::{
+            send::{SendError, SymlinkPolicy},
+            Error,
+        };
+
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let root = dir.join("project");
+        write(&root, "a.txt", "a");
+        write(&root, "sub/b.txt", "b");
+        write(&dir, "outside.txt", "outside");
+        symlink(root.join("a.txt"), root.join("link.txt")).unwrap();
+        symlink(dir.join("outside.txt"), root.join("outside.txt")).unwrap();
+        symlink(root.join("sub"), root.join("linked")).unwrap();
+        symlink(&root, root.join("sub/loop")).unwrap();
+        symlink(root.join("missing.txt"), root.join("dangling.txt")).unwrap();
+
+        let add = |symlinks| {
+            let mut files = SendingFiles::default();
+            let filter = DirFilter {
+                symlinks,
+                ..Default::default()
+            };
+            files.add_dir_with_filter(&root, &filter).map(|report| {
+                let mut skipped = report.symlinks;
+                skipped.sort();
+                (file_names(&files), skipped)
+            })
+        };
+
+        let (names, skipped) = add(SymlinkPolicy::Skip).unwrap();
+        assert_eq!(names, vec!["project/a.txt", "project/sub/b.txt"]);
+        assert_eq!(
+            skipped,
+            vec![
+                root.join("dangling.txt"),
+                root.join("link.txt"),
+                root.join("linked"),
+                root.join("outside.txt"),
+                root.join("sub/loop"),
+            ]
+        );
+
+        // targets are sent under the names of the links, even from outside the directory
+        let (names, skipped) = add(SymlinkPolicy::Follow).unwrap();
+        assert_eq!(
+            names,
+            vec![
+                "project/a.txt",
+                "project/link.txt",
+                "project/linked/b.txt",
+                "project/outside.txt",
+                "project/sub/b.txt",
+            ]
+        );
+        assert_eq!(skipped.len(), 3);
+        assert!(skipped.contains(&root.join("dangling.txt")));
+        assert!(skipped.contains(&root.join("sub/loop")));
+        assert!(skipped.contains(&root.join("linked/loop")));
+
+        match add(SymlinkPolicy::Error) {
+            Err(Error::Send(SendError::BrokenSymlink(path))) => {
+                assert_eq!(path, root.join("dangling.txt"))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        std::fs::remove_file(root.join("dangling.txt")).unwrap();
+        let (names, skipped) = add(SymlinkPolicy::Error).unwrap();
+        assert_eq!(
+            names,
+            vec![
+                "project/a.txt",
+                "project/link.txt",
+                "project/outside.txt",
+                "project/sub/b.txt",
+            ]
+        );
+        assert!(skipped.is_empty());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_transfer_timing() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        write(&dir, "a.txt", "a");
+        write(&dir, "b.txt", "b");
+        write(&dir, "c.txt", "c");
+        let mut files = SendingFiles::default();
+        files.add_dir(&dir).unwrap();
+        let ids: Vec<String> = files.keys().cloned().collect();
+        let token = ids[..2].iter().map(|id| (id.clone(), id.clone())).collect();
+        files.update_token(token);
+
+        files.to_sending_status(&ids[0]);
+        std::thread::sleep(Duration::from_millis(10));
+        files.to_finish_status(ids[0].clone(), true);
+        files.to_sending_status(&ids[1]);
+        files.to_finish_status(ids[1].clone(), false);
+
+        let finished = files.get(&ids[0]).unwrap();
+        let failed = files.get(&ids[1]).unwrap();
+        let skipped = files.get(&ids[2]).unwrap();
+        assert!(finished.duration().unwrap() >= Duration::from_millis(10));
+        assert!(finished.finished.unwrap() <= failed.started.unwrap());
+        assert!(finished.speed().unwrap() > 0.0);
+        assert_eq!(failed.status, FileStatus::Failed);
+        assert!(failed.duration().is_some());
+        assert_eq!(failed.speed(), None);
+        assert_eq!(skipped.status, FileStatus::Skipped);
+        assert_eq!(skipped.duration(), None);
+        assert_eq!(skipped.speed(), None);
+
+        assert_eq!(throughput(1000, Duration::from_secs(2)), 500.0);
+        assert_eq!(throughput(1000, Duration::ZERO), 0.0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut files = SendingFiles::default();
+        for text in ["a", "b", "c", "d"] {
+            files.add_text(text, true);
+        }
+        let ids: Vec<String> = files.keys().cloned().collect();
+        files.retain(&[ids[3].clone(), ids[1].clone()]);
+
+        let kept: Vec<(usize, &str)> = files
+            .values()
+            .map(|f| (f.index, f.file.preview.as_deref().unwrap()))
+            .collect();
+        assert_eq!(kept, vec![(0, "b"), (1, "d")]);
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut files = SendingFiles::default().with_content_types(ContentTypes {
+            overrides: vec![],
+            sniff: false,
+        });
+        for text in ["a", "b", "c", "d", "e"] {
+            files.add_text(text, true);
+        }
+        let chunks: Vec<Vec<(usize, String)>> = files
+            .chunks(2)
+            .into_iter()
+            .map(|chunk| {
+                chunk
+                    .values()
+                    .map(|f| (f.index, f.file.preview.clone().unwrap()))
+                    .collect()
+            })
+            .collect();
+        let expected = [
+            vec![(0, "a"), (1, "b")],
+            vec![(0, "c"), (1, "d")],
+            vec![(0, "e")],
+        ];
+        assert_eq!(chunks.len(), expected.len());
+        for (chunk, expected) in chunks.iter().zip(expected) {
+            let chunk: Vec<(usize, &str)> = chunk.iter().map(|(i, s)| (*i, s.as_str())).collect();
+            assert_eq!(chunk, expected);
+        }
+        // the chunks resolve content types like the files they were split from
+        assert!(files
+            .chunks(2)
+            .iter()
+            .all(|chunk| !chunk.content_types.sniff));
+        assert_eq!(files.chunks(5).len(), 1);
+        assert!(SendingFiles::default().chunks(2).is_empty());
+    }
+
+    #[test]
+    fn test_order() {
+        let mut files = SendingFiles::default();
+        for text in ["a", "b", "c"] {
+            files.add_text(text, true);
+        }
+        let previews = |files: &SendingFiles| -> Vec<(usize, String)> {
+            files
+                .to_dto_list()
+                .into_iter()
+                .map(|dto| (files.get(&dto.id).unwrap().index, dto.preview.unwrap()))
+                .collect()
+        };
+
+        // a replaced file keeps its place and number
+        let mut second = files.values().nth(1).unwrap().clone();
+        second.file.preview = Some("B".to_owned());
+        files.push(second);
+        let expected = [(0, "a"), (1, "B"), (2, "c")].map(|(i, s)| (i, s.to_owned()));
+        assert_eq!(previews(&files), expected);
+
+        // chunks joined again are numbered as before the split
+        let mut joined = SendingFiles::default();
+        for chunk in files.chunks(2) {
+            joined.extend(chunk);
+        }
+        assert!(joined.keys().eq(files.keys()));
+        assert_eq!(previews(&joined), expected);
+    }
+
+    #[test]
+    fn test_texts_and_real_files() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        write(&dir, "photo.jpg", "jpg");
+
+        let mut files = SendingFiles::default();
+        files.add_text("meet at 7pm", true);
+        files.add_file(dir.join("photo.jpg"), None).unwrap();
+        files.add_note("for the album");
+        // without a preview the message is unknown, it is neither
+        files.add_text("too long", false);
+
+        let texts: Vec<_> = files.texts().filter_map(|file| file.text()).collect();
+        assert_eq!(texts, vec!["meet at 7pm"]);
+        let real: Vec<_> = files
+            .real_files()
+            .map(|file| file.file.file_name.as_str())
+            .collect();
+        assert_eq!(real, vec!["photo.jpg"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_looks_like_path() {
+        for input in [
+            "photos/holiday.jpg",
+            "C:\\Users\\me",
+            "./notes",
+            "report.pdf",
+            "archive.tar.gz",
+        ] {
+            assert!(looks_like_path(input), "{}", input);
+        }
+        for input in [
+            "meet at 7pm",
+            "See you. Bye",
+            "version 1.2",
+            "done.",
+            ".",
+            "hello . txt",
+            "mp3",
+            "https://example.com/a.html",
+        ] {
+            assert!(!looks_like_path(input), "{}", input);
+        }
+    }
+}
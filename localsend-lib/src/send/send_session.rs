@@ -1,26 +1,58 @@
-use std::cmp::min;
+use std::{
+    cmp::min,
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use futures_util::StreamExt;
+use futures_util::{
+    future::{select, Either},
+    StreamExt,
+};
 use localsend_proto::{
-    dto::{FileType, PrepareUploadRequestDto, PrepareUploadResponseDto, RegisterDto},
-    ApiRoute, Device, PROTOCOL_VERSION_1,
+    dto::{
+        ExtensionDto, FileDto, FileType, PrepareUploadRequestDto, PrepareUploadResponseDto,
+        RegisterDto, UploadResponseDto,
+    },
+    ApiRoute, Device,
 };
 use once_cell::sync::Lazy;
-use reqwest::{header, Body, Client, StatusCode};
+use parking_lot::RwLock;
+use reqwest::{header, Body, Client, Response, StatusCode};
 use thiserror::Error;
-use tokio::{
-    fs::File,
-    sync::mpsc::Sender,
-    task::{AbortHandle, JoinError},
+use tokio::fs::File;
+use tokio_util::{
+    io::{ReaderStream, StreamReader},
+    sync::CancellationToken,
 };
-use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
-use crate::{send::FileStatus, server::MutexServerState, Result};
+#[allow(deprecated)]
+pub use crate::progress::UploadProgress;
+use crate::{
+    progress::{ProgressSender, ProgressStream},
+    send::FileStatus,
+    server::{CancelledBy, MutexServerState, ProgressEvents, SessionEvent},
+    util::{
+        compression::{is_compressible, Compression, COMPRESS_HEADER},
+        inhibit::TransferGuard,
+        trace,
+    },
+    ErrorDto, Result,
+};
 
-use super::{SendingFile, SendingFiles};
+use super::{
+    client_for, describe_candidates, describe_host_attempt, pin_error, Pacing, SendingFile,
+    SendingFiles, UploadPacer,
+};
 
-static CLIENT: Lazy<Client> = Lazy::new(|| {
+pub(crate) static CLIENT: Lazy<Client> = Lazy::new(|| {
     reqwest::ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .build()
@@ -39,27 +71,48 @@ pub enum SendError {
     Cancelled,
     #[error("No permission")]
     NoPermission,
-    #[error(transparent)]
-    Aborted(JoinError),
+    #[error("Device not found: {0}")]
+    DeviceNotFound(String),
+    #[error("Several devices match, choose one with --to-fingerprint: {}", describe_candidates(.0))]
+    AmbiguousTarget(Vec<Device>),
+    #[error("{} does not answer at {}:{}, it may have gone offline", device.alias, device.ip, device.port)]
+    TargetUnreachable { device: Box<Device> },
+    #[error("Files not found: {}", .0.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "))]
+    MissingFiles(Vec<PathBuf>),
+    #[error("Broken symlink: {}", .0.display())]
+    BrokenSymlink(PathBuf),
+    #[error("Cancelled by sender")]
+    Aborted,
+    #[error("Not delivered: {}", .0.join(", "))]
+    NotDelivered(Vec<String>),
+    #[error("{host} could not be resolved: {reason}")]
+    HostNotFound { host: String, reason: String },
+    #[error("{}", describe_host_attempt(host, *port, tried))]
+    HostUnreachable {
+        host: String,
+        port: u16,
+        tried: Vec<IpAddr>,
+    },
+    #[error("The certificate of {} at {}:{} does not match its fingerprint", device.alias, device.ip, device.port)]
+    CertificateMismatch { device: Box<Device> },
     #[error("Unknown response status code: {0}")]
     Unknown(StatusCode),
 }
 
-#[derive(Debug)]
-pub struct UploadProgress {
-    pub file_id: String,
-    pub position: u64,
-    pub finish: bool,
-}
-
 #[derive(Debug)]
 pub struct SendSession {
     pub session_id: String,
     info: RegisterDto,
     target: Device,
-    files: SendingFiles,
+    /// Pinned to the certificate of `target` when it is reached over HTTPS
+    client: Client,
+    /// Updated by the upload loop, the server state lock is never taken for it
+    files: Arc<RwLock<SendingFiles>>,
     pub remote_session_id: Option<String>, // v1 nullable
-    cancel_token: Option<AbortHandle>,
+    cancel: CancellationToken,
+    cancelled_by_receiver: Arc<AtomicBool>,
+    progress: Option<ProgressSender>,
+    pacing: Pacing,
 }
 
 impl SendSession {
@@ -67,28 +120,103 @@ impl SendSession {
         Self {
             session_id: Uuid::new_v4().to_string(),
             info: device.clone().into(),
+            client: client_for(&target, false),
             target,
-            files: files.clone(),
+            files: Arc::new(RwLock::new(files.clone())),
             remote_session_id: None,
-            cancel_token: None,
+            cancel: CancellationToken::new(),
+            cancelled_by_receiver: Arc::new(AtomicBool::new(false)),
+            progress: None,
+            pacing: Pacing::default(),
         }
     }
 
+    /// The progress of the upload, nothing is reported unless it is taken before
+    /// [`Self::upload`]. Taking it again ends the stream taken before.
+    pub fn progress(&mut self) -> ProgressStream {
+        let (tx, stream) = ProgressStream::channel();
+        self.progress = Some(tx);
+        stream
+    }
+
+    /// Reports to the stream of `progress`, e.g. shared with the sessions before.
+    pub fn with_progress(mut self, progress: ProgressSender) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Accepts any certificate of an HTTPS target instead of the one of its fingerprint.
+    pub fn with_insecure_tls(mut self, insecure: bool) -> Self {
+        self.client = client_for(&self.target, insecure);
+        self
+    }
+
+    /// Paces the uploads, they go as fast as the connection takes them by default.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    pub fn target(&self) -> &Device {
+        &self.target
+    }
+
+    /// Returns a snapshot of the files and their current status.
+    pub fn files(&self) -> SendingFiles {
+        self.files.read().clone()
+    }
+
+    /// Uploads the files, returning their final status.
+    ///
+    /// Cancelling `cancel` stops the running upload and tells the receiver. While
+    /// running, the session is kept in the `send_sessions` of `state`, so that
+    /// [`Self::cancel_by_sender`] and a cancel request of the receiver reach it.
+    /// The progress ends with [`crate::progress::ProgressEvent::SessionEnded`]
+    /// whatever the result.
     pub async fn upload(
         mut self,
-        state: MutexServerState,
-        progress_tx: Sender<UploadProgress>,
-    ) -> Result<()> {
-        let files = self.files.to_dto_map();
+        state: Option<MutexServerState>,
+        cancel: &CancellationToken,
+    ) -> Result<SendingFiles> {
+        let progress = self.progress.take();
+        let result = self.upload_files(state, &progress, cancel).await;
+        if let Some(progress) = &progress {
+            progress.session_ended().await;
+        }
+        result
+    }
+
+    async fn upload_files(
+        mut self,
+        state: Option<MutexServerState>,
+        progress_tx: &Option<ProgressSender>,
+        cancel: &CancellationToken,
+    ) -> Result<SendingFiles> {
+        self.cancel = cancel.child_token();
+        let cancel = self.cancel.clone();
+        let events = match &state {
+            Some(state) => Some(state.lock().await.events.clone()),
+            None => None,
+        };
+
+        let files = self.files.read().to_dto_map();
         let request_dto = PrepareUploadRequestDto {
             info: self.info.clone(),
             files,
+            extension: Some(ExtensionDto {
+                compress: Compression::SUPPORTED
+                    .iter()
+                    .map(|c| c.name().to_owned())
+                    .collect(),
+            }),
         };
-        let response = CLIENT
-            .post(ApiRoute::PrepareUpload.target(&self.target))
-            .json(&request_dto)
-            .send()
-            .await?;
+        let request = self
+            .client
+            .post(ApiRoute::PrepareUpload.target(&self.target)?)
+            .json(&request_dto);
+        let response = until_cancelled(&cancel, trace::send(request))
+            .await?
+            .map_err(|e| pin_error(e, &self.target))?;
         match response.status() {
             // 200
             StatusCode::OK => {}
@@ -100,8 +228,8 @@ impl SendSession {
             StatusCode::FORBIDDEN => {
                 return Err(SendError::Rejected.into());
             }
-            // 409
-            StatusCode::CONFLICT => {
+            // 409, or 429 when too many senders wait for the receiver already
+            StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS => {
                 return Err(SendError::Busy.into());
             }
             _ => {
@@ -109,75 +237,213 @@ impl SendSession {
             }
         }
 
-        let file_token = if self.target.version == PROTOCOL_VERSION_1 {
-            response.json().await?
+        // only localsend-rs receivers acknowledge the extension
+        let compression = response
+            .headers()
+            .get(COMPRESS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Compression::from_name);
+
+        let (file_token, filtered, final_names) = if self.target.protocol_version()?.major == 1 {
+            (response.json().await?, HashMap::new(), HashMap::new())
         } else {
             let response_dto = response.json::<PrepareUploadResponseDto>().await?;
             self.remote_session_id = Some(response_dto.session_id);
-            response_dto.files
+            (
+                response_dto.files,
+                response_dto.filtered,
+                response_dto.final_names,
+            )
         };
-        if file_token.is_empty() {
+        let nothing_selected = file_token.is_empty();
+        {
+            let mut files = self.files.write();
+            files.update_token(file_token);
+            files.update_reasons(filtered);
+            files.update_final_names(final_names);
+        }
+        if nothing_selected {
+            // accepted with everything deselected, the session stays open on the receiver until cancelled
+            let queue: Vec<SendingFile> = self.files.read().values().cloned().collect();
+            report_skipped(progress_tx, &queue).await;
+            if self.remote_session_id.is_some() {
+                let peer = Peer {
+                    device: self.target.clone(),
+                    client: self.client.clone(),
+                };
+                if let Err(e) = send_cancel(&peer, &self.remote_session_id).await {
+                    log::warn!(
+                        "Failed to close the empty session on {:?}: {}",
+                        peer.device.alias,
+                        e
+                    );
+                }
+            }
             return Err(SendError::NothingSelected.into());
         }
+        if let Some(events) = &events {
+            let files = self.files.read();
+            events.emit(SessionEvent::SendStarted {
+                session_id: self.session_id.clone(),
+                target: self.target.clone(),
+                files: files
+                    .values()
+                    .filter(|file| file.status != FileStatus::Skipped)
+                    .map(|file| file.file.clone())
+                    .collect(),
+            });
+        }
 
-        self.files.update_token(file_token);
-
-        let join_handle = {
-            let remote_session_id = self.remote_session_id.clone();
-            let target = self.target.clone();
-            let files = self.files.clone();
-            let new_state = state.clone();
+        let session_id = self.session_id.clone();
+        let remote_session_id = self.remote_session_id.clone();
+        let peer = Peer {
+            device: self.target.clone(),
+            client: self.client.clone(),
+        };
+        let files = self.files.clone();
+        let cancelled_by_receiver = self.cancelled_by_receiver.clone();
+        let pacer = UploadPacer::new(self.pacing);
+        if let Some(state) = &state {
+            state
+                .lock()
+                .await
+                .send_sessions
+                .insert(self.session_id.clone(), self);
+        }
 
-            let handle = tokio::spawn(async move {
-                for (file_id, file) in files.files {
-                    if file.status == FileStatus::Skipped {
-                        continue;
-                    }
+        let _awake = TransferGuard::acquire(&format!("Sending files to {}", peer.device.alias));
+        // the upload loop only touches the files of this session, never the server state
+        let queue: Vec<SendingFile> = files.read().values().cloned().collect();
+        report_skipped(progress_tx, &queue).await;
+        let probe = tokio::spawn(
+            pacer
+                .clone()
+                .probe(peer.device.clone(), cancel.child_token()),
+        );
+        for file in queue {
+            if file.status == FileStatus::Skipped {
+                continue;
+            }
+            if cancel.is_cancelled() {
+                break;
+            }
+            files.write().to_sending_status(&file.file.id);
+            if let Some(events) = &events {
+                events.emit(SessionEvent::FileStarted {
+                    session_id: session_id.clone(),
+                    file_id: file.file.id.clone(),
+                });
+            }
 
-                    let send_result =
-                        Self::upload_file(&remote_session_id, &file, &target, progress_tx.clone())
-                            .await;
-                    if let Err(e) = &send_result {
-                        log::error!("Failed to upload file {}: {}", file_id, e);
+            let send_result = Self::upload_file(
+                &remote_session_id,
+                &file,
+                &peer,
+                compression,
+                &pacer,
+                progress_tx.clone(),
+                events
+                    .as_ref()
+                    .map(|events| events.file_progress(&session_id, &file.file.id)),
+                &cancel,
+            )
+            .await;
+            match &send_result {
+                Err(e) => {
+                    log::error!("Failed to upload file {}: {}", file.file.id, e);
+                    if let Some(progress_tx) = progress_tx {
+                        progress_tx.done(&file.file.id, FileStatus::Failed).await;
                     }
-
-                    let mut state = new_state.lock().await;
-                    if let Some(session) = &mut state.send_session {
-                        session.files.to_finish_status(file_id, send_result.is_ok());
+                }
+                // files and texts without chunks report nothing while uploading
+                Ok(_) if file.path.is_none() || file.file.size == 0 => {
+                    if let Some(progress_tx) = progress_tx {
+                        progress_tx.done(&file.file.id, FileStatus::Finished).await;
                     }
                 }
-            });
-            self.cancel_token = Some(handle.abort_handle());
+                Ok(_) => {}
+            }
+            // the name it was saved under replaces the one expected when preparing
+            if let Ok(Some(saved)) = &send_result {
+                if let Some(sent) = files.write().get_mut(&file.file.id) {
+                    sent.final_name.clone_from(&saved.final_name);
+                }
+            }
 
-            let mut state = state.lock().await;
-            state.send_session = Some(self);
-            handle
-        };
+            if let Some(events) = &events {
+                events.emit(SessionEvent::FileFinished {
+                    session_id: session_id.clone(),
+                    file_id: file.file.id.clone(),
+                    status: if send_result.is_ok() {
+                        FileStatus::Finished
+                    } else {
+                        FileStatus::Failed
+                    },
+                });
+            }
+            files
+                .write()
+                .to_finish_status(file.file.id, send_result.is_ok());
+        }
+        probe.abort();
 
-        let result = join_handle.await;
-        {
-            let mut state = state.lock().await;
-            state.send_session.take();
+        if let Some(state) = &state {
+            state.lock().await.send_sessions.remove(&session_id);
         }
-        if let Err(join_error) = result {
-            if join_error.is_cancelled() {
+        if cancel.is_cancelled() {
+            let by_receiver = cancelled_by_receiver.load(Ordering::SeqCst);
+            if let Some(events) = &events {
+                events.emit(SessionEvent::SessionCancelled {
+                    session_id: session_id.clone(),
+                    by: if by_receiver {
+                        CancelledBy::Receiver
+                    } else {
+                        CancelledBy::Sender
+                    },
+                });
+            }
+            if by_receiver {
                 return Err(SendError::Cancelled.into());
-            } else {
-                return Err(SendError::Aborted(join_error).into());
             }
+            if let Err(e) = send_cancel(&peer, &remote_session_id).await {
+                log::warn!(
+                    "Failed to tell {:?} about the cancellation: {}",
+                    peer.device.alias,
+                    e
+                );
+            }
+            log::info!(
+                "{} cancelled, remote session_id: {:?}",
+                session_id,
+                remote_session_id
+            );
+            return Err(SendError::Aborted.into());
         }
 
-        Ok(())
+        if let Some(events) = &events {
+            events.emit(SessionEvent::SendFinished { session_id });
+        }
+        let files = files.read().clone();
+        Ok(files)
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn upload_file(
         remote_session_id: &Option<String>,
         sending_file: &SendingFile,
-        target: &Device,
-        progress_tx: Sender<UploadProgress>,
-    ) -> Result<()> {
+        peer: &Peer,
+        compression: Option<Compression>,
+        pacer: &UploadPacer,
+        progress_tx: Option<ProgressSender>,
+        mut events: Option<ProgressEvents>,
+        cancel: &CancellationToken,
+    ) -> Result<Option<UploadResponseDto>> {
         let file = &sending_file.file;
         let file_size = file.size;
+        // only localsend-rs receivers acknowledge compression and answer empty retries
+        let localsend_rs = compression.is_some();
+        let compression =
+            compression.filter(|_| sending_file.path.is_some() && is_compressible(file));
 
         let body;
         match &sending_file.path {
@@ -186,23 +452,33 @@ impl SendSession {
                 let file = File::open(path).await?;
                 let mut reader_stream = ReaderStream::new(file);
                 let mut uploaded = 0;
+                let started = Instant::now();
+                let pacer = pacer.clone();
 
                 let async_stream = async_stream::stream! {
                     while let Some(chunk) = reader_stream.next().await {
                         if let Ok(chunk) = &chunk {
+                            pacer.pace(chunk.len()).await;
                             let pos = min(uploaded + (chunk.len() as u64), file_size);
                             uploaded = pos;
-                            let progress = UploadProgress{
-                                file_id: file_id.clone(),
-                                position: pos,
-                                finish: pos >= file_size,
-                            };
-                            progress_tx.send(progress).await.ok();
+                            if let Some(progress_tx) = &progress_tx {
+                                progress_tx.position(&file_id, pos, file_size, started.elapsed()).await;
+                            }
+                            if let Some(events) = &mut events {
+                                events.update(pos, pos >= file_size);
+                            }
                         }
                         yield chunk;
                     }
                 };
-                body = Body::wrap_stream(async_stream);
+                body = match compression {
+                    // progress counts the bytes read from the file, not the compressed ones
+                    Some(compression) => {
+                        let reader = StreamReader::new(Box::pin(async_stream));
+                        Body::wrap_stream(ReaderStream::new(compression.encoder(reader)))
+                    }
+                    None => Body::wrap_stream(async_stream),
+                };
             }
             None => {
                 if file.file_type == FileType::Text && file.preview.is_some() {
@@ -214,70 +490,855 @@ impl SendSession {
             }
         }
 
-        let content_type = mime_guess::from_path(&file.file_name)
-            .first_or_octet_stream()
-            .to_string();
+        let content_type = sending_file.content_type();
 
-        let v2_args = if let Some(session_id) = remote_session_id {
-            format!("&sessionId={}", session_id,)
-        } else {
-            String::default()
+        let mut query = vec![
+            ("fileId", file.id.as_str()),
+            (
+                "token",
+                sending_file.token.as_deref().expect("No file token"),
+            ),
+        ];
+        if let Some(session_id) = remote_session_id {
+            query.push(("sessionId", session_id));
+        }
+        let request = peer
+            .client
+            .post(ApiRoute::Upload.target(&peer.device)?)
+            .query(&query)
+            .header(header::CONTENT_TYPE, content_type);
+        let upload = match compression {
+            Some(compression) => request
+                .try_clone()
+                .expect("No body yet")
+                .header(header::CONTENT_ENCODING, compression.name()),
+            None => request
+                .try_clone()
+                .expect("No body yet")
+                .header(header::CONTENT_LENGTH, file_size),
+        };
+        // dropping the request closes the connection, the receiver removes the partial file
+        let response = match until_cancelled(cancel, trace::send(upload.body(body))).await? {
+            Ok(response) => response,
+            Err(e) if !localsend_rs => return Err(pin_error(e, &peer.device)),
+            Err(e) => {
+                // the receiver may have saved the file before the response got lost,
+                // it answers an empty retry of a saved file without receiving it again
+                log::warn!("Upload of file {} failed, asking again: {}", file.id, e);
+                let probe = request
+                    .header(header::CONTENT_LENGTH, 0)
+                    .body(Body::from(Vec::new()));
+                match until_cancelled(cancel, trace::send(probe)).await? {
+                    Ok(response) if response.status() == StatusCode::OK => response,
+                    _ => return Err(e.into()),
+                }
+            }
         };
-        let url = format!(
-            "{}?fileId={}&token={}{}",
-            ApiRoute::Upload.target(&target),
-            file.id,
-            sending_file.token.as_ref().expect("No file token"),
-            v2_args,
-        );
-        let response = CLIENT
-            .post(url)
-            .header(header::CONTENT_LENGTH, file_size)
-            .header(header::CONTENT_TYPE, content_type)
-            .body(body)
-            .send()
-            .await?;
         match response.status() {
-            StatusCode::OK => Ok(()),
-            _ => Err(SendError::Unknown(response.status()).into()),
+            StatusCode::OK => Ok(confirm_upload(file, response).await),
+            status => {
+                if let Ok(error) = response.json::<ErrorDto>().await {
+                    log::warn!(
+                        "Receiver refused file {}: {:?} {}",
+                        file.id,
+                        error.code,
+                        error.message
+                    );
+                }
+                Err(SendError::Unknown(status).into())
+            }
         }
     }
 
-    pub async fn cancel_by_receiver(self) -> Result<()> {
-        self.cancel(false).await
+    /// Stops the upload after the receiver cancelled the session.
+    pub fn cancel_by_receiver(&self) {
+        self.cancelled_by_receiver.store(true, Ordering::SeqCst);
+        self.cancel.cancel();
     }
 
-    pub async fn cancel_by_sender(self) -> Result<()> {
-        self.cancel(true).await
+    /// Stops the upload and tells the receiver.
+    pub fn cancel_by_sender(&self) {
+        self.cancel.cancel();
     }
+}
 
-    pub async fn cancel(self, from_sender: bool) -> Result<()> {
-        let cancel_token = self.cancel_token.ok_or(SendError::NoPermission)?;
-        let cancel_result = if from_sender {
-            let v2_args = if let Some(session_id) = &self.remote_session_id {
-                format!("?sessionId={}", session_id,)
-            } else {
-                String::default()
-            };
-            let url = format!("{}{}", ApiRoute::Cancel.target(&self.target), v2_args,);
-            let status_code = CLIENT.post(url).send().await.map(|r| r.status());
-            match status_code {
-                // 200
-                Ok(StatusCode::OK) => Ok(()),
-                // 403
-                Ok(StatusCode::FORBIDDEN) => Err(SendError::NoPermission),
-                _ => Err(SendError::Unknown(status_code?)),
+/// Compares what a localsend-rs receiver saved with what was sent, `None` for the
+/// official apps.
+async fn confirm_upload(file: &FileDto, response: Response) -> Option<UploadResponseDto> {
+    // the official apps answer with an empty body
+    let body = response.bytes().await.unwrap_or_default();
+    if body.is_empty() {
+        return None;
+    }
+    let saved = match serde_json::from_slice::<UploadResponseDto>(&body) {
+        Ok(saved) => saved,
+        Err(e) => {
+            log::debug!("Invalid upload response for file {}: {}", file.id, e);
+            return None;
+        }
+    };
+    if saved.bytes != file.size {
+        log::warn!(
+            "Receiver saved {} of {} bytes of file {}",
+            saved.bytes,
+            file.size,
+            file.id
+        );
+    }
+    if let (Some(sent), Some(verified)) = (&file.hash, &saved.sha256) {
+        if !sent.eq_ignore_ascii_case(verified) {
+            log::warn!(
+                "Receiver verified file {} against {} instead of {}",
+                file.id,
+                verified,
+                sent
+            );
+        }
+    }
+    Some(saved)
+}
+
+/// The receiver of a session and the client talking to it.
+#[derive(Debug)]
+struct Peer {
+    device: Device,
+    client: Client,
+}
+
+/// Ends the progress of the files the receiver did not select, with the reason of its filters.
+async fn report_skipped(progress_tx: &Option<ProgressSender>, files: &[SendingFile]) {
+    let Some(progress_tx) = progress_tx else {
+        return;
+    };
+    for file in files.iter().filter(|f| f.status == FileStatus::Skipped) {
+        match &file.reason {
+            Some(reason) => progress_tx.filtered(&file.file.id, reason).await,
+            None => progress_tx.done(&file.file.id, FileStatus::Skipped).await,
+        }
+    }
+}
+
+async fn send_cancel(peer: &Peer, remote_session_id: &Option<String>) -> Result<()> {
+    let mut request = peer.client.post(ApiRoute::Cancel.target(&peer.device)?);
+    if let Some(session_id) = remote_session_id {
+        request = request.query(&[("sessionId", session_id)]);
+    }
+    match trace::send(request).await?.status() {
+        // 200
+        StatusCode::OK => Ok(()),
+        // 403
+        StatusCode::FORBIDDEN => Err(SendError::NoPermission)?,
+        status => Err(SendError::Unknown(status))?,
+    }
+}
+
+/// Runs `future` unless `cancel` is cancelled first.
+async fn until_cancelled<F: Future>(cancel: &CancellationToken, future: F) -> Result<F::Output> {
+    match select(Box::pin(cancel.cancelled()), Box::pin(future)).await {
+        Either::Left(_) => Err(SendError::Aborted)?,
+        Either::Right((output, _)) => Ok(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::Query,
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::post,
+        Json, Router,
+    };
+    use futures_util::future::{join, select};
+    use localsend_proto::{
+        dto::{PrepareUploadRequestDto, PrepareUploadResponseDto},
+        fixtures::device,
+        ApiRoute, Device,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        progress::ProgressEvent,
+        send::{FileStatus, SendError},
+        server::{MutexServerState, ServerMessage, ServerState, SessionEvent},
+        test_util::TestReceiver,
+        util::compression::{Compression, COMPRESS_HEADER},
+        CollisionPolicy, Error,
+    };
+
+    use super::{SendSession, SendingFiles};
+
+    fn idle_state() -> MutexServerState {
+        let (server_tx, _) = tokio::sync::mpsc::channel(1);
+        let (_, client_rx) = tokio::sync::mpsc::channel(1);
+        Arc::new(tokio::sync::Mutex::new(ServerState::new(
+            server_tx, client_rx,
+        )))
+    }
+
+    type Uploads = Arc<std::sync::Mutex<Vec<(Option<String>, Bytes)>>>;
+
+    // characters with a meaning in urls must survive the round trip
+    const SESSION_ID: &str = "session+/=&#";
+
+    fn token(file_id: &str) -> String {
+        format!("{}+a/b=c&d#e", file_id)
+    }
+
+    /// Receiver that records uploads, acknowledging compression with `ack` like localsend-rs
+    /// or ignoring the extension like the official app when `None`.
+    async fn mock_receiver(ack: Option<&'static str>) -> (u16, Uploads) {
+        let uploads = Uploads::default();
+        let prepare = move |Json(dto): Json<PrepareUploadRequestDto>| async move {
+            assert!(dto.extension.is_some());
+            let files = dto
+                .files
+                .into_keys()
+                .map(|id| (id.clone(), token(&id)))
+                .collect();
+            let mut headers = HeaderMap::new();
+            if let Some(ack) = ack {
+                headers.insert(COMPRESS_HEADER, ack.parse().unwrap());
             }
-        } else {
-            Ok(())
+            let session_id = SESSION_ID.to_owned();
+            (
+                headers,
+                Json(PrepareUploadResponseDto {
+                    session_id,
+                    files,
+                    filtered: HashMap::new(),
+                    final_names: HashMap::new(),
+                }),
+            )
         };
-        cancel_token.abort();
-        cancel_result?;
-        log::info!(
-            "{} cancelled, remote session_id: {:?}",
-            self.session_id,
-            self.remote_session_id
+        let upload = {
+            let uploads = uploads.clone();
+            move |Query(query): Query<HashMap<String, String>>, headers: HeaderMap, body: Bytes| async move {
+                if query["sessionId"] != SESSION_ID || query["token"] != token(&query["fileId"]) {
+                    return StatusCode::FORBIDDEN;
+                }
+                let encoding = headers
+                    .get(header::CONTENT_ENCODING)
+                    .map(|v| v.to_str().unwrap().to_owned());
+                uploads.lock().unwrap().push((encoding, body));
+                StatusCode::OK
+            }
+        };
+        let router = Router::new()
+            .route(&ApiRoute::PrepareUpload.v2(), post(prepare))
+            .route(&ApiRoute::Upload.v2(), post(upload));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (port, uploads)
+    }
+
+    async fn send_csv(device: &Device, state: MutexServerState, data: &[u8]) {
+        let path = std::env::temp_dir().join(format!("{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&path, data).unwrap();
+        let mut files = SendingFiles::default();
+        files.add_file(&path, None).unwrap();
+
+        SendSession::new(device, device.clone(), &files)
+            .upload(Some(state), &CancellationToken::new())
+            .await
+            .unwrap();
+        std::fs::remove_file(path).ok();
+    }
+
+    fn cancel_after(delay: Duration) -> CancellationToken {
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            token.cancel();
+        });
+        cancel
+    }
+
+    fn text_files() -> SendingFiles {
+        let mut files = SendingFiles::default();
+        files.add_text("hello", true);
+        files
+    }
+
+    #[tokio::test]
+    async fn test_cancel_during_negotiation() {
+        // the receiver never answers, like a user that does not look at the prompt
+        let prepare = post(std::future::pending::<StatusCode>);
+        let router = Router::new().route(&ApiRoute::PrepareUpload.v2(), prepare);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let device = device("local", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let cancel = cancel_after(Duration::from_millis(200));
+        let state = idle_state();
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            SendSession::new(&device, device.clone(), &text_files())
+                .upload(Some(state.clone()), &cancel),
+        )
+        .await
+        .expect("upload not cancelled");
+        assert!(matches!(result, Err(Error::Send(SendError::Aborted))));
+        assert!(state.lock().await.send_sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_mid_file() {
+        let cancelled = Arc::new(std::sync::Mutex::new(None));
+        let prepare = |Json(dto): Json<PrepareUploadRequestDto>| async move {
+            let files = dto
+                .files
+                .into_keys()
+                .map(|id| (id.clone(), token(&id)))
+                .collect();
+            let session_id = SESSION_ID.to_owned();
+            Json(PrepareUploadResponseDto {
+                session_id,
+                files,
+                filtered: HashMap::new(),
+                final_names: HashMap::new(),
+            })
+        };
+        // the upload stalls after the headers, the body is never read
+        let upload = std::future::pending::<StatusCode>;
+        let cancel = {
+            let cancelled = cancelled.clone();
+            move |Query(query): Query<HashMap<String, String>>| async move {
+                *cancelled.lock().unwrap() = query.get("sessionId").cloned();
+                StatusCode::OK
+            }
+        };
+        let router = Router::new()
+            .route(&ApiRoute::PrepareUpload.v2(), post(prepare))
+            .route(&ApiRoute::Upload.v2(), post(upload))
+            .route(&ApiRoute::Cancel.v2(), post(cancel));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let device = device("local", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let cancel = cancel_after(Duration::from_millis(300));
+        let state = idle_state();
+        let mut events = state.lock().await.events.subscribe();
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            SendSession::new(&device, device.clone(), &text_files())
+                .upload(Some(state.clone()), &cancel),
+        )
+        .await
+        .expect("upload not cancelled");
+        assert!(matches!(result, Err(Error::Send(SendError::Aborted))));
+        assert!(state.lock().await.send_sessions.is_empty());
+        assert_eq!(cancelled.lock().unwrap().as_deref(), Some(SESSION_ID));
+
+        let mut sequence = vec![];
+        while let Ok(event) = events.try_recv() {
+            sequence.push(match event {
+                SessionEvent::SendStarted { files, .. } => format!("started {}", files.len()),
+                SessionEvent::FileStarted { .. } => "file started".to_owned(),
+                SessionEvent::FileFinished { status, .. } => format!("file {:?}", status),
+                SessionEvent::SessionCancelled { by, .. } => format!("cancelled by {:?}", by),
+                event => format!("{:?}", event),
+            });
+        }
+        assert_eq!(
+            sequence,
+            [
+                "started 1",
+                "file started",
+                "file Failed",
+                "cancelled by Sender"
+            ]
+        );
+    }
+
+    fn csv() -> Vec<u8> {
+        "id,name,value\n1,localsend,42\n".repeat(20000).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_no_compression_for_official_receiver() {
+        let data = csv();
+        let (port, uploads) = mock_receiver(None).await;
+        send_csv(&device("local", port), idle_state(), &data).await;
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].0, None);
+        assert_eq!(&uploads[0].1[..], &data[..]);
+    }
+
+    #[tokio::test]
+    async fn test_note_for_official_receiver() {
+        let (port, uploads) = mock_receiver(None).await;
+        let device = device("local", port);
+        let mut files = text_files();
+        files.add_note("photos from Tuesday");
+
+        let sent = SendSession::new(&device, device.clone(), &files)
+            .upload(Some(idle_state()), &CancellationToken::new())
+            .await
+            .unwrap();
+        // a receiver without the convention gets a plain text file
+        let mut bodies: Vec<Bytes> = uploads.lock().unwrap().drain(..).map(|(_, b)| b).collect();
+        bodies.sort();
+        assert_eq!(bodies, ["hello", "photos from Tuesday"]);
+        assert!(sent
+            .values()
+            .all(|file| file.status == FileStatus::Finished));
+    }
+
+    #[tokio::test]
+    async fn test_filtered_by_receiver() {
+        const REASON: &str = "Exceeds the limit of 1 files per session";
+        // like a localsend-rs receiver whose filters drop the second text
+        let prepare = |Json(dto): Json<PrepareUploadRequestDto>| async move {
+            let (files, filtered) = dto
+                .files
+                .into_values()
+                .partition::<Vec<_>, _>(|file| file.preview.as_deref() == Some("hello"));
+            Json(PrepareUploadResponseDto {
+                session_id: SESSION_ID.to_owned(),
+                files: files
+                    .into_iter()
+                    .map(|f| (f.id.clone(), token(&f.id)))
+                    .collect(),
+                filtered: filtered
+                    .into_iter()
+                    .map(|f| (f.id, REASON.to_owned()))
+                    .collect(),
+                final_names: HashMap::new(),
+            })
+        };
+        let router = Router::new()
+            .route(&ApiRoute::PrepareUpload.v2(), post(prepare))
+            .route(&ApiRoute::Upload.v2(), post(|| async { StatusCode::OK }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let device = device("local", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut files = text_files();
+        files.add_text("world", true);
+        let mut session = SendSession::new(&device, device.clone(), &files);
+        let mut progress = session.progress();
+        let cancel = CancellationToken::new();
+        let upload = session.upload(None, &cancel);
+        let (sent, events) = join(upload, async {
+            let mut events = vec![];
+            while let Some(event) = progress.recv().await {
+                events.push(event);
+            }
+            events
+        })
+        .await;
+
+        let sent = sent.unwrap();
+        let world = sent
+            .values()
+            .find(|file| file.file.preview.as_deref() == Some("world"))
+            .unwrap();
+        assert_eq!(world.status, FileStatus::Skipped);
+        assert_eq!(world.reason.as_deref(), Some(REASON));
+        assert!(events.contains(&ProgressEvent::Skipped {
+            file_id: world.file.id.clone(),
+            reason: Some(REASON.to_owned()),
+        }));
+    }
+
+    /// Receiver accepting nothing, with 200 and an empty files map, or with 204 when `empty_map`
+    /// is false. Returns the session ids it was asked to cancel.
+    async fn declining_receiver(empty_map: bool) -> (Device, Arc<std::sync::Mutex<Vec<String>>>) {
+        let cancelled = Arc::new(std::sync::Mutex::new(vec![]));
+        let prepare = move || async move {
+            if !empty_map {
+                return StatusCode::NO_CONTENT.into_response();
+            }
+            Json(PrepareUploadResponseDto {
+                session_id: SESSION_ID.to_owned(),
+                files: HashMap::new(),
+                filtered: HashMap::new(),
+                final_names: HashMap::new(),
+            })
+            .into_response()
+        };
+        let cancel = {
+            let cancelled = cancelled.clone();
+            move |Query(query): Query<HashMap<String, String>>| async move {
+                cancelled.lock().unwrap().push(query["sessionId"].clone());
+                StatusCode::OK
+            }
+        };
+        let router = Router::new()
+            .route(&ApiRoute::PrepareUpload.v2(), post(prepare))
+            .route(&ApiRoute::Cancel.v2(), post(cancel));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let device = device("local", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (device, cancelled)
+    }
+
+    #[tokio::test]
+    async fn test_nothing_selected() {
+        // accepted with everything deselected: the session is closed, every file skipped
+        let (device, cancelled) = declining_receiver(true).await;
+        let mut files = text_files();
+        files.add_text("world", true);
+        let mut session = SendSession::new(&device, device.clone(), &files);
+        let mut progress = session.progress();
+        let cancel = CancellationToken::new();
+        let upload = session.upload(None, &cancel);
+        let (sent, events) = join(upload, async {
+            let mut events = vec![];
+            while let Some(event) = progress.recv().await {
+                events.push(event);
+            }
+            events
+        })
+        .await;
+        assert!(matches!(sent, Err(Error::Send(SendError::NothingSelected))));
+        assert_eq!(*cancelled.lock().unwrap(), [SESSION_ID]);
+        let skipped = events
+            .iter()
+            .filter(|event| event.outcome() == Some(FileStatus::Skipped))
+            .count();
+        assert_eq!(skipped, 2);
+        assert_eq!(events.last(), Some(&ProgressEvent::SessionEnded));
+
+        // 204 issues no session, there is nothing to cancel
+        let (device, cancelled) = declining_receiver(false).await;
+        let sent = SendSession::new(&device, device.clone(), &files)
+            .upload(None, &cancel)
+            .await;
+        assert!(matches!(sent, Err(Error::Send(SendError::NothingSelected))));
+        assert!(cancelled.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let data = csv();
+        let (port, _) = mock_receiver(None).await;
+        let device = device("local", port);
+        let path = std::env::temp_dir().join(format!("{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &data).unwrap();
+        let mut files = text_files();
+        files.add_file(&path, None).unwrap();
+        let csv_id = files
+            .values()
+            .find(|file| file.path.is_some())
+            .map(|file| file.file.id.clone())
+            .unwrap();
+
+        let mut session = SendSession::new(&device, device.clone(), &files);
+        let mut progress = session.progress();
+        let cancel = CancellationToken::new();
+        let upload = session.upload(Some(idle_state()), &cancel);
+        let (sent, events) = join(upload, async {
+            let mut events = vec![];
+            while let Some(event) = progress.recv().await {
+                events.push(event);
+            }
+            events
+        })
+        .await;
+        sent.unwrap();
+        std::fs::remove_file(path).ok();
+
+        // positions grow until the file ends, the session ends last
+        assert_eq!(events.last(), Some(&ProgressEvent::SessionEnded));
+        let csv: Vec<_> = events
+            .iter()
+            .filter(|event| event.file_id() == Some(csv_id.as_str()))
+            .collect();
+        assert!(csv.windows(2).all(|w| w[0].position() < w[1].position()));
+        let (last, positions) = csv.split_last().unwrap();
+        assert!(positions
+            .iter()
+            .all(|event| matches!(event, ProgressEvent::Position { .. })));
+        assert_eq!(last.outcome(), Some(FileStatus::Finished));
+        assert_eq!(last.position(), data.len() as u64);
+        let text = events
+            .iter()
+            .find(|event| event.file_id().is_some_and(|id| id != csv_id))
+            .unwrap();
+        assert_eq!(text.outcome(), Some(FileStatus::Finished));
+        assert_eq!(text.position(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_compression_acknowledged() {
+        let data = csv();
+        let (port, uploads) = mock_receiver(Some("zstd")).await;
+        send_csv(&device("local", port), idle_state(), &data).await;
+
+        let (encoding, body) = uploads.lock().unwrap().pop().unwrap();
+        assert_eq!(encoding.as_deref(), Some("zstd"));
+        assert!(body.len() < data.len() / 10);
+        let mut decompressed = vec![];
+        Compression::Zstd
+            .decoder(std::io::Cursor::new(body))
+            .read_to_end(&mut decompressed)
+            .await
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_round_trip() {
+        let data = csv();
+        let mut receiver = TestReceiver::start().await;
+
+        send_csv(&receiver.device(), receiver.state.clone(), &data).await;
+
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert_eq!(report.finished(), 1);
+        assert_eq!(report.total_bytes, data.len() as u64);
+        let path = report.files[0].path.clone().unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), data);
+        receiver.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_send_and_receive() {
+        const FILES: usize = 20;
+
+        let source = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&source).unwrap();
+
+        let mut files = SendingFiles::default();
+        for i in 0..FILES {
+            let path = source.join(format!("{}.bin", i));
+            std::fs::write(&path, vec![i as u8; 256 * 1024]).unwrap();
+            files.add_file(&path, None).unwrap();
+        }
+
+        let mut receiver = TestReceiver::start().await;
+        let state = receiver.state.clone();
+        // send to ourselves, so the same server state receives while sending
+        let device = receiver.device();
+
+        let running = Arc::new(AtomicBool::new(true));
+        let prober = {
+            let state = state.clone();
+            let running = running.clone();
+            tokio::spawn(async move {
+                let mut max_wait = Duration::ZERO;
+                while running.load(Ordering::Relaxed) {
+                    let instant = Instant::now();
+                    drop(state.lock().await);
+                    max_wait = max_wait.max(instant.elapsed());
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                max_wait
+            })
+        };
+
+        SendSession::new(&device, device.clone(), &files)
+            .upload(Some(state.clone()), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        running.store(false, Ordering::Relaxed);
+        let max_wait = prober.await.unwrap();
+        assert!(max_wait < Duration::from_millis(500), "{:?}", max_wait);
+        for i in 0..FILES {
+            let data = std::fs::read(receiver.destination.join(format!("{}.bin", i))).unwrap();
+            assert_eq!(data, vec![i as u8; 256 * 1024]);
+        }
+        assert!(state.lock().await.send_sessions.is_empty());
+
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert_eq!(report.finished(), FILES);
+        assert_eq!(report.total_bytes, (FILES * 256 * 1024) as u64);
+        assert!(report
+            .files
+            .iter()
+            .all(|f| f.path.is_some() && f.duration_secs.is_some()));
+        receiver.stop().await;
+        std::fs::remove_dir_all(source).ok();
+    }
+
+    /// A quick-saving receiver and the device announcing it as `alias`.
+    async fn quick_save_peer(alias: &str) -> (TestReceiver, Device) {
+        let receiver = TestReceiver::start().await;
+        let device = device(alias, receiver.port());
+        (receiver, device)
+    }
+
+    #[tokio::test]
+    async fn test_send_both_ways() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let (receiver_a, device_a) = quick_save_peer("a").await;
+        let (receiver_b, device_b) = quick_save_peer("b").await;
+
+        let files = |name: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![7u8; 512 * 1024]).unwrap();
+            let mut files = SendingFiles::default();
+            files.add_file(&path, None).unwrap();
+            files
+        };
+        let (files_a, files_b) = (files("from-a.bin"), files("from-b.bin"));
+        let upload =
+            |from: &Device, to: &Device, files: &SendingFiles, state: &MutexServerState| {
+                let session = SendSession::new(from, to.clone(), files);
+                let state = state.clone();
+                async move { session.upload(Some(state), &CancellationToken::new()).await }
+            };
+
+        // each server receives from the peer it is uploading to
+        let both = join(
+            upload(&device_a, &device_b, &files_a, &receiver_a.state),
+            upload(&device_b, &device_a, &files_b, &receiver_b.state),
+        );
+        let (a, b) = tokio::time::timeout(Duration::from_secs(10), both)
+            .await
+            .expect("sends in both directions block each other");
+        a.unwrap();
+        b.unwrap();
+        assert!(receiver_b.destination.join("from-a.bin").exists());
+        assert!(receiver_a.destination.join("from-b.bin").exists());
+
+        receiver_a.stop().await;
+        receiver_b.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_final_names() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.collision_policy = CollisionPolicy::Rename;
+        })
+        .await;
+        let destination = receiver.destination.clone();
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("photo.jpg"), "taken").unwrap();
+
+        let mut files = SendingFiles::default();
+        for name in ["photo.jpg", "notes.md"] {
+            std::fs::write(dir.join(name), name).unwrap();
+            files.add_file(dir.join(name), None).unwrap();
+        }
+        let session = SendSession::new(&device("local", 0), receiver.device(), &files);
+        let sent = session
+            .upload(None, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        // the collision renamed the photo on the receiver, the report has its name there
+        let final_name = |name: &str| {
+            let file = sent.values().find(|f| f.file.file_name == name);
+            file.unwrap().final_name.clone()
+        };
+        assert_eq!(final_name("photo.jpg").as_deref(), Some("photo (1).jpg"));
+        assert_eq!(final_name("notes.md"), None);
+        assert_eq!(
+            std::fs::read(destination.join("photo (1).jpg")).unwrap(),
+            b"photo.jpg"
         );
-        Ok(())
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    /// Forwards connections to `port`, closing the first connection that carries an
+    /// upload once the receiver answers it, like a link failing at the last moment.
+    async fn dropping_proxy(port: u16) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+        let dropped = Arc::new(AtomicBool::new(false));
+        tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let server = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+                let dropped = dropped.clone();
+                tokio::spawn(async move {
+                    let (mut client_read, mut client_write) = client.into_split();
+                    let (mut server_read, mut server_write) = server.into_split();
+                    let uploading = Arc::new(AtomicBool::new(false));
+                    let upstream = {
+                        let uploading = uploading.clone();
+                        async move {
+                            let mut buf = vec![0u8; 64 * 1024];
+                            while let Ok(len @ 1..) = client_read.read(&mut buf).await {
+                                if buf[..len].windows(8).any(|w| w == b"/upload?") {
+                                    uploading.store(true, Ordering::SeqCst);
+                                }
+                                if server_write.write_all(&buf[..len]).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    };
+                    let downstream = async move {
+                        let mut buf = vec![0u8; 64 * 1024];
+                        while let Ok(len @ 1..) = server_read.read(&mut buf).await {
+                            if uploading.load(Ordering::SeqCst)
+                                && !dropped.swap(true, Ordering::SeqCst)
+                            {
+                                break;
+                            }
+                            if client_write.write_all(&buf[..len]).await.is_err() {
+                                break;
+                            }
+                        }
+                    };
+                    select(Box::pin(upstream), Box::pin(downstream)).await;
+                });
+            }
+        });
+        proxy_port
+    }
+
+    #[tokio::test]
+    async fn test_lost_upload_response() {
+        let data = csv();
+        let mut receiver = TestReceiver::start().await;
+        let device = device("local", dropping_proxy(receiver.port()).await);
+
+        let path = std::env::temp_dir().join(format!("{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &data).unwrap();
+        let mut files = SendingFiles::default();
+        files.add_file(&path, None).unwrap();
+        let sent = SendSession::new(&device, device.clone(), &files)
+            .upload(None, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        // the receiver confirmed the file when asked again, both sides agree
+        assert!(sent.values().all(|f| f.status == FileStatus::Finished));
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert_eq!(report.finished(), 1);
+        let saved = report.files[0].path.clone().unwrap();
+        assert_eq!(std::fs::read(saved).unwrap(), data);
+
+        receiver.stop().await;
+        std::fs::remove_file(path).ok();
     }
 }
@@ -0,0 +1,211 @@
+use std::{fmt, net::IpAddr, time::Duration};
+
+use localsend_proto::Device;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+
+use super::SendError;
+
+/// A device to send to as given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "by", content = "value", rename_all = "camelCase")]
+pub enum Target {
+    /// Matched case-insensitively, a trailing `*` matches any rest
+    Alias(String),
+    Fingerprint(String),
+    Ip(IpAddr),
+}
+
+impl Target {
+    pub fn matches(&self, device: &Device) -> bool {
+        match self {
+            Target::Alias(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => device
+                    .alias
+                    .to_lowercase()
+                    .starts_with(&prefix.to_lowercase()),
+                None => device.alias.to_lowercase() == pattern.to_lowercase(),
+            },
+            Target::Fingerprint(fingerprint) => {
+                device.fingerprint.eq_ignore_ascii_case(fingerprint)
+            }
+            Target::Ip(ip) => device.ip.parse() == Ok(*ip),
+        }
+    }
+
+    /// Picks the only device of `devices` matching this target.
+    ///
+    /// Several matches fail with [`SendError::AmbiguousTarget`] rather than picking
+    /// whichever device answered first.
+    pub fn resolve(&self, devices: &[Device]) -> Result<Device, SendError> {
+        let mut candidates: Vec<Device> = devices
+            .iter()
+            .filter(|device| self.matches(device))
+            .cloned()
+            .collect();
+        match candidates.len() {
+            0 => Err(SendError::DeviceNotFound(self.to_string())),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(SendError::AmbiguousTarget(candidates)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Alias(alias) => f.write_str(alias),
+            Target::Fingerprint(fingerprint) => write!(f, "fingerprint {}", fingerprint),
+            Target::Ip(ip) => write!(f, "ip {}", ip),
+        }
+    }
+}
+
+/// How long [`check_reachable`] waits for a connection.
+pub const PRECHECK_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Connects to the http port of `device`, failing fast when its announcement is stale.
+///
+/// Otherwise the first request only fails after the much longer connect timeout.
+pub async fn check_reachable(device: &Device) -> Result<(), SendError> {
+    let addr = (device.ip.as_str(), device.port);
+    match tokio::time::timeout(PRECHECK_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Ok(()),
+        result => {
+            log::debug!("{:?} is not reachable: {:?}", device.alias, result);
+            Err(SendError::TargetUnreachable {
+                device: Box::new(device.clone()),
+            })
+        }
+    }
+}
+
+/// Describes a host target no address of answered, naming the addresses tried.
+pub(crate) fn describe_host_attempt(host: &str, port: u16, tried: &[IpAddr]) -> String {
+    match tried.len() {
+        0 => format!("{} resolved to no addresses", host),
+        count => format!(
+            "{} resolved to {} address{}, none reachable on port {} (tried {})",
+            host,
+            count,
+            if count == 1 { "" } else { "es" },
+            port,
+            tried
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Lists devices sharing an alias so that one of them can be chosen by fingerprint.
+pub(crate) fn describe_candidates(devices: &[Device]) -> String {
+    devices
+        .iter()
+        .map(|device| {
+            format!(
+                "{} (fingerprint {}, {})",
+                device.alias, device.fingerprint, device.ip
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::{fixtures, Device};
+
+    use crate::send::SendError;
+
+    use super::{check_reachable, Target};
+
+    fn device(alias: &str, index: usize) -> Device {
+        Device {
+            ip: format!("192.168.1.{}", index),
+            fingerprint: format!("fingerprint-{}", index),
+            ..fixtures::device(alias, 53317)
+        }
+    }
+
+    fn devices() -> Vec<Device> {
+        vec![
+            device("Pixel 7", 1),
+            device("pixel 7", 2),
+            device("Pixel 8", 3),
+            device("Laptop", 4),
+        ]
+    }
+
+    fn resolve(target: Target) -> Result<Device, SendError> {
+        target.resolve(&devices())
+    }
+
+    #[test]
+    fn test_resolve_alias() {
+        assert_eq!(
+            resolve(Target::Alias("LAPTOP".into())).unwrap(),
+            device("Laptop", 4)
+        );
+        assert_eq!(
+            resolve(Target::Alias("pixel 8".into())).unwrap(),
+            device("Pixel 8", 3)
+        );
+        assert_eq!(
+            resolve(Target::Alias("lap*".into())).unwrap(),
+            device("Laptop", 4)
+        );
+        assert!(matches!(
+            resolve(Target::Alias("Phone".into())),
+            Err(SendError::DeviceNotFound(alias)) if alias == "Phone"
+        ));
+        // the star only globs at the end
+        assert!(resolve(Target::Alias("*top".into())).is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_alias() {
+        let Err(SendError::AmbiguousTarget(candidates)) = resolve(Target::Alias("Pixel 7".into()))
+        else {
+            panic!("expected an ambiguous target");
+        };
+        assert_eq!(candidates, vec![device("Pixel 7", 1), device("pixel 7", 2)]);
+
+        let error = resolve(Target::Alias("pixel*".into())).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Several devices match, choose one with --to-fingerprint: \
+            Pixel 7 (fingerprint fingerprint-1, 192.168.1.1), \
+            pixel 7 (fingerprint fingerprint-2, 192.168.1.2), \
+            Pixel 8 (fingerprint fingerprint-3, 192.168.1.3)"
+        );
+    }
+
+    #[test]
+    fn test_resolve_fingerprint_and_ip() {
+        let target = Target::Fingerprint("FINGERPRINT-2".into());
+        assert_eq!(resolve(target).unwrap(), device("pixel 7", 2));
+        let target = Target::Ip("192.168.1.1".parse().unwrap());
+        assert_eq!(resolve(target).unwrap(), device("Pixel 7", 1));
+
+        let error = resolve(Target::Ip("10.0.0.1".parse().unwrap())).unwrap_err();
+        assert_eq!(error.to_string(), "Device not found: ip 10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_check_reachable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut target = device("Laptop", 1);
+        "127.0.0.1".clone_into(&mut target.ip);
+        target.port = listener.local_addr().unwrap().port();
+        check_reachable(&target).await.unwrap();
+
+        drop(listener);
+        let result = check_reachable(&target).await;
+        assert!(matches!(
+            result,
+            Err(SendError::TargetUnreachable { device }) if *device == target
+        ));
+    }
+}
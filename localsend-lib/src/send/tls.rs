@@ -0,0 +1,200 @@
+use std::{error::Error as StdError, fmt, io, sync::Arc, time::SystemTime};
+
+use localsend_proto::Device;
+use reqwest::Client;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, CertificateError, ClientConfig, ServerName,
+};
+
+use crate::util::hash::FileHash;
+
+use super::{SendError, CLIENT};
+
+/// SHA-256 of a DER encoded certificate, the fingerprint of devices reached over HTTPS.
+pub fn certificate_fingerprint(der: &[u8]) -> String {
+    FileHash::sha256(der).to_string()
+}
+
+/// A client for `target`, accepting only the certificate of its fingerprint over HTTPS.
+///
+/// Plain HTTP targets and `insecure` get the shared client accepting any certificate.
+pub(crate) fn client_for(target: &Device, insecure: bool) -> Client {
+    if !target.https || insecure {
+        return CLIENT.clone();
+    }
+    let verifier = PinnedVerifier {
+        fingerprint: target.fingerprint.clone(),
+    };
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    reqwest::ClientBuilder::new()
+        .use_preconfigured_tls(config)
+        .build()
+        .expect("Failed to create pinned reqwest client")
+}
+
+/// Turns a handshake refused by the pinning into [`SendError::CertificateMismatch`].
+pub(crate) fn pin_error(e: reqwest::Error, target: &Device) -> crate::Error {
+    if is_mismatch(&e) {
+        SendError::CertificateMismatch {
+            device: Box::new(target.clone()),
+        }
+        .into()
+    } else {
+        e.into()
+    }
+}
+
+#[derive(Debug)]
+struct FingerprintMismatch;
+
+impl fmt::Display for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("certificate does not match the fingerprint")
+    }
+}
+
+impl StdError for FingerprintMismatch {}
+
+/// Trusts the one certificate hashing to the fingerprint, self-signed as LocalSend's are.
+struct PinnedVerifier {
+    fingerprint: String,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = certificate_fingerprint(&end_entity.0);
+        if fingerprint.eq_ignore_ascii_case(&self.fingerprint) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        log::warn!(
+            "Certificate {} does not match fingerprint {}",
+            fingerprint,
+            self.fingerprint
+        );
+        let mismatch = CertificateError::Other(Arc::new(FingerprintMismatch));
+        Err(rustls::Error::InvalidCertificate(mismatch))
+    }
+}
+
+fn is_mismatch(e: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(e))) =
+            e.downcast_ref::<rustls::Error>()
+        {
+            if e.is::<FingerprintMismatch>() {
+                return true;
+            }
+        }
+        // the sources of an io::Error skip the error it wraps
+        source = match e.downcast_ref::<io::Error>().and_then(io::Error::get_ref) {
+            Some(inner) => Some(inner),
+            None => e.source(),
+        };
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use localsend_proto::{fixtures, Device};
+    use rustls::{Certificate, PrivateKey, ServerConfig};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{certificate_fingerprint, client_for, pin_error};
+    use crate::{send::SendError, Error};
+
+    /// Answers every request with "ok" over TLS with a new self-signed certificate.
+    async fn https_server() -> (SocketAddr, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let der = cert.serialize_der().unwrap();
+        let fingerprint = certificate_fingerprint(&der);
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(der)],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // refused handshakes end here
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    // the requests have no body
+                    let (mut request, mut buf) = (vec![], [0; 1024]);
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(len) => request.extend_from_slice(&buf[..len]),
+                        }
+                    }
+                    let response =
+                        "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+                    stream.write_all(response.as_bytes()).await.ok();
+                    stream.shutdown().await.ok();
+                });
+            }
+        });
+        (addr, fingerprint)
+    }
+
+    fn device(addr: SocketAddr, fingerprint: &str) -> Device {
+        Device {
+            ip: addr.ip().to_string(),
+            https: true,
+            fingerprint: fingerprint.to_owned(),
+            ..fixtures::device("receiver", addr.port())
+        }
+    }
+
+    async fn get(target: &Device, insecure: bool) -> crate::Result<String> {
+        let url = format!("https://{}:{}/", target.ip, target.port);
+        let response = client_for(target, insecure)
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| pin_error(e, target))?;
+        Ok(response.text().await?)
+    }
+
+    #[tokio::test]
+    async fn test_certificate_pinning() {
+        let (addr, fingerprint) = https_server().await;
+        let pinned = device(addr, &fingerprint.to_ascii_uppercase());
+        assert_eq!(get(&pinned, false).await.unwrap(), "ok");
+
+        let other = device(addr, &certificate_fingerprint(b"another certificate"));
+        assert!(matches!(
+            get(&other, false).await,
+            Err(Error::Send(SendError::CertificateMismatch { .. }))
+        ));
+        // accepted for debugging
+        assert_eq!(get(&other, true).await.unwrap(), "ok");
+    }
+}
@@ -0,0 +1,798 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures_util::future::{join, select, Either};
+use localsend_proto::Device;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::{ErrorCode, ErrorDto},
+    progress::{ProgressEvent, ProgressStream},
+    receive::{PendingDecider, PendingOffer, ReceiveError, ReceiverStatus},
+    scanner::KnownDevices,
+    send::{send_to, SendingFiles, Target},
+    Result, SettingsLoader,
+};
+
+use super::{reload_settings, MutexServerState};
+
+/// Finished jobs beyond this many are forgotten, oldest first.
+pub const MAX_FINISHED_JOBS: usize = 64;
+
+/// A command for the control socket of a daemon, sent as one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "command",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ControlRequest {
+    /// The devices currently online
+    ListDevices,
+    /// Sends the files at `paths` and the `texts` to every target in the background
+    Send {
+        #[serde(default)]
+        paths: Vec<PathBuf>,
+        #[serde(default)]
+        texts: Vec<String>,
+        targets: Vec<Target>,
+    },
+    /// The send jobs, the receive session and the offer waiting for an answer
+    Sessions,
+    /// Accepts all files of the pending offer or declines it
+    Respond { accept: bool },
+    /// Loads the settings again for the sessions started from now on
+    Reload,
+}
+
+/// The answer to a [`ControlRequest`], one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "result",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ControlResponse {
+    Devices {
+        devices: Vec<Device>,
+    },
+    /// One job per target
+    Queued {
+        job_ids: Vec<String>,
+    },
+    Sessions {
+        jobs: Vec<SendJob>,
+        receiver: ReceiverStatus,
+        pending: Option<Box<PendingOffer>>,
+    },
+    Ok,
+    /// The settings swapped in, see `ServerState::settings_version`
+    Reloaded {
+        settings_version: u64,
+    },
+    Error {
+        error: ErrorDto,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+}
+
+/// Files sent to a single device in the background.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendJob {
+    pub id: String,
+    pub target: Device,
+    pub status: JobStatus,
+    pub total_size: u64,
+    pub total_position: u64,
+    pub error: Option<ErrorDto>,
+}
+
+/// Answers the requests of the control socket with the state of a running server.
+pub struct ControlService {
+    local: Device,
+    state: MutexServerState,
+    devices: KnownDevices,
+    decider: Arc<PendingDecider>,
+    jobs: Arc<Mutex<Vec<SendJob>>>,
+    settings_loader: Option<SettingsLoader>,
+}
+
+impl ControlService {
+    /// Offers to the server of `state` wait for [`ControlRequest::Respond`] from now on,
+    /// unless `Settings::quick_save` accepts them.
+    pub async fn new(local: Device, state: MutexServerState, devices: KnownDevices) -> Self {
+        let decider = Arc::new(PendingDecider::default());
+        state.lock().await.decider = decider.clone();
+        Self {
+            local,
+            state,
+            devices,
+            decider,
+            jobs: Arc::default(),
+            settings_loader: None,
+        }
+    }
+
+    /// Answers [`ControlRequest::Reload`] with the settings of `loader`, without one
+    /// reloading fails.
+    pub fn with_settings_loader(mut self, loader: SettingsLoader) -> Self {
+        self.settings_loader = Some(loader);
+        self
+    }
+
+    pub async fn handle(&self, request: ControlRequest) -> ControlResponse {
+        let result = match request {
+            ControlRequest::ListDevices => Ok(ControlResponse::Devices {
+                devices: self.devices.devices(),
+            }),
+            ControlRequest::Send {
+                paths,
+                texts,
+                targets,
+            } => self.send(&paths, &texts, &targets).await,
+            ControlRequest::Sessions => {
+                let receiver = self.state.lock().await.status_tracker.current();
+                Ok(ControlResponse::Sessions {
+                    jobs: self.jobs.lock().unwrap().clone(),
+                    receiver,
+                    pending: self.decider.pending().map(Box::new),
+                })
+            }
+            ControlRequest::Respond { accept } => match self.decider.respond(accept) {
+                true => Ok(ControlResponse::Ok),
+                false => Err(ReceiveError::SessionNotExists.into()),
+            },
+            ControlRequest::Reload => return self.reload().await,
+        };
+        result.unwrap_or_else(|e| ControlResponse::Error { error: e.to_dto() })
+    }
+
+    async fn reload(&self) -> ControlResponse {
+        let result = match &self.settings_loader {
+            Some(loader) => reload_settings(&self.state, loader).await,
+            None => Err("This daemon has nothing to reload its settings from".to_owned()),
+        };
+        match result {
+            Ok(settings_version) => ControlResponse::Reloaded { settings_version },
+            Err(message) => ControlResponse::Error {
+                error: ErrorDto {
+                    code: ErrorCode::InvalidParameters,
+                    message,
+                    file_id: None,
+                    status_code: None,
+                },
+            },
+        }
+    }
+
+    async fn send(
+        &self,
+        paths: &[PathBuf],
+        texts: &[String],
+        targets: &[Target],
+    ) -> Result<ControlResponse> {
+        let devices = self.devices.devices();
+        let targets = targets
+            .iter()
+            .map(|target| target.resolve(&devices))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let preview_policy = self.state.lock().await.settings.text_preview.clone();
+        let mut files = SendingFiles::default().with_preview_policy(preview_policy);
+        for path in paths {
+            if path.is_dir() {
+                files.add_dir(path)?;
+            } else {
+                files.add_file(path, None)?;
+            }
+        }
+        for text in texts {
+            files.add_text(text, true);
+        }
+        if targets.is_empty() || files.is_empty() {
+            return Err(ReceiveError::InvalidParameters.into());
+        }
+
+        let cancel = self.state.lock().await.cancel.clone();
+        let job_ids = targets
+            .into_iter()
+            .map(|target| self.spawn_job(target, files.clone(), &cancel))
+            .collect();
+        Ok(ControlResponse::Queued { job_ids })
+    }
+
+    fn spawn_job(&self, target: Device, files: SendingFiles, cancel: &CancellationToken) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let job = SendJob {
+            id: id.clone(),
+            target: target.clone(),
+            status: JobStatus::Running,
+            total_size: files.values().map(|file| file.file.size).sum(),
+            total_position: 0,
+            error: None,
+        };
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.push(job);
+            let finished = jobs
+                .iter()
+                .filter(|job| job.status != JobStatus::Running)
+                .count();
+            let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+            jobs.retain(|job| {
+                let forget = excess > 0 && job.status != JobStatus::Running;
+                excess -= forget as usize;
+                !forget
+            });
+        }
+
+        let local = self.local.clone();
+        let state = self.state.clone();
+        let jobs = self.jobs.clone();
+        let cancel = cancel.child_token();
+        let job_id = id.clone();
+        let update = move |update: &dyn Fn(&mut SendJob)| {
+            if let Some(job) = jobs.lock().unwrap().iter_mut().find(|j| j.id == job_id) {
+                update(job);
+            }
+        };
+        tokio::spawn(async move {
+            let (progress_tx, mut progress) = ProgressStream::channel();
+            let upload = send_to(
+                &target,
+                &local,
+                &files,
+                Some(state),
+                Some(progress_tx),
+                &cancel,
+            );
+            let progress = async {
+                let mut positions = HashMap::new();
+                while let Some(event) = progress.recv().await {
+                    let (ProgressEvent::Position {
+                        file_id, position, ..
+                    }
+                    | ProgressEvent::Finished {
+                        file_id, position, ..
+                    }) = event
+                    else {
+                        continue;
+                    };
+                    positions.insert(file_id, position);
+                    let total_position = positions.values().sum();
+                    update(&|job| job.total_position = total_position);
+                }
+            };
+            let (result, _) = join(upload, progress).await;
+            match result {
+                Ok(()) => update(&|job| job.status = JobStatus::Finished),
+                Err(e) => {
+                    log::warn!("Sending to {:?} failed: {}", target.alias, e);
+                    let error = e.to_dto();
+                    update(&|job| {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(error.clone());
+                    });
+                }
+            }
+        });
+        id
+    }
+}
+
+/// Where the daemon listens unless told otherwise.
+pub fn default_control_path() -> PathBuf {
+    if cfg!(windows) {
+        return PathBuf::from(r"\\.\pipe\localsend");
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("localsend.sock"),
+        None => private_dir().join("localsend.sock"),
+    }
+}
+
+/// A directory of the current user below the temporary directory, created with
+/// mode 0700 by [start_control_server].
+#[cfg(unix)]
+fn private_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("localsend-{}", current_uid()))
+}
+
+#[cfg(not(unix))]
+fn private_dir() -> PathBuf {
+    std::env::temp_dir()
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Creates `dir` only its owner may enter unless it exists, and refuses it if
+/// another user could swap the socket inside.
+#[cfg(unix)]
+fn prepare_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    let metadata = std::fs::metadata(dir)?;
+    // like /tmp, a directory anyone may write to is fine if it is sticky
+    let shared = metadata.mode() & 0o022 != 0 && metadata.mode() & 0o1000 == 0;
+    if shared || (metadata.uid() != current_uid() && metadata.uid() != 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{:?} may be changed by other users", dir),
+        ));
+    }
+    Ok(())
+}
+
+/// Serves the control protocol on `path` until `cancel` is cancelled.
+///
+/// On unix `path` is a socket only its owner may connect to, which is all the
+/// authentication there is. On windows it is the name of a named pipe.
+pub async fn start_control_server(
+    path: &Path,
+    service: Arc<ControlService>,
+    cancel: &CancellationToken,
+) -> io::Result<JoinHandle<()>> {
+    let task = listen(path.to_path_buf(), service, cancel.clone())?;
+    log::debug!("control server listening on {:?}", path);
+    Ok(task)
+}
+
+#[cfg(unix)]
+fn listen(
+    path: PathBuf,
+    service: Arc<ControlService>,
+    cancel: CancellationToken,
+) -> io::Result<JoinHandle<()>> {
+    use std::{
+        fs::Permissions,
+        os::unix::fs::{DirBuilderExt, PermissionsExt},
+    };
+
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{:?} is used by another daemon", path),
+        ));
+    }
+    prepare_dir(path.parent().unwrap_or(Path::new(".")))?;
+    // bound in a directory only we may enter first, whatever the umask the socket
+    // is never reachable before its permissions are set
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let staging = path.with_file_name(format!(".{}.{}", file_name, std::process::id()));
+    std::fs::remove_dir_all(&staging).ok();
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let bound = (|| {
+        let socket = staging.join(&*file_name);
+        let listener = tokio::net::UnixListener::bind(&socket)?;
+        std::fs::set_permissions(&socket, Permissions::from_mode(0o600))?;
+        std::fs::rename(&socket, &path)?;
+        Ok::<_, io::Error>(listener)
+    })();
+    std::fs::remove_dir_all(&staging).ok();
+    let listener = bound?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            let accepted = select(Box::pin(cancel.cancelled()), Box::pin(listener.accept())).await;
+            match accepted {
+                Either::Left(_) => break,
+                Either::Right((Ok((stream, _)), _)) => {
+                    tokio::spawn(serve_connection(stream, service.clone()));
+                }
+                Either::Right((Err(e), _)) => log::warn!("Failed to accept control client: {}", e),
+            }
+        }
+        std::fs::remove_file(&path).ok();
+    }))
+}
+
+#[cfg(windows)]
+fn listen(
+    path: PathBuf,
+    service: Arc<ControlService>,
+    cancel: CancellationToken,
+) -> io::Result<JoinHandle<()>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&path)?;
+    Ok(tokio::spawn(async move {
+        loop {
+            let connected = select(Box::pin(cancel.cancelled()), Box::pin(server.connect())).await;
+            match connected {
+                Either::Left(_) => break,
+                Either::Right((Ok(()), _)) => {}
+                Either::Right((Err(e), _)) => {
+                    log::warn!("Failed to accept control client: {}", e);
+                    continue;
+                }
+            }
+            // the next client connects to a new instance of the pipe
+            let next = match ServerOptions::new().create(&path) {
+                Ok(next) => next,
+                Err(e) => {
+                    log::error!("Failed to create control pipe {:?}: {}", path, e);
+                    break;
+                }
+            };
+            let client = std::mem::replace(&mut server, next);
+            tokio::spawn(serve_connection(client, service.clone()));
+        }
+    }))
+}
+
+async fn serve_connection(stream: impl AsyncRead + AsyncWrite, service: Arc<ControlService>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => service.handle(request).await,
+            Err(e) => ControlResponse::Error {
+                error: ErrorDto {
+                    code: ErrorCode::InvalidParameters,
+                    message: format!("Invalid request: {}", e),
+                    file_id: None,
+                    status_code: None,
+                },
+            },
+        };
+        let Ok(mut json) = serde_json::to_string(&response) else {
+            break;
+        };
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// A connection to the control socket of a running daemon.
+pub struct ControlClient {
+    reader: BufReader<Box<dyn AsyncRead + Send + Unpin>>,
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl ControlClient {
+    pub async fn connect(path: &Path) -> io::Result<Self> {
+        #[cfg(unix)]
+        let stream = {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            // whoever listens gets the requests, it must be the daemon of this user
+            if stream.peer_cred()?.uid() != current_uid() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{:?} is served by another user", path),
+                ));
+            }
+            stream
+        };
+        #[cfg(windows)]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(path)?;
+        let (reader, writer) = tokio::io::split(stream);
+        Ok(Self {
+            reader: BufReader::new(Box::new(reader)),
+            writer: Box::new(writer),
+        })
+    }
+
+    pub async fn request(&mut self, request: &ControlRequest) -> io::Result<ControlResponse> {
+        let mut json = serde_json::to_string(request)?;
+        json.push('\n');
+        self.writer.write_all(json.as_bytes()).await?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use localsend_proto::fixtures::device;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        error::ErrorCode,
+        receive::ReceiverStatus,
+        scanner::{DeviceEvent, KnownDevices},
+        send::{send_text_to, Target},
+        server::{ServerMessage, ServerState},
+        test_util::TestReceiver,
+        Settings, SettingsLoader,
+    };
+
+    use super::{
+        start_control_server, ControlClient, ControlRequest, ControlResponse, ControlService,
+        JobStatus,
+    };
+
+    struct TestDaemon {
+        receiver: TestReceiver,
+        devices: KnownDevices,
+        client: ControlClient,
+        /// Holds the control socket and the files to send
+        dir: PathBuf,
+    }
+
+    impl TestDaemon {
+        async fn start(quick_save: bool) -> Self {
+            let receiver =
+                TestReceiver::start_with(|state| state.settings.quick_save = quick_save).await;
+            let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            let devices = KnownDevices::default();
+            let local = device("daemon", receiver.port());
+            let service = ControlService::new(local, receiver.state.clone(), devices.clone()).await;
+            let path = dir.join("control.sock");
+            start_control_server(&path, Arc::new(service), &receiver.cancel)
+                .await
+                .unwrap();
+            let client = ControlClient::connect(&path).await.unwrap();
+            Self {
+                receiver,
+                devices,
+                client,
+                dir,
+            }
+        }
+
+        async fn request(&mut self, request: ControlRequest) -> ControlResponse {
+            self.client.request(&request).await.unwrap()
+        }
+
+        async fn stop(self) {
+            self.receiver.stop().await;
+            std::fs::remove_dir_all(self.dir).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_through_socket() {
+        let mut receiver = TestDaemon::start(true).await;
+        let mut daemon = TestDaemon::start(true).await;
+        let target = device("receiver", receiver.receiver.port());
+        daemon.devices.apply(DeviceEvent::Found(target.clone()));
+
+        let response = daemon.request(ControlRequest::ListDevices).await;
+        match response {
+            ControlResponse::Devices { devices } => assert_eq!(devices, vec![target]),
+            response => panic!("unexpected response: {:?}", response),
+        }
+
+        let path = daemon.dir.join("a.txt");
+        std::fs::write(&path, b"data").unwrap();
+        let response = daemon
+            .request(ControlRequest::Send {
+                paths: vec![path],
+                texts: vec![],
+                targets: vec![Target::Alias("receiver".to_owned())],
+            })
+            .await;
+        let ControlResponse::Queued { job_ids } = response else {
+            panic!("unexpected response: {:?}", response);
+        };
+        assert_eq!(job_ids.len(), 1);
+
+        let message =
+            tokio::time::timeout(Duration::from_secs(5), receiver.receiver.server_rx.recv());
+        assert!(matches!(
+            message.await,
+            Ok(Some(ServerMessage::SessionFinished(_)))
+        ));
+        assert_eq!(
+            std::fs::read(receiver.receiver.destination.join("a.txt")).unwrap(),
+            b"data"
+        );
+        let job = loop {
+            let response = daemon.request(ControlRequest::Sessions).await;
+            let ControlResponse::Sessions { jobs, .. } = response else {
+                panic!("unexpected response: {:?}", response);
+            };
+            if jobs[0].status != JobStatus::Running {
+                break jobs[0].clone();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        assert_eq!(job.id, job_ids[0]);
+        assert_eq!(job.status, JobStatus::Finished);
+        assert_eq!((job.total_position, job.total_size), (4, 4));
+
+        // targets are resolved when queueing
+        let response = daemon
+            .request(ControlRequest::Send {
+                paths: vec![],
+                texts: vec!["hello".to_owned()],
+                targets: vec![Target::Alias("phone".to_owned())],
+            })
+            .await;
+        match response {
+            ControlResponse::Error { error } => assert_eq!(error.code, ErrorCode::DeviceNotFound),
+            response => panic!("unexpected response: {:?}", response),
+        }
+
+        daemon.stop().await;
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_respond_to_offer() {
+        let mut daemon = TestDaemon::start(false).await;
+        let target = daemon.receiver.device();
+        let sender =
+            tokio::spawn(async move { send_text_to(&target, &device("sender", 0), "hello").await });
+
+        let offer = loop {
+            let response = daemon.request(ControlRequest::Sessions).await;
+            let ControlResponse::Sessions {
+                pending, receiver, ..
+            } = response
+            else {
+                panic!("unexpected response: {:?}", response);
+            };
+            assert_eq!(receiver, ReceiverStatus::Idle);
+            if let Some(offer) = pending {
+                break offer;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        assert_eq!(offer.sender.alias, "sender");
+        assert_eq!(offer.files.len(), 1);
+
+        let response = daemon
+            .request(ControlRequest::Respond { accept: true })
+            .await;
+        assert!(matches!(response, ControlResponse::Ok));
+        sender.await.unwrap().unwrap();
+        let message =
+            tokio::time::timeout(Duration::from_secs(5), daemon.receiver.server_rx.recv());
+        assert!(matches!(
+            message.await,
+            Ok(Some(
+                ServerMessage::TextReceived(_) | ServerMessage::SessionFinished(_)
+            ))
+        ));
+
+        // nothing is pending anymore
+        let response = daemon
+            .request(ControlRequest::Respond { accept: false })
+            .await;
+        assert!(matches!(response, ControlResponse::Error { .. }));
+        daemon.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request() {
+        let daemon = TestDaemon::start(true).await;
+        let path = daemon.dir.join("control.sock");
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let mut lines = {
+            use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+            stream
+                .write_all(b"{\"command\":\"reboot\"}\n")
+                .await
+                .unwrap();
+            BufReader::new(stream).lines()
+        };
+        let line = lines.next_line().await.unwrap().unwrap();
+        let response: ControlResponse = serde_json::from_str(&line).unwrap();
+        match response {
+            ControlResponse::Error { error } => {
+                assert_eq!(error.code, ErrorCode::InvalidParameters)
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        daemon.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_socket_directory() {
+        use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+        let (server_tx, _server_rx) = tokio::sync::mpsc::channel(1);
+        let (_, client_rx) = tokio::sync::mpsc::channel(1);
+        let state = Arc::new(tokio::sync::Mutex::new(ServerState::new(
+            server_tx, client_rx,
+        )));
+        let service = Arc::new(
+            ControlService::new(device("daemon", 0), state, KnownDevices::default()).await,
+        );
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let cancel = CancellationToken::new();
+
+        // a missing directory is created for the owner only
+        let path = dir.join("run").join("control.sock");
+        let server = start_control_server(&path, service.clone(), &cancel)
+            .await
+            .unwrap();
+        let mode = std::fs::metadata(dir.join("run"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let names: Vec<_> = std::fs::read_dir(dir.join("run"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["control.sock"]);
+        ControlClient::connect(&path).await.unwrap();
+        cancel.cancel();
+        server.await.unwrap();
+
+        // other users could replace the socket in a directory anyone may write to
+        let shared = dir.join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, Permissions::from_mode(0o777)).unwrap();
+        let result = start_control_server(
+            &shared.join("control.sock"),
+            service,
+            &CancellationToken::new(),
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let (server_tx, _server_rx) = tokio::sync::mpsc::channel(1);
+        let (_, client_rx) = tokio::sync::mpsc::channel(1);
+        let state = Arc::new(tokio::sync::Mutex::new(ServerState::new(
+            server_tx, client_rx,
+        )));
+        let service =
+            ControlService::new(device("daemon", 0), state.clone(), KnownDevices::default()).await;
+        let response = service.handle(ControlRequest::Reload).await;
+        assert!(matches!(response, ControlResponse::Error { .. }));
+
+        let service = service.with_settings_loader(SettingsLoader::new(|| {
+            Ok(Settings {
+                quick_save: true,
+                ..Settings::default()
+            })
+        }));
+        let response = service.handle(ControlRequest::Reload).await;
+        assert!(matches!(
+            response,
+            ControlResponse::Reloaded {
+                settings_version: 2
+            }
+        ));
+        assert!(state.lock().await.settings.quick_save);
+    }
+}
@@ -1,51 +1,137 @@
-use std::{collections::HashMap, io, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
     body::Body,
-    extract::{ConnectInfo, Query, State},
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, HeaderValue},
     Json,
 };
-use futures_util::{pin_mut, TryStreamExt};
+use futures_util::{
+    future::{select, Either},
+    pin_mut, FutureExt, StreamExt, TryStreamExt,
+};
 use localsend_proto::{
-    dto::{PrepareUploadRequestDto, PrepareUploadResponseDto},
-    DEFAULT_PORT,
+    dto::{
+        FileDto, FileType, PrepareUploadRequestDto, PrepareUploadResponseDto, UploadResponseDto,
+    },
+    Device, Validate, DEFAULT_PORT,
 };
 use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    io::{AsyncRead, AsyncWriteExt},
+    sync::{mpsc::Sender, Mutex, MutexGuard, OwnedSemaphorePermit},
 };
 use tokio_util::io::StreamReader;
 
-use super::MutexServerState;
+use super::{
+    wait_for_turn, CancelledBy, EventBus, MutexServerState, ServerState, SessionEvent, StrictQuery,
+    UploadQuery,
+};
 
 use crate::{
-    receive::{ReceiveError, ReceiveSession, ReceiveSessionStatus, ReceivingFile},
-    send::{FileStatus, SendError, UploadProgress},
-    server::ClientMessage,
-    Result,
+    receive::{
+        check_destination_parent, copy_body, fs_path, is_same_file, place_duplicate,
+        resolve_destination, AcceptAll, Activity, ArchiveFormat, ArchiveWriter, AuditSink,
+        Decision, DedupAction, DedupIndex, FileToken, FinishedSession, FsSink, HookRuns,
+        PreviewFile, Quarantine, RateLimiter, ReceiveDecider, ReceiveError, ReceiveReport,
+        ReceiveSession, ReceiveSessionStatus, ReceiveSink, ReceivedFileInfo, ReceivingFile,
+        SessionJournal,
+    },
+    send::{FileStatus, SendError},
+    server::ServerMessage,
+    util::{
+        compression::{Compression, COMPRESS_HEADER},
+        fs::{
+            is_destination_error, probe_writable, resolve_collision, resolve_session_collision,
+            saved_name, CaseSensitivity, DangerousExtensions, NameRules, SessionNames,
+        },
+        hash::FileHash,
+        inhibit::TransferGuard,
+        note::is_note,
+    },
+    CollisionPolicy, Result, Settings,
 };
 
-pub async fn cancel_v1(State(state): State<MutexServerState>) -> Result<()> {
+pub async fn cancel_v1(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<MutexServerState>,
+) -> Result<()> {
     let mut state = state.lock().await;
-    let session = state.send_session.take().ok_or(SendError::NoPermission)?;
-    session.cancel_by_receiver().await?;
+    if let Some(session) = &state.receive_session {
+        if session.sender.ip == addr.ip().to_string() {
+            let mut session = state
+                .receive_session
+                .take()
+                .ok_or(ReceiveError::SessionNotExists)?;
+            log::info!("Session {} cancelled by sender", session.session_id);
+            session.abort().await;
+            state.events.emit(SessionEvent::SessionCancelled {
+                session_id: session.session_id,
+                by: CancelledBy::Sender,
+            });
+            return Ok(());
+        }
+    }
+    // v1 has no session id, the receiver is identified by its address
+    let session_id = state
+        .send_sessions
+        .values()
+        .find(|session| session.target().ip == addr.ip().to_string())
+        .map(|session| session.session_id.clone())
+        .ok_or(SendError::NoPermission)?;
+    let session = state
+        .send_sessions
+        .remove(&session_id)
+        .ok_or(SendError::NoPermission)?;
+    session.cancel_by_receiver();
     Ok(())
 }
 
 pub async fn cancel_v2(
-    Query(query): Query<HashMap<String, String>>,
+    StrictQuery(query): StrictQuery,
     State(state): State<MutexServerState>,
 ) -> Result<()> {
     let remote_session_id = query.get("sessionId").ok_or(SendError::NoPermission)?;
     log::debug!("remote sessionId: {}", remote_session_id);
     let mut state = state.lock().await;
-    if let Some(session) = &state.send_session {
-        if session.remote_session_id.as_ref() != Some(remote_session_id) {
-            return Err(SendError::NoPermission)?;
+    if let Some(session) = &state.receive_session {
+        if &session.session_id == remote_session_id {
+            let mut session = state
+                .receive_session
+                .take()
+                .ok_or(ReceiveError::SessionNotExists)?;
+            log::info!("Session {} cancelled by sender", session.session_id);
+            session.abort().await;
+            state.events.emit(SessionEvent::SessionCancelled {
+                session_id: session.session_id,
+                by: CancelledBy::Sender,
+            });
+            return Ok(());
         }
     }
-    let session = state.send_session.take().ok_or(SendError::NoPermission)?;
-    session.cancel_by_receiver().await?;
+    let Some(session_id) = state
+        .send_sessions
+        .values()
+        .find(|session| session.remote_session_id.as_ref() == Some(remote_session_id))
+        .map(|session| session.session_id.clone())
+    else {
+        // e.g. a sender closing an offer we answered with 204, there is nothing to cancel
+        log::debug!("No session {} to cancel", remote_session_id);
+        return Ok(());
+    };
+    let session = state
+        .send_sessions
+        .remove(&session_id)
+        .ok_or(SendError::NoPermission)?;
+    session.cancel_by_receiver();
 
     Ok(())
 }
@@ -54,175 +140,1003 @@ pub async fn prepare_upload_v1(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<MutexServerState>,
     Json(dto): Json<PrepareUploadRequestDto>,
-) -> Result<Json<HashMap<String, String>>> {
-    let dto = prepare_upload(addr, state, dto).await?;
-    Ok(dto.files.into())
+) -> Result<(HeaderMap, Json<HashMap<String, String>>)> {
+    let (dto, compression) = prepare_upload(addr, state, dto).await?;
+    Ok((compress_headers(compression), dto.files.into()))
 }
 
 pub async fn prepare_upload_v2(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<MutexServerState>,
     Json(dto): Json<PrepareUploadRequestDto>,
-) -> Result<Json<PrepareUploadResponseDto>> {
-    let dto = prepare_upload(addr, state, dto).await?;
-    Ok(dto.into())
+) -> Result<(HeaderMap, Json<PrepareUploadResponseDto>)> {
+    let (dto, compression) = prepare_upload(addr, state, dto).await?;
+    Ok((compress_headers(compression), dto.into()))
+}
+
+/// Acknowledges the compression extension, other clients never see the header.
+fn compress_headers(compression: Option<Compression>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(compression) = compression {
+        headers.insert(
+            COMPRESS_HEADER,
+            HeaderValue::from_static(compression.name()),
+        );
+    }
+    headers
 }
 
 async fn prepare_upload(
     addr: SocketAddr,
     state: MutexServerState,
     dto: PrepareUploadRequestDto,
-) -> Result<PrepareUploadResponseDto> {
+) -> Result<(PrepareUploadResponseDto, Option<Compression>)> {
     log::info!("Client Addr: {}", addr);
+    dto.validate().map_err(ReceiveError::from)?;
 
-    let mut _state = state.try_lock().map_err(|_| ReceiveError::SessionBlocked)?;
-    if _state.receive_session.is_some() {
-        return Err(ReceiveError::SessionBlocked)?;
-    }
+    let Some(mut _state) = wait_for_start(addr, &state, &dto).await? else {
+        return extend_session(state, dto).await;
+    };
 
     if dto.files.is_empty() {
         return Err(ReceiveError::EmptyFiles)?;
     }
+    let files = dto.files.into_values().collect();
+    let (files, rejected) = check_structure(&_state.settings, [], files)?;
 
     let settings = &_state.settings;
-    let destination = &settings.destination;
     let quick_save = settings.quick_save;
+    let auto_text_size =
+        Some(settings.auto_accept_text_size).filter(|_| settings.auto_accept_texts);
+    // an audit writes nothing, neither into archives nor quarantines nor the dedup index
+    let audit = settings.audit;
+    let archive_name = settings.archive.clone().filter(|_| !audit);
+    let archive_texts = settings.archive_texts;
+    let collision_policy = settings.collision_policy;
+    // the quarantine saves to disk itself, archives and custom sinks are not previewed
+    let preview_dir = settings
+        .preview_dir
+        .clone()
+        .filter(|_| !quick_save && archive_name.is_none() && settings.sink_factory.is_none())
+        .filter(|_| !audit);
+    let preview_max_size = settings.preview_max_size;
+    // files received before are looked up where they were saved, not in archives or custom sinks
+    let dedup_index = settings
+        .dedup_index
+        .clone()
+        .filter(|_| archive_name.is_none() && settings.sink_factory.is_none() && !audit);
+    let dedup_action = settings.dedup_action;
+    let custom_sink = settings.sink_factory.is_some() || audit;
+    let case_sensitivity = settings.case_sensitivity;
+    let name_rules = settings.name_rules;
+    let name_replacement = settings.name_replacement;
+    let dangerous_extensions = settings.dangerous_extensions.clone();
     let session_id = uuid::Uuid::new_v4().to_string();
+    let sender = dto
+        .info
+        .to_device(addr.ip().to_string(), DEFAULT_PORT, false);
+    // created by the sink on the first write, declined sessions leave nothing behind
+    let destination = resolve_destination(
+        &settings.destination,
+        &sender,
+        &session_id,
+        settings.name_rules,
+        settings.name_replacement,
+    );
 
-    log::info!("Session Id: {}", session_id);
-    log::info!(
-        "Destination Directory: {:?}, Quick Save: {}",
-        destination,
-        quick_save
+    let names = SessionNames::new(
+        case_sensitivity.unwrap_or_else(|| CaseSensitivity::detect_or_native(&destination)),
     );
 
+    log::info!("Session Id: {}", session_id);
+    if audit {
+        log::info!("Auditing, nothing is saved. Quick Save: {}", quick_save);
+    } else {
+        log::info!(
+            "Destination Directory: {:?}, Quick Save: {}",
+            destination,
+            quick_save
+        );
+    }
+
+    // only files saved by the sink are journaled, the quarantine sweeps its own
+    let journal_dir = _state.journal_dir.clone().filter(|_| !custom_sink);
+    let (sink, journal) = session_sink(
+        settings,
+        &session_id,
+        &sender,
+        &destination,
+        &names,
+        journal_dir.as_deref(),
+    );
     let receive_session = ReceiveSession {
         session_id: session_id.clone(),
         status: ReceiveSessionStatus::Waiting,
-        sender: dto
-            .info
-            .to_device(addr.ip().to_string(), DEFAULT_PORT, false),
+        sender,
         files: HashMap::new(),
-        destination_directory: settings.destination.clone(),
+        destination_directory: destination.clone(),
         progress_tx: None,
+        archive: None,
+        print_texts: archive_name.is_some() && !archive_texts,
+        started: None,
+        compression: dto
+            .extension
+            .as_ref()
+            .and_then(|extension| Compression::negotiate(&extension.compress)),
+        last_activity: Activity::default(),
+        cancel: _state.cancel.child_token(),
+        sink,
+        audited: audit,
+        journal,
+        status_tracker: _state.status_tracker.clone(),
+        hooks: HookRuns::default(),
+        quarantine: None,
+        dedup: None,
+        names,
+        rate_limiter: settings.receive_rate_limit.map(RateLimiter::new),
+        destination_lost: None,
+        transfer_guard: None,
+        settings: Arc::new(settings.clone()),
+        settings_version: _state.settings_version,
     };
+    let sender = receive_session.sender.clone();
     _state.receive_session = Some(receive_session);
+    _state.closed |= _state.once;
+    let events = _state.events.clone();
+    events.emit(SessionEvent::ReceiveRequested {
+        session_id: session_id.clone(),
+        sender: sender.clone(),
+        files: files.clone(),
+    });
+    let decider = decider(&_state, &_state.settings);
+    let decision_timeout = _state.settings.decision_timeout;
+    // the session is reserved, other senders are blocked while the decider runs
+    drop(_state);
 
-    struct Guard(MutexServerState);
+    struct Guard(MutexServerState, String);
 
     impl Drop for Guard {
         fn drop(&mut self) {
             let state = self.0.clone();
+            let session_id = std::mem::take(&mut self.1);
             tokio::task::spawn_blocking(move || {
                 let mut state = state.blocking_lock();
                 if let Some(session) = &state.receive_session {
-                    if session.status == ReceiveSessionStatus::Waiting {
+                    if session.session_id == session_id
+                        && session.status == ReceiveSessionStatus::Waiting
+                    {
+                        // the sender gave up on the request before it was answered
                         state.receive_session = None;
+                        state.events.emit(SessionEvent::SessionCancelled {
+                            session_id,
+                            by: CancelledBy::Sender,
+                        });
                     }
                 }
             });
         }
     }
 
-    let _guard = Guard(state.clone());
-
-    let files = dto.files.into_values().collect();
+    let _guard = Guard(state.clone(), session_id.clone());
 
-    let (progress_tx, selection) = if quick_save {
-        (None, Some(files))
+    let offered = files.clone();
+    let mut dedup = dedup_index.map(DedupIndex::load);
+    if let Some(index) = dedup.as_mut() {
+        let pruned = index.prune();
+        if pruned > 0 {
+            log::info!("Dropped {} deleted files from the dedup index", pruned);
+            if let Err(e) = index.save().await {
+                log::warn!("Failed to save dedup index: {}", e);
+            }
+        }
+    }
+    // files received before are not asked about, texts are always shown
+    let mut duplicates = vec![];
+    let files: Vec<FileDto> = match &dedup {
+        Some(index) => files
+            .into_iter()
+            .filter_map(|file| {
+                let existing = file
+                    .hash
+                    .as_deref()
+                    .filter(|_| !is_text_message(&file))
+                    .and_then(|hash| index.lookup(hash, file.size))
+                    .map(Path::to_path_buf);
+                match existing {
+                    Some(existing) => {
+                        duplicates.push((file, existing));
+                        None
+                    }
+                    None => Some(file),
+                }
+            })
+            .collect(),
+        None => files,
+    };
+    // small files are received without asking and reviewed once they arrived
+    let (previewed, files): (Vec<FileDto>, Vec<FileDto>) = match &preview_dir {
+        Some(_) => files
+            .into_iter()
+            .partition(|file| file.size <= preview_max_size && !is_text_message(file)),
+        None => (vec![], files),
+    };
+    // short texts are accepted right away, the decider only sees the remaining files
+    let (auto_texts, files): (Vec<FileDto>, Vec<FileDto>) = match auto_text_size {
+        Some(max_size) => files
+            .into_iter()
+            .partition(|file| is_text_message(file) && !is_note(file) && file.size <= max_size),
+        None => (vec![], files),
+    };
+    let decision = if files.is_empty() {
+        Ok(Decision::Accept(vec![]))
     } else {
-        let tx = _state.server_tx.clone();
-        tx.send(crate::server::ServerMessage::SelectedFiles(files))
+        decide(decider.as_ref(), sender, files, decision_timeout).await
+    };
+    // a directory chosen with the selection replaces the configured one
+    let chosen_destination = match &decision {
+        Ok(Decision::Accept(_)) => decider.destination(),
+        _ => None,
+    };
+    let destination = chosen_destination.clone().unwrap_or(destination);
+    // a missing mount or a full disk fails the session before anything is uploaded,
+    // the directory itself is created by the first file saved
+    let unavailable = match &decision {
+        Ok(Decision::Accept(_)) if !custom_sink => check_destination_parent(&destination).err(),
+        _ => None,
+    };
+    let chosen_names = chosen_destination.as_ref().map(|destination| {
+        SessionNames::new(
+            case_sensitivity.unwrap_or_else(|| CaseSensitivity::detect_or_native(destination)),
+        )
+    });
+    let names = match &chosen_names {
+        Some(names) => names.clone(),
+        None => state
+            .lock()
             .await
-            .unwrap();
-
-        match _state.client_rx.recv().await {
-            None => return Err(ReceiveError::NothingSelected)?,
-            Some(ClientMessage::FilesSelected(progress_tx, files)) => {
-                (Some(progress_tx), Some(files))
-            }
-            Some(ClientMessage::Declined) => (None, None),
+            .receive_session
+            .as_ref()
+            .map(|session| session.names.clone())
+            .ok_or(ReceiveError::InvalidServerState)?,
+    };
+    let duplicates = match &decision {
+        Ok(Decision::Accept(_)) => {
+            let naming = (
+                collision_policy,
+                name_rules,
+                name_replacement,
+                &dangerous_extensions,
+            );
+            place_duplicates(duplicates, &destination, dedup_action, naming, &names).await
         }
+        _ => vec![],
     };
 
+    let mut _state = state.lock().await;
+    let server_tx = _state.server_tx.clone();
     let receive_session = _state
         .receive_session
         .as_mut()
+        .filter(|session| session.session_id == session_id)
         .ok_or(ReceiveError::InvalidServerState)?;
-    receive_session.progress_tx = progress_tx;
 
-    let selection = match selection {
-        Some(selection) => selection,
-        None => {
+    let declined = SessionEvent::ReceiveDeclined {
+        session_id: session_id.clone(),
+    };
+    if let Some(e) = unavailable {
+        log::error!("{}", e);
+        _state.receive_session = None;
+        events.emit(declined);
+        return Err(ReceiveError::DestinationUnavailable(e))?;
+    }
+    let mut selection = match decision {
+        Ok(Decision::Accept(selection)) => selection,
+        // declining the files leaves the texts accepted before
+        Ok(Decision::Decline) if !auto_texts.is_empty() => vec![],
+        Ok(Decision::Timeout) if !auto_texts.is_empty() => {
+            server_tx
+                .try_send(ServerMessage::SelectionTimedOut(session_id.clone()))
+                .ok();
+            vec![]
+        }
+        Ok(Decision::Decline) => {
             _state.receive_session = None;
+            events.emit(declined);
             return Err(ReceiveError::SessionDeclined)?;
         }
+        Ok(Decision::Timeout) => {
+            _state.receive_session = None;
+            events.emit(declined);
+            _state
+                .server_tx
+                .try_send(ServerMessage::SelectionTimedOut(session_id))
+                .ok();
+            return Err(ReceiveError::DecisionTimeout)?;
+        }
+        Err(e) => {
+            _state.receive_session = None;
+            events.emit(declined);
+            return Err(e);
+        }
     };
+    receive_session.progress_tx = decider.progress_tx();
+    selection
+        .retain(|selected| !is_note(selected) && offered.iter().any(|file| file.id == selected.id));
+    if let Some(preview_dir) = preview_dir.filter(|_| !previewed.is_empty()) {
+        let file_ids = previewed.iter().map(|file| file.id.clone()).collect();
+        let quarantine = Quarantine::new(
+            &preview_dir,
+            &session_id,
+            file_ids,
+            name_rules,
+            name_replacement,
+            dangerous_extensions,
+        );
+        log::info!(
+            "Quarantining {} files in {:?}",
+            previewed.len(),
+            quarantine.dir()
+        );
+        receive_session.quarantine = Some(quarantine);
+        selection.extend(previewed);
+    }
+    selection.extend(auto_texts);
 
-    if selection.is_empty() {
+    if selection.is_empty() && duplicates.is_empty() {
         _state.receive_session = None;
+        events.emit(declined);
         return Err(ReceiveError::NothingSelected)?;
     }
+    // the note is not saved, but uploaded to complete the offer for the sender
+    if !selection.is_empty() {
+        selection.extend(offered.iter().filter(|file| is_note(file)).cloned());
+    }
+
+    let chosen = chosen_destination.zip(chosen_names);
+    let journal_dir = journal_dir.as_deref();
+    if let Err(e) = set_up_destination(
+        receive_session,
+        chosen,
+        journal_dir,
+        archive_name.as_deref(),
+        &selection,
+    )
+    .await
+    {
+        _state.receive_session = None;
+        events.emit(SessionEvent::SessionFailed {
+            session_id,
+            reason: format!("Failed to create archive: {}", e),
+        });
+        return Err(ReceiveError::SaveFileFailed)?;
+    }
 
+    events.emit(SessionEvent::ReceiveAccepted {
+        session_id: session_id.clone(),
+        files: selection.clone(),
+    });
+    if let Some(journal) = &receive_session.journal {
+        journal.add_files(&selection);
+    }
     receive_session.status = ReceiveSessionStatus::Sending;
-    receive_session.files = selection
+    receive_session.transfer_guard = Some(TransferGuard::acquire(&format!(
+        "Receiving files from {}",
+        receive_session.sender.alias
+    )));
+    receive_session.started = Some(Instant::now());
+    // waiting for the selection does not count as idle
+    receive_session.last_activity.touch();
+    let (filtered, final_names) =
+        add_files(receive_session, offered, &selection, duplicates, rejected);
+    receive_session.dedup = dedup;
+    receive_session.status_tracker.start(receive_session);
+
+    if selection.is_empty() {
+        // everything offered was received before, the sender has nothing to upload
+        let server_tx = _state.server_tx.clone();
+        let Some(session) = _state.receive_session.take() else {
+            return Err(ReceiveError::InvalidServerState)?;
+        };
+        drop(_state);
+        session.status_tracker.finish();
+        report_finished(&server_tx, &events, session.report()).await;
+        return Err(ReceiveError::NothingSelected)?;
+    }
+
+    let session_id = receive_session.session_id.clone();
+    let compression = receive_session.compression;
+    let files = receive_session
+        .files
+        .iter()
+        .filter_map(|(id, file)| Some((id.clone(), file.token.as_ref()?.as_str().to_owned())))
+        .collect();
+    let dto = PrepareUploadResponseDto {
+        session_id,
+        files,
+        filtered,
+        final_names,
+    };
+
+    Ok((dto, compression))
+}
+
+/// Locks the state for a new session once no other one runs, requests arriving during
+/// a session wait in the queue. `None` when the files extend the session of their sender.
+async fn wait_for_start<'a>(
+    addr: SocketAddr,
+    state: &'a MutexServerState,
+    dto: &PrepareUploadRequestDto,
+) -> Result<Option<MutexGuard<'a, ServerState>>> {
+    let _state = state.lock().await;
+    if let Some(session) = &_state.receive_session {
+        let same_sender = session.sender.ip == addr.ip().to_string()
+            && session.sender.fingerprint == dto.info.fingerprint;
+        if same_sender && _state.settings.allow_session_extend {
+            return Ok(None);
+        }
+    }
+    if _state.closed {
+        return Err(ReceiveError::Closed)?;
+    }
+    // requests queued before go first, even once the session ended
+    if _state.receive_session.is_some() || !_state.session_queue.is_empty() {
+        drop(_state);
+        return Ok(Some(wait_for_turn(state).await?));
+    }
+    Ok(Some(_state))
+}
+
+/// Moves the session to the destination chosen with the selection, if any, and opens
+/// the archive the selected files are saved into there.
+async fn set_up_destination(
+    receive_session: &mut ReceiveSession,
+    chosen: Option<(PathBuf, SessionNames)>,
+    journal_dir: Option<&Path>,
+    archive_name: Option<&Path>,
+    selection: &[FileDto],
+) -> io::Result<()> {
+    let settings = receive_session.settings.clone();
+    if let Some((destination, names)) = chosen {
+        log::info!("Destination Directory: {:?}", destination);
+        // custom sinks and audits keep theirs, they save nothing below a destination
+        if settings.sink_factory.is_none() && !receive_session.audited {
+            (receive_session.sink, receive_session.journal) = session_sink(
+                &settings,
+                &receive_session.session_id,
+                &receive_session.sender,
+                &destination,
+                &names,
+                journal_dir,
+            );
+        }
+        receive_session.destination_directory = destination;
+        receive_session.names = names;
+    }
+
+    let Some(archive_name) = archive_name else {
+        return Ok(());
+    };
+    let archived = selection
+        .iter()
+        .filter(|file| !is_note(file))
+        .any(|file| settings.archive_texts || !is_text_message(file));
+    if !archived {
+        return Ok(());
+    }
+    let path = receive_session.destination_directory.join(archive_name);
+    let path = resolve_collision(path, settings.collision_policy);
+    let format = ArchiveFormat::from_path(&path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unknown archive format"))?;
+    let archive = create_archive(&path, format).await.map_err(|e| {
+        log::error!("Failed to create archive {:?}: {:?}", path, e);
+        e
+    })?;
+    log::info!("Saving files to archive {:?}", path);
+    receive_session.archive = Some(Arc::new(Mutex::new(Some(archive))));
+    Ok(())
+}
+
+/// The sink saving the files of a session below `destination`, with the journal of
+/// the default sink when there is a `journal_dir`.
+fn session_sink(
+    settings: &Settings,
+    session_id: &str,
+    sender: &Device,
+    destination: &Path,
+    names: &SessionNames,
+    journal_dir: Option<&Path>,
+) -> (Arc<dyn ReceiveSink>, Option<SessionJournal>) {
+    if settings.audit {
+        let sink = AuditSink::new(session_id, sender, settings.audit_log.clone());
+        return (Arc::new(sink), None);
+    }
+    if let Some(factory) = &settings.sink_factory {
+        return (factory.create(session_id), None);
+    }
+    let journal = journal_dir.map(|dir| SessionJournal::new(dir, session_id, sender, destination));
+    let sink = FsSink::new(destination, settings.collision_policy)
+        .with_name_rules(settings.name_rules, settings.name_replacement)
+        .with_dangerous_extensions(settings.dangerous_extensions.clone())
+        .with_session_names(names.clone())
+        .with_append_any_type(settings.append_any_type);
+    let sink = match &journal {
+        Some(journal) => sink.with_journal(journal.clone()),
+        None => sink,
+    };
+    (Arc::new(sink), journal)
+}
+
+/// Adds the offered files to the session, with a token for each selected one.
+///
+/// Returns why the others got none and the names files are saved under where they
+/// differ from the offered ones, both by file id.
+fn add_files(
+    receive_session: &mut ReceiveSession,
+    offered: Vec<FileDto>,
+    selection: &[FileDto],
+    duplicates: Vec<ReceivingFile>,
+    rejected: Vec<ReceivingFile>,
+) -> (HashMap<String, String>, HashMap<String, String>) {
+    let token_issuer = &receive_session.settings.token_issuer;
+    receive_session.files = offered
         .into_iter()
         .map(|file| {
-            let token = uuid::Uuid::new_v4().to_string();
-            (
-                file.id.clone(),
-                ReceivingFile {
-                    file: file.clone(),
-                    status: FileStatus::Queue,
-                    token: Some(token),
-                },
-            )
+            let mut receiving_file = ReceivingFile::new(file.clone(), None);
+            if selection.iter().any(|selected| selected.id == file.id) {
+                receiving_file.token = Some(FileToken::new(token_issuer.issue()));
+            } else {
+                // kept for the report only
+                receiving_file.status = FileStatus::Skipped;
+                receiving_file.reason = Some("Not selected".to_owned());
+            }
+            (file.id, receiving_file)
         })
         .collect();
+    // and the names its files are saved under, the quarantine names them once they are kept
+    let mut final_names = final_names(
+        selection.iter().filter(|file| {
+            !receive_session
+                .quarantine
+                .as_ref()
+                .is_some_and(|quarantine| quarantine.contains(&file.id))
+        }),
+        &receive_session.destination_directory,
+        &receive_session.settings,
+        receive_session.names.case(),
+    );
+    // the sender learns why files got no token, without the paths of this device
+    let mut filtered = HashMap::new();
+    for duplicate in duplicates {
+        filtered.insert(duplicate.file.id.clone(), "Already received".to_owned());
+        final_names.extend(
+            duplicate
+                .saved_name
+                .clone()
+                .map(|name| (duplicate.file.id.clone(), name)),
+        );
+        log::info!(
+            "File {:?} has been received before as {:?}",
+            duplicate.file.file_name,
+            duplicate.duplicate_of
+        );
+        receive_session
+            .files
+            .insert(duplicate.file.id.clone(), duplicate);
+    }
+    for file in rejected {
+        filtered.extend(
+            file.reason
+                .clone()
+                .map(|reason| (file.file.id.clone(), reason)),
+        );
+        receive_session.files.insert(file.file.id.clone(), file);
+    }
+    (filtered, final_names)
+}
 
-    let session_id = receive_session.session_id.clone();
-    let files = receive_session
+/// Handles the offered files received before according to `action` instead of receiving them.
+async fn place_duplicates(
+    duplicates: Vec<(FileDto, PathBuf)>,
+    destination: &Path,
+    action: DedupAction,
+    (collision_policy, name_rules, name_replacement, dangerous): (
+        CollisionPolicy,
+        NameRules,
+        char,
+        &DangerousExtensions,
+    ),
+    names: &SessionNames,
+) -> Vec<ReceivingFile> {
+    let mut placed = Vec::with_capacity(duplicates.len());
+    for (file, existing) in duplicates {
+        let mut receiving_file = ReceivingFile::new(file, None);
+        receiving_file.bytes = receiving_file.file.size;
+        if action == DedupAction::Skip {
+            receiving_file.status = FileStatus::Skipped;
+            receiving_file.reason = Some(format!("Already received as {}", existing.display()));
+            receiving_file.duplicate_of = Some(existing);
+            placed.push(receiving_file);
+            continue;
+        }
+        let file_name = &receiving_file.file.file_name;
+        let path = fs_path(
+            destination,
+            file_name,
+            name_rules,
+            name_replacement,
+            dangerous,
+        );
+        // offered again under the name it was saved as
+        let path = if is_same_file(&path, &existing) {
+            names.insert(&path);
+            path
+        } else {
+            resolve_session_collision(path, collision_policy, names)
+        };
+        match place_duplicate(&existing, &path, action).await {
+            Ok(()) => {
+                let verb = match action {
+                    DedupAction::Link => "Linked to",
+                    _ => "Copied from",
+                };
+                receiving_file.status = FileStatus::Finished;
+                receiving_file.reason = Some(format!("{} {}", verb, existing.display()));
+                receiving_file.saved_name =
+                    saved_name(&path, destination).filter(|name| name != file_name);
+                receiving_file.completed_at = Some(Instant::now());
+                receiving_file.path = Some(path);
+            }
+            Err(e) => {
+                log::error!("Failed to reuse {:?} for {:?}: {:?}", existing, path, e);
+                receiving_file.status = FileStatus::Failed;
+                receiving_file.reason =
+                    Some(format!("Failed to reuse {}: {}", existing.display(), e));
+            }
+        }
+        receiving_file.duplicate_of = Some(existing);
+        placed.push(receiving_file);
+    }
+    placed
+}
+
+/// Names the default sink is expected to save `files` under where they differ from the
+/// offered ones, by file id.
+///
+/// Only a hint for the sender, the upload responses tell the names files were saved under.
+fn final_names<'a>(
+    files: impl IntoIterator<Item = &'a FileDto>,
+    destination: &Path,
+    settings: &Settings,
+    case: CaseSensitivity,
+) -> HashMap<String, String> {
+    if settings.archive.is_some() || settings.sink_factory.is_some() || settings.audit {
+        return HashMap::new();
+    }
+    // the files of the offer take names from each other, not only from the directory
+    let names = SessionNames::new(case);
+    let mut appended = vec![];
+    let mut final_names = HashMap::new();
+    for file in files.into_iter().filter(|file| !is_note(file)) {
+        let path = fs_path(
+            destination,
+            &file.file_name,
+            settings.name_rules,
+            settings.name_replacement,
+            &settings.dangerous_extensions,
+        );
+        // like `FsSink`, an existing file is appended to once per session
+        let appends = settings.collision_policy == CollisionPolicy::Append
+            && (settings.append_any_type
+                || matches!(file.file_type, FileType::Text | FileType::Other))
+            && path.is_file()
+            && !appended.contains(&path);
+        let path = if appends {
+            appended.push(path.clone());
+            path
+        } else {
+            resolve_session_collision(path, settings.collision_policy, &names)
+        };
+        if let Some(name) = saved_name(&path, destination).filter(|name| name != &file.file_name) {
+            final_names.insert(file.id.clone(), name);
+        }
+    }
+    final_names
+}
+
+/// Adds the files of another prepare-upload of the sender to its session.
+///
+/// The files already accepted keep uploading, the response only contains tokens
+/// for the added files.
+async fn extend_session(
+    state: MutexServerState,
+    dto: PrepareUploadRequestDto,
+) -> Result<(PrepareUploadResponseDto, Option<Compression>)> {
+    let (session_id, sender, decider, decision_timeout, files, rejected) = {
+        let state = state.lock().await;
+        let session = state
+            .receive_session
+            .as_ref()
+            .ok_or(ReceiveError::InvalidServerState)?;
+        // the first selection must be complete
+        if session.status != ReceiveSessionStatus::Sending {
+            return Err(ReceiveError::SessionBlocked)?;
+        }
+        if dto.files.is_empty() {
+            return Err(ReceiveError::EmptyFiles)?;
+        }
+        if dto.files.keys().any(|id| session.files.contains_key(id)) {
+            log::warn!("Session extension repeats file ids");
+            return Err(ReceiveError::InvalidParameters)?;
+        }
+        let known = session
+            .files
+            .values()
+            .filter(|file| file.token.is_some())
+            .map(|file| file.file.file_name.as_str());
+        let files = dto.files.into_values().collect();
+        // the session goes on with the settings it started with
+        let (files, rejected) = check_structure(&session.settings, known, files)?;
+        (
+            session.session_id.clone(),
+            session.sender.clone(),
+            decider(&state, &session.settings),
+            session.settings.decision_timeout,
+            files,
+            rejected,
+        )
+    };
+
+    let selection = match decide(decider.as_ref(), sender, files.clone(), decision_timeout).await? {
+        Decision::Accept(selection) => selection,
+        Decision::Decline | Decision::Timeout => vec![],
+    };
+    let progress_tx = decider.progress_tx();
+    if selection.is_empty() {
+        // the session goes on with the files accepted before
+        return Err(ReceiveError::NothingSelected)?;
+    }
+
+    let mut state = state.lock().await;
+    let session = state
+        .receive_session
+        .as_mut()
+        .filter(|session| session.session_id == session_id)
+        .ok_or(ReceiveError::Cancelled)?;
+    let token_issuer = session.settings.token_issuer.clone();
+    // another extension may have added the same files meanwhile
+    if files
+        .iter()
+        .any(|file| session.files.contains_key(&file.id))
+    {
+        return Err(ReceiveError::InvalidParameters)?;
+    }
+    if session.progress_tx.is_none() {
+        session.progress_tx = progress_tx;
+    }
+    if let Some(journal) = &session.journal {
+        journal.add_files(&selection);
+    }
+    let mut added = Vec::with_capacity(files.len());
+    for file in files {
+        let mut receiving_file = ReceivingFile::new(file.clone(), None);
+        if selection.iter().any(|selected| selected.id == file.id) {
+            receiving_file.token = Some(FileToken::new(token_issuer.issue()));
+        } else {
+            receiving_file.status = FileStatus::Skipped;
+            receiving_file.reason = Some("Not selected".to_owned());
+        }
+        added.push(receiving_file);
+    }
+    let filtered = rejected
+        .iter()
+        .filter_map(|file| Some((file.file.id.clone(), file.reason.clone()?)))
+        .collect();
+    let final_names = final_names(
+        &selection,
+        &session.destination_directory,
+        &session.settings,
+        session.names.case(),
+    );
+    added.extend(rejected);
+    session.status_tracker.add_files(&added);
+    session.last_activity.touch();
+    log::info!(
+        "Session {} extended by {} files",
+        session.session_id,
+        selection.len()
+    );
+
+    let files = added
+        .iter()
+        .filter_map(|file| {
+            Some((
+                file.file.id.clone(),
+                file.token.as_ref()?.as_str().to_owned(),
+            ))
+        })
+        .collect();
+    session
         .files
-        .iter_mut()
-        .map(|(id, file)| (id.clone(), file.token.clone().unwrap()))
+        .extend(added.into_iter().map(|file| (file.file.id.clone(), file)));
+    let dto = PrepareUploadResponseDto {
+        session_id: session.session_id.clone(),
+        files,
+        filtered,
+        final_names,
+    };
+    let compression = session.compression;
+    state.events.emit(SessionEvent::ReceiveAccepted {
+        session_id,
+        files: selection,
+    });
+    Ok((dto, compression))
+}
+
+/// Splits off the files breaking the structure limits, they are kept for the report only.
+///
+/// Fails when no file is left, or on the first broken limit with `Settings::strict_structure`.
+fn check_structure<'a>(
+    settings: &Settings,
+    known: impl IntoIterator<Item = &'a str>,
+    files: Vec<FileDto>,
+) -> std::result::Result<(Vec<FileDto>, Vec<ReceivingFile>), ReceiveError> {
+    let (accepted, rejected) = settings.structure_limits.check(known, files);
+    let mut limits = vec![];
+    for (file, limit) in &rejected {
+        if !limits.contains(limit) {
+            log::warn!(
+                "Refusing {:?} and others beyond the limit of {}",
+                file.file_name,
+                limit
+            );
+            limits.push(*limit);
+        }
+    }
+    if let Some((file, limit)) = rejected.first() {
+        if settings.strict_structure || accepted.is_empty() {
+            return Err(ReceiveError::StructureLimitExceeded {
+                file_name: file.file_name.clone(),
+                limit: *limit,
+            });
+        }
+    }
+    let rejected = rejected
+        .into_iter()
+        .map(|(file, limit)| {
+            let mut receiving_file = ReceivingFile::new(file, None);
+            receiving_file.status = FileStatus::Skipped;
+            receiving_file.reason = Some(format!("Exceeds the limit of {}", limit));
+            receiving_file
+        })
+        .collect();
+    Ok((accepted, rejected))
+}
+
+/// The decider of the next prepare-upload, quick save accepts everything.
+fn decider(state: &ServerState, settings: &Settings) -> Arc<dyn ReceiveDecider> {
+    if settings.quick_save {
+        Arc::new(AcceptAll)
+    } else {
+        state.decider.clone()
+    }
+}
+
+/// Asks `decider` without holding the server state, giving up after `timeout`.
+async fn decide(
+    decider: &dyn ReceiveDecider,
+    sender: Device,
+    files: Vec<FileDto>,
+    timeout: Duration,
+) -> Result<Decision> {
+    let decision = AssertUnwindSafe(decider.decide(sender, files)).catch_unwind();
+    match tokio::time::timeout(timeout, decision).await {
+        Ok(Ok(decision)) => Ok(decision),
+        Ok(Err(_)) => {
+            log::error!("Receive decider panicked");
+            Err(ReceiveError::InvalidServerState)?
+        }
+        Err(_) => Ok(Decision::Timeout),
+    }
+}
+
+/// Asks `decider` which quarantined files are kept, all of them are when it panics
+/// or does not answer within `timeout`.
+async fn review(
+    decider: &dyn ReceiveDecider,
+    sender: Device,
+    files: Vec<PreviewFile>,
+    timeout: Duration,
+) -> Vec<String> {
+    if files.is_empty() {
+        return vec![];
+    }
+    let ids = files
+        .iter()
+        .map(|preview| preview.file.id.clone())
         .collect();
-    let dto = PrepareUploadResponseDto { session_id, files };
+    let review = AssertUnwindSafe(decider.review(sender, files)).catch_unwind();
+    match tokio::time::timeout(timeout, review).await {
+        Ok(Ok(keep)) => keep,
+        Ok(Err(_)) => {
+            log::error!("Receive decider panicked, keeping the previewed files");
+            ids
+        }
+        Err(_) => {
+            log::warn!("No review in time, keeping the previewed files");
+            ids
+        }
+    }
+}
+
+async fn create_archive(
+    path: &std::path::Path,
+    format: ArchiveFormat,
+) -> io::Result<ArchiveWriter> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    ArchiveWriter::create(path, format).await
+}
 
-    Ok(dto)
+fn is_text_message(file: &FileDto) -> bool {
+    file.file_type == FileType::Text && file.preview.is_some()
 }
 
 pub async fn upload_v1(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Query(query): Query<HashMap<String, String>>,
+    StrictQuery(query): StrictQuery,
     State(state): State<MutexServerState>,
+    headers: HeaderMap,
     body: Body,
-) -> Result<()> {
-    upload(addr, query, body, state, false).await?;
-    Ok(())
+) -> Result<Json<UploadResponseDto>> {
+    Ok(Json(
+        upload(addr, query, &headers, body, state, false).await?,
+    ))
 }
 
 pub async fn upload_v2(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Query(query): Query<HashMap<String, String>>,
+    StrictQuery(query): StrictQuery,
     State(state): State<MutexServerState>,
+    headers: HeaderMap,
     body: Body,
-) -> Result<()> {
-    upload(addr, query, body, state, true).await?;
-    Ok(())
+) -> Result<Json<UploadResponseDto>> {
+    Ok(Json(
+        upload(addr, query, &headers, body, state, true).await?,
+    ))
+}
+
+/// Waits for a free slot when `Settings::max_concurrent_uploads` is set.
+///
+/// Waiting uploads leave their bodies unread, so their senders are held back by TCP.
+async fn upload_permit(state: &MutexServerState) -> Option<OwnedSemaphorePermit> {
+    let limit = state.lock().await.upload_limit.clone()?;
+    limit.acquire_owned().await.ok()
 }
 
 async fn upload(
     addr: SocketAddr,
     query: HashMap<String, String>,
+    headers: &HeaderMap,
     body: Body,
     state: MutexServerState,
     v2: bool,
-) -> Result<()> {
+) -> Result<UploadResponseDto> {
+    let _permit = upload_permit(&state).await;
     let mut _state = state.lock().await;
+    let server_tx = _state.server_tx.clone();
+    let events = _state.events.clone();
+    if _state.receive_session.is_none() {
+        return retry_finished(&_state, addr, &query, v2).await;
+    }
     let receive_session = _state
         .receive_session
         .as_mut()
         .ok_or(ReceiveError::SessionNotExists)?;
+    let token_policy = receive_session.settings.token_policy;
+    let custom_sink =
+        receive_session.settings.sink_factory.is_some() || receive_session.settings.audit;
+    let text_preview = receive_session.settings.text_preview.clone();
 
     if addr.ip().to_string() != receive_session.sender.ip {
         log::warn!(
@@ -242,41 +1156,86 @@ async fn upload(
         return Err(ReceiveError::InvalidRecipient)?;
     }
 
-    let file_id = query.get("fileId").ok_or(ReceiveError::InvalidParameters)?;
-    let token = query.get("token").ok_or(ReceiveError::InvalidParameters)?;
-
-    if v2 {
-        let session_id = query
-            .get("sessionId")
-            .ok_or(ReceiveError::InvalidParameters)?;
+    let upload_query = UploadQuery::from_params(&query, v2)?;
+    if let Some(session_id) = &upload_query.session_id {
         if session_id != &receive_session.session_id {
             return Err(ReceiveError::InvalidSessionId)?;
         }
     }
+    let (file_id, token) = (&upload_query.file_id, &upload_query.token);
 
     let receiving_file = receive_session
         .files
         .get_mut(file_id)
         .ok_or(ReceiveError::InvalidToken)?;
 
-    let receiving_file_token = receiving_file
-        .token
-        .as_ref()
-        .ok_or(ReceiveError::InvalidToken)?;
-    if token != receiving_file_token {
+    if let Err(rejection) =
+        token_policy.validate(receiving_file.token.as_ref(), token, Instant::now())
+    {
+        log::warn!("Refused upload of file {}: {}", file_id, rejection);
+        return Err(ReceiveError::InvalidToken)?;
+    }
+    if receiving_file.status != FileStatus::Queue {
+        let archived = receive_session.archive.is_some();
+        receive_session.last_activity.touch();
+        return answer_retry(receiving_file, archived).await;
+    }
+
+    // only the encoding agreed on in prepare-upload is accepted
+    let compression = match headers.get(header::CONTENT_ENCODING) {
+        None => None,
+        Some(value) if value == "identity" => None,
+        Some(value) => {
+            let compression = value.to_str().ok().and_then(Compression::from_name);
+            if compression.is_none() || compression != receive_session.compression {
+                log::warn!("Unexpected content encoding: {:?}", value);
+                return Err(ReceiveError::InvalidParameters)?;
+            }
+            compression
+        }
+    };
+    // a shorter body can not complete the file, e.g. a sender probing for a lost response
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if compression.is_none() && length.is_some_and(|length| length != receiving_file.file.size) {
         log::warn!(
-            "Wrong file token: {} (expected: {})",
-            token,
-            receiving_file_token
+            "Unexpected content length {:?} for {} bytes",
+            length,
+            receiving_file.file.size
         );
-        return Err(ReceiveError::InvalidToken)?;
+        return Err(ReceiveError::InvalidParameters)?;
     }
 
+    let receiving_file = receive_session
+        .files
+        .get_mut(file_id)
+        .ok_or(ReceiveError::InvalidToken)?;
     receiving_file.status = FileStatus::Sending;
-    receiving_file.token = None; // remove token to reject further uploads of the same file
+    receiving_file.started = Some(Instant::now());
+    if let Some(token) = receiving_file.token.as_mut() {
+        token.mark_used();
+    }
+    let session_id = receive_session.session_id.clone();
+    events.emit(SessionEvent::FileStarted {
+        session_id: session_id.clone(),
+        file_id: file_id.clone(),
+    });
 
     let receiving_file = receiving_file.clone();
-    let destination = &receive_session.destination_directory.clone();
+    let quarantine = receive_session
+        .quarantine
+        .as_ref()
+        .filter(|quarantine| quarantine.contains(file_id));
+    let quarantined = quarantine.is_some();
+    let destination = &match quarantine {
+        Some(quarantine) => quarantine.dir().to_path_buf(),
+        None => receive_session.destination_directory.clone(),
+    };
+    let sink = match quarantine {
+        Some(quarantine) => quarantine.sink(),
+        None => receive_session.sink.clone(),
+    };
     log::info!(
         "Saving {} to {:?}",
         receiving_file.file.file_name,
@@ -284,6 +1243,33 @@ async fn upload(
     );
 
     let progress_tx = receive_session.progress_tx.clone();
+    let activity = receive_session.last_activity.clone();
+    let status_tracker = receive_session.status_tracker.clone();
+    let cancel = receive_session.cancel.clone();
+    let rate_limiter = receive_session.rate_limiter.clone();
+    activity.touch();
+    // longer texts are archived
+    let print_text = receive_session.print_texts
+        && is_text_message(&receiving_file.file)
+        && receiving_file.file.size <= text_preview.max_bytes as u64;
+    let note = is_note(&receiving_file.file);
+    let archive = if print_text || note {
+        None
+    } else {
+        receive_session.archive.clone()
+    };
+    let saved_to_sink = !print_text && !note && archive.is_none();
+    // only the default sink saves to the destination
+    let to_destination = saved_to_sink && !quarantined && !custom_sink;
+    // a destination files were saved to before is gone, e.g. an unplugged drive
+    let saved_before = receive_session.files.values().any(|f| {
+        f.status == FileStatus::Finished
+            && f.path
+                .as_ref()
+                .is_some_and(|path| path.starts_with(destination))
+    });
+    let lost_before = (to_destination && saved_before && !destination.is_dir())
+        .then(|| format!("{} no longer exists", destination.display()));
 
     // release state lock
     drop(_state);
@@ -291,85 +1277,2157 @@ async fn upload(
     let save_file = || async {
         let stream = body.into_data_stream();
         let stream = stream.map_err(|e| io::Error::new(io::ErrorKind::Other, e));
-        let reader = StreamReader::new(stream);
-        pin_mut!(reader);
-
-        const BUF_SIZE: usize = 1024 * 8;
-        let path = std::path::Path::new(destination).join(&receiving_file.file.file_name);
-        if let Some(path) = path.parent() {
-            if !path.exists() {
-                tokio::fs::create_dir_all(path).await?;
-            }
-        }
-
-        let file = File::create(&path).await?;
-        let mut file_buf = BufWriter::with_capacity(BUF_SIZE, file);
-
-        let mut buf = [0u8; BUF_SIZE];
-        let mut position: u64 = 0;
-
-        loop {
-            match reader.read(&mut buf[..]).await {
-                Ok(0) => break,
-                Ok(len) => {
-                    position += len as u64;
-                    file_buf.write(&buf[0..len]).await.unwrap();
-                    if let Some(ref progress_tx) = progress_tx {
-                        progress_tx
-                            .send(UploadProgress {
-                                file_id: receiving_file.file.id.clone(),
-                                position,
-                                finish: position >= receiving_file.file.size,
-                            })
-                            .await
-                            .ok();
+        // an expired or cancelled session fails the body, so partial files are removed like on errors
+        let stream = async_stream::stream! {
+            pin_mut!(stream);
+            loop {
+                match select(Box::pin(cancel.cancelled()), stream.next()).await {
+                    Either::Left(_) => {
+                        yield Err(io::Error::other("Session cancelled"));
+                        break;
                     }
+                    Either::Right((Some(chunk), _)) => {
+                        activity.touch();
+                        // the next chunk is read once this one fits the rate
+                        if let (Some(limiter), Ok(bytes)) = (&rate_limiter, &chunk) {
+                            let paced = Box::pin(limiter.consume(bytes.len()));
+                            if let Either::Left(_) = select(Box::pin(cancel.cancelled()), paced).await {
+                                yield Err(io::Error::other("Session cancelled"));
+                                break;
+                            }
+                        }
+                        yield chunk;
+                    }
+                    Either::Right((None, _)) => break,
                 }
-                Err(e) => {
-                    log::warn!("Error: {:?}", e);
-                    tokio::fs::remove_file(path).await.ok();
-                    return Err(ReceiveError::Cancelled)?;
+            }
+        };
+        let reader = StreamReader::new(Box::pin(stream));
+        // progress and sizes count the decompressed bytes
+        let mut reader: Pin<Box<dyn AsyncRead + Send>> = match compression {
+            Some(compression) => compression.decoder(reader),
+            None => Box::pin(reader),
+        };
+
+        let file = &receiving_file.file;
+        let mut progress_events = events.file_progress(&session_id, &file.id);
+
+        if note {
+            // shown with the offer already, read only to complete it for the sender,
+            // the receiving UI knows nothing of it
+            let bytes = copy_body(
+                &mut reader,
+                &mut tokio::io::sink(),
+                file,
+                &None,
+                Some(&status_tracker),
+                Some(&mut progress_events),
+            )
+            .await?;
+            return Result::Ok((None, bytes));
+        }
+
+        if print_text {
+            // the size is the sender's word, more than a preview is never reserved
+            let capacity = file.size.min(text_preview.max_bytes as u64);
+            let mut text = Vec::with_capacity(capacity as usize);
+            let bytes = copy_body(
+                &mut reader,
+                &mut text,
+                file,
+                &progress_tx,
+                Some(&status_tracker),
+                Some(&mut progress_events),
+            )
+            .await?;
+            match text_preview.printable(&text) {
+                Some(text) => {
+                    server_tx.send(ServerMessage::TextReceived(text)).await.ok();
                 }
+                None => log::warn!("Not printing {}, it is not text", file.file_name),
             }
+            return Result::Ok((None, bytes));
         }
 
-        file_buf.flush().await?;
+        if let Some(archive) = archive {
+            let mut archive = archive.lock().await;
+            let archive = archive.as_mut().ok_or(ReceiveError::Cancelled)?;
+            archive.start_entry(&file.file_name, file.size).await?;
+            let copy_result = copy_body(
+                &mut reader,
+                &mut archive.entry_writer(),
+                file,
+                &progress_tx,
+                Some(&status_tracker),
+                Some(&mut progress_events),
+            )
+            .await;
+            return match copy_result {
+                Ok(bytes) => {
+                    archive.finish_entry().await?;
+                    Ok((Some(archive.path().to_path_buf()), bytes))
+                }
+                Err(e) => {
+                    archive.abort_entry().await?;
+                    Err(e)
+                }
+            };
+        }
 
-        Result::Ok(())
+        let mut writer = sink.open(file).await?;
+        let copy_result = copy_body(
+            &mut reader,
+            &mut writer,
+            file,
+            &progress_tx,
+            Some(&status_tracker),
+            Some(&mut progress_events),
+        )
+        .await;
+        match copy_result {
+            Ok(bytes) => {
+                writer.shutdown().await?;
+                drop(writer);
+                Ok((sink.finish(file).await?, bytes))
+            }
+            Err(e) => {
+                drop(writer);
+                sink.abort(file).await;
+                Err(e)
+            }
+        }
     };
 
-    let save_result = save_file().await;
+    let save_result = match &lost_before {
+        // the body is not read
+        Some(reason) => Err(ReceiveError::DestinationLost(reason.clone()).into()),
+        None => save_file().await,
+    };
+    let lost = match &save_result {
+        _ if lost_before.is_some() => lost_before,
+        Err(crate::Error::Io(e)) if to_destination && is_destination_error(e) => {
+            probe_writable(destination)
+                .await
+                .err()
+                .map(|probe| format!("{} can not be written to: {}", destination.display(), probe))
+        }
+        _ => None,
+    };
 
     let mut _state = state.lock().await;
-    let receive_session = _state
-        .receive_session
-        .as_mut()
-        .ok_or(ReceiveError::Cancelled)?;
+    // uploads still running when the destination was lost fail like their session
+    let lost_session = _state
+        .finished_session
+        .as_ref()
+        .filter(|session| session.session_id == session_id)
+        .and_then(|session| session.destination_lost.clone());
+    let reviewer = _state.decider.clone();
+    let receive_session = _state.receive_session.as_mut().ok_or(match lost_session {
+        Some(reason) => ReceiveError::DestinationLost(reason),
+        None => ReceiveError::Cancelled,
+    })?;
+    let hook = receive_session.settings.receive_hook.clone();
+    let hook_timeout = receive_session.settings.hook_timeout;
+    let collision_policy = receive_session.settings.collision_policy;
+    let review_timeout = receive_session.settings.decision_timeout;
+
+    if receive_session.archive.is_some() {
+        if let Err(crate::Error::Receive(ReceiveError::Cancelled)) = save_result {
+            log::warn!("Upload cancelled, discarding session archive");
+            receive_session.abort_archive().await;
+            receive_session.status_tracker.clear();
+            _state.receive_session = None;
+            events.emit(SessionEvent::SessionFailed {
+                session_id,
+                reason: "Upload cancelled, the archive was discarded".to_owned(),
+            });
+            return Err(ReceiveError::Cancelled)?;
+        }
+    }
     let receiving_file = receive_session
         .files
         .get_mut(file_id)
         .ok_or(ReceiveError::InvalidToken)?;
 
+    receiving_file.finished = Some(Instant::now());
     let result = match save_result {
-        Ok(_) => {
+        Ok((path, bytes)) => {
             log::info!("File {:?} has been saved", receiving_file.file.file_name);
+            // quarantined files are named and hooked once they are kept
+            if saved_to_sink && !quarantined {
+                receiving_file.saved_name = path
+                    .as_deref()
+                    .and_then(|path| saved_name(path, destination))
+                    .filter(|name| name != &receiving_file.file.file_name);
+            }
             receiving_file.status = FileStatus::Finished;
-            Ok(())
-        }
-        Err(e) => {
-            log::error!("Failed to save file: {:?}", e);
+            receiving_file.completed_at = receiving_file.finished;
+            receiving_file.path = path;
+            receiving_file.bytes = bytes;
+            if saved_to_sink {
+                receiving_file.appended_to_size = sink.appended(&receiving_file.file);
+            }
+            // only bodies that matched their digest are received with one, the
+            // digest of an appended body is not the one of the file
+            let hash = receiving_file.file.hash.as_deref();
+            if let (Some(index), Some(hash), Some(path)) = (
+                receive_session.dedup.as_mut().filter(|_| {
+                    saved_to_sink && !quarantined && receiving_file.appended_to_size.is_none()
+                }),
+                hash,
+                &receiving_file.path,
+            ) {
+                index.insert(hash, path, bytes);
+                if let Err(e) = index.save().await {
+                    log::warn!("Failed to save dedup index: {}", e);
+                }
+            }
+            let response = upload_response(receiving_file);
+            if let Some(hook) = hook.clone().filter(|_| saved_to_sink && !quarantined) {
+                let info = ReceivedFileInfo {
+                    session_id: receive_session.session_id.clone(),
+                    sender: receive_session.sender.clone(),
+                    file: receiving_file.file.clone(),
+                    path: receiving_file.path.clone(),
+                };
+                receive_session.hooks.spawn(hook, info, hook_timeout);
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            log::error!("Failed to save file: {:?}", e);
             receiving_file.status = FileStatus::Failed;
+            receiving_file.reason = Some(e.to_string());
             Err(ReceiveError::SaveFileFailed.into())
         }
     };
+    receive_session
+        .status_tracker
+        .file_finished(file_id, receiving_file.status.clone());
+    events.emit(SessionEvent::FileFinished {
+        session_id: session_id.clone(),
+        file_id: file_id.clone(),
+        status: receiving_file.status.clone(),
+    });
+    let result = match lost {
+        Some(reason) => {
+            for file_id in receive_session.lose_destination(reason.clone()) {
+                receive_session
+                    .status_tracker
+                    .file_finished(&file_id, FileStatus::Failed);
+                events.emit(SessionEvent::FileFinished {
+                    session_id: session_id.clone(),
+                    file_id,
+                    status: FileStatus::Failed,
+                });
+            }
+            Err(ReceiveError::DestinationLost(reason).into())
+        }
+        None => result,
+    };
 
-    let finish = receive_session
-        .files
-        .iter()
-        .all(|f| f.1.status == FileStatus::Finished || f.1.status == FileStatus::Failed);
+    let finish = receive_session.files.values().all(|f| {
+        matches!(
+            f.status,
+            FileStatus::Finished | FileStatus::Failed | FileStatus::Skipped
+        )
+    });
     if finish {
-        _state.receive_session = None;
+        if let Some(mut session) = _state.receive_session.take() {
+            drop(_state);
+            let archived = session.archive.is_some();
+            match session.finish_archive().await {
+                Ok(Some(path)) => log::info!("Archive {:?} has been saved", path),
+                Ok(None) => {}
+                Err(e) => {
+                    log::error!("Failed to finish archive: {:?}", e);
+                    for file in session.files.values_mut() {
+                        if file.status == FileStatus::Finished && file.path.is_some() {
+                            file.status = FileStatus::Failed;
+                            file.reason = Some(format!("Failed to finish archive: {}", e));
+                        }
+                    }
+                }
+            }
+            let mut report = session.report();
+            session.status_tracker.finish();
+            if let Some(progress_tx) = session.progress_tx.take() {
+                progress_tx.session_ended().await;
+            }
+            let quarantine = session.quarantine.take();
+            state.lock().await.finished_session = Some(FinishedSession {
+                session_id: session.session_id.clone(),
+                sender_ip: session.sender.ip.clone(),
+                files: match quarantine {
+                    // still needed for the review
+                    Some(_) => session.files.clone(),
+                    None => std::mem::take(&mut session.files),
+                },
+                archived,
+                destination_lost: session.destination_lost.clone(),
+            });
+            let mut hooks = std::mem::take(&mut session.hooks);
+            if let Some(quarantine) = quarantine {
+                // the sender is answered right away, the report waits for the review
+                tokio::spawn(async move {
+                    let previews = quarantine.files(&session.files);
+                    let sender = session.sender.clone();
+                    let keep = review(reviewer.as_ref(), sender, previews, review_timeout).await;
+                    let destination = &session.destination_directory;
+                    let kept = quarantine
+                        .release(&mut session.files, &keep, destination, collision_policy)
+                        .await;
+                    if let Some(hook) = &hook {
+                        for file_id in &kept {
+                            let info = ReceivedFileInfo {
+                                session_id: session.session_id.clone(),
+                                sender: session.sender.clone(),
+                                file: session.files[file_id].file.clone(),
+                                path: session.files[file_id].path.clone(),
+                            };
+                            hooks.spawn(hook.clone(), info, hook_timeout);
+                        }
+                    }
+                    // timings stay those of the transfer
+                    report.files = session.report().files;
+                    hooks.finish(&mut report).await;
+                    report_finished(&server_tx, &events, report).await;
+                });
+                return result;
+            }
+            drop(session);
+            if hooks.is_empty() {
+                report_finished(&server_tx, &events, report).await;
+            } else {
+                // the sender is answered right away, the report waits for the hooks
+                tokio::spawn(async move {
+                    hooks.finish(&mut report).await;
+                    report_finished(&server_tx, &events, report).await;
+                });
+            }
+        }
     }
 
     result
 }
+
+/// Tells the client and the subscribers of the events that a receive ended.
+async fn report_finished(
+    server_tx: &Sender<ServerMessage>,
+    events: &EventBus,
+    report: ReceiveReport,
+) {
+    events.emit(SessionEvent::SessionFinished {
+        report: report.clone(),
+    });
+    server_tx
+        .send(ServerMessage::SessionFinished(report))
+        .await
+        .ok();
+}
+
+/// What a sender is told about a saved file.
+///
+/// Saved bodies matched the digest announced for them, so it is repeated back. The
+/// name it was saved under may differ from the one expected in prepare-upload.
+fn upload_response(file: &ReceivingFile) -> UploadResponseDto {
+    UploadResponseDto {
+        bytes: file.bytes,
+        sha256: file
+            .file
+            .hash
+            .as_deref()
+            .and_then(FileHash::parse)
+            .map(String::from),
+        final_name: file.saved_name.clone(),
+    }
+}
+
+/// Answers the upload of a file that was uploaded before.
+///
+/// The sender may retry after it missed the response, a file that is still there
+/// is not received again and one still being received makes the sender back off.
+async fn answer_retry(file: &ReceivingFile, archived: bool) -> Result<UploadResponseDto> {
+    match file.status {
+        FileStatus::Sending => {
+            log::warn!("File {:?} is still being received", file.file.file_name);
+            Err(ReceiveError::UploadInProgress)?
+        }
+        FileStatus::Finished if file.is_complete(archived).await => {
+            log::info!("File {:?} has been received already", file.file.file_name);
+            Ok(upload_response(file))
+        }
+        _ => Err(ReceiveError::InvalidToken)?,
+    }
+}
+
+/// Answers a retried upload of the last finished session.
+async fn retry_finished(
+    state: &ServerState,
+    addr: SocketAddr,
+    query: &HashMap<String, String>,
+    v2: bool,
+) -> Result<UploadResponseDto> {
+    let session = state
+        .finished_session
+        .as_ref()
+        .filter(|session| session.sender_ip == addr.ip().to_string())
+        .ok_or(ReceiveError::SessionNotExists)?;
+    if v2 && query.get("sessionId") != Some(&session.session_id) {
+        return Err(ReceiveError::SessionNotExists)?;
+    }
+    let file_id = query.get("fileId").ok_or(ReceiveError::InvalidParameters)?;
+    let token = query.get("token").ok_or(ReceiveError::InvalidParameters)?;
+    let file = session
+        .files
+        .get(file_id)
+        .ok_or(ReceiveError::InvalidToken)?;
+    if let Err(rejection) =
+        state
+            .settings
+            .token_policy
+            .validate(file.token.as_ref(), token, Instant::now())
+    {
+        log::warn!("Refused retry of file {}: {}", file_id, rejection);
+        return Err(ReceiveError::InvalidToken)?;
+    }
+    if let (Some(reason), FileStatus::Failed) = (&session.destination_lost, &file.status) {
+        return Err(ReceiveError::DestinationLost(reason.clone()))?;
+    }
+    answer_retry(file, session.archived).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        future::Future,
+        io,
+        path::PathBuf,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        task::{ready, Context, Poll},
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+    use localsend_proto::{
+        dto::{FileDto, FileType, PrepareUploadResponseDto, UploadResponseDto},
+        ApiRoute, Device,
+    };
+    use reqwest::{header, Body, StatusCode};
+    use tokio::io::AsyncWrite;
+
+    use crate::{
+        error::{ErrorCode, ErrorDto},
+        progress::{ProgressEvent, ProgressSender, ProgressStream},
+        receive::{
+            clean_stale_journals, AuditRecord, Decision, DedupAction, JournalEntry, PreviewFile,
+            RandomTokens, ReceiveDecider, ReceiveHook, ReceiveSink, ReceivedFileInfo, SinkFactory,
+            SinkWriter, StructureLimits, QUARANTINE_PREFIX,
+        },
+        send::FileStatus,
+        server::{reload_settings, ServerMessage, SessionEvent},
+        test_util::{file, TestReceiver},
+        util::{
+            fs::CaseSensitivity,
+            hash::FileHash,
+            inhibit,
+            note::{note_file, NOTE_FILE_NAME},
+        },
+        CollisionPolicy, Settings, SettingsLoader,
+    };
+
+    fn stalled_body() -> Body {
+        Body::wrap_stream(async_stream::stream! {
+            yield std::io::Result::Ok(b"00".to_vec());
+            std::future::pending::<()>().await;
+        })
+    }
+
+    #[tokio::test]
+    async fn test_extend_during_upload() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.allow_session_extend = true;
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        let upload = tokio::spawn(receiver.upload(&session, "0", stalled_body()).send());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let extension: PrepareUploadResponseDto =
+            receiver.prepare(&["1"]).await.json().await.unwrap();
+        assert_eq!(extension.session_id, session.session_id);
+        assert_eq!(extension.files.keys().collect::<Vec<_>>(), vec!["1"]);
+        // ids of the session can not be offered again
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = receiver
+            .upload(&extension, "1", "1111")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            std::fs::read(receiver.destination.join("1.bin")).unwrap(),
+            b"1111"
+        );
+        // the first upload is still running, the session is not finished
+        assert!(receiver.server_rx.try_recv().is_err());
+
+        upload.abort();
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_extend_after_failed_file() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.allow_session_extend = true;
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+
+        // the sender gives up on the first file
+        let upload = tokio::spawn(receiver.upload(&session, "0", stalled_body()).send());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        upload.abort();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let extension: PrepareUploadResponseDto =
+            receiver.prepare(&["2"]).await.json().await.unwrap();
+        assert_eq!(extension.session_id, session.session_id);
+        for (session, file_id) in [(&session, "1"), (&extension, "2")] {
+            let response = receiver
+                .upload(session, file_id, "0000")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv()).await;
+        let Ok(Some(ServerMessage::SessionFinished(report))) = message else {
+            panic!("unexpected message: {:?}", message);
+        };
+        let statuses: Vec<_> = report.files.iter().map(|f| f.status.clone()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                FileStatus::Failed,
+                FileStatus::Finished,
+                FileStatus::Finished
+            ]
+        );
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_extend_disabled() {
+        let receiver = TestReceiver::start().await;
+        let response = receiver.prepare(&["0", "1"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = receiver.prepare(&["2"]).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_queued_sessions() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.max_pending_sessions = 1;
+        })
+        .await;
+        let first: PrepareUploadResponseDto = receiver.prepare(&["0"]).await.json().await.unwrap();
+        // the second sender is held until the first session ended
+        let mut second = Box::pin(receiver.prepare(&["1"]));
+        let waiting = tokio::time::timeout(Duration::from_millis(300), &mut second).await;
+        assert!(waiting.is_err());
+        // a third one does not fit into the queue anymore
+        let third = receiver.prepare(&["2"]).await;
+        assert_eq!(third.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(third.headers()[header::RETRY_AFTER], "120");
+
+        let response = receiver.upload(&first, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let second: PrepareUploadResponseDto = second.await.json().await.unwrap();
+        assert_eq!(second.files.keys().collect::<Vec<_>>(), ["1"]);
+        let response = receiver.upload(&second, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(receiver.destination.join("1.bin").exists());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_queued_sender_gives_up() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.max_pending_sessions = 1;
+        })
+        .await;
+        let first: PrepareUploadResponseDto = receiver.prepare(&["0"]).await.json().await.unwrap();
+        let gave_up = tokio::time::timeout(Duration::from_millis(300), receiver.prepare(&["1"]));
+        assert!(gave_up.await.is_err());
+
+        // its place is given to the next sender instead of being kept forever
+        let queue = receiver.state.lock().await.session_queue.clone();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !queue.is_empty() {
+            assert!(Instant::now() < deadline, "the queue kept a closed request");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut next = Box::pin(receiver.prepare(&["2"]));
+        let waiting = tokio::time::timeout(Duration::from_millis(300), &mut next).await;
+        assert!(waiting.is_err());
+        let response = receiver.upload(&first, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let next: PrepareUploadResponseDto = next.await.json().await.unwrap();
+        assert_eq!(next.files.keys().collect::<Vec<_>>(), ["2"]);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_queue_wait() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.max_pending_sessions = 1;
+            state.settings.pending_session_wait = Duration::from_millis(200);
+        })
+        .await;
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // still running when the wait is over, the sender is turned away like without a queue
+        let response = receiver.prepare(&["1"]).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(receiver.state.lock().await.session_queue.is_empty());
+        receiver.stop().await;
+    }
+
+    /// Describes an event without its session id, ids of files are sorted.
+    fn describe(event: &SessionEvent) -> String {
+        let ids = |files: &[FileDto]| {
+            let mut ids: Vec<&str> = files.iter().map(|file| file.id.as_str()).collect();
+            ids.sort();
+            ids.join(",")
+        };
+        match event {
+            SessionEvent::ReceiveRequested { files, .. } => format!("requested {}", ids(files)),
+            SessionEvent::ReceiveAccepted { files, .. } => format!("accepted {}", ids(files)),
+            SessionEvent::FileStarted { file_id, .. } => format!("started {}", file_id),
+            SessionEvent::FileProgress {
+                file_id, position, ..
+            } => format!("progress {} {}", file_id, position),
+            SessionEvent::FileFinished {
+                file_id, status, ..
+            } => format!("finished {} {:?}", file_id, status),
+            SessionEvent::SessionFinished { report } => {
+                format!("session finished {}", report.finished())
+            }
+            event => format!("{:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_events() {
+        let mut receiver = TestReceiver::start().await;
+        let mut events = receiver.state.lock().await.events.subscribe();
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        for file_id in ["0", "1"] {
+            let response = receiver
+                .upload(&session, file_id, "0000")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv()).await;
+        assert!(matches!(
+            message,
+            Ok(Some(ServerMessage::SessionFinished(_)))
+        ));
+
+        let mut sequence = vec![];
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.session_id(), session.session_id);
+            sequence.push(describe(&event));
+        }
+        assert_eq!(
+            sequence,
+            [
+                "requested 0,1",
+                "accepted 0,1",
+                "started 0",
+                "progress 0 4",
+                "finished 0 Finished",
+                "started 1",
+                "progress 1 4",
+                "finished 1 Finished",
+                "session finished 2",
+            ]
+        );
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_cancel_mid_file() {
+        let receiver = TestReceiver::start().await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        let upload = tokio::spawn(receiver.upload(&session, "0", stalled_body()).send());
+        let partial = receiver.destination.join("0.bin");
+        for _ in 0..100 {
+            if partial.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(partial.exists());
+
+        // cancelling the root token fails the running upload and stops the server
+        receiver.cancel.cancel();
+        let upload = tokio::time::timeout(Duration::from_secs(5), upload)
+            .await
+            .expect("upload not cancelled")
+            .unwrap();
+        assert!(upload.map_or(true, |response| response.status() != StatusCode::OK));
+        assert!(!partial.exists());
+        tokio::time::timeout(Duration::from_secs(5), receiver.server.wait())
+            .await
+            .expect("server still running")
+            .unwrap();
+        std::fs::remove_dir_all(receiver.destination).ok();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_archive() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.archive = Some(PathBuf::from("received.zip"));
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let archive = receiver.destination.join("received.zip");
+        assert!(archive.exists());
+
+        // the sender gives up halfway, nothing of the session is kept
+        let response = reqwest::Client::new()
+            .post(receiver.url(ApiRoute::Cancel))
+            .query(&[("sessionId", session.session_id.as_str())])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!archive.exists());
+        assert!(!receiver.destination.join("0.bin").exists());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_archive_name_conflict() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.archive = Some(PathBuf::from("received.tar"));
+            state.settings.collision_policy = CollisionPolicy::Rename;
+        })
+        .await;
+        std::fs::create_dir_all(&receiver.destination).unwrap();
+        let existing = receiver.destination.join("received.tar");
+        std::fs::write(&existing, "earlier").unwrap();
+
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => assert_eq!(report.finished(), 1),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert_eq!(std::fs::read(&existing).unwrap(), b"earlier");
+        let renamed = std::fs::read(receiver.destination.join("received (1).tar")).unwrap();
+        assert_eq!(&renamed[..5], b"0.bin");
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_transfer_guard() {
+        let mut receiver = TestReceiver::start().await;
+        let inhibitor = inhibit::mock::recording();
+
+        // held while the files come in, released once the last one is saved
+        let reason = "Receiving files from finishing";
+        let response = receiver
+            .prepare_from("finishing", vec![file("0", 4), file("1", 4)])
+            .await;
+        let session: PrepareUploadResponseDto = response.json().await.unwrap();
+        assert_eq!(inhibitor.held(reason), 1);
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(inhibitor.held(reason), 1);
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(matches!(
+            receiver.server_rx.recv().await,
+            Some(ServerMessage::SessionFinished(_))
+        ));
+        assert_eq!(inhibitor.held(reason), 0);
+
+        // and when the sender gives up
+        let reason = "Receiving files from cancelling";
+        let response = receiver
+            .prepare_from("cancelling", vec![file("2", 4), file("3", 4)])
+            .await;
+        let session: PrepareUploadResponseDto = response.json().await.unwrap();
+        assert_eq!(inhibitor.held(reason), 1);
+        let response = reqwest::Client::new()
+            .post(receiver.url(ApiRoute::Cancel))
+            .query(&[("sessionId", session.session_id.as_str())])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(inhibitor.held(reason), 0);
+        receiver.stop().await;
+    }
+
+    /// Accepts the files with these ids.
+    struct AcceptIds(&'static [&'static str]);
+
+    #[async_trait]
+    impl ReceiveDecider for AcceptIds {
+        async fn decide(&self, sender: Device, files: Vec<FileDto>) -> Decision {
+            assert_eq!(sender.alias, "sender");
+            Decision::Accept(
+                files
+                    .into_iter()
+                    .filter(|file| self.0.contains(&file.id.as_str()))
+                    .collect(),
+            )
+        }
+    }
+
+    struct Panicking;
+
+    #[async_trait]
+    impl ReceiveDecider for Panicking {
+        async fn decide(&self, _sender: Device, _files: Vec<FileDto>) -> Decision {
+            panic!("decider failed")
+        }
+    }
+
+    struct Sleeping;
+
+    #[async_trait]
+    impl ReceiveDecider for Sleeping {
+        async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Decision::Accept(files)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decider_accepts_subset() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(AcceptIds(&["0", "2"]));
+        })
+        .await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare(&["0", "1", "2"])
+            .await
+            .json()
+            .await
+            .unwrap();
+        let mut ids: Vec<_> = session.files.keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["0", "2"]);
+
+        for id in ["0", "2"] {
+            let response = receiver.upload(&session, id, "0000").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert_eq!(report.finished(), 2);
+        assert!(!receiver.destination.join("1.bin").exists());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_cancel_after_nothing_selected() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(AcceptIds(&[]));
+        })
+        .await;
+        let response = receiver.prepare(&["0", "1"]).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // a sender closing the session it never got is not an error
+        let response = reqwest::Client::new()
+            .post(receiver.url(ApiRoute::Cancel))
+            .query(&[("sessionId", "never-issued")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(receiver.state.lock().await.receive_session.is_none());
+        receiver.stop().await;
+    }
+
+    /// Accepts everything into another directory.
+    struct AcceptInto(PathBuf);
+
+    #[async_trait]
+    impl ReceiveDecider for AcceptInto {
+        async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+            Decision::Accept(files)
+        }
+
+        fn destination(&self) -> Option<PathBuf> {
+            Some(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decider_destination() {
+        let chosen = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(AcceptInto(chosen.clone()));
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => assert_eq!(report.finished(), 1),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert_eq!(std::fs::read(chosen.join("0.bin")).unwrap(), b"0000");
+        assert!(!receiver.destination.join("0.bin").exists());
+        receiver.stop().await;
+        std::fs::remove_dir_all(chosen).ok();
+    }
+
+    struct AcceptWithProgress(ProgressSender);
+
+    #[async_trait]
+    impl ReceiveDecider for AcceptWithProgress {
+        async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+            Decision::Accept(files)
+        }
+
+        fn progress_tx(&self) -> Option<ProgressSender> {
+            Some(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_progress() {
+        let (progress_tx, mut progress) = ProgressStream::channel();
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(AcceptWithProgress(progress_tx));
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        for id in ["0", "1"] {
+            let response = receiver.upload(&session, id, "0000").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => assert_eq!(report.finished(), 2),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        let mut events = vec![];
+        while let Some(event) = progress.recv().await {
+            events.push(event);
+            if events.last() == Some(&ProgressEvent::SessionEnded) {
+                break;
+            }
+        }
+        let finished: Vec<_> = events
+            .iter()
+            .filter(|event| event.outcome() == Some(FileStatus::Finished))
+            .map(|event| (event.file_id().unwrap(), event.position()))
+            .collect();
+        assert_eq!(finished, vec![("0", 4), ("1", 4)]);
+        assert_eq!(events.last(), Some(&ProgressEvent::SessionEnded));
+        receiver.stop().await;
+    }
+
+    /// Declines every offer, recording the ids of the files it was asked about.
+    #[derive(Default)]
+    struct RecordingDecider(std::sync::Mutex<Vec<Vec<String>>>);
+
+    #[async_trait]
+    impl ReceiveDecider for RecordingDecider {
+        async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+            let mut ids: Vec<String> = files.into_iter().map(|file| file.id).collect();
+            ids.sort();
+            self.0.lock().unwrap().push(ids);
+            Decision::Decline
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auto_accept_texts() {
+        let text = |id: &str, size: usize| FileDto {
+            id: id.to_owned(),
+            file_name: format!("{}.txt", id),
+            size: size as u64,
+            file_type: FileType::Text,
+            hash: None,
+            preview: Some("x".repeat(size)),
+        };
+        let binary = FileDto {
+            id: "bin".to_owned(),
+            file_name: "bin.bin".to_owned(),
+            size: 4,
+            file_type: FileType::Other,
+            hash: None,
+            preview: None,
+        };
+        let offer = |files: Vec<FileDto>| async move {
+            let decider = Arc::new(RecordingDecider::default());
+            let receiver = TestReceiver::start_with(|state| {
+                state.settings.auto_accept_texts = true;
+                state.settings.auto_accept_text_size = 16;
+                state.decider = decider.clone();
+            })
+            .await;
+            let response = receiver.prepare_files(files).await;
+            let status = response.status();
+            let mut ids: Vec<String> = match status {
+                StatusCode::OK => {
+                    let session: PrepareUploadResponseDto = response.json().await.unwrap();
+                    session.files.into_keys().collect()
+                }
+                _ => vec![],
+            };
+            ids.sort();
+            let statuses: HashMap<String, FileStatus> = receiver
+                .state
+                .lock()
+                .await
+                .receive_session
+                .as_ref()
+                .map(|session| {
+                    session
+                        .files
+                        .iter()
+                        .map(|(id, file)| (id.clone(), file.status.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            receiver.stop().await;
+            let asked = decider.0.lock().unwrap().clone();
+            (status, ids, asked, statuses)
+        };
+
+        // texts only: no one is asked
+        let (status, ids, asked, _) = offer(vec![text("a", 5), text("b", 5)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids, ["a", "b"]);
+        assert!(asked.is_empty());
+
+        // mixed: only the file is asked about, declining it keeps the text
+        let (status, ids, asked, statuses) = offer(vec![text("a", 5), binary.clone()]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids, ["a"]);
+        assert_eq!(asked, [["bin"]]);
+        assert_eq!(statuses["a"], FileStatus::Queue);
+        assert_eq!(statuses["bin"], FileStatus::Skipped);
+
+        // a long text is asked about like any file
+        let (status, ids, asked, _) = offer(vec![text("long", 17)]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(ids.is_empty());
+        assert_eq!(asked, [["long"]]);
+    }
+
+    #[tokio::test]
+    async fn test_offer_note() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(AcceptIds(&["0"]));
+        })
+        .await;
+        let note = note_file("the RAWs are in the subfolder");
+        let mut files: Vec<FileDto> = ["0", "1"]
+            .into_iter()
+            .map(|id| FileDto {
+                id: id.to_owned(),
+                file_name: format!("{}.bin", id),
+                size: 4,
+                file_type: FileType::Other,
+                hash: None,
+                preview: None,
+            })
+            .collect();
+        files.push(note.clone());
+        let session: PrepareUploadResponseDto =
+            receiver.prepare_files(files).await.json().await.unwrap();
+        // the note needs no selection, its upload completes the offer for the sender
+        let mut ids: Vec<_> = session.files.keys().cloned().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["0".to_owned(), note.id.clone()]);
+
+        let uploads = [
+            ("0", "0000".to_owned()),
+            (note.id.as_str(), note.preview.clone().unwrap()),
+        ];
+        for (id, body) in uploads {
+            let response = receiver.upload(&session, id, body).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.finished(), 1);
+        assert!(!receiver.destination.join(NOTE_FILE_NAME).exists());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_panicking_decider() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(Panicking);
+        })
+        .await;
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(receiver.state.lock().await.receive_session.is_none());
+
+        // the receiver stays usable
+        receiver.state.lock().await.settings.quick_save = true;
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_decider_timeout() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.decider = Arc::new(Sleeping);
+            state.settings.decision_timeout = Duration::from_millis(100);
+        })
+        .await;
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(receiver.state.lock().await.receive_session.is_none());
+        assert!(matches!(
+            receiver.server_rx.try_recv(),
+            Ok(ServerMessage::SelectionTimedOut(_))
+        ));
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_reject_malformed_prepare_upload() {
+        let receiver = TestReceiver::start().await;
+        let dto = serde_json::json!({
+            "info": {"alias": "sender", "version": "2.0", "fingerprint": "sender"},
+            "files": {"0": {"id": "1", "fileName": "0.bin", "size": 4, "fileType": "bin"}},
+        });
+        let response = reqwest::Client::new()
+            .post(receiver.url(ApiRoute::PrepareUpload))
+            .json(&dto)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidParameters);
+        assert!(error.message.contains(r#"files["0"]"#), "{}", error.message);
+        assert!(receiver.state.lock().await.receive_session.is_none());
+        receiver.stop().await;
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingHook(std::sync::Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl ReceiveHook for RecordingHook {
+        async fn on_file_received(&self, info: &ReceivedFileInfo) -> Result<(), String> {
+            assert_eq!(std::fs::read(info.path.as_ref().unwrap()).unwrap(), b"0000");
+            self.0.lock().unwrap().push(info.file.file_name.clone());
+            match info.file.id.as_str() {
+                "1" => Err("exit status: 1".to_owned()),
+                "2" => std::future::pending().await,
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_hook() {
+        let hook = Arc::new(RecordingHook::default());
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.receive_hook = Some(hook.clone());
+            state.settings.hook_timeout = Duration::from_millis(200);
+        })
+        .await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare(&["0", "1", "2"])
+            .await
+            .json()
+            .await
+            .unwrap();
+        for file_id in ["0", "1", "2"] {
+            let response = receiver
+                .upload(&session, file_id, "0000")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv()).await;
+        let Ok(Some(ServerMessage::SessionFinished(report))) = message else {
+            panic!("unexpected message: {:?}", message);
+        };
+        // failed hooks leave the files finished
+        assert!(report
+            .files
+            .iter()
+            .all(|f| f.status == FileStatus::Finished));
+        let errors: Vec<_> = report.files.iter().map(|f| f.hook_error.clone()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                None,
+                Some("exit status: 1".to_owned()),
+                Some("timed out after 200ms".to_owned())
+            ]
+        );
+        assert_eq!(hook.0.lock().unwrap().len(), 3);
+        receiver.stop().await;
+    }
+
+    /// Discards the data, pausing after every mebibyte like a slow disk.
+    #[derive(Default)]
+    struct SlowWriter {
+        written: u64,
+        pause: Option<Pin<Box<tokio::time::Sleep>>>,
+    }
+
+    impl AsyncWrite for SlowWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if let Some(pause) = self.pause.as_mut() {
+                ready!(pause.as_mut().poll(cx));
+                self.pause = None;
+            }
+            let before = self.written >> 20;
+            self.written += buf.len() as u64;
+            if self.written >> 20 != before {
+                self.pause = Some(Box::pin(tokio::time::sleep(Duration::from_millis(1))));
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[derive(Debug)]
+    struct SlowSink;
+
+    #[async_trait]
+    impl ReceiveSink for SlowSink {
+        async fn open(&self, _file: &FileDto) -> io::Result<SinkWriter> {
+            Ok(Box::pin(SlowWriter::default()))
+        }
+
+        async fn finish(&self, _file: &FileDto) -> io::Result<Option<PathBuf>> {
+            Ok(None)
+        }
+
+        async fn abort(&self, _file: &FileDto) {}
+    }
+
+    /// Resident memory of the test process in bytes.
+    #[cfg(target_os = "linux")]
+    fn rss() -> u64 {
+        let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+        let pages: u64 = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+        pages * 4096
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_backpressure() {
+        const SIZE: u64 = 1 << 30;
+        const CHUNK: usize = 1 << 16;
+
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.sink_factory = Some(SinkFactory::new(|_| Arc::new(SlowSink)));
+        })
+        .await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare_sized(&[("0", SIZE)])
+            .await
+            .json()
+            .await
+            .unwrap();
+
+        let baseline = rss();
+        let peak = Arc::new(AtomicU64::new(baseline));
+        let sampler = {
+            let peak = peak.clone();
+            tokio::spawn(async move {
+                loop {
+                    peak.fetch_max(rss(), Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            })
+        };
+        let zeros = futures_util::stream::iter(0..SIZE / CHUNK as u64)
+            .map(|_| io::Result::Ok(vec![0u8; CHUNK]));
+        let response = receiver
+            .upload(&session, "0", Body::wrap_stream(zeros))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        sampler.abort();
+
+        // the sender is held back instead of the body piling up in memory
+        let growth = peak.load(Ordering::Relaxed).saturating_sub(baseline);
+        assert!(growth < 128 << 20, "memory grew by {} bytes", growth);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_uploads() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.max_concurrent_uploads = Some(1);
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+
+        let first = tokio::spawn(receiver.upload(&session, "0", stalled_body()).send());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let second = tokio::spawn(receiver.upload(&session, "1", "1111").send());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // waits for the slot of the stalled upload
+        assert!(!second.is_finished());
+        assert!(!receiver.destination.join("1.bin").exists());
+
+        first.abort();
+        let response = tokio::time::timeout(Duration::from_secs(5), second)
+            .await
+            .expect("upload still waiting")
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            std::fs::read(receiver.destination.join("1.bin")).unwrap(),
+            b"1111"
+        );
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_destination_unavailable() {
+        let blocked = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&blocked, "").unwrap();
+        let destination = blocked.join("incoming");
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.destination.clone_from(&destination);
+        })
+        .await;
+
+        // refused before any upload, the sender is told why
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::DestinationLost);
+        assert!(
+            error.message.contains("is not a directory"),
+            "{}",
+            error.message
+        );
+        assert!(receiver.state.lock().await.receive_session.is_none());
+
+        receiver.stop().await;
+        std::fs::remove_file(blocked).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_destination_template() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.collision_policy = CollisionPolicy::Rename;
+            let template = state.settings.destination.join("{alias}");
+            state.settings.destination = template;
+        })
+        .await;
+        let directory = receiver.destination.join("sender");
+
+        for saved_name in ["0.bin", "0 (1).bin"] {
+            let session: PrepareUploadResponseDto =
+                receiver.prepare(&["0"]).await.json().await.unwrap();
+            // nothing is created before the first write
+            if saved_name == "0.bin" {
+                assert!(!directory.exists());
+            }
+            let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv());
+            match message.await {
+                Ok(Some(ServerMessage::SessionFinished(report))) => {
+                    assert_eq!(report.destination, directory);
+                    assert_eq!(report.files[0].path, Some(directory.join(saved_name)));
+                }
+                message => panic!("unexpected message: {:?}", message),
+            }
+        }
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_reload_settings() {
+        let mut receiver = TestReceiver::start().await;
+        let next = receiver.destination.join("next");
+        let loader = {
+            let next = next.clone();
+            SettingsLoader::new(move || {
+                Ok(Settings {
+                    quick_save: true,
+                    destination: next.clone(),
+                    ..Settings::default()
+                })
+            })
+        };
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(reload_settings(&receiver.state, &loader).await, Ok(2));
+        // the running session goes on with the settings it started with
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv());
+        match message.await {
+            Ok(Some(ServerMessage::SessionFinished(report))) => {
+                assert_eq!(report.settings_version, 1);
+                assert_eq!(report.destination, receiver.destination);
+                assert_eq!(
+                    report.files[1].path,
+                    Some(receiver.destination.join("1.bin"))
+                );
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert!(!next.exists());
+
+        // a broken config keeps the settings before
+        let broken = SettingsLoader::new(|| Err("invalid config".to_owned()));
+        assert!(reload_settings(&receiver.state, &broken).await.is_err());
+        assert_eq!(receiver.state.lock().await.settings_version, 2);
+
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["2"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "2", "2222").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv());
+        match message.await {
+            Ok(Some(ServerMessage::SessionFinished(report))) => {
+                assert_eq!(report.settings_version, 2);
+                assert_eq!(report.files[0].path, Some(next.join("2.bin")));
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_destination_lost() {
+        let mut receiver = TestReceiver::start().await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare(&["0", "1", "2"])
+            .await
+            .json()
+            .await
+            .unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // like an unplugged drive, the next upload is refused without reading its body
+        std::fs::remove_dir_all(&receiver.destination).unwrap();
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::DestinationLost);
+        assert!(error.message.contains("no longer exists"));
+
+        let report = match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => report,
+            message => panic!("unexpected message: {:?}", message),
+        };
+        assert!(report.destination_lost.is_some());
+        assert_eq!(report.finished(), 1);
+        assert!(report.files[1..]
+            .iter()
+            .all(|f| f.status == FileStatus::Failed));
+
+        // the other files are refused right away too
+        let response = receiver.upload(&session, "2", "2222").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::DestinationLost);
+
+        // the next session saves again once the destination is back
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["3"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "3", "3333").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(receiver.destination.join("3.bin").exists());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_append_policy() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.collision_policy = CollisionPolicy::Append;
+        })
+        .await;
+
+        for (content, appended_to_size) in [("0000", None), ("1111", Some(8))] {
+            let session: PrepareUploadResponseDto =
+                receiver.prepare(&["0"]).await.json().await.unwrap();
+            let response = receiver
+                .upload(&session, "0", content)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            match receiver.server_rx.recv().await {
+                Some(ServerMessage::SessionFinished(report)) => {
+                    assert_eq!(
+                        report.files[0].path,
+                        Some(receiver.destination.join("0.bin"))
+                    );
+                    assert_eq!(report.files[0].bytes, 4);
+                    assert_eq!(report.files[0].appended_to_size, appended_to_size);
+                }
+                message => panic!("unexpected message: {:?}", message),
+            }
+        }
+        assert_eq!(
+            std::fs::read(receiver.destination.join("0.bin")).unwrap(),
+            b"00001111"
+        );
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_case_insensitive_names() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.collision_policy = CollisionPolicy::Rename;
+            state.settings.case_sensitivity = Some(CaseSensitivity::Insensitive);
+        })
+        .await;
+        let files = [("0", "Readme.md"), ("1", "README.md")]
+            .into_iter()
+            .map(|(id, name)| FileDto {
+                id: id.to_owned(),
+                file_name: name.to_owned(),
+                size: 4,
+                file_type: FileType::Other,
+                hash: None,
+                preview: None,
+            })
+            .collect();
+        let session: PrepareUploadResponseDto =
+            receiver.prepare_files(files).await.json().await.unwrap();
+        for (id, content) in [("0", "0000"), ("1", "1111")] {
+            let response = receiver.upload(&session, id, content).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => assert_eq!(report.finished(), 2),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        // the second name only differs in case, it is renamed instead of replacing the first
+        let destination = &receiver.destination;
+        assert_eq!(
+            std::fs::read(destination.join("Readme.md")).unwrap(),
+            b"0000"
+        );
+        assert_eq!(
+            std::fs::read(destination.join("README (1).md")).unwrap(),
+            b"1111"
+        );
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_final_names() {
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.collision_policy = CollisionPolicy::Rename;
+        })
+        .await;
+        let destination = &receiver.destination;
+        std::fs::create_dir_all(destination).unwrap();
+        std::fs::write(destination.join("a.txt"), "taken").unwrap();
+        let files = [("0", "a.txt"), ("1", "b.txt")]
+            .into_iter()
+            .map(|(id, name)| FileDto {
+                id: id.to_owned(),
+                file_name: name.to_owned(),
+                size: 4,
+                file_type: FileType::Other,
+                hash: None,
+                preview: None,
+            })
+            .collect();
+        let session: PrepareUploadResponseDto =
+            receiver.prepare_files(files).await.json().await.unwrap();
+        assert_eq!(
+            session.final_names,
+            HashMap::from([("0".to_owned(), "a (1).txt".to_owned())])
+        );
+
+        // taken after the sender was told, the upload response has the name it got
+        std::fs::write(destination.join("b.txt"), "taken").unwrap();
+        let mut saved = vec![];
+        for (id, content) in [("0", "0000"), ("1", "1111")] {
+            let response = receiver.upload(&session, id, content).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let response: UploadResponseDto = response.json().await.unwrap();
+            saved.push(response.final_name.unwrap());
+        }
+        assert_eq!(saved, ["a (1).txt", "b (1).txt"]);
+        assert_eq!(
+            std::fs::read(destination.join("b (1).txt")).unwrap(),
+            b"1111"
+        );
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let log = std::env::temp_dir().join(format!("{}.jsonl", uuid::Uuid::new_v4()));
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.audit = true;
+            state.settings.audit_log = Some(log.clone());
+            // features writing files are off while auditing
+            state.settings.archive = Some(PathBuf::from("received.tar"));
+            state.settings.dedup_index = Some(state.settings.destination.join("index.json"));
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        match receiver.server_rx.recv().await {
+            Some(ServerMessage::SessionFinished(report)) => {
+                assert!(report.audited);
+                assert_eq!(report.finished(), 2);
+                assert!(report.files.iter().all(|file| file.path.is_none()));
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert!(!receiver.destination.exists());
+
+        let log_content = std::fs::read_to_string(&log).unwrap();
+        let records: Vec<AuditRecord> = log_content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        let finished = records.iter().find(|r| r.file_name == "0.bin").unwrap();
+        assert_eq!(finished.status, FileStatus::Finished);
+        assert_eq!((finished.size, finished.bytes), (4, 4));
+        assert_eq!(finished.sender_fingerprint, "sender");
+        assert_eq!(
+            finished.sha256.as_deref(),
+            Some(FileHash::sha256("0000").hex())
+        );
+        receiver.stop().await;
+        std::fs::remove_file(log).ok();
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let journals = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.journal_dir = Some(journals.clone());
+        })
+        .await;
+        let port_dir = journals.join(receiver.server.local_addr().port().to_string());
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        let journal = port_dir.join(format!("{}.json", session.session_id));
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let upload = tokio::spawn(receiver.upload(&session, "1", stalled_body()).send());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let entry: JournalEntry =
+            serde_json::from_slice(&std::fs::read(&journal).unwrap()).unwrap();
+        assert_eq!(entry.sender_fingerprint, "sender");
+        let finished = &entry.files.iter().find(|file| file.id == "0").unwrap();
+        assert!(finished.finished);
+        let partial = &entry.files.iter().find(|file| file.id == "1").unwrap();
+        assert!(!partial.finished);
+        assert_eq!(partial.path, Some(receiver.destination.join("1.bin")));
+
+        // what a crash during the upload leaves behind, a clean stop leaves nothing
+        let destination = receiver.destination.clone();
+        receiver.stop().await;
+        upload.await.ok();
+        assert!(!journal.exists());
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("0.bin"), "0000").unwrap();
+        std::fs::write(destination.join("1.bin"), "11").unwrap();
+        std::fs::write(&journal, serde_json::to_vec(&entry).unwrap()).unwrap();
+
+        let cleanup = clean_stale_journals(&journals, false);
+        assert_eq!(cleanup.sessions, vec![entry]);
+        assert_eq!(cleanup.removed, vec![destination.join("1.bin")]);
+        assert!(destination.join("0.bin").exists());
+        assert!(!destination.join("1.bin").exists());
+        assert!(!port_dir.exists());
+        std::fs::remove_dir_all(destination).ok();
+        std::fs::remove_dir_all(journals).ok();
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.dedup_index = Some(state.settings.destination.join("index.json"));
+            state.settings.dedup_action = DedupAction::Link;
+        })
+        .await;
+        let file = |id: &str, name: &str, content: &str| FileDto {
+            id: id.to_owned(),
+            file_name: name.to_owned(),
+            size: content.len() as u64,
+            file_type: FileType::Other,
+            hash: Some(FileHash::sha256(content).to_string()),
+            preview: None,
+        };
+
+        let session: PrepareUploadResponseDto = receiver
+            .prepare_files(vec![file("0", "0.bin", "0000")])
+            .await
+            .json()
+            .await
+            .unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        receiver.server_rx.recv().await.unwrap();
+
+        // sent again under another name, only the new file gets a token
+        let files = vec![file("0", "copy.bin", "0000"), file("1", "1.bin", "1111")];
+        let session: PrepareUploadResponseDto =
+            receiver.prepare_files(files).await.json().await.unwrap();
+        assert_eq!(session.files.keys().collect::<Vec<_>>(), ["1"]);
+        assert_eq!(
+            session.filtered,
+            HashMap::from([("0".to_owned(), "Already received".to_owned())])
+        );
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let Some(ServerMessage::SessionFinished(report)) = receiver.server_rx.recv().await else {
+            panic!("session not finished");
+        };
+        assert_eq!((report.finished(), report.deduplicated()), (1, 1));
+        assert_eq!(report.total_bytes, 4);
+        let copy = &report.files[1];
+        assert_eq!(copy.duplicate_of, Some(receiver.destination.join("0.bin")));
+        assert_eq!(copy.path, Some(receiver.destination.join("copy.bin")));
+        assert_eq!(
+            std::fs::read(receiver.destination.join("copy.bin")).unwrap(),
+            b"0000"
+        );
+
+        // nothing left to upload, the session ends right away
+        let response = receiver
+            .prepare_files(vec![file("1", "1.bin", "1111")])
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let Some(ServerMessage::SessionFinished(report)) = receiver.server_rx.recv().await else {
+            panic!("session not finished");
+        };
+        assert_eq!((report.finished(), report.deduplicated()), (0, 1));
+        assert!(receiver.state.lock().await.receive_session.is_none());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_structure_limits() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.structure_limits = StructureLimits {
+                max_depth: 2,
+                max_directories: 2,
+                ..StructureLimits::default()
+            };
+        })
+        .await;
+        let file = |id: &str, name: &str| FileDto {
+            id: id.to_owned(),
+            file_name: name.to_owned(),
+            size: 4,
+            file_type: FileType::Other,
+            hash: None,
+            preview: None,
+        };
+        let files = || {
+            vec![
+                file("0", "a/b/ok.bin"),
+                file("1", "a/b/c/deep.bin"),
+                file("2", "x/y.bin"),
+            ]
+        };
+
+        // the files beyond the limits are left out
+        let session: PrepareUploadResponseDto =
+            receiver.prepare_files(files()).await.json().await.unwrap();
+        assert_eq!(session.files.keys().collect::<Vec<_>>(), ["0"]);
+        // and the sender is told why
+        assert_eq!(
+            session.filtered.get("1").map(String::as_str),
+            Some("Exceeds the limit of 2 nested directories")
+        );
+        assert_eq!(session.filtered.len(), 2);
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let Some(ServerMessage::SessionFinished(report)) = receiver.server_rx.recv().await else {
+            panic!("session not finished");
+        };
+        let reasons: Vec<_> = report.files.iter().map(|f| f.reason.as_deref()).collect();
+        assert!(reasons.contains(&Some("Exceeds the limit of 2 nested directories")));
+        assert!(reasons.contains(&Some("Exceeds the limit of 2 directories per session")));
+        assert!(receiver.destination.join("a/b/ok.bin").exists());
+        assert!(!receiver.destination.join("x").exists());
+
+        // or the whole offer is refused, naming the file and the limit
+        receiver.state.lock().await.settings.strict_structure = true;
+        let response = receiver.prepare_files(files()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::StructureLimitExceeded);
+        assert_eq!(
+            error.message,
+            "\"a/b/c/deep.bin\" exceeds the limit of 2 nested directories"
+        );
+        assert!(receiver.state.lock().await.receive_session.is_none());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_success() {
+        let mut receiver = TestReceiver::start().await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let saved: UploadResponseDto = response.json().await.unwrap();
+        assert_eq!(saved.bytes, 4);
+
+        // answered without reading the body again
+        let response = receiver.upload(&session, "0", "").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<UploadResponseDto>().await.unwrap(), saved);
+        assert_eq!(
+            std::fs::read(receiver.destination.join("0.bin")).unwrap(),
+            b"0000"
+        );
+
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv());
+        assert!(matches!(
+            message.await,
+            Ok(Some(ServerMessage::SessionFinished(_)))
+        ));
+        // the last file is answered after the session finished as well
+        let response = receiver.upload(&session, "1", "xxxx").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // a file changed since is not taken as received
+        std::fs::write(receiver.destination.join("1.bin"), b"1").unwrap();
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_short_body() {
+        let receiver = TestReceiver::start().await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        // an empty retry of a file that was never received must not save it empty
+        let response = receiver
+            .upload(&session, "0", "")
+            .header(reqwest::header::CONTENT_LENGTH, 0)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!receiver.destination.join("0.bin").exists());
+
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_retry_during_write() {
+        let receiver = TestReceiver::start().await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        let upload = tokio::spawn(receiver.upload(&session, "0", stalled_body()).send());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidState);
+
+        upload.abort();
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_wrong_token() {
+        let mut receiver = TestReceiver::start().await;
+        let mut session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv());
+        assert!(matches!(
+            message.await,
+            Ok(Some(ServerMessage::SessionFinished(_)))
+        ));
+
+        session.files.insert("0".to_owned(), "wrong".to_owned());
+        let response = receiver.upload(&session, "0", "xxxx").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let error: ErrorDto = response.json().await.unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidToken);
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_single_use_tokens() {
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.token_issuer = Arc::new(RandomTokens);
+            state.settings.token_policy.single_use = true;
+        })
+        .await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+        assert_eq!(session.files["0"].len(), 43);
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // not answered like a retry, the token is spent
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv());
+        assert!(matches!(
+            message.await,
+            Ok(Some(ServerMessage::SessionFinished(_)))
+        ));
+        let response = receiver.upload(&session, "1", "1111").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        receiver.stop().await;
+    }
+
+    /// Accepts the offered files and keeps only the first previewed one.
+    #[derive(Debug, Default)]
+    struct Previewing {
+        offered: std::sync::Mutex<Vec<String>>,
+        previewed: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ReceiveDecider for Previewing {
+        async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+            *self.offered.lock().unwrap() = files.iter().map(|f| f.id.clone()).collect();
+            Decision::Accept(files)
+        }
+
+        async fn review(&self, _sender: Device, files: Vec<PreviewFile>) -> Vec<String> {
+            for preview in &files {
+                assert_eq!(std::fs::read(&preview.path).unwrap(), b"0000");
+            }
+            let ids: Vec<_> = files.into_iter().map(|f| f.file.id).collect();
+            self.previewed.lock().unwrap().clone_from(&ids);
+            ids.into_iter().take(1).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preview_quarantine() {
+        let decider = Arc::new(Previewing::default());
+        let preview_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let stale = preview_dir.join(format!("{}crashed", QUARANTINE_PREFIX));
+        std::fs::create_dir_all(&stale).unwrap();
+        let mut receiver = TestReceiver::start_with(|state| {
+            state.decider = decider.clone();
+            state.settings.preview_dir = Some(preview_dir.clone());
+            state.settings.preview_max_size = 4;
+        })
+        .await;
+        // left over from an earlier run
+        assert!(!stale.exists());
+
+        let session: PrepareUploadResponseDto = receiver
+            .prepare_sized(&[("0", 4), ("1", 4), ("2", 8)])
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(session.files.len(), 3);
+        // only the large file is asked about
+        assert_eq!(*decider.offered.lock().unwrap(), vec!["2"]);
+
+        for (file_id, body) in [("0", "0000"), ("1", "0000"), ("2", "22222222")] {
+            // quarantined until the session finished and they were reviewed
+            assert!(!receiver.destination.join("0.bin").exists());
+            let response = receiver
+                .upload(&session, file_id, body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv()).await;
+        let Ok(Some(ServerMessage::SessionFinished(report))) = message else {
+            panic!("unexpected message: {:?}", message);
+        };
+        assert_eq!(*decider.previewed.lock().unwrap(), vec!["0", "1"]);
+        let statuses: Vec<_> = report.files.iter().map(|f| f.status.clone()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                FileStatus::Finished,
+                FileStatus::Skipped,
+                FileStatus::Finished
+            ]
+        );
+        assert_eq!(
+            report.files[1].reason.as_deref(),
+            Some("Discarded after preview")
+        );
+        assert_eq!(
+            report.files[0].path,
+            Some(receiver.destination.join("0.bin"))
+        );
+        assert_eq!(
+            std::fs::read(receiver.destination.join("0.bin")).unwrap(),
+            b"0000"
+        );
+        assert!(!receiver.destination.join("1.bin").exists());
+        assert_eq!(std::fs::read_dir(&preview_dir).unwrap().count(), 0);
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(preview_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_receive_rate_limit() {
+        const RATE: u64 = 1 << 20;
+        const SIZE: u64 = 1 << 20;
+        const CHUNK: usize = 1 << 14;
+
+        let receiver = TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.receive_rate_limit = Some(RATE);
+        })
+        .await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare_sized(&[("0", SIZE), ("1", SIZE)])
+            .await
+            .json()
+            .await
+            .unwrap();
+        let mut events = receiver.state.lock().await.events.subscribe();
+        let start = Instant::now();
+        // when the first half of file 0 was reported
+        let halfway = tokio::spawn(async move {
+            while let Ok(event) = events.recv().await {
+                if let SessionEvent::FileProgress {
+                    file_id, position, ..
+                } = event
+                {
+                    if file_id == "0" && position >= SIZE / 2 {
+                        return start.elapsed();
+                    }
+                }
+            }
+            unreachable!()
+        });
+
+        // both files share the rate of the session
+        let uploads = ["0", "1"].map(|id| {
+            let zeros = futures_util::stream::iter(0..SIZE / CHUNK as u64)
+                .map(|_| io::Result::Ok(vec![0u8; CHUNK]));
+            receiver
+                .upload(&session, id, Body::wrap_stream(zeros))
+                .send()
+        });
+        for response in futures_util::future::join_all(uploads).await {
+            assert_eq!(response.unwrap().status(), StatusCode::OK);
+        }
+        let elapsed = start.elapsed();
+
+        let throughput = (2 * SIZE) as f64 / elapsed.as_secs_f64();
+        let deviation = (throughput - RATE as f64).abs() / RATE as f64;
+        assert!(deviation < 0.15, "received {:.0} bytes/s", throughput);
+
+        // progress follows the paced reads, each file gets half of the rate
+        let halfway = halfway.await.unwrap();
+        assert!(halfway > Duration::from_millis(800), "{:?}", halfway);
+        receiver.stop().await;
+    }
+}
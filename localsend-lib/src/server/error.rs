@@ -1,4 +1,8 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 
 use crate::{error::Error, receive::ReceiveError, send::SendError};
 
@@ -15,10 +19,13 @@ impl Error {
 impl From<&ReceiveError> for StatusCode {
     fn from(value: &ReceiveError) -> Self {
         match value {
-            ReceiveError::Cancelled => StatusCode::OK,           // 200
+            ReceiveError::Cancelled => StatusCode::OK, // 200
+            ReceiveError::DecisionTimeout => StatusCode::REQUEST_TIMEOUT, // 408
+            ReceiveError::DownloadUnsupported => StatusCode::BAD_REQUEST, // 400
             ReceiveError::EmptyFiles => StatusCode::BAD_REQUEST, // 400
             ReceiveError::InvalidIp(_) => StatusCode::FORBIDDEN, // 403
             ReceiveError::InvalidParameters => StatusCode::BAD_REQUEST, // 400
+            ReceiveError::InvalidDto(_) => StatusCode::BAD_REQUEST, // 400
             ReceiveError::InvalidRecipient => StatusCode::CONFLICT, // 409
             ReceiveError::InvalidServerState => StatusCode::INTERNAL_SERVER_ERROR, // 500
             ReceiveError::InvalidSessionId => StatusCode::FORBIDDEN, // 403
@@ -26,8 +33,14 @@ impl From<&ReceiveError> for StatusCode {
             ReceiveError::NothingSelected => StatusCode::NO_CONTENT, // 204
             ReceiveError::SaveFileFailed => StatusCode::INTERNAL_SERVER_ERROR, // 500
             ReceiveError::SessionBlocked => StatusCode::CONFLICT, // 409
+            ReceiveError::QueueFull(_) => StatusCode::TOO_MANY_REQUESTS, // 429
+            ReceiveError::Closed => StatusCode::CONFLICT, // 409
             ReceiveError::SessionDeclined => StatusCode::FORBIDDEN, // 403
             ReceiveError::SessionNotExists => StatusCode::CONFLICT, // 409
+            ReceiveError::StructureLimitExceeded { .. } => StatusCode::BAD_REQUEST, // 400
+            ReceiveError::UploadInProgress => StatusCode::CONFLICT, // 409
+            ReceiveError::DestinationLost(_) => StatusCode::INTERNAL_SERVER_ERROR, // 500
+            ReceiveError::DestinationUnavailable(_) => StatusCode::INTERNAL_SERVER_ERROR, // 500
         }
     }
 }
@@ -44,11 +57,24 @@ impl From<&SendError> for StatusCode {
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         let status_code = self.status_code();
-        let message = if let StatusCode::INTERNAL_SERVER_ERROR = status_code {
-            "Internal server error".to_owned()
-        } else {
-            self.to_string()
-        };
-        (status_code, message).into_response()
+        let mut dto = self.to_dto();
+        // the sender may show why it should not retry
+        let lost = matches!(
+            self,
+            Error::Receive(
+                ReceiveError::DestinationLost(_) | ReceiveError::DestinationUnavailable(_)
+            )
+        );
+        if status_code == StatusCode::INTERNAL_SERVER_ERROR && !lost {
+            "Internal server error".clone_into(&mut dto.message);
+        }
+        let mut response = (status_code, Json(dto)).into_response();
+        if let Error::Receive(ReceiveError::QueueFull(retry_after)) = &self {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs()),
+            );
+        }
+        response
     }
 }
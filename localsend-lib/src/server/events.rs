@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use localsend_proto::{dto::FileDto, Device};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{receive::ReceiveReport, send::FileStatus};
+
+/// Events a subscriber may fall behind by before it misses the oldest ones.
+pub const EVENT_CAPACITY: usize = 1024;
+/// `SessionEvent::FileProgress` of a file is published at most this often.
+pub const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which side ended a session early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CancelledBy {
+    Sender,
+    Receiver,
+}
+
+/// What happens to the receive and send sessions of a server, published on its [`EventBus`].
+///
+/// The events of one session arrive in the order they happened: a receive goes
+/// `ReceiveRequested`, then `ReceiveAccepted` or `ReceiveDeclined`, then per file
+/// `FileStarted`, `FileProgress` and `FileFinished`, and ends with `SessionFinished`,
+/// `SessionCancelled`, `SessionExpired` or `SessionFailed`. A send starts with
+/// `SendStarted` and ends with `SendFinished` or `SessionCancelled`. Files uploaded
+/// in parallel interleave, events of different sessions are not ordered.
+///
+/// `session_id` is the id the receiver assigned for receives and the local id of
+/// the [`crate::send::SendSession`] for sends.
+///
+/// As JSON an event is an object with its fields in camelCase and its variant
+/// in `type`, e.g. `{"type": "fileStarted", "sessionId": "…", "fileId": "…"}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum SessionEvent {
+    /// A device offers files, the decider is asked next
+    ReceiveRequested {
+        session_id: String,
+        sender: Device,
+        files: Vec<FileDto>,
+    },
+    /// The files that will be received, again for files added to a running session
+    ReceiveAccepted {
+        session_id: String,
+        files: Vec<FileDto>,
+    },
+    /// Nothing of the offer is received, it was declined, timed out or nothing was selected
+    ReceiveDeclined {
+        session_id: String,
+    },
+    /// The receiver accepted, the files are uploaded next
+    SendStarted {
+        session_id: String,
+        target: Device,
+        files: Vec<FileDto>,
+    },
+    FileStarted {
+        session_id: String,
+        file_id: String,
+    },
+    /// Bytes of the file transferred so far, see [`PROGRESS_EVENT_INTERVAL`]
+    FileProgress {
+        session_id: String,
+        file_id: String,
+        position: u64,
+    },
+    FileFinished {
+        session_id: String,
+        file_id: String,
+        status: FileStatus,
+    },
+    /// A receive ended, after the receive hooks and the preview review
+    SessionFinished {
+        report: ReceiveReport,
+    },
+    /// A send ended without being cancelled, failed files included
+    SendFinished {
+        session_id: String,
+    },
+    SessionCancelled {
+        session_id: String,
+        by: CancelledBy,
+    },
+    /// A receive was dropped after the sender stopped uploading
+    SessionExpired {
+        session_id: String,
+    },
+    /// A session was dropped for another reason, e.g. a change of the network
+    SessionFailed {
+        session_id: String,
+        reason: String,
+    },
+}
+
+impl SessionEvent {
+    /// The session the event belongs to.
+    pub fn session_id(&self) -> &str {
+        match self {
+            SessionEvent::SessionFinished { report } => &report.session_id,
+            SessionEvent::ReceiveRequested { session_id, .. }
+            | SessionEvent::ReceiveAccepted { session_id, .. }
+            | SessionEvent::ReceiveDeclined { session_id }
+            | SessionEvent::SendStarted { session_id, .. }
+            | SessionEvent::FileStarted { session_id, .. }
+            | SessionEvent::FileProgress { session_id, .. }
+            | SessionEvent::FileFinished { session_id, .. }
+            | SessionEvent::SendFinished { session_id }
+            | SessionEvent::SessionCancelled { session_id, .. }
+            | SessionEvent::SessionExpired { session_id }
+            | SessionEvent::SessionFailed { session_id, .. } => session_id,
+        }
+    }
+}
+
+/// Publishes [`SessionEvent`]s to every subscriber, events nobody listens to are dropped.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<SessionEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Receives the events published from now on.
+    ///
+    /// A subscriber more than the capacity behind gets `RecvError::Lagged` and
+    /// continues with the oldest event still kept.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn emit(&self, event: SessionEvent) {
+        self.tx.send(event).ok();
+    }
+
+    /// How many subscribers there are, including lagging ones.
+    pub fn receiver_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    pub(crate) fn file_progress(&self, session_id: &str, file_id: &str) -> ProgressEvents {
+        ProgressEvents {
+            bus: self.clone(),
+            session_id: session_id.to_owned(),
+            file_id: file_id.to_owned(),
+            last: None,
+        }
+    }
+}
+
+/// Publishes the progress of one file, at most every [`PROGRESS_EVENT_INTERVAL`].
+#[derive(Debug)]
+pub(crate) struct ProgressEvents {
+    bus: EventBus,
+    session_id: String,
+    file_id: String,
+    last: Option<Instant>,
+}
+
+impl ProgressEvents {
+    /// Publishes `position` when the interval passed or the file is `complete`.
+    pub(crate) fn update(&mut self, position: u64, complete: bool) {
+        let now = Instant::now();
+        let due = self.last.map_or(true, |last| {
+            now.duration_since(last) >= PROGRESS_EVENT_INTERVAL
+        });
+        if !due && !complete {
+            return;
+        }
+        self.last = Some(now);
+        self.bus.emit(SessionEvent::FileProgress {
+            session_id: self.session_id.clone(),
+            file_id: self.file_id.clone(),
+            position,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelledBy, EventBus, SessionEvent};
+
+    #[test]
+    fn test_progress_events() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut progress = bus.file_progress("session", "file");
+        progress.update(10, false);
+        // within the interval, only the end of the file is published
+        progress.update(20, false);
+        progress.update(30, true);
+
+        let mut positions = vec![];
+        while let Ok(event) = events.try_recv() {
+            let SessionEvent::FileProgress { position, .. } = event else {
+                panic!("unexpected event: {:?}", event);
+            };
+            positions.push(position);
+        }
+        assert_eq!(positions, [10, 30]);
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = SessionEvent::FileProgress {
+            session_id: "session".to_owned(),
+            file_id: "file".to_owned(),
+            position: 10,
+        };
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            serde_json::json!({
+                "type": "fileProgress",
+                "sessionId": "session",
+                "fileId": "file",
+                "position": 10,
+            })
+        );
+        let event = SessionEvent::SessionCancelled {
+            session_id: "session".to_owned(),
+            by: CancelledBy::Sender,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"sessionCancelled","sessionId":"session","by":"sender"}"#
+        );
+    }
+}
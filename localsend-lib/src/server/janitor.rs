@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::receive::ReceiveSessionStatus;
+
+use super::{MutexServerState, ServerMessage, SessionEvent};
+
+/// Drops receive sessions whose sender stopped uploading.
+///
+/// Only accepted sessions expire, a session waiting for the user to select
+/// files is dropped when the prepare-upload request ends.
+pub(crate) fn spawn(state: MutexServerState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let timeout = state.lock().await.settings.session_timeout;
+            tokio::time::sleep(check_interval(timeout)).await;
+            expire(&state).await;
+        }
+    })
+}
+
+fn check_interval(timeout: Duration) -> Duration {
+    (timeout / 4).clamp(Duration::from_millis(10), Duration::from_secs(5))
+}
+
+async fn expire(state: &MutexServerState) {
+    let mut state = state.lock().await;
+    let Some(session) = &state.receive_session else {
+        return;
+    };
+    // a session expires after the timeout it started with
+    let timeout = session.settings.session_timeout;
+    if session.status != ReceiveSessionStatus::Sending || session.last_activity.idle() < timeout {
+        return;
+    }
+    let Some(mut session) = state.receive_session.take() else {
+        return;
+    };
+    let server_tx = state.server_tx.clone();
+    let events = state.events.clone();
+    drop(state);
+
+    log::warn!(
+        "Session {} expired after {:.0}s without activity",
+        session.session_id,
+        timeout.as_secs_f64()
+    );
+    session.abort().await;
+    events.emit(SessionEvent::SessionExpired {
+        session_id: session.session_id.clone(),
+    });
+    server_tx
+        .send(ServerMessage::SessionExpired(session.session_id))
+        .await
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use localsend_proto::dto::PrepareUploadResponseDto;
+    use reqwest::{Body, StatusCode};
+
+    use crate::{server::ServerMessage, test_util::TestReceiver};
+
+    async fn receiver(timeout: Duration) -> TestReceiver {
+        TestReceiver::start_with(|state| {
+            state.settings.quick_save = true;
+            state.settings.session_timeout = timeout;
+        })
+        .await
+    }
+
+    async fn expect_expired(receiver: &mut TestReceiver, session_id: &str) {
+        let message = tokio::time::timeout(Duration::from_secs(5), receiver.server_rx.recv()).await;
+        match message {
+            Ok(Some(ServerMessage::SessionExpired(id))) => assert_eq!(id, session_id),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert!(receiver.state.lock().await.receive_session.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expire_after_prepare_upload() {
+        let mut receiver = receiver(Duration::from_millis(200)).await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0"]).await.json().await.unwrap();
+
+        // the sender vanishes, a new session is blocked until the old one expires
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        expect_expired(&mut receiver, &session.session_id).await;
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_expire_between_files() {
+        let mut receiver = receiver(Duration::from_millis(300)).await;
+        let session: PrepareUploadResponseDto =
+            receiver.prepare(&["0", "1"]).await.json().await.unwrap();
+
+        let response = receiver.upload(&session, "0", "0000").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        expect_expired(&mut receiver, &session.session_id).await;
+
+        assert_eq!(
+            std::fs::read(receiver.destination.join("0.bin")).unwrap(),
+            b"0000"
+        );
+        assert!(!receiver.destination.join("1.bin").exists());
+        receiver.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_slow_upload_and_stall() {
+        let mut receiver = receiver(Duration::from_millis(300)).await;
+        let session: PrepareUploadResponseDto = receiver
+            .prepare_sized(&[("0", 10), ("1", 10)])
+            .await
+            .json()
+            .await
+            .unwrap();
+
+        // slower than the timeout in total, but never idle for that long
+        let slow = async_stream::stream! {
+            for _ in 0..10 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                yield std::io::Result::Ok(b"0".to_vec());
+            }
+        };
+        let response = receiver
+            .upload(&session, "0", Body::wrap_stream(slow))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // stalls in the middle of a file
+        let stalled = async_stream::stream! {
+            yield std::io::Result::Ok(b"1".to_vec());
+            std::future::pending::<()>().await;
+        };
+        let upload = tokio::spawn(
+            receiver
+                .upload(&session, "1", Body::wrap_stream(stalled))
+                .send(),
+        );
+        expect_expired(&mut receiver, &session.session_id).await;
+
+        // the partial file is removed once the upload notices the expiry
+        let partial = receiver.destination.join("1.bin");
+        for _ in 0..50 {
+            if !partial.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!partial.exists());
+        upload.abort();
+        assert_eq!(
+            std::fs::read(receiver.destination.join("0.bin")).unwrap(),
+            b"0000000000"
+        );
+        receiver.stop().await;
+    }
+}
@@ -1,74 +1,486 @@
 use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    collections::HashMap,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::PathBuf,
     sync::Arc,
 };
 
-use axum::{routing::post, Router};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
 use localsend_proto::{dto::FileDto, ApiRoute};
+use thiserror::Error;
 use tokio::{
     net::TcpListener,
     sync::{
         mpsc::{Receiver, Sender},
-        Mutex,
+        watch, Mutex, Semaphore,
     },
+    task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::send::{SendSession, UploadProgress};
-use crate::{receive::ReceiveSession, Settings};
+use crate::{progress::ProgressSender, send::SendSession};
+use crate::{
+    receive::{
+        clean_journals, spawn_status_writer, sweep_quarantines, ChannelDecider, FinishedSession,
+        PreviewFile, ReceiveDecider, ReceiveReport, ReceiveSession, StatusTracker,
+    },
+    util::trace,
+    Settings, SettingsLoader,
+};
 
 use self::controller::*;
+use self::share::*;
 
+mod control;
 mod controller;
 mod error;
+mod events;
+mod janitor;
+mod network;
+mod once;
+mod query;
+mod queue;
+mod range;
+mod share;
+
+pub use control::*;
+pub use events::*;
+pub use network::*;
+pub use once::*;
+pub use query::*;
+pub use queue::*;
+pub use range::*;
+pub use share::SharedText;
 
 pub type MutexServerState = Arc<Mutex<ServerState>>;
 
 #[derive(Clone, Debug)]
 pub enum ClientMessage {
-    FilesSelected(Sender<UploadProgress>, Vec<FileDto>),
+    /// Answers [`ServerMessage::SelectedFiles`], with a directory to save the
+    /// files to instead of `Settings::destination`
+    FilesSelected(ProgressSender, Vec<FileDto>, Option<PathBuf>),
+    /// Answers [`ServerMessage::ReviewFiles`] with the ids of the files to keep
+    FilesReviewed(Vec<String>),
     Declined,
 }
 
 #[derive(Clone, Debug)]
 pub enum ServerMessage {
     SelectedFiles(Vec<FileDto>),
+    /// The quarantined files of a finished session, answered by [`ClientMessage::FilesReviewed`]
+    ReviewFiles(Vec<PreviewFile>),
+    TextReceived(String),
+    SessionFinished(ReceiveReport),
+    /// The session with this id was dropped after the sender stopped responding
+    SessionExpired(String),
+    /// The session with this id was dropped, the files were not selected in time
+    SelectionTimedOut(String),
+    /// A device fetched the shared text
+    Downloaded(IpAddr),
+    /// The local address changed to this one, running sessions were failed
+    NetworkChanged(IpAddr),
 }
 
 pub struct ServerState {
+    /// Used by the sessions started from now on, see [`ServerState::reload_settings`]
     pub settings: Settings,
+    /// Counts the settings swapped in since the server state was created, from 1
+    pub settings_version: u64,
     pub server_tx: Sender<ServerMessage>,
-    pub client_rx: Receiver<ClientMessage>,
+    /// Decides which files are received unless `Settings::quick_save` is set,
+    /// by default through [`ServerMessage::SelectedFiles`] and the client messages
+    pub decider: Arc<dyn ReceiveDecider>,
     pub receive_session: Option<ReceiveSession>,
-    pub send_session: Option<SendSession>,
+    /// Prepare-uploads waiting for `receive_session` to end
+    pub session_queue: SessionQueue,
+    /// Answers retried uploads once `receive_session` finished
+    pub finished_session: Option<FinishedSession>,
+    /// Running uploads keyed by their local session id
+    pub send_sessions: HashMap<String, SendSession>,
+    /// Progress of the receive sessions, written to `Settings::status_file`
+    pub status_tracker: StatusTracker,
+    /// Offered to every device asking for downloads
+    pub shared_text: Option<SharedText>,
+    /// Cancelled when the server shuts down, receive sessions derive their tokens from it
+    pub cancel: CancellationToken,
+    /// Slots of `Settings::max_concurrent_uploads`, set when the server starts
+    pub upload_limit: Option<Arc<Semaphore>>,
+    /// The directory of this server below `Settings::journal_dir`, set when the server starts
+    pub journal_dir: Option<PathBuf>,
+    /// Every session event of this server, see [`SessionEvent`] for their order
+    pub events: EventBus,
+    /// Only answer the devices of `send_sessions`, the server is then of no use to
+    /// anyone else, e.g. in a send that never announces this device
+    pub stealth: bool,
+    /// Take a single receive session, see [`watch_once`]
+    pub once: bool,
+    /// Refuse new receive sessions, set once the session of `once` started
+    pub closed: bool,
 }
 
 impl ServerState {
     pub fn new(server_tx: Sender<ServerMessage>, client_rx: Receiver<ClientMessage>) -> Self {
         Self {
             settings: Settings::default(),
+            settings_version: 1,
+            decider: Arc::new(ChannelDecider::new(server_tx.clone(), client_rx)),
             server_tx,
-            client_rx,
             receive_session: None,
-            send_session: None,
+            session_queue: SessionQueue::default(),
+            finished_session: None,
+            send_sessions: HashMap::new(),
+            status_tracker: StatusTracker::default(),
+            shared_text: None,
+            cancel: CancellationToken::new(),
+            upload_limit: None,
+            journal_dir: None,
+            events: EventBus::default(),
+            stealth: false,
+            once: false,
+            closed: false,
+        }
+    }
+
+    /// Swaps in `settings` for the sessions started from now on and returns their
+    /// version, a running session keeps the settings it started with.
+    ///
+    /// The upload limit, the status file and the journal directory are set up when
+    /// the server starts, changing them takes a restart.
+    pub fn reload_settings(&mut self, settings: Settings) -> u64 {
+        self.settings = settings;
+        self.settings_version += 1;
+        self.settings_version
+    }
+
+    /// Counts what the server holds on to, see [`StateStats`].
+    pub fn stats(&self) -> StateStats {
+        StateStats {
+            receive_sessions: usize::from(self.receive_session.is_some()),
+            receive_files: self
+                .receive_session
+                .as_ref()
+                .map_or(0, |session| session.files.len()),
+            finished_files: self
+                .finished_session
+                .as_ref()
+                .map_or(0, |session| session.files.len()),
+            send_sessions: self.send_sessions.len(),
+            queued_sessions: self.session_queue.len(),
+            event_subscribers: self.events.receiver_count(),
+            status_subscribers: self.status_tracker.receiver_count(),
+        }
+    }
+}
+
+/// What a [`ServerState`] holds on to, nothing of it should grow over the sessions
+/// of a long-running receiver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateStats {
+    /// The running receive session, 0 or 1
+    pub receive_sessions: usize,
+    /// Files of the running receive session
+    pub receive_files: usize,
+    /// Files of the last finished session, kept to answer retries
+    pub finished_files: usize,
+    pub send_sessions: usize,
+    /// Prepare-uploads waiting for the running session to end
+    pub queued_sessions: usize,
+    /// Receivers of [`ServerState::events`]
+    pub event_subscribers: usize,
+    /// Receivers of the [`StatusTracker`]
+    pub status_subscribers: usize,
+}
+
+/// Loads new settings with `loader` and swaps them in, see [`ServerState::reload_settings`].
+///
+/// Settings that fail to load are logged and the ones before are kept.
+pub async fn reload_settings(
+    state: &MutexServerState,
+    loader: &SettingsLoader,
+) -> std::result::Result<u64, String> {
+    let settings = loader.load().map_err(|e| {
+        log::error!(
+            "Failed to reload the settings, keeping the ones before: {}",
+            e
+        );
+        e
+    })?;
+    let mut state = state.lock().await;
+    let version = state.reload_settings(settings);
+    match &state.receive_session {
+        Some(session) => log::info!(
+            "Reloaded the settings, version {}; session {} goes on with version {}",
+            version,
+            session.session_id,
+            session.settings_version
+        ),
+        None => log::info!("Reloaded the settings, version {}", version),
+    }
+    Ok(version)
+}
+
+#[derive(Error, Debug)]
+pub enum ServerError {
+    #[error("Address {0} is already in use")]
+    AddrInUse(SocketAddr),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A running api server.
+///
+/// Dropping the handle leaves the server running, use [`ServerHandle::shutdown`] to stop it.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    ready: watch::Receiver<bool>,
+    cancel: CancellationToken,
+    task: JoinHandle<std::io::Result<()>>,
+    janitor: JoinHandle<()>,
+    status_writer: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address the server is bound to, with the concrete port when binding port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Waits until the server accepts connections.
+    pub async fn ready(&self) {
+        let mut ready = self.ready.clone();
+        ready.wait_for(|ready| *ready).await.ok();
+    }
+
+    /// Stops accepting connections, cancels running uploads and waits for the server to stop.
+    pub async fn shutdown(self) -> std::io::Result<()> {
+        self.cancel.cancel();
+        self.wait().await
+    }
+
+    /// Waits until the server stopped, e.g. after the token passed to [`start_api_server`]
+    /// was cancelled.
+    pub async fn wait(self) -> std::io::Result<()> {
+        let result = self.task.await?;
+        self.janitor.abort();
+        if let Some(status_writer) = &self.status_writer {
+            status_writer.abort();
+        }
+        result
+    }
+}
+
+/// Binds the api server on `port` and starts serving in the background.
+///
+/// The server stops once `cancel` is cancelled, running uploads are cancelled
+/// and remove their partial files.
+pub async fn start_api_server(
+    port: u16,
+    state: MutexServerState,
+    cancel: &CancellationToken,
+) -> std::result::Result<ServerHandle, ServerError> {
+    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            return Err(ServerError::AddrInUse(addr.into()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let local_addr = listener.local_addr()?;
+
+    let cancel = cancel.child_token();
+    let (status_writer, preview_dir, journal_dir) = {
+        let mut state = state.lock().await;
+        state.cancel = cancel.clone();
+        state.upload_limit = state
+            .settings
+            .max_concurrent_uploads
+            .map(|limit| Arc::new(Semaphore::new(limit)));
+        let status_writer = state
+            .settings
+            .status_file
+            .clone()
+            .map(|path| spawn_status_writer(path, &state.status_tracker));
+        state.journal_dir = state
+            .settings
+            .journal_dir
+            .as_ref()
+            .map(|dir| dir.join(local_addr.port().to_string()));
+        (
+            status_writer,
+            state.settings.preview_dir.clone(),
+            state.journal_dir.clone(),
+        )
+    };
+    // no session runs yet, quarantines found are left over from earlier runs
+    if let Some(preview_dir) = preview_dir {
+        let removed = sweep_quarantines(&preview_dir).await;
+        if removed > 0 {
+            log::info!(
+                "Removed {} stale quarantines from {:?}",
+                removed,
+                preview_dir
+            );
         }
     }
+    // the port is ours, every journal of it belongs to a crashed receiver
+    if let Some(journal_dir) = journal_dir {
+        let cleanup = clean_journals(&journal_dir, false);
+        if !cleanup.is_empty() {
+            log::info!(
+                "Cleaned up {} crashed sessions: removed {} partial files, kept {} complete ones",
+                cleanup.sessions.len(),
+                cleanup.removed.len(),
+                cleanup.completed.len()
+            );
+        }
+    }
+
+    // upload bodies are streamed, hyper only reads ahead as far as its bounded buffers
+    // allow, so a slow destination slows the sender down through TCP
+    let router = Router::new()
+        .route(&ApiRoute::PrepareUpload.v1(), post(prepare_upload_v1))
+        .route(&ApiRoute::PrepareUpload.v2(), post(prepare_upload_v2))
+        .route(&ApiRoute::Upload.v1(), post(upload_v1))
+        .route(&ApiRoute::Upload.v2(), post(upload_v2))
+        .route(&ApiRoute::Cancel.v1(), post(cancel_v1))
+        .route(&ApiRoute::Cancel.v2(), post(cancel_v2))
+        .route(&ApiRoute::PrepareDownload.v2(), post(prepare_download))
+        .route(&ApiRoute::Download.v2(), get(download))
+        .route("/", get(share_page))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            only_send_peers,
+        ))
+        .with_state(state.clone());
+    let router = if trace::is_tracing() {
+        router.layer(axum::middleware::from_fn(trace::trace_requests))
+    } else {
+        router
+    };
+
+    let (ready_tx, ready) = watch::channel(false);
+    let task = {
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            let serve = axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async move { cancel.cancelled().await });
+            ready_tx.send(true).ok();
+            serve.await
+        })
+    };
+    log::debug!("api server listening on {}", local_addr);
+
+    Ok(ServerHandle {
+        local_addr,
+        ready,
+        cancel,
+        task,
+        janitor: janitor::spawn(state),
+        status_writer,
+    })
 }
 
-pub async fn start_api_server(port: u16, state: MutexServerState) -> std::io::Result<()> {
-    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)).await?;
-    axum::serve(
-        listener,
-        Router::new()
-            .route(&ApiRoute::PrepareUpload.v1(), post(prepare_upload_v1))
-            .route(&ApiRoute::PrepareUpload.v2(), post(prepare_upload_v2))
-            .route(&ApiRoute::Upload.v1(), post(upload_v1))
-            .route(&ApiRoute::Upload.v2(), post(upload_v2))
-            .route(&ApiRoute::Cancel.v1(), post(cancel_v1))
-            .route(&ApiRoute::Cancel.v2(), post(cancel_v2))
-            .with_state(state)
-            .into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .expect("Failed to start api server");
-    Ok(())
+/// Answers others as if nothing was served while [`ServerState::stealth`] is set,
+/// the devices files are sent to can still cancel.
+async fn only_send_peers(
+    State(state): State<MutexServerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let allowed = {
+        let state = state.lock().await;
+        !state.stealth
+            || state
+                .send_sessions
+                .values()
+                .any(|session| session.target().ip.parse() == Ok(addr.ip()))
+    };
+    if allowed {
+        next.run(request).await
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use localsend_proto::{fixtures::device, ApiRoute, Device};
+    use reqwest::StatusCode;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{start_api_server, ServerError, ServerState};
+    use crate::{
+        send::{SendSession, SendingFiles},
+        test_util::TestReceiver,
+    };
+
+    #[tokio::test]
+    async fn test_start_api_server() {
+        let state = || {
+            let (server_tx, _) = tokio::sync::mpsc::channel(1);
+            let (_, client_rx) = tokio::sync::mpsc::channel(1);
+            Arc::new(tokio::sync::Mutex::new(ServerState::new(
+                server_tx, client_rx,
+            )))
+        };
+
+        let server = start_api_server(0, state(), &CancellationToken::new())
+            .await
+            .unwrap();
+        server.ready().await;
+        let port = server.local_addr().port();
+        assert_ne!(port, 0);
+        tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+
+        let result = start_api_server(port, state(), &CancellationToken::new()).await;
+        assert!(matches!(result, Err(ServerError::AddrInUse(_))));
+
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stealth() {
+        let receiver = TestReceiver::start_with(|_| {}).await;
+        let url = receiver.url(ApiRoute::PrepareUpload);
+        let prepare = || async { reqwest::Client::new().post(&url).send().await.unwrap() };
+        // without a JSON body, rejected by the route
+        assert_eq!(prepare().await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        receiver.state.lock().await.stealth = true;
+        assert_eq!(prepare().await.status(), StatusCode::NOT_FOUND);
+
+        // the receiver of a running send is answered
+        let local = Device {
+            ip: "192.168.1.2".to_owned(),
+            ..device("sender", 53317)
+        };
+        let session = SendSession::new(&local, device("receiver", 53317), &SendingFiles::default());
+        receiver
+            .state
+            .lock()
+            .await
+            .send_sessions
+            .insert(session.session_id.clone(), session);
+        assert_eq!(prepare().await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        receiver.stop().await;
+    }
 }
@@ -0,0 +1,137 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+
+use crate::{scanner::MulticastDeviceScanner, util::device};
+
+use super::{MutexServerState, ServerMessage, SessionEvent};
+
+/// Interval between lookups of the local address.
+pub const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Follows the local address, e.g. across sleep and wake or a switch of networks.
+///
+/// Once it changed, the scanner rejoins the multicast group on the new interface,
+/// running sessions are failed and [`ServerMessage::NetworkChanged`] is sent.
+/// Stops together with the server.
+pub fn spawn_network_watcher(
+    state: MutexServerState,
+    scanner: Arc<MulticastDeviceScanner>,
+    ip: IpAddr,
+) -> JoinHandle<()> {
+    spawn_with(state, scanner, ip, NETWORK_CHECK_INTERVAL, || {
+        device::local_addr().ok().map(|addr| addr.ip())
+    })
+}
+
+fn spawn_with(
+    state: MutexServerState,
+    scanner: Arc<MulticastDeviceScanner>,
+    mut ip: IpAddr,
+    interval: Duration,
+    resolve: impl Fn() -> Option<IpAddr> + Send + 'static,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let cancel = state.lock().await.cancel.clone();
+        while !cancel.is_cancelled() {
+            tokio::time::sleep(interval).await;
+            // offline for now, the address is kept until another network is up
+            let Some(current) = resolve() else {
+                continue;
+            };
+            if current == ip {
+                continue;
+            }
+            log::info!("Local address changed from {} to {}", ip, current);
+            ip = current;
+            if let Err(e) = scanner.network_changed(ip) {
+                log::warn!("Failed to rejoin the multicast group on {}: {}", ip, e);
+            }
+            fail_sessions(&state).await;
+            let server_tx = state.lock().await.server_tx.clone();
+            server_tx.send(ServerMessage::NetworkChanged(ip)).await.ok();
+        }
+    })
+}
+
+/// Fails the running sessions, their peers are not reachable on the old address.
+async fn fail_sessions(state: &MutexServerState) {
+    let mut state = state.lock().await;
+    for session in state.send_sessions.values() {
+        session.cancel_by_sender();
+    }
+    let session = state.receive_session.take();
+    let events = state.events.clone();
+    drop(state);
+
+    if let Some(mut session) = session {
+        log::warn!(
+            "Session {} dropped after the network changed",
+            session.session_id
+        );
+        session.abort().await;
+        events.emit(SessionEvent::SessionFailed {
+            session_id: session.session_id,
+            reason: "The network changed".to_owned(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use reqwest::StatusCode;
+
+    use crate::{scanner::MulticastDeviceScanner, server::ServerMessage, test_util::TestReceiver};
+
+    #[tokio::test]
+    async fn test_network_change() {
+        let mut receiver = TestReceiver::start().await;
+        let state = receiver.state.clone();
+        let response = receiver.prepare(&["0"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.lock().await.receive_session.is_some());
+
+        let scanner =
+            MulticastDeviceScanner::new(&receiver.device(), Ipv4Addr::new(224, 0, 0, 199), 0, 9)
+                .await
+                .unwrap();
+        let old: IpAddr = Ipv4Addr::new(192, 168, 1, 2).into();
+        let resolved = Arc::new(Mutex::new(Some(old)));
+        let watcher = {
+            let resolved = resolved.clone();
+            super::spawn_with(
+                state.clone(),
+                Arc::new(scanner),
+                old,
+                Duration::from_millis(20),
+                move || *resolved.lock().unwrap(),
+            )
+        };
+
+        // going offline is no change
+        *resolved.lock().unwrap() = None;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(state.lock().await.receive_session.is_some());
+
+        let new: IpAddr = Ipv4Addr::LOCALHOST.into();
+        *resolved.lock().unwrap() = Some(new);
+        let message = tokio::time::timeout(Duration::from_secs(2), receiver.server_rx.recv()).await;
+        match message {
+            Ok(Some(ServerMessage::NetworkChanged(ip))) => assert_eq!(ip, new),
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert!(state.lock().await.receive_session.is_none());
+
+        receiver.stop().await;
+        tokio::time::timeout(Duration::from_secs(1), watcher)
+            .await
+            .expect("watcher not stopped")
+            .unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::receive::ReceiveReport;
+
+use super::{CancelledBy, SessionEvent};
+
+/// How the only session of `receive --once` ended.
+#[derive(Debug, Clone)]
+pub enum OnceOutcome {
+    /// Every accepted file was received
+    Received(ReceiveReport),
+    /// Files failed, or the session was cancelled or dropped before it finished
+    Failed {
+        reason: String,
+        report: Option<ReceiveReport>,
+    },
+    /// The offer was declined, not answered in time or nothing of it was selected
+    Declined,
+    /// Nothing was offered within the wait timeout
+    TimedOut,
+    /// Stopped before the session ended, e.g. by Ctrl-C
+    Interrupted,
+}
+
+impl OnceOutcome {
+    /// The exit code of `receive --once`, documented in the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OnceOutcome::Received(_) => 0,
+            OnceOutcome::Failed { .. } => 1,
+            OnceOutcome::Declined => 3,
+            OnceOutcome::TimedOut => 4,
+            OnceOutcome::Interrupted => 130,
+        }
+    }
+
+    /// The report of a session that finished, failed files and all.
+    pub fn report(&self) -> Option<&ReceiveReport> {
+        match self {
+            OnceOutcome::Received(report) => Some(report),
+            OnceOutcome::Failed { report, .. } => report.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn failed(reason: impl Into<String>) -> Self {
+        OnceOutcome::Failed {
+            reason: reason.into(),
+            report: None,
+        }
+    }
+}
+
+/// Follows the first receive session of a server to its end, `events` must be
+/// subscribed before the server takes any session.
+///
+/// Waits at most `wait` for an offer, the session itself may take as long as it
+/// takes. Set [`super::ServerState::once`] so that nobody else gets a session.
+pub async fn watch_once(
+    mut events: broadcast::Receiver<SessionEvent>,
+    wait: Option<Duration>,
+    cancel: CancellationToken,
+) -> OnceOutcome {
+    let offered = async {
+        loop {
+            match events.recv().await {
+                Ok(SessionEvent::ReceiveRequested { session_id, .. }) => return Some(session_id),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    };
+    let offered = async {
+        match wait {
+            Some(wait) => tokio::time::timeout(wait, offered).await.map_err(|_| ()),
+            None => Ok(offered.await),
+        }
+    };
+    let session_id = tokio::select! {
+        offered = offered => match offered {
+            Ok(Some(session_id)) => session_id,
+            Ok(None) => return OnceOutcome::Interrupted,
+            Err(()) => return OnceOutcome::TimedOut,
+        },
+        _ = cancel.cancelled() => return OnceOutcome::Interrupted,
+    };
+    log::debug!("Following session {} to its end", session_id);
+
+    let ended = async {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) if event.session_id() == session_id => event,
+                // the events ending a session come last, lagging only skips progress
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return OnceOutcome::Interrupted,
+            };
+            match event {
+                SessionEvent::ReceiveDeclined { .. } => return OnceOutcome::Declined,
+                SessionEvent::SessionFinished { report } => return finished(report),
+                SessionEvent::SessionCancelled { by, .. } => {
+                    return OnceOutcome::failed(match by {
+                        CancelledBy::Sender => "Cancelled by the sender",
+                        CancelledBy::Receiver => "Cancelled",
+                    })
+                }
+                SessionEvent::SessionExpired { .. } => {
+                    return OnceOutcome::failed("Sender stopped responding")
+                }
+                SessionEvent::SessionFailed { reason, .. } => return OnceOutcome::failed(reason),
+                _ => {}
+            }
+        }
+    };
+    tokio::select! {
+        outcome = ended => outcome,
+        _ = cancel.cancelled() => OnceOutcome::Interrupted,
+    }
+}
+
+fn finished(report: ReceiveReport) -> OnceOutcome {
+    let reason = match (&report.destination_lost, report.failed()) {
+        (Some(reason), _) => format!("Destination lost: {}", reason),
+        (None, 0) => return OnceOutcome::Received(report),
+        (None, failed) => format!("{} of {} files failed", failed, report.files.len()),
+    };
+    OnceOutcome::Failed {
+        reason,
+        report: Some(report),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use localsend_proto::{fixtures::device, Device};
+    use tokio::sync::broadcast;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        send::{SendError, SendSession, SendingFiles},
+        server::{ClientMessage, ServerMessage, SessionEvent},
+        test_util::TestReceiver,
+        Error,
+    };
+
+    use super::{watch_once, OnceOutcome};
+
+    /// A receiver taking one session, asking through its channels unless `quick_save`.
+    async fn once_receiver(quick_save: bool) -> (TestReceiver, broadcast::Receiver<SessionEvent>) {
+        let receiver = TestReceiver::start_with(|state| {
+            state.once = true;
+            state.settings.quick_save = quick_save;
+        })
+        .await;
+        let events = receiver.state.lock().await.events.subscribe();
+        (receiver, events)
+    }
+
+    fn files(dir: &Path) -> SendingFiles {
+        let path = dir.join(format!("{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, vec![7u8; 64 * 1024]).unwrap();
+        let mut files = SendingFiles::default();
+        files.add_file(&path, None).unwrap();
+        files
+    }
+
+    async fn send(
+        target: &Device,
+        files: &SendingFiles,
+        cancel: &CancellationToken,
+    ) -> crate::Result<SendingFiles> {
+        SendSession::new(&device("sender", 0), target.clone(), files)
+            .upload(None, cancel)
+            .await
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_quick_save_once() {
+        let dir = temp_dir();
+        let (receiver, events) = once_receiver(true).await;
+        let watch = tokio::spawn(watch_once(
+            events.resubscribe(),
+            Some(Duration::from_secs(10)),
+            CancellationToken::new(),
+        ));
+
+        let first = files(&dir);
+        send(&receiver.device(), &first, &CancellationToken::new())
+            .await
+            .unwrap();
+        let outcome = watch.await.unwrap();
+        let OnceOutcome::Received(report) = &outcome else {
+            panic!("{:?}", outcome);
+        };
+        assert_eq!(report.finished(), 1);
+        assert_eq!(outcome.exit_code(), 0);
+
+        // the next sender is turned away while the receiver shuts down
+        let result = send(&receiver.device(), &files(&dir), &CancellationToken::new()).await;
+        assert!(
+            matches!(result, Err(Error::Send(SendError::Busy))),
+            "{:?}",
+            result
+        );
+        assert!(receiver.state.lock().await.receive_session.is_none());
+        assert_eq!(std::fs::read_dir(&receiver.destination).unwrap().count(), 1);
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_declined_once() {
+        let dir = temp_dir();
+        let (mut receiver, events) = once_receiver(false).await;
+        let watch = tokio::spawn(watch_once(
+            events.resubscribe(),
+            None,
+            CancellationToken::new(),
+        ));
+
+        let (sending, target, cancel) = (files(&dir), receiver.device(), CancellationToken::new());
+        let answer = async {
+            let message = receiver.server_rx.recv().await;
+            assert!(matches!(message, Some(ServerMessage::SelectedFiles(_))));
+            receiver
+                .client_tx
+                .send(ClientMessage::Declined)
+                .await
+                .unwrap();
+        };
+        let (result, ()) = tokio::join!(send(&target, &sending, &cancel), answer);
+        assert!(
+            matches!(result, Err(Error::Send(SendError::Rejected))),
+            "{:?}",
+            result
+        );
+
+        let outcome = watch.await.unwrap();
+        assert!(matches!(outcome, OnceOutcome::Declined), "{:?}", outcome);
+        assert_eq!(outcome.exit_code(), 3);
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_once() {
+        let dir = temp_dir();
+        let (receiver, events) = once_receiver(false).await;
+        let watch = tokio::spawn(watch_once(
+            events.resubscribe(),
+            None,
+            CancellationToken::new(),
+        ));
+
+        // the sender gives up while the receiver is still asked
+        let cancel = CancellationToken::new();
+        let (sending, target) = (files(&dir), receiver.device());
+        let upload = send(&target, &sending, &cancel);
+        let give_up = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        };
+        let (result, ()) = tokio::join!(upload, give_up);
+        assert!(result.is_err());
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), watch)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            matches!(outcome, OnceOutcome::Failed { report: None, .. }),
+            "{:?}",
+            outcome
+        );
+        assert_eq!(outcome.exit_code(), 1);
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_wait_timeout() {
+        let (receiver, events) = once_receiver(true).await;
+        let outcome = watch_once(
+            events.resubscribe(),
+            Some(Duration::from_millis(100)),
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(outcome, OnceOutcome::TimedOut), "{:?}", outcome);
+        assert_eq!(outcome.exit_code(), 4);
+
+        // Ctrl-C while waiting
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let outcome = watch_once(events.resubscribe(), None, cancel).await;
+        assert_eq!(outcome.exit_code(), 130);
+
+        receiver.stop().await;
+    }
+}
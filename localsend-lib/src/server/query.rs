@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+use crate::{receive::ReceiveError, Error};
+
+/// Longest accepted query value after decoding.
+pub const MAX_QUERY_VALUE_LEN: usize = 1024;
+
+/// Decoded query parameters.
+///
+/// Unlike [`axum::extract::Query`] duplicate keys and values longer than
+/// [`MAX_QUERY_VALUE_LEN`] are rejected with [`ReceiveError::InvalidParameters`]
+/// instead of silently picking one.
+#[derive(Debug, Default)]
+pub struct StrictQuery(pub HashMap<String, String>);
+
+impl StrictQuery {
+    pub fn parse(query: &str) -> Result<Self, ReceiveError> {
+        let mut params = HashMap::new();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            if value.len() > MAX_QUERY_VALUE_LEN {
+                log::warn!("Query parameter {} is too long", key);
+                return Err(ReceiveError::InvalidParameters);
+            }
+            if params.insert(key.to_string(), value.into_owned()).is_some() {
+                log::warn!("Duplicate query parameter {}", key);
+                return Err(ReceiveError::InvalidParameters);
+            }
+        }
+        Ok(Self(params))
+    }
+}
+
+/// The parameters of an upload, `sessionId` is required by v2 only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadQuery {
+    pub session_id: Option<String>,
+    pub file_id: String,
+    pub token: String,
+}
+
+impl UploadQuery {
+    pub fn from_params(params: &HashMap<String, String>, v2: bool) -> Result<Self, ReceiveError> {
+        let get = |key: &str| params.get(key).cloned();
+        let session_id = match v2 {
+            true => Some(get("sessionId").ok_or(ReceiveError::InvalidParameters)?),
+            false => None,
+        };
+        Ok(Self {
+            session_id,
+            file_id: get("fileId").ok_or(ReceiveError::InvalidParameters)?,
+            token: get("token").ok_or(ReceiveError::InvalidParameters)?,
+        })
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for StrictQuery {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::parse(parts.uri.query().unwrap_or_default())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::receive::ReceiveError;
+
+    use super::{StrictQuery, UploadQuery, MAX_QUERY_VALUE_LEN};
+
+    #[test]
+    fn test_parse() {
+        let query =
+            StrictQuery::parse("sessionId=s%2B%2F%3D&fileId=1&token=a%2Bb%2Fc%3D%26%23").unwrap();
+        assert_eq!(query.0["sessionId"], "s+/=");
+        assert_eq!(query.0["fileId"], "1");
+        assert_eq!(query.0["token"], "a+b/c=&#");
+        assert!(StrictQuery::parse("").unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_reject() {
+        let duplicate = StrictQuery::parse("token=a&fileId=1&token=b");
+        assert!(matches!(duplicate, Err(ReceiveError::InvalidParameters)));
+
+        let long = format!("token={}", "a".repeat(MAX_QUERY_VALUE_LEN + 1));
+        assert!(matches!(
+            StrictQuery::parse(&long),
+            Err(ReceiveError::InvalidParameters)
+        ));
+        let max = format!("token={}", "a".repeat(MAX_QUERY_VALUE_LEN));
+        assert!(StrictQuery::parse(&max).is_ok());
+    }
+
+    #[test]
+    fn test_upload_query() {
+        let params = StrictQuery::parse("fileId=1&token=abc").unwrap().0;
+        let query = UploadQuery::from_params(&params, false).unwrap();
+        assert_eq!(query.session_id, None);
+        assert_eq!((query.file_id.as_str(), query.token.as_str()), ("1", "abc"));
+        assert!(matches!(
+            UploadQuery::from_params(&params, true),
+            Err(ReceiveError::InvalidParameters)
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::future::select;
+use tokio::sync::{MutexGuard, Notify};
+
+use crate::{receive::ReceiveError, Result};
+
+use super::{MutexServerState, ServerState};
+
+/// How often a queued request looks at the state when it was not woken, e.g. after
+/// a session ended without an event.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Prepare-uploads waiting for the running receive session to end, first come first served.
+///
+/// See `Settings::max_pending_sessions`.
+#[derive(Debug, Clone, Default)]
+pub struct SessionQueue {
+    inner: Arc<QueueInner>,
+}
+
+#[derive(Debug, Default)]
+struct QueueInner {
+    tickets: Mutex<Tickets>,
+    /// Wakes the waiting requests when one leaves the queue
+    changed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Tickets {
+    next: u64,
+    waiting: VecDeque<u64>,
+}
+
+impl SessionQueue {
+    /// Adds a request at the end of the queue, `None` when `max` are waiting already.
+    pub fn join(&self, max: usize) -> Option<QueueTicket> {
+        let mut tickets = self.inner.tickets.lock().unwrap();
+        if tickets.waiting.len() >= max {
+            return None;
+        }
+        let id = tickets.next;
+        tickets.next += 1;
+        tickets.waiting.push_back(id);
+        Some(QueueTicket {
+            queue: self.clone(),
+            id,
+        })
+    }
+
+    /// How many requests are waiting.
+    pub fn len(&self) -> usize {
+        self.inner.tickets.lock().unwrap().waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    async fn changed(&self) {
+        self.inner.changed.notified().await;
+    }
+}
+
+/// A place in the [`SessionQueue`], given up when dropped, e.g. when the sender
+/// closed the request.
+#[derive(Debug)]
+pub struct QueueTicket {
+    queue: SessionQueue,
+    id: u64,
+}
+
+impl QueueTicket {
+    /// Whether every request queued before left the queue.
+    pub fn is_next(&self) -> bool {
+        let tickets = self.queue.inner.tickets.lock().unwrap();
+        tickets.waiting.front() == Some(&self.id)
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        let mut tickets = self.queue.inner.tickets.lock().unwrap();
+        tickets.waiting.retain(|id| *id != self.id);
+        drop(tickets);
+        self.queue.inner.changed.notify_waiters();
+    }
+}
+
+/// Holds a prepare-upload arriving while another session runs until it is its turn.
+///
+/// Returns the locked state without a session once the session before ended and the
+/// requests queued before got theirs, so the caller starts its session before anyone
+/// else looks. Fails with [`ReceiveError::SessionBlocked`] without a queue or after
+/// `Settings::pending_session_wait`, and with [`ReceiveError::QueueFull`] when
+/// `Settings::max_pending_sessions` are waiting already.
+pub(crate) async fn wait_for_turn(state: &MutexServerState) -> Result<MutexGuard<'_, ServerState>> {
+    let (ticket, wait, mut events, cancel) = {
+        let state = state.lock().await;
+        let settings = &state.settings;
+        if settings.max_pending_sessions == 0 {
+            return Err(ReceiveError::SessionBlocked)?;
+        }
+        let ticket = state
+            .session_queue
+            .join(settings.max_pending_sessions)
+            .ok_or(ReceiveError::QueueFull(settings.pending_session_wait))?;
+        log::info!(
+            "Another session is running, {} requests waiting",
+            state.session_queue.len()
+        );
+        (
+            ticket,
+            settings.pending_session_wait,
+            state.events.subscribe(),
+            state.cancel.clone(),
+        )
+    };
+    let queue = ticket.queue.clone();
+    // the state is not locked while waiting, sessions end with an event and requests
+    // leaving the queue wake the others
+    let turn = async {
+        loop {
+            let state = state.lock().await;
+            if state.receive_session.is_none() && ticket.is_next() {
+                return Ok(state);
+            }
+            drop(state);
+            if cancel.is_cancelled() {
+                return Err(ReceiveError::SessionBlocked);
+            }
+            let woken = select(Box::pin(events.recv()), Box::pin(queue.changed()));
+            tokio::time::timeout(POLL_INTERVAL, woken).await.ok();
+        }
+    };
+    match tokio::time::timeout(wait, turn).await {
+        // the ticket leaves the queue while the state is locked
+        Ok(Ok(state)) => Ok(state),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => {
+            log::warn!(
+                "Gave up waiting for the running session after {}s",
+                wait.as_secs()
+            );
+            Err(ReceiveError::SessionBlocked.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionQueue;
+
+    #[test]
+    fn test_queue_order() {
+        let queue = SessionQueue::default();
+        let first = queue.join(2).unwrap();
+        let second = queue.join(2).unwrap();
+        assert!(queue.join(2).is_none());
+        assert!(first.is_next() && !second.is_next());
+
+        // a request giving up makes room and lets the next one move up
+        drop(first);
+        assert!(second.is_next());
+        let third = queue.join(2).unwrap();
+        assert!(!third.is_next());
+        drop(second);
+        assert!(third.is_next());
+        drop(third);
+        assert!(queue.is_empty());
+    }
+}
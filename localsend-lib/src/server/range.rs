@@ -0,0 +1,233 @@
+use std::{io::SeekFrom, ops::Range, path::Path};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+use crate::Result;
+
+/// Outcome of matching a `Range` request header against a file size.
+///
+/// Only a single range is supported, requests with multiple ranges are answered
+/// with 416 rather than a multipart body. Headers that can not be parsed or use
+/// another unit are ignored and the whole file is served.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    pub fn parse(header: Option<&HeaderValue>, size: u64) -> Self {
+        let Some(value) = header.and_then(|h| h.to_str().ok()) else {
+            return ByteRange::Full;
+        };
+        let Some(spec) = value.trim().strip_prefix("bytes=") else {
+            return ByteRange::Full;
+        };
+        if spec.contains(',') {
+            return ByteRange::Unsatisfiable;
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return ByteRange::Full;
+        };
+
+        let (first, last) = match (first.trim(), last.trim()) {
+            // bytes=-N, the last N bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return ByteRange::Unsatisfiable,
+                Ok(suffix) => (size.saturating_sub(suffix), size.saturating_sub(1)),
+                Err(_) => return ByteRange::Full,
+            },
+            // bytes=N-
+            (first, "") => match first.parse::<u64>() {
+                Ok(first) => (first, size.saturating_sub(1)),
+                Err(_) => return ByteRange::Full,
+            },
+            // bytes=N-M
+            (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(first), Ok(last)) => (first, last.min(size.saturating_sub(1))),
+                _ => return ByteRange::Full,
+            },
+        };
+        if first >= size || last < first {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Partial(first..last + 1)
+    }
+}
+
+/// How much of a served file is read at once.
+///
+/// hyper writes the body from its own buffers, so `sendfile` can not be used,
+/// but large reads into a reused buffer keep the copies and wakeups per byte low.
+pub const SERVE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Serves the file at `path` honoring `Range` and `HEAD` requests.
+pub async fn serve_file(path: &Path, method: &Method, headers: &HeaderMap) -> Result<Response> {
+    serve_file_buffered(path, method, headers, SERVE_BUFFER_SIZE).await
+}
+
+/// [`serve_file`] reading `buffer_size` bytes at a time, e.g. for benchmarks.
+pub async fn serve_file_buffered(
+    path: &Path,
+    method: &Method,
+    headers: &HeaderMap,
+    buffer_size: usize,
+) -> Result<Response> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let content_type = mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .to_string();
+
+    let range = ByteRange::parse(headers.get(header::RANGE), size);
+    let (status, range) = match range {
+        ByteRange::Full => (StatusCode::OK, 0..size),
+        ByteRange::Partial(range) => (StatusCode::PARTIAL_CONTENT, range),
+        ByteRange::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [
+                    (header::ACCEPT_RANGES, "bytes".to_owned()),
+                    (header::CONTENT_RANGE, format!("bytes */{}", size)),
+                ],
+            )
+                .into_response());
+        }
+    };
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    response_headers.insert(header::CONTENT_LENGTH, (range.end - range.start).into());
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, size);
+        response_headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+    }
+
+    if method == Method::HEAD {
+        return Ok((status, response_headers).into_response());
+    }
+
+    file.seek(SeekFrom::Start(range.start)).await?;
+    // the buffer is reclaimed once hyper wrote a chunk, it is not allocated per chunk
+    let reader = file.take(range.end - range.start);
+    let body = Body::from_stream(ReaderStream::with_capacity(reader, buffer_size));
+    Ok((status, response_headers, body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+
+    use super::{serve_file, ByteRange, SERVE_BUFFER_SIZE};
+
+    fn parse(value: &str, size: u64) -> ByteRange {
+        ByteRange::parse(Some(&HeaderValue::from_str(value).unwrap()), size)
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(ByteRange::parse(None, 10), ByteRange::Full);
+        assert_eq!(parse("items=0-1", 10), ByteRange::Full);
+        assert_eq!(parse("bytes=a-b", 10), ByteRange::Full);
+        assert_eq!(parse("bytes=0-", 10), ByteRange::Partial(0..10));
+        assert_eq!(parse("bytes=2-4", 10), ByteRange::Partial(2..5));
+        assert_eq!(parse("bytes=-3", 10), ByteRange::Partial(7..10));
+        assert_eq!(parse("bytes=-30", 10), ByteRange::Partial(0..10));
+        assert_eq!(parse("bytes=5-100", 10), ByteRange::Partial(5..10));
+
+        // at and past EOF
+        assert_eq!(parse("bytes=9-", 10), ByteRange::Partial(9..10));
+        assert_eq!(parse("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=10-20", 10), ByteRange::Unsatisfiable);
+
+        // zero length
+        assert_eq!(parse("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=5-4", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-1", 0), ByteRange::Unsatisfiable);
+
+        // multiple ranges are rejected
+        assert_eq!(parse("bytes=0-1,4-5", 10), ByteRange::Unsatisfiable);
+    }
+
+    #[tokio::test]
+    async fn test_serve_file() {
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        let mut headers = HeaderMap::new();
+        let response = serve_file(&path, &Method::GET, &headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 100)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"0123456789");
+
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=7-"));
+        let response = serve_file(&path, &Method::GET, &headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 7-9/10");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "3");
+        let body = axum::body::to_bytes(response.into_body(), 100)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"789");
+
+        let response = serve_file(&path, &Method::HEAD, &headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+        let body = axum::body::to_bytes(response.into_body(), 100)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=10-"));
+        let response = serve_file(&path, &Method::GET, &headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_serve_large_file() {
+        let path = std::env::temp_dir().join(format!("{}.bin", uuid::Uuid::new_v4()));
+        // several buffers with an uneven rest
+        let content: Vec<u8> = (0..SERVE_BUFFER_SIZE as u32 * 4 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let mut headers = HeaderMap::new();
+        let response = serve_file(&path, &Method::GET, &headers).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body[..] == content[..]);
+
+        // a range across buffer boundaries
+        let start = SERVE_BUFFER_SIZE - 3;
+        let end = SERVE_BUFFER_SIZE * 3 + 5;
+        let range = format!("bytes={}-{}", start, end - 1);
+        headers.insert(header::RANGE, HeaderValue::from_str(&range).unwrap());
+        let response = serve_file(&path, &Method::GET, &headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body[..] == content[start..end]);
+
+        std::fs::remove_file(path).ok();
+    }
+}
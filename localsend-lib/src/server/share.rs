@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::header,
+    response::{Html, IntoResponse},
+    Json,
+};
+use localsend_proto::{
+    dto::{FileDto, FileType, PrepareDownloadResponseDto},
+    Device,
+};
+
+use crate::{receive::ReceiveError, util::hash::FileHash, Result};
+
+use super::{MutexServerState, ServerMessage, StrictQuery};
+
+/// A text offered through the download api to any number of devices.
+#[derive(Debug, Clone)]
+pub struct SharedText {
+    /// This device, as described to the devices fetching the text
+    pub device: Device,
+    pub session_id: String,
+    pub file: FileDto,
+    pub text: String,
+}
+
+impl SharedText {
+    pub fn new(device: &Device, text: impl ToString) -> Self {
+        let text = text.to_string();
+        let text_hash = FileHash::sha256(&text).to_string();
+        let file = FileDto {
+            id: uuid::Uuid::new_v4().to_string(),
+            file_name: format!("{}.txt", text_hash),
+            size: text.len() as u64,
+            file_type: FileType::Text,
+            hash: Some(text_hash),
+            preview: Some(text.clone()),
+        };
+        Self {
+            device: device.clone(),
+            session_id: uuid::Uuid::new_v4().to_string(),
+            file,
+            text,
+        }
+    }
+}
+
+/// Lists the shared text, every call counts as a fetch.
+pub async fn prepare_download(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<MutexServerState>,
+) -> Result<Json<PrepareDownloadResponseDto>> {
+    let state = state.lock().await;
+    let shared = shared_text(&state.shared_text)?;
+    state
+        .server_tx
+        .send(ServerMessage::Downloaded(addr.ip()))
+        .await
+        .ok();
+    Ok(Json(PrepareDownloadResponseDto {
+        info: shared.device.clone().into(),
+        session_id: shared.session_id.clone(),
+        files: [(shared.file.id.clone(), shared.file.clone())].into(),
+    }))
+}
+
+pub async fn download(
+    StrictQuery(query): StrictQuery,
+    State(state): State<MutexServerState>,
+) -> Result<impl IntoResponse> {
+    let state = state.lock().await;
+    let shared = shared_text(&state.shared_text)?;
+    if query.get("sessionId") != Some(&shared.session_id) {
+        return Err(ReceiveError::InvalidSessionId)?;
+    }
+    if query.get("fileId") != Some(&shared.file.id) {
+        return Err(ReceiveError::InvalidToken)?;
+    }
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        shared.text.clone(),
+    ))
+}
+
+/// A page showing the text to browsers, for phones without the app.
+pub async fn share_page(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<MutexServerState>,
+) -> Result<Html<String>> {
+    let state = state.lock().await;
+    let shared = shared_text(&state.shared_text)?;
+    state
+        .server_tx
+        .send(ServerMessage::Downloaded(addr.ip()))
+        .await
+        .ok();
+    Ok(Html(format!(
+        concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+            "<title>{}</title>\n</head>\n<body>\n",
+            "<pre style=\"white-space: pre-wrap; word-break: break-all; font-size: 1.5em\">{}</pre>\n",
+            "</body>\n</html>\n"
+        ),
+        escape_html(&shared.device.alias),
+        escape_html(&shared.text)
+    )))
+}
+
+fn shared_text(shared: &Option<SharedText>) -> Result<&SharedText> {
+    Ok(shared.as_ref().ok_or(ReceiveError::DownloadUnsupported)?)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use localsend_proto::{fixtures::device, Device};
+    use tokio::sync::mpsc::Receiver;
+
+    use crate::{
+        receive::DownloadSession, server::ServerMessage, test_util::TestReceiver, CollisionPolicy,
+    };
+
+    use super::{escape_html, SharedText};
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    async fn expect_fetched(server_rx: &mut Receiver<ServerMessage>) {
+        let message = tokio::time::timeout(Duration::from_secs(5), server_rx.recv()).await;
+        match message {
+            Ok(Some(ServerMessage::Downloaded(peer))) => {
+                assert_eq!(peer, "127.0.0.1".parse::<IpAddr>().unwrap())
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_text() {
+        const TEXT: &str = "wifi: <home> & \"guest\"";
+
+        let mut receiver = TestReceiver::start_with(|_| {}).await;
+        let device = Device {
+            download: true,
+            ..device("server", receiver.port())
+        };
+        receiver.state.lock().await.shared_text = Some(SharedText::new(&device, TEXT));
+
+        // any number of devices can fetch the text
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        for _ in 0..2 {
+            let session = DownloadSession::prepare(&device).await.unwrap();
+            expect_fetched(&mut receiver.server_rx).await;
+            let files = session.files();
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].preview.as_deref(), Some(TEXT));
+            session
+                .download(&files, &dir, CollisionPolicy::Rename, None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        let page = reqwest::get(format!("http://127.0.0.1:{}/", device.port))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        expect_fetched(&mut receiver.server_rx).await;
+        assert!(page.contains("wifi: &lt;home&gt; &amp; &quot;guest&quot;"));
+
+        receiver.stop().await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
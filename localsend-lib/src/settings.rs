@@ -1,9 +1,131 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
-#[derive(Debug)]
+use crate::{
+    receive::{
+        DedupAction, ReceiveHook, SinkFactory, StructureLimits, TokenIssuer, TokenPolicy,
+        UuidTokens,
+    },
+    util::{
+        fs::{CaseSensitivity, DangerousExtensions, NameRules},
+        preview::PreviewPolicy,
+    },
+};
+
+/// Accepted sessions are dropped after this long without any upload activity.
+pub const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(180);
+/// Offered files are declined when no decision arrives in this time.
+pub const DEFAULT_DECISION_TIMEOUT: Duration = Duration::from_secs(300);
+/// A receive hook still running after this long counts as failed.
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+/// Files up to this size are quarantined for a preview when `Settings::preview_dir` is set.
+pub const DEFAULT_PREVIEW_MAX_SIZE: u64 = 5 * 1024 * 1024;
+/// A prepare-upload queued behind a running session is answered with 409 after this long.
+pub const DEFAULT_PENDING_SESSION_WAIT: Duration = Duration::from_secs(120);
+/// Texts up to this size are accepted without asking when `Settings::auto_accept_texts` is set.
+pub const DEFAULT_AUTO_ACCEPT_TEXT_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Save as `name (1).ext`, `name (2).ext`, ...
+    Rename,
+    /// Append the body to the existing file once it arrived completely. Only for
+    /// texts and files of unknown type unless `Settings::append_any_type`, the
+    /// others are renamed
+    Append,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "rename" => Ok(CollisionPolicy::Rename),
+            "append" => Ok(CollisionPolicy::Append),
+            _ => Err(format!("unknown collision policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Settings {
+    /// May contain the placeholders of [`crate::receive::DESTINATION_PLACEHOLDERS`], resolved per session
     pub destination: PathBuf,
     pub quick_save: bool,
+    /// Accept text messages up to `auto_accept_text_size` without asking, the decider
+    /// is only asked about the other files of an offer
+    pub auto_accept_texts: bool,
+    pub auto_accept_text_size: u64,
+    pub collision_policy: CollisionPolicy,
+    /// Append files of any type with [`CollisionPolicy::Append`]
+    pub append_any_type: bool,
+    /// Write all received files into this archive (relative to `destination`)
+    pub archive: Option<PathBuf>,
+    /// Also write text messages into the archive instead of printing them
+    pub archive_texts: bool,
+    /// Which text messages are printed instead of archived, and how; also decides
+    /// the previews of texts the daemon sends
+    pub text_preview: PreviewPolicy,
+    /// Drop a receive session after this long without activity from the sender
+    pub session_timeout: Duration,
+    /// Give up on the receive decider after this long
+    pub decision_timeout: Duration,
+    /// Which names the destination accepts, invalid characters are replaced
+    pub name_rules: NameRules,
+    pub name_replacement: char,
+    /// Files with these extensions are saved with `.received` appended, renamed rather
+    /// than rejected; archives and custom sinks keep the names offered
+    pub dangerous_extensions: DangerousExtensions,
+    /// Whether the destination tells apart names differing in case, detected per session when `None`
+    pub case_sensitivity: Option<CaseSensitivity>,
+    /// Creates the sink of each session instead of saving to `destination`
+    pub sink_factory: Option<SinkFactory>,
+    /// Receive into an [`crate::receive::AuditSink`] instead of any other sink, nothing
+    /// is saved. Archives, previews and dedup are off, they need the files on disk
+    pub audit: bool,
+    /// Append the records of an audit to this file as JSON lines
+    pub audit_log: Option<PathBuf>,
+    /// Journal the sessions saving to `destination` in a directory per port below
+    /// this one, so the files of a crashed receiver are cleaned up on its next start
+    pub journal_dir: Option<PathBuf>,
+    /// Keep a JSON document describing the receiver's progress at this path
+    pub status_file: Option<PathBuf>,
+    /// Add the files of another prepare-upload of the same sender to its running session
+    pub allow_session_extend: bool,
+    /// Let this many prepare-uploads arriving during a session wait for it to end, in
+    /// the order they arrived; the ones beyond are answered with 429. With 0 they are
+    /// answered with 409 right away
+    pub max_pending_sessions: usize,
+    /// Answer a waiting prepare-upload with 409 after this long
+    pub pending_session_wait: Duration,
+    /// Runs for every file saved by the sink
+    pub receive_hook: Option<Arc<dyn ReceiveHook>>,
+    /// Give up on the receive hook of a file after this long
+    pub hook_timeout: Duration,
+    /// Upload requests beyond this many wait without reading their bodies
+    pub max_concurrent_uploads: Option<usize>,
+    /// Read the bodies of a session at most this many bytes per second, all its
+    /// uploads together
+    pub receive_rate_limit: Option<u64>,
+    /// Receive files up to `preview_max_size` into a quarantine below this directory
+    /// without asking, the user keeps or discards them once they arrived
+    pub preview_dir: Option<PathBuf>,
+    pub preview_max_size: u64,
+    /// Look up offered files by their digest in the index at this path, files
+    /// received before are handled by `dedup_action` instead of being received.
+    /// Only applies when saving files to `destination`.
+    pub dedup_index: Option<PathBuf>,
+    pub dedup_action: DedupAction,
+    /// Files whose names break these limits are not received
+    pub structure_limits: StructureLimits,
+    /// Reject the whole offer instead when a file breaks `structure_limits`
+    pub strict_structure: bool,
+    /// Creates the upload tokens, UUIDs by default
+    pub token_issuer: Arc<dyn TokenIssuer>,
+    pub token_policy: TokenPolicy,
 }
 
 impl Default for Settings {
@@ -11,6 +133,63 @@ impl Default for Settings {
         Self {
             destination: PathBuf::from("."),
             quick_save: false,
+            auto_accept_texts: false,
+            auto_accept_text_size: DEFAULT_AUTO_ACCEPT_TEXT_SIZE,
+            collision_policy: CollisionPolicy::default(),
+            append_any_type: false,
+            archive: None,
+            archive_texts: false,
+            text_preview: PreviewPolicy::default(),
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+            decision_timeout: DEFAULT_DECISION_TIMEOUT,
+            name_rules: NameRules::native(),
+            name_replacement: '_',
+            dangerous_extensions: DangerousExtensions::native(),
+            case_sensitivity: None,
+            sink_factory: None,
+            audit: false,
+            audit_log: None,
+            journal_dir: None,
+            status_file: None,
+            allow_session_extend: false,
+            max_pending_sessions: 0,
+            pending_session_wait: DEFAULT_PENDING_SESSION_WAIT,
+            receive_hook: None,
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            max_concurrent_uploads: None,
+            receive_rate_limit: None,
+            preview_dir: None,
+            preview_max_size: DEFAULT_PREVIEW_MAX_SIZE,
+            dedup_index: None,
+            dedup_action: DedupAction::default(),
+            structure_limits: StructureLimits::default(),
+            strict_structure: false,
+            token_issuer: Arc::new(UuidTokens),
+            token_policy: TokenPolicy::default(),
         }
     }
 }
+
+type LoadFn = dyn Fn() -> Result<Settings, String> + Send + Sync;
+
+/// Builds the settings a reload swaps in, e.g. from a config file.
+///
+/// An error keeps the settings before, see [`crate::server::reload_settings`].
+#[derive(Clone)]
+pub struct SettingsLoader(Arc<LoadFn>);
+
+impl SettingsLoader {
+    pub fn new(loader: impl Fn() -> Result<Settings, String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(loader))
+    }
+
+    pub fn load(&self) -> Result<Settings, String> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for SettingsLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SettingsLoader")
+    }
+}
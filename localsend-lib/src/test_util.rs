@@ -0,0 +1,142 @@
+//! An in-process receiver for the tests of this crate and, with the `test-util` feature,
+//! of others. Devices are built with `localsend_proto::fixtures::device`.
+
+use std::{path::PathBuf, sync::Arc};
+
+use localsend_proto::{
+    dto::{FileDto, FileType, PrepareUploadRequestDto, PrepareUploadResponseDto},
+    fixtures::device,
+    ApiRoute, Device, ProtocolVersion,
+};
+use reqwest::Body;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+
+use crate::server::{
+    start_api_server, ClientMessage, MutexServerState, ServerHandle, ServerMessage, ServerState,
+};
+
+/// Serves the API on a free port of 127.0.0.1 and saves below a new temporary directory.
+pub struct TestReceiver {
+    pub server: ServerHandle,
+    pub state: MutexServerState,
+    pub server_rx: Receiver<ServerMessage>,
+    /// Answers the offers of a receiver that does not save right away
+    pub client_tx: Sender<ClientMessage>,
+    /// Created with the first file saved
+    pub destination: PathBuf,
+    /// Stops the server when cancelled
+    pub cancel: CancellationToken,
+}
+
+impl TestReceiver {
+    /// A receiver saving every offer without asking.
+    pub async fn start() -> Self {
+        Self::start_with(|state| state.settings.quick_save = true).await
+    }
+
+    pub async fn start_with(configure: impl FnOnce(&mut ServerState)) -> Self {
+        let destination = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let (server_tx, server_rx) = tokio::sync::mpsc::channel(4);
+        let (client_tx, client_rx) = tokio::sync::mpsc::channel(1);
+        let mut state = ServerState::new(server_tx, client_rx);
+        state.settings.destination.clone_from(&destination);
+        configure(&mut state);
+        let cancel = CancellationToken::new();
+        let state = Arc::new(tokio::sync::Mutex::new(state));
+        let server = start_api_server(0, state.clone(), &cancel).await.unwrap();
+        server.ready().await;
+        Self {
+            server,
+            state,
+            server_rx,
+            client_tx,
+            destination,
+            cancel,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.server.local_addr().port()
+    }
+
+    /// The receiver as senders see it.
+    pub fn device(&self) -> Device {
+        device("receiver", self.port())
+    }
+
+    pub fn url(&self, route: ApiRoute) -> String {
+        route
+            .target_raw("127.0.0.1", self.port(), false, ProtocolVersion::V2)
+            .unwrap()
+    }
+
+    /// Offers a file of 4 bytes for each of `ids`.
+    pub async fn prepare(&self, ids: &[&str]) -> reqwest::Response {
+        let files: Vec<_> = ids.iter().map(|id| (*id, 4)).collect();
+        self.prepare_sized(&files).await
+    }
+
+    pub async fn prepare_sized(&self, files: &[(&str, u64)]) -> reqwest::Response {
+        let files = files.iter().map(|(id, size)| file(id, *size)).collect();
+        self.prepare_files(files).await
+    }
+
+    pub async fn prepare_files(&self, files: Vec<FileDto>) -> reqwest::Response {
+        self.prepare_from("sender", files).await
+    }
+
+    /// Offers `files` as the device named `alias`.
+    pub async fn prepare_from(&self, alias: &str, files: Vec<FileDto>) -> reqwest::Response {
+        let files = files
+            .into_iter()
+            .map(|file| (file.id.clone(), file))
+            .collect();
+        let dto = PrepareUploadRequestDto {
+            info: device(alias, 0).into(),
+            files,
+            extension: None,
+        };
+        reqwest::Client::new()
+            .post(self.url(ApiRoute::PrepareUpload))
+            .json(&dto)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub fn upload(
+        &self,
+        session: &PrepareUploadResponseDto,
+        file_id: &str,
+        body: impl Into<Body>,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .post(self.url(ApiRoute::Upload))
+            .query(&[
+                ("sessionId", session.session_id.as_str()),
+                ("fileId", file_id),
+                ("token", session.files[file_id].as_str()),
+            ])
+            .body(body)
+    }
+
+    /// Stops the server and whatever else was started with [Self::cancel].
+    pub async fn stop(self) {
+        self.cancel.cancel();
+        self.server.wait().await.unwrap();
+        std::fs::remove_dir_all(self.destination).ok();
+    }
+}
+
+/// An offered file named `<id>.bin`.
+pub fn file(id: &str, size: u64) -> FileDto {
+    FileDto {
+        id: id.to_owned(),
+        file_name: format!("{}.bin", id),
+        size,
+        file_type: FileType::Other,
+        hash: None,
+        preview: None,
+    }
+}
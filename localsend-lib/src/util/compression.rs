@@ -0,0 +1,144 @@
+use std::pin::Pin;
+
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder, ZstdDecoder, ZstdEncoder};
+use localsend_proto::dto::FileDto;
+use tokio::io::{AsyncRead, BufReader};
+
+/// Response header of prepare-upload naming the accepted upload encoding.
+pub const COMPRESS_HEADER: &str = "x-localsend-rs-compress";
+
+/// Files smaller than this are never compressed.
+pub const COMPRESS_THRESHOLD: u64 = 64 * 1024;
+
+/// Content encodings for uploads between localsend-rs peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    /// Supported encodings, most preferred first.
+    pub const SUPPORTED: [Compression; 2] = [Compression::Zstd, Compression::Gzip];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::SUPPORTED
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Picks the first offered encoding we support.
+    pub fn negotiate(offered: &[String]) -> Option<Self> {
+        offered.iter().find_map(|name| Self::from_name(name))
+    }
+
+    pub fn encoder<R>(&self, reader: R) -> Pin<Box<dyn AsyncRead + Send + Sync>>
+    where
+        R: AsyncRead + Send + Sync + 'static,
+    {
+        let reader = BufReader::new(reader);
+        match self {
+            Compression::Zstd => Box::pin(ZstdEncoder::new(reader)),
+            Compression::Gzip => Box::pin(GzipEncoder::new(reader)),
+        }
+    }
+
+    pub fn decoder<R>(&self, reader: R) -> Pin<Box<dyn AsyncRead + Send>>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        let reader = BufReader::new(reader);
+        match self {
+            Compression::Zstd => Box::pin(ZstdDecoder::new(reader)),
+            Compression::Gzip => Box::pin(GzipDecoder::new(reader)),
+        }
+    }
+}
+
+/// Whether compressing `file` is likely to pay off.
+pub fn is_compressible(file: &FileDto) -> bool {
+    use mime_guess::mime::*;
+
+    if file.size < COMPRESS_THRESHOLD {
+        return false;
+    }
+    let mime = mime_guess::from_path(&file.file_name).first_or_octet_stream();
+    match (mime.type_(), mime.subtype()) {
+        (TEXT, _) => true,
+        (APPLICATION, JSON | XML | JAVASCRIPT) => true,
+        (APPLICATION, name) => matches!(
+            name.as_str(),
+            "x-ndjson" | "sql" | "x-sh" | "x-yaml" | "yaml" | "toml" | "csv"
+        ),
+        _ => file.file_name.ends_with(".log"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::dto::{FileDto, FileType};
+    use tokio::io::AsyncReadExt;
+
+    use super::{is_compressible, Compression, COMPRESS_THRESHOLD};
+
+    fn file(name: &str, size: u64) -> FileDto {
+        FileDto {
+            id: name.to_owned(),
+            file_name: name.to_owned(),
+            size,
+            file_type: FileType::Other,
+            hash: None,
+            preview: None,
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let offered = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Compression::negotiate(&offered(&["br", "gzip", "zstd"])),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::negotiate(&offered(&["br"])), None);
+        assert_eq!(Compression::negotiate(&[]), None);
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible(&file("data.csv", COMPRESS_THRESHOLD)));
+        assert!(is_compressible(&file("dump.json", COMPRESS_THRESHOLD)));
+        assert!(is_compressible(&file("server.log", COMPRESS_THRESHOLD)));
+        assert!(!is_compressible(&file("data.csv", COMPRESS_THRESHOLD - 1)));
+        assert!(!is_compressible(&file("photo.jpg", COMPRESS_THRESHOLD)));
+        assert!(!is_compressible(&file("archive.zip", COMPRESS_THRESHOLD)));
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let data = "timestamp,level,message\n".repeat(10000).into_bytes();
+        for compression in Compression::SUPPORTED {
+            let mut compressed = vec![];
+            compression
+                .encoder(std::io::Cursor::new(data.clone()))
+                .read_to_end(&mut compressed)
+                .await
+                .unwrap();
+            assert!(compressed.len() < data.len() / 10);
+
+            let mut decompressed = vec![];
+            compression
+                .decoder(std::io::Cursor::new(compressed))
+                .read_to_end(&mut decompressed)
+                .await
+                .unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+}
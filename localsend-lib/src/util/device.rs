@@ -1,5 +1,9 @@
-use std::{ffi::OsString, net::SocketAddr};
+use std::{
+    ffi::OsString,
+    net::{IpAddr, SocketAddr},
+};
 
+use localsend_proto::{Device, MAX_ALIAS_LEN};
 use uuid::Uuid;
 
 use crate::Result;
@@ -13,6 +17,22 @@ pub fn alias() -> String {
     "Desktop CLI".to_string()
 }
 
+/// Cuts `alias` to [`MAX_ALIAS_LEN`] characters, receivers reject longer ones.
+pub fn truncate_alias(alias: &str) -> &str {
+    match alias.char_indices().nth(MAX_ALIAS_LEN) {
+        Some((end, _)) => &alias[..end],
+        None => alias,
+    }
+}
+
+/// `device` as shown to others under `alias`, e.g. for a single send.
+pub fn with_alias(device: &Device, alias: &str) -> Device {
+    Device {
+        alias: truncate_alias(alias).to_owned(),
+        ..device.clone()
+    }
+}
+
 #[cfg(target_os = "windows")]
 pub fn device_model() -> String {
     "Windows".to_string()
@@ -42,3 +62,31 @@ pub fn local_addr() -> Result<SocketAddr> {
     socket.connect("8.8.8.8:80")?;
     Ok(socket.local_addr()?)
 }
+
+/// Returns whether `ip` is assigned to one of the local interfaces.
+pub fn is_local_ip(ip: IpAddr) -> bool {
+    std::net::UdpSocket::bind((ip, 0)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::MAX_ALIAS_LEN;
+
+    use super::truncate_alias;
+
+    #[test]
+    fn test_truncate_alias() {
+        assert_eq!(
+            truncate_alias("Work Laptop (urgent)"),
+            "Work Laptop (urgent)"
+        );
+        let alias = "a".repeat(MAX_ALIAS_LEN);
+        assert_eq!(truncate_alias(&alias), alias);
+
+        // multi-byte characters are never split
+        let alias = "笔记本🚀".repeat(MAX_ALIAS_LEN);
+        let truncated = truncate_alias(&alias);
+        assert_eq!(truncated.chars().count(), MAX_ALIAS_LEN);
+        assert!(alias.starts_with(truncated));
+    }
+}
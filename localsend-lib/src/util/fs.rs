@@ -0,0 +1,617 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::CollisionPolicy;
+
+/// Directory for files kept between runs, `None` without a home directory.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(base.join("localsend-rs"))
+}
+
+/// Directory for state kept between runs like indexes, `None` without a home directory.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+        }
+    };
+    Some(base.join("localsend-rs"))
+}
+
+/// Resolves the path a file should be saved to according to `policy`.
+pub fn resolve_collision(path: impl AsRef<Path>, policy: CollisionPolicy) -> PathBuf {
+    resolve_collision_by(path.as_ref(), policy, |path| path.exists())
+}
+
+/// Like [`resolve_collision`], also renaming when a name of the directory or one
+/// saved before in the session only differs in case or Unicode normalization,
+/// as far as the filesystem of `names` does not tell them apart.
+pub fn resolve_session_collision(
+    path: impl AsRef<Path>,
+    policy: CollisionPolicy,
+    names: &SessionNames,
+) -> PathBuf {
+    let path = resolve_collision_by(path.as_ref(), policy, |path| {
+        path.exists() || names.contains(path)
+    });
+    names.insert(&path);
+    path
+}
+
+fn resolve_collision_by(
+    path: &Path,
+    policy: CollisionPolicy,
+    exists: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    if policy == CollisionPolicy::Overwrite || !exists(path) {
+        return path.to_path_buf();
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{} ({}){}", stem, i, extension)))
+        .find(|p| !exists(p))
+        .unwrap()
+}
+
+/// Checks that files can still be created in `dir` by creating and removing a probe file.
+pub async fn probe_writable(dir: &Path) -> io::Result<()> {
+    if !tokio::fs::metadata(dir).await?.is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    let probe = dir.join(format!(
+        ".localsend-probe-{}",
+        uuid::Uuid::new_v4().simple()
+    ));
+    tokio::fs::File::create(&probe).await?;
+    tokio::fs::remove_file(&probe).await.ok();
+    Ok(())
+}
+
+/// Bytes that can still be written below `dir`, `None` when unknown, e.g. on
+/// platforms other than Unix.
+#[cfg(unix)]
+pub fn available_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is NUL terminated and statvfs fills `stat` when it succeeds
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // the field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Whether writing may have failed because the directory is gone or read-only, e.g.
+/// an unplugged drive.
+pub fn is_destination_error(e: &io::Error) -> bool {
+    // EROFS, the error kind is not stable yet
+    let read_only = cfg!(unix) && e.raw_os_error() == Some(30);
+    read_only
+        || matches!(
+            e.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+        )
+}
+
+/// Whether a filesystem tells apart names that only differ in case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    Sensitive,
+    /// The default of macOS and Windows
+    Insensitive,
+}
+
+impl CaseSensitivity {
+    /// What the platform uses by default, when it can not be detected.
+    pub fn native() -> Self {
+        if cfg!(any(windows, target_os = "macos")) {
+            CaseSensitivity::Insensitive
+        } else {
+            CaseSensitivity::Sensitive
+        }
+    }
+
+    /// Creates a probe file in `dir`, or its closest existing parent, and looks
+    /// it up in upper case.
+    pub fn detect(dir: &Path) -> io::Result<Self> {
+        let dir = dir
+            .ancestors()
+            .map(|dir| match dir.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => dir,
+            })
+            .find(|dir| dir.is_dir())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let name = format!(".localsend-case-{}", uuid::Uuid::new_v4().simple());
+        let probe = dir.join(&name);
+        std::fs::File::create(&probe)?;
+        let insensitive = dir.join(name.to_uppercase()).exists();
+        std::fs::remove_file(&probe).ok();
+        Ok(match insensitive {
+            true => CaseSensitivity::Insensitive,
+            false => CaseSensitivity::Sensitive,
+        })
+    }
+
+    /// [`Self::detect`] falling back to [`Self::native`].
+    pub fn detect_or_native(dir: &Path) -> Self {
+        Self::detect(dir).unwrap_or_else(|e| {
+            log::debug!("Failed to detect the case sensitivity of {:?}: {}", dir, e);
+            Self::native()
+        })
+    }
+}
+
+impl FromStr for CaseSensitivity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sensitive" => Ok(CaseSensitivity::Sensitive),
+            "insensitive" => Ok(CaseSensitivity::Insensitive),
+            _ => Err(format!("unknown case sensitivity: {}", s)),
+        }
+    }
+}
+
+/// The form of `name` shared by all names a filesystem of `case` takes for the
+/// same file: NFC normalized, and in lower case when it is insensitive.
+///
+/// Names are compared in NFC on every filesystem, so that the NFD names of a Mac
+/// and the NFC names of others collide.
+pub fn collision_key(name: &str, case: CaseSensitivity) -> String {
+    let nfc: String = name.nfc().collect();
+    match case {
+        CaseSensitivity::Sensitive => nfc,
+        CaseSensitivity::Insensitive => nfc.to_lowercase(),
+    }
+}
+
+/// The collision keys of the names in the directories a session saves to, both
+/// those found there and those saved by the session.
+#[derive(Debug, Clone)]
+pub struct SessionNames {
+    case: CaseSensitivity,
+    /// Directory to the keys of its names, listed on first use
+    dirs: Arc<Mutex<HashMap<PathBuf, HashSet<String>>>>,
+}
+
+impl SessionNames {
+    pub fn new(case: CaseSensitivity) -> Self {
+        Self {
+            case,
+            dirs: Arc::default(),
+        }
+    }
+
+    pub fn case(&self) -> CaseSensitivity {
+        self.case
+    }
+
+    /// Whether a name of the directory of `path` collides with its file name.
+    pub fn contains(&self, path: &Path) -> bool {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+        let key = collision_key(&name.to_string_lossy(), self.case);
+        self.with_dir(dir, |names| names.contains(&key))
+    }
+
+    pub fn insert(&self, path: &Path) {
+        if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+            let key = collision_key(&name.to_string_lossy(), self.case);
+            self.with_dir(dir, |names| names.insert(key));
+        }
+    }
+
+    fn with_dir<T>(&self, dir: &Path, f: impl FnOnce(&mut HashSet<String>) -> T) -> T {
+        let mut dirs = self.dirs.lock().unwrap();
+        let names = dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+            std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| collision_key(&entry.file_name().to_string_lossy(), self.case))
+                .collect()
+        });
+        f(names)
+    }
+}
+
+/// The name of a saved file relative to the destination, with `/` separators.
+pub fn saved_name(path: &Path, destination: &Path) -> Option<String> {
+    let relative = path.strip_prefix(destination).ok()?;
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Some(components.join("/"))
+}
+
+/// Longest file name most filesystems accept, in bytes.
+pub const MAX_NAME_BYTES: usize = 255;
+
+const WINDOWS_INVALID_CHARS: &str = "<>:\"\\|?*";
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Which file names the destination filesystem accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameRules {
+    /// Anything but NUL
+    Unix,
+    /// Also applies to FAT and exFAT drives on other platforms
+    Windows,
+}
+
+impl NameRules {
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            NameRules::Windows
+        } else {
+            NameRules::Unix
+        }
+    }
+
+    pub fn is_invalid(&self, ch: char) -> bool {
+        match self {
+            NameRules::Unix => ch == '\0',
+            NameRules::Windows => ch < ' ' || WINDOWS_INVALID_CHARS.contains(ch),
+        }
+    }
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self::native()
+    }
+}
+
+/// Turns a received file name into a relative path the destination accepts.
+///
+/// Components are separated by `/`. Empty, `.` and `..` components are dropped,
+/// characters invalid for `rules` are replaced with `replacement` and names
+/// longer than [`MAX_NAME_BYTES`] are truncated keeping their extension.
+pub fn normalize_file_name(name: &str, rules: NameRules, replacement: char) -> String {
+    let components: Vec<String> = name
+        .split('/')
+        .filter(|c| !matches!(*c, "" | "." | ".."))
+        .map(|c| normalize_component(c, rules, replacement))
+        .collect();
+    if components.is_empty() {
+        return replacement.to_string();
+    }
+    components.join("/")
+}
+
+fn normalize_component(name: &str, rules: NameRules, replacement: char) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|ch| {
+            if rules.is_invalid(ch) {
+                replacement
+            } else {
+                ch
+            }
+        })
+        .collect();
+
+    if rules == NameRules::Windows {
+        let trimmed = name.trim_end_matches(['.', ' ']);
+        if trimmed.len() != name.len() {
+            let trailing = name.len() - trimmed.len();
+            name = format!("{}{}", trimmed, replacement.to_string().repeat(trailing));
+        }
+        let (stem, rest) = name.split_at(name.find('.').unwrap_or(name.len()));
+        if WINDOWS_RESERVED_NAMES.contains(&stem.to_ascii_uppercase().as_str()) {
+            name = format!("{}{}{}", stem, replacement, rest);
+        }
+    }
+
+    truncate_name(&name, MAX_NAME_BYTES)
+}
+
+fn truncate_name(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_owned();
+    }
+    // keep short extensions only, a dot far into a long name is not one
+    let extension = match name.rfind('.') {
+        Some(i) if i > 0 && name.len() - i <= 16 => &name[i..],
+        _ => "",
+    };
+    let stem = &name[..name.len() - extension.len()];
+    let mut end = max_bytes - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], extension)
+}
+
+/// Appended to the names of received files with a dangerous extension.
+pub const DEFUSED_SUFFIX: &str = ".received";
+
+const WINDOWS_DANGEROUS_EXTENSIONS: [&str; 9] =
+    ["exe", "scr", "bat", "cmd", "com", "lnk", "ps1", "vbs", "js"];
+
+/// Extensions of files that run when opened, saved with [`DEFUSED_SUFFIX`] appended.
+///
+/// Only the extension of the name saved is looked at, after the name was made one
+/// the destination accepts and without trailing dots and spaces, which Windows drops.
+/// Extensions are compared ignoring ASCII case, lookalike characters are no extension
+/// any program runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DangerousExtensions(Vec<String>);
+
+impl DangerousExtensions {
+    pub fn new<S: AsRef<str>>(extensions: impl IntoIterator<Item = S>) -> Self {
+        Self(
+            extensions
+                .into_iter()
+                .map(|extension| extension.as_ref().trim_start_matches('.').to_owned())
+                .filter(|extension| !extension.is_empty())
+                .collect(),
+        )
+    }
+
+    /// The extensions Windows runs when opened there, none elsewhere.
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Self::new(WINDOWS_DANGEROUS_EXTENSIONS)
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The name to save a normalized file name as when its extension is dangerous.
+    pub fn defuse(&self, name: &str) -> Option<String> {
+        let (parent, file_name) = match name.rsplit_once('/') {
+            Some((parent, file_name)) => (Some(parent), file_name),
+            None => (None, name),
+        };
+        let trimmed = file_name.trim_end_matches(['.', ' ']);
+        let (_, extension) = trimmed.rsplit_once('.')?;
+        if !self
+            .0
+            .iter()
+            .any(|dangerous| dangerous.eq_ignore_ascii_case(extension))
+        {
+            return None;
+        }
+        let defused = truncate_name(&format!("{}{}", trimmed, DEFUSED_SUFFIX), MAX_NAME_BYTES);
+        Some(match parent {
+            Some(parent) => format!("{}/{}", parent, defused),
+            None => defused,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        collision_key, normalize_file_name, resolve_session_collision, CaseSensitivity,
+        DangerousExtensions, NameRules, SessionNames, MAX_NAME_BYTES,
+    };
+    use crate::CollisionPolicy;
+
+    fn windows(name: &str) -> String {
+        normalize_file_name(name, NameRules::Windows, '_')
+    }
+
+    fn unix(name: &str) -> String {
+        normalize_file_name(name, NameRules::Unix, '_')
+    }
+
+    #[test]
+    fn test_invalid_chars() {
+        assert_eq!(windows("photo:2024?.jpg"), "photo_2024_.jpg");
+        assert_eq!(windows("a<b>c\"d\\e|f*g\u{1}.txt"), "a_b_c_d_e_f_g_.txt");
+        assert_eq!(unix("photo:2024?.jpg"), "photo:2024?.jpg");
+        assert_eq!(unix("nul\0byte"), "nul_byte");
+        assert_eq!(normalize_file_name("a?b", NameRules::Windows, '-'), "a-b");
+    }
+
+    #[test]
+    fn test_windows_names() {
+        assert_eq!(windows("notes. "), "notes__");
+        assert_eq!(windows("dir./file"), "dir_/file");
+        assert_eq!(windows("CON"), "CON_");
+        assert_eq!(windows("nul.txt"), "nul_.txt");
+        assert_eq!(windows("com1.tar.gz"), "com1_.tar.gz");
+        assert_eq!(windows("CONSOLE.txt"), "CONSOLE.txt");
+        assert_eq!(unix("CON"), "CON");
+        assert_eq!(unix("notes. "), "notes. ");
+    }
+
+    #[test]
+    fn test_components() {
+        assert_eq!(unix("photos/2024/a.jpg"), "photos/2024/a.jpg");
+        assert_eq!(unix("../../etc/passwd"), "etc/passwd");
+        assert_eq!(unix("/abs//./a.jpg"), "abs/a.jpg");
+        assert_eq!(unix(".."), "_");
+    }
+
+    #[test]
+    fn test_truncate() {
+        let long = format!("{}.jpg", "a".repeat(300));
+        let name = unix(&long);
+        assert_eq!(name.len(), MAX_NAME_BYTES);
+        assert!(name.ends_with("a.jpg"));
+
+        // never splits a multi-byte character
+        let long = format!("{}.txt", "é".repeat(200));
+        let name = unix(&long);
+        assert!(name.len() <= MAX_NAME_BYTES);
+        assert!(name.ends_with("é.txt"));
+
+        let no_extension = "b".repeat(300);
+        assert_eq!(unix(&no_extension).len(), MAX_NAME_BYTES);
+    }
+
+    #[test]
+    fn test_dangerous_extensions() {
+        let dangerous = DangerousExtensions::new(["exe", ".lnk", "js"]);
+        let defuse = |name: &str| dangerous.defuse(&unix(name));
+        assert_eq!(
+            defuse("invoice.pdf.exe").as_deref(),
+            Some("invoice.pdf.exe.received")
+        );
+        assert_eq!(defuse("SETUP.EXE").as_deref(), Some("SETUP.EXE.received"));
+        assert_eq!(defuse("Report.Lnk").as_deref(), Some("Report.Lnk.received"));
+        // Windows drops trailing dots and spaces
+        assert_eq!(defuse("run.exe.").as_deref(), Some("run.exe.received"));
+        assert_eq!(defuse("run.exe . ").as_deref(), Some("run.exe.received"));
+        assert_eq!(
+            defuse("photos/app.js").as_deref(),
+            Some("photos/app.js.received")
+        );
+        assert_eq!(defuse("invoice.exe.pdf"), None);
+        assert_eq!(defuse("exe"), None);
+        assert_eq!(defuse("notes.json"), None);
+        // lookalikes are no extension Windows runs
+        assert_eq!(defuse("run.\u{ff45}\u{ff58}\u{ff45}"), None);
+        assert_eq!(defuse("run.\u{435}x\u{435}"), None);
+        // the name saved on Windows has no trailing dot to drop
+        assert_eq!(dangerous.defuse(&windows("run.exe.")), None);
+
+        let long = format!("{}.exe", "a".repeat(300));
+        let defused = defuse(&long).unwrap();
+        assert_eq!(defused.len(), MAX_NAME_BYTES);
+        assert!(defused.ends_with(".received"));
+
+        assert_eq!(DangerousExtensions::default().defuse("run.exe"), None);
+        assert_eq!(DangerousExtensions::native().is_empty(), cfg!(not(windows)));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_native_rules() {
+        assert_eq!(NameRules::native(), NameRules::Windows);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_native_rules() {
+        assert_eq!(NameRules::native(), NameRules::Unix);
+    }
+
+    #[test]
+    fn test_collision_key() {
+        let nfc = "caf\u{e9}.txt";
+        let nfd = "cafe\u{301}.txt";
+        assert_ne!(nfc, nfd);
+        for case in [CaseSensitivity::Sensitive, CaseSensitivity::Insensitive] {
+            assert_eq!(collision_key(nfc, case), collision_key(nfd, case));
+        }
+        assert_ne!(
+            collision_key("Readme.md", CaseSensitivity::Sensitive),
+            collision_key("README.md", CaseSensitivity::Sensitive)
+        );
+        assert_eq!(
+            collision_key("Readme.md", CaseSensitivity::Insensitive),
+            collision_key("README.md", CaseSensitivity::Insensitive)
+        );
+        assert_eq!(
+            collision_key("CAF\u{c9}", CaseSensitivity::Insensitive),
+            collision_key("cafe\u{301}", CaseSensitivity::Insensitive)
+        );
+    }
+
+    #[test]
+    fn test_session_collisions() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Readme.md"), "").unwrap();
+        let rename = CollisionPolicy::Rename;
+        let resolve = |names: &SessionNames, name: &str| {
+            let path = resolve_session_collision(dir.join(name), rename, names);
+            path.file_name().unwrap().to_string_lossy().to_string()
+        };
+
+        let insensitive = SessionNames::new(CaseSensitivity::Insensitive);
+        // an existing file and one of the session differing in case
+        assert_eq!(resolve(&insensitive, "README.md"), "README (1).md");
+        assert_eq!(resolve(&insensitive, "notes.txt"), "notes.txt");
+        assert_eq!(resolve(&insensitive, "NOTES.txt"), "NOTES (1).txt");
+        assert_eq!(resolve(&insensitive, "cafe\u{301}"), "cafe\u{301}");
+        assert_eq!(resolve(&insensitive, "CAF\u{c9}"), "CAF\u{c9} (1)");
+
+        let sensitive = SessionNames::new(CaseSensitivity::Sensitive);
+        assert_eq!(resolve(&sensitive, "README.md"), "README.md");
+        assert_eq!(resolve(&sensitive, "caf\u{e9}"), "caf\u{e9}");
+        // the same name in another normal form still collides
+        assert_eq!(resolve(&sensitive, "cafe\u{301}"), "cafe\u{301} (1)");
+        assert!(!sensitive.contains(Path::new("/nonexistent/x")));
+
+        // overwriting keeps the name as offered
+        let path = resolve_session_collision(
+            dir.join("readme.md"),
+            CollisionPolicy::Overwrite,
+            &insensitive,
+        );
+        assert_eq!(path, dir.join("readme.md"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_detect_case_sensitivity() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        // the closest existing parent is probed, nothing is left behind
+        let case = CaseSensitivity::detect(&dir.join("missing")).unwrap();
+        assert!(!dir.exists());
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(CaseSensitivity::detect(&dir).unwrap(), case);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Digest algorithm of a [`FileHash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Sent by peers older than protocol v2.1
+    Md5,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Picks the algorithm of a hex digest by its length.
+    pub fn detect(hex: &str) -> Option<Self> {
+        if !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return None;
+        }
+        match hex.len() {
+            32 => Some(HashAlgorithm::Md5),
+            64 => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// A digest as carried in the `sha256` field of a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FileHash {
+    algorithm: HashAlgorithm,
+    hex: String,
+}
+
+impl FileHash {
+    pub fn sha256(data: impl AsRef<[u8]>) -> Self {
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(data.as_ref());
+        hasher.finalize()
+    }
+
+    /// Reads a digest sent by a peer, `None` when its algorithm is unknown.
+    pub fn parse(hex: &str) -> Option<Self> {
+        let algorithm = HashAlgorithm::detect(hex)?;
+        Some(Self {
+            algorithm,
+            hex: hex.to_ascii_lowercase(),
+        })
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+
+    pub fn verify(&self, data: impl AsRef<[u8]>) -> bool {
+        let mut hasher = self.algorithm.hasher();
+        hasher.update(data.as_ref());
+        hasher.finalize() == *self
+    }
+}
+
+impl fmt::Display for FileHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.hex)
+    }
+}
+
+impl TryFrom<String> for FileHash {
+    type Error = String;
+
+    fn try_from(hex: String) -> Result<Self, Self::Error> {
+        Self::parse(&hex).ok_or_else(|| format!("not an md5 or sha256 digest: {}", hex))
+    }
+}
+
+impl From<FileHash> for String {
+    fn from(hash: FileHash) -> Self {
+        hash.hex
+    }
+}
+
+/// Incremental form of [`FileHash`], for bodies read in chunks.
+pub enum Hasher {
+    Md5(md5::Context),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(context) => context.consume(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> FileHash {
+        match self {
+            Hasher::Md5(context) => FileHash {
+                algorithm: HashAlgorithm::Md5,
+                hex: format!("{:x}", context.compute()),
+            },
+            Hasher::Sha256(hasher) => FileHash {
+                algorithm: HashAlgorithm::Sha256,
+                hex: format!("{:x}", hasher.finalize()),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hasher::Md5(_) => f.write_str("Hasher::Md5"),
+            Hasher::Sha256(_) => f.write_str("Hasher::Sha256"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::dto::FileDto;
+
+    use super::{FileHash, HashAlgorithm};
+
+    /// A text message as offered by the official app, with a shortened id.
+    const OFFICIAL_TEXT: &str = r#"{
+        "id": "1a2b",
+        "fileName": "Hello from LocalSend.txt",
+        "size": 21,
+        "fileType": "text/plain",
+        "sha256": "240b1eabb6e32072effb1dea67ffc04a6a7854006e0fdeaefb1c19666c0eb5ef",
+        "preview": "Hello from LocalSend!"
+    }"#;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            HashAlgorithm::detect("5d41402abc4b2a76b9719d911017c592"),
+            Some(HashAlgorithm::Md5)
+        );
+        assert_eq!(
+            HashAlgorithm::detect(
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+            ),
+            Some(HashAlgorithm::Sha256)
+        );
+        assert_eq!(HashAlgorithm::detect("2cf24dba"), None);
+        assert_eq!(
+            HashAlgorithm::detect("zz41402abc4b2a76b9719d911017c592"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let sha256 = FileHash::sha256("hello");
+        assert_eq!(sha256.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(
+            sha256.hex(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert!(sha256.verify("hello"));
+        assert!(!sha256.verify("hello!"));
+
+        // digests of older peers
+        let md5 = FileHash::parse("5D41402ABC4B2A76B9719D911017C592").unwrap();
+        assert_eq!(md5.algorithm(), HashAlgorithm::Md5);
+        assert!(md5.verify("hello"));
+        assert!(!md5.verify("hello!"));
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let hash = FileHash::sha256("hello");
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(
+            json,
+            "\"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\""
+        );
+        assert_eq!(serde_json::from_str::<FileHash>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<FileHash>("\"abc\"").is_err());
+
+        let file: FileDto = serde_json::from_str(OFFICIAL_TEXT).unwrap();
+        let hash = FileHash::parse(file.hash.as_deref().unwrap()).unwrap();
+        assert_eq!(hash.algorithm(), HashAlgorithm::Sha256);
+        assert!(hash.verify(file.preview.as_deref().unwrap()));
+
+        let json = serde_json::to_value(&file).unwrap();
+        assert_eq!(json["sha256"], hash.hex());
+        assert!(json.get("hash").is_none());
+    }
+
+    #[test]
+    fn test_legacy_hash_field() {
+        let file: FileDto = serde_json::from_str(
+            r#"{"id": "a", "fileName": "a.txt", "size": 5, "fileType": "text/plain",
+                "hash": "5d41402abc4b2a76b9719d911017c592", "preview": "hello"}"#,
+        )
+        .unwrap();
+        let hash = FileHash::parse(file.hash.as_deref().unwrap()).unwrap();
+        assert_eq!(hash.algorithm(), HashAlgorithm::Md5);
+        assert!(hash.verify("hello"));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    fmt, io,
+    sync::{Arc, OnceLock},
+};
+
+/// Keeps the system awake while it is held, released on drop.
+pub trait InhibitLock: Send + Sync {}
+
+/// Asks the operating system not to sleep, e.g. on idle or when the lid is closed.
+pub trait SleepInhibitor: Send + Sync + fmt::Debug {
+    fn inhibit(&self, reason: &str) -> io::Result<Box<dyn InhibitLock>>;
+}
+
+/// Inhibits nothing, what builds without the `inhibit-sleep` feature use.
+#[derive(Debug, Default)]
+pub struct NoInhibitor;
+
+struct NoLock;
+
+impl InhibitLock for NoLock {}
+
+impl SleepInhibitor for NoInhibitor {
+    fn inhibit(&self, _reason: &str) -> io::Result<Box<dyn InhibitLock>> {
+        Ok(Box::new(NoLock))
+    }
+}
+
+static INHIBITOR: OnceLock<Arc<dyn SleepInhibitor>> = OnceLock::new();
+
+/// Replaces the inhibitor of every [`TransferGuard::acquire`], only the first call counts.
+pub fn set_sleep_inhibitor(inhibitor: Arc<dyn SleepInhibitor>) {
+    if INHIBITOR.set(inhibitor).is_err() {
+        log::debug!("The sleep inhibitor was set before");
+    }
+}
+
+fn sleep_inhibitor() -> &'static Arc<dyn SleepInhibitor> {
+    INHIBITOR.get_or_init(default_inhibitor)
+}
+
+#[cfg(all(not(test), feature = "inhibit-sleep"))]
+fn default_inhibitor() -> Arc<dyn SleepInhibitor> {
+    Arc::new(system::SystemInhibitor)
+}
+
+/// Tests share one recording inhibitor, in place before any transfer may start.
+#[cfg(test)]
+fn default_inhibitor() -> Arc<dyn SleepInhibitor> {
+    mock::recording()
+}
+
+#[cfg(all(not(test), not(feature = "inhibit-sleep")))]
+fn default_inhibitor() -> Arc<dyn SleepInhibitor> {
+    Arc::new(NoInhibitor)
+}
+
+/// Keeps the system awake for as long as a transfer runs.
+///
+/// Held from the start of the upload loop of a send and from the moment a receive
+/// session starts receiving, dropped with the session however it ends. A receiver
+/// waiting for offers holds none. Failing to inhibit sleep is only a warning.
+pub struct TransferGuard {
+    lock: Option<Box<dyn InhibitLock>>,
+}
+
+impl TransferGuard {
+    pub fn acquire(reason: &str) -> Self {
+        Self::with(sleep_inhibitor().as_ref(), reason)
+    }
+
+    pub fn with(inhibitor: &dyn SleepInhibitor, reason: &str) -> Self {
+        let lock = match inhibitor.inhibit(reason) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                log::warn!("Failed to keep the system awake during the transfer: {}", e);
+                None
+            }
+        };
+        Self { lock }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.lock.is_some()
+    }
+}
+
+impl fmt::Debug for TransferGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferGuard")
+            .field("held", &self.is_held())
+            .finish()
+    }
+}
+
+#[cfg(feature = "inhibit-sleep")]
+mod system {
+    use std::io;
+    #[cfg(unix)]
+    use std::{
+        process::Child,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{InhibitLock, SleepInhibitor};
+
+    /// systemd-logind on Linux, IOPMAssertions through caffeinate on macOS and
+    /// `SetThreadExecutionState` on Windows.
+    #[derive(Debug, Default)]
+    pub struct SystemInhibitor;
+
+    /// The helper holds the lock until it is killed, taken out of the mutex on drop.
+    #[cfg(unix)]
+    struct ChildLock(Arc<Mutex<Option<Child>>>);
+
+    #[cfg(unix)]
+    impl InhibitLock for ChildLock {}
+
+    #[cfg(unix)]
+    impl Drop for ChildLock {
+        fn drop(&mut self) {
+            let child = self.0.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(mut child) = child {
+                child.kill().ok();
+                child.wait().ok();
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn spawn(program: &str, args: &[&str]) -> io::Result<Box<dyn InhibitLock>> {
+        use std::process::{Command, Stdio};
+
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let child = Arc::new(Mutex::new(Some(child)));
+        // a helper refused by logind or without a session bus exits right away,
+        // checked aside so the transfer does not wait for it
+        let watched = child.clone();
+        let program = program.to_owned();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            let mut child = watched.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(Ok(Some(status))) = child.as_mut().map(Child::try_wait) {
+                log::warn!(
+                    "{} exited with {}, the system may sleep during the transfer",
+                    program,
+                    status
+                );
+            }
+        });
+        Ok(Box::new(ChildLock(child)))
+    }
+
+    #[cfg(target_os = "macos")]
+    impl SleepInhibitor for SystemInhibitor {
+        fn inhibit(&self, _reason: &str) -> io::Result<Box<dyn InhibitLock>> {
+            spawn("caffeinate", &["-i", "-s"])
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    impl SleepInhibitor for SystemInhibitor {
+        fn inhibit(&self, reason: &str) -> io::Result<Box<dyn InhibitLock>> {
+            spawn(
+                "systemd-inhibit",
+                &[
+                    "--what=sleep:idle:handle-lid-switch",
+                    "--who=localsend",
+                    &format!("--why={}", reason),
+                    "--mode=block",
+                    "sleep",
+                    "infinity",
+                ],
+            )
+        }
+    }
+
+    /// The execution state belongs to a thread, so one is kept for the lock.
+    #[cfg(windows)]
+    struct ThreadLock(Option<std::sync::mpsc::Sender<()>>);
+
+    #[cfg(windows)]
+    impl InhibitLock for ThreadLock {}
+
+    #[cfg(windows)]
+    impl Drop for ThreadLock {
+        fn drop(&mut self) {
+            // the thread resets the state once the sender is gone
+            self.0.take();
+        }
+    }
+
+    #[cfg(windows)]
+    impl SleepInhibitor for SystemInhibitor {
+        fn inhibit(&self, _reason: &str) -> io::Result<Box<dyn InhibitLock>> {
+            use windows_sys::Win32::System::Power::{
+                SetThreadExecutionState, ES_CONTINUOUS, ES_SYSTEM_REQUIRED,
+            };
+
+            let (release, released) = std::sync::mpsc::channel::<()>();
+            let (started, start) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let previous =
+                    unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+                started.send(previous != 0).ok();
+                released.recv().ok();
+                unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+            });
+            match start.recv() {
+                Ok(true) => Ok(Box::new(ThreadLock(Some(release)))),
+                _ => Err(io::Error::last_os_error()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::{
+        io,
+        sync::{Arc, Mutex, OnceLock},
+    };
+
+    use super::{InhibitLock, SleepInhibitor};
+
+    /// Records the reasons of the locks held.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockInhibitor {
+        held: Arc<Mutex<Vec<String>>>,
+        fail: bool,
+    }
+
+    impl MockInhibitor {
+        /// Fails every attempt, as without logind.
+        pub(crate) fn failing() -> Self {
+            Self {
+                fail: true,
+                ..Default::default()
+            }
+        }
+
+        /// How many locks are held for `reason`.
+        pub(crate) fn held(&self, reason: &str) -> usize {
+            let held = self.held.lock().unwrap();
+            held.iter().filter(|held| *held == reason).count()
+        }
+    }
+
+    struct MockLock(Arc<Mutex<Vec<String>>>, String);
+
+    impl InhibitLock for MockLock {}
+
+    impl Drop for MockLock {
+        fn drop(&mut self) {
+            let mut held = self.0.lock().unwrap();
+            if let Some(index) = held.iter().position(|held| *held == self.1) {
+                held.remove(index);
+            }
+        }
+    }
+
+    impl SleepInhibitor for MockInhibitor {
+        fn inhibit(&self, reason: &str) -> io::Result<Box<dyn InhibitLock>> {
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no logind"));
+            }
+            self.held.lock().unwrap().push(reason.to_owned());
+            Ok(Box::new(MockLock(self.held.clone(), reason.to_owned())))
+        }
+    }
+
+    /// The inhibitor of every [super::TransferGuard::acquire] in tests.
+    pub(crate) fn recording() -> Arc<MockInhibitor> {
+        static RECORDING: OnceLock<Arc<MockInhibitor>> = OnceLock::new();
+        RECORDING.get_or_init(Default::default).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::MockInhibitor, TransferGuard};
+
+    #[test]
+    fn test_transfer_guard() {
+        let inhibitor = MockInhibitor::default();
+        let first = TransferGuard::with(&inhibitor, "sending");
+        let second = TransferGuard::with(&inhibitor, "receiving");
+        assert!(first.is_held());
+        assert_eq!(inhibitor.held("sending"), 1);
+        assert_eq!(inhibitor.held("receiving"), 1);
+        drop(first);
+        assert_eq!(inhibitor.held("sending"), 0);
+        drop(second);
+        assert_eq!(inhibitor.held("receiving"), 0);
+
+        // a failure only warns
+        let failing = MockInhibitor::failing();
+        assert!(!TransferGuard::with(&failing, "sending").is_held());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{io::Read, path::Path, str::FromStr};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Bytes read from the start of a file to sniff its content type.
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// The content type of a file starting with `head`, `None` when nothing can be told,
+/// e.g. for an empty file.
+///
+/// Text with a UTF-16 byte order mark and anything without NUL bytes is plain text.
+pub fn sniff_mime(head: &[u8]) -> Option<&'static str> {
+    match head {
+        [] => None,
+        [0xFF, 0xFE, ..] => Some("text/plain; charset=utf-16le"),
+        [0xFE, 0xFF, ..] => Some("text/plain; charset=utf-16be"),
+        _ if head.contains(&0) => None,
+        _ if std::str::from_utf8(head).is_ok() => Some("text/plain; charset=utf-8"),
+        // cut in the middle of a character or another encoding
+        _ => Some("text/plain"),
+    }
+}
+
+/// Sniffs the first [`SNIFF_LEN`] bytes of the file at `path`, blocking.
+pub fn sniff_file(path: &Path) -> std::io::Result<Option<&'static str>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(sniff_mime(&head))
+}
+
+/// A `--mime` rule, files matching the glob are uploaded with the content type.
+#[derive(Debug, Clone)]
+pub struct MimeOverride {
+    pub glob: String,
+    pub mime: String,
+    matcher: Gitignore,
+}
+
+impl MimeOverride {
+    pub fn matches(&self, file_name: &str) -> bool {
+        self.matcher.matched(file_name, false).is_ignore()
+    }
+}
+
+impl FromStr for MimeOverride {
+    type Err = String;
+
+    /// Parses `<glob>=<type>`, like `*.jsonl=application/x-ndjson`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (glob, mime) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected <glob>=<type>, got {}", s))?;
+        let (glob, mime) = (glob.trim(), mime.trim());
+        if glob.is_empty() {
+            return Err("missing glob".to_owned());
+        }
+        if !mime.contains('/') || mime.contains(char::is_whitespace) {
+            return Err(format!("invalid content type {}", mime));
+        }
+        let mut builder = GitignoreBuilder::new("");
+        builder
+            .add_line(None, glob)
+            .map_err(|e| format!("invalid glob {}: {}", glob, e))?;
+        let matcher = builder.build().map_err(|e| e.to_string())?;
+        Ok(Self {
+            glob: glob.to_owned(),
+            mime: mime.to_owned(),
+            matcher,
+        })
+    }
+}
+
+/// How the content types of uploads are chosen, the file types offered in the
+/// prepare-upload request are not affected.
+#[derive(Debug, Clone)]
+pub struct ContentTypes {
+    /// Checked in order before anything else, the first matching one wins
+    pub overrides: Vec<MimeOverride>,
+    /// Look into files whose name says nothing about them
+    pub sniff: bool,
+}
+
+impl Default for ContentTypes {
+    fn default() -> Self {
+        Self {
+            overrides: vec![],
+            sniff: true,
+        }
+    }
+}
+
+impl ContentTypes {
+    /// The content type of the file `file_name` read from `path`, `None` when the
+    /// guess by name is as good as it gets.
+    ///
+    /// Reads the start of the file, call it while preparing the files.
+    pub fn resolve(&self, file_name: &str, path: &Path) -> Option<String> {
+        if let Some(rule) = self.overrides.iter().find(|rule| rule.matches(file_name)) {
+            return Some(rule.mime.clone());
+        }
+        if !self.sniff || guess_mime(file_name) != OCTET_STREAM {
+            return None;
+        }
+        match sniff_file(path) {
+            Ok(mime) => mime.map(str::to_owned),
+            Err(e) => {
+                log::debug!("failed to sniff {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+/// The content type of a file by its name, octet-stream when unknown.
+pub fn guess_mime(file_name: &str) -> String {
+    mime_guess::from_path(file_name)
+        .first_or_octet_stream()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{sniff_mime, ContentTypes, MimeOverride, SNIFF_LEN};
+
+    #[test]
+    fn test_sniff_mime() {
+        assert_eq!(
+            sniff_mime(b"all:\n\tcargo build\n"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            sniff_mime("Grüße".as_bytes()),
+            Some("text/plain; charset=utf-8")
+        );
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("hi".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(sniff_mime(&utf16), Some("text/plain; charset=utf-16le"));
+        assert_eq!(sniff_mime(b"\x7fELF\x02\x01\x01\x00\x00"), None);
+        assert_eq!(sniff_mime(b""), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let makefile = dir.join("Makefile");
+        std::fs::write(&makefile, "all:\n").unwrap();
+        // text after the sniffed part does not count
+        let binary = dir.join("app");
+        let mut bytes = vec![0u8; SNIFF_LEN];
+        bytes.extend(b"text");
+        std::fs::write(&binary, bytes).unwrap();
+
+        let types = ContentTypes::default();
+        assert_eq!(
+            types.resolve("Makefile", &makefile).as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(types.resolve("app", &binary), None);
+        // known by name, not read
+        assert_eq!(types.resolve("photo.jpg", Path::new("missing")), None);
+
+        let types = ContentTypes {
+            overrides: vec!["*.jpg=image/x-custom".parse().unwrap()],
+            sniff: false,
+        };
+        assert_eq!(
+            types
+                .resolve("dir/photo.jpg", Path::new("missing"))
+                .as_deref(),
+            Some("image/x-custom")
+        );
+        assert_eq!(types.resolve("Makefile", &makefile), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_override() {
+        let rule: MimeOverride = "*.jsonl=application/x-ndjson".parse().unwrap();
+        assert!(rule.matches("logs/today.jsonl"));
+        assert!(!rule.matches("today.json"));
+        assert!("*.jsonl".parse::<MimeOverride>().is_err());
+        assert!("=text/plain".parse::<MimeOverride>().is_err());
+        assert!("*.txt=plain".parse::<MimeOverride>().is_err());
+    }
+}
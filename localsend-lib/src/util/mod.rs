@@ -1 +1,10 @@
+pub mod compression;
 pub mod device;
+pub mod fs;
+pub mod hash;
+pub mod inhibit;
+pub mod mime;
+pub mod note;
+pub mod preview;
+pub mod resolve;
+pub mod trace;
@@ -0,0 +1,73 @@
+use localsend_proto::dto::{FileDto, FileType};
+use uuid::Uuid;
+
+use crate::util::hash::FileHash;
+
+/// Name of the text file carrying a note, what official receivers save it as.
+pub const NOTE_FILE_NAME: &str = "_localsend_note.txt";
+/// Prefix of the file id telling localsend-rs receivers a note from a text file.
+pub const NOTE_ID_PREFIX: &str = "x-localsend-rs-note-";
+
+/// A note attached to an offer, sent as a small text file with the note as preview.
+///
+/// localsend-rs receivers show the note when asking about the offer and do not
+/// save it, other receivers get a harmless text file.
+pub fn note_file(note: &str) -> FileDto {
+    let hash = FileHash::sha256(note).to_string();
+    FileDto {
+        id: format!("{}{}", NOTE_ID_PREFIX, Uuid::new_v4()),
+        file_name: NOTE_FILE_NAME.to_owned(),
+        size: note.len() as u64,
+        file_type: FileType::Text,
+        hash: Some(hash),
+        preview: Some(note.to_owned()),
+    }
+}
+
+/// Whether an offered file is a note, its type is not checked since it is read
+/// back as a mime type.
+pub fn is_note(file: &FileDto) -> bool {
+    file.id.starts_with(NOTE_ID_PREFIX)
+        && file.file_name == NOTE_FILE_NAME
+        && file.preview.is_some()
+}
+
+/// Removes the note from the offered `files`, returning its text.
+pub fn take_note(files: &mut Vec<FileDto>) -> Option<String> {
+    let index = files.iter().position(is_note)?;
+    files.remove(index).preview
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::dto::FileDto;
+
+    use super::{is_note, note_file, take_note, NOTE_FILE_NAME};
+
+    #[test]
+    fn test_note_file() {
+        let note = note_file("photos from Tuesday");
+        assert!(is_note(&note));
+        assert_eq!(note.size, 19);
+
+        // a text file of the same name sent by anyone else stays a file
+        let text = FileDto {
+            id: "1".to_owned(),
+            ..note.clone()
+        };
+        assert!(!is_note(&text));
+        let renamed = FileDto {
+            file_name: format!("x{}", NOTE_FILE_NAME),
+            ..note.clone()
+        };
+        assert!(!is_note(&renamed));
+
+        let mut files = vec![text.clone(), note];
+        assert_eq!(
+            take_note(&mut files).as_deref(),
+            Some("photos from Tuesday")
+        );
+        assert_eq!(files.len(), 1);
+        assert_eq!(take_note(&mut files), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Longest text previewed by default, what localsend-rs always used.
+pub const DEFAULT_PREVIEW_MAX_BYTES: usize = 1024;
+
+/// Which content is sent and shown inline as a preview rather than as a file.
+///
+/// Previews of texts are what official receivers show as messages. Files are only
+/// previewed when `files` is set, since those receivers would show them as messages
+/// too. Content with NUL bytes is binary whatever its name says.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PreviewPolicy {
+    /// Largest content previewed, in bytes
+    pub max_bytes: usize,
+    /// Extensions of files previewed whatever their mime type, e.g. `log`
+    pub extensions: Vec<String>,
+    /// Mime types of files previewed, matched as prefixes, e.g. `text/plain`
+    pub mime_prefixes: Vec<String>,
+    /// Remove terminal escape sequences from texts printed to the terminal
+    pub strip_ansi: bool,
+    /// Send previews of small files of the allowed types
+    pub files: bool,
+}
+
+impl Default for PreviewPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_PREVIEW_MAX_BYTES,
+            extensions: ["txt", "md", "log"].map(String::from).to_vec(),
+            mime_prefixes: ["text/plain", "text/markdown"].map(String::from).to_vec(),
+            strip_ansi: true,
+            files: false,
+        }
+    }
+}
+
+impl PreviewPolicy {
+    /// Whether a file of this name may be previewed, by extension or mime type.
+    pub fn allows(&self, file_name: &str) -> bool {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        if self
+            .extensions
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(extension))
+        {
+            return true;
+        }
+        let mime = mime_guess::from_path(file_name).first_or_octet_stream();
+        self.mime_prefixes
+            .iter()
+            .any(|prefix| mime.essence_str().starts_with(prefix.as_str()))
+    }
+
+    /// The preview of `content`, `None` when it is too large or not text.
+    pub fn preview(&self, content: &[u8]) -> Option<String> {
+        if content.len() > self.max_bytes || content.contains(&0) {
+            return None;
+        }
+        String::from_utf8(content.to_vec()).ok()
+    }
+
+    /// The preview of a file to send, `None` unless file previews are on and the
+    /// file is small and of an allowed type.
+    pub fn file_preview(&self, path: &Path, file_name: &str, size: u64) -> Option<String> {
+        if !self.files || size > self.max_bytes as u64 || !self.allows(file_name) {
+            return None;
+        }
+        self.preview(&std::fs::read(path).ok()?)
+    }
+
+    /// What is printed of received `content`, `None` when it is too large or not text.
+    pub fn printable(&self, content: &[u8]) -> Option<String> {
+        let text = self.preview(content)?;
+        Some(match self.strip_ansi {
+            true => strip_ansi(&text),
+            false => text,
+        })
+    }
+}
+
+/// Removes escape sequences, e.g. colors or cursor movements, from `text`.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            stripped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            // CSI, parameters up to a final byte
+            Some('[') => {
+                for ch in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&ch) {
+                        break;
+                    }
+                }
+            }
+            // OSC, up to BEL or ESC \
+            Some(']') => {
+                while let Some(ch) = chars.next() {
+                    if ch == '\x07' {
+                        break;
+                    }
+                    if ch == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_ansi, PreviewPolicy};
+
+    #[test]
+    fn test_preview_policy() {
+        let policy = PreviewPolicy {
+            max_bytes: 8,
+            ..PreviewPolicy::default()
+        };
+        assert_eq!(policy.preview(b"hello").as_deref(), Some("hello"));
+        assert_eq!(policy.preview(b"12345678").as_deref(), Some("12345678"));
+        assert_eq!(policy.preview(b"123456789"), None);
+        // NUL bytes are binary, whatever the name
+        assert_eq!(policy.preview(b"a\0b"), None);
+        assert_eq!(policy.preview(&[0xff, 0xfe]), None);
+
+        assert!(policy.allows("notes.md"));
+        assert!(policy.allows("server.LOG"));
+        assert!(policy.allows("readme.txt"));
+        assert!(!policy.allows("data.json"));
+        assert!(!policy.allows("main.rs"));
+        assert!(!policy.allows("photo.jpg"));
+    }
+
+    #[test]
+    fn test_file_preview() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = dir.join("a.log");
+        std::fs::write(&text, b"started").unwrap();
+        let binary = dir.join("b.log");
+        std::fs::write(&binary, b"x\0y").unwrap();
+
+        let policy = PreviewPolicy {
+            files: true,
+            ..PreviewPolicy::default()
+        };
+        assert_eq!(
+            policy.file_preview(&text, "a.log", 7).as_deref(),
+            Some("started")
+        );
+        assert_eq!(policy.file_preview(&binary, "b.log", 3), None);
+        assert_eq!(policy.file_preview(&text, "a.json", 7), None);
+        assert_eq!(policy.file_preview(&text, "a.log", 2048), None);
+        assert_eq!(
+            PreviewPolicy::default().file_preview(&text, "a.log", 7),
+            None
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_printable() {
+        let text = "\x1b[31mred\x1b[0m \x1b]0;title\x07done\n";
+        assert_eq!(strip_ansi(text), "red done\n");
+        let policy = PreviewPolicy::default();
+        assert_eq!(
+            policy.printable(text.as_bytes()).as_deref(),
+            Some("red done\n")
+        );
+        let raw = PreviewPolicy {
+            strip_ansi: false,
+            ..PreviewPolicy::default()
+        };
+        assert_eq!(raw.printable(text.as_bytes()).as_deref(), Some(text));
+        assert_eq!(policy.printable(b"\0"), None);
+    }
+}
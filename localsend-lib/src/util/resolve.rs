@@ -0,0 +1,317 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use async_trait::async_trait;
+use localsend_proto::{dto::RegisterDto, ApiRoute, Device, ProtocolVersion, DEFAULT_PORT};
+use tokio::net::TcpStream;
+
+use crate::send::{SendError, PRECHECK_TIMEOUT};
+
+/// A device to send to by host name or address, e.g. `mylaptop.local:53317`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostTarget {
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for HostTarget {
+    type Err = String;
+
+    /// Accepts `host`, `host:port`, `[v6]:port` and bare IPv6 addresses, the port
+    /// defaults to [`DEFAULT_PORT`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            match rest.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => match port.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(format!("invalid host: {}", s)),
+                },
+                None => return Err(format!("invalid host: {}", s)),
+            }
+        } else if s.parse::<IpAddr>().is_ok() {
+            (s, None)
+        } else {
+            match s.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            }
+        };
+        if host.is_empty() {
+            return Err("expected a host name or address".to_owned());
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("invalid port: {}", port))?,
+            None => DEFAULT_PORT,
+        };
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for HostTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// Looks up the addresses of a host name.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// The resolver of the system, which also resolves `.local` names where mDNS is set
+/// up, e.g. with nss-mdns or Bonjour.
+#[derive(Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok(tokio::net::lookup_host((host, port)).await?.collect())
+    }
+}
+
+/// Asks an address for the device answering there.
+#[async_trait]
+pub trait DeviceProbe: Send + Sync {
+    async fn probe(&self, addr: SocketAddr) -> Option<Device>;
+}
+
+/// Connects within [`PRECHECK_TIMEOUT`], then asks the info route over HTTPS and
+/// HTTP, newest protocol version first.
+#[derive(Debug, Default)]
+pub struct InfoProbe;
+
+#[async_trait]
+impl DeviceProbe for InfoProbe {
+    async fn probe(&self, addr: SocketAddr) -> Option<Device> {
+        match tokio::time::timeout(PRECHECK_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => {}
+            result => {
+                log::debug!("{} is not reachable: {:?}", addr, result);
+                return None;
+            }
+        }
+        let ip = addr.ip().to_string();
+        for https in [true, false] {
+            for version in [ProtocolVersion::V2, ProtocolVersion::V1] {
+                let url = ApiRoute::Info
+                    .target_raw(&ip, addr.port(), https, version)
+                    .ok()?;
+                let response = crate::send::CLIENT
+                    .get(&url)
+                    .timeout(PRECHECK_TIMEOUT)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                let info = match response {
+                    Ok(response) => response.json::<RegisterDto>().await,
+                    Err(e) => Err(e),
+                };
+                match info {
+                    Ok(info) => {
+                        let mut device = info.to_device(&ip, addr.port(), https);
+                        device.port = addr.port();
+                        device.https = https;
+                        return Some(device);
+                    }
+                    Err(e) => log::debug!("No device info at {}: {}", url, e),
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Puts the addresses of the preferred family first, keeping the resolver's order
+/// otherwise and dropping duplicates.
+pub fn order_addresses(addrs: Vec<SocketAddr>, prefer_ipv6: bool) -> Vec<SocketAddr> {
+    let mut ordered: Vec<SocketAddr> = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if !ordered.contains(&addr) {
+            ordered.push(addr);
+        }
+    }
+    // stable, so equal families keep their order
+    ordered.sort_by_key(|addr| addr.is_ipv6() != prefer_ipv6);
+    ordered
+}
+
+/// Resolves `target` and returns the device of the first address answering the probe.
+pub async fn resolve_host(
+    target: &HostTarget,
+    resolver: &dyn Resolver,
+    probe: &dyn DeviceProbe,
+    prefer_ipv6: bool,
+) -> Result<Device, SendError> {
+    let addrs = resolver
+        .lookup(&target.host, target.port)
+        .await
+        .map_err(|e| SendError::HostNotFound {
+            host: target.host.clone(),
+            reason: e.to_string(),
+        })?;
+    let addrs = order_addresses(addrs, prefer_ipv6);
+    for addr in &addrs {
+        if let Some(device) = probe.probe(*addr).await {
+            log::debug!("{} answered at {}", target.host, addr);
+            return Ok(device);
+        }
+    }
+    Err(SendError::HostUnreachable {
+        host: target.host.clone(),
+        port: target.port,
+        tried: addrs.iter().map(SocketAddr::ip).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::SocketAddr, sync::Mutex};
+
+    use async_trait::async_trait;
+    use localsend_proto::{fixtures, Device};
+
+    use crate::send::SendError;
+
+    use super::{order_addresses, resolve_host, DeviceProbe, HostTarget, Resolver};
+
+    struct StubResolver(io::Result<Vec<SocketAddr>>);
+
+    #[async_trait]
+    impl Resolver for StubResolver {
+        async fn lookup(&self, _host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            match &self.0 {
+                Ok(addrs) => Ok(addrs
+                    .iter()
+                    .map(|addr| SocketAddr::new(addr.ip(), port))
+                    .collect()),
+                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            }
+        }
+    }
+
+    /// Answers at `answering` only and remembers what was probed.
+    #[derive(Default)]
+    struct StubProbe {
+        answering: Option<SocketAddr>,
+        probed: Mutex<Vec<SocketAddr>>,
+    }
+
+    #[async_trait]
+    impl DeviceProbe for StubProbe {
+        async fn probe(&self, addr: SocketAddr) -> Option<Device> {
+            self.probed.lock().unwrap().push(addr);
+            (Some(addr) == self.answering).then(|| Device {
+                ip: addr.ip().to_string(),
+                https: true,
+                fingerprint: "abc".to_owned(),
+                ..fixtures::device("laptop", addr.port())
+            })
+        }
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_host_target() {
+        let parse = |s: &str| s.parse::<HostTarget>();
+        assert_eq!(
+            parse("mylaptop.local:53318").unwrap(),
+            HostTarget {
+                host: "mylaptop.local".to_owned(),
+                port: 53318
+            }
+        );
+        assert_eq!(parse("nas").unwrap().port, 53317);
+        assert_eq!(parse("10.0.0.2:80").unwrap().host, "10.0.0.2");
+        assert_eq!(parse("fe80::1").unwrap().host, "fe80::1");
+        assert_eq!(parse("[fe80::1]:8080").unwrap().port, 8080);
+        assert_eq!(parse("[fe80::1]").unwrap().port, 53317);
+        assert!(parse("nas:http").is_err());
+        assert!(parse(":53317").is_err());
+        assert!(parse("[fe80::1").is_err());
+    }
+
+    #[test]
+    fn test_order_addresses() {
+        let addrs = vec![
+            addr("[fe80::1]:1"),
+            addr("10.0.0.2:1"),
+            addr("[fe80::2]:1"),
+            addr("10.0.0.3:1"),
+            addr("10.0.0.2:1"),
+        ];
+        assert_eq!(
+            order_addresses(addrs.clone(), false),
+            vec![
+                addr("10.0.0.2:1"),
+                addr("10.0.0.3:1"),
+                addr("[fe80::1]:1"),
+                addr("[fe80::2]:1"),
+            ]
+        );
+        assert_eq!(order_addresses(addrs, true)[0], addr("[fe80::1]:1"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_host() {
+        let target: HostTarget = "mylaptop.local".parse().unwrap();
+        let resolver = StubResolver(Ok(vec![
+            addr("[fe80::1]:0"),
+            addr("192.168.1.5:0"),
+            addr("192.168.1.6:0"),
+        ]));
+
+        // tried in order until one answers, IPv4 first
+        let probe = StubProbe {
+            answering: Some(addr("192.168.1.6:53317")),
+            ..Default::default()
+        };
+        let device = resolve_host(&target, &resolver, &probe, false)
+            .await
+            .unwrap();
+        assert_eq!(device.ip, "192.168.1.6");
+        assert_eq!(device.alias, "laptop");
+        assert_eq!(
+            *probe.probed.lock().unwrap(),
+            vec![addr("192.168.1.5:53317"), addr("192.168.1.6:53317")]
+        );
+
+        let error = resolve_host(&target, &resolver, &StubProbe::default(), true)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SendError::HostUnreachable { ref tried, .. } if tried.len() == 3));
+        assert_eq!(
+            error.to_string(),
+            "mylaptop.local resolved to 3 addresses, none reachable on port 53317 \
+             (tried fe80::1, 192.168.1.5, 192.168.1.6)"
+        );
+
+        let unknown = StubResolver(Err(io::Error::new(io::ErrorKind::Other, "no such host")));
+        let error = resolve_host(&target, &unknown, &StubProbe::default(), false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "mylaptop.local could not be resolved: no such host"
+        );
+        let none = StubResolver(Ok(vec![]));
+        let error = resolve_host(&target, &none, &StubProbe::default(), false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "mylaptop.local resolved to no addresses");
+    }
+}
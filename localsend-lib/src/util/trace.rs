@@ -0,0 +1,412 @@
+//! Tracing of the HTTP requests exchanged with other devices, for debugging interop with
+//! other implementations without a packet capture.
+//!
+//! Both sides format a message the same way: the request or status line, the headers that
+//! matter to the protocol and the body, with every line prefixed by `>` when this device
+//! sent it and `<` when it received it. JSON bodies are pretty-printed, anything else is
+//! summarized by its size.
+
+use std::{fmt, str::FromStr};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+
+/// JSON bodies larger than this are summarized like file bodies.
+const MAX_TRACED_BODY: usize = 64 * 1024;
+/// Headers shown in a trace, the others do not matter to the protocol.
+const TRACED_HEADERS: [&str; 3] = ["content-type", "content-length", "content-encoding"];
+/// Query parameters and JSON fields masked unless tracing with [`HttpTrace::Full`].
+const SENSITIVE: [&str; 3] = ["sessionId", "token", "pin"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpTrace {
+    /// Tokens, session ids and PINs are cut to their first characters
+    #[default]
+    Masked,
+    /// Everything as exchanged
+    Full,
+}
+
+impl fmt::Display for HttpTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HttpTrace::Masked => "masked",
+            HttpTrace::Full => "full",
+        })
+    }
+}
+
+impl FromStr for HttpTrace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "masked" => Ok(HttpTrace::Masked),
+            "full" => Ok(HttpTrace::Full),
+            _ => Err(format!(
+                "unknown trace mode: {}, expected masked or full",
+                s
+            )),
+        }
+    }
+}
+
+/// Which way a traced message went, seen from this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+}
+
+impl Direction {
+    pub fn marker(&self) -> char {
+        match self {
+            Direction::Outgoing => '>',
+            Direction::Incoming => '<',
+        }
+    }
+}
+
+type Sink = Box<dyn Fn(Direction, &str) + Send + Sync>;
+
+struct Tracer {
+    mode: HttpTrace,
+    sink: Sink,
+}
+
+static TRACER: OnceCell<Tracer> = OnceCell::new();
+
+/// Passes every traced message to `sink`, for the rest of the process.
+///
+/// Must be called before the server starts, returns false when tracing was already set up.
+pub fn set_http_trace(
+    mode: HttpTrace,
+    sink: impl Fn(Direction, &str) + Send + Sync + 'static,
+) -> bool {
+    let tracer = Tracer {
+        mode,
+        sink: Box::new(sink),
+    };
+    TRACER.set(tracer).is_ok()
+}
+
+pub(crate) fn is_tracing() -> bool {
+    TRACER.get().is_some()
+}
+
+/// Shows the first characters of a sensitive value, short ones are hidden entirely.
+pub fn mask(value: &str, mode: HttpTrace) -> String {
+    if mode == HttpTrace::Full {
+        return value.to_owned();
+    }
+    if value.chars().count() <= 8 {
+        return "***".to_owned();
+    }
+    let start: String = value.chars().take(4).collect();
+    format!("{}…", start)
+}
+
+/// The path or url with its query decoded, masking the sensitive parameters.
+pub fn mask_path(path_and_query: &str, mode: HttpTrace) -> String {
+    let Some((path, query)) = path_and_query.split_once('?') else {
+        return path_and_query.to_owned();
+    };
+    let query: Vec<String> = form_urlencoded::parse(query.as_bytes())
+        .map(|(key, value)| match SENSITIVE.contains(&key.as_ref()) {
+            true => format!("{}={}", key, mask(&value, mode)),
+            false => format!("{}={}", key, value),
+        })
+        .collect();
+    format!("{}?{}", path, query.join("&"))
+}
+
+fn mask_json(value: &mut Value, mode: HttpTrace) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(s) if SENSITIVE.contains(&key.as_str()) => {
+                        *s = mask(s, mode);
+                    }
+                    // the tokens of a prepare-upload response, by file id
+                    Value::Object(files) if key == "files" => {
+                        for token in files.values_mut() {
+                            match token {
+                                Value::String(s) => *s = mask(s, mode),
+                                value => mask_json(value, mode),
+                            }
+                        }
+                    }
+                    value => mask_json(value, mode),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| mask_json(value, mode)),
+        _ => {}
+    }
+}
+
+/// Pretty-prints a JSON body, `None` when it is not JSON.
+pub fn format_json(body: &[u8], mode: HttpTrace) -> Option<String> {
+    let mut value: Value = serde_json::from_slice(body).ok()?;
+    mask_json(&mut value, mode);
+    serde_json::to_string_pretty(&value).ok()
+}
+
+pub fn summarize_body(len: Option<u64>) -> String {
+    match len {
+        Some(len) => format!("« {} bytes »", len),
+        None => "« streamed body »".to_owned(),
+    }
+}
+
+fn is_json(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// The body worth showing, `body` is only given when it was buffered anyway.
+fn format_body(
+    content_type: Option<&str>,
+    len: Option<u64>,
+    body: Option<&[u8]>,
+    mode: HttpTrace,
+) -> Option<String> {
+    match body {
+        Some([]) => None,
+        Some(body) if is_json(content_type) => {
+            format_json(body, mode).or_else(|| Some(summarize_body(Some(body.len() as u64))))
+        }
+        Some(body) => Some(summarize_body(Some(body.len() as u64))),
+        None if len == Some(0) => None,
+        None => Some(summarize_body(len)),
+    }
+}
+
+/// One traced message, every line prefixed by the marker of `direction`.
+pub fn format_message(
+    direction: Direction,
+    head: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+) -> String {
+    let marker = direction.marker();
+    let mut lines = vec![format!("{} {}", marker, head)];
+    lines.extend(
+        headers
+            .iter()
+            .map(|(name, value)| format!("{} {}: {}", marker, name, value)),
+    );
+    if let Some(body) = body {
+        lines.push(marker.to_string());
+        lines.extend(body.lines().map(|line| format!("{} {}", marker, line)));
+    }
+    lines.join("\n")
+}
+
+fn traced_headers<'a>(get: impl Fn(&str) -> Option<&'a str>) -> Vec<(&'static str, &'a str)> {
+    TRACED_HEADERS
+        .iter()
+        .filter_map(|name| get(name).map(|value| (*name, value)))
+        .collect()
+}
+
+fn emit(
+    tracer: &Tracer,
+    direction: Direction,
+    head: &str,
+    headers: &[(&str, &str)],
+    body: Option<String>,
+) {
+    let message = format_message(direction, head, headers, body.as_deref());
+    (tracer.sink)(direction, &message);
+}
+
+fn content_length(value: Option<&str>) -> Option<u64> {
+    value.and_then(|value| value.parse().ok())
+}
+
+/// Sends a request like [`reqwest::RequestBuilder::send`], tracing it and its response.
+pub(crate) async fn send(builder: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    let Some(tracer) = TRACER.get() else {
+        return builder.send().await;
+    };
+    let (client, request) = builder.build_split();
+    let request = request?;
+
+    let head = format!(
+        "{} {}",
+        request.method(),
+        mask_path(request.url().as_str(), tracer.mode)
+    );
+    let headers = request.headers();
+    let traced = traced_headers(|name| headers.get(name).and_then(|v| v.to_str().ok()));
+    let content_type = headers.get("content-type").and_then(|v| v.to_str().ok());
+    let body = request.body().map(|body| body.as_bytes());
+    let body = match body {
+        None => None,
+        Some(bytes) => format_body(
+            content_type,
+            content_length(headers.get("content-length").and_then(|v| v.to_str().ok())),
+            bytes,
+            tracer.mode,
+        ),
+    };
+    emit(tracer, Direction::Outgoing, &head, &traced, body);
+
+    let response = client.execute(request).await?;
+    let status = response.status();
+    let head = format!("{:?} {}", response.version(), status);
+    let headers = response.headers().clone();
+    let traced = traced_headers(|name| headers.get(name).and_then(|v| v.to_str().ok()));
+    let content_type = headers.get("content-type").and_then(|v| v.to_str().ok());
+    let len = response.content_length();
+    if !is_json(content_type) || len.map_or(true, |len| len as usize > MAX_TRACED_BODY) {
+        emit(
+            tracer,
+            Direction::Incoming,
+            &head,
+            &traced,
+            format_body(content_type, len, None, tracer.mode),
+        );
+        return Ok(response);
+    }
+
+    // the body is read for the trace, the caller gets a response with the same body
+    let version = response.version();
+    let bytes = response.bytes().await?;
+    emit(
+        tracer,
+        Direction::Incoming,
+        &head,
+        &traced,
+        format_body(content_type, len, Some(&bytes), tracer.mode),
+    );
+    let mut rebuilt = http::Response::new(bytes);
+    *rebuilt.status_mut() = status;
+    *rebuilt.version_mut() = version;
+    *rebuilt.headers_mut() = headers;
+    Ok(rebuilt.into())
+}
+
+/// Server middleware tracing the requests of other devices and the responses to them.
+pub(crate) async fn trace_requests(request: Request, next: Next) -> Response {
+    let Some(tracer) = TRACER.get() else {
+        return next.run(request).await;
+    };
+    let head = format!(
+        "{} {}",
+        request.method(),
+        request.uri().path_and_query().map_or_else(
+            || request.uri().path().to_owned(),
+            |p| mask_path(p.as_str(), tracer.mode)
+        )
+    );
+    let (parts, body) = request.into_parts();
+    let (request, traced_body) = buffer_json(&parts.headers, body, tracer.mode).await;
+    let traced = traced_headers(|name| parts.headers.get(name).and_then(|v| v.to_str().ok()));
+    emit(tracer, Direction::Incoming, &head, &traced, traced_body);
+    let request = Request::from_parts(parts, request);
+
+    let response = next.run(request).await;
+    let head = format!("{:?} {}", response.version(), response.status());
+    let (parts, body) = response.into_parts();
+    let (body, traced_body) = buffer_json(&parts.headers, body, tracer.mode).await;
+    let traced = traced_headers(|name| parts.headers.get(name).and_then(|v| v.to_str().ok()));
+    emit(tracer, Direction::Outgoing, &head, &traced, traced_body);
+    Response::from_parts(parts, body)
+}
+
+/// Reads small JSON bodies for the trace, others are passed on untouched and summarized.
+async fn buffer_json(
+    headers: &axum::http::HeaderMap,
+    body: Body,
+    mode: HttpTrace,
+) -> (Body, Option<String>) {
+    let content_type = headers.get("content-type").and_then(|v| v.to_str().ok());
+    let len = content_length(headers.get("content-length").and_then(|v| v.to_str().ok()));
+    if !is_json(content_type) || len.map_or(true, |len| len as usize > MAX_TRACED_BODY) {
+        return (body, format_body(content_type, len, None, mode));
+    }
+    match to_bytes(body, MAX_TRACED_BODY).await {
+        Ok(bytes) => {
+            let traced = format_body(content_type, len, Some(&bytes), mode);
+            (Body::from(bytes), traced)
+        }
+        Err(e) => (Body::empty(), Some(format!("« unreadable body: {} »", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_body, format_json, format_message, mask, mask_path, Direction, HttpTrace};
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("2f1c9a3e-5b1d-4c59", HttpTrace::Masked), "2f1c…");
+        assert_eq!(mask("123456", HttpTrace::Masked), "***");
+        assert_eq!(mask("123456", HttpTrace::Full), "123456");
+
+        assert_eq!(
+            mask_path(
+                "/api/localsend/v2/upload?sessionId=2f1c9a3e-5b1d&fileId=a%2Fb&token=0123456789",
+                HttpTrace::Masked
+            ),
+            "/api/localsend/v2/upload?sessionId=2f1c…&fileId=a/b&token=0123…"
+        );
+        assert_eq!(
+            mask_path("/api/localsend/v2/prepare-upload?pin=1234", HttpTrace::Full),
+            "/api/localsend/v2/prepare-upload?pin=1234"
+        );
+        assert_eq!(mask_path("/", HttpTrace::Masked), "/");
+    }
+
+    #[test]
+    fn test_format_message() {
+        let body = format_json(
+            br#"{"sessionId":"2f1c9a3e-5b1d","files":{"a":"0123456789"},"alias":"nas"}"#,
+            HttpTrace::Masked,
+        )
+        .unwrap();
+        let message = format_message(
+            Direction::Incoming,
+            "HTTP/1.1 200 OK",
+            &[("content-type", "application/json")],
+            Some(&body),
+        );
+        assert_eq!(
+            message,
+            [
+                "< HTTP/1.1 200 OK",
+                "< content-type: application/json",
+                "<",
+                "< {",
+                "<   \"alias\": \"nas\",",
+                "<   \"files\": {",
+                "<     \"a\": \"0123…\"",
+                "<   },",
+                "<   \"sessionId\": \"2f1c…\"",
+                "< }",
+            ]
+            .join("\n")
+        );
+
+        let json = Some("application/json");
+        let octets = Some("application/octet-stream");
+        assert_eq!(
+            format_body(octets, Some(104857600), None, HttpTrace::Masked).as_deref(),
+            Some("« 104857600 bytes »")
+        );
+        assert_eq!(
+            format_body(json, Some(5), Some(b"{]"), HttpTrace::Masked).as_deref(),
+            Some("« 2 bytes »")
+        );
+        assert_eq!(format_body(json, Some(0), None, HttpTrace::Masked), None);
+        assert_eq!(format_body(None, None, Some(b""), HttpTrace::Masked), None);
+    }
+}
@@ -1,8 +1,8 @@
-pub const PROTOCOL_VERSION_2: &'static str = "2.0";
-pub const PROTOCOL_VERSION_1: &'static str = "1.0";
-pub const FALLBACK_PROTOCOL_VERSION: &'static str = PROTOCOL_VERSION_1;
+pub const PROTOCOL_VERSION_2: &str = "2.0";
+pub const PROTOCOL_VERSION_1: &str = "1.0";
+pub const FALLBACK_PROTOCOL_VERSION: &str = PROTOCOL_VERSION_1;
 
-pub const DEFAULT_MULTICAST: &'static str = "224.0.0.167";
+pub const DEFAULT_MULTICAST: &str = "224.0.0.167";
 
 pub const DEFAULT_PORT: u16 = 53317;
 pub const DEFAULT_HTTP_PORT: u16 = DEFAULT_PORT + 1;
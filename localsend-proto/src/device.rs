@@ -1,13 +1,19 @@
+use std::{fmt, str::FromStr};
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
+use crate::{ProtocolVersion, RouteError};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
 pub enum DeviceType {
     Mobile,
     Desktop,
     Web,
     Headless,
     Server,
+    /// Sent by other clients, e.g. `cli` or `tv`, kept as is to be sent back
+    Unknown(String),
 }
 
 impl Default for DeviceType {
@@ -16,6 +22,69 @@ impl Default for DeviceType {
     }
 }
 
+impl DeviceType {
+    pub const ALL: [DeviceType; 5] = [
+        DeviceType::Mobile,
+        DeviceType::Desktop,
+        DeviceType::Web,
+        DeviceType::Headless,
+        DeviceType::Server,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            DeviceType::Mobile => "mobile",
+            DeviceType::Desktop => "desktop",
+            DeviceType::Web => "web",
+            DeviceType::Headless => "headless",
+            DeviceType::Server => "server",
+            DeviceType::Unknown(name) => name,
+        }
+    }
+}
+
+impl From<String> for DeviceType {
+    fn from(name: String) -> Self {
+        DeviceType::ALL
+            .into_iter()
+            .find(|t| t.name() == name)
+            .unwrap_or(DeviceType::Unknown(name))
+    }
+}
+
+impl From<DeviceType> for String {
+    fn from(device_type: DeviceType) -> Self {
+        match device_type {
+            DeviceType::Unknown(name) => name,
+            known => known.name().to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DeviceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeviceType::ALL
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = DeviceType::ALL.iter().map(|t| t.name()).collect();
+                format!(
+                    "unknown device type: {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Device {
     pub ip: String,
@@ -28,3 +97,46 @@ pub struct Device {
     pub device_type: DeviceType,
     pub download: bool,
 }
+
+impl Device {
+    pub fn protocol_version(&self) -> Result<ProtocolVersion, RouteError> {
+        self.version.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeviceType;
+
+    #[test]
+    fn test_device_type_from_str() {
+        assert_eq!("server".parse::<DeviceType>(), Ok(DeviceType::Server));
+        assert_eq!("Desktop".parse::<DeviceType>(), Ok(DeviceType::Desktop));
+        // only known types are announced by us
+        assert!("laptop".parse::<DeviceType>().is_err());
+        assert!("".parse::<DeviceType>().is_err());
+    }
+
+    #[test]
+    fn test_device_type_serde() {
+        for device_type in DeviceType::ALL {
+            let json = serde_json::to_string(&device_type).unwrap();
+            assert_eq!(json, format!("\"{}\"", device_type));
+            let parsed: DeviceType = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, device_type);
+            assert_eq!(
+                device_type.to_string().parse::<DeviceType>(),
+                Ok(device_type)
+            );
+        }
+
+        let unknown: DeviceType = serde_json::from_str("\"tv\"").unwrap();
+        assert_eq!(unknown, DeviceType::Unknown("tv".to_owned()));
+        assert_eq!(serde_json::to_string(&unknown).unwrap(), "\"tv\"");
+        // names are matched exactly, like the official apps send them
+        assert_eq!(
+            serde_json::from_str::<DeviceType>("\"Mobile\"").unwrap(),
+            DeviceType::Unknown("Mobile".to_owned())
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::str::FromStr;
 use mime_guess::Mime;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum FileType {
     Image,
@@ -11,15 +11,10 @@ pub enum FileType {
     Pdf,
     Text,
     Apk,
+    #[default]
     Other,
 }
 
-impl Default for FileType {
-    fn default() -> Self {
-        FileType::Other
-    }
-}
-
 impl From<Mime> for FileType {
     fn from(mime: Mime) -> Self {
         use mime_guess::mime::*;
@@ -35,25 +30,54 @@ impl From<Mime> for FileType {
     }
 }
 
+impl FileType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::Image => "image",
+            FileType::Video => "video",
+            FileType::Pdf => "pdf",
+            FileType::Text => "text",
+            FileType::Apk => "apk",
+            FileType::Other => "other",
+        }
+    }
+}
+
+/// Accepts the names we and v1 apps send as well as the mime types of v2 apps.
 impl<'de> Deserialize<'de> for FileType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let file_type = mime_guess::Mime::from_str(&String::deserialize(deserializer)?)
-            .map(Self::from)
-            .unwrap_or_default();
+        let name = String::deserialize(deserializer)?;
+        let by_name = [
+            FileType::Image,
+            FileType::Video,
+            FileType::Pdf,
+            FileType::Text,
+            FileType::Apk,
+            FileType::Other,
+        ]
+        .into_iter()
+        .find(|file_type| file_type.name() == name);
+        let file_type = by_name.unwrap_or_else(|| {
+            mime_guess::Mime::from_str(&name)
+                .map(Self::from)
+                .unwrap_or_default()
+        });
         Ok(file_type)
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FileDto {
     pub id: String, // unique inside session
     pub file_name: String,
     pub size: u64,
     pub file_type: FileType,
+    /// Hex digest of the content, sha256 since v2.1, md5 from older peers
+    #[serde(rename = "sha256", alias = "hash")]
     pub hash: Option<String>,
     pub preview: Option<String>,
 }
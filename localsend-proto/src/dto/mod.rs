@@ -1,11 +1,15 @@
 mod file_dto;
 mod multicast_dto;
+mod prepare_download_dto;
 mod prepare_upload_dto;
 mod protocol_type;
 mod register_dto;
+mod upload_dto;
 
 pub use file_dto::*;
 pub use multicast_dto::*;
+pub use prepare_download_dto::*;
 pub use prepare_upload_dto::*;
 pub use protocol_type::*;
 pub use register_dto::*;
+pub use upload_dto::*;
@@ -4,7 +4,7 @@ use crate::{Device, DeviceType, FALLBACK_PROTOCOL_VERSION, PROTOCOL_VERSION_2};
 
 use super::ProtocolType;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MulticastDto {
     pub alias: String,
@@ -41,13 +41,15 @@ impl MulticastDto {
         }
     }
 
+    /// An announcement when `announce` is set, asking others to answer, otherwise
+    /// the answer to one, see [`Self::into_response`].
     pub fn v2(
         alias: impl ToString,
         device_model: Option<String>,
         device_type: DeviceType,
         fingerprint: impl ToString,
         port: u16,
-        announcement: bool,
+        announce: bool,
     ) -> Self {
         Self {
             alias: alias.to_string(),
@@ -58,11 +60,26 @@ impl MulticastDto {
             port: Some(port),
             protocol: Some(ProtocolType::Http),
             download: None,
-            announcement: Some(announcement),
-            announce: None,
+            announcement: Some(announce),
+            announce: Some(announce),
+        }
+    }
+
+    /// The same device answering an announcement, with both the v1 and the v2 flag cleared.
+    pub fn into_response(self) -> Self {
+        Self {
+            announcement: self.announcement.map(|_| false),
+            announce: self.announce.map(|_| false),
+            ..self
         }
     }
 
+    /// Whether the sender asks for an answer, v2 peers set `announce`, v1 peers
+    /// only `announcement`.
+    pub fn is_announce(&self) -> bool {
+        self.announce.or(self.announcement).unwrap_or(false)
+    }
+
     pub fn to_device(self, ip: impl ToString, own_port: u16, own_https: bool) -> Device {
         Device {
             ip: ip.to_string(),
@@ -84,7 +101,7 @@ impl MulticastDto {
 #[cfg(test)]
 mod tests {
 
-    use crate::DeviceType;
+    use crate::{dto::RegisterDto, DeviceType};
 
     use super::MulticastDto;
 
@@ -103,4 +120,79 @@ mod tests {
         assert_eq!(dto.alias, new_dto.alias);
         assert_eq!(dto.fingerprint, new_dto.fingerprint);
     }
+
+    #[test]
+    fn test_v2_announce_and_response() {
+        let dto = MulticastDto::v2(
+            "Nice Orange",
+            Some("Samsung".to_owned()),
+            DeviceType::Mobile,
+            "random string",
+            53317,
+            true,
+        );
+        assert!(dto.is_announce());
+        assert_eq!(
+            serde_json::to_string(&dto).unwrap(),
+            r#"{"alias":"Nice Orange","version":"2.0","deviceModel":"Samsung","deviceType":"mobile","fingerprint":"random string","port":53317,"protocol":"http","download":null,"announcement":true,"announce":true}"#
+        );
+        let response = dto.into_response();
+        assert!(!response.is_announce());
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"alias":"Nice Orange","version":"2.0","deviceModel":"Samsung","deviceType":"mobile","fingerprint":"random string","port":53317,"protocol":"http","download":null,"announcement":false,"announce":false}"#
+        );
+        // a v1 response stays without the v2 flag
+        let v1 = MulticastDto::v1("a", None, DeviceType::Desktop, "f", true).into_response();
+        assert_eq!((v1.announcement, v1.announce), (Some(false), None));
+    }
+
+    #[test]
+    fn test_official_packets() {
+        let parse = |json: &str| serde_json::from_str::<MulticastDto>(json).unwrap();
+        // as the official app sends them periodically and when answering
+        let announce = parse(
+            r#"{"alias":"Nice Orange","version":"2.0","deviceModel":"Pixel 7","deviceType":"mobile","fingerprint":"a1b2c3","port":53317,"protocol":"https","download":false,"announcement":true,"announce":true}"#,
+        );
+        assert!(announce.is_announce());
+        let response = parse(
+            r#"{"alias":"Nice Orange","version":"2.0","deviceModel":"Pixel 7","deviceType":"mobile","fingerprint":"a1b2c3","port":53317,"protocol":"https","download":false,"announcement":false,"announce":false}"#,
+        );
+        assert!(!response.is_announce());
+        // v1 apps only know the legacy flag, v2 wins when both are there
+        let v1 = parse(
+            r#"{"alias":"Old","deviceModel":null,"deviceType":"desktop","fingerprint":"d4e5f6","announcement":true}"#,
+        );
+        assert!(v1.is_announce());
+        let strict = parse(r#"{"alias":"Strict","fingerprint":"f","announce":false}"#);
+        assert!(!strict.is_announce());
+        let mixed =
+            parse(r#"{"alias":"Mixed","fingerprint":"f","announcement":true,"announce":false}"#);
+        assert!(!mixed.is_announce());
+        assert!(!parse(r#"{"alias":"Silent","fingerprint":"f"}"#).is_announce());
+    }
+
+    #[test]
+    fn test_nullable_device_type() {
+        let to_device = |device_type: &str| {
+            let json = format!(
+                r#"{{"alias":"tv","version":"2.0","fingerprint":"f","port":53317,"protocol":"http","announce":true{}}}"#,
+                device_type
+            );
+            let dto: MulticastDto = serde_json::from_str(&json).unwrap();
+            dto.to_device("192.168.1.20", 53317, false)
+        };
+        let null = to_device(r#","deviceType":null"#);
+        assert_eq!(null.device_type, DeviceType::Desktop);
+        assert!(!null.download);
+        assert_eq!(to_device("").device_type, DeviceType::Desktop);
+        assert_eq!(
+            to_device(r#","deviceType":"server""#).device_type,
+            DeviceType::Server
+        );
+        let tv = to_device(r#","deviceType":"tv""#);
+        assert_eq!(tv.device_type, DeviceType::Unknown("tv".to_owned()));
+        let echoed = serde_json::to_string(&RegisterDto::from(tv)).unwrap();
+        assert!(echoed.contains(r#""deviceType":"tv""#), "{}", echoed);
+    }
 }
@@ -0,0 +1,14 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{FileDto, RegisterDto};
+
+/// v2
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrepareDownloadResponseDto {
+    pub info: RegisterDto,
+    pub session_id: String,
+    pub files: HashMap<String, FileDto>,
+}
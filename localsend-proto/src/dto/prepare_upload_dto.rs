@@ -4,17 +4,47 @@ use serde::{Deserialize, Serialize};
 
 use super::{FileDto, RegisterDto};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PrepareUploadRequestDto {
     pub info: RegisterDto,
     pub files: HashMap<String, FileDto>,
+    #[serde(
+        rename = "x-localsend-rs",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extension: Option<ExtensionDto>,
+}
+
+/// Vendor extension understood by localsend-rs, ignored by the official apps.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionDto {
+    /// Supported upload content encodings, most preferred first
+    #[serde(default)]
+    pub compress: Vec<String>,
 }
 
 /// v2
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PrepareUploadResponseDto {
     pub session_id: String,
     pub files: HashMap<String, String>,
+    /// Why the receiver's filters left files without a token, by file id. Sent by
+    /// localsend-rs receivers only, the official apps ignore it
+    #[serde(
+        rename = "x-filtered",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub filtered: HashMap<String, String>,
+    /// Names the receiver expects to save files under where they differ from the
+    /// offered ones, by file id. Sent by localsend-rs receivers only like `x-filtered`
+    #[serde(
+        rename = "x-final-names",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub final_names: HashMap<String, String>,
 }
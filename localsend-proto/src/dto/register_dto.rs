@@ -4,7 +4,7 @@ use crate::{Device, DeviceType, FALLBACK_PROTOCOL_VERSION};
 
 use super::ProtocolType;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RegisterDto {
     pub alias: String,
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// Body of a successful upload sent by localsend-rs receivers, the official apps
+/// answer with an empty body.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadResponseDto {
+    /// Bytes saved, after decompression
+    pub bytes: u64,
+    /// The digest the file was verified against, if the sender announced one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Name the file was saved under relative to the destination, if it differs from
+    /// the offered one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_name: Option<String>,
+}
@@ -0,0 +1,182 @@
+//! Canonical JSON of the protocol messages, as localsend-rs emits and accepts them.
+//!
+//! The files live in `testdata/` for implementations in other languages. Every fixture
+//! except those in [`ACCEPTED`] is exactly what our serde types produce, a change of the
+//! serialization breaks the conformance tests below. Enable the `fixtures` feature to
+//! reuse them in other crates' tests.
+
+use crate::{Device, DeviceType, PROTOCOL_VERSION_2};
+
+/// Our v1 multicast announcement
+pub const MULTICAST_V1: &str = include_str!("../testdata/multicast_v1.json");
+/// Our v2 multicast announcement, answers clear `announcement` and `announce`
+pub const MULTICAST_V2: &str = include_str!("../testdata/multicast_v2.json");
+/// The device info of register requests and responses
+pub const REGISTER: &str = include_str!("../testdata/register.json");
+/// A prepare-upload request with the localsend-rs extension
+pub const PREPARE_UPLOAD_REQUEST: &str = include_str!("../testdata/prepare_upload_request.json");
+/// A prepare-upload request like the official app sends it, with mime types and metadata
+pub const PREPARE_UPLOAD_REQUEST_OFFICIAL: &str =
+    include_str!("../testdata/prepare_upload_request_official.json");
+/// A prepare-upload response of a localsend-rs receiver whose filters skipped a file
+/// and that renames another one
+pub const PREPARE_UPLOAD_RESPONSE: &str = include_str!("../testdata/prepare_upload_response.json");
+pub const PREPARE_DOWNLOAD_RESPONSE: &str =
+    include_str!("../testdata/prepare_download_response.json");
+/// The body of a successful upload to a localsend-rs receiver
+pub const UPLOAD_RESPONSE: &str = include_str!("../testdata/upload_response.json");
+/// The body of an error response of a localsend-rs receiver
+pub const ERROR: &str = include_str!("../testdata/error.json");
+
+/// Every fixture by file name.
+pub const ALL: [(&str, &str); 9] = [
+    ("multicast_v1.json", MULTICAST_V1),
+    ("multicast_v2.json", MULTICAST_V2),
+    ("register.json", REGISTER),
+    ("prepare_upload_request.json", PREPARE_UPLOAD_REQUEST),
+    (
+        "prepare_upload_request_official.json",
+        PREPARE_UPLOAD_REQUEST_OFFICIAL,
+    ),
+    ("prepare_upload_response.json", PREPARE_UPLOAD_RESPONSE),
+    ("prepare_download_response.json", PREPARE_DOWNLOAD_RESPONSE),
+    ("upload_response.json", UPLOAD_RESPONSE),
+    ("error.json", ERROR),
+];
+
+/// Fixtures we parse but do not emit the same way, e.g. mime types are sent as names.
+pub const ACCEPTED: [&str; 1] = ["prepare_upload_request_official.json"];
+
+/// A v2 device on 127.0.0.1 whose fingerprint is its alias, over plain HTTP.
+pub fn device(alias: &str, port: u16) -> Device {
+    Device {
+        ip: "127.0.0.1".to_owned(),
+        version: PROTOCOL_VERSION_2.to_owned(),
+        port,
+        https: false,
+        fingerprint: alias.to_owned(),
+        alias: alias.to_owned(),
+        device_model: None,
+        device_type: DeviceType::Headless,
+        download: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::Value;
+
+    use crate::{
+        dto::{
+            ExtensionDto, FileType, MulticastDto, PrepareDownloadResponseDto,
+            PrepareUploadRequestDto, PrepareUploadResponseDto, ProtocolType, RegisterDto,
+            UploadResponseDto,
+        },
+        DeviceType,
+    };
+
+    use super::*;
+
+    /// Parses `json` and serializes it again, failing on any difference.
+    fn round_trip<T: DeserializeOwned + Serialize>(json: &str) -> T {
+        let dto: T = serde_json::from_str(json).unwrap();
+        let expected: Value = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_value(&dto).unwrap(), expected);
+        dto
+    }
+
+    #[test]
+    fn test_fixtures_are_json() {
+        for (name, json) in ALL {
+            assert!(serde_json::from_str::<Value>(json).is_ok(), "{}", name);
+        }
+        assert!(ACCEPTED
+            .iter()
+            .all(|name| ALL.iter().any(|(n, _)| n == name)));
+    }
+
+    #[test]
+    fn test_multicast() {
+        let v1: MulticastDto = round_trip(MULTICAST_V1);
+        assert_eq!(
+            v1,
+            MulticastDto::v1(
+                "Nice Orange",
+                Some("Samsung".to_owned()),
+                DeviceType::Mobile,
+                "2f1c9a3e-5b1d-4c59-9a8e-0c1d2e3f4a5b",
+                true
+            )
+        );
+        let v2: MulticastDto = round_trip(MULTICAST_V2);
+        assert_eq!(
+            v2,
+            MulticastDto::v2(
+                "Nice Orange",
+                Some("Samsung".to_owned()),
+                DeviceType::Mobile,
+                "2f1c9a3e-5b1d-4c59-9a8e-0c1d2e3f4a5b",
+                53317,
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn test_register() {
+        let register: RegisterDto = round_trip(REGISTER);
+        assert_eq!(register.protocol, Some(ProtocolType::Https));
+        assert_eq!(register.device_type, Some(DeviceType::Headless));
+        let device = register.clone().to_device("192.168.1.2", 53317, false);
+        assert_eq!(RegisterDto::from(device), register);
+    }
+
+    #[test]
+    fn test_prepare_upload() {
+        let request: PrepareUploadRequestDto = round_trip(PREPARE_UPLOAD_REQUEST);
+        assert_eq!(request.files["a1"].file_type, FileType::Image);
+        assert_eq!(request.files["b2"].file_type, FileType::Text);
+        assert_eq!(request.files["b2"].preview.as_deref(), Some("hello world"));
+        assert_eq!(
+            request.extension,
+            Some(ExtensionDto {
+                compress: vec!["zstd".to_owned(), "gzip".to_owned()]
+            })
+        );
+
+        let official: PrepareUploadRequestDto =
+            serde_json::from_str(PREPARE_UPLOAD_REQUEST_OFFICIAL).unwrap();
+        let file = &official.files["some-file-id"];
+        assert_eq!(file.file_type, FileType::Image);
+        assert_eq!(file.hash.as_deref(), Some("*sha256 hash*"));
+        assert_eq!(official.extension, None);
+
+        let response: PrepareUploadResponseDto = round_trip(PREPARE_UPLOAD_RESPONSE);
+        assert_eq!(
+            response,
+            PrepareUploadResponseDto {
+                session_id: "mySessionId".to_owned(),
+                files: HashMap::from([("a1".to_owned(), "fileToken".to_owned())]),
+                filtered: HashMap::from([(
+                    "b2".to_owned(),
+                    "Exceeds the limit of 1 files per session".to_owned()
+                )]),
+                final_names: HashMap::from([("a1".to_owned(), "photo (2).jpg".to_owned())]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_download_and_upload() {
+        let download: PrepareDownloadResponseDto = round_trip(PREPARE_DOWNLOAD_RESPONSE);
+        assert_eq!(download.files["a1"].file_type, FileType::Pdf);
+        assert_eq!(download.info.download, Some(true));
+
+        let upload: UploadResponseDto = round_trip(UPLOAD_RESPONSE);
+        assert_eq!(upload.bytes, 324734);
+        assert_eq!(upload.final_name.as_deref(), Some("photo (2).jpg"));
+    }
+}
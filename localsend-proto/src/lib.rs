@@ -1,8 +1,14 @@
 mod constants;
 mod device;
 mod route;
+mod validate;
+mod version;
 
 pub mod dto;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
 pub use constants::*;
 pub use device::*;
 pub use route::*;
+pub use validate::*;
+pub use version::*;
@@ -1,58 +1,240 @@
-use crate::{Device, PROTOCOL_VERSION_1, PROTOCOL_VERSION_2};
+use std::{fmt, net::Ipv6Addr};
 
+use crate::{Device, ProtocolVersion};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ApiRoute {
+    Info,
+    Register,
     PrepareUpload,
     Upload,
     Cancel,
+    PrepareDownload,
+    Download,
+}
+
+/// Why no url can be built for a route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// Not a `major.minor` version
+    InvalidVersion(String),
+    /// A major version whose routes are unknown
+    UnsupportedVersion(ProtocolVersion),
+    /// The route is not part of the version
+    Unavailable(ApiRoute, ProtocolVersion),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidVersion(version) => {
+                write!(f, "{:?} is not a major.minor protocol version", version)
+            }
+            RouteError::UnsupportedVersion(version) => {
+                write!(f, "Protocol version {} is not supported", version)
+            }
+            RouteError::Unavailable(route, version) => {
+                write!(f, "{:?} is not part of protocol version {}", route, version)
+            }
+        }
+    }
 }
 
+impl std::error::Error for RouteError {}
+
 impl ApiRoute {
+    pub const ALL: [ApiRoute; 7] = [
+        ApiRoute::Info,
+        ApiRoute::Register,
+        ApiRoute::PrepareUpload,
+        ApiRoute::Upload,
+        ApiRoute::Cancel,
+        ApiRoute::PrepareDownload,
+        ApiRoute::Download,
+    ];
+
+    /// The path in protocol v1.
+    ///
+    /// # Panics
+    ///
+    /// If the route was only added in v2.
     pub fn v1(&self) -> String {
-        self.route(PROTOCOL_VERSION_1)
+        self.route(ProtocolVersion::V1).expect("Not a v1 route")
     }
 
     pub fn v2(&self) -> String {
-        self.route(PROTOCOL_VERSION_2)
+        self.route(ProtocolVersion::V2).expect("Not a v2 route")
     }
 
-    fn _v1(&self) -> &'static str {
+    fn _v1(&self) -> Option<&'static str> {
         match self {
-            ApiRoute::PrepareUpload => "send-request",
-            ApiRoute::Upload => "send",
-            ApiRoute::Cancel => "cancel",
+            ApiRoute::Info => Some("info"),
+            ApiRoute::Register => Some("register"),
+            ApiRoute::PrepareUpload => Some("send-request"),
+            ApiRoute::Upload => Some("send"),
+            ApiRoute::Cancel => Some("cancel"),
+            // reverse transfers came with v2
+            ApiRoute::PrepareDownload | ApiRoute::Download => None,
         }
     }
 
     fn _v2(&self) -> &'static str {
         match self {
+            ApiRoute::Info => "info",
+            ApiRoute::Register => "register",
             ApiRoute::PrepareUpload => "prepare-upload",
             ApiRoute::Upload => "upload",
-            _ => self._v1(),
+            ApiRoute::Cancel => "cancel",
+            ApiRoute::PrepareDownload => "prepare-download",
+            ApiRoute::Download => "download",
         }
     }
 
-    fn route(&self, version: impl AsRef<str>) -> String {
-        let path = if version.as_ref() == PROTOCOL_VERSION_1 {
-            format!("/v1/{}", self._v1())
-        } else {
-            format!("/v2/{}", self._v2())
+    /// The path of the route in the major version of `version`.
+    pub fn route(&self, version: ProtocolVersion) -> Result<String, RouteError> {
+        let name = match version.major {
+            1 => self._v1().ok_or(RouteError::Unavailable(*self, version))?,
+            2 => self._v2(),
+            _ => return Err(RouteError::UnsupportedVersion(version)),
         };
-        format!("/api/localsend{}", path)
+        Ok(format!("/api/localsend/v{}/{}", version.major, name))
     }
 
-    pub fn target(&self, device: &Device) -> String {
-        self.target_raw(&device.ip, device.port, device.https, &device.version)
+    pub fn target(&self, device: &Device) -> Result<String, RouteError> {
+        let version = device.protocol_version()?;
+        self.target_raw(&device.ip, device.port, device.https, version)
     }
 
+    /// The url of the route, IPv6 hosts are put in brackets.
     pub fn target_raw(
         &self,
         ip: impl AsRef<str>,
         port: u16,
         https: bool,
-        version: impl AsRef<str>,
-    ) -> String {
+        version: ProtocolVersion,
+    ) -> Result<String, RouteError> {
         let protocol = if https { "https" } else { "http" };
-        let route = self.route(&version);
-        format!("{}://{}:{}{}", protocol, ip.as_ref(), port, route)
+        let route = self.route(version)?;
+        let ip = ip.as_ref();
+        // the zone of a link-local address is not part of the address itself
+        let address = ip.split_once('%').map_or(ip, |(address, _)| address);
+        if address.parse::<Ipv6Addr>().is_ok() {
+            Ok(format!("{}://[{}]:{}{}", protocol, ip, port, route))
+        } else {
+            Ok(format!("{}://{}:{}{}", protocol, ip, port, route))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Device, DeviceType, ProtocolVersion, RouteError, PROTOCOL_VERSION_2};
+
+    use super::ApiRoute;
+
+    #[test]
+    fn test_routes() {
+        let v1 = ProtocolVersion::V1;
+        let v2 = ProtocolVersion::V2;
+        let cases = [
+            (ApiRoute::Info, v1, Some("/api/localsend/v1/info")),
+            (ApiRoute::Register, v1, Some("/api/localsend/v1/register")),
+            (
+                ApiRoute::PrepareUpload,
+                v1,
+                Some("/api/localsend/v1/send-request"),
+            ),
+            (ApiRoute::Upload, v1, Some("/api/localsend/v1/send")),
+            (ApiRoute::Cancel, v1, Some("/api/localsend/v1/cancel")),
+            (ApiRoute::PrepareDownload, v1, None),
+            (ApiRoute::Download, v1, None),
+            (ApiRoute::Info, v2, Some("/api/localsend/v2/info")),
+            (ApiRoute::Register, v2, Some("/api/localsend/v2/register")),
+            (
+                ApiRoute::PrepareUpload,
+                v2,
+                Some("/api/localsend/v2/prepare-upload"),
+            ),
+            (ApiRoute::Upload, v2, Some("/api/localsend/v2/upload")),
+            (ApiRoute::Cancel, v2, Some("/api/localsend/v2/cancel")),
+            (
+                ApiRoute::PrepareDownload,
+                v2,
+                Some("/api/localsend/v2/prepare-download"),
+            ),
+            (ApiRoute::Download, v2, Some("/api/localsend/v2/download")),
+        ];
+        assert_eq!(cases.len(), ApiRoute::ALL.len() * 2);
+        for (route, version, path) in cases {
+            let expected = path
+                .map(str::to_owned)
+                .ok_or(RouteError::Unavailable(route, version));
+            assert_eq!(route.route(version), expected, "{:?} {}", route, version);
+            // minor versions share the routes of their major one
+            let minor = ProtocolVersion::new(version.major, 1);
+            assert_eq!(route.route(minor).ok(), path.map(str::to_owned));
+        }
+        assert_eq!(ApiRoute::Upload.v1(), "/api/localsend/v1/send");
+        assert_eq!(ApiRoute::Upload.v2(), "/api/localsend/v2/upload");
+
+        for route in ApiRoute::ALL {
+            for version in [ProtocolVersion::new(0, 9), ProtocolVersion::new(3, 0)] {
+                assert_eq!(
+                    route.route(version),
+                    Err(RouteError::UnsupportedVersion(version))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_targets() {
+        let url = |ip: &str, https: bool| {
+            ApiRoute::Upload
+                .target_raw(ip, 53317, https, ProtocolVersion::V2)
+                .unwrap()
+        };
+        assert_eq!(
+            url("192.168.1.2", false),
+            "http://192.168.1.2:53317/api/localsend/v2/upload"
+        );
+        assert_eq!(
+            url("fd00::2", true),
+            "https://[fd00::2]:53317/api/localsend/v2/upload"
+        );
+        assert_eq!(
+            url("fe80::1%eth0", false),
+            "http://[fe80::1%eth0]:53317/api/localsend/v2/upload"
+        );
+        assert_eq!(
+            url("nas.local", false),
+            "http://nas.local:53317/api/localsend/v2/upload"
+        );
+
+        let mut device = Device {
+            alias: "alias".to_owned(),
+            version: PROTOCOL_VERSION_2.to_owned(),
+            device_model: None,
+            device_type: DeviceType::Desktop,
+            fingerprint: "fingerprint".to_owned(),
+            port: 53317,
+            https: false,
+            download: false,
+            ip: "::1".to_owned(),
+        };
+        assert_eq!(
+            ApiRoute::Cancel.target(&device).unwrap(),
+            "http://[::1]:53317/api/localsend/v2/cancel"
+        );
+        "3.0".clone_into(&mut device.version);
+        assert_eq!(
+            ApiRoute::Cancel.target(&device),
+            Err(RouteError::UnsupportedVersion(ProtocolVersion::new(3, 0)))
+        );
+        "two".clone_into(&mut device.version);
+        assert_eq!(
+            ApiRoute::Cancel.target(&device),
+            Err(RouteError::InvalidVersion("two".to_owned()))
+        );
     }
 }
@@ -0,0 +1,255 @@
+use std::fmt;
+
+use crate::{
+    dto::{FileDto, MulticastDto, PrepareUploadRequestDto, RegisterDto},
+    ProtocolVersion,
+};
+
+/// Longest alias accepted, in characters.
+pub const MAX_ALIAS_LEN: usize = 128;
+/// Longest file id or fingerprint accepted, in bytes.
+pub const MAX_ID_LEN: usize = 256;
+/// Longest file name accepted including its directories, in bytes.
+pub const MAX_FILE_NAME_LEN: usize = 4096;
+/// Larger sizes are no real files, only markers to exhaust the receiver.
+pub const MAX_FILE_SIZE: u64 = u64::MAX / 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    Empty,
+    /// Longer than the given limit
+    TooLong(usize),
+    TooLarge,
+    /// A file is listed under a key different from its id
+    KeyMismatch,
+    /// Not a `major.minor` version
+    InvalidVersion,
+}
+
+/// A structural problem of a deserialized dto, `field` is the json path to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub field: String,
+    pub problem: Problem,
+}
+
+impl ValidationError {
+    pub fn new(field: impl ToString, problem: Problem) -> Self {
+        Self {
+            field: field.to_string(),
+            problem,
+        }
+    }
+
+    fn within(mut self, parent: &str) -> Self {
+        self.field = format!("{}.{}", parent, self.field);
+        self
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.problem {
+            Problem::Empty => write!(f, "{} must not be empty", self.field),
+            Problem::TooLong(max) => write!(f, "{} must not be longer than {}", self.field, max),
+            Problem::TooLarge => write!(f, "{} is too large", self.field),
+            Problem::KeyMismatch => write!(f, "{} does not match the id of the file", self.field),
+            Problem::InvalidVersion => write!(f, "{} is not a major.minor version", self.field),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Structural checks of a dto, run after deserializing it.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationError>;
+}
+
+impl Validate for FileDto {
+    fn validate(&self) -> Result<(), ValidationError> {
+        check_id("id", &self.id)?;
+        check_len("fileName", &self.file_name, MAX_FILE_NAME_LEN)?;
+        if self.size > MAX_FILE_SIZE {
+            return Err(ValidationError::new("size", Problem::TooLarge));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for RegisterDto {
+    fn validate(&self) -> Result<(), ValidationError> {
+        check_device(&self.alias, &self.fingerprint, self.version.as_deref())
+    }
+}
+
+impl Validate for MulticastDto {
+    fn validate(&self) -> Result<(), ValidationError> {
+        check_device(&self.alias, &self.fingerprint, self.version.as_deref())
+    }
+}
+
+impl Validate for PrepareUploadRequestDto {
+    fn validate(&self) -> Result<(), ValidationError> {
+        self.info.validate().map_err(|e| e.within("info"))?;
+        for (key, file) in &self.files {
+            let field = format!("files[{:?}]", key);
+            file.validate().map_err(|e| e.within(&field))?;
+            // keys are unique, so ids matching them are unique as well
+            if *key != file.id {
+                return Err(ValidationError::new(field, Problem::KeyMismatch));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_device(
+    alias: &str,
+    fingerprint: &str,
+    version: Option<&str>,
+) -> Result<(), ValidationError> {
+    if alias.chars().count() > MAX_ALIAS_LEN {
+        return Err(ValidationError::new(
+            "alias",
+            Problem::TooLong(MAX_ALIAS_LEN),
+        ));
+    }
+    check_id("fingerprint", fingerprint)?;
+    match version {
+        Some(version) if !is_version(version) => {
+            Err(ValidationError::new("version", Problem::InvalidVersion))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_id(field: &str, id: &str) -> Result<(), ValidationError> {
+    check_len(field, id, MAX_ID_LEN)
+}
+
+fn check_len(field: &str, value: &str, max: usize) -> Result<(), ValidationError> {
+    if value.is_empty() {
+        return Err(ValidationError::new(field, Problem::Empty));
+    }
+    if value.len() > max {
+        return Err(ValidationError::new(field, Problem::TooLong(max)));
+    }
+    Ok(())
+}
+
+fn is_version(version: &str) -> bool {
+    version.parse::<ProtocolVersion>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dto::{FileDto, MulticastDto, PrepareUploadRequestDto};
+
+    use super::{Problem, Validate, ValidationError};
+
+    const VALID: &str = r#"{
+        "info": {"alias": "Nice Orange", "version": "2.0", "deviceModel": null, "deviceType": "mobile", "fingerprint": "abc", "port": 53317, "protocol": "http", "download": false},
+        "files": {"f1": {"id": "f1", "fileName": "a/b.txt", "size": 12, "fileType": "text/plain", "hash": null, "preview": null}}
+    }"#;
+
+    fn check(json: &str) -> Result<(), ValidationError> {
+        let dto: PrepareUploadRequestDto = serde_json::from_str(json).unwrap();
+        dto.validate()
+    }
+
+    #[test]
+    fn test_prepare_upload() {
+        assert_eq!(check(VALID), Ok(()));
+
+        let cases = [
+            (
+                r#""fingerprint": "abc""#,
+                r#""fingerprint": """#,
+                "info.fingerprint",
+                Problem::Empty,
+            ),
+            (
+                r#""version": "2.0""#,
+                r#""version": "v2""#,
+                "info.version",
+                Problem::InvalidVersion,
+            ),
+            (
+                r#""version": "2.0""#,
+                r#""version": "2.""#,
+                "info.version",
+                Problem::InvalidVersion,
+            ),
+            (
+                r#""id": "f1""#,
+                r#""id": """#,
+                r#"files["f1"].id"#,
+                Problem::Empty,
+            ),
+            (
+                r#""id": "f1""#,
+                r#""id": "f2""#,
+                r#"files["f1"]"#,
+                Problem::KeyMismatch,
+            ),
+            (
+                r#""fileName": "a/b.txt""#,
+                r#""fileName": """#,
+                r#"files["f1"].fileName"#,
+                Problem::Empty,
+            ),
+            (
+                r#""size": 12"#,
+                r#""size": 18446744073709551615"#,
+                r#"files["f1"].size"#,
+                Problem::TooLarge,
+            ),
+        ];
+        for (from, to, field, problem) in cases {
+            let error = check(&VALID.replace(from, to)).unwrap_err();
+            assert_eq!(error, ValidationError::new(field, problem), "{}", to);
+        }
+
+        let alias = "a".repeat(129);
+        let error = check(&VALID.replace("Nice Orange", &alias)).unwrap_err();
+        assert_eq!(error.to_string(), "info.alias must not be longer than 128");
+    }
+
+    #[test]
+    fn test_multicast() {
+        let mut dto = MulticastDto::v2("alias", None, Default::default(), "fp", 53317, true);
+        assert_eq!(dto.validate(), Ok(()));
+        dto.version = None;
+        assert_eq!(dto.validate(), Ok(()));
+        dto.fingerprint = String::new();
+        assert_eq!(
+            dto.validate().unwrap_err().to_string(),
+            "fingerprint must not be empty"
+        );
+    }
+
+    /// Mutates single bytes of a valid payload, deserializing and validating must never panic.
+    #[test]
+    fn test_malformed_payloads() {
+        let bytes = VALID.as_bytes();
+        let replacements = [b'"', b'{', b'}', b'0', b'9', b'-', b'.', b'\\', b' ', 0xff];
+        let mut seed = 0x2545_f491_u32;
+        for _ in 0..2000 {
+            let mut payload = bytes.to_vec();
+            for _ in 0..(seed % 3 + 1) {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let index = seed as usize % payload.len();
+                payload[index] = replacements[(seed >> 8) as usize % replacements.len()];
+            }
+            if let Ok(dto) = serde_json::from_slice::<PrepareUploadRequestDto>(&payload) {
+                let _ = dto.validate();
+            }
+            if let Ok(dto) = serde_json::from_slice::<FileDto>(&payload) {
+                let _ = dto.validate();
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::{fmt, str::FromStr};
+
+use crate::RouteError;
+
+/// A `major.minor` protocol version, routes only depend on the major one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ProtocolVersion {
+    pub const V1: ProtocolVersion = ProtocolVersion::new(1, 0);
+    pub const V2: ProtocolVersion = ProtocolVersion::new(2, 0);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = RouteError;
+
+    /// Both numbers have one to four digits, as in "2.0" or "1.12".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |s: &str| match s.len() {
+            1..=4 if s.bytes().all(|b| b.is_ascii_digit()) => s.parse().ok(),
+            _ => None,
+        };
+        s.split_once('.')
+            .and_then(|(major, minor)| Some(Self::new(number(major)?, number(minor)?)))
+            .ok_or_else(|| RouteError::InvalidVersion(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RouteError, PROTOCOL_VERSION_1, PROTOCOL_VERSION_2};
+
+    use super::ProtocolVersion;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(PROTOCOL_VERSION_1.parse(), Ok(ProtocolVersion::V1));
+        assert_eq!(PROTOCOL_VERSION_2.parse(), Ok(ProtocolVersion::V2));
+        assert_eq!("1.1".parse(), Ok(ProtocolVersion::new(1, 1)));
+        assert_eq!("3.12".parse(), Ok(ProtocolVersion::new(3, 12)));
+        assert_eq!(ProtocolVersion::new(2, 1).to_string(), "2.1");
+        for invalid in ["", "2", "2.", ".0", "v2.0", "2.0.1", "2.+1", "12345.0"] {
+            assert_eq!(
+                invalid.parse::<ProtocolVersion>(),
+                Err(RouteError::InvalidVersion(invalid.to_owned())),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
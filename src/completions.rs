@@ -0,0 +1,618 @@
+//! Shell completion scripts, generated from the clap definition of the arguments.
+//!
+//! Aliases for `--to` and fingerprints for `--to-fingerprint` are completed by calling
+//! the hidden `__complete-targets` subcommand, which reads the static devices of the
+//! config file and the send history without touching the network.
+
+use std::fmt::Write;
+
+use clap::{Arg, ArgAction, Command};
+use localsend_lib::{scanner::StaticDevice, send::HistoryEntry};
+
+/// Name of the hidden subcommand printing the candidates of `--to`.
+pub const COMPLETE_TARGETS: &str = "__complete-targets";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// The completion script of `cmd` for `shell`.
+pub fn generate(shell: Shell, mut cmd: Command) -> String {
+    // adds --help and propagates the global arguments to the subcommands
+    cmd.build();
+    let name = cmd.get_name().to_owned();
+    let root = Node::new(&cmd, &name);
+    match shell {
+        Shell::Bash => bash(&name, &root),
+        Shell::Zsh => zsh(&name, &root),
+        Shell::Fish => fish(&name, &root),
+        Shell::Powershell => powershell(&name, &root),
+    }
+}
+
+/// The aliases, or the fingerprints, of the devices sent to before and the static
+/// devices, one per line and each once.
+pub fn target_candidates(
+    devices: &[StaticDevice],
+    history: &[HistoryEntry],
+    fingerprints: bool,
+) -> String {
+    let mut candidates: Vec<&str> = devices
+        .iter()
+        .map(|device| match fingerprints {
+            true => device.fingerprint.as_str(),
+            false => device.alias.as_str(),
+        })
+        .chain(history.iter().map(|entry| match fingerprints {
+            true => entry.fingerprint.as_str(),
+            false => entry.alias.as_str(),
+        }))
+        // a completion offers one candidate per line
+        .filter(|candidate| !candidate.is_empty() && !candidate.contains(['\n', '\r']))
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates.iter().map(|c| format!("{}\n", c)).collect()
+}
+
+/// What is completed after a flag.
+#[derive(Debug, Clone, PartialEq)]
+enum Values {
+    /// The flag takes no value
+    None,
+    /// Files, the default of the shell
+    Any,
+    Choices(Vec<String>),
+    Aliases,
+    Fingerprints,
+}
+
+#[derive(Debug)]
+struct Flag {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    values: Values,
+}
+
+impl Flag {
+    fn new(arg: &Arg) -> Option<Self> {
+        if arg.is_positional() || arg.is_hide_set() {
+            return None;
+        }
+        let long = arg.get_long().map(str::to_owned);
+        // values of optional ones are given with `=`, completing one would be wrong
+        let takes_value = matches!(arg.get_action(), ArgAction::Set | ArgAction::Append)
+            && !arg.is_require_equals_set();
+        let choices: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_owned())
+            .collect();
+        let values = match long.as_deref() {
+            _ if !takes_value => Values::None,
+            Some("to") => Values::Aliases,
+            Some("to-fingerprint") => Values::Fingerprints,
+            _ if !choices.is_empty() => Values::Choices(choices),
+            _ => Values::Any,
+        };
+        let help = arg
+            .get_help()
+            .map(|help| help.to_string())
+            .unwrap_or_default();
+        Some(Self {
+            long,
+            short: arg.get_short(),
+            help: help.split_whitespace().collect::<Vec<_>>().join(" "),
+            values,
+        })
+    }
+
+    /// The spellings of the flag, like `--verbose` and `-v`.
+    fn names(&self) -> Vec<String> {
+        let long = self.long.iter().map(|long| format!("--{}", long));
+        let short = self.short.iter().map(|short| format!("-{}", short));
+        long.chain(short).collect()
+    }
+}
+
+/// A command and its subcommands, `id` tells them apart in the scripts.
+#[derive(Debug)]
+struct Node {
+    id: String,
+    name: String,
+    about: String,
+    flags: Vec<Flag>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(cmd: &Command, id: &str) -> Self {
+        let children = cmd
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+            .map(|sub| Node::new(sub, &format!("{}_{}", id, sub.get_name().replace('-', "_"))))
+            .collect();
+        let about = cmd
+            .get_about()
+            .map(|about| about.to_string())
+            .unwrap_or_default();
+        Self {
+            id: id.to_owned(),
+            name: cmd.get_name().to_owned(),
+            about,
+            flags: cmd.get_arguments().filter_map(Flag::new).collect(),
+            children,
+        }
+    }
+
+    /// This node and all below it, parents first.
+    fn all(&self) -> Vec<&Node> {
+        let mut nodes = vec![self];
+        for child in &self.children {
+            nodes.extend(child.all());
+        }
+        nodes
+    }
+
+    fn flag_names(&self) -> Vec<String> {
+        self.flags.iter().flat_map(Flag::names).collect()
+    }
+
+    fn child_names(&self) -> Vec<&str> {
+        self.children
+            .iter()
+            .map(|child| child.name.as_str())
+            .collect()
+    }
+}
+
+fn bash(name: &str, root: &Node) -> String {
+    let function = format!("_{}", root.id);
+    let mut script = String::new();
+    writeln!(script, "{}() {{", function).unwrap();
+    script.push_str("    local cur prev node i\n");
+    script.push_str("    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    script.push_str("    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    writeln!(script, "    node=\"{}\"", root.id).unwrap();
+    script.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    script.push_str("        case \"$node:${COMP_WORDS[i]}\" in\n");
+    for node in root.all() {
+        for child in &node.children {
+            writeln!(
+                script,
+                "            {}:{}) node=\"{}\" ;;",
+                node.id, child.name, child.id
+            )
+            .unwrap();
+        }
+    }
+    script.push_str("        esac\n    done\n\n");
+    script.push_str("    case \"$node:$prev\" in\n");
+    for node in root.all() {
+        for flag in node.flags.iter().filter(|f| f.values != Values::None) {
+            let words = match &flag.values {
+                Values::Choices(choices) => {
+                    format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", choices.join(" "))
+                }
+                Values::Aliases => format!(
+                    "local IFS=$'\\n'; COMPREPLY=($(compgen -W \"$({} {} 2>/dev/null)\" -- \"$cur\"))",
+                    name, COMPLETE_TARGETS
+                ),
+                Values::Fingerprints => format!(
+                    "local IFS=$'\\n'; COMPREPLY=($(compgen -W \"$({} {} --fingerprints 2>/dev/null)\" -- \"$cur\"))",
+                    name, COMPLETE_TARGETS
+                ),
+                _ => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_owned(),
+            };
+            let patterns: Vec<String> = flag
+                .names()
+                .iter()
+                .map(|flag| format!("{}:{}", node.id, flag))
+                .collect();
+            writeln!(
+                script,
+                "        {})\n            {}\n            return ;;",
+                patterns.join("|"),
+                words
+            )
+            .unwrap();
+        }
+    }
+    script.push_str("    esac\n\n");
+    script.push_str("    local flags subcommands\n    case \"$node\" in\n");
+    for node in root.all() {
+        writeln!(
+            script,
+            "        {})\n            flags=\"{}\"\n            subcommands=\"{}\" ;;",
+            node.id,
+            node.flag_names().join(" "),
+            node.child_names().join(" ")
+        )
+        .unwrap();
+    }
+    script.push_str("    esac\n");
+    script.push_str("    if [[ \"$cur\" == -* ]]; then\n");
+    script.push_str("        COMPREPLY=($(compgen -W \"$flags\" -- \"$cur\"))\n");
+    script.push_str("    elif [[ -n \"$subcommands\" ]]; then\n");
+    script.push_str("        COMPREPLY=($(compgen -W \"$subcommands\" -- \"$cur\"))\n");
+    script.push_str("    else\n");
+    script.push_str("        COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+    script.push_str("    fi\n}\n\n");
+    writeln!(
+        script,
+        "complete -F {} -o bashdefault -o default {}",
+        function, name
+    )
+    .unwrap();
+    script
+}
+
+fn zsh(name: &str, root: &Node) -> String {
+    let function = format!("_{}", root.id);
+    let mut script = format!("#compdef {}\n\n", name);
+    writeln!(script, "{}() {{", function).unwrap();
+    writeln!(script, "    local node=\"{}\" i", root.id).unwrap();
+    script.push_str("    for ((i = 2; i < CURRENT; i++)); do\n");
+    script.push_str("        case \"$node:${words[i]}\" in\n");
+    for node in root.all() {
+        for child in &node.children {
+            writeln!(
+                script,
+                "            {}:{}) node=\"{}\" ;;",
+                node.id, child.name, child.id
+            )
+            .unwrap();
+        }
+    }
+    script.push_str("        esac\n    done\n\n");
+    script.push_str("    local -a candidates\n");
+    script.push_str("    case \"$node:${words[CURRENT-1]}\" in\n");
+    for node in root.all() {
+        for flag in node.flags.iter().filter(|f| f.values != Values::None) {
+            let words = match &flag.values {
+                Values::Choices(choices) => format!("compadd -- {}", choices.join(" ")),
+                Values::Aliases => format!(
+                    "candidates=(\"${{(@f)$({} {} 2>/dev/null)}}\"); compadd -a candidates",
+                    name, COMPLETE_TARGETS
+                ),
+                Values::Fingerprints => format!(
+                    "candidates=(\"${{(@f)$({} {} --fingerprints 2>/dev/null)}}\"); compadd -a candidates",
+                    name, COMPLETE_TARGETS
+                ),
+                _ => "_files".to_owned(),
+            };
+            let patterns: Vec<String> = flag
+                .names()
+                .iter()
+                .map(|flag| format!("{}:{}", node.id, flag))
+                .collect();
+            writeln!(
+                script,
+                "        {})\n            {}\n            return ;;",
+                patterns.join("|"),
+                words
+            )
+            .unwrap();
+        }
+    }
+    script.push_str("    esac\n\n");
+    script.push_str("    local -a flags subcommands\n    case \"$node\" in\n");
+    for node in root.all() {
+        writeln!(
+            script,
+            "        {})\n            flags=({})\n            subcommands=({}) ;;",
+            node.id,
+            node.flag_names().join(" "),
+            node.child_names().join(" ")
+        )
+        .unwrap();
+    }
+    script.push_str("    esac\n");
+    script.push_str("    if [[ \"$PREFIX\" == -* ]]; then\n");
+    script.push_str("        compadd -a flags\n");
+    script.push_str("    elif (( ${#subcommands} )); then\n");
+    script.push_str("        compadd -a subcommands\n");
+    script.push_str("    else\n");
+    script.push_str("        _files\n");
+    script.push_str("    fi\n}\n\n");
+    writeln!(
+        script,
+        "if [ \"$funcstack[1]\" = \"{}\" ]; then\n    {} \"$@\"\nelse\n    compdef {} {}\nfi",
+        function, function, function, name
+    )
+    .unwrap();
+    script
+}
+
+/// Quotes `text` for a single quoted fish string.
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(name: &str, root: &Node) -> String {
+    let mut script = String::new();
+    for node in root.all() {
+        // the root is completed until a subcommand was given, the others once theirs was
+        let condition = match node.id == root.id {
+            true => "__fish_use_subcommand".to_owned(),
+            false => format!("__fish_seen_subcommand_from {}", node.name),
+        };
+        for child in &node.children {
+            writeln!(
+                script,
+                "complete -c {} -n {} -f -a {} -d {}",
+                name,
+                fish_quote(&condition),
+                fish_quote(&child.name),
+                fish_quote(&child.about)
+            )
+            .unwrap();
+        }
+        for flag in &node.flags {
+            let mut line = format!("complete -c {} -n {}", name, fish_quote(&condition));
+            if let Some(long) = &flag.long {
+                write!(line, " -l {}", long).unwrap();
+            }
+            if let Some(short) = flag.short {
+                write!(line, " -s {}", short).unwrap();
+            }
+            match &flag.values {
+                Values::None => {}
+                Values::Any => line.push_str(" -r"),
+                Values::Choices(choices) => {
+                    write!(line, " -x -a {}", fish_quote(&choices.join(" "))).unwrap()
+                }
+                Values::Aliases => write!(
+                    line,
+                    " -x -a {}",
+                    fish_quote(&format!("({} {} 2>/dev/null)", name, COMPLETE_TARGETS))
+                )
+                .unwrap(),
+                Values::Fingerprints => write!(
+                    line,
+                    " -x -a {}",
+                    fish_quote(&format!(
+                        "({} {} --fingerprints 2>/dev/null)",
+                        name, COMPLETE_TARGETS
+                    ))
+                )
+                .unwrap(),
+            }
+            if !flag.help.is_empty() {
+                write!(line, " -d {}", fish_quote(&flag.help)).unwrap();
+            }
+            writeln!(script, "{}", line).unwrap();
+        }
+    }
+    script
+}
+
+/// Quotes `text` for a single quoted PowerShell string.
+fn powershell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+fn powershell_list(items: &[impl AsRef<str>]) -> String {
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| powershell_quote(item.as_ref()))
+        .collect();
+    format!("@({})", items.join(", "))
+}
+
+fn powershell(name: &str, root: &Node) -> String {
+    let mut script = format!(
+        "Register-ArgumentCompleter -Native -CommandName {} -ScriptBlock {{\n",
+        powershell_quote(name)
+    );
+    script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    script.push_str("    $words = @($commandAst.CommandElements |\n");
+    script.push_str("        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |\n");
+    script.push_str("        Select-Object -Skip 1 | ForEach-Object { $_.ToString() })\n");
+    writeln!(script, "    $node = {}", powershell_quote(&root.id)).unwrap();
+    script.push_str("    foreach ($word in $words) {\n        switch (\"${node}:$word\") {\n");
+    for node in root.all() {
+        for child in &node.children {
+            writeln!(
+                script,
+                "            {} {{ $node = {} }}",
+                powershell_quote(&format!("{}:{}", node.id, child.name)),
+                powershell_quote(&child.id)
+            )
+            .unwrap();
+        }
+    }
+    script.push_str("        }\n    }\n");
+    script.push_str("    $prev = if ($words.Count) { $words[-1] } else { '' }\n");
+    script.push_str("    $candidates = $null\n");
+    script.push_str("    switch (\"${node}:$prev\") {\n");
+    for node in root.all() {
+        for flag in node.flags.iter().filter(|f| f.values != Values::None) {
+            let words = match &flag.values {
+                Values::Choices(choices) => format!("$candidates = {}", powershell_list(choices)),
+                Values::Aliases => {
+                    format!("$candidates = @({} {} 2>$null)", name, COMPLETE_TARGETS)
+                }
+                Values::Fingerprints => format!(
+                    "$candidates = @({} {} --fingerprints 2>$null)",
+                    name, COMPLETE_TARGETS
+                ),
+                // paths are completed by PowerShell itself
+                _ => "return".to_owned(),
+            };
+            for flag_name in flag.names() {
+                writeln!(
+                    script,
+                    "        {} {{ {} }}",
+                    powershell_quote(&format!("{}:{}", node.id, flag_name)),
+                    words
+                )
+                .unwrap();
+            }
+        }
+    }
+    script.push_str("    }\n");
+    script.push_str("    if ($null -eq $candidates) {\n        switch ($node) {\n");
+    for node in root.all() {
+        let list = match node.children.is_empty() {
+            true => "$flags".to_owned(),
+            false => "$subcommands".to_owned(),
+        };
+        writeln!(
+            script,
+            "            {} {{ $flags = {}; $subcommands = {}; $default = {} }}",
+            powershell_quote(&node.id),
+            powershell_list(&node.flag_names()),
+            powershell_list(&node.child_names()),
+            list
+        )
+        .unwrap();
+    }
+    script.push_str("        }\n");
+    script.push_str(
+        "        $candidates = if ($wordToComplete -like '-*') { $flags } else { $default }\n",
+    );
+    script.push_str("    }\n");
+    script.push_str(
+        "    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n",
+    );
+    script.push_str("        $text = if ($_ -match '\\s') { \"'$_'\" } else { $_ }\n");
+    script.push_str(
+        "        [System.Management.Automation.CompletionResult]::new($text, $_, 'ParameterValue', $_)\n",
+    );
+    script.push_str("    }\n}\n");
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::CommandFactory;
+    use localsend_lib::{scanner::StaticDevice, send::HistoryEntry};
+
+    use super::{generate, target_candidates, Shell, COMPLETE_TARGETS};
+    use crate::Args;
+
+    fn static_device(alias: &str, fingerprint: &str) -> StaticDevice {
+        toml::from_str(&format!(
+            "alias = {:?}\nip = \"192.168.1.2\"\nfingerprint = {:?}",
+            alias, fingerprint
+        ))
+        .unwrap()
+    }
+
+    fn sent_to(alias: &str, fingerprint: &str) -> HistoryEntry {
+        HistoryEntry {
+            fingerprint: fingerprint.to_owned(),
+            alias: alias.to_owned(),
+            finished_at: 0,
+            files: 1,
+            bytes: 1,
+            success: true,
+        }
+    }
+
+    #[test]
+    fn test_target_candidates() {
+        let devices = [static_device("NAS", "nas-1"), static_device("", "unnamed")];
+        let history = [
+            sent_to("Nice Orange", "abc"),
+            sent_to("NAS", "nas-1"),
+            sent_to("bad\nname", "def"),
+        ];
+        // read by the scripts line by line, the format must not change
+        assert_eq!(
+            target_candidates(&devices, &history, false),
+            "NAS\nNice Orange\n"
+        );
+        assert_eq!(
+            target_candidates(&devices, &history, true),
+            "abc\ndef\nnas-1\nunnamed\n"
+        );
+        assert_eq!(target_candidates(&[], &[], false), "");
+    }
+
+    /// Every flag of every visible subcommand, like `receive --quick-save`.
+    fn all_flags() -> Vec<(String, String)> {
+        let mut cmd = Args::command();
+        cmd.build();
+        let mut flags = vec![];
+        let mut commands = vec![(String::new(), &cmd)];
+        while let Some((path, cmd)) = commands.pop() {
+            for arg in cmd.get_arguments().filter(|arg| !arg.is_hide_set()) {
+                if let Some(long) = arg.get_long() {
+                    flags.push((path.clone(), format!("--{}", long)));
+                }
+            }
+            for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+                if sub.get_name() != "help" {
+                    commands.push((format!("{} {}", path, sub.get_name()), sub));
+                }
+            }
+        }
+        flags
+    }
+
+    #[test]
+    fn test_every_flag_completed() {
+        let flags = all_flags();
+        assert!(flags
+            .iter()
+            .any(|(path, flag)| path == " send" && flag == "--to"));
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Powershell] {
+            let script = generate(shell, Args::command());
+            for (path, flag) in &flags {
+                // fish names long flags without their dashes
+                let name = match shell {
+                    Shell::Fish => format!("-l {}", flag.trim_start_matches("--")),
+                    _ => flag.clone(),
+                };
+                assert!(
+                    script.contains(&name),
+                    "{:?} does not complete {}{}",
+                    shell,
+                    path,
+                    flag
+                );
+            }
+            assert!(script.contains(COMPLETE_TARGETS));
+            assert!(!script.contains("__complete-targets:"));
+        }
+    }
+
+    /// Compares the scripts with the ones in `testdata/completions`, which are written
+    /// again with `LOCALSEND_BLESS=1` after the flags changed on purpose.
+    #[test]
+    #[cfg(not(feature = "self-update"))]
+    fn test_golden_scripts() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/completions");
+        let bless = std::env::var_os("LOCALSEND_BLESS").is_some();
+        for (shell, file) in [
+            (Shell::Bash, "localsend.bash"),
+            (Shell::Zsh, "_localsend"),
+            (Shell::Fish, "localsend.fish"),
+            (Shell::Powershell, "localsend.ps1"),
+        ] {
+            let script = generate(shell, Args::command());
+            let path = dir.join(file);
+            if bless {
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(&path, &script).unwrap();
+                continue;
+            }
+            let golden = std::fs::read_to_string(&path).unwrap();
+            assert!(
+                script == golden,
+                "{} is outdated, run the tests with LOCALSEND_BLESS=1 if the flags changed on purpose",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use localsend_lib::{
+    receive::validate_destination,
+    scanner::{StaticDevice, StaticDeviceProvider},
+    server::{reload_settings, MutexServerState},
+    util::{fs::DangerousExtensions, preview::PreviewPolicy},
+    CollisionPolicy, Settings, SettingsLoader,
+};
+use serde::Deserialize;
+
+/// Kept in the config directory next to the theme.
+pub const CONFIG_FILE: &str = "config.toml";
+/// How often a long running process looks at the config file.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The `[[devices]]` tables, reachable without discovery
+    #[serde(default)]
+    pub devices: Vec<StaticDevice>,
+    /// The `[preview]` table, which texts and files are shown inline
+    #[serde(default)]
+    pub preview: PreviewPolicy,
+    /// Extensions of received files saved with `.received` appended, the ones Windows
+    /// runs when opened on Windows and none elsewhere when not set
+    #[serde(default, rename = "dangerous-extensions")]
+    pub dangerous_extensions: Option<Vec<String>>,
+    /// The `[receive]` table, read again when a receiver reloads its settings
+    #[serde(default)]
+    pub receive: ReceiveConfig,
+}
+
+/// Settings of a receiver that win over the command line, so reloading the config
+/// changes them while it runs.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReceiveConfig {
+    /// Like --dest, with the same placeholders
+    pub destination: Option<PathBuf>,
+    pub quick_save: Option<bool>,
+    /// Like --on-conflict
+    pub on_conflict: Option<String>,
+}
+
+impl ReceiveConfig {
+    /// Overrides `settings` with the values set, failing on invalid ones.
+    pub fn apply(&self, settings: &mut Settings) -> std::result::Result<(), String> {
+        if let Some(destination) = &self.destination {
+            validate_destination(destination)?;
+            settings.destination.clone_from(destination);
+        }
+        if let Some(quick_save) = self.quick_save {
+            settings.quick_save = quick_save;
+        }
+        if let Some(policy) = &self.on_conflict {
+            settings.collision_policy = policy.parse::<CollisionPolicy>()?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn dangerous_extensions(&self) -> DangerousExtensions {
+        match &self.dangerous_extensions {
+            Some(extensions) => DangerousExtensions::new(extensions),
+            None => DangerousExtensions::native(),
+        }
+    }
+}
+
+pub fn parse_config(toml: &str) -> std::result::Result<Config, String> {
+    toml::from_str(toml).map_err(|e| e.to_string())
+}
+
+/// Reads the config file, one that does not exist is empty.
+pub fn read_config(path: &Path) -> std::result::Result<Config, String> {
+    match std::fs::read_to_string(path) {
+        Ok(toml) => parse_config(&toml).map_err(|e| format!("Invalid config {:?}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(format!("Failed to read config {:?}: {}", path, e)),
+    }
+}
+
+/// Loads the static devices of the config file, naming every broken entry.
+pub fn load_static_devices(path: &Path) -> std::result::Result<StaticDeviceProvider, Vec<String>> {
+    let config = read_config(path).map_err(|e| vec![e])?;
+    StaticDeviceProvider::new(&config.devices)
+        .map_err(|errors| errors.iter().map(ToString::to_string).collect())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reloads the static devices whenever the config file changes, until the process exits.
+///
+/// A broken config is reported and the devices loaded before are kept.
+pub fn watch_config(path: PathBuf, provider: StaticDeviceProvider) {
+    tokio::spawn(async move {
+        let mut last = modified(&path);
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let current = modified(&path);
+            if current == last {
+                continue;
+            }
+            last = current;
+            let reloaded = read_config(&path).and_then(|config| {
+                provider.reload(&config.devices).map_err(|errors| {
+                    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                    errors.join(", ")
+                })
+            });
+            match reloaded {
+                Ok(()) => log::info!(
+                    "Reloaded {:?}, {} static devices",
+                    path,
+                    provider.devices().len()
+                ),
+                Err(e) => log::error!("{}, keeping the static devices loaded before", e),
+            }
+        }
+    });
+}
+
+/// Reloads the settings of `state` with `loader` on every SIGHUP, until the process exits.
+#[cfg(unix)]
+pub fn reload_on_hangup(state: MutexServerState, loader: SettingsLoader) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::warn!(
+                "Failed to listen for SIGHUP, settings are not reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            log::info!("SIGHUP received, reloading the settings");
+            // a failure is logged and keeps the settings before
+            reload_settings(&state, &loader).await.ok();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_hangup(_state: MutexServerState, _loader: SettingsLoader) {}
+
+#[cfg(test)]
+mod tests {
+    use localsend_lib::{
+        util::{fs::DangerousExtensions, preview::PreviewPolicy},
+        CollisionPolicy, Settings,
+    };
+
+    use super::parse_config;
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config(
+            r#"
+            [[devices]]
+            alias = "nas"
+            ip = "10.0.0.2"
+            fingerprint = "2f1c9a3e"
+
+            [[devices]]
+            alias = "printer"
+            ip = "10.0.0.3"
+            port = 53317
+            https = true
+            fingerprint = "7b2e"
+            version = "1.0"
+            device-type = "server"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.devices[0].port, 53318);
+        assert_eq!(config.devices[0].version, "2.0");
+        assert!(config.devices[1].https);
+        assert_eq!(config.devices[1].device_type.name(), "server");
+
+        assert_eq!(config.preview, PreviewPolicy::default());
+        assert!(parse_config("").unwrap().devices.is_empty());
+        assert!(parse_config("[[devices]]\naddress = \"10.0.0.2\"").is_err());
+
+        let config = parse_config(
+            r#"
+            [preview]
+            max-bytes = 4096
+            extensions = ["log", "csv"]
+            strip-ansi = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.preview.max_bytes, 4096);
+        assert_eq!(config.preview.extensions, ["log", "csv"]);
+        assert!(!config.preview.strip_ansi);
+        assert_eq!(
+            config.preview.mime_prefixes,
+            PreviewPolicy::default().mime_prefixes
+        );
+        assert!(parse_config("[preview]\nmax-size = 1").is_err());
+
+        assert_eq!(
+            parse_config("").unwrap().dangerous_extensions(),
+            DangerousExtensions::native()
+        );
+        let config = parse_config("dangerous-extensions = [\"exe\", \".apk\"]").unwrap();
+        assert_eq!(
+            config.dangerous_extensions(),
+            DangerousExtensions::new(["exe", "apk"])
+        );
+        assert!(parse_config("dangerous-extensions = []")
+            .unwrap()
+            .dangerous_extensions()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_receive_config() {
+        let config = parse_config(
+            r#"
+            [receive]
+            destination = "/srv/inbox/{alias}"
+            quick-save = true
+            on-conflict = "rename"
+            "#,
+        )
+        .unwrap();
+        let mut settings = Settings::default();
+        config.receive.apply(&mut settings).unwrap();
+        assert_eq!(settings.destination.to_str(), Some("/srv/inbox/{alias}"));
+        assert!(settings.quick_save);
+        assert_eq!(settings.collision_policy, CollisionPolicy::Rename);
+
+        // nothing set keeps the command line
+        let mut settings = Settings::default();
+        parse_config("")
+            .unwrap()
+            .receive
+            .apply(&mut settings)
+            .unwrap();
+        assert!(!settings.quick_save);
+
+        let invalid = parse_config("[receive]\non-conflict = \"merge\"").unwrap();
+        assert!(invalid.receive.apply(&mut Settings::default()).is_err());
+        let invalid = parse_config("[receive]\ndestination = \"{owner}\"").unwrap();
+        assert!(invalid.receive.apply(&mut Settings::default()).is_err());
+        assert!(parse_config("[receive]\nquick_save = true").is_err());
+    }
+}
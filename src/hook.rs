@@ -0,0 +1,65 @@
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use localsend_lib::receive::{ReceiveHook, ReceivedFileInfo};
+use tokio::process::Command;
+
+/// Runs a shell command for every received file, described by `LS_*` variables.
+#[derive(Debug)]
+pub struct CommandHook {
+    command: String,
+}
+
+impl CommandHook {
+    pub fn new(command: String) -> Self {
+        Self { command }
+    }
+
+    #[cfg(unix)]
+    fn shell(&self) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&self.command);
+        command
+    }
+
+    #[cfg(windows)]
+    fn shell(&self) -> Command {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(&self.command);
+        command
+    }
+}
+
+#[async_trait]
+impl ReceiveHook for CommandHook {
+    async fn on_file_received(&self, info: &ReceivedFileInfo) -> Result<(), String> {
+        let file_type = serde_json::to_value(&info.file.file_type)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_owned))
+            .unwrap_or_default();
+        let output = self
+            .shell()
+            .env("LS_FILE_PATH", info.path.clone().unwrap_or_default())
+            .env("LS_FILE_NAME", &info.file.file_name)
+            .env("LS_FILE_TYPE", file_type)
+            .env("LS_SENDER_ALIAS", &info.sender.alias)
+            .env("LS_SESSION_ID", &info.session_id)
+            .stdin(Stdio::null())
+            // a timed out command is killed
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run {:?}: {}", self.command, e))?;
+
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            log::info!("on-receive: {}", line);
+        }
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            log::warn!("on-receive: {}", line);
+        }
+        if !output.status.success() {
+            return Err(output.status.to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::{net::IpAddr, path::Path};
+
+use localsend_lib::{
+    send::{FileStatus, SendingFiles, Target},
+    Result,
+};
+use localsend_proto::Device;
+use serde::Deserialize;
+
+/// One send of a batch, the inputs are paths or texts like the input of `send`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub inputs: Vec<String>,
+    pub target: Target,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchFile {
+    #[serde(default, rename = "job")]
+    jobs: Vec<JobEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct JobEntry {
+    files: Vec<String>,
+    to: Option<String>,
+    to_fingerprint: Option<String>,
+    to_ip: Option<IpAddr>,
+}
+
+/// Reads the `[[job]]` tables of a batch file.
+///
+/// Each job has `files` and exactly one of `to`, `to-fingerprint` and `to-ip`.
+pub fn parse_jobs(toml: &str) -> std::result::Result<Vec<Job>, String> {
+    let batch: BatchFile = toml::from_str(toml).map_err(|e| e.to_string())?;
+    if batch.jobs.is_empty() {
+        return Err("no [[job]] in batch".to_owned());
+    }
+    batch
+        .jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            if entry.files.is_empty() {
+                return Err(format!("job {} has no files", index + 1));
+            }
+            let target = match (entry.to, entry.to_fingerprint, entry.to_ip) {
+                (Some(alias), None, None) => Target::Alias(alias),
+                (None, Some(fingerprint), None) => Target::Fingerprint(fingerprint),
+                (None, None, Some(ip)) => Target::Ip(ip),
+                _ => {
+                    return Err(format!(
+                        "job {} needs exactly one of to, to-fingerprint and to-ip",
+                        index + 1
+                    ))
+                }
+            };
+            Ok(Job {
+                inputs: entry.files,
+                target,
+            })
+        })
+        .collect()
+}
+
+/// Reads a batch file, relative paths in it are relative to the file.
+pub fn read_jobs(path: &Path) -> std::result::Result<Vec<Job>, String> {
+    let toml = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read batch {:?}: {}", path, e))?;
+    let mut jobs = parse_jobs(&toml).map_err(|e| format!("Invalid batch {:?}: {}", path, e))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    for input in jobs.iter_mut().flat_map(|job| job.inputs.iter_mut()) {
+        let path = base.join(&*input);
+        if Path::new(input).is_relative() && path.exists() {
+            *input = path.to_string_lossy().to_string();
+        }
+    }
+    Ok(jobs)
+}
+
+/// How a job of a batch ended, `result` is `None` for jobs not run after `--fail-fast`.
+pub struct JobReport {
+    pub job: Job,
+    pub device: Option<Device>,
+    pub result: Option<Result<SendingFiles>>,
+}
+
+impl JobReport {
+    /// Whether every file of the job was delivered.
+    pub fn succeeded(&self) -> bool {
+        match &self.result {
+            Some(Ok(files)) => files
+                .values()
+                .all(|file| file.status == FileStatus::Finished),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_lib::send::{SendError, SendingFiles, Target};
+
+    use super::{parse_jobs, Job, JobReport};
+
+    #[test]
+    fn test_parse_jobs() {
+        let jobs = parse_jobs(
+            r#"
+            [[job]]
+            files = ["a.txt", "photos"]
+            to = "phone"
+
+            [[job]]
+            files = ["b.mkv"]
+            to-ip = "192.168.1.20"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            jobs,
+            [
+                Job {
+                    inputs: vec!["a.txt".to_owned(), "photos".to_owned()],
+                    target: Target::Alias("phone".to_owned()),
+                },
+                Job {
+                    inputs: vec!["b.mkv".to_owned()],
+                    target: Target::Ip("192.168.1.20".parse().unwrap()),
+                },
+            ]
+        );
+
+        let errors = [
+            "",
+            "[[job]]\nfiles = []\nto = \"phone\"",
+            "[[job]]\nfiles = [\"a\"]",
+            "[[job]]\nfiles = [\"a\"]\nto = \"phone\"\nto-ip = \"10.0.0.1\"",
+            "[[job]]\nfiles = [\"a\"]\nto = \"phone\"\nretry = true",
+        ];
+        for toml in errors {
+            assert!(parse_jobs(toml).is_err(), "{:?}", toml);
+        }
+    }
+
+    #[test]
+    fn test_job_succeeded() {
+        let report = |result| JobReport {
+            job: Job {
+                inputs: vec![],
+                target: Target::Alias("phone".to_owned()),
+            },
+            device: None,
+            result,
+        };
+        let mut files = SendingFiles::default();
+        files.add_text("hello", true);
+        assert!(!report(Some(Ok(files.clone()))).succeeded());
+        let id = files.keys().next().unwrap().clone();
+        files.to_finish_status(id, true);
+        assert!(report(Some(Ok(files))).succeeded());
+        assert!(!report(Some(Err(SendError::Rejected.into()))).succeeded());
+        assert!(!report(None).succeeded());
+    }
+}
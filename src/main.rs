@@ -1,22 +1,89 @@
-use std::{net::Ipv4Addr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    io::IsTerminal,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use colored::Colorize;
+use indicatif::MultiProgress;
 use itertools::Itertools;
 use localsend_lib::{
-    scanner::MulticastDeviceScanner,
-    send::{SendError, SendSession, SendingFiles, UploadProgress},
-    server::{start_api_server, ClientMessage, ServerMessage, ServerState},
-    util::device,
-    Result, Settings,
+    diagnostics::{
+        bind_advice, excluded_port_ranges, probe_binds, run_diagnostics, DiagnosticsOptions,
+        Platform, Transport,
+    },
+    progress::{ProgressSender, ProgressStream},
+    receive::{
+        check_destination, clean_stale_journals, destination_root, sweep_markers,
+        validate_destination, ArchiveFormat, CompletionFifo, CompletionMarker, DedupAction,
+        DestinationError, DownloadSession, HookChain, PreviewFile, ReceiveHook, StructureLimits,
+        DEDUP_INDEX_FILE, DEFAULT_MAX_DIRECTORIES, DEFAULT_MAX_FILES, DEFAULT_MAX_PATH_DEPTH,
+        JOURNAL_DIR,
+    },
+    scanner::{
+        announcement, DeviceScanner, Discovery, KnownDevices, MulticastDeviceScanner, ScanOptions,
+        ScannerRoles, StaticDeviceProvider, DEFAULT_ANNOUNCE_LIMIT, DEFAULT_SCAN_SETTLE,
+    },
+    send::{
+        check_reachable, looks_like_path, read_manifest, DirFilter, FileStatus, FilterReport,
+        HistoryEntry, Pace, Pacing, SendError, SendSession, SendingFiles, SymlinkPolicy, Target,
+        TransferHistory, DEFAULT_CHUNK_FILES, HISTORY_FILE,
+    },
+    server::{
+        default_control_path, spawn_network_watcher, start_api_server, start_control_server,
+        watch_once, CancelledBy, ClientMessage, ControlClient, ControlRequest, ControlResponse,
+        ControlService, MutexServerState, OnceOutcome, ServerError, ServerHandle, ServerMessage,
+        ServerState, SessionEvent, SharedText,
+    },
+    util::{
+        device::{self, with_alias},
+        fs::{config_dir, data_dir, CaseSensitivity, DangerousExtensions, NameRules},
+        mime::{ContentTypes, MimeOverride},
+        preview::PreviewPolicy,
+        resolve::{resolve_host, HostTarget, InfoProbe, SystemResolver},
+        trace::{set_http_trace, Direction, HttpTrace},
+    },
+    CollisionPolicy, Result, Settings, SettingsLoader, DEFAULT_HOOK_TIMEOUT,
+    DEFAULT_PENDING_SESSION_WAIT, DEFAULT_SESSION_TIMEOUT,
 };
 use localsend_proto::{
-    Device, DEFAULT_HTTP_PORT, DEFAULT_MULTICAST, DEFAULT_PORT, PROTOCOL_VERSION_2,
+    Device, DeviceType, DEFAULT_HTTP_PORT, DEFAULT_MULTICAST, DEFAULT_PORT, MAX_ALIAS_LEN,
+    PROTOCOL_VERSION_2,
 };
 use simple_logger::SimpleLogger;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
-use crate::ui::{FileProgressBar, InteractiveUI, PromptUI};
+use crate::completions::Shell;
+use crate::config::{
+    load_static_devices, read_config, reload_on_hangup, watch_config, Config, CONFIG_FILE,
+};
+use crate::hook::CommandHook;
+use crate::jobs::{read_jobs, Job, JobReport};
+use crate::merge::{default_merge_path, merge_inputs, Merge};
+use crate::presentation::{sanitize_display, IconSet, Theme, THEME_FILE};
+use crate::ui::{
+    sorted_by_name, DeviceOrder, FileProgressBar, InteractiveUI, NextAction, ProgressMode,
+    ProgressOptions, PromptUI,
+};
 
+mod completions;
+mod config;
+mod hook;
+mod jobs;
+mod merge;
+mod presentation;
+mod stress;
 mod ui;
+#[cfg(feature = "self-update")]
+mod update;
+
+const RETRY_BUSY_DELAY: Duration = Duration::from_secs(3);
+/// Created in the config directory once the ports could be bound.
+const FIRST_RUN_MARKER: &str = "first-run-done";
 
 #[derive(Parser)]
 struct Args {
@@ -36,21 +103,92 @@ struct Args {
     #[arg(long, env = "LOCALSEND_HTTP_PORT", default_value_t = DEFAULT_HTTP_PORT)]
     http_port: u16,
 
+    /// UDP port to send announcements to, same as --port by default
+    #[arg(long, env = "LOCALSEND_ANNOUNCE_PORT")]
+    announce_port: Option<u16>,
+
+    /// IP address advertised to other devices, e.g. the host address of a container
+    #[arg(long, env = "LOCALSEND_ADVERTISE_IP")]
+    advertise_ip: Option<IpAddr>,
+
+    /// Http port advertised to other devices, same as --http-port by default
+    #[arg(long, env = "LOCALSEND_ADVERTISE_PORT")]
+    advertise_port: Option<u16>,
+
+    /// Device type shown to other devices: mobile, desktop, web, headless, server
+    #[arg(long, env = "LOCALSEND_DEVICE_TYPE", default_value_t = DeviceType::Headless)]
+    device_type: DeviceType,
+
+    /// Device model shown to other devices, the operating system by default
+    #[arg(long, env = "LOCALSEND_DEVICE_MODEL", value_parser = parse_device_model)]
+    device_model: Option<String>,
+
+    /// Keep announcements within this many bytes, the device model and then the alias are cut to fit
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_ANNOUNCE_LIMIT)]
+    announce_limit: usize,
+
+    /// End scans once no new device answered for this long, 0 always listens 2 seconds
+    #[arg(long, value_name = "MS", default_value_t = DEFAULT_SCAN_SETTLE.as_millis() as u64)]
+    scan_settle_ms: u64,
+
+    /// Where to find devices: static for the [[devices]] of the config file only,
+    /// multicast for announcing ones only, or both
+    #[arg(long, env = "LOCALSEND_DISCOVERY", value_name = "MODE", default_value_t = Discovery::Both)]
+    discovery: Discovery,
+
+    /// Send without ever announcing this device or answering others, only devices
+    /// announcing themselves are found. --to-host and static devices are reached directly
+    #[arg(long, env = "LOCALSEND_STEALTH")]
+    stealth: bool,
+
+    /// Config file with the static [[devices]], the config.toml in the config directory by default
+    #[arg(long, env = "LOCALSEND_CONFIG", value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Connect to every static device at startup, those not answering are shown as unreachable
+    #[arg(long = "probe-static")]
+    probe_static: bool,
+
     /// Do not use nerd fonts
     #[arg(long)]
     no_nerd: bool,
 
+    /// How to show transfer progress: auto, full, compact or none
+    #[arg(long, value_name = "MODE", default_value = "auto")]
+    progress: ProgressMode,
+
+    /// Colors and icons: default, high-contrast, mono or the path of a JSON theme,
+    /// the theme.json in the config directory by default
+    #[arg(long, value_name = "NAME|PATH")]
+    theme: Option<String>,
+
+    /// Log debug messages, e.g. the errors behind hints
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Print the HTTP requests and responses exchanged with other devices to stderr, tokens
+    /// and session ids are masked unless --trace-http=full; turns off automatic progress
+    #[arg(
+        long,
+        global = true,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "masked"
+    )]
+    trace_http: Option<HttpTrace>,
+
     #[clap(subcommand)]
     cmd: SubCommand,
 }
 
 impl Args {
     fn is_receive_mode(&self) -> bool {
-        if let SubCommand::Receive(_) = self.cmd {
-            true
-        } else {
-            false
-        }
+        matches!(self.cmd, SubCommand::Receive(_))
+    }
+
+    fn is_serve_text_mode(&self) -> bool {
+        matches!(self.cmd, SubCommand::ServeText(_))
     }
 }
 
@@ -60,196 +198,2382 @@ enum SubCommand {
     Receive(ReceiveArgs),
     /// Run as send client
     Send(SendArgs),
+    /// Download files offered by a device
+    Pull(PullArgs),
+    /// Offer a text to any device until stopped
+    ServeText(ServeTextArgs),
+    /// Check the network for common problems
+    Doctor(DoctorArgs),
+    /// Run in the background, taking commands from a local control socket
+    Daemon(DaemonArgs),
+    /// Inspect what is sent to other devices
+    #[command(subcommand)]
+    Debug(DebugCommand),
+    /// Print a shell completion script, e.g. `localsend completions bash >> ~/.bashrc`
+    Completions(CompletionsArgs),
+    /// Print the candidates of --to for the completion scripts, one per line
+    #[command(name = "__complete-targets", hide = true)]
+    CompleteTargets(CompleteTargetsArgs),
+    /// Loop sessions against an in-process receiver and report what leaks
+    #[command(hide = true)]
+    Stress(stress::StressArgs),
+    /// Replace this executable with the latest release
+    #[cfg(feature = "self-update")]
+    SelfUpdate(SelfUpdateArgs),
 }
 
-#[derive(Parser)]
+#[derive(clap::Subcommand)]
+enum DebugCommand {
+    /// Print the announcement broadcast to other devices
+    Announce,
+}
+
+#[derive(Parser, Clone)]
 struct ReceiveArgs {
-    /// File save destination path
-    #[arg(long = "dest", env = "LOCALSEND_DESTINATION", default_value = ".")]
+    /// File save destination path, may contain {alias}, {fingerprint}, {date}, {time} and {sessionId}
+    #[arg(long = "dest", env = "LOCALSEND_DESTINATION", default_value = ".", value_parser = parse_destination)]
     destination: PathBuf,
 
     /// Quickly save all files without asking
     #[arg(long = "quick-save")]
     quick_save: bool,
+
+    /// Accept text messages right away, e.g. clipboard texts, still asking about files
+    #[arg(long = "auto-accept-texts")]
+    auto_accept_texts: bool,
+
+    /// Save accepted files to --dest without asking where to save them
+    #[arg(long = "no-dest-prompt")]
+    no_dest_prompt: bool,
+
+    /// Create --dest when it does not exist instead of refusing to start
+    #[arg(long = "create-dest")]
+    create_dest: bool,
+
+    /// What to do when a file already exists: overwrite, rename or append. Append adds
+    /// complete bodies to the end of texts and files of unknown type, others are renamed
+    #[arg(long = "on-conflict", default_value = "overwrite")]
+    on_conflict: CollisionPolicy,
+
+    /// Append files of any type with --on-conflict append, e.g. two images
+    #[arg(long = "append-any-type")]
+    append_any_type: bool,
+
+    /// Save all received files into a single .tar or .zip archive
+    #[arg(long, value_parser = parse_archive)]
+    archive: Option<PathBuf>,
+
+    /// Also save text messages into the archive instead of printing them
+    #[arg(long = "archive-texts", requires = "archive")]
+    archive_texts: bool,
+
+    /// Only save names Windows accepts, e.g. when saving to a FAT or exFAT drive
+    #[arg(long = "portable-names")]
+    portable_names: bool,
+
+    /// Character replacing the ones the destination does not accept in file names
+    #[arg(long = "replace-char", value_name = "CHAR", default_value_t = '_', value_parser = parse_replace_char)]
+    replace_char: char,
+
+    /// Save files that may run when opened, e.g. .exe or .lnk on Windows, under the
+    /// name offered instead of appending .received
+    #[arg(long = "keep-dangerous-names")]
+    keep_dangerous_names: bool,
+
+    /// Whether names differing only in case collide, "sensitive" or "insensitive",
+    /// detected on the destination by default
+    #[arg(long = "name-case", value_name = "CASE")]
+    name_case: Option<CaseSensitivity>,
+
+    /// Drop a session when the sender stops uploading for this many seconds
+    #[arg(long = "session-timeout", value_name = "SECS", default_value_t = DEFAULT_SESSION_TIMEOUT.as_secs())]
+    session_timeout: u64,
+
+    /// Keep a JSON file describing the current transfer up to date, e.g. for status bars
+    #[arg(long = "status-file", value_name = "PATH")]
+    status_file: Option<PathBuf>,
+
+    /// Let a sender add files to its running session, like some official app flows do
+    #[arg(long = "allow-extend")]
+    allow_extend: bool,
+
+    /// Let up to this many senders wait while a session runs instead of turning them
+    /// away, they are received in the order they arrived
+    #[arg(long = "queue", value_name = "N", default_value_t = 0)]
+    queue: usize,
+
+    /// Turn a waiting sender away after this many seconds
+    #[arg(long = "queue-wait", value_name = "SECS", default_value_t = DEFAULT_PENDING_SESSION_WAIT.as_secs())]
+    queue_wait: u64,
+
+    /// Run this shell command for every saved file, with LS_FILE_PATH, LS_FILE_NAME,
+    /// LS_FILE_TYPE, LS_SENDER_ALIAS and LS_SESSION_ID set
+    #[arg(long = "on-receive", value_name = "COMMAND")]
+    on_receive: Option<String>,
+
+    /// Kill the --on-receive command after this many seconds
+    #[arg(long = "on-receive-timeout", value_name = "SECS", default_value_t = DEFAULT_HOOK_TIMEOUT.as_secs(), requires = "on_receive")]
+    on_receive_timeout: u64,
+
+    /// Write <name>.localsend-complete with size, digest, sender and time next to every
+    /// saved file once it is complete, for tools watching the destination
+    #[arg(long = "completion-marker")]
+    completion_marker: bool,
+
+    /// Remove markers older than this on startup, e.g. 12h or 7d
+    #[arg(long = "completion-marker-max-age", value_name = "AGE", default_value = "7d", value_parser = parse_age, requires = "completion_marker")]
+    completion_marker_max_age: Duration,
+
+    /// Write one JSON line per saved file to this named pipe, dropped while nothing reads it
+    #[arg(long = "completion-fifo", value_name = "PATH")]
+    completion_fifo: Option<PathBuf>,
+
+    /// Let only this many uploads write at the same time, the others wait
+    #[arg(long = "max-concurrent-uploads", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_concurrent_uploads: Option<u32>,
+
+    /// Receive at most this many bytes per second, e.g. 500K or 10M, for all
+    /// files of a session together; senders are slowed down to match. `send`
+    /// uploads at most this rate as well, whatever --pace measures
+    #[arg(long = "limit-rate", value_name = "RATE", value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+
+    /// Receive files up to --preview-max-size into this directory without asking,
+    /// then keep or discard them once they can be opened
+    #[arg(long = "preview-dir", value_name = "PATH", conflicts_with_all = ["quick_save", "archive"])]
+    preview_dir: Option<PathBuf>,
+
+    /// Largest file received for a preview, e.g. 500K or 5M
+    #[arg(long = "preview-max-size", value_name = "SIZE", default_value = "5M", value_parser = parse_size, requires = "preview_dir")]
+    preview_max_size: u64,
+
+    /// Do not receive files again that were received before with the same digest,
+    /// files sent without a digest are always received
+    #[arg(long, conflicts_with = "archive")]
+    dedup: bool,
+
+    /// What to do with a file received before: skip, link or copy it to the offered name
+    #[arg(
+        long = "dedup-action",
+        value_name = "ACTION",
+        default_value = "skip",
+        requires = "dedup"
+    )]
+    dedup_action: DedupAction,
+
+    /// Receive as usual but save nothing, every file is counted, hashed and logged,
+    /// e.g. to observe senders before trusting --quick-save or to measure throughput
+    #[arg(long, conflicts_with_all = ["archive", "preview_dir", "dedup"])]
+    audit: bool,
+
+    /// Append what --audit received to this file, one JSON object per file
+    #[arg(long = "audit-log", value_name = "PATH", requires = "audit")]
+    audit_log: Option<PathBuf>,
+
+    /// Refuse files nested deeper than this many directories
+    #[arg(long = "max-depth", value_name = "N", default_value_t = DEFAULT_MAX_PATH_DEPTH)]
+    max_depth: usize,
+
+    /// Refuse files creating more than this many directories in a session
+    #[arg(long = "max-dirs", value_name = "N", default_value_t = DEFAULT_MAX_DIRECTORIES)]
+    max_dirs: usize,
+
+    /// Refuse files beyond this many in a session
+    #[arg(long = "max-files", value_name = "N", default_value_t = DEFAULT_MAX_FILES)]
+    max_files: usize,
+
+    /// Refuse the whole offer when a file breaks --max-depth, --max-dirs or --max-files
+    #[arg(long)]
+    strict: bool,
+
+    /// Remove what receivers that crashed left half written, then exit. Receivers
+    /// also clean up after themselves when they start on the same port again
+    #[arg(long)]
+    cleanup: bool,
+
+    /// Receive a single session, then exit with a code telling how it went: 0 received,
+    /// 1 failed or cancelled, 3 declined, 4 nothing offered within --wait-timeout
+    #[arg(long)]
+    once: bool,
+
+    /// Give up waiting for an offer with --once after this many seconds
+    #[arg(long = "wait-timeout", value_name = "SECS", requires = "once")]
+    wait_timeout: Option<u64>,
+}
+
+fn parse_device_model(s: &str) -> std::result::Result<String, String> {
+    let model = s.trim();
+    if model.is_empty() {
+        return Err("device model must not be empty".to_owned());
+    }
+    if model.chars().count() > 64 {
+        return Err("device model must not be longer than 64 characters".to_owned());
+    }
+    Ok(model.to_owned())
+}
+
+fn parse_alias(s: &str) -> std::result::Result<String, String> {
+    let alias = s.trim();
+    if alias.is_empty() {
+        return Err("alias must not be empty".to_owned());
+    }
+    Ok(alias.to_owned())
+}
+
+/// Reads a size in bytes with an optional K, M or G suffix (powers of 1024).
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor: u64 = match s[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        unit => return Err(format!("unknown size unit: {}", unit)),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(factor))
+        .ok_or_else(|| format!("invalid size: {}", s))
+}
+
+fn parse_rate(s: &str) -> std::result::Result<u64, String> {
+    match parse_size(s)? {
+        0 => Err("rate must be greater than 0".to_owned()),
+        rate => Ok(rate),
+    }
+}
+
+fn parse_window(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let (digits, millis) = match (s.strip_suffix("ms"), s.strip_suffix('s')) {
+        (Some(digits), _) => (digits, 1),
+        (None, Some(digits)) => (digits, 1000),
+        (None, None) => (s, 1000),
+    };
+    match digits.trim().parse::<u64>() {
+        Ok(0) => Err("window must be greater than 0".to_owned()),
+        Ok(n) => Ok(Duration::from_millis(n.saturating_mul(millis))),
+        Err(_) => Err(format!("invalid duration: {}, e.g. 2s or 500ms", s)),
+    }
+}
+
+fn parse_age(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let (digits, secs) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    match digits.trim().parse::<u64>() {
+        Ok(n) => Ok(Duration::from_secs(n.saturating_mul(secs))),
+        Err(_) => Err(format!("invalid age: {}, e.g. 30m, 12h or 7d", s)),
+    }
+}
+
+fn parse_replace_char(s: &str) -> std::result::Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) if ch != '/' && !NameRules::Windows.is_invalid(ch) => Ok(ch),
+        (Some(_), None) => Err(format!("{:?} is not allowed in file names", s)),
+        _ => Err("expected a single character".to_owned()),
+    }
+}
+
+fn parse_destination(s: &str) -> std::result::Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    validate_destination(&path)?;
+    Ok(path)
+}
+
+fn parse_archive(s: &str) -> std::result::Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    match ArchiveFormat::from_path(&path) {
+        Some(_) => Ok(path),
+        None => Err("archive name must end with .tar or .zip".to_owned()),
+    }
+}
+
+fn parse_pull_conflict(s: &str) -> std::result::Result<CollisionPolicy, String> {
+    match s.parse()? {
+        CollisionPolicy::Append => Err("append only applies to receiving".to_owned()),
+        policy => Ok(policy),
+    }
+}
+
+#[derive(Parser)]
+struct DaemonArgs {
+    #[command(flatten)]
+    receive: ReceiveArgs,
+
+    /// Socket to take commands from, a named pipe name on Windows
+    #[arg(
+        long = "control-socket",
+        env = "LOCALSEND_CONTROL_SOCKET",
+        value_name = "PATH"
+    )]
+    control_socket: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct PullArgs {
+    /// Alias of the device to download from, select interactively by default
+    device: Option<String>,
+
+    /// File save destination path
+    #[arg(long = "dest", env = "LOCALSEND_DESTINATION", default_value = ".")]
+    destination: PathBuf,
+
+    /// What to do when a file already exists: overwrite, rename
+    #[arg(long = "on-conflict", default_value = "overwrite", value_parser = parse_pull_conflict)]
+    on_conflict: CollisionPolicy,
+}
+
+#[cfg(feature = "self-update")]
+#[derive(Parser)]
+struct SelfUpdateArgs {
+    /// Only report whether a newer release exists
+    #[arg(long)]
+    check: bool,
+
+    /// Do not ask before replacing the executable
+    #[arg(short, long, conflicts_with = "check")]
+    yes: bool,
+}
+
+#[derive(Parser)]
+struct DoctorArgs {
+    /// Address of a device that can not be reached, e.g. 192.168.1.20 or 192.168.1.20:53318
+    #[arg(long, value_parser = parse_peer)]
+    peer: Option<SocketAddr>,
+
+    /// Print the results as JSON, e.g. for bug reports
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Parser)]
+struct CompletionsArgs {
+    shell: Shell,
+}
+
+#[derive(Parser)]
+struct CompleteTargetsArgs {
+    /// Print the fingerprints instead, for --to-fingerprint
+    #[arg(long)]
+    fingerprints: bool,
+}
+
+fn parse_peer(s: &str) -> std::result::Result<SocketAddr, String> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, DEFAULT_HTTP_PORT)),
+        Err(_) => Err("expected an ip address with an optional port".to_owned()),
+    }
+}
+
+#[derive(Parser)]
+struct ServeTextArgs {
+    /// Text offered to other devices
+    text: String,
+
+    /// Do not show a QR code of the link for browsers
+    #[arg(long = "no-qr")]
+    no_qr: bool,
 }
 
 #[derive(Parser)]
 struct SendArgs {
     /// Text or file path to be sent
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["from_file", "batch"])]
     input: Vec<String>,
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
-        .env()
-        .init()
-        .expect("Failed to init logger");
+    /// Send the files listed in a file, one path per line, `-` reads stdin.
+    /// A tab separates a path from the name the receiver sees
+    #[arg(long = "from-file", value_name = "FILE")]
+    from_file: Option<PathBuf>,
 
-    let args: Args = Args::parse();
+    /// Run the sends of a TOML file one after another, each `[[job]]` has `files` and
+    /// one of `to`, `to-fingerprint` and `to-ip`. Paths are relative to the file
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input", "from_file", "daemon", "bidirectional"]
+    )]
+    batch: Option<PathBuf>,
 
-    let local_addr = device::local_addr()?;
-    log::debug!("local_addr: {:?}", local_addr);
+    /// Stop the batch at the first job that fails, the others run anyway by default
+    #[arg(long = "fail-fast", requires = "batch")]
+    fail_fast: bool,
 
-    let device = Device {
-        ip: local_addr.ip().to_string(),
-        alias: args.alias.clone().unwrap_or(device::alias()),
-        fingerprint: device::fingerprint(),
-        version: PROTOCOL_VERSION_2.to_string(),
-        device_model: Some(device::device_model()),
-        device_type: localsend_proto::DeviceType::Headless,
-        download: false,
-        https: false,
-        port: args.http_port,
-    };
+    /// Send inputs that look like paths but do not exist as texts without asking
+    #[arg(short, long)]
+    yes: bool,
 
-    let (server_tx, mut server_rx) = tokio::sync::mpsc::channel(1);
-    let (client_tx, client_rx) = tokio::sync::mpsc::channel(1);
-    let mut state = ServerState::new(server_tx, client_rx);
-    {
-        let mut settings = Settings::default();
-        if let SubCommand::Receive(args) = &args.cmd {
-            settings.destination = args.destination.clone();
-            settings.quick_save = args.quick_save;
-        };
-        state.settings = settings;
-    }
-    let shared_state = Arc::new(tokio::sync::Mutex::new(state));
-    let server_state = shared_state.clone();
-    tokio::spawn(async move {
-        start_api_server(args.http_port, server_state)
-            .await
-            .expect("Failed to start api server")
-    });
+    /// Do not skip .git, .svn, .DS_Store and Thumbs.db in directories
+    #[arg(long = "include-hidden")]
+    include_hidden: bool,
 
-    let mut send_files = SendingFiles::default();
+    /// Skip files ignored by .gitignore files in directories
+    #[arg(long = "respect-gitignore")]
+    respect_gitignore: bool,
 
-    if let SubCommand::Send(args) = &args.cmd {
-        for text in args.input.iter().unique().collect_vec() {
-            if let Ok(path) = std::fs::canonicalize(text) {
-                if path.is_file() {
-                    send_files.add_file(path, None)?;
-                    continue;
-                } else if path.is_dir() {
-                    send_files.add_dir(path)?;
-                    continue;
-                }
-            }
-            send_files.add_text(text, text.len() < 1024);
+    /// Skip files in directories matching the glob, can be repeated
+    #[arg(long = "exclude", value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// What to do with symlinks in directories: follow, skip, error
+    #[arg(long = "symlinks", default_value = "skip")]
+    symlinks: SymlinkPolicy,
+
+    /// Upload files matching the glob with a content type, e.g. '*.jsonl=application/x-ndjson',
+    /// can be repeated, the first match wins
+    #[arg(long = "mime", value_name = "GLOB=TYPE")]
+    mime: Vec<MimeOverride>,
+
+    /// Do not read files without a known extension to tell text from binary uploads
+    #[arg(long = "no-sniff")]
+    no_sniff: bool,
+
+    /// Alias of a device to send to, can be repeated, select interactively by default.
+    /// Case-insensitive, a trailing * matches any rest
+    #[arg(long = "to", value_name = "ALIAS")]
+    to: Vec<String>,
+
+    /// Fingerprint of a device to send to, can be repeated
+    #[arg(long = "to-fingerprint", value_name = "FINGERPRINT")]
+    to_fingerprint: Vec<String>,
+
+    /// Ip of a device to send to, can be repeated
+    #[arg(long = "to-ip", value_name = "IP")]
+    to_ip: Vec<IpAddr>,
+
+    /// Host name of a device to send to, with an optional port, e.g. mylaptop.local:53317;
+    /// can be repeated. The device is asked for its info instead of being discovered
+    #[arg(long = "to-host", value_name = "HOST[:PORT]")]
+    to_host: Vec<HostTarget>,
+
+    /// Try the IPv6 addresses of --to-host names before the IPv4 ones
+    #[arg(long = "prefer-ipv6", requires = "to_host")]
+    prefer_ipv6: bool,
+
+    /// Only list and match devices of this type, can be repeated
+    #[arg(long = "only-type", value_name = "TYPE")]
+    only_type: Vec<DeviceType>,
+
+    /// Only list and match devices whose alias contains this text, case-insensitive
+    #[arg(long = "alias-contains", value_name = "TEXT")]
+    alias_contains: Option<String>,
+
+    /// Send to all devices at the same time instead of one after another
+    #[arg(long = "parallel-targets")]
+    parallel_targets: bool,
+
+    /// Retry once after a short delay when a device is busy with another transfer
+    #[arg(long = "retry-busy")]
+    retry_busy: bool,
+
+    /// Offer at most this many files per session, larger sends are split into sessions
+    /// to the same device one after another
+    #[arg(
+        long = "chunk-size",
+        value_name = "N",
+        default_value_t = DEFAULT_CHUNK_FILES as u32,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    chunk_size: u32,
+
+    /// Alias shown to the receivers of this send only, the configured alias stays unchanged
+    #[arg(long = "alias-once", value_name = "ALIAS", value_parser = parse_alias, conflicts_with = "daemon")]
+    alias_once: Option<String>,
+
+    /// Note shown by localsend-rs receivers before accepting, other receivers get it
+    /// as the text file _localsend_note.txt
+    #[arg(long, value_name = "TEXT", conflicts_with = "daemon")]
+    note: Option<String>,
+
+    /// Order of the devices to choose from: alias, recent (last sent to first) or usage
+    /// (most transfers first). Devices never sent to come last
+    #[arg(long, value_name = "ORDER", default_value = "recent")]
+    sort: DeviceOrder,
+
+    /// Do not check that devices answer before sending, e.g. behind filters dropping the probe
+    #[arg(long = "no-precheck")]
+    no_precheck: bool,
+
+    /// Accept any certificate of devices reached over HTTPS instead of only the one
+    /// matching their fingerprint, for debugging
+    #[arg(long)]
+    insecure: bool,
+
+    /// Pace uploads: off, or adaptive to back off once they make the latency to the
+    /// receiver grow, leaving room for other traffic. --limit-rate caps either
+    #[arg(long, value_name = "MODE", default_value_t = Pace::Off, conflicts_with = "daemon")]
+    pace: Pace,
+
+    /// Hand the input to a running `localsend daemon` and return once it is queued
+    #[arg(long, conflicts_with_all = ["from_file", "parallel_targets", "retry_busy"])]
+    daemon: bool,
+
+    /// Control socket of the daemon
+    #[arg(
+        long = "control-socket",
+        env = "LOCALSEND_CONTROL_SOCKET",
+        value_name = "PATH",
+        requires = "daemon"
+    )]
+    control_socket: Option<PathBuf>,
+
+    /// Also receive while sending, offers are answered once the current prompt closes
+    /// unless --quick-save accepts them right away
+    #[arg(long, conflicts_with = "daemon")]
+    bidirectional: bool,
+
+    /// Merge the input of other invocations started within this time, e.g. "2s", into one
+    /// send: the first one sends, the others hand it their input and exit. For file
+    /// managers starting one process per selected file
+    #[arg(
+        long = "merge-window",
+        value_name = "DURATION",
+        value_parser = parse_window,
+        conflicts_with_all = ["batch", "daemon"]
+    )]
+    merge_window: Option<Duration>,
+
+    #[command(flatten, next_help_heading = "Receiving with --bidirectional")]
+    receive: Box<ReceiveArgs>,
+}
+
+impl SendArgs {
+    fn dir_filter(&self) -> DirFilter {
+        DirFilter {
+            include_hidden: self.include_hidden,
+            respect_gitignore: self.respect_gitignore,
+            exclude: self.exclude.clone(),
+            symlinks: self.symlinks,
         }
     }
 
-    let (running_tx, mut running_rx) = tokio::sync::mpsc::channel(1);
-    if let Ok(_) = ctrlc::set_handler(move || running_tx.blocking_send(false).unwrap()) {
-        let state = shared_state.clone();
-        tokio::spawn(async move {
-            running_rx.recv().await;
+    fn pacing(&self) -> Pacing {
+        Pacing {
+            limit: self.receive.limit_rate,
+            pace: self.pace,
+        }
+    }
 
-            let mut state = state.lock().await;
-            if let Some(session) = state.send_session.take() {
-                session
-                    .cancel_by_sender()
-                    .await
-                    .expect("Failed to cancel task");
-            }
-            std::process::exit(0)
-        });
+    fn content_types(&self) -> ContentTypes {
+        ContentTypes {
+            overrides: self.mime.clone(),
+            sniff: !self.no_sniff,
+        }
     }
 
-    let scanner =
-        MulticastDeviceScanner::new(&device, args.multiaddr, args.port, args.http_port).await?;
-    let scanner = Arc::new(scanner);
-    let mut ui = PromptUI::default();
-    ui.use_nerd_fonts = !args.no_nerd;
+    fn targets(&self) -> Vec<Target> {
+        let aliases = self.to.iter().cloned().map(Target::Alias);
+        let fingerprints = self.to_fingerprint.iter().cloned().map(Target::Fingerprint);
+        let ips = self.to_ip.iter().copied().map(Target::Ip);
+        aliases.chain(fingerprints).chain(ips).collect()
+    }
 
-    if args.is_receive_mode() {
-        let scanner = scanner.clone();
-        tokio::spawn(async move {
-            loop {
-                for ms in vec![100, 500, 2000] {
-                    scanner.send_announcement().await;
-                    tokio::time::sleep(Duration::from_millis(ms)).await;
-                }
-            }
+    /// Hides devices not matching `--only-type` and `--alias-contains` from `scanner`.
+    fn apply_filter(&self, scanner: &MulticastDeviceScanner) {
+        if self.only_type.is_empty() && self.alias_contains.is_none() {
+            return;
+        }
+        let types = self.only_type.clone();
+        let alias = self.alias_contains.as_deref().map(str::to_lowercase);
+        scanner.set_filter(move |device| {
+            (types.is_empty() || types.contains(&device.device_type))
+                && alias
+                    .as_deref()
+                    .map_or(true, |alias| device.alias.to_lowercase().contains(alias))
         });
+    }
+}
 
-        if let SubCommand::Receive(args) = args.cmd {
-            if args.quick_save {
-                std::future::pending::<()>().await
-            }
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args: Args = Args::parse();
+
+    // printed before the logger exists, the output is read by the shell
+    match &args.cmd {
+        SubCommand::Completions(completions_args) => {
+            print!(
+                "{}",
+                completions::generate(completions_args.shell, Args::command())
+            );
+            return Ok(());
         }
+        SubCommand::CompleteTargets(targets_args) => {
+            complete_targets(&args, targets_args.fingerprints);
+            return Ok(());
+        }
+        _ => {}
+    }
 
-        let message = ui
-            .show_loading("Waiting".to_string(), async move { server_rx.recv().await })
-            .await;
-        match message {
-            Some(ServerMessage::SelectedFiles(files)) => {
-                let (progress_tx, mut progress_rx) =
-                    tokio::sync::mpsc::channel::<UploadProgress>(100);
-
-                let files = match ui.select_files(files) {
-                    Some(files) => files,
-                    None => {
-                        client_tx.send(ClientMessage::Declined).await.unwrap();
-                        return Ok(());
-                    }
-                };
-                let pb_files = files
-                    .iter()
-                    .map(|file| (file.id.clone(), file.clone()))
-                    .collect();
+    let level = if args.verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    };
+    SimpleLogger::new()
+        .with_level(level)
+        .env()
+        .init()
+        .expect("Failed to init logger");
+    if let Some(mode) = args.trace_http {
+        trace_http(mode);
+    }
 
-                client_tx
-                    .send(ClientMessage::FilesSelected(progress_tx, files))
-                    .await
-                    .unwrap();
+    #[cfg(feature = "self-update")]
+    if let SubCommand::SelfUpdate(update_args) = &args.cmd {
+        self_update(update_args).await;
+        return Ok(());
+    }
 
-                let mut pb = FileProgressBar::new(pb_files, !args.no_nerd);
-                while let Some(progress) = progress_rx.recv().await {
-                    pb.update(progress);
-                }
-            }
-            _ => return Ok(()),
+    if args.stealth {
+        if let Some(mode) = stealth_conflict(&args.cmd) {
+            log::error!(
+                "--stealth cannot be used {}, receiving needs this device to be discoverable",
+                mode
+            );
+            std::process::exit(1)
         }
-
-        std::process::exit(0)
+        log::info!("Stealth: only devices announcing themselves are found, use --to-host or [[devices]] for others");
     }
 
-    let run = || async {
-        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::channel::<UploadProgress>(100);
-        let mut pb = FileProgressBar::new(send_files.to_dto_map(), !args.no_nerd);
-        tokio::spawn(async move {
-            while let Some(progress) = progress_rx.recv().await {
-                pb.update(progress);
+    let local_addr = device::local_addr()?;
+    log::debug!("local_addr: {:?}", local_addr);
+
+    let ip = match args.advertise_ip {
+        Some(ip) => {
+            if !device::is_local_ip(ip) {
+                log::warn!(
+                    "Advertised ip {} is not assigned to any local interface",
+                    ip
+                );
             }
-        });
+            ip
+        }
+        None => local_addr.ip(),
+    };
 
-        ui.print_files(&send_files);
+    if let SubCommand::Doctor(doctor_args) = &args.cmd {
+        let options = DiagnosticsOptions {
+            multiaddr: args.multiaddr,
+            port: args.port,
+            http_port: args.http_port,
+            local_ip: ip,
+            peer: doctor_args.peer,
+        };
+        let ui = PromptUI {
+            theme: load_theme(&args)?,
+            ..Default::default()
+        };
+        let results = ui
+            .show_loading("Checking".to_owned(), async move {
+                run_diagnostics(&options).await
+            })
+            .await;
+        if doctor_args.json {
+            let json = serde_json::to_string_pretty(&results).map_err(std::io::Error::from)?;
+            println!("{}", json);
+        } else {
+            ui.print_diagnostics(&results);
+        }
+        return Ok(());
+    }
 
-        let target = ui.select_device(&scanner).await?;
-        let session = SendSession::new(&device, target, &send_files);
+    if let SubCommand::Stress(stress_args) = &args.cmd {
+        let report = stress::run_stress(stress_args).await?;
+        report.print();
+        if !report.passed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-        session
-            .upload(shared_state.clone(), progress_tx.clone())
-            .await?;
-        localsend_lib::Result::<()>::Ok(())
-    };
+    if let SubCommand::Debug(DebugCommand::Announce) = &args.cmd {
+        let device = local_device(&args, ip, args.http_port);
+        let payload = announcement(&device, args.announce_limit)?;
+        println!("{}", payload);
+        eprintln!("{} of at most {} bytes", payload.len(), args.announce_limit);
+        return Ok(());
+    }
 
-    loop {
-        match run().await {
-            Ok(_) => {}
-            Err(localsend_lib::Error::Send(SendError::NothingSelected)) => {}
-            Err(e) => {
-                ui.print_error(&e);
+    if let SubCommand::Receive(receive_args) = &args.cmd {
+        if receive_args.cleanup {
+            clean_up_sessions();
+            return Ok(());
+        }
+    }
+
+    if let SubCommand::Send(send_args) = &mut args.cmd {
+        if let Some(window) = send_args.merge_window {
+            if !merge_with_others(send_args, window).await {
+                return Ok(());
             }
         }
-        println!();
-        if !ui.ask_continue() {
-            break;
+    }
+
+    if let SubCommand::Send(send_args) = &args.cmd {
+        if send_args.daemon {
+            return send_through_daemon(send_args).await;
         }
     }
 
+    let mut ui = PromptUI {
+        theme: load_theme(&args)?,
+        ..Default::default()
+    };
+    if let SubCommand::Send(send_args) = &args.cmd {
+        ui.device_order = send_args.sort;
+        if let Some(history) = transfer_history() {
+            ui.peer_stats = Arc::new(history.stats_by_peer().clone());
+        }
+    }
+    first_run_check(&ui, &mut args);
+    #[cfg(feature = "self-update")]
+    if matches!(args.cmd, SubCommand::Send(_) | SubCommand::Receive(_))
+        && std::io::stdin().is_terminal()
+    {
+        tokio::spawn(notify_update());
+    }
+
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| config_dir().map(|dir| dir.join(CONFIG_FILE)));
+    let config = match &config_path {
+        Some(path) => read_config(path).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            std::process::exit(1)
+        }),
+        None => Config::default(),
+    };
+    let preview_policy = config.preview.clone();
+
+    let (server_tx, mut server_rx) = tokio::sync::mpsc::channel(1);
+    let (client_tx, client_rx) = tokio::sync::mpsc::channel(1);
+    let mut state = ServerState::new(server_tx, client_rx);
+    state.stealth = args.stealth;
+    // subscribed before the server takes the session to follow
+    let once = match &args.cmd {
+        SubCommand::Receive(args) if args.once => {
+            state.once = true;
+            Some((
+                state.events.subscribe(),
+                args.wait_timeout.map(Duration::from_secs),
+            ))
+        }
+        _ => None,
+    };
+    let receive_args = match &args.cmd {
+        SubCommand::Receive(args) | SubCommand::Daemon(DaemonArgs { receive: args, .. }) => {
+            Some(args)
+        }
+        SubCommand::Send(args) if args.bidirectional => Some(args.receive.as_ref()),
+        _ => None,
+    };
+    if let Some(args) = receive_args {
+        state.settings = receive_settings(args, &config).unwrap_or_else(|e| {
+            log::error!("Invalid config: {}", e);
+            std::process::exit(1)
+        });
+        if let Err(e) = check_receive_destination(&state.settings, args.create_dest) {
+            log::error!("{}", e);
+            std::process::exit(1)
+        }
+        if args.completion_marker {
+            let root = destination_root(&state.settings.destination);
+            let removed = sweep_markers(&root, args.completion_marker_max_age).await;
+            if removed > 0 {
+                log::info!(
+                    "Removed {} old completion markers below {:?}",
+                    removed,
+                    root
+                );
+            }
+        }
+    }
+    let shared_state = Arc::new(tokio::sync::Mutex::new(state));
+
+    // the first Ctrl-C stops everything in order, a second one exits right away
+    let cancel = CancellationToken::new();
+    {
+        let cancel = cancel.clone();
+        if let Err(e) = ctrlc::set_handler(move || {
+            if cancel.is_cancelled() {
+                std::process::exit(130)
+            }
+            cancel.cancel();
+        }) {
+            log::warn!("Failed to set the Ctrl-C handler: {}", e);
+        }
+    }
+
+    let server = match start_api_server(args.http_port, shared_state.clone(), &cancel).await {
+        Ok(server) => server,
+        Err(ServerError::AddrInUse(addr)) => {
+            log::error!(
+                "Port {} is already in use, is another localsend running? Choose another one with --http-port",
+                addr.port()
+            );
+            std::process::exit(1)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // announced ports must be live before the first announcement
+    server.ready().await;
+
+    let device = local_device(&args, ip, server.local_addr().port());
+
+    // announcements and offers of this send only, the receive server keeps the alias
+    let sender = match &args.cmd {
+        SubCommand::Send(SendArgs {
+            alias_once: Some(alias),
+            ..
+        }) => {
+            if alias.chars().count() > MAX_ALIAS_LEN {
+                log::warn!("--alias-once is cut to {} characters", MAX_ALIAS_LEN);
+            }
+            with_alias(&device, alias)
+        }
+        _ => device.clone(),
+    };
+
+    let mut send_files = SendingFiles::default().with_preview_policy(preview_policy.clone());
+    let mut filter_report = FilterReport::default();
+    let mut jobs = vec![];
+
+    if let SubCommand::Send(args) = &args.cmd {
+        send_files.content_types = args.content_types();
+        match add_inputs(&mut send_files, &args.input, &args.dir_filter()) {
+            Ok(report) => filter_report = report,
+            Err(e @ localsend_lib::Error::Send(SendError::BrokenSymlink(_))) => {
+                log::error!("{}", e);
+                std::process::exit(1)
+            }
+            Err(e) => return Err(e),
+        }
+        // most likely a mistyped path
+        let path_like: Vec<&str> = send_files
+            .texts()
+            .filter_map(|file| file.text())
+            .filter(|text| looks_like_path(text))
+            .collect();
+        if !args.yes && !path_like.is_empty() && !ui.ask_send_as_text(&path_like) {
+            log::error!("Nothing sent, --yes sends such inputs as texts");
+            std::process::exit(1)
+        }
+        if let Some(path) = &args.from_file {
+            let entries = if path.as_os_str() == "-" {
+                read_manifest(std::io::stdin().lock())?
+            } else {
+                read_manifest(std::io::BufReader::new(std::fs::File::open(path)?))?
+            };
+            if let Err(e) = send_files.add_manifest(&entries) {
+                log::error!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        if let Some(note) = &args.note {
+            send_files.add_note(note);
+        }
+        if let Some(path) = &args.batch {
+            jobs = read_jobs(path).unwrap_or_else(|e| {
+                log::error!("{}", e);
+                std::process::exit(1)
+            });
+        }
+    }
+
+    let scanner = MulticastDeviceScanner::new(
+        &sender,
+        args.multiaddr,
+        args.port,
+        args.announce_port.unwrap_or(args.port),
+    )
+    .await?
+    .with_announce_limit(args.announce_limit)?
+    .with_roles(if args.stealth {
+        ScannerRoles::listen_only()
+    } else {
+        ScannerRoles::default()
+    })
+    .with_scan_options(ScanOptions {
+        settle: (args.scan_settle_ms > 0).then(|| Duration::from_millis(args.scan_settle_ms)),
+        ..ScanOptions::default()
+    });
+    let static_devices = match &config_path {
+        Some(path) => load_static_devices(path).unwrap_or_else(|errors| {
+            for e in errors {
+                log::error!("{}", e);
+            }
+            std::process::exit(1)
+        }),
+        None => StaticDeviceProvider::default(),
+    };
+    if args.discovery == Discovery::Static && static_devices.is_empty() {
+        log::warn!("Static discovery without any [[devices]] in the config file");
+    }
+    if args.probe_static && args.discovery.static_devices() {
+        static_devices.probe().await;
+    }
+    // long running processes pick up changes of the static devices
+    if let (Some(path), SubCommand::Daemon(_) | SubCommand::Receive(_)) = (&config_path, &args.cmd)
+    {
+        watch_config(path.clone(), static_devices.clone());
+    }
+    let scanner = scanner.with_static_devices(static_devices, args.discovery);
+    if let SubCommand::Send(send_args) = &args.cmd {
+        send_args.apply_filter(&scanner);
+    }
+    let scanner = Arc::new(scanner);
+    // an advertised ip is chosen by the user, it does not follow the network
+    if args.advertise_ip.is_none() {
+        spawn_network_watcher(shared_state.clone(), scanner.clone(), ip);
+    }
+    let scanner: Arc<dyn DeviceScanner> = scanner;
+    if let SubCommand::Daemon(daemon_args) = &args.cmd {
+        let path = daemon_args
+            .control_socket
+            .clone()
+            .unwrap_or_else(default_control_path);
+        let devices = KnownDevices::track(scanner.clone());
+        let mut service = ControlService::new(device, shared_state.clone(), devices).await;
+        if let Some(config_path) = &config_path {
+            let loader = settings_loader(&daemon_args.receive, config_path.clone(), true);
+            reload_on_hangup(shared_state.clone(), loader.clone());
+            service = service.with_settings_loader(loader);
+        }
+        if let Err(e) = start_control_server(&path, Arc::new(service), &cancel).await {
+            log::error!("Failed to listen on {:?}: {}", path, e);
+            std::process::exit(1)
+        }
+        log::info!("Taking commands on {:?}", path);
+        print_sessions(&ui, server_rx, &shared_state, &cancel).await;
+        return Ok(server.wait().await?);
+    }
+
+    let progress = progress_options(&args, &ui.theme);
+    if let SubCommand::Pull(pull_args) = &args.cmd {
+        let dangerous = config.dangerous_extensions();
+        return pull(&ui, &scanner, pull_args, dangerous, progress, &cancel).await;
+    }
+
+    if let SubCommand::ServeText(serve_args) = &args.cmd {
+        spawn_announcements(&scanner);
+        serve_text(&ui, &device, &shared_state, server_rx, serve_args, &cancel).await?;
+        return Ok(server.shutdown().await?);
+    }
+
+    if args.is_receive_mode() {
+        spawn_announcements(&scanner);
+        let once = once.map(|(events, wait)| {
+            let cancel = cancel.clone();
+            tokio::spawn(async move {
+                let outcome = watch_once(events, wait, cancel.clone()).await;
+                if matches!(outcome, OnceOutcome::TimedOut) {
+                    log::warn!(
+                        "Nothing was offered within {}s",
+                        wait.unwrap_or_default().as_secs()
+                    );
+                    // stops waiting for the offer
+                    cancel.cancel();
+                }
+                outcome
+            })
+        });
+
+        if let SubCommand::Receive(args) = &args.cmd {
+            // the [receive] table of the config may turn quick save on too
+            if shared_state.lock().await.settings.quick_save {
+                if let Some(config_path) = &config_path {
+                    let loader = settings_loader(args, config_path.clone(), false);
+                    reload_on_hangup(shared_state.clone(), loader);
+                }
+                if let Some(once) = once {
+                    let outcome = print_once(&ui, server_rx, once).await;
+                    if let Some(report) = outcome.report() {
+                        ui.print_receive_report(report);
+                    }
+                    return exit_once(server, Some(outcome)).await;
+                }
+                print_sessions(&ui, server_rx, &shared_state, &cancel).await;
+                // running uploads remove their partial files before the server stops
+                return Ok(server.wait().await?);
+            }
+        }
+
+        let waiting = {
+            let cancel = cancel.clone();
+            ui.show_loading("Waiting".to_string(), async move {
+                loop {
+                    let message = tokio::select! {
+                        message = server_rx.recv() => message,
+                        _ = cancel.cancelled() => None,
+                    };
+                    match message {
+                        // nothing runs yet, keep waiting on the new network
+                        Some(ServerMessage::NetworkChanged(ip)) => {
+                            log::info!("Network changed, now reachable at {}", ip)
+                        }
+                        message => return (message, server_rx),
+                    }
+                }
+            })
+        };
+        let (message, mut server_rx) = waiting.await;
+        if let Some(ServerMessage::ReviewFiles(files)) = &message {
+            // every file was small enough for a preview, nothing was asked before
+            client_tx.send(review_answer(&ui, files.clone())).await.ok();
+            tokio::select! {
+                Some(ServerMessage::SessionFinished(report)) = server_rx.recv() => {
+                    ui.print_receive_report(&report)
+                }
+                _ = cancel.cancelled() => {}
+            }
+        }
+        if let Some(ServerMessage::SelectedFiles(files)) = message {
+            let (progress_tx, mut progress_rx) = ProgressStream::channel();
+
+            let files = match ui.select_files(files) {
+                Some(files) => files,
+                None => {
+                    client_tx.send(ClientMessage::Declined).await.unwrap();
+                    return exit_once(server, await_once(once).await).await;
+                }
+            };
+            let pb_files = sorted_by_name(&files);
+
+            let destination = match &args.cmd {
+                SubCommand::Receive(args) => ask_destination(&ui, args),
+                _ => None,
+            };
+
+            // the session keeps the sender, files added later report through it too
+            let progress_weak = progress_tx.downgrade();
+            client_tx
+                .send(ClientMessage::FilesSelected(
+                    progress_tx,
+                    files,
+                    destination,
+                ))
+                .await
+                .unwrap();
+
+            let mut pb = FileProgressBar::new(pb_files, progress);
+            let mut receiving = true;
+            loop {
+                tokio::select! {
+                    progress = progress_rx.recv(), if receiving => match progress {
+                        Some(progress) => pb.update(progress),
+                        None => receiving = false,
+                    },
+                    message = server_rx.recv() => match message {
+                        Some(ServerMessage::TextReceived(text)) => ui.print_text(&text),
+                        Some(ServerMessage::SessionFinished(report)) => {
+                            ui.print_receive_report(&report);
+                            break;
+                        }
+                        Some(ServerMessage::SessionExpired(_)) => {
+                            pb.clear();
+                            log::warn!("Sender stopped responding, session dropped");
+                            break;
+                        }
+                        Some(ServerMessage::SelectionTimedOut(_)) => {
+                            pb.clear();
+                            log::warn!("Files were not selected in time, session dropped");
+                            break;
+                        }
+                        Some(ServerMessage::NetworkChanged(ip)) => {
+                            pb.clear();
+                            log::warn!("Network changed to {}, session dropped", ip);
+                            break;
+                        }
+                        Some(ServerMessage::SelectedFiles(files)) => {
+                            let selection = ui.select_files(files).filter(|f| !f.is_empty());
+                            let message = match (selection, progress_weak.upgrade()) {
+                                (Some(files), Some(progress_tx)) => {
+                                    pb.add_files(&sorted_by_name(&files));
+                                    // added to the running session, saved where it saves
+                                    ClientMessage::FilesSelected(progress_tx, files, None)
+                                }
+                                _ => ClientMessage::Declined,
+                            };
+                            client_tx.send(message).await.ok();
+                        }
+                        Some(ServerMessage::ReviewFiles(files)) => {
+                            pb.clear();
+                            client_tx.send(review_answer(&ui, files)).await.ok();
+                        }
+                        Some(_) => {}
+                        None => break,
+                    },
+                    // cancelled sessions do not send a report
+                    _ = tokio::time::sleep(Duration::from_secs(1)), if !receiving => break,
+                    _ = cancel.cancelled() => {
+                        pb.clear();
+                        break;
+                    }
+                }
+            }
+        }
+
+        return exit_once(server, await_once(once).await).await;
+    }
+
+    let SubCommand::Send(send_args) = &args.cmd else {
+        unreachable!()
+    };
+
+    if !jobs.is_empty() {
+        let count = jobs.len();
+        let mut reports: Vec<JobReport> = vec![];
+        let mut jobs = jobs.into_iter();
+        for (index, job) in jobs.by_ref().enumerate() {
+            println!(
+                "Job {}/{}: {} to {}",
+                index + 1,
+                count,
+                job.inputs.join(", "),
+                job.target
+            );
+            let report =
+                match prepare_job(&ui, &scanner, send_args, &job, &preview_policy, &cancel).await {
+                    Ok((files, device)) => {
+                        ui.print_files(&files);
+                        let targets = vec![device];
+                        let mut results = send(
+                            &ui,
+                            &sender,
+                            targets,
+                            &files,
+                            &shared_state,
+                            send_args,
+                            progress,
+                            &cancel,
+                        )
+                        .await;
+                        ui.print_send_summary(&results);
+                        let (device, result) = results.remove(0);
+                        JobReport {
+                            job,
+                            device: Some(device),
+                            result: Some(result),
+                        }
+                    }
+                    Err(e) => {
+                        ui.print_error(&e);
+                        JobReport {
+                            job,
+                            device: None,
+                            result: Some(Err(e)),
+                        }
+                    }
+                };
+            println!();
+            let failed = !report.succeeded();
+            reports.push(report);
+            if cancel.is_cancelled() || (failed && send_args.fail_fast) {
+                break;
+            }
+        }
+        // not run after --fail-fast or Ctrl-C
+        reports.extend(jobs.map(|job| JobReport {
+            job,
+            device: None,
+            result: None,
+        }));
+        ui.print_batch_summary(&reports);
+        server.shutdown().await?;
+        if !reports.iter().all(JobReport::succeeded) {
+            std::process::exit(1)
+        }
+        return Ok(());
+    }
+
+    // offers wait here while a prompt is open, the others are printed right away
+    let mut offers = send_args.bidirectional.then(|| {
+        spawn_announcements(&scanner);
+        spawn_incoming(&ui, server_rx)
+    });
+
+    let target_args = send_args.targets();
+    let mut targets: Option<Vec<Device>> = None;
+    loop {
+        if let Some(offers) = &mut offers {
+            answer_offers(&ui, offers, &client_tx, &send_args.receive).await;
+        }
+        ui.print_files(&send_files);
+        if filter_report.total() > 0 || !filter_report.symlinks.is_empty() {
+            ui.print_filter_report(&filter_report);
+        }
+
+        let selected = match targets.take() {
+            Some(targets) => Ok(targets),
+            None if target_args.is_empty() && send_args.to_host.is_empty() => {
+                ui.select_devices(&scanner).await
+            }
+            None => find_targets(&ui, &scanner, send_args, &target_args, &cancel).await,
+        };
+        let selected = match selected {
+            Ok(devices) if !send_args.no_precheck => precheck(&ui, &scanner, devices).await,
+            selected => selected,
+        };
+        let results = match selected {
+            Ok(selected) => {
+                send(
+                    &ui,
+                    &sender,
+                    selected,
+                    &send_files,
+                    &shared_state,
+                    send_args,
+                    progress,
+                    &cancel,
+                )
+                .await
+            }
+            Err(_) if cancel.is_cancelled() => vec![],
+            // back to the picker, the device is gone from it
+            Err(e @ localsend_lib::Error::Send(SendError::TargetUnreachable { .. }))
+                if target_args.is_empty() && send_args.to_host.is_empty() =>
+            {
+                ui.print_error(&e);
+                continue;
+            }
+            Err(e) => {
+                ui.print_error(&e);
+                vec![]
+            }
+        };
+
+        if cancel.is_cancelled() {
+            if results.len() > 1 {
+                ui.print_send_summary(&results);
+            }
+            break;
+        }
+        match results.as_slice() {
+            [(_, Err(localsend_lib::Error::Send(SendError::NothingSelected)))] => {}
+            [(target, Err(e))] => {
+                ui.print_error(e);
+                println!();
+                match ui.after_failure(e, &send_files) {
+                    NextAction::Retry => targets = Some(vec![target.clone()]),
+                    NextAction::OtherDevice => {}
+                    NextAction::EditFiles(file_ids) => {
+                        send_files.retain(&file_ids);
+                        targets = Some(vec![target.clone()]);
+                    }
+                    NextAction::Quit => break,
+                }
+                continue;
+            }
+            [_, _, ..] => ui.print_send_summary(&results),
+            _ => {}
+        }
+
+        println!();
+        if let Some(offers) = &mut offers {
+            answer_offers(&ui, offers, &client_tx, &send_args.receive).await;
+        }
+        if !send_args.to.is_empty() || !ui.ask_continue() {
+            break;
+        }
+    }
+
+    Ok(server.shutdown().await?)
+}
+
+/// The settings of a receiver started with `args`, the `[receive]` table of `config`
+/// wins over them.
+fn receive_settings(args: &ReceiveArgs, config: &Config) -> std::result::Result<Settings, String> {
+    let mut settings = Settings::default();
+    settings.destination.clone_from(&args.destination);
+    settings.quick_save = args.quick_save;
+    settings.auto_accept_texts = args.auto_accept_texts;
+    settings.collision_policy = args.on_conflict;
+    settings.append_any_type = args.append_any_type;
+    settings.archive.clone_from(&args.archive);
+    settings.archive_texts = args.archive_texts;
+    settings.text_preview = config.preview.clone();
+    settings.session_timeout = Duration::from_secs(args.session_timeout);
+    if args.portable_names {
+        settings.name_rules = NameRules::Windows;
+    }
+    settings.name_replacement = args.replace_char;
+    settings.case_sensitivity = args.name_case;
+    settings.dangerous_extensions = match args.keep_dangerous_names {
+        true => DangerousExtensions::default(),
+        false => config.dangerous_extensions(),
+    };
+    settings.audit = args.audit;
+    settings.audit_log.clone_from(&args.audit_log);
+    settings.journal_dir = data_dir().map(|dir| dir.join(JOURNAL_DIR));
+    settings.status_file.clone_from(&args.status_file);
+    settings.allow_session_extend = args.allow_extend;
+    settings.max_pending_sessions = args.queue;
+    settings.pending_session_wait = Duration::from_secs(args.queue_wait);
+    let mut hooks: Vec<Arc<dyn ReceiveHook>> = vec![];
+    if args.completion_marker {
+        hooks.push(Arc::new(CompletionMarker));
+    }
+    if let Some(path) = &args.completion_fifo {
+        hooks.push(Arc::new(CompletionFifo::new(path)));
+    }
+    if let Some(command) = &args.on_receive {
+        hooks.push(Arc::new(CommandHook::new(command.clone())));
+    }
+    settings.receive_hook = match hooks.len() {
+        0 => None,
+        1 => hooks.pop(),
+        _ => Some(Arc::new(HookChain(hooks))),
+    };
+    settings.hook_timeout = Duration::from_secs(args.on_receive_timeout);
+    settings.max_concurrent_uploads = args.max_concurrent_uploads.map(|n| n as usize);
+    settings.receive_rate_limit = args.limit_rate;
+    settings.preview_dir.clone_from(&args.preview_dir);
+    settings.preview_max_size = args.preview_max_size;
+    if args.dedup {
+        settings.dedup_index = data_dir().map(|dir| dir.join(DEDUP_INDEX_FILE));
+        if settings.dedup_index.is_none() {
+            log::warn!("No data directory, --dedup is ignored");
+        }
+        settings.dedup_action = args.dedup_action;
+    }
+    settings.structure_limits = StructureLimits {
+        max_depth: args.max_depth,
+        max_directories: args.max_dirs,
+        max_files: args.max_files,
+        ..StructureLimits::default()
+    };
+    settings.strict_structure = args.strict;
+    config.receive.apply(&mut settings)?;
+    Ok(settings)
+}
+
+/// Checks that received files can be saved, below the placeholders for templates.
+fn check_receive_destination(settings: &Settings, create: bool) -> std::result::Result<(), String> {
+    // an audit saves nothing
+    if settings.audit {
+        return Ok(());
+    }
+    match check_destination(&destination_root(&settings.destination), create) {
+        Ok(root) => {
+            log::debug!("destination root: {:?}", root);
+            Ok(())
+        }
+        Err(e @ DestinationError::Missing(_)) => {
+            Err(format!("{}, create it or pass --create-dest", e))
+        }
+        Err(e @ DestinationError::NoSpace(_)) => {
+            Err(format!("{}, free some or pick another --dest", e))
+        }
+        Err(e) => Err(format!("{}, pick another --dest", e)),
+    }
+}
+
+/// Reads the config at `path` again for [`receive_settings`] on every reload.
+///
+/// A receiver without a prompt can not ask about offers, quick save stays on there.
+fn settings_loader(args: &ReceiveArgs, path: PathBuf, prompt: bool) -> SettingsLoader {
+    let args = args.clone();
+    SettingsLoader::new(move || {
+        let config = read_config(&path)?;
+        let settings = receive_settings(&args, &config)?;
+        check_receive_destination(&settings, args.create_dest)?;
+        if !prompt && !settings.quick_save {
+            return Err("quick-save can only be turned off in a daemon".to_owned());
+        }
+        Ok(settings)
+    })
+}
+
+/// Adds texts, files and directories to `files`, in the order given and once each.
+fn add_inputs(
+    files: &mut SendingFiles,
+    inputs: &[String],
+    filter: &DirFilter,
+) -> Result<FilterReport> {
+    let mut report = FilterReport::default();
+    for text in inputs.iter().unique() {
+        if let Ok(path) = std::fs::canonicalize(text) {
+            if path.is_file() {
+                files.add_file(path, None)?;
+                continue;
+            } else if path.is_dir() {
+                report.merge(files.add_dir_with_filter(path, filter)?);
+                continue;
+            }
+        }
+        files.add_text(text, true);
+    }
+    Ok(report)
+}
+
+/// Collects the files of a batch job and finds its device.
+async fn prepare_job(
+    ui: &PromptUI,
+    scanner: &Arc<dyn DeviceScanner>,
+    args: &SendArgs,
+    job: &Job,
+    preview_policy: &PreviewPolicy,
+    cancel: &CancellationToken,
+) -> Result<(SendingFiles, Device)> {
+    let mut files = SendingFiles::default()
+        .with_preview_policy(preview_policy.clone())
+        .with_content_types(args.content_types());
+    let report = add_inputs(&mut files, &job.inputs, &args.dir_filter())?;
+    if let Some(note) = &args.note {
+        files.add_note(note);
+    }
+    if report.total() > 0 || !report.symlinks.is_empty() {
+        ui.print_filter_report(&report);
+    }
+    let mut devices = find_devices(ui, scanner, std::slice::from_ref(&job.target), cancel).await?;
+    if !args.no_precheck {
+        devices = precheck(ui, scanner, devices).await?;
+    }
+    Ok((files, devices.remove(0)))
+}
+
+/// Sends the files to every target, a failure on one target does not stop the others.
+#[allow(clippy::too_many_arguments)]
+async fn send(
+    ui: &PromptUI,
+    device: &Device,
+    targets: Vec<Device>,
+    files: &SendingFiles,
+    state: &MutexServerState,
+    args: &SendArgs,
+    progress: ProgressOptions,
+    cancel: &CancellationToken,
+) -> Vec<(Device, Result<SendingFiles>)> {
+    let multi = MultiProgress::new();
+    let grouped = targets.len() > 1;
+    let chunks = files.chunks(args.chunk_size as usize);
+    // a failed session of a sequential send asks whether to go on with the next ones
+    let ask = (!args.parallel_targets && std::io::stdin().is_terminal()).then(|| ui.clone());
+
+    let mut uploads = vec![];
+    for target in targets {
+        let (progress_tx, progress_rx) = ProgressStream::channel();
+        let mut pb = FileProgressBar::new(files.to_dto_list(), progress).with_sessions(&chunks);
+        if grouped {
+            pb = pb.for_device(&target.alias, &multi);
+        }
+        tokio::spawn(pb.consume(progress_rx));
+
+        let new_session = {
+            let (device, target) = (device.clone(), target.clone());
+            let (insecure, pacing) = (args.insecure, args.pacing());
+            move |files: &SendingFiles| {
+                SendSession::new(&device, target.clone(), files)
+                    .with_insecure_tls(insecure)
+                    .with_pacing(pacing)
+            }
+        };
+        let upload = upload_chunks(
+            target.clone(),
+            new_session,
+            chunks.clone(),
+            state.clone(),
+            progress_tx,
+            args.retry_busy,
+            ask.clone(),
+            cancel.child_token(),
+        );
+        uploads.push((target, upload));
+    }
+
+    let mut results = vec![];
+    if args.parallel_targets {
+        let handles: Vec<_> = uploads
+            .into_iter()
+            .map(|(target, upload)| (target, tokio::spawn(upload)))
+            .collect();
+        for (target, handle) in handles {
+            let result = handle.await.expect("Send task panicked");
+            results.push((target, result));
+        }
+    } else {
+        for (target, upload) in uploads {
+            results.push((target, upload.await));
+        }
+    }
+    record_history(&results);
+    results
+}
+
+/// Prints the `--to` candidates, leaving out what can not be read.
+fn complete_targets(args: &Args, fingerprints: bool) {
+    let devices = args
+        .config
+        .clone()
+        .or_else(|| config_dir().map(|dir| dir.join(CONFIG_FILE)))
+        .and_then(|path| read_config(&path).ok())
+        .map(|config| config.devices)
+        .unwrap_or_default();
+    let history = transfer_history()
+        .and_then(|history| history.entries().ok())
+        .unwrap_or_default();
+    print!(
+        "{}",
+        completions::target_candidates(&devices, &history, fingerprints)
+    );
+}
+
+/// The history of sends kept in the data directory.
+fn transfer_history() -> Option<TransferHistory> {
+    data_dir().map(|dir| TransferHistory::new(dir.join(HISTORY_FILE)))
+}
+
+fn record_history(results: &[(Device, Result<SendingFiles>)]) {
+    let Some(history) = transfer_history() else {
+        return;
+    };
+    for (target, result) in results {
+        if let Err(e) = history.record(&HistoryEntry::new(target, result)) {
+            log::warn!("Failed to record the send to {:?}: {}", target.alias, e);
+            return;
+        }
+    }
+}
+
+/// Sends the chunks of a large send one session after another, see `--chunk-size`.
+///
+/// The files of all sessions are returned together, those of failed sessions as
+/// failed and those never offered as skipped. Only a send without any finished
+/// session fails.
+#[allow(clippy::too_many_arguments)]
+async fn upload_chunks(
+    target: Device,
+    new_session: impl Fn(&SendingFiles) -> SendSession,
+    chunks: Vec<SendingFiles>,
+    state: MutexServerState,
+    progress_tx: ProgressSender,
+    retry_busy: bool,
+    ask: Option<PromptUI>,
+    cancel: CancellationToken,
+) -> Result<SendingFiles> {
+    let count = chunks.len();
+    if count <= 1 {
+        let files = chunks.into_iter().next().unwrap_or_default();
+        let new_session = || new_session(&files);
+        return upload(new_session, state, progress_tx, retry_busy, cancel).await;
+    }
+
+    let mut sent = SendingFiles::default();
+    let mut first_error = None;
+    let mut finished_sessions = 0;
+    let mut chunks = chunks.into_iter().enumerate();
+    for (index, chunk) in chunks.by_ref() {
+        log::info!(
+            "Session {}/{} to {:?}: {} files",
+            index + 1,
+            count,
+            target.alias,
+            chunk.len()
+        );
+        let new_session = || new_session(&chunk);
+        let result = upload(
+            new_session,
+            state.clone(),
+            progress_tx.clone(),
+            retry_busy,
+            cancel.clone(),
+        )
+        .await;
+        match result {
+            Ok(files) => {
+                finished_sessions += 1;
+                sent.extend(files);
+            }
+            Err(e) if cancel.is_cancelled() => return Err(e),
+            Err(e) => {
+                log::warn!(
+                    "Session {}/{} to {:?} failed: {}",
+                    index + 1,
+                    count,
+                    target.alias,
+                    e
+                );
+                sent.extend(with_status(chunk, FileStatus::Failed));
+                let go_on = index + 1 < count
+                    && ask
+                        .as_ref()
+                        .is_some_and(|ui| ui.ask_continue_sessions(index + 1, count, &e));
+                first_error.get_or_insert(e);
+                if !go_on {
+                    break;
+                }
+            }
+        }
+    }
+    for (_, chunk) in chunks {
+        sent.extend(with_status(chunk, FileStatus::Skipped));
+    }
+
+    match first_error {
+        Some(e) if finished_sessions == 0 => Err(e),
+        _ => {
+            let finished = sent
+                .values()
+                .filter(|f| f.status == FileStatus::Finished)
+                .count();
+            log::info!(
+                "Sent {} of {} files to {:?} in {} of {} sessions",
+                finished,
+                sent.len(),
+                target.alias,
+                finished_sessions,
+                count
+            );
+            Ok(sent)
+        }
+    }
+}
+
+fn with_status(mut files: SendingFiles, status: FileStatus) -> SendingFiles {
+    for file in files.values_mut() {
+        file.status = status.clone();
+    }
+    files
+}
+
+async fn upload(
+    new_session: impl Fn() -> SendSession,
+    state: MutexServerState,
+    progress_tx: ProgressSender,
+    retry_busy: bool,
+    cancel: CancellationToken,
+) -> Result<SendingFiles> {
+    // a retry reports to the same stream
+    let session = new_session().with_progress(progress_tx.clone());
+    let target = session.target().clone();
+    let result = session.upload(Some(state.clone()), &cancel).await;
+    match result {
+        Err(localsend_lib::Error::Send(SendError::Busy)) if retry_busy => {
+            log::warn!(
+                "{:?} is busy, retrying in {}s",
+                target.alias,
+                RETRY_BUSY_DELAY.as_secs()
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(RETRY_BUSY_DELAY) => {}
+                _ = cancel.cancelled() => return Err(SendError::Aborted.into()),
+            }
+            new_session()
+                .with_progress(progress_tx)
+                .upload(Some(state), &cancel)
+                .await
+        }
+        result => result,
+    }
+}
+
+/// Prints what the server receives until stopped.
+async fn print_sessions(
+    ui: &PromptUI,
+    mut server_rx: tokio::sync::mpsc::Receiver<ServerMessage>,
+    state: &MutexServerState,
+    cancel: &CancellationToken,
+) {
+    let mut events = state.lock().await.events.subscribe();
+    loop {
+        tokio::select! {
+            message = server_rx.recv() => match message {
+                Some(ServerMessage::TextReceived(text)) => ui.print_text(&text),
+                Some(ServerMessage::SessionFinished(report)) => ui.print_receive_report(&report),
+                Some(ServerMessage::NetworkChanged(ip)) => {
+                    log::warn!("Network changed, now reachable at {}", ip)
+                }
+                Some(_) => {}
+                None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => print_event(&event),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                    log::debug!("Missed {} session events", missed)
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+            _ = cancel.cancelled() => break,
+        }
+    }
+}
+
+/// Prints the texts of the session of `receive --once` until it ended, its report
+/// comes with the outcome.
+async fn print_once(
+    ui: &PromptUI,
+    mut server_rx: tokio::sync::mpsc::Receiver<ServerMessage>,
+    mut once: JoinHandle<OnceOutcome>,
+) -> OnceOutcome {
+    let print = |message| match message {
+        ServerMessage::TextReceived(text) => ui.print_text(&text),
+        ServerMessage::NetworkChanged(ip) => {
+            log::warn!("Network changed, now reachable at {}", ip)
+        }
+        _ => {}
+    };
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut once => break outcome.expect("Once task panicked"),
+            Some(message) = server_rx.recv() => print(message),
+        }
+    };
+    // texts are told before the session ends
+    while let Ok(message) = server_rx.try_recv() {
+        print(message);
+    }
+    outcome
+}
+
+/// The outcome of the session of `receive --once`, if one is followed.
+async fn await_once(once: Option<JoinHandle<OnceOutcome>>) -> Option<OnceOutcome> {
+    match once {
+        Some(once) => Some(once.await.expect("Once task panicked")),
+        None => None,
+    }
+}
+
+/// Stops the receive server, then exits with the code of the `receive --once` session.
+async fn exit_once(server: ServerHandle, outcome: Option<OnceOutcome>) -> Result<()> {
+    server.shutdown().await?;
+    let Some(outcome) = outcome else {
+        return Ok(());
+    };
+    match &outcome {
+        OnceOutcome::Failed { reason, .. } => log::error!("Receive failed: {}", reason),
+        OnceOutcome::Declined => log::info!("Offer declined"),
+        _ => {}
+    }
+    match outcome.exit_code() {
+        0 => Ok(()),
+        code => std::process::exit(code),
+    }
+}
+
+/// Prints what the receive server reports while sending and passes on the messages
+/// waiting for an answer, see [`answer_offers`].
+fn spawn_incoming(
+    ui: &PromptUI,
+    mut server_rx: tokio::sync::mpsc::Receiver<ServerMessage>,
+) -> tokio::sync::mpsc::UnboundedReceiver<ServerMessage> {
+    let ui = ui.clone();
+    let (offers_tx, offers_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(message) = server_rx.recv().await {
+            match message {
+                ServerMessage::SelectedFiles(files) => {
+                    log::info!(
+                        "{} files offered, answered once the current prompt closes",
+                        files.len()
+                    );
+                    offers_tx.send(ServerMessage::SelectedFiles(files)).ok();
+                }
+                message @ ServerMessage::ReviewFiles(_) => {
+                    offers_tx.send(message).ok();
+                }
+                ServerMessage::TextReceived(text) => ui.print_text(&text),
+                ServerMessage::SessionFinished(report) => ui.print_receive_report(&report),
+                ServerMessage::SessionExpired(_) => {
+                    log::warn!("Sender stopped responding, session dropped")
+                }
+                ServerMessage::SelectionTimedOut(_) => {
+                    log::warn!("Files were not selected in time, session dropped")
+                }
+                ServerMessage::NetworkChanged(ip) => {
+                    log::warn!("Network changed, now reachable at {}", ip)
+                }
+                ServerMessage::Downloaded(_) => {}
+            }
+        }
+    });
+    offers_rx
+}
+
+/// Asks where to save the selected files unless --no-dest-prompt is given.
+fn ask_destination(ui: &PromptUI, args: &ReceiveArgs) -> Option<PathBuf> {
+    // an audit saves nowhere
+    if args.no_dest_prompt || args.audit {
+        return None;
+    }
+    ui.choose_destination(&args.destination)
+}
+
+/// Asks about the files offered while sending, offers that timed out meanwhile
+/// are answered in vain.
+async fn answer_offers(
+    ui: &PromptUI,
+    offers: &mut tokio::sync::mpsc::UnboundedReceiver<ServerMessage>,
+    client_tx: &tokio::sync::mpsc::Sender<ClientMessage>,
+    receive_args: &ReceiveArgs,
+) {
+    while let Ok(message) = offers.try_recv() {
+        let answer = match message {
+            ServerMessage::SelectedFiles(files) => match ui.select_files(files) {
+                Some(files) => {
+                    // the report tells how the files went, the bars would cut into prompts
+                    let (progress_tx, _) = ProgressStream::channel();
+                    let destination = ask_destination(ui, receive_args);
+                    ClientMessage::FilesSelected(progress_tx, files, destination)
+                }
+                None => ClientMessage::Declined,
+            },
+            ServerMessage::ReviewFiles(files) => review_answer(ui, files),
+            _ => continue,
+        };
+        // a full channel holds an answer nobody waits for anymore
+        if client_tx.try_send(answer).is_err() {
+            log::warn!("The offer was given up on before it was answered");
+        }
+    }
+}
+
+/// Logs the session events that have no report of their own.
+fn print_event(event: &SessionEvent) {
+    match event {
+        SessionEvent::ReceiveRequested { sender, files, .. } => {
+            log::info!("{:?} offers {} files", sender.alias, files.len())
+        }
+        SessionEvent::ReceiveDeclined { .. } => log::info!("Offer declined"),
+        SessionEvent::SendStarted { target, files, .. } => {
+            log::info!("Sending {} files to {:?}", files.len(), target.alias)
+        }
+        SessionEvent::SendFinished { .. } => log::info!("Send finished"),
+        SessionEvent::SessionCancelled { by, .. } => match by {
+            CancelledBy::Sender => log::warn!("Session cancelled by the sender"),
+            CancelledBy::Receiver => log::warn!("Session cancelled by the receiver"),
+        },
+        SessionEvent::SessionExpired { .. } => {
+            log::warn!("Sender stopped responding, session dropped")
+        }
+        SessionEvent::SessionFailed { reason, .. } => log::warn!("Session dropped: {}", reason),
+        _ => {}
+    }
+}
+
+/// Collects the input of other invocations into `args`, false when another one sends it.
+async fn merge_with_others(args: &mut SendArgs, window: Duration) -> bool {
+    // the invocations may run in different directories
+    let inputs = args
+        .input
+        .iter()
+        .map(|input| match std::fs::canonicalize(input) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => input.clone(),
+        })
+        .collect();
+    match merge_inputs(&default_merge_path(), window, inputs).await {
+        Ok(Merge::HandedOver) => {
+            log::info!("Handed over to the localsend collecting the files");
+            false
+        }
+        Ok(Merge::Collected {
+            inputs,
+            invocations,
+        }) => {
+            if invocations > 1 {
+                log::info!(
+                    "Collected {} files from {} invocations",
+                    inputs.len(),
+                    invocations
+                );
+            }
+            args.input = inputs;
+            true
+        }
+        Err(e) => {
+            log::warn!("Sending without merging other invocations: {}", e);
+            true
+        }
+    }
+}
+
+/// Cleans up after the sessions of receivers no longer running and prints what was done.
+fn clean_up_sessions() {
+    let Some(dir) = data_dir().map(|dir| dir.join(JOURNAL_DIR)) else {
+        eprintln!("No data directory, there is nothing to clean up");
+        return;
+    };
+    let cleanup = clean_stale_journals(&dir, true);
+    for session in &cleanup.sessions {
+        println!(
+            "Session {} from {} ({}) saving to {:?}",
+            session.session_id,
+            sanitize_display(&session.sender_alias),
+            session.sender_ip,
+            session.destination
+        );
+    }
+    for path in &cleanup.removed {
+        println!("  removed {:?}", path);
+    }
+    for path in &cleanup.completed {
+        println!("  kept {:?}, it arrived completely", path);
+    }
+    for path in &cleanup.invalid {
+        println!("Removed unreadable journal {:?}", path);
+    }
+    println!(
+        "Cleaned up {} crashed sessions: removed {} partial files, kept {} complete ones",
+        cleanup.sessions.len(),
+        cleanup.removed.len(),
+        cleanup.completed.len()
+    );
+}
+
+/// Queues the input with a running daemon and prints the ids of its jobs.
+async fn send_through_daemon(args: &SendArgs) -> Result<()> {
+    if !args.to_host.is_empty() {
+        log::error!("--daemon does not support --to-host yet");
+        std::process::exit(1)
+    }
+    let targets = args.targets();
+    if targets.is_empty() {
+        log::error!("--daemon needs --to, --to-fingerprint or --to-ip");
+        std::process::exit(1)
+    }
+    let mut paths = vec![];
+    let mut texts = vec![];
+    for input in args.input.iter().unique() {
+        // the daemon runs in another directory
+        match std::fs::canonicalize(input) {
+            Ok(path) => paths.push(path),
+            Err(_) => texts.push(input.clone()),
+        }
+    }
+
+    let path = args
+        .control_socket
+        .clone()
+        .unwrap_or_else(default_control_path);
+    let mut client = match ControlClient::connect(&path).await {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("No daemon is listening on {:?}: {}", path, e);
+            std::process::exit(1)
+        }
+    };
+    let request = ControlRequest::Send {
+        paths,
+        texts,
+        targets,
+    };
+    match client.request(&request).await? {
+        ControlResponse::Queued { job_ids } => {
+            for job_id in job_ids {
+                println!("{}", job_id);
+            }
+            Ok(())
+        }
+        ControlResponse::Error { error } => {
+            log::error!("{}", error.message);
+            std::process::exit(1)
+        }
+        response => {
+            log::error!("Unexpected response of the daemon: {:?}", response);
+            std::process::exit(1)
+        }
+    }
+}
+
+/// How `cmd` would receive, which `--stealth` rules out.
+fn stealth_conflict(cmd: &SubCommand) -> Option<&'static str> {
+    match cmd {
+        SubCommand::Receive(_) => Some("to receive"),
+        SubCommand::Daemon(_) => Some("with the daemon"),
+        SubCommand::ServeText(_) => Some("to serve a text"),
+        SubCommand::Send(args) if args.bidirectional => Some("with --bidirectional"),
+        SubCommand::Send(args) if args.daemon => Some("to send through the daemon"),
+        _ => None,
+    }
+}
+
+fn spawn_announcements(scanner: &Arc<dyn DeviceScanner>) {
+    let scanner = scanner.clone();
+    tokio::spawn(async move {
+        loop {
+            for ms in [100, 500, 2000] {
+                scanner.announce().await;
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+            }
+        }
+    });
+}
+
+/// Offers the text until Ctrl-C, counting every fetch.
+async fn serve_text(
+    ui: &PromptUI,
+    device: &Device,
+    state: &MutexServerState,
+    mut server_rx: tokio::sync::mpsc::Receiver<ServerMessage>,
+    args: &ServeTextArgs,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    state.lock().await.shared_text = Some(SharedText::new(device, &args.text));
+
+    let url = format!("http://{}:{}/", device.ip, device.port);
+    if !args.no_qr {
+        ui.print_qr_code(&url);
+    }
+    println!(
+        "Serving text as {} at {}, Ctrl-C to stop",
+        device.alias, url
+    );
+
+    let mut count = 0;
+    loop {
+        let message = tokio::select! {
+            message = server_rx.recv() => message,
+            _ = cancel.cancelled() => break,
+        };
+        let Some(message) = message else {
+            break;
+        };
+        match message {
+            ServerMessage::Downloaded(peer) => {
+                count += 1;
+                log::info!("Fetched by {} ({} in total)", peer, count);
+            }
+            ServerMessage::NetworkChanged(ip) => {
+                let url = format!("http://{}:{}/", ip, device.port);
+                if !args.no_qr {
+                    ui.print_qr_code(&url);
+                }
+                println!("Network changed, now serving at {}", url);
+            }
+            _ => {}
+        }
+    }
     Ok(())
 }
+
+/// This device as announced and offered to others, serving on `port`.
+fn local_device(args: &Args, ip: IpAddr, port: u16) -> Device {
+    Device {
+        ip: ip.to_string(),
+        alias: args.alias.clone().unwrap_or(device::alias()),
+        fingerprint: device::fingerprint(),
+        version: PROTOCOL_VERSION_2.to_string(),
+        device_model: Some(args.device_model.clone().unwrap_or(device::device_model())),
+        device_type: args.device_type.clone(),
+        download: args.is_serve_text_mode(),
+        https: false,
+        port: args.advertise_port.unwrap_or(port),
+    }
+}
+
+fn progress_options(args: &Args, theme: &Theme) -> ProgressOptions {
+    let mode = match args.progress {
+        // bars redrawn on stderr would tear the traced messages apart
+        ProgressMode::Auto if args.trace_http.is_some() => ProgressMode::None,
+        mode => mode,
+    };
+    ProgressOptions {
+        mode,
+        use_nerd_fonts: theme.nerd_fonts(),
+    }
+}
+
+/// Prints the traced HTTP messages to stderr, colored by direction on a terminal.
+fn trace_http(mode: HttpTrace) {
+    let color = std::io::stderr().is_terminal();
+    set_http_trace(mode, move |direction, message| {
+        let message = match (color, direction) {
+            (false, _) => message.normal(),
+            (true, Direction::Outgoing) => message.green(),
+            (true, Direction::Incoming) => message.cyan(),
+        };
+        eprintln!("{}", message);
+    });
+}
+
+/// The theme of `--theme`, else the theme file in the config directory if there is one.
+fn load_theme(args: &Args) -> Result<Theme> {
+    let mut theme = match &args.theme {
+        Some(name_or_path) => Theme::load(name_or_path),
+        None => match config_dir().map(|dir| dir.join(THEME_FILE)) {
+            Some(path) if path.exists() => Theme::from_file(&path),
+            _ => Ok(Theme::default()),
+        },
+    }
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    if args.no_nerd && theme.icons == IconSet::Nerd {
+        theme.icons = IconSet::None;
+    }
+    Ok(theme.honor_no_color())
+}
+
+/// Answers the review of the quarantined files, skipping it keeps all of them.
+fn review_answer(ui: &PromptUI, files: Vec<PreviewFile>) -> ClientMessage {
+    let kept = ui.review_files(files.clone()).unwrap_or(files);
+    ClientMessage::FilesReviewed(kept.into_iter().map(|preview| preview.file.id).collect())
+}
+
+/// Binds the ports early on the first run and explains why they could not be bound.
+///
+/// Other ports are asked for on a terminal until binding succeeds.
+fn first_run_check(ui: &PromptUI, args: &mut Args) {
+    let Some(marker) = config_dir().map(|dir| dir.join(FIRST_RUN_MARKER)) else {
+        return;
+    };
+    if marker.exists() {
+        return;
+    }
+    loop {
+        let failure = match probe_binds(args.port, args.http_port) {
+            Ok(()) => break,
+            Err(failure) => failure,
+        };
+        log::debug!(
+            "Binding {} port {} failed: {:?}",
+            failure.transport,
+            failure.port,
+            failure.error
+        );
+        let excluded = excluded_port_ranges(failure.transport);
+        let advice = bind_advice(&failure, Platform::current(), &excluded);
+        match (ui.ask_other_port(&advice), failure.transport) {
+            (Some(port), Transport::Udp) => {
+                println!("Pass --port {} to use it again", port);
+                args.port = port;
+            }
+            (Some(port), Transport::Tcp) => {
+                println!("Pass --http-port {} to use it again", port);
+                args.http_port = port;
+            }
+            (None, _) => std::process::exit(1),
+        }
+    }
+    let written = marker
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&marker, ""));
+    if let Err(e) = written {
+        log::debug!("Failed to write {:?}: {}", marker, e);
+    }
+}
+
+/// Scans once and looks up a device for every target.
+///
+/// Several devices matching a target are offered for selection on a terminal,
+/// scripts fail instead of guessing.
+/// Asks the `--to-host` devices for their info, then finds the other targets.
+async fn find_targets(
+    ui: &PromptUI,
+    scanner: &Arc<dyn DeviceScanner>,
+    args: &SendArgs,
+    targets: &[Target],
+    cancel: &CancellationToken,
+) -> Result<Vec<Device>> {
+    let mut devices = vec![];
+    for host in &args.to_host {
+        let host = host.clone();
+        let prefer_ipv6 = args.prefer_ipv6;
+        let device = ui
+            .show_loading(format!("Resolving {}", host.host), async move {
+                resolve_host(&host, &SystemResolver, &InfoProbe, prefer_ipv6).await
+            })
+            .await?;
+        devices.push(device);
+    }
+    if !targets.is_empty() {
+        devices.extend(find_devices(ui, scanner, targets, cancel).await?);
+    }
+    Ok(devices)
+}
+
+async fn find_devices(
+    ui: &PromptUI,
+    scanner: &Arc<dyn DeviceScanner>,
+    targets: &[Target],
+    cancel: &CancellationToken,
+) -> Result<Vec<Device>> {
+    // exact matches among the static devices need no scan
+    let configured = scanner.configured_devices();
+    if !configured.is_empty() {
+        let resolved: std::result::Result<Vec<Device>, SendError> = targets
+            .iter()
+            .map(|target| target.resolve(&configured))
+            .collect();
+        if let Ok(devices) = resolved {
+            return Ok(devices);
+        }
+    }
+    let devices = {
+        let scanner = scanner.clone();
+        let cancel = cancel.clone();
+        ui.show_loading(
+            "Scanning".to_owned(),
+            async move { scanner.scan(&cancel).await },
+        )
+        .await?
+    };
+    if cancel.is_cancelled() {
+        return Err(SendError::Aborted.into());
+    }
+    let interactive = std::io::stdin().is_terminal();
+    targets
+        .iter()
+        .map(|target| match target.resolve(&devices) {
+            Err(SendError::AmbiguousTarget(candidates)) if interactive => ui
+                .select_candidate(target, candidates)
+                .ok_or_else(|| SendError::NothingSelected.into()),
+            result => result.map_err(Into::into),
+        })
+        .collect()
+}
+
+#[cfg(feature = "self-update")]
+async fn self_update(args: &SelfUpdateArgs) {
+    let fail = |e: String| -> ! {
+        log::error!("{}", e);
+        std::process::exit(1)
+    };
+    let fetch = update::HttpFetch::new().unwrap_or_else(|e| fail(e));
+    let current = update::current_version();
+    let artifact = update::artifact_name();
+    let update = match update::find_update(&fetch, &current, &artifact).await {
+        Ok(Some(update)) => update,
+        Ok(None) => {
+            println!("localsend {} is the latest release", current);
+            return;
+        }
+        Err(e) => fail(e),
+    };
+    println!(
+        "localsend {} is available, this is {}",
+        update.version, current
+    );
+    if args.check {
+        return;
+    }
+    let confirmed = args.yes
+        || inquire::Confirm::new(&format!("Replace this executable with {}?", update.version))
+            .with_default(false)
+            .prompt_skippable()
+            .is_ok_and(|r| r == Some(true));
+    if !confirmed {
+        return;
+    }
+    let executable = update::download(&fetch, &update)
+        .await
+        .unwrap_or_else(|e| fail(e));
+    if let Err(e) = update::replace_current(&executable) {
+        fail(format!("Failed to replace the executable: {}", e));
+    }
+    println!("Updated to localsend {}", update.version);
+}
+
+/// Mentions a newer release at most once a day, quietly giving up on any failure.
+#[cfg(feature = "self-update")]
+async fn notify_update() {
+    let Some(stamp) = data_dir().map(|dir| dir.join(update::CHECK_STAMP_FILE)) else {
+        return;
+    };
+    let now = std::time::SystemTime::now();
+    if !update::check_due(&stamp, now) {
+        return;
+    }
+    if let Err(e) = update::record_check(&stamp, now) {
+        log::debug!("Failed to record the update check: {}", e);
+    }
+    let result = match update::HttpFetch::new() {
+        Ok(fetch) => {
+            update::find_update(&fetch, &update::current_version(), &update::artifact_name()).await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(Some(update)) => log::info!(
+            "localsend {} is available, run `localsend self-update` to install it",
+            update.version
+        ),
+        Ok(None) => {}
+        Err(e) => log::debug!("Failed to check for updates: {}", e),
+    }
+}
+
+/// Fails on the first device that does not answer and drops it from the scanner.
+async fn precheck(
+    ui: &PromptUI,
+    scanner: &Arc<dyn DeviceScanner>,
+    devices: Vec<Device>,
+) -> Result<Vec<Device>> {
+    for device in &devices {
+        let message = format!("Connecting to {}", sanitize_display(&device.alias));
+        let probe = {
+            let device = device.clone();
+            async move { check_reachable(&device).await }
+        };
+        if let Err(e) = ui.show_loading(message, probe).await {
+            scanner.mark_stale(device);
+            return Err(e.into());
+        }
+    }
+    Ok(devices)
+}
+
+async fn pull(
+    ui: &PromptUI,
+    scanner: &Arc<dyn DeviceScanner>,
+    args: &PullArgs,
+    dangerous: DangerousExtensions,
+    progress: ProgressOptions,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let target = match &args.device {
+        Some(alias) => {
+            match find_devices(ui, scanner, &[Target::Alias(alias.clone())], cancel).await {
+                Err(_) if cancel.is_cancelled() => return Ok(()),
+                devices => devices?.remove(0),
+            }
+        }
+        None => ui.select_device(scanner).await?,
+    };
+
+    let session = {
+        let target = target.clone();
+        let message = format!("Connecting to {}", sanitize_display(&target.alias));
+        ui.show_loading(
+            message,
+            async move { DownloadSession::prepare(&target).await },
+        )
+        .await?
+        .with_dangerous_extensions(dangerous)
+    };
+
+    let files = match ui.select_files(session.files()) {
+        Some(files) if !files.is_empty() => files,
+        _ => return Ok(()),
+    };
+
+    let (progress_tx, progress_rx) = ProgressStream::channel();
+    let pb = FileProgressBar::new(sorted_by_name(&files), progress);
+    let progress = tokio::spawn(pb.consume(progress_rx));
+
+    // partial files are kept on cancellation, the next pull resumes them
+    let result = tokio::select! {
+        result = session.download(
+            &files,
+            &args.destination,
+            args.on_conflict,
+            Some(progress_tx),
+        ) => result,
+        _ = cancel.cancelled() => Ok(()),
+    };
+    progress.await.ok();
+    result
+}
@@ -0,0 +1,315 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use itertools::Itertools;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+/// The inputs of one invocation and how the collecting instance confirms them.
+type Handoff = (Vec<String>, oneshot::Sender<()>);
+
+/// What became of the inputs of this invocation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Merge {
+    /// Another instance collects them, this one is done
+    HandedOver,
+    /// The inputs of every invocation within the window, without repetitions
+    Collected {
+        inputs: Vec<String>,
+        invocations: usize,
+    },
+}
+
+/// Where invocations started with `--merge-window` meet.
+pub fn default_merge_path() -> PathBuf {
+    if cfg!(windows) {
+        return PathBuf::from(r"\\.\pipe\localsend-merge");
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("localsend-merge.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("localsend-merge-{}.sock", user))
+        }
+    }
+}
+
+/// Hands `inputs` to the instance collecting on `path`, or becomes that instance.
+///
+/// The collecting instance waits until no invocation arrived for `window`, e.g.
+/// while Explorer starts one process per selected file. Inputs that are paths
+/// should be absolute, the invocations may run in different directories.
+pub async fn merge_inputs(path: &Path, window: Duration, inputs: Vec<String>) -> io::Result<Merge> {
+    // a collector may appear or go away between connecting and listening
+    for _ in 0..3 {
+        match hand_over(path, &inputs).await {
+            Ok(()) => return Ok(Merge::HandedOver),
+            Err(e) => log::debug!("No instance is collecting on {:?}: {}", path, e),
+        }
+        match Collector::bind(path) {
+            Ok(collector) => return Ok(collector.collect(window, inputs).await),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("{:?} is neither collecting nor free", path),
+    ))
+}
+
+async fn hand_over(path: &Path, inputs: &[String]) -> io::Result<()> {
+    let stream = connect(path).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut json = serde_json::to_string(inputs)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+
+    // the collector only answers once the inputs are part of its send
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    if line.trim_end() == "ok" {
+        Ok(())
+    } else {
+        Err(io::ErrorKind::ConnectionAborted.into())
+    }
+}
+
+#[cfg(unix)]
+async fn connect(path: &Path) -> io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(path).await
+}
+
+#[cfg(windows)]
+async fn connect(path: &Path) -> io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    const ERROR_PIPE_BUSY: i32 = 231;
+    // every instance of the pipe may be taken by the other invocations
+    for _ in 0..20 {
+        match ClientOptions::new().open(path) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                tokio::time::sleep(Duration::from_millis(50)).await
+            }
+            result => return result,
+        }
+    }
+    Err(io::ErrorKind::TimedOut.into())
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn connect(_path: &Path) -> io::Result<tokio::io::DuplexStream> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+struct Collector {
+    path: PathBuf,
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+    #[cfg(windows)]
+    server: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+impl Collector {
+    /// Fails with [`io::ErrorKind::AddrInUse`] while another instance collects.
+    #[cfg(unix)]
+    fn bind(path: &Path) -> io::Result<Self> {
+        use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+        // other users must not add files, the socket only appears under its name
+        // once it is private, and linking fails if another instance got there first
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let staging = path.with_file_name(format!(".{}.{}", file_name, std::process::id()));
+        std::fs::remove_file(&staging).ok();
+        let listener = tokio::net::UnixListener::bind(&staging)?;
+        let linked = std::fs::set_permissions(&staging, Permissions::from_mode(0o600))
+            .and_then(|_| std::fs::hard_link(&staging, path));
+        std::fs::remove_file(&staging).ok();
+        match linked {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                // left behind by an instance that did not exit cleanly
+                if std::os::unix::net::UnixStream::connect(path).is_err() {
+                    log::debug!("Removing stale merge socket {:?}", path);
+                    std::fs::remove_file(path).ok();
+                }
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+        })
+    }
+
+    #[cfg(windows)]
+    fn bind(path: &Path) -> io::Result<Self> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::PermissionDenied => io::ErrorKind::AddrInUse.into(),
+                _ => e,
+            })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            server,
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn bind(_path: &Path) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Takes the inputs of other invocations until none arrived for `window`.
+    async fn collect(mut self, window: Duration, inputs: Vec<String>) -> Merge {
+        let (tx, mut rx) = mpsc::channel::<Handoff>(16);
+        let mut inputs = inputs;
+        let mut invocations = 1;
+        let deadline = tokio::time::sleep(window);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                accepted = self.accept() => match accepted {
+                    Ok(stream) => {
+                        tokio::spawn(receive(stream, tx.clone()));
+                    }
+                    Err(e) => log::warn!("Failed to accept another invocation: {}", e),
+                },
+                Some((more, confirm)) = rx.recv() => {
+                    inputs.extend(more);
+                    invocations += 1;
+                    confirm.send(()).ok();
+                    deadline.as_mut().reset(Instant::now() + window);
+                }
+            }
+        }
+        // invocations still handing over are not confirmed and send on their own
+        self.close();
+        drop(rx);
+        Merge::Collected {
+            inputs: inputs.into_iter().unique().collect(),
+            invocations,
+        }
+    }
+
+    #[cfg(unix)]
+    async fn accept(&mut self) -> io::Result<impl AsyncRead + AsyncWrite + Send + 'static> {
+        Ok(self.listener.accept().await?.0)
+    }
+
+    #[cfg(windows)]
+    async fn accept(&mut self) -> io::Result<impl AsyncRead + AsyncWrite + Send + 'static> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        self.server.connect().await?;
+        // the next invocation connects to a new instance of the pipe
+        let next = ServerOptions::new().create(&self.path)?;
+        Ok(std::mem::replace(&mut self.server, next))
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    async fn accept(&mut self) -> io::Result<tokio::io::DuplexStream> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn close(self) {
+        // removed before the listener closes, so nobody takes the name for stale meanwhile
+        #[cfg(unix)]
+        std::fs::remove_file(&self.path).ok();
+        log::debug!("Stopped collecting on {:?}", self.path);
+    }
+}
+
+async fn receive(stream: impl AsyncRead + AsyncWrite, tx: mpsc::Sender<Handoff>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut line = String::new();
+    if BufReader::new(reader).read_line(&mut line).await.is_err() {
+        return;
+    }
+    let inputs: Vec<String> = match serde_json::from_str(&line) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            log::warn!("Invalid inputs of another invocation: {}", e);
+            return;
+        }
+    };
+    let (confirm, confirmed) = oneshot::channel();
+    if tx.send((inputs, confirm)).await.is_err() || confirmed.await.is_err() {
+        return;
+    }
+    writer.write_all(b"ok\n").await.ok();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use super::{merge_inputs, Merge};
+
+    #[tokio::test]
+    async fn test_merge_inputs() {
+        let path =
+            std::env::temp_dir().join(format!("localsend-merge-{}.sock", std::process::id()));
+        let window = Duration::from_millis(500);
+        let inputs = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        let collector = tokio::spawn({
+            let path = path.clone();
+            async move { merge_inputs(&path, window, inputs(&["/a", "/b"])).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // each arrival restarts the window, together they take longer than one
+        for names in [&["/c"][..], &["/b", "/d"][..]] {
+            tokio::time::sleep(Duration::from_millis(350)).await;
+            let merge = merge_inputs(&path, window, inputs(names)).await.unwrap();
+            assert_eq!(merge, Merge::HandedOver);
+        }
+        let merge = collector.await.unwrap().unwrap();
+        assert_eq!(
+            merge,
+            Merge::Collected {
+                inputs: inputs(&["/a", "/b", "/c", "/d"]),
+                invocations: 3,
+            }
+        );
+        assert!(!path.exists());
+
+        // once the window closed the next invocation collects on its own
+        let merge = merge_inputs(&path, Duration::from_millis(10), inputs(&["/e"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            merge,
+            Merge::Collected {
+                inputs: inputs(&["/e"]),
+                invocations: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_socket() {
+        let path =
+            std::env::temp_dir().join(format!("localsend-stale-{}.sock", std::process::id()));
+        // a socket nobody listens on anymore
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let merge = merge_inputs(&path, Duration::from_millis(10), vec!["/a".to_owned()])
+            .await
+            .unwrap();
+        assert!(matches!(merge, Merge::Collected { invocations: 1, .. }));
+        assert!(!path.exists());
+    }
+}
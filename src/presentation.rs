@@ -0,0 +1,329 @@
+use std::{borrow::Cow, path::Path};
+
+use localsend_proto::{dto::FileType, Device, DeviceType};
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Read from the config directory when no `--theme` is given.
+pub const THEME_FILE: &str = "theme.json";
+pub const BUILTIN_THEMES: [&str; 3] = ["default", "high-contrast", "mono"];
+
+pub type Rgb = (u8, u8, u8);
+
+/// A foreground color, or none in themes without colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style(Option<Rgb>);
+
+impl Style {
+    pub fn paint(&self, text: &str) -> String {
+        match self.0 {
+            Some((r, g, b)) => format!("\x1b[38;2;{};{};{}m{}\x1b[0m", r, g, b, text),
+            None => text.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconSet {
+    Nerd,
+    Ascii,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceColors {
+    pub mobile: Rgb,
+    pub desktop: Rgb,
+    pub web: Rgb,
+    pub headless: Rgb,
+    pub server: Rgb,
+}
+
+/// Colors and icons of devices and files, built in or read from a JSON file.
+///
+/// Fields missing from a file keep the values of the default theme.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub devices: DeviceColors,
+    pub icons: IconSet,
+    /// Plain text without any colors, also forced by the NO_COLOR variable
+    pub color: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            devices: DeviceColors {
+                mobile: (95, 175, 0),
+                desktop: (95, 175, 255),
+                web: (0, 128, 128),
+                headless: (95, 0, 175),
+                server: (128, 0, 128),
+            },
+            icons: IconSet::Nerd,
+            color: true,
+        }
+    }
+}
+
+impl Theme {
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            // the Okabe-Ito colors, told apart with any kind of color blindness
+            "high-contrast" => Some(Self {
+                devices: DeviceColors {
+                    mobile: (230, 159, 0),
+                    desktop: (86, 180, 233),
+                    web: (0, 158, 115),
+                    headless: (240, 228, 66),
+                    server: (204, 121, 167),
+                },
+                ..Self::default()
+            }),
+            "mono" => Some(Self {
+                icons: IconSet::Ascii,
+                color: false,
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Reads a built-in theme by name or a theme file by path.
+    pub fn load(name_or_path: &str) -> Result<Self, String> {
+        if let Some(theme) = Self::builtin(name_or_path) {
+            return Ok(theme);
+        }
+        Self::from_file(Path::new(name_or_path))
+            .map_err(|e| format!("{} (built-in themes: {})", e, BUILTIN_THEMES.join(", ")))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read theme {:?}: {}", path, e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Invalid theme {:?}: {}", path, e))
+    }
+
+    /// Turns the colors off when the NO_COLOR variable is set to anything but empty.
+    pub fn honor_no_color(mut self) -> Self {
+        if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            self.color = false;
+        }
+        self
+    }
+
+    pub fn nerd_fonts(&self) -> bool {
+        self.icons == IconSet::Nerd
+    }
+
+    pub fn device_style(&self, device: &Device) -> Style {
+        if !self.color {
+            return Style(None);
+        }
+        let colors = &self.devices;
+        match device.device_type {
+            DeviceType::Mobile => Style(Some(colors.mobile)),
+            DeviceType::Desktop => Style(Some(colors.desktop)),
+            DeviceType::Web => Style(Some(colors.web)),
+            DeviceType::Headless => Style(Some(colors.headless)),
+            DeviceType::Server => Style(Some(colors.server)),
+            // the terminal's own color, no theme has one for types it does not know
+            DeviceType::Unknown(_) => Style(None),
+        }
+    }
+
+    pub fn file_icon(&self, file_type: &FileType) -> &'static str {
+        file_icon(file_type, self.icons)
+    }
+}
+
+pub fn file_icon(file_type: &FileType, icons: IconSet) -> &'static str {
+    match icons {
+        IconSet::Nerd => match file_type {
+            FileType::Image => "󰈟",
+            FileType::Video => "󰈫",
+            FileType::Pdf => "󰈧",
+            FileType::Text => "󰈙",
+            FileType::Apk => "󰀲",
+            FileType::Other => "󰈔",
+        },
+        IconSet::Ascii => match file_type {
+            FileType::Image => "[img]",
+            FileType::Video => "[vid]",
+            FileType::Pdf => "[pdf]",
+            FileType::Text => "[txt]",
+            FileType::Apk => "[apk]",
+            FileType::Other => "[file]",
+        },
+        IconSet::None => "",
+    }
+}
+
+/// Formats bytes like "1.30 MB", in powers of 1000.
+pub fn format_size(bytes: u64) -> String {
+    humansize::format_size(bytes, humansize::DECIMAL)
+}
+
+/// Whether printing `c` could move the cursor, start an escape sequence or reorder
+/// the text around it: C0 and C1 controls, DEL and the bidi formatting characters.
+fn is_unsafe_char(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+        )
+}
+
+/// A string from another device made safe to print, like an alias or a file name.
+///
+/// Characters that could spoof the terminal are replaced with their visible escapes,
+/// an escape sequence like `\x1b[2J` shows as `\u{1b}[2J`.
+pub fn sanitize_display(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_unsafe_char) {
+        return Cow::Borrowed(text);
+    }
+    let mut safe = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        if is_unsafe_char(c) {
+            safe.extend(c.escape_default());
+        } else {
+            safe.push(c);
+        }
+    }
+    Cow::Owned(safe)
+}
+
+/// Sanitizes `text` and keeps its first `width` columns, followed by `…` when cut.
+/// Wide characters and combined ones are never split.
+pub fn truncate_display(text: &str, width: usize) -> String {
+    let text = sanitize_display(text);
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += grapheme.width();
+        if used > width {
+            return format!("{}…", &text[..index]);
+        }
+    }
+    text.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use localsend_proto::{dto::FileType, fixtures::device, Device, DeviceType};
+
+    use super::{format_size, sanitize_display, truncate_display, IconSet, Theme, BUILTIN_THEMES};
+
+    /// Renders a mobile device and a file of each type.
+    fn render(theme: &Theme) -> String {
+        let phone = Device {
+            device_type: DeviceType::Mobile,
+            ..device("phone", 53317)
+        };
+        let mut rendered = theme.device_style(&phone).paint("phone");
+        for file_type in [FileType::Image, FileType::Other] {
+            rendered.push(' ');
+            rendered.push_str(theme.file_icon(&file_type));
+        }
+        rendered
+    }
+
+    #[test]
+    fn test_builtin_themes() {
+        let rendered: Vec<String> = BUILTIN_THEMES
+            .iter()
+            .map(|name| render(&Theme::builtin(name).unwrap()))
+            .collect();
+        assert_eq!(
+            rendered,
+            [
+                "\x1b[38;2;95;175;0mphone\x1b[0m 󰈟 󰈔",
+                "\x1b[38;2;230;159;0mphone\x1b[0m 󰈟 󰈔",
+                "phone [img] [file]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_theme_file() {
+        let path =
+            std::env::temp_dir().join(format!("localsend-theme-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"icons": "ascii", "devices": {"mobile": [1, 2, 3], "desktop": [0, 0, 0], "web": [0, 0, 0], "headless": [0, 0, 0], "server": [0, 0, 0]}}"#).unwrap();
+        let theme = Theme::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(theme.icons, IconSet::Ascii);
+        assert_eq!(render(&theme), "\x1b[38;2;1;2;3mphone\x1b[0m [img] [file]");
+
+        std::fs::write(&path, r#"{"colour": false}"#).unwrap();
+        assert!(Theme::load(path.to_str().unwrap()).is_err());
+        assert!(Theme::load("missing-theme").is_err());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_unknown_device_style() {
+        let theme = Theme::default();
+        let tv = Device {
+            device_type: DeviceType::Unknown("tv".to_owned()),
+            ..device("tv", 53317)
+        };
+        assert_eq!(theme.device_style(&tv).paint("tv"), "tv");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(1_300_000), "1.30 MB");
+    }
+
+    #[test]
+    fn test_sanitize_display() {
+        assert!(matches!(
+            sanitize_display("Pixel 7 · Grüße 📱"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+        // colors, clearing the screen and a title set by OSC, ended by BEL or ST
+        assert_eq!(
+            sanitize_display("\x1b[31mred\x1b[0m\x1b[2J"),
+            "\\u{1b}[31mred\\u{1b}[0m\\u{1b}[2J"
+        );
+        assert_eq!(
+            sanitize_display("\x1b]0;owned\x07\x1b]2;x\x1b\\"),
+            "\\u{1b}]0;owned\\u{7}\\u{1b}]2;x\\u{1b}\\"
+        );
+        // an escape hidden in another one stays visible
+        assert_eq!(sanitize_display("\x1b[\x1b[31m"), "\\u{1b}[\\u{1b}[31m");
+        // C1 controls, the 8-bit CSI included, and line breaks
+        assert_eq!(
+            sanitize_display("a\u{9b}31mb\r\nc\u{7f}"),
+            "a\\u{9b}31mb\\r\\nc\\u{7f}"
+        );
+        // "photo_gpj.exe" shown as "photo_exe.jpg"
+        assert_eq!(
+            sanitize_display("photo_\u{202E}gpj.exe"),
+            "photo_\\u{202e}gpj.exe"
+        );
+        assert_eq!(
+            sanitize_display("\u{2067}a\u{2069}\u{200f}"),
+            "\\u{2067}a\\u{2069}\\u{200f}"
+        );
+    }
+
+    #[test]
+    fn test_truncate_display() {
+        assert_eq!(truncate_display("short", 10), "short");
+        assert_eq!(truncate_display("exactly", 7), "exactly");
+        assert_eq!(truncate_display("truncated", 5), "trunc…");
+        // wide characters take two columns and are not split
+        assert_eq!(truncate_display("日本語の名前", 5), "日本…");
+        // a combining accent stays with its letter
+        assert_eq!(
+            truncate_display("e\u{301}e\u{301}e\u{301}", 2),
+            "e\u{301}e\u{301}…"
+        );
+        // escapes count with the width they are shown with
+        assert_eq!(truncate_display("\x1b[2Jabc", 8), "\\u{1b}[2…");
+    }
+}
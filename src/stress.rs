@@ -0,0 +1,460 @@
+//! `localsend stress`, a soak test running a receiver and senders in this process to
+//! find what a long-running receiver leaks.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use comfy_table::Table;
+use localsend_lib::{
+    receive::{Decision, ReceiveDecider},
+    send::{SendSession, SendingFiles},
+    server::{start_api_server, MutexServerState, ServerState, StateStats},
+    Result,
+};
+use localsend_proto::{dto::FileDto, Device, DeviceType, PROTOCOL_VERSION_2};
+use tokio_util::sync::CancellationToken;
+
+use crate::presentation::format_size;
+
+/// Samples taken before these are left out, buffers and pools grow until they are warm.
+const WARM_UP_SAMPLES: usize = 2;
+/// Samples needed after the warm up to call a steady growth a leak.
+const MIN_LEAK_SAMPLES: usize = 4;
+/// Sessions cancelled while preparing are never known to their sender, the receiver
+/// drops them after this long without uploads.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(clap::Parser, Debug, Clone)]
+pub struct StressArgs {
+    /// How long to run, in seconds
+    #[arg(long, default_value_t = 60)]
+    pub duration: u64,
+
+    /// Files offered in each session
+    #[arg(long, default_value_t = 4)]
+    pub files: usize,
+
+    /// Largest size of a file in bytes, each one gets a random size up to it
+    #[arg(long, default_value_t = 256 * 1024)]
+    pub size: u64,
+
+    /// Share of the offered files that are text messages, from 0 to 1
+    #[arg(long, default_value_t = 0.25)]
+    pub texts: f64,
+
+    /// Probability that the receiver declines a session
+    #[arg(long, default_value_t = 0.1)]
+    pub decline: f64,
+
+    /// Probability that a sender cancels its session while uploading
+    #[arg(long, default_value_t = 0.1)]
+    pub cancel: f64,
+
+    /// Senders running at the same time, the receiver queues them
+    #[arg(long, default_value_t = 1)]
+    pub parallel: usize,
+
+    /// Seconds between two samples of the resources
+    #[arg(long, default_value_t = 5)]
+    pub sample: u64,
+}
+
+/// A tiny xorshift generator, the sessions only need to differ.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn seeded(stream: u64) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Self((nanos ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Whether an event of probability `p` happens.
+    fn chance(&mut self, p: f64) -> bool {
+        (self.next() % 10_000) as f64 / 10_000.0 < p
+    }
+
+    fn below(&mut self, max: u64) -> u64 {
+        match max {
+            0 => 0,
+            max => self.next() % max,
+        }
+    }
+}
+
+/// Declines sessions at random, accepting everything else.
+struct RandomDecider {
+    decline: f64,
+    rng: std::sync::Mutex<Rng>,
+}
+
+#[async_trait]
+impl ReceiveDecider for RandomDecider {
+    async fn decide(&self, _sender: Device, files: Vec<FileDto>) -> Decision {
+        match self.rng.lock().unwrap().chance(self.decline) {
+            true => Decision::Decline,
+            false => Decision::Accept(files),
+        }
+    }
+}
+
+/// How the sessions of all senders ended.
+#[derive(Debug, Default)]
+struct Outcomes {
+    finished: AtomicUsize,
+    declined: AtomicUsize,
+    cancelled: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// Resources of the process and the server state at one point.
+#[derive(Debug, Clone, Default)]
+struct Sample {
+    elapsed: Duration,
+    /// Resident memory in bytes, Linux only
+    rss: Option<u64>,
+    /// Open file descriptors, Linux only
+    fds: Option<u64>,
+    /// Live tokio tasks, only counted in builds with `--cfg tokio_unstable`
+    tasks: Option<u64>,
+    state: StateStats,
+}
+
+fn sample(started: Instant, state: &ServerState) -> Sample {
+    Sample {
+        elapsed: started.elapsed(),
+        rss: resident_memory(),
+        fds: open_fds(),
+        tasks: live_tasks(),
+        state: state.stats(),
+    }
+}
+
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn open_fds() -> Option<u64> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
+
+// tokio only counts its tasks in unstable builds
+#[allow(unexpected_cfgs)]
+fn live_tasks() -> Option<u64> {
+    #[cfg(tokio_unstable)]
+    return Some(
+        tokio::runtime::Handle::current()
+            .metrics()
+            .active_tasks_count() as u64,
+    );
+    #[cfg(not(tokio_unstable))]
+    None
+}
+
+/// Whether `values` rose at every sample after the warm up, the sign of a leak
+/// rather than of a cache filling up and staying.
+fn grows_steadily(values: &[u64]) -> bool {
+    let values = values.get(WARM_UP_SAMPLES..).unwrap_or_default();
+    values.len() >= MIN_LEAK_SAMPLES
+        && values.windows(2).all(|pair| pair[1] >= pair[0])
+        && values.last() > values.first()
+}
+
+/// The resources that grew steadily over `samples`.
+fn leaks(samples: &[Sample]) -> Vec<&'static str> {
+    type Measure = fn(&Sample) -> Option<u64>;
+    let measures: [(&str, Measure); 6] = [
+        ("resident memory", |s| s.rss),
+        ("open file descriptors", |s| s.fds),
+        ("tokio tasks", |s| s.tasks),
+        ("event subscribers", |s| {
+            Some(s.state.event_subscribers as u64)
+        }),
+        ("status subscribers", |s| {
+            Some(s.state.status_subscribers as u64)
+        }),
+        ("send sessions", |s| Some(s.state.send_sessions as u64)),
+    ];
+    measures
+        .into_iter()
+        .filter(|(_, measure)| {
+            let values: Option<Vec<u64>> = samples.iter().map(measure).collect();
+            values.is_some_and(|values| grows_steadily(&values))
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Writes the files offered by a sender, reused by all of its sessions.
+fn write_files(dir: &Path, args: &StressArgs, rng: &mut Rng) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    (0..args.files)
+        .map(|i| {
+            let path = dir.join(format!("{}.bin", i));
+            let size = 1 + rng.below(args.size) as usize;
+            let content: Vec<u8> = (0..size).map(|_| rng.next() as u8).collect();
+            std::fs::write(&path, content)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+fn offer(paths: &[PathBuf], args: &StressArgs, rng: &mut Rng) -> Result<SendingFiles> {
+    let mut files = SendingFiles::default();
+    for (i, path) in paths.iter().enumerate() {
+        match rng.chance(args.texts) {
+            true => files.add_text(format!("stress message {} {}", i, rng.next()), true),
+            false => files.add_file(path, None)?,
+        }
+    }
+    Ok(files)
+}
+
+async fn run_sender(
+    id: usize,
+    receiver: Device,
+    args: StressArgs,
+    dir: PathBuf,
+    outcomes: Arc<Outcomes>,
+    stop: CancellationToken,
+) -> Result<()> {
+    let mut rng = Rng::seeded(id as u64 + 1);
+    let paths = write_files(&dir.join(format!("sender-{}", id)), &args, &mut rng)?;
+    let mut device = local_device(0);
+    device.fingerprint = format!("stress-{}", id);
+    device.alias = format!("Stress {}", id);
+    while !stop.is_cancelled() {
+        let files = offer(&paths, &args, &mut rng)?;
+        let session = SendSession::new(&device, receiver.clone(), &files);
+        let cancel = stop.child_token();
+        let cancelling = rng.chance(args.cancel);
+        if cancelling {
+            let cancel = cancel.clone();
+            let delay = Duration::from_millis(rng.below(50));
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                cancel.cancel();
+            });
+        }
+        let outcome = match session.upload(None, &cancel).await {
+            Ok(_) => &outcomes.finished,
+            Err(_) if cancel.is_cancelled() => &outcomes.cancelled,
+            Err(localsend_lib::Error::Send(localsend_lib::send::SendError::Rejected)) => {
+                &outcomes.declined
+            }
+            Err(e) => {
+                log::debug!("stress session failed: {}", e);
+                &outcomes.failed
+            }
+        };
+        outcome.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+fn local_device(port: u16) -> Device {
+    Device {
+        ip: "127.0.0.1".to_owned(),
+        version: PROTOCOL_VERSION_2.to_owned(),
+        port,
+        https: false,
+        fingerprint: "stress-receiver".to_owned(),
+        alias: "Stress Receiver".to_owned(),
+        device_model: None,
+        device_type: DeviceType::Headless,
+        download: false,
+    }
+}
+
+/// What a run found, see [`StressReport::passed`].
+#[derive(Debug)]
+pub struct StressReport {
+    samples: Vec<Sample>,
+    finished: usize,
+    declined: usize,
+    cancelled: usize,
+    failed: usize,
+    /// The state once all senders stopped, it should hold no sessions
+    idle: StateStats,
+    leaks: Vec<&'static str>,
+}
+
+impl StressReport {
+    pub fn sessions(&self) -> usize {
+        self.finished + self.declined + self.cancelled + self.failed
+    }
+
+    /// Whether nothing grew steadily and nothing was left in the state.
+    pub fn passed(&self) -> bool {
+        self.leaks.is_empty() && self.left_in_state().is_none()
+    }
+
+    fn left_in_state(&self) -> Option<String> {
+        let idle = &self.idle;
+        (idle.receive_sessions + idle.send_sessions + idle.queued_sessions > 0)
+            .then(|| format!("{:?}", idle))
+    }
+
+    pub fn print(&self) {
+        let mut table = Table::new();
+        table.set_header(vec!["Time", "Memory", "Files", "Tasks", "State"]);
+        let optional = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+        for sample in &self.samples {
+            table.add_row(vec![
+                format!("{}s", sample.elapsed.as_secs()),
+                sample.rss.map(format_size).unwrap_or_default(),
+                optional(sample.fds),
+                optional(sample.tasks),
+                format!(
+                    "{} sessions, {} files, {} subscribers",
+                    sample.state.receive_sessions + sample.state.send_sessions,
+                    sample.state.receive_files + sample.state.finished_files,
+                    sample.state.event_subscribers + sample.state.status_subscribers
+                ),
+            ]);
+        }
+        println!("{}", table);
+        println!(
+            "{} sessions: {} finished, {} declined, {} cancelled, {} failed",
+            self.sessions(),
+            self.finished,
+            self.declined,
+            self.cancelled,
+            self.failed
+        );
+        for leak in &self.leaks {
+            println!("Leak: {} grew at every sample", leak);
+        }
+        if let Some(left) = self.left_in_state() {
+            println!("Leak: the idle server state holds {}", left);
+        }
+    }
+}
+
+/// Loops sessions between a receiver and `args.parallel` senders for `args.duration`,
+/// sampling the resources every `args.sample`.
+pub async fn run_stress(args: &StressArgs) -> Result<StressReport> {
+    let dir = std::env::temp_dir().join(format!("localsend-stress-{}", std::process::id()));
+    let (server_tx, mut server_rx) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move { while server_rx.recv().await.is_some() {} });
+    let (_, client_rx) = tokio::sync::mpsc::channel(1);
+    let mut state = ServerState::new(server_tx, client_rx);
+    state.settings.destination = dir.join("received");
+    state.settings.max_pending_sessions = args.parallel;
+    state.settings.session_timeout = SESSION_TIMEOUT;
+    state.decider = Arc::new(RandomDecider {
+        decline: args.decline,
+        rng: std::sync::Mutex::new(Rng::seeded(0)),
+    });
+    let state: MutexServerState = Arc::new(tokio::sync::Mutex::new(state));
+    let server_cancel = CancellationToken::new();
+    let server = start_api_server(0, state.clone(), &server_cancel).await?;
+    server.ready().await;
+    let receiver = local_device(server.local_addr().port());
+
+    let started = Instant::now();
+    let stop = CancellationToken::new();
+    let outcomes = Arc::new(Outcomes::default());
+    let senders: Vec<_> = (0..args.parallel.max(1))
+        .map(|id| {
+            let sender = run_sender(
+                id,
+                receiver.clone(),
+                args.clone(),
+                dir.clone(),
+                outcomes.clone(),
+                stop.clone(),
+            );
+            tokio::spawn(sender)
+        })
+        .collect();
+
+    let mut samples = vec![sample(started, &*state.lock().await)];
+    let duration = Duration::from_secs(args.duration);
+    let interval =
+        Duration::from_secs(args.sample.max(1)).min(duration.max(Duration::from_millis(1)));
+    while started.elapsed() < duration {
+        tokio::time::sleep(interval.min(duration.saturating_sub(started.elapsed()))).await;
+        samples.push(sample(started, &*state.lock().await));
+    }
+    stop.cancel();
+    for sender in senders {
+        if let Ok(Err(e)) = sender.await {
+            log::error!("stress sender failed: {}", e);
+        }
+    }
+    // cancelled sessions end once the receiver noticed or they expired
+    let settle = Instant::now();
+    let idle = loop {
+        let stats = state.lock().await.stats();
+        if stats.receive_sessions == 0 || settle.elapsed() > SESSION_TIMEOUT * 3 {
+            break stats;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    server.shutdown().await?;
+    std::fs::remove_dir_all(&dir).ok();
+
+    let leaks = leaks(&samples);
+    Ok(StressReport {
+        samples,
+        finished: outcomes.finished.load(Ordering::Relaxed),
+        declined: outcomes.declined.load(Ordering::Relaxed),
+        cancelled: outcomes.cancelled.load(Ordering::Relaxed),
+        failed: outcomes.failed.load(Ordering::Relaxed),
+        idle,
+        leaks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grows_steadily, run_stress, StressArgs};
+
+    #[test]
+    fn test_grows_steadily() {
+        // the warm up may grow, so may a cache that fills once
+        assert!(!grows_steadily(&[1, 5, 9, 9, 9, 9]));
+        assert!(!grows_steadily(&[1, 2, 3, 4, 5]));
+        assert!(grows_steadily(&[1, 2, 3, 3, 4, 5]));
+        assert!(!grows_steadily(&[1, 2, 3, 5, 4, 6]));
+        assert!(!grows_steadily(&[]));
+    }
+
+    #[tokio::test]
+    async fn test_short_soak() {
+        let args = StressArgs {
+            duration: 2,
+            files: 3,
+            size: 64 * 1024,
+            texts: 0.3,
+            decline: 0.2,
+            cancel: 0.2,
+            parallel: 2,
+            sample: 1,
+        };
+        let report = run_stress(&args).await.unwrap();
+        assert!(report.sessions() > 0);
+        assert!(report.left_in_state().is_none(), "{:?}", report.idle);
+    }
+}
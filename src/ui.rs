@@ -1,71 +1,628 @@
-use std::{collections::HashMap, fmt::Write, future::Future, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    future::Future,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use colored::Colorize;
 use comfy_table::Table;
-use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    terminal::{self, ClearType},
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use inquire::{autocompletion::Replacement, validator::Validation, Autocomplete, CustomUserError};
 use localsend_lib::{
-    scanner::MulticastDeviceScanner,
-    send::{SendingFiles, UploadProgress},
+    diagnostics::{BindAdvice, CheckResult, CheckStatus},
+    progress::{ProgressEvent, ProgressStream},
+    receive::{check_destination_parent, PreviewFile, ReceiveReport},
+    scanner::{DeviceEvent, DeviceScanner, StaticDeviceProvider},
+    send::{FileStatus, FilterReport, PeerStats, SendError, SendingFiles, Target},
+    util::note::take_note,
     Error, Result,
 };
-use localsend_proto::{
-    dto::{FileDto, FileType},
-    Device,
+use localsend_proto::{dto::FileDto, Device};
+use tokio::sync::mpsc::Receiver;
+
+use crate::{
+    jobs::JobReport,
+    presentation::{format_size, sanitize_display, truncate_display, Theme},
 };
 
-const PROGRESS_BAR_NO_NERD_TICK_CHARS: &'static str = "+x*";
+const PROGRESS_BAR_NO_NERD_TICK_CHARS: &str = "+x*";
+
+/// Samples of the overall rate are at least this far apart.
+const RATE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Width below which the file bars leave out the elapsed time and the eta.
+const NARROW_WIDTH: u16 = 100;
+/// Plain progress lines are at least this far apart, unless a file ended.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+/// Offers with more files are summarized before they are listed.
+const LARGE_OFFER: usize = 500;
+
+/// How transfers show their progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Full on a terminal, compact otherwise
+    #[default]
+    Auto,
+    /// A bar for each file and a summary line
+    Full,
+    /// Only the summary line, plain lines when not on a terminal
+    Compact,
+    /// No progress at all, the final report is still printed
+    None,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ProgressMode::Auto),
+            "full" => Ok(ProgressMode::Full),
+            "compact" => Ok(ProgressMode::Compact),
+            "none" => Ok(ProgressMode::None),
+            _ => Err(format!("unknown progress mode: {}", s)),
+        }
+    }
+}
+
+impl ProgressMode {
+    /// Replaces `Auto` by the mode for an output that is a terminal or not.
+    fn resolve(self, is_terminal: bool) -> Self {
+        match self {
+            ProgressMode::Auto if is_terminal => ProgressMode::Full,
+            ProgressMode::Auto => ProgressMode::Compact,
+            mode => mode,
+        }
+    }
+}
+
+/// Order of the devices in the picker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceOrder {
+    /// Alphabetical
+    Alias,
+    /// Last sent to first
+    #[default]
+    Recent,
+    /// Most transfers first
+    Usage,
+}
+
+impl FromStr for DeviceOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "alias" => Ok(DeviceOrder::Alias),
+            "recent" => Ok(DeviceOrder::Recent),
+            "usage" => Ok(DeviceOrder::Usage),
+            _ => Err(format!("unknown sort order: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressOptions {
+    pub mode: ProgressMode,
+    pub use_nerd_fonts: bool,
+}
+
+/// Progress lines without control sequences, for logs and pipes.
+struct PlainProgress {
+    out: Box<dyn std::io::Write + Send>,
+    /// When the last line was written
+    last: Option<Instant>,
+}
 
 pub struct FileProgressBar {
     style: ProgressStyle,
+    finish_style: ProgressStyle,
     pbs: HashMap<String, ProgressBar>,
     files: HashMap<String, FileDto>,
+    /// The position of every file in display order, which numbers its bar
+    indices: HashMap<String, usize>,
+    alias: Option<String>,
+    multi: MultiProgress,
+    session: SessionProgress,
+    /// Overall progress below the file bars, shown for more than one file
+    summary: Option<ProgressBar>,
+    mode: ProgressMode,
+    /// Replaces the summary line in compact mode when stderr is not a terminal
+    plain: Option<PlainProgress>,
+    /// The session of every file when a send is split into several
+    sessions: HashMap<String, usize>,
+    session_count: usize,
+    /// The session of the latest event
+    current_session: Option<usize>,
 }
 
 impl FileProgressBar {
-    pub fn new(files: HashMap<String, FileDto>, use_nerd_fonts: bool) -> Self {
-        let mut style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} [{elapsed_precise}] [{msg}] [{bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+    /// Shows the progress of `files`, numbered in the order given.
+    pub fn new(files: Vec<FileDto>, options: ProgressOptions) -> Self {
+        let is_terminal = std::io::stderr().is_terminal();
+        let mode = options.mode.resolve(is_terminal);
+        let narrow = terminal::size().is_ok_and(|(width, _)| width < NARROW_WIDTH);
+        let template = if narrow {
+            "{prefix:.bold.dim} {spinner} [{msg}] [{bar:.cyan/blue}] {bytes}/{total_bytes}"
+        } else {
+            "{prefix:.bold.dim} {spinner} [{elapsed_precise}] [{msg}] [{bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})"
+        };
+        let mut style = ProgressStyle::with_template(template)
             .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+            .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+                write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
+            })
             .progress_chars("#>-");
-        if !use_nerd_fonts {
+        if !options.use_nerd_fonts {
             style = style.tick_chars(PROGRESS_BAR_NO_NERD_TICK_CHARS);
         }
+        let plain = (mode == ProgressMode::Compact && !is_terminal).then(|| PlainProgress {
+            out: Box::new(std::io::stderr()),
+            last: None,
+        });
         Self {
             style,
+            finish_style: ProgressStyle::with_template("{prefix:.bold.dim} [{msg}]").unwrap(),
             pbs: HashMap::new(),
-            files,
+            session: SessionProgress::new(&files, Instant::now()),
+            indices: HashMap::new(),
+            files: HashMap::new(),
+            alias: None,
+            multi: MultiProgress::new(),
+            summary: None,
+            mode,
+            plain,
+            sessions: HashMap::new(),
+            session_count: 0,
+            current_session: None,
         }
+        .with_files(files)
     }
 
-    pub fn update(&mut self, progress: UploadProgress) {
-        if let Some(pb) = self.pbs.get(&progress.file_id) {
-            pb.set_position(progress.position);
-            if progress.finish {
-                pb.finish();
-            }
+    fn with_files(mut self, files: Vec<FileDto>) -> Self {
+        for file in files {
+            self.push(file);
+        }
+        self
+    }
+
+    /// Appends a file after the others, a known one keeps its number.
+    fn push(&mut self, file: FileDto) {
+        let index = self.indices.len();
+        self.indices.entry(file.id.clone()).or_insert(index);
+        self.files.insert(file.id.clone(), file);
+    }
+
+    /// Shows which of the `chunks` of a split send the files belong to.
+    pub fn with_sessions(mut self, chunks: &[SendingFiles]) -> Self {
+        if chunks.len() > 1 {
+            self.sessions = chunks
+                .iter()
+                .enumerate()
+                .flat_map(|(index, chunk)| chunk.keys().map(move |id| (id.clone(), index)))
+                .collect();
+            self.session_count = chunks.len();
+        }
+        self
+    }
+
+    /// Writes compact progress as plain lines to `out`, as without a terminal.
+    #[cfg(test)]
+    fn with_plain_output(mut self, out: impl std::io::Write + Send + 'static) -> Self {
+        self.mode = ProgressMode::Compact;
+        self.plain = Some(PlainProgress {
+            out: Box::new(out),
+            last: None,
+        });
+        self
+    }
+
+    /// Groups the bars under `alias`, for sending to several devices at once.
+    pub fn for_device(mut self, alias: impl ToString, multi: &MultiProgress) -> Self {
+        self.alias = Some(sanitize_display(&alias.to_string()).into_owned());
+        self.multi = multi.clone();
+        self
+    }
+
+    /// Adds files selected after the transfer started.
+    pub fn add_files(&mut self, files: &[FileDto]) {
+        for file in files {
+            self.push(file.clone());
+        }
+        self.session.add_files(files);
+    }
+
+    /// Shows the events of `progress` until it ends.
+    pub async fn consume(mut self, mut progress: ProgressStream) {
+        while let Some(event) = progress.recv().await {
+            self.update(event);
+        }
+    }
+
+    pub fn update(&mut self, event: ProgressEvent) {
+        let Some(file_id) = event.file_id() else {
             return;
+        };
+        self.session.update(&event, Instant::now());
+        if let Some(index) = self.sessions.get(file_id) {
+            self.current_session = Some(*index);
+        }
+        if let ProgressEvent::Skipped {
+            reason: Some(reason),
+            ..
+        } = &event
+        {
+            self.print_filtered(file_id, reason);
+        }
+        match self.mode {
+            ProgressMode::Full | ProgressMode::Auto => {}
+            ProgressMode::Compact => return self.update_compact(&event),
+            ProgressMode::None => return,
+        }
+        self.update_summary();
+
+        match event {
+            ProgressEvent::Skipped { .. } => return,
+            ProgressEvent::Failed { .. } => {
+                if let Some(pb) = self.pbs.get(file_id) {
+                    pb.abandon_with_message(format!("{}: failed", self.file_name(file_id)));
+                }
+                return;
+            }
+            _ => {}
         }
 
-        let file = self.files.get(&progress.file_id).unwrap();
-        let index = self.files.values().position(|f| f.id == file.id).unwrap();
+        if let Some(pb) = self.pbs.get(file_id) {
+            pb.set_position(event.position());
+            if event.outcome().is_some() {
+                self.finish(pb, &event);
+            }
+            return;
+        }
 
+        let file = self.files.get(file_id).unwrap();
         let pb = indicatif::ProgressBar::new(file.size)
-            .with_prefix(format!("[{}/{}]", index + 1, self.files.len()))
+            .with_prefix(self.prefix(file_id))
             .with_style(self.style.clone())
-            .with_message(file.file_name.clone())
-            .with_position(progress.position);
+            .with_message(self.file_name(file_id).into_owned())
+            .with_position(event.position());
+        let pb = match &self.summary {
+            Some(summary) => self.multi.insert_before(summary, pb),
+            None => self.multi.add(pb),
+        };
+
+        if event.outcome().is_some() {
+            self.finish(&pb, &event);
+        }
+        self.pbs.insert(file_id.to_owned(), pb);
+    }
+
+    /// Numbers the bar of a file like "[2/5]", behind the device when grouped.
+    fn prefix(&self, file_id: &str) -> String {
+        let prefix = format!("[{}/{}]", self.indices[file_id] + 1, self.indices.len());
+        match &self.alias {
+            Some(alias) => format!("[{}] {}", alias, prefix),
+            None => prefix,
+        }
+    }
+
+    /// Removes the bars of unfinished files.
+    pub fn clear(&self) {
+        for pb in self.pbs.values().chain(&self.summary) {
+            if !pb.is_finished() {
+                pb.finish_and_clear();
+            }
+        }
+    }
+
+    fn update_summary(&mut self) {
+        if self.files.len() < 2 {
+            return;
+        }
+        let message = self.with_session(self.session.summary());
+        let summary = self.summary.get_or_insert_with(|| {
+            let style = ProgressStyle::with_template("{prefix:.bold.dim} {msg}").unwrap();
+            let pb = ProgressBar::new_spinner()
+                .with_style(style)
+                .with_prefix(self.alias.clone().unwrap_or_default());
+            self.multi.add(pb)
+        });
+        summary.set_message(message);
+        if self.session.remaining() == 0 {
+            summary.finish();
+        }
+    }
+
+    /// Updates the single line of compact mode, or writes a plain line when one is due.
+    fn update_compact(&mut self, event: &ProgressEvent) {
+        let mut line = self.with_session(self.session.compact());
+        if let Some(alias) = &self.alias {
+            line = format!("[{}] {}", alias, line);
+        }
+        let Some(plain) = &mut self.plain else {
+            let summary = self.summary.get_or_insert_with(|| {
+                let style = ProgressStyle::with_template("{msg}").unwrap();
+                self.multi.add(ProgressBar::new_spinner().with_style(style))
+            });
+            summary.set_message(line);
+            if self.session.remaining() == 0 {
+                summary.finish();
+            }
+            return;
+        };
+        let now = Instant::now();
+        let due = event.outcome().is_some()
+            || plain
+                .last
+                .map_or(true, |last| now.duration_since(last) >= PLAIN_INTERVAL);
+        if due {
+            plain.last = Some(now);
+            if let Err(e) = writeln!(plain.out, "{}", line) {
+                log::debug!("Failed to write progress: {}", e);
+            }
+        }
+    }
+
+    /// Tells which file the receiver's filters dropped, the other skips are left to the report.
+    fn print_filtered(&mut self, file_id: &str, reason: &str) {
+        let mut line = format!(
+            "{}: skipped by receiver: {}",
+            self.file_name(file_id),
+            sanitize_display(reason)
+        );
+        if let Some(alias) = &self.alias {
+            line = format!("[{}] {}", alias, line);
+        }
+        let written = match (&mut self.plain, self.mode) {
+            (_, ProgressMode::None) => return,
+            (Some(plain), _) => writeln!(plain.out, "{}", line),
+            (None, _) => self.multi.println(line),
+        };
+        if let Err(e) = written {
+            log::debug!("Failed to write progress: {}", e);
+        }
+    }
+
+    /// Prefixes `line` like "session 3/25 — " while a split send runs.
+    fn with_session(&self, line: String) -> String {
+        match self.current_session {
+            Some(index) => format!("session {}/{} — {}", index + 1, self.session_count, line),
+            None => line,
+        }
+    }
+
+    /// The name of a file as shown, empty for unknown ones.
+    fn file_name(&self, file_id: &str) -> Cow<'_, str> {
+        self.files
+            .get(file_id)
+            .map_or(Cow::Borrowed(""), |file| sanitize_display(&file.file_name))
+    }
+
+    fn finish(&self, pb: &ProgressBar, event: &ProgressEvent) {
+        let file_name = event
+            .file_id()
+            .map_or(Cow::Borrowed(""), |file_id| self.file_name(file_id));
+        pb.set_style(self.finish_style.clone());
+        pb.finish_with_message(format!(
+            "{}: {}",
+            file_name,
+            format_timing(event.elapsed(), event.speed())
+        ));
+    }
+}
+
+/// The received files in a stable display order. Offers carry their files as a
+/// JSON map, which keeps no order of the sender, so they are sorted by name.
+pub fn sorted_by_name(files: &[FileDto]) -> Vec<FileDto> {
+    let mut files = files.to_vec();
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name).then_with(|| a.id.cmp(&b.id)));
+    files
+}
+
+/// Bytes and files of a whole session, fed by the events of its files.
+///
+/// Only files of a known size count towards the bytes, skipped files and the
+/// untransferred rest of failed ones are left out.
+#[derive(Debug)]
+struct SessionProgress {
+    sizes: HashMap<String, u64>,
+    positions: HashMap<String, u64>,
+    outcomes: HashMap<String, FileStatus>,
+    /// Smoothed bytes per second
+    rate: f64,
+    /// Time and bytes of the last rate sample
+    sample: (Instant, u64),
+}
+
+impl SessionProgress {
+    fn new<'a>(files: impl IntoIterator<Item = &'a FileDto>, now: Instant) -> Self {
+        Self {
+            sizes: files
+                .into_iter()
+                .map(|file| (file.id.clone(), file.size))
+                .collect(),
+            positions: HashMap::new(),
+            outcomes: HashMap::new(),
+            rate: 0.0,
+            sample: (now, 0),
+        }
+    }
+
+    fn add_files(&mut self, files: &[FileDto]) {
+        for file in files {
+            self.sizes.insert(file.id.clone(), file.size);
+        }
+    }
+
+    fn update(&mut self, event: &ProgressEvent, now: Instant) {
+        let Some(file_id) = event.file_id() else {
+            return;
+        };
+        if event.position() > 0 {
+            self.positions.insert(file_id.to_owned(), event.position());
+        }
+        if let Some(outcome) = event.outcome() {
+            self.outcomes.insert(file_id.to_owned(), outcome);
+        }
+
+        let (since, bytes) = self.sample;
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed >= RATE_WINDOW {
+            let transferred = self.transferred();
+            let rate = transferred.saturating_sub(bytes) as f64 / elapsed.as_secs_f64();
+            self.rate = if self.rate == 0.0 {
+                rate
+            } else {
+                0.3 * rate + 0.7 * self.rate
+            };
+            self.sample = (now, transferred);
+        }
+    }
+
+    fn status(&self, file_id: &str) -> Option<&FileStatus> {
+        self.outcomes.get(file_id)
+    }
+
+    fn position(&self, file_id: &str) -> u64 {
+        self.positions.get(file_id).copied().unwrap_or_default()
+    }
+
+    fn transferred(&self) -> u64 {
+        self.sizes
+            .iter()
+            .filter(|(_, size)| **size > 0)
+            .map(|(id, size)| self.position(id).min(*size))
+            .sum()
+    }
+
+    fn total(&self) -> u64 {
+        self.sizes
+            .iter()
+            .map(|(id, size)| match self.status(id) {
+                Some(FileStatus::Skipped) => 0,
+                Some(FileStatus::Failed) => self.position(id).min(*size),
+                _ => *size,
+            })
+            .sum()
+    }
+
+    fn count(&self, status: FileStatus) -> usize {
+        self.outcomes.values().filter(|s| **s == status).count()
+    }
+
+    fn remaining(&self) -> usize {
+        self.sizes.len() - self.outcomes.len()
+    }
+
+    /// Time until the remaining bytes are transferred at the current rate.
+    fn eta(&self) -> Option<Duration> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+        let remaining = self.total().saturating_sub(self.transferred());
+        Some(Duration::from_secs_f64(remaining as f64 / self.rate))
+    }
+
+    /// Renders like "[12/300] 33% — 1.3 GB / 4.0 GB — 11 MB/s", short enough for one line.
+    fn compact(&self) -> String {
+        let total = self.total();
+        let percent = match total {
+            0 if self.remaining() == 0 => 100,
+            0 => 0,
+            _ => self.transferred() * 100 / total,
+        };
+        let mut parts = vec![
+            format!(
+                "[{}/{}] {}%",
+                self.outcomes.len(),
+                self.sizes.len(),
+                percent
+            ),
+            format!(
+                "{} / {}",
+                format_size(self.transferred()),
+                format_size(total)
+            ),
+        ];
+        if self.rate > 0.0 && self.remaining() > 0 {
+            parts.push(format!("{}/s", format_size(self.rate as u64)));
+        }
+        parts.join(" — ")
+    }
 
-        if progress.finish {
-            pb.finish();
+    /// Renders like "overall: 1.3 GB / 4.0 GB — 6m remaining — 11 MB/s — 12 done, 1 failed, 287 left".
+    fn summary(&self) -> String {
+        let mut parts = vec![format!(
+            "overall: {} / {}",
+            format_size(self.transferred()),
+            format_size(self.total())
+        )];
+        if let Some(eta) = self.eta().filter(|_| self.remaining() > 0) {
+            parts.push(format!("{} remaining", format_eta(eta)));
+            parts.push(format!("{}/s", format_size(self.rate as u64)));
         }
-        self.pbs.insert(progress.file_id, pb);
+        let mut files = format!("{} done", self.count(FileStatus::Finished));
+        let failed = self.count(FileStatus::Failed);
+        if failed > 0 {
+            files.push_str(&format!(", {} failed", failed));
+        }
+        files.push_str(&format!(", {} left", self.remaining()));
+        parts.push(files);
+        parts.join(" — ")
+    }
+}
+
+/// Formats a remaining time like "42s", "6m" or "1h 5m".
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
     }
 }
 
+/// Formats a finished transfer like "done in 12.4s (81 MB/s)".
+fn format_timing(duration: Duration, speed: f64) -> String {
+    format!(
+        "done in {:.1}s ({}/s)",
+        duration.as_secs_f64(),
+        format_size(speed as u64)
+    )
+}
+
+/// Renders `data` as a QR code with half-height unicode blocks, `None` when it does not fit.
+fn render_qr_code(data: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(data).ok()?;
+    Some(
+        code.render::<qrcode::render::unicode::Dense1x2>()
+            .dark_color(qrcode::render::unicode::Dense1x2::Light)
+            .light_color(qrcode::render::unicode::Dense1x2::Dark)
+            .build(),
+    )
+}
+
 #[async_trait]
 pub trait InteractiveUI {
-    async fn select_device(&self, scanner: &Arc<MulticastDeviceScanner>) -> Result<Device>;
+    async fn select_device(&self, scanner: &Arc<dyn DeviceScanner>) -> Result<Device>;
+
+    async fn select_devices(&self, scanner: &Arc<dyn DeviceScanner>) -> Result<Vec<Device>> {
+        Ok(vec![self.select_device(scanner).await?])
+    }
 
     async fn show_loading<T>(&self, message: String, task: T) -> T::Output
     where
@@ -74,91 +631,78 @@ pub trait InteractiveUI {
 
     fn select_files(&self, files: Vec<FileDto>) -> Option<Vec<FileDto>>;
 
+    /// Asks where to save the selected files, `None` keeps `default`.
+    fn choose_destination(&self, default: &Path) -> Option<PathBuf>;
+
+    /// Asks which of the quarantined files to keep, `None` keeps all of them.
+    fn review_files(&self, files: Vec<PreviewFile>) -> Option<Vec<PreviewFile>>;
+
+    /// Asks which of the devices matching `target` is meant.
+    fn select_candidate(&self, target: &Target, candidates: Vec<Device>) -> Option<Device>;
+
+    /// Prints the text messages as quotes and a table of the other files.
     fn print_files(&self, files: &SendingFiles);
 
+    fn print_filter_report(&self, report: &FilterReport);
+
+    fn print_send_summary(&self, results: &[(Device, Result<SendingFiles>)]);
+
+    fn print_batch_summary(&self, reports: &[JobReport]);
+
+    fn print_receive_report(&self, report: &ReceiveReport);
+
     fn print_error(&self, error: &Error);
 
+    fn print_text(&self, text: &str);
+
+    fn print_qr_code(&self, data: &str);
+
+    fn print_diagnostics(&self, results: &[CheckResult]);
+
+    /// Explains why a port could not be bound and asks for another one.
+    fn ask_other_port(&self, advice: &BindAdvice) -> Option<u16>;
+
     fn ask_continue(&self) -> bool;
+
+    /// Asks whether to send `texts`, inputs looking like paths that do not exist, as texts.
+    fn ask_send_as_text(&self, texts: &[&str]) -> bool;
+
+    /// Asks whether to go on with the next sessions after session `session` of
+    /// `sessions` of a split send failed.
+    fn ask_continue_sessions(&self, session: usize, sessions: usize, error: &Error) -> bool;
+
+    /// Asks what to do after sending to a single device failed.
+    fn after_failure(&self, error: &Error, files: &SendingFiles) -> NextAction;
 }
 
-#[derive(Clone)]
-pub struct PromptUI {
-    pub use_nerd_fonts: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub enum NextAction {
+    /// Send to the same device again
+    Retry,
+    /// Select another device
+    OtherDevice,
+    /// Send only the files with these ids to the same device
+    EditFiles(Vec<String>),
+    Quit,
 }
 
-impl Default for PromptUI {
-    fn default() -> Self {
-        Self {
-            use_nerd_fonts: true,
-        }
-    }
+#[derive(Clone, Default)]
+pub struct PromptUI {
+    pub theme: Theme,
+    /// Earlier sends by device fingerprint, shown and sorted by in the device picker
+    pub peer_stats: Arc<HashMap<String, PeerStats>>,
+    pub device_order: DeviceOrder,
 }
 
 #[async_trait]
 impl InteractiveUI for PromptUI {
-    async fn select_device(&self, scanner: &Arc<MulticastDeviceScanner>) -> Result<Device> {
-        loop {
-            let devices = {
-                let scanner = scanner.clone();
-                self.show_loading("Scanning".to_owned(), async move { scanner.scan().await })
-                    .await?
-            };
-
-            use colored::Colorize;
-            fn format_device_alias(device: &Device) -> String {
-                let (r, g, b) = match device.device_type {
-                    localsend_proto::DeviceType::Mobile => (95, 175, 0),
-                    localsend_proto::DeviceType::Desktop => (95, 175, 255),
-                    localsend_proto::DeviceType::Web => (0, 128, 128),
-                    localsend_proto::DeviceType::Headless => (95, 0, 175),
-                    localsend_proto::DeviceType::Server => (128, 0, 128),
-                };
-                let alias = device.alias.truecolor(r, g, b);
-                if let Some(model) = &device.device_model {
-                    format!("{} {}", model.truecolor(r, g, b), alias)
-                } else {
-                    format!("{}", alias)
-                }
-            }
-
-            enum SelectItem<'a> {
-                Refresh,
-                Device(&'a Device),
-            }
-
-            impl<'a> std::fmt::Display for SelectItem<'a> {
-                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                    match self {
-                        SelectItem::Refresh => {
-                            f.write_str("Refresh devices".bold().to_string().as_str())
-                        }
-                        SelectItem::Device(device) => {
-                            f.write_str(format_device_alias(device).as_str())
-                        }
-                    }
-                }
-            }
+    async fn select_device(&self, scanner: &Arc<dyn DeviceScanner>) -> Result<Device> {
+        let mut devices = self.pick_devices(scanner, false).await?;
+        Ok(devices.remove(0))
+    }
 
-            let mut items: Vec<SelectItem> = devices
-                .iter()
-                .map(|device| SelectItem::Device(device))
-                .collect();
-            items.insert(0, SelectItem::Refresh);
-
-            let selection = inquire::Select::new("Select the device you want to send to", items)
-                .with_help_message("↑↓ to move, enter to select, type to filter, esc to exit")
-                .with_vim_mode(true)
-                .prompt_skippable();
-            match selection {
-                Ok(Some(SelectItem::Refresh)) => {
-                    continue;
-                }
-                Ok(Some(SelectItem::Device(device))) => {
-                    return Ok(device.clone());
-                }
-                _ => std::process::exit(0),
-            }
-        }
+    async fn select_devices(&self, scanner: &Arc<dyn DeviceScanner>) -> Result<Vec<Device>> {
+        self.pick_devices(scanner, true).await
     }
 
     async fn show_loading<T>(&self, message: String, task: T) -> T::Output
@@ -167,7 +711,7 @@ impl InteractiveUI for PromptUI {
         T::Output: Send + 'static,
     {
         let mut style = ProgressStyle::default_spinner();
-        if !self.use_nerd_fonts {
+        if !self.theme.nerd_fonts() {
             style = style.tick_chars(PROGRESS_BAR_NO_NERD_TICK_CHARS);
         }
         let pb = indicatif::ProgressBar::new_spinner();
@@ -186,79 +730,1701 @@ impl InteractiveUI for PromptUI {
         output
     }
 
-    fn select_files(&self, files: Vec<FileDto>) -> Option<Vec<FileDto>> {
-        struct SelectItem<'a>(&'a PromptUI, &'a FileDto);
+    fn select_files(&self, mut files: Vec<FileDto>) -> Option<Vec<FileDto>> {
+        if let Some(note) = take_note(&mut files) {
+            println!("{} {}", "Note:".dimmed(), sanitize_display(&note).bold());
+        }
+        if files.len() > LARGE_OFFER && std::io::stdin().is_terminal() {
+            return self.select_large_offer(files);
+        }
+        self.multi_select_files("Select the files you want to receive", files)
+    }
+
+    fn choose_destination(&self, default: &Path) -> Option<PathBuf> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        let default = default.to_string_lossy().to_string();
+        // the default may hold placeholders, it is resolved per session
+        let keep = default.clone();
+        let answer = inquire::Text::new("Save to:")
+            .with_default(&default)
+            .with_help_message("enter to keep, tab to complete, ~ for your home directory")
+            .with_autocomplete(DirCompletion)
+            .with_validator(move |input: &str| {
+                if input == keep {
+                    return Ok(Validation::Valid);
+                }
+                Ok(match check_destination(&expand_tilde(input, home_dir())) {
+                    Ok(()) => Validation::Valid,
+                    Err(e) => Validation::Invalid(e.into()),
+                })
+            })
+            .prompt_skippable()
+            .ok()
+            .flatten()?;
+        (answer != default).then(|| expand_tilde(&answer, home_dir()))
+    }
+
+    fn review_files(&self, files: Vec<PreviewFile>) -> Option<Vec<PreviewFile>> {
+        struct SelectItem<'a>(&'a PromptUI, PreviewFile);
 
         impl<'a> std::fmt::Display for SelectItem<'a> {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                f.write_str(
-                    format!("{} {}", self.0.file_name(self.1), self.0.file_size(self.1)).as_str(),
+                let file = &self.1.file;
+                let path = self.1.path.display();
+                write!(
+                    f,
+                    "{} {} {}",
+                    self.0.file_name(file),
+                    self.0.file_size(file),
+                    path
                 )
             }
         }
 
-        let items: Vec<SelectItem> = files.iter().map(|file| SelectItem(self, &file)).collect();
-        let defaults: Vec<usize> = items.iter().enumerate().map(|(index, _)| index).collect();
-        let selection = inquire::MultiSelect::new("Select the files you want to receive", items)
-            .with_default(&defaults)
-            .with_help_message(
-                "↑↓ to move, space to select one, → to all, ← to none, type to filter, esc to cancel",
-            )
-            .with_vim_mode(true)
-            .prompt_skippable();
+        let items: Vec<SelectItem> = files.into_iter().map(|f| SelectItem(self, f)).collect();
+        let defaults: Vec<usize> = (0..items.len()).collect();
+        let selection = inquire::MultiSelect::new(
+            "Open the files and select the ones to keep",
+            items,
+        )
+        .with_default(&defaults)
+        .with_help_message(
+            "↑↓ to move, space to select one, → to all, ← to none, type to filter, esc to keep all",
+        )
+        .with_vim_mode(true)
+        .prompt_skippable();
         match selection {
-            Ok(Some(files)) => Some(files.into_iter().map(|f| f.1.to_owned()).collect()),
+            Ok(Some(items)) => Some(items.into_iter().map(|item| item.1).collect()),
+            _ => None,
+        }
+    }
+
+    fn select_candidate(&self, target: &Target, candidates: Vec<Device>) -> Option<Device> {
+        struct SelectItem(Device);
+
+        impl std::fmt::Display for SelectItem {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    f,
+                    "{} {} {}",
+                    sanitize_display(&self.0.alias),
+                    self.0.ip,
+                    sanitize_display(&self.0.fingerprint)
+                )
+            }
+        }
+
+        let message = format!("Several devices match {}, select one", target);
+        let items = candidates.into_iter().map(SelectItem).collect();
+        match inquire::Select::new(&message, items)
+            .with_vim_mode(true)
+            .prompt_skippable()
+        {
+            Ok(Some(item)) => Some(item.0),
             _ => None,
         }
     }
 
     fn print_files(&self, files: &SendingFiles) {
+        for file in files.texts() {
+            let text = file.text().unwrap_or_default();
+            println!("Sending message: {}", quote_message(text));
+        }
         let mut table = Table::new();
         table.set_header(vec!["No.", "Name", "Size"]);
-        for file in files.files.values() {
+        let mut size = 0;
+        for (number, file) in files.real_files().enumerate() {
+            size += file.file.size;
             table.add_row(vec![
-                &format!("{}", file.index + 1),
+                &format!("{}", number + 1),
                 &self.file_name(&file.file),
                 &self.file_size(&file.file),
             ]);
         }
-        println!("{}", table);
-    }
-
-    fn print_error(&self, error: &Error) {
-        println!("{}", error.to_string().bold().red());
+        if table.row_count() > 0 {
+            println!("{}", table);
+        }
+        let count = format_offer_count(files.real_files().count(), size, files.texts().count());
+        println!("{}", count.dimmed());
     }
 
-    fn ask_continue(&self) -> bool {
-        inquire::Confirm::new("Do you want to continue sending to other device?")
-            .with_default(true)
-            .with_help_message("enter to continue, other to exit")
-            .with_parser(&|s| Ok(s == "y" || s == "Y"))
-            .prompt_skippable()
-            .is_ok_and(|r| r == Some(true))
+    fn print_filter_report(&self, report: &FilterReport) {
+        if report.total() > 0 {
+            let rules = report
+                .excluded
+                .iter()
+                .map(|(rule, count)| format!("{} by {}", count, rule))
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!(
+                "{}",
+                format!("Excluded {} files ({})", report.total(), rules).yellow()
+            );
+        }
+        match report.symlinks.len() {
+            0 => {}
+            1 => println!("{}", "1 symlink skipped".yellow()),
+            count => println!("{}", format!("{} symlinks skipped", count).yellow()),
+        }
     }
-}
 
-impl PromptUI {
-    fn file_name(&self, file: &FileDto) -> String {
-        format!("{} {}", self.file_icon(&file.file_type), file.file_name)
+    fn print_send_summary(&self, results: &[(Device, Result<SendingFiles>)]) {
+        let mut table = Table::new();
+        table.set_header(vec!["Device", "Name", "Status", "Time"]);
+        for (device, result) in results {
+            match result {
+                Ok(files) => {
+                    for file in files.values() {
+                        let status = match file.status {
+                            FileStatus::Finished => "Finished".green(),
+                            FileStatus::Skipped => match &file.reason {
+                                Some(reason) => {
+                                    format!("Skipped: {}", sanitize_display(reason)).yellow()
+                                }
+                                None => "Skipped".yellow(),
+                            },
+                            FileStatus::Failed => "Failed".red(),
+                            FileStatus::Queue | FileStatus::Sending => "Incomplete".red(),
+                        };
+                        let time = match (file.duration(), file.speed()) {
+                            (Some(duration), Some(speed)) => format_timing(duration, speed),
+                            (Some(duration), None) => format!("{:.1}s", duration.as_secs_f64()),
+                            _ => String::default(),
+                        };
+                        // the receiver may save it under another name, e.g. after a collision
+                        let name = match &file.final_name {
+                            Some(final_name) => {
+                                let final_name = sanitize_display(final_name);
+                                format!("{} -> {}", self.file_name(&file.file), final_name)
+                            }
+                            None => match file.text() {
+                                Some(text) => quote_message(text),
+                                None => self.file_name(&file.file),
+                            },
+                        };
+                        let alias = sanitize_display(&device.alias).into_owned();
+                        table.add_row(vec![alias, name, status.to_string(), time]);
+                    }
+                }
+                Err(e) => {
+                    table.add_row(vec![
+                        sanitize_display(&device.alias).into_owned(),
+                        String::default(),
+                        e.to_string().red().to_string(),
+                        String::default(),
+                    ]);
+                }
+            }
+        }
+        println!("{}", table);
     }
 
-    fn file_icon(&self, file_type: &FileType) -> &'static str {
-        if !self.use_nerd_fonts {
-            return "";
+    fn print_batch_summary(&self, reports: &[JobReport]) {
+        let mut table = Table::new();
+        table.set_header(vec!["Job", "Device", "Files", "Result"]);
+        for (index, report) in reports.iter().enumerate() {
+            let device = match &report.device {
+                Some(device) => sanitize_display(&device.alias).into_owned(),
+                None => report.job.target.to_string(),
+            };
+            let (files, result) = match &report.result {
+                Some(Ok(files)) => {
+                    let finished = files
+                        .values()
+                        .filter(|file| file.status == FileStatus::Finished)
+                        .count();
+                    let result = if report.succeeded() {
+                        "Sent".green()
+                    } else {
+                        "Incomplete".red()
+                    };
+                    (format!("{}/{}", finished, files.len()), result)
+                }
+                Some(Err(e)) => (String::default(), e.to_string().red()),
+                None => (String::default(), "Not run".yellow()),
+            };
+            table.add_row(vec![
+                format!("{}", index + 1),
+                device,
+                files,
+                result.to_string(),
+            ]);
         }
-        match file_type {
-            FileType::Image => "󰈟",
-            FileType::Video => "󰈫",
-            FileType::Pdf => "󰈧",
-            FileType::Text => "󰈙",
-            FileType::Apk => "󰀲",
-            FileType::Other => "󰈔",
+        println!("{}", table);
+        let succeeded = reports.iter().filter(|report| report.succeeded()).count();
+        let summary = format!("{} of {} jobs succeeded", succeeded, reports.len());
+        if succeeded == reports.len() {
+            println!("{}", summary.green());
+        } else {
+            println!("{}", summary.red());
         }
     }
 
-    fn file_size(&self, file: &FileDto) -> String {
-        humansize::format_size(file.size, humansize::DECIMAL)
+    fn print_receive_report(&self, report: &ReceiveReport) {
+        let mut table = Table::new();
+        table.set_header(vec!["Name", "Saved to", "Size", "Status", "Time"]);
+        for file in &report.files {
+            let status = match file.status {
+                _ if file.duplicate_of.is_some() && file.status != FileStatus::Failed => {
+                    "Already have it".cyan()
+                }
+                FileStatus::Finished if file.appended_to_size.is_some() => "Appended".green(),
+                FileStatus::Finished => "Finished".green(),
+                FileStatus::Skipped => "Skipped".yellow(),
+                _ => "Failed".red(),
+            };
+            let status = match file.appended_to_size {
+                Some(size) => format!("{} ({} total)", status, format_size(size)),
+                None => status.to_string(),
+            };
+            let status = match &file.reason {
+                Some(reason) => format!("{}: {}", status, sanitize_display(reason)),
+                None => status,
+            };
+            let status = match &file.hook_error {
+                Some(error) => format!("{} ({}: {})", status, "hook failed".yellow(), error),
+                None => status,
+            };
+            let file_name = sanitize_display(&file.file_name);
+            let name = match &file.saved_name {
+                Some(saved_name) => format!("{} -> {}", file_name, sanitize_display(saved_name)),
+                None => file_name.into_owned(),
+            };
+            table.add_row(vec![
+                name,
+                file.path
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default(),
+                format_size(file.bytes),
+                status,
+                match (file.duration_secs, file.speed) {
+                    (Some(secs), Some(speed)) => {
+                        format_timing(Duration::from_secs_f64(secs), speed)
+                    }
+                    (Some(secs), None) => format!("{:.1}s", secs),
+                    _ => String::default(),
+                },
+            ]);
+        }
+        println!("{}", table);
+        let deduplicated = match report.deduplicated() {
+            0 => String::default(),
+            count => format!(" ({} already received before)", count),
+        };
+        if report.audited {
+            println!(
+                "Audited {}/{} files from {}, not saved, {} in {:.1}s ({}/s)",
+                report.finished(),
+                report.files.len(),
+                sanitize_display(&report.sender),
+                format_size(report.total_bytes),
+                report.duration_secs,
+                format_size(report.average_speed as u64),
+            );
+            return;
+        }
+        println!(
+            "Received {}/{} files{} from {} into {}, {} in {:.1}s ({}/s)",
+            report.finished(),
+            report.files.len(),
+            deduplicated,
+            sanitize_display(&report.sender),
+            report.destination.display(),
+            format_size(report.total_bytes),
+            report.duration_secs,
+            format_size(report.average_speed as u64),
+        );
+        if let Some(reason) = &report.destination_lost {
+            let message = format!("The session was aborted, {}", reason);
+            println!("{}", message.bold().red());
+        }
+    }
+
+    fn print_error(&self, error: &Error) {
+        println!("{}", error.to_string().bold().red());
+    }
+
+    fn print_text(&self, text: &str) {
+        println!("{}", text.bold());
+    }
+
+    fn print_diagnostics(&self, results: &[CheckResult]) {
+        let mut table = Table::new();
+        table.set_header(vec!["", "Check", "Result", "Hint"]);
+        for result in results {
+            let mark = match result.status {
+                CheckStatus::Ok => "✓".green(),
+                CheckStatus::Warning => "!".yellow(),
+                CheckStatus::Failed => "✗".red(),
+            };
+            table.add_row(vec![
+                mark.to_string(),
+                result.name.clone(),
+                result.detail.clone(),
+                result.hint.clone().unwrap_or_default(),
+            ]);
+        }
+        println!("{}", table);
+    }
+
+    fn print_qr_code(&self, data: &str) {
+        match render_qr_code(data) {
+            Some(code) => println!("{}", code),
+            None => log::warn!("{} is too long for a QR code", data),
+        }
+    }
+
+    fn ask_other_port(&self, advice: &BindAdvice) -> Option<u16> {
+        println!("{} {}", "✗".red(), advice.explanation);
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        let mut prompt = inquire::CustomType::<u16>::new("Retry with port:")
+            .with_error_message("Enter a port number")
+            .with_help_message("esc to exit");
+        if let Some(port) = advice.suggested_port {
+            prompt = prompt.with_default(port);
+        }
+        prompt.prompt_skippable().ok().flatten()
+    }
+
+    fn ask_continue(&self) -> bool {
+        inquire::Confirm::new("Do you want to continue sending to other device?")
+            .with_default(true)
+            .with_help_message("enter to continue, other to exit")
+            .with_parser(&|s| Ok(s == "y" || s == "Y"))
+            .prompt_skippable()
+            .is_ok_and(|r| r == Some(true))
+    }
+
+    fn ask_send_as_text(&self, texts: &[&str]) -> bool {
+        for text in texts {
+            println!(
+                "{} {} is neither a file nor a directory",
+                "!".yellow(),
+                quote_message(text)
+            );
+        }
+        inquire::Confirm::new("Send as text anyway?")
+            .with_default(false)
+            .prompt_skippable()
+            .is_ok_and(|r| r == Some(true))
+    }
+
+    fn ask_continue_sessions(&self, session: usize, sessions: usize, error: &Error) -> bool {
+        println!("{} Session {}/{}: {}", "✗".red(), session, sessions, error);
+        inquire::Confirm::new(&format!(
+            "Continue with the remaining {} sessions?",
+            sessions - session
+        ))
+        .with_default(true)
+        .with_help_message("enter to continue, esc to stop")
+        .prompt_skippable()
+        .is_ok_and(|r| r == Some(true))
+    }
+
+    fn after_failure(&self, error: &Error, files: &SendingFiles) -> NextAction {
+        const RETRY: &str = "Retry the same device";
+        const OTHER_DEVICE: &str = "Pick a different device";
+        const EDIT_FILES: &str = "Edit the file selection";
+        const QUIT: &str = "Quit";
+
+        loop {
+            // a busy device is worth another try, a declined one rather not
+            let starting_cursor = match error {
+                Error::Send(SendError::Busy) => 0,
+                _ => 1,
+            };
+            let action = inquire::Select::new(
+                "What do you want to do?",
+                vec![RETRY, OTHER_DEVICE, EDIT_FILES, QUIT],
+            )
+            .with_starting_cursor(starting_cursor)
+            .with_help_message("↑↓ to move, enter to select, esc to quit")
+            .with_vim_mode(true)
+            .prompt_skippable();
+            match action {
+                Ok(Some(RETRY)) => return NextAction::Retry,
+                Ok(Some(OTHER_DEVICE)) => return NextAction::OtherDevice,
+                Ok(Some(EDIT_FILES)) => {
+                    let files: Vec<FileDto> = files.values().map(|f| f.file.clone()).collect();
+                    // back to the menu when cancelled or nothing is left to send
+                    let selection = self.multi_select_files("Select the files to send", files);
+                    if let Some(files) = selection.filter(|f| !f.is_empty()) {
+                        return NextAction::EditFiles(files.into_iter().map(|f| f.id).collect());
+                    }
+                }
+                _ => return NextAction::Quit,
+            }
+        }
+    }
+}
+
+fn format_device_alias(device: &Device, theme: &Theme) -> String {
+    let style = theme.device_style(device);
+    let alias = style.paint(&sanitize_display(&device.alias));
+    let alias = if let Some(model) = &device.device_model {
+        format!("{} {}", style.paint(&sanitize_display(model)), alias)
+    } else {
+        alias
+    };
+    if device.download {
+        format!("{} {}", alias, "(download)".dimmed())
+    } else {
+        alias
+    }
+}
+
+/// "2 days ago", the largest unit only.
+fn format_ago(secs: u64) -> String {
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_owned(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+/// What the history knows about a device, e.g. "last sent 2 days ago, 14 transfers, 9.3 GB".
+fn format_peer_stats(stats: &PeerStats, now: u64) -> String {
+    let mut parts = vec![];
+    if let Some(last) = stats.last_success {
+        parts.push(format!(
+            "last sent {}",
+            format_ago(now.saturating_sub(last))
+        ));
+    }
+    parts.push(match stats.transfers {
+        1 => "1 transfer".to_owned(),
+        transfers => format!("{} transfers", transfers),
+    });
+    if stats.failures > 0 {
+        parts.push(format!("{} failed", stats.failures));
+    }
+    if stats.bytes > 0 {
+        parts.push(format_size(stats.bytes));
+    }
+    parts.join(", ")
+}
+
+/// Devices shown by the picker, the highlight sticks to a device rather than a row.
+#[derive(Default)]
+struct DeviceList {
+    devices: Vec<Device>,
+    /// Earlier sends by fingerprint, devices without any sort last
+    stats: Arc<HashMap<String, PeerStats>>,
+    order: DeviceOrder,
+    filter: String,
+    // fingerprint of the highlighted device
+    selected: Option<String>,
+    // fingerprints of the devices checked in multi-select mode
+    checked: Vec<String>,
+}
+
+impl DeviceList {
+    fn apply(&mut self, event: DeviceEvent) {
+        match event {
+            DeviceEvent::Found(device) => {
+                match self
+                    .devices
+                    .iter_mut()
+                    .find(|d| d.fingerprint == device.fingerprint)
+                {
+                    Some(existing) => *existing = device,
+                    None => self.devices.push(device),
+                }
+            }
+            DeviceEvent::Lost(device) => {
+                self.devices.retain(|d| d.fingerprint != device.fingerprint);
+            }
+        }
+    }
+
+    fn visible(&self) -> Vec<&Device> {
+        let filter = self.filter.to_lowercase();
+        let mut visible: Vec<&Device> = self
+            .devices
+            .iter()
+            .filter(|device| {
+                device.alias.to_lowercase().contains(&filter)
+                    || device
+                        .device_model
+                        .as_ref()
+                        .is_some_and(|model| model.to_lowercase().contains(&filter))
+            })
+            .collect();
+        visible.sort_by(|a, b| self.compare(a, b));
+        visible
+    }
+
+    fn compare(&self, a: &Device, b: &Device) -> Ordering {
+        let alias = || a.alias.to_lowercase().cmp(&b.alias.to_lowercase());
+        let (a_stats, b_stats) = (
+            self.stats.get(&a.fingerprint),
+            self.stats.get(&b.fingerprint),
+        );
+        let known = || b_stats.is_some().cmp(&a_stats.is_some());
+        match self.order {
+            DeviceOrder::Alias => alias(),
+            DeviceOrder::Recent => known()
+                .then_with(|| {
+                    let last = |stats: Option<&PeerStats>| stats.and_then(|s| s.last_success);
+                    last(b_stats).cmp(&last(a_stats))
+                })
+                .then_with(alias),
+            DeviceOrder::Usage => known()
+                .then_with(|| {
+                    let transfers = |stats: Option<&PeerStats>| stats.map_or(0, |s| s.transfers);
+                    transfers(b_stats).cmp(&transfers(a_stats))
+                })
+                .then_with(alias),
+        }
+    }
+
+    /// Index of the highlighted device in [`Self::visible`].
+    fn cursor(&self) -> Option<usize> {
+        let visible = self.visible();
+        if visible.is_empty() {
+            return None;
+        }
+        let index = self
+            .selected
+            .as_ref()
+            .and_then(|selected| visible.iter().position(|d| &d.fingerprint == selected));
+        Some(index.unwrap_or(0))
+    }
+
+    /// Pins the highlight to the device currently under the cursor.
+    fn pin(&mut self) {
+        self.selected = self
+            .cursor()
+            .map(|index| self.visible()[index].fingerprint.clone());
+    }
+
+    fn move_cursor(&mut self, up: bool) {
+        let Some(cursor) = self.cursor() else {
+            return;
+        };
+        let visible = self.visible();
+        let index = if up {
+            cursor.checked_sub(1).unwrap_or(visible.len() - 1)
+        } else {
+            (cursor + 1) % visible.len()
+        };
+        self.selected = Some(visible[index].fingerprint.clone());
+    }
+
+    /// Returns the pinned device if it is still available.
+    fn selection(&self) -> Option<&Device> {
+        let selected = self.selected.as_ref()?;
+        self.devices.iter().find(|d| &d.fingerprint == selected)
+    }
+
+    fn is_checked(&self, device: &Device) -> bool {
+        self.checked.contains(&device.fingerprint)
+    }
+
+    fn toggle(&mut self) {
+        let Some(selected) = self.selected.clone() else {
+            return;
+        };
+        match self.checked.iter().position(|f| f == &selected) {
+            Some(index) => {
+                self.checked.remove(index);
+            }
+            None => self.checked.push(selected),
+        }
+    }
+
+    /// Returns the checked devices, or the checked devices which are no longer available.
+    fn checked_devices(&self) -> std::result::Result<Vec<&Device>, Vec<String>> {
+        let mut devices = vec![];
+        let mut lost = vec![];
+        for fingerprint in &self.checked {
+            match self.devices.iter().find(|d| &d.fingerprint == fingerprint) {
+                Some(device) => devices.push(device),
+                None => lost.push(fingerprint.clone()),
+            }
+        }
+        if lost.is_empty() {
+            Ok(devices)
+        } else {
+            Err(lost)
+        }
+    }
+}
+
+/// Raw mode with a hidden cursor, the terminal is restored when dropped however
+/// the picker ends.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let raw = Self;
+        execute!(std::io::stdout(), cursor::Hide)?;
+        Ok(raw)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        execute!(std::io::stdout(), cursor::Show).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Device list that updates while the scanner is running.
+struct DevicePicker {
+    events: Receiver<DeviceEvent>,
+    list: DeviceList,
+    /// Tags the configured devices
+    static_devices: Option<StaticDeviceProvider>,
+    theme: Theme,
+    multiple: bool,
+    notice: Option<String>,
+    /// Seconds since the Unix epoch, for the time since the last send
+    now: u64,
+    tick: usize,
+    rendered_lines: u16,
+}
+
+impl DevicePicker {
+    const PAGE_SIZE: usize = 7;
+
+    fn new(
+        events: Receiver<DeviceEvent>,
+        static_devices: Option<StaticDeviceProvider>,
+        theme: Theme,
+        multiple: bool,
+    ) -> Self {
+        Self {
+            events,
+            list: DeviceList::default(),
+            now: 0,
+            static_devices,
+            theme,
+            multiple,
+            notice: None,
+            tick: 0,
+            rendered_lines: 0,
+        }
+    }
+
+    fn run(mut self) -> Result<Option<Vec<Device>>> {
+        let _raw = RawMode::enable()?;
+        let mut stdout = std::io::stdout();
+        let result = self.event_loop(&mut stdout);
+        self.clear(&mut stdout)?;
+        Ok(result?)
+    }
+
+    fn event_loop(
+        &mut self,
+        stdout: &mut impl std::io::Write,
+    ) -> std::io::Result<Option<Vec<Device>>> {
+        loop {
+            self.receive_events();
+            self.render(stdout)?;
+            if !event::poll(Duration::from_millis(100))? {
+                self.tick += 1;
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.notice = None;
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if ctrl => return Ok(None),
+                KeyCode::Up => self.list.move_cursor(true),
+                KeyCode::Char('p') if ctrl => self.list.move_cursor(true),
+                KeyCode::Down => self.list.move_cursor(false),
+                KeyCode::Char('n') if ctrl => self.list.move_cursor(false),
+                KeyCode::Char(' ') if self.multiple => self.list.toggle(),
+                KeyCode::Enter => {
+                    // the chosen devices may have been lost since the last render
+                    self.receive_events();
+                    if !self.list.checked.is_empty() {
+                        match self.list.checked_devices() {
+                            Ok(devices) => {
+                                return Ok(Some(devices.into_iter().cloned().collect()));
+                            }
+                            Err(lost) => {
+                                self.list.checked.retain(|f| !lost.contains(f));
+                                self.notice =
+                                    Some("Some devices are no longer available".to_owned());
+                            }
+                        }
+                        continue;
+                    }
+                    match self.list.selection() {
+                        Some(device) => return Ok(Some(vec![device.clone()])),
+                        None if self.list.selected.is_some() => {
+                            self.notice = Some("The device is no longer available".to_owned());
+                        }
+                        None => {}
+                    }
+                }
+                KeyCode::Backspace => {
+                    self.list.filter.pop();
+                }
+                KeyCode::Char(c) if !ctrl => self.list.filter.push(c),
+                _ => {}
+            }
+        }
+    }
+
+    /// Sorts and annotates the devices by the earlier sends in `stats`.
+    fn with_stats(mut self, stats: Arc<HashMap<String, PeerStats>>, order: DeviceOrder) -> Self {
+        self.list.stats = stats;
+        self.list.order = order;
+        self.now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self
+    }
+
+    fn stats_tag(&self, device: &Device) -> String {
+        match self.list.stats.get(&device.fingerprint) {
+            Some(stats) => format!(" {}", format_peer_stats(stats, self.now).dimmed()),
+            // only once there is a history to compare with
+            None if !self.list.stats.is_empty() => format!(" {}", "(new)".dimmed()),
+            None => String::default(),
+        }
+    }
+
+    fn static_tag(&self, device: &Device) -> String {
+        match &self.static_devices {
+            Some(provider) if provider.is_stale(&device.fingerprint) => {
+                format!(" {}", "(static, unreachable)".dimmed())
+            }
+            Some(provider) if provider.contains(&device.fingerprint) => {
+                format!(" {}", "(static)".dimmed())
+            }
+            _ => String::default(),
+        }
+    }
+
+    fn receive_events(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            self.list.apply(event);
+        }
+    }
+
+    fn clear(&mut self, stdout: &mut impl std::io::Write) -> std::io::Result<()> {
+        if self.rendered_lines > 0 {
+            queue!(stdout, cursor::MoveToPreviousLine(self.rendered_lines))?;
+        }
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::FromCursorDown)
+        )?;
+        self.rendered_lines = 0;
+        stdout.flush()
+    }
+
+    fn render(&mut self, stdout: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.list.pin();
+        let cursor = self.list.cursor();
+        let visible = self.list.visible();
+
+        let tick_chars: Vec<char> = if self.theme.nerd_fonts() {
+            "⠁⠂⠄⡀⢀⠠⠐⠈".chars().collect()
+        } else {
+            PROGRESS_BAR_NO_NERD_TICK_CHARS.chars().collect()
+        };
+        let tick = tick_chars[self.tick % tick_chars.len()];
+
+        let mut lines = vec![
+            format!(
+                "{} {} {}",
+                "?".green(),
+                if self.multiple {
+                    "Select the devices you want to send to".bold()
+                } else {
+                    "Select the device you want to send to".bold()
+                },
+                self.list.filter
+            ),
+            format!("  {} {}", tick.to_string().cyan(), "Scanning…".dimmed()),
+        ];
+        let start = cursor
+            .map(|c| (c + 1).saturating_sub(Self::PAGE_SIZE))
+            .unwrap_or(0);
+        for (index, device) in visible.iter().enumerate().skip(start).take(Self::PAGE_SIZE) {
+            let pointer = if Some(index) == cursor {
+                ">".cyan().to_string()
+            } else {
+                " ".to_owned()
+            };
+            let check = match (self.multiple, self.list.is_checked(device)) {
+                (false, _) => String::default(),
+                (true, true) => format!("{} ", "[x]".green()),
+                (true, false) => "[ ] ".to_owned(),
+            };
+            lines.push(format!(
+                "{} {}{}{}{}",
+                pointer,
+                check,
+                format_device_alias(device, &self.theme),
+                self.static_tag(device),
+                self.stats_tag(device)
+            ));
+        }
+        if let Some(notice) = &self.notice {
+            lines.push(notice.red().to_string());
+        }
+        let help = if self.multiple {
+            "[↑↓ to move, space to toggle, enter to select, type to filter, esc to exit]"
+        } else {
+            "[↑↓ to move, enter to select, type to filter, esc to exit]"
+        };
+        lines.push(help.cyan().to_string());
+
+        self.clear(stdout)?;
+        write!(stdout, "{}", lines.join("\r\n"))?;
+        self.rendered_lines = (lines.len() - 1) as u16;
+        stdout.flush()
+    }
+}
+
+impl PromptUI {
+    async fn pick_devices(
+        &self,
+        scanner: &Arc<dyn DeviceScanner>,
+        multiple: bool,
+    ) -> Result<Vec<Device>> {
+        let events = scanner.clone().subscribe();
+        let static_devices = scanner.static_devices().cloned();
+        let theme = self.theme.clone();
+        let (stats, order) = (self.peer_stats.clone(), self.device_order);
+        let selection = tokio::task::spawn_blocking(move || {
+            DevicePicker::new(events, static_devices, theme, multiple)
+                .with_stats(stats, order)
+                .run()
+        })
+        .await
+        .expect("Device picker panicked")?;
+        match selection {
+            Some(devices) => Ok(devices),
+            None => std::process::exit(0),
+        }
+    }
+
+    /// Summarizes an offer too large to list file by file, choosing files one by one
+    /// stays possible.
+    fn select_large_offer(&self, files: Vec<FileDto>) -> Option<Vec<FileDto>> {
+        const ACCEPT_ALL: &str = "Accept all";
+        const BY_FOLDER: &str = "Choose by folder";
+        const BY_FILE: &str = "Choose files";
+        const DECLINE: &str = "Decline";
+
+        let size: u64 = files.iter().map(|f| f.size).sum();
+        let message = format!("{} files, {}", files.len(), format_size(size));
+        loop {
+            let choice =
+                inquire::Select::new(&message, vec![ACCEPT_ALL, BY_FOLDER, BY_FILE, DECLINE])
+                    .with_help_message("↑↓ to move, enter to select, esc to decline")
+                    .with_vim_mode(true)
+                    .prompt_skippable();
+            let selection = match choice {
+                Ok(Some(ACCEPT_ALL)) => return Some(files),
+                Ok(Some(BY_FOLDER)) => self.select_folders(&files),
+                Ok(Some(BY_FILE)) => {
+                    self.multi_select_files("Select the files you want to receive", files.clone())
+                }
+                _ => return None,
+            };
+            // back to the summary when cancelled
+            if selection.is_some() {
+                return selection;
+            }
+        }
+    }
+
+    fn select_folders(&self, files: &[FileDto]) -> Option<Vec<FileDto>> {
+        struct SelectItem(FolderGroup);
+
+        impl std::fmt::Display for SelectItem {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let group = &self.0;
+                let name = match group.folder.as_str() {
+                    "" => "(loose files)".to_owned(),
+                    folder => format!("{}/", sanitize_display(folder)),
+                };
+                write!(
+                    f,
+                    "{} ({} files, {})",
+                    name,
+                    group.files.len(),
+                    format_size(group.size())
+                )
+            }
+        }
+
+        let items: Vec<SelectItem> = group_by_folder(files).into_iter().map(SelectItem).collect();
+        let defaults: Vec<usize> = (0..items.len()).collect();
+        let selection = inquire::MultiSelect::new("Select the folders you want to receive", items)
+            .with_default(&defaults)
+            .with_help_message(
+                "↑↓ to move, space to select one, → to all, ← to none, type to filter, esc to cancel",
+            )
+            .with_vim_mode(true)
+            .prompt_skippable();
+        match selection {
+            Ok(Some(groups)) => Some(groups.into_iter().flat_map(|g| g.0.files).collect()),
+            _ => None,
+        }
+    }
+
+    fn multi_select_files(&self, message: &str, files: Vec<FileDto>) -> Option<Vec<FileDto>> {
+        struct SelectItem<'a>(&'a PromptUI, &'a FileDto);
+
+        impl<'a> std::fmt::Display for SelectItem<'a> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(
+                    format!("{} {}", self.0.file_name(self.1), self.0.file_size(self.1)).as_str(),
+                )
+            }
+        }
+
+        let items: Vec<SelectItem> = files.iter().map(|file| SelectItem(self, file)).collect();
+        let defaults: Vec<usize> = items.iter().enumerate().map(|(index, _)| index).collect();
+        let selection = inquire::MultiSelect::new(message, items)
+            .with_default(&defaults)
+            .with_help_message(
+                "↑↓ to move, space to select one, → to all, ← to none, type to filter, esc to cancel",
+            )
+            .with_vim_mode(true)
+            .prompt_skippable();
+        match selection {
+            Ok(Some(files)) => Some(files.into_iter().map(|f| f.1.to_owned()).collect()),
+            _ => None,
+        }
+    }
+
+    fn file_name(&self, file: &FileDto) -> String {
+        format!(
+            "{} {}",
+            self.theme.file_icon(&file.file_type),
+            sanitize_display(&file.file_name)
+        )
+    }
+
+    fn file_size(&self, file: &FileDto) -> String {
+        format_size(file.size)
+    }
+}
+
+/// Columns of a message shown when sending it.
+const MESSAGE_QUOTE_LEN: usize = 60;
+
+/// A message on one line between guillemets, cut after [`MESSAGE_QUOTE_LEN`] columns.
+fn quote_message(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!("»{}«", truncate_display(&line, MESSAGE_QUOTE_LEN))
+}
+
+/// Like `2 files (1.50 kB), 1 message`, leaving out what is not sent.
+fn format_offer_count(files: usize, size: u64, messages: usize) -> String {
+    let mut parts = vec![];
+    if files > 0 || messages == 0 {
+        let noun = if files == 1 { "file" } else { "files" };
+        parts.push(format!("{} {} ({})", files, noun, format_size(size)));
+    }
+    if messages > 0 {
+        let noun = if messages == 1 { "message" } else { "messages" };
+        parts.push(format!("{} {}", messages, noun));
+    }
+    parts.join(", ")
+}
+
+/// The files of an offer below one top-level folder, `folder` is empty for files
+/// outside of any.
+#[derive(Debug)]
+struct FolderGroup {
+    folder: String,
+    files: Vec<FileDto>,
+}
+
+impl FolderGroup {
+    fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+}
+
+/// Groups `files` by the first component of their names, folders sorted by name.
+fn group_by_folder(files: &[FileDto]) -> Vec<FolderGroup> {
+    let mut groups: BTreeMap<String, Vec<FileDto>> = BTreeMap::new();
+    for file in files {
+        let (folder, _) = file.file_name.split_once('/').unwrap_or_default();
+        groups
+            .entry(folder.to_owned())
+            .or_default()
+            .push(file.clone());
+    }
+    groups
+        .into_iter()
+        .map(|(folder, files)| FolderGroup { folder, files })
+        .collect()
+}
+
+fn home_dir() -> Option<PathBuf> {
+    let home = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    std::env::var_os(home)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Replaces a leading `~` of `input` with `home`.
+fn expand_tilde(input: &str, home: Option<PathBuf>) -> PathBuf {
+    match (input.strip_prefix('~'), home) {
+        (Some(""), Some(home)) => home,
+        (Some(rest), Some(home)) if rest.starts_with(is_separator) => home.join(&rest[1..]),
+        _ => PathBuf::from(input),
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == std::path::MAIN_SEPARATOR
+}
+
+/// Checks that files can be saved below `dir` without creating anything, the
+/// directory or else its closest existing parent must take a probe file.
+fn check_destination(dir: &Path) -> std::result::Result<(), String> {
+    check_destination_parent(dir).map_err(|e| e.to_string())
+}
+
+/// Completes the last component of a path with the directories it may name.
+#[derive(Clone)]
+struct DirCompletion;
+
+impl Autocomplete for DirCompletion {
+    fn get_suggestions(
+        &mut self,
+        input: &str,
+    ) -> std::result::Result<Vec<String>, CustomUserError> {
+        Ok(complete_dirs(input, home_dir()))
+    }
+
+    fn get_completion(
+        &mut self,
+        input: &str,
+        highlighted: Option<String>,
+    ) -> std::result::Result<Replacement, CustomUserError> {
+        if highlighted.is_some() {
+            return Ok(highlighted);
+        }
+        let suggestions = complete_dirs(input, home_dir());
+        let common = common_prefix(&suggestions);
+        Ok((common.len() > input.len()).then(|| common.to_owned()))
+    }
+}
+
+/// The directories whose names continue the last component of `input`, written
+/// like `input` with a trailing separator. Hidden ones only when asked for.
+fn complete_dirs(input: &str, home: Option<PathBuf>) -> Vec<String> {
+    if input == "~" {
+        return vec!["~/".to_owned()];
+    }
+    let (parent, prefix) = match input.rfind(is_separator) {
+        Some(index) => input.split_at(index + 1),
+        None => ("", input),
+    };
+    let dir = match parent {
+        "" => PathBuf::from("."),
+        parent => expand_tilde(parent, home),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| {
+            name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.'))
+        })
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .take(20)
+        .map(|name| format!("{}{}/", parent, name))
+        .collect()
+}
+
+fn common_prefix(items: &[String]) -> &str {
+    let Some(first) = items.first() else {
+        return "";
+    };
+    let mut end = first.len();
+    for item in &items[1..] {
+        end = first
+            .char_indices()
+            .zip(item.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8())
+            .min(end);
+    }
+    &first[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use localsend_lib::{
+        progress::ProgressEvent,
+        scanner::{DeviceEvent, DeviceScanner, MockScanner},
+        send::{FileStatus, PeerStats, SendingFile, SendingFiles},
+    };
+    use localsend_proto::{
+        dto::{FileDto, FileType},
+        fixtures::device,
+    };
+
+    use super::{
+        check_destination, common_prefix, complete_dirs, expand_tilde, format_eta,
+        format_offer_count, format_peer_stats, format_timing, group_by_folder, quote_message,
+        render_qr_code, sorted_by_name, DeviceList, DeviceOrder, DevicePicker, FileProgressBar,
+        ProgressMode, ProgressOptions, SessionProgress,
+    };
+
+    #[test]
+    fn test_device_list_selection() {
+        let mut list = DeviceList::default();
+        list.apply(DeviceEvent::Found(device("a", 53317)));
+        list.apply(DeviceEvent::Found(device("b", 53317)));
+        list.pin();
+        list.move_cursor(false);
+        assert_eq!(list.selection().unwrap().alias, "b");
+
+        // the highlight follows the device when rows move
+        list.apply(DeviceEvent::Lost(device("a", 53317)));
+        list.apply(DeviceEvent::Found(device("c", 53317)));
+        assert_eq!(list.cursor(), Some(0));
+        assert_eq!(list.selection().unwrap().alias, "b");
+
+        // a lost device can not be selected even if it was highlighted
+        list.apply(DeviceEvent::Lost(device("b", 53317)));
+        assert!(list.selection().is_none());
+        list.pin();
+        assert_eq!(list.selection().unwrap().alias, "c");
+
+        list.filter.push('x');
+        list.pin();
+        assert!(list.cursor().is_none());
+        assert!(list.selection().is_none());
+    }
+
+    /// Receives the events of the picker until it lists `aliases`.
+    async fn wait_for_devices(picker: &mut DevicePicker, aliases: &[&str]) {
+        let listed = |picker: &DevicePicker| -> Vec<String> {
+            let visible = picker.list.visible();
+            visible.iter().map(|d| d.alias.clone()).collect()
+        };
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            picker.receive_events();
+            if listed(picker) == aliases {
+                return;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "listed {:?}, expected {:?}",
+                listed(picker),
+                aliases
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_device_picker_follows_scanner() {
+        let ms = Duration::from_millis;
+        let addr = "192.168.1.9:53317".parse().unwrap();
+        let scanner: Arc<dyn DeviceScanner> = Arc::new(
+            MockScanner::new([device("a", 53317)])
+                .add_at(ms(50), device("b", 53317))
+                .packet_at(ms(80), &b"not an announcement"[..], addr)
+                .remove_at(ms(100), &device("a", 53317))
+                .add_at(ms(150), device("a", 53317)),
+        );
+        let mut picker = DevicePicker::new(
+            scanner.clone().subscribe(),
+            scanner.static_devices().cloned(),
+            Default::default(),
+            false,
+        );
+        wait_for_devices(&mut picker, &["a"]).await;
+        picker.list.pin();
+        assert_eq!(picker.list.selection().unwrap().alias, "a");
+
+        wait_for_devices(&mut picker, &["a", "b"]).await;
+        // the highlighted device disappears and can not be picked
+        wait_for_devices(&mut picker, &["b"]).await;
+        assert!(picker.list.selection().is_none());
+        picker.list.pin();
+        assert_eq!(picker.list.selection().unwrap().alias, "b");
+
+        // and comes back, the malformed packet changed nothing
+        wait_for_devices(&mut picker, &["a", "b"]).await;
+    }
+
+    #[test]
+    fn test_device_list_checked() {
+        let mut list = DeviceList::default();
+        list.apply(DeviceEvent::Found(device("a", 53317)));
+        list.apply(DeviceEvent::Found(device("b", 53317)));
+        list.pin();
+        list.toggle();
+        list.move_cursor(false);
+        list.toggle();
+        let checked: Vec<&str> = list
+            .checked_devices()
+            .unwrap()
+            .iter()
+            .map(|d| d.alias.as_str())
+            .collect();
+        assert_eq!(checked, vec!["a", "b"]);
+
+        list.toggle();
+        list.apply(DeviceEvent::Lost(device("a", 53317)));
+        assert_eq!(list.checked_devices().unwrap_err(), vec!["a".to_owned()]);
+    }
+
+    #[test]
+    fn test_device_list_order() {
+        let stats = |transfers, last_success| PeerStats {
+            transfers,
+            failures: 0,
+            bytes: 0,
+            last_success,
+        };
+        let mut list = DeviceList {
+            stats: Arc::new(HashMap::from([
+                ("b".to_owned(), stats(1, Some(200))),
+                ("d".to_owned(), stats(5, Some(100))),
+                ("c".to_owned(), stats(2, None)),
+            ])),
+            ..Default::default()
+        };
+        for alias in ["e", "d", "c", "b", "a"] {
+            list.apply(DeviceEvent::Found(device(alias, 53317)));
+        }
+        let order = |list: &DeviceList| -> Vec<String> {
+            list.visible().iter().map(|d| d.alias.clone()).collect()
+        };
+        assert_eq!(order(&list), vec!["b", "d", "c", "a", "e"]);
+        list.order = DeviceOrder::Usage;
+        assert_eq!(order(&list), vec!["d", "c", "b", "a", "e"]);
+        list.order = DeviceOrder::Alias;
+        assert_eq!(order(&list), vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_format_peer_stats() {
+        let stats = PeerStats {
+            transfers: 14,
+            failures: 0,
+            bytes: 9_300_000_000,
+            last_success: Some(1_000),
+        };
+        assert_eq!(
+            format_peer_stats(&stats, 1_000 + 2 * 86400 + 5),
+            "last sent 2 days ago, 14 transfers, 9.30 GB"
+        );
+        let failed = PeerStats {
+            transfers: 1,
+            failures: 1,
+            ..Default::default()
+        };
+        assert_eq!(format_peer_stats(&failed, 0), "1 transfer, 1 failed");
+    }
+
+    #[test]
+    fn test_format_timing() {
+        assert_eq!(
+            format_timing(Duration::from_millis(12400), 81_000_000.0),
+            "done in 12.4s (81 MB/s)"
+        );
+        assert_eq!(format_timing(Duration::ZERO, 0.0), "done in 0.0s (0 B/s)");
+    }
+
+    #[test]
+    fn test_render_qr_code() {
+        let code = render_qr_code("http://192.168.1.2:53317/").unwrap();
+        let lines: Vec<&str> = code.lines().collect();
+        assert!(lines.len() > 10);
+        assert!(lines
+            .iter()
+            .all(|l| l.chars().count() == lines[0].chars().count()));
+        assert!(render_qr_code(&"x".repeat(8000)).is_none());
+    }
+
+    fn file(id: &str, size: u64) -> FileDto {
+        FileDto {
+            id: id.to_owned(),
+            file_name: format!("{}.bin", id),
+            size,
+            file_type: FileType::Other,
+            hash: None,
+            preview: None,
+        }
+    }
+
+    fn progress(id: &str, position: u64, status: FileStatus) -> ProgressEvent {
+        let (file_id, elapsed) = (id.to_owned(), Duration::from_secs(1));
+        match status {
+            FileStatus::Finished => ProgressEvent::Finished {
+                file_id,
+                position,
+                elapsed,
+            },
+            FileStatus::Failed => ProgressEvent::Failed { file_id },
+            FileStatus::Skipped => ProgressEvent::Skipped {
+                file_id,
+                reason: None,
+            },
+            _ => ProgressEvent::Position {
+                file_id,
+                position,
+                elapsed,
+            },
+        }
+    }
+
+    #[test]
+    fn test_session_progress() {
+        let files = [
+            file("a", 100_000),
+            file("b", 0),
+            file("c", 300_000),
+            file("d", 50_000),
+        ];
+        let start = Instant::now();
+        let mut session = SessionProgress::new(&files, start);
+
+        session.update(&progress("a", 50_000, FileStatus::Sending), start);
+        // the receiver skips d, it never counts
+        session.update(&progress("d", 0, FileStatus::Skipped), start);
+        session.update(
+            &progress("a", 100_000, FileStatus::Finished),
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(session.total(), 400_000);
+        assert_eq!(session.transferred(), 100_000);
+        assert_eq!(session.eta(), Some(Duration::from_secs(3)));
+        assert_eq!(
+            session.summary(),
+            "overall: 100 kB / 400 kB — 3s remaining — 100 kB/s — 1 done, 2 left"
+        );
+
+        // only the transferred part of a failed file is left in the total
+        session.update(
+            &progress("c", 100_000, FileStatus::Sending),
+            start + Duration::from_millis(1200),
+        );
+        session.update(
+            &progress("c", 0, FileStatus::Failed),
+            start + Duration::from_millis(1300),
+        );
+        // empty files only count as files
+        session.update(
+            &progress("b", 0, FileStatus::Finished),
+            start + Duration::from_millis(1400),
+        );
+        assert_eq!(session.total(), 200_000);
+        assert_eq!(session.remaining(), 0);
+        assert_eq!(
+            session.summary(),
+            "overall: 200 kB / 200 kB — 2 done, 1 failed, 0 left"
+        );
+    }
+
+    /// Collects what the progress writes, shared with the test.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_compact_progress_without_terminal() {
+        let files = vec![file("a", 100_000), file("b", 300_000)];
+        let options = ProgressOptions {
+            mode: ProgressMode::Compact,
+            use_nerd_fonts: true,
+        };
+        let output = Output::default();
+        let mut pb = FileProgressBar::new(files, options).with_plain_output(output.clone());
+
+        pb.update(progress("a", 50_000, FileStatus::Sending));
+        // within the interval and no file ended, nothing is written
+        pb.update(progress("a", 60_000, FileStatus::Sending));
+        pb.update(progress("a", 100_000, FileStatus::Finished));
+        pb.update(progress("b", 0, FileStatus::Failed));
+        pb.clear();
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains('\x1b'));
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("[0/2] 12% — 50 kB / 400 kB"));
+        assert_eq!(lines[2], "[2/2] 100% — 100 kB / 100 kB");
+    }
+
+    #[test]
+    fn test_progress_escapes_peer_strings() {
+        let mut spoofed = file("a", 100);
+        spoofed.file_name = "\x1b]0;title\x07a.txt".to_owned();
+        let files = vec![spoofed];
+        let options = ProgressOptions {
+            mode: ProgressMode::Compact,
+            use_nerd_fonts: true,
+        };
+        let output = Output::default();
+        let mut pb = FileProgressBar::new(files, options)
+            .for_device("\x1b[2Jphone", &indicatif::MultiProgress::new())
+            .with_plain_output(output.clone());
+
+        pb.update(ProgressEvent::Skipped {
+            file_id: "a".to_owned(),
+            reason: Some("\u{202E}txt.exe".to_owned()),
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains(['\x1b', '\x07', '\u{202E}']));
+        assert!(output.starts_with(
+            "[\\u{1b}[2Jphone] \\u{1b}]0;title\\u{7}a.txt: skipped by receiver: \\u{202e}txt.exe"
+        ));
+    }
+
+    #[test]
+    fn test_progress_of_split_send() {
+        let files = [file("a", 100), file("b", 100), file("c", 100)];
+        let chunks: Vec<SendingFiles> = [&files[..2], &files[2..]]
+            .iter()
+            .map(|chunk| {
+                let mut sending = SendingFiles::default();
+                for file in chunk.iter() {
+                    sending.push(SendingFile::new(file.clone(), None));
+                }
+                sending
+            })
+            .collect();
+        let options = ProgressOptions {
+            mode: ProgressMode::Compact,
+            use_nerd_fonts: true,
+        };
+        let output = Output::default();
+        let mut pb = FileProgressBar::new(files.to_vec(), options)
+            .with_sessions(&chunks)
+            .with_plain_output(output.clone());
+
+        pb.update(progress("a", 100, FileStatus::Finished));
+        pb.update(progress("c", 100, FileStatus::Finished));
+        pb.clear();
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("session 1/2 — [1/3]"));
+        assert!(lines[1].starts_with("session 2/2 — [2/3]"));
+    }
+
+    #[test]
+    fn test_numbering_of_sent_files() {
+        let mut sending = SendingFiles::default();
+        for text in ["z", "a", "m", "b"] {
+            sending.add_text(text, true);
+        }
+        let options = ProgressOptions {
+            mode: ProgressMode::Full,
+            use_nerd_fonts: true,
+        };
+        let pb = FileProgressBar::new(sending.to_dto_list(), options);
+
+        // numbered as added, whatever the names or ids
+        for (index, id) in sending.keys().enumerate() {
+            assert_eq!(pb.prefix(id), format!("[{}/4]", index + 1));
+            assert_eq!(sending.get(id).unwrap().index, index);
+        }
+    }
+
+    #[test]
+    fn test_numbering_of_received_files() {
+        let named = |id: &str, name: &str| FileDto {
+            file_name: name.to_owned(),
+            ..file(id, 1)
+        };
+        let offered = [
+            named("1", "c.txt"),
+            named("2", "a.txt"),
+            named("3", "b.txt"),
+        ];
+        let options = ProgressOptions {
+            mode: ProgressMode::Full,
+            use_nerd_fonts: true,
+        };
+        let mut reversed = offered.to_vec();
+        reversed.reverse();
+        let prefixes = |pb: &FileProgressBar| -> Vec<String> {
+            let mut ids: Vec<&String> = pb.files.keys().collect();
+            ids.sort();
+            ids.into_iter().map(|id| pb.prefix(id)).collect()
+        };
+
+        // the same numbers however the offer was ordered
+        let mut pb = FileProgressBar::new(sorted_by_name(&offered), options);
+        let other = FileProgressBar::new(sorted_by_name(&reversed), options);
+        pb.add_files(&[named("4", "0.txt")]);
+        assert_eq!(prefixes(&pb), ["[3/4]", "[1/4]", "[2/4]", "[4/4]"]);
+        assert_eq!(prefixes(&other), ["[3/3]", "[1/3]", "[2/3]"]);
+
+        // added again, a file keeps its number
+        pb.add_files(&[named("2", "a.txt")]);
+        assert_eq!(pb.prefix("2"), "[1/4]");
+    }
+
+    #[test]
+    fn test_group_by_folder() {
+        let named = |id: &str, name: &str, size: u64| FileDto {
+            file_name: name.to_owned(),
+            ..file(id, size)
+        };
+        let files = [
+            named("1", "photos/2024/a.jpg", 10),
+            named("2", "notes.txt", 1),
+            named("3", "docs/b.pdf", 5),
+            named("4", "photos/c.jpg", 20),
+        ];
+        let groups = group_by_folder(&files);
+        let summary: Vec<(&str, usize, u64)> = groups
+            .iter()
+            .map(|g| (g.folder.as_str(), g.files.len(), g.size()))
+            .collect();
+        assert_eq!(summary, vec![("", 1, 1), ("docs", 1, 5), ("photos", 2, 30)]);
+        // files keep the order of the offer within a folder
+        assert_eq!(groups[2].files[0].id, "1");
+    }
+
+    #[test]
+    fn test_parse_progress_mode() {
+        assert_eq!("compact".parse(), Ok(ProgressMode::Compact));
+        assert_eq!(ProgressMode::Auto.resolve(true), ProgressMode::Full);
+        assert_eq!(ProgressMode::Auto.resolve(false), ProgressMode::Compact);
+        assert_eq!(ProgressMode::None.resolve(true), ProgressMode::None);
+        assert!("quiet".parse::<ProgressMode>().is_err());
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(Duration::from_secs(42)), "42s");
+        assert_eq!(format_eta(Duration::from_secs(6 * 60 + 59)), "6m");
+        assert_eq!(format_eta(Duration::from_secs(3900)), "1h 5m");
+    }
+
+    #[test]
+    fn test_quote_message() {
+        assert_eq!(quote_message("meet at 7pm"), "»meet at 7pm«");
+        assert_eq!(quote_message("two\nlines "), "»two lines«");
+        let long = "ä".repeat(70);
+        assert_eq!(quote_message(&long), format!("»{}…«", "ä".repeat(60)));
+        let wide = "字".repeat(40);
+        assert_eq!(quote_message(&wide), format!("»{}…«", "字".repeat(30)));
+        assert_eq!(quote_message("\x1b[8mhidden"), "»\\u{1b}[8mhidden«");
+    }
+
+    #[test]
+    fn test_format_offer_count() {
+        assert_eq!(
+            format_offer_count(2, 1500, 1),
+            "2 files (1.50 kB), 1 message"
+        );
+        assert_eq!(format_offer_count(0, 0, 2), "2 messages");
+        assert_eq!(format_offer_count(1, 3, 0), "1 file (3 B)");
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = Some(std::path::PathBuf::from("/home/me"));
+        assert_eq!(
+            expand_tilde("~", home.clone()),
+            std::path::Path::new("/home/me")
+        );
+        assert_eq!(
+            expand_tilde("~/Pictures", home.clone()),
+            std::path::Path::new("/home/me/Pictures")
+        );
+        // only the own home directory is known
+        assert_eq!(
+            expand_tilde("~bob/x", home.clone()),
+            std::path::Path::new("~bob/x")
+        );
+        assert_eq!(expand_tilde("/srv/~", home), std::path::Path::new("/srv/~"));
+        assert_eq!(expand_tilde("~/x", None), std::path::Path::new("~/x"));
+    }
+
+    #[test]
+    fn test_check_destination() {
+        let dir = std::env::temp_dir().join(format!("localsend-dest-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Photos")).unwrap();
+        std::fs::write(dir.join("file"), "").unwrap();
+
+        assert_eq!(check_destination(&dir.join("Photos")), Ok(()));
+        // created by the first file saved, nothing is created by the check
+        assert_eq!(check_destination(&dir.join("new/nested")), Ok(()));
+        assert!(!dir.join("new").exists());
+        let error = check_destination(&dir.join("file/below")).unwrap_err();
+        assert!(error.ends_with("is not a directory"), "{}", error);
+        assert!(std::fs::read_dir(&dir).unwrap().count() == 2);
+
+        let input = format!("{}/P", dir.display());
+        assert_eq!(
+            complete_dirs(&input, None),
+            vec![format!("{}/Photos/", dir.display())]
+        );
+        assert!(complete_dirs(&format!("{}/x", dir.display()), None).is_empty());
+        assert_eq!(complete_dirs("~", None), vec!["~/"]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let items = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&items(&["Photos/", "Phones/"])), "Pho");
+        assert_eq!(common_prefix(&items(&["Música/", "Músicas/"])), "Música");
+        assert_eq!(common_prefix(&items(&["a/", "b/"])), "");
+        assert_eq!(common_prefix(&[]), "");
     }
 }
@@ -0,0 +1,327 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use localsend_lib::util::hash::FileHash;
+use semver::Version;
+use serde::Deserialize;
+
+pub const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/zpp0196/localsend-rs/releases/latest";
+/// Lists the sha256 of every artifact of a release, as written by `sha256sum`.
+pub const CHECKSUMS_ASSET: &str = "sha256sums.txt";
+/// Kept in the data directory, the last time the interactive UI looked for an update.
+pub const CHECK_STAMP_FILE: &str = "update-check";
+/// The interactive UI looks for an update at most this often.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+/// A newer release with an artifact for this platform.
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub version: Version,
+    pub artifact: Asset,
+    pub checksums: Asset,
+}
+
+/// Downloads over HTTP, replaced by canned responses in tests.
+#[async_trait]
+pub trait Fetch: Send + Sync {
+    async fn get(&self, url: &str) -> Result<Vec<u8>, String>;
+}
+
+pub struct HttpFetch(reqwest::Client);
+
+impl HttpFetch {
+    pub fn new() -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            // required by the GitHub API
+            .user_agent(concat!("localsend-rs/", env!("CARGO_PKG_VERSION")))
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self(client))
+    }
+}
+
+#[async_trait]
+impl Fetch for HttpFetch {
+    async fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+        let response = self
+            .0
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to get {}: {}", url, e))?;
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to get {}: {}", url, e))?;
+        Ok(body.to_vec())
+    }
+}
+
+pub fn current_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("Invalid package version")
+}
+
+/// Name of the release artifact of this platform, e.g. `localsend-x86_64-linux`.
+pub fn artifact_name() -> String {
+    format!(
+        "localsend-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// Looks up the latest stable release, `None` when it is not newer than `current`.
+pub async fn find_update(
+    fetch: &dyn Fetch,
+    current: &Version,
+    artifact: &str,
+) -> Result<Option<Update>, String> {
+    let body = fetch.get(LATEST_RELEASE_URL).await?;
+    let release: Release =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid release: {}", e))?;
+    let version = Version::parse(release.tag_name.trim_start_matches('v'))
+        .map_err(|e| format!("Invalid release version {:?}: {}", release.tag_name, e))?;
+    if release.draft || release.prerelease || !version.pre.is_empty() || version <= *current {
+        return Ok(None);
+    }
+    let asset = |name: &str| release.assets.iter().find(|asset| asset.name == name);
+    let artifact = asset(artifact)
+        .ok_or_else(|| format!("Release {} has no {}", version, artifact))?
+        .clone();
+    let checksums = asset(CHECKSUMS_ASSET)
+        .ok_or_else(|| format!("Release {} has no {}", version, CHECKSUMS_ASSET))?
+        .clone();
+    Ok(Some(Update {
+        version,
+        artifact,
+        checksums,
+    }))
+}
+
+/// The digest of `name` in a `sha256sum` listing, binary mode names start with `*`.
+pub fn checksum_of<'a>(checksums: &'a str, name: &str) -> Option<&'a str> {
+    checksums.lines().find_map(|line| {
+        let (hash, file) = line.trim().split_once(char::is_whitespace)?;
+        let file = file.trim_start();
+        (file.strip_prefix('*').unwrap_or(file) == name).then_some(hash)
+    })
+}
+
+/// Downloads the artifact of `update`, failing unless it matches its checksum.
+pub async fn download(fetch: &dyn Fetch, update: &Update) -> Result<Vec<u8>, String> {
+    let checksums = fetch.get(&update.checksums.browser_download_url).await?;
+    let checksums = String::from_utf8_lossy(&checksums);
+    let expected = checksum_of(&checksums, &update.artifact.name)
+        .ok_or_else(|| format!("No checksum of {}", update.artifact.name))?;
+    let artifact = fetch.get(&update.artifact.browser_download_url).await?;
+    let actual = FileHash::sha256(&artifact).to_string();
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "Checksum mismatch of {}: expected {}, got {}",
+            update.artifact.name, expected, actual
+        ));
+    }
+    Ok(artifact)
+}
+
+/// Replaces the running executable with `executable`.
+pub fn replace_current(executable: &[u8]) -> io::Result<()> {
+    // beside the executable rather than in the shared temporary directory, where
+    // another user could have put a file of that name to be installed instead
+    let current = std::env::current_exe()?.canonicalize()?;
+    let dir = current
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "executable has no directory"))?;
+    let (path, mut file) = create_new_in(dir)?;
+    let written = file.write_all(executable).and_then(|()| {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+        }
+        file.sync_all()
+    });
+    drop(file);
+    let result = written.and_then(|()| self_replace::self_replace(&path));
+    std::fs::remove_file(&path).ok();
+    result
+}
+
+/// Creates a file in `dir` that did not exist before.
+fn create_new_in(dir: &Path) -> io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let name = format!(
+            ".{}-{}-{}.new",
+            artifact_name(),
+            std::process::id(),
+            attempt
+        );
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether the last check recorded in `stamp` is older than [`CHECK_INTERVAL`].
+pub fn check_due(stamp: &Path, now: SystemTime) -> bool {
+    let last = std::fs::read_to_string(stamp)
+        .ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    match last {
+        Some(last) => now
+            .duration_since(last)
+            .map_or(false, |elapsed| elapsed >= CHECK_INTERVAL),
+        None => true,
+    }
+}
+
+pub fn record_check(stamp: &Path, now: SystemTime) -> io::Result<()> {
+    if let Some(dir) = stamp.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    std::fs::write(stamp, secs.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
+
+    use async_trait::async_trait;
+    use localsend_lib::util::hash::FileHash;
+    use semver::Version;
+
+    use super::{
+        check_due, checksum_of, download, find_update, record_check, Fetch, CHECK_INTERVAL,
+        LATEST_RELEASE_URL,
+    };
+
+    /// Canned responses by url.
+    #[derive(Default)]
+    struct StaticFetch(HashMap<String, Vec<u8>>);
+
+    #[async_trait]
+    impl Fetch for StaticFetch {
+        async fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+            self.0
+                .get(url)
+                .cloned()
+                .ok_or_else(|| format!("Failed to get {}: not found", url))
+        }
+    }
+
+    const ARTIFACT: &str = "localsend-x86_64-linux";
+
+    fn fetch(tag: &str, checksum: &str) -> StaticFetch {
+        let release = format!(
+            r#"{{"tag_name": "{}", "prerelease": false, "assets": [
+                {{"name": "{}", "browser_download_url": "https://example.com/bin"}},
+                {{"name": "sha256sums.txt", "browser_download_url": "https://example.com/sums"}}
+            ]}}"#,
+            tag, ARTIFACT
+        );
+        let sums = format!(
+            "{}  localsend-aarch64-macos\n{} *{}\n",
+            "0".repeat(64),
+            checksum,
+            ARTIFACT
+        );
+        let mut fetch = StaticFetch::default();
+        fetch
+            .0
+            .insert(LATEST_RELEASE_URL.to_owned(), release.into_bytes());
+        fetch
+            .0
+            .insert("https://example.com/sums".to_owned(), sums.into_bytes());
+        fetch
+            .0
+            .insert("https://example.com/bin".to_owned(), b"new build".to_vec());
+        fetch
+    }
+
+    #[tokio::test]
+    async fn test_find_update() {
+        let current = Version::new(0, 1, 1);
+        let checksum = FileHash::sha256("new build").to_string();
+        for tag in ["v0.1.0", "v0.1.1", "v0.2.0-rc.1"] {
+            let update = find_update(&fetch(tag, &checksum), &current, ARTIFACT).await;
+            assert!(update.unwrap().is_none(), "{}", tag);
+        }
+
+        let fetch = fetch("v0.2.0", &checksum);
+        let update = find_update(&fetch, &current, ARTIFACT)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.version, Version::new(0, 2, 0));
+        assert_eq!(download(&fetch, &update).await.unwrap(), b"new build");
+        assert!(find_update(&fetch, &current, "localsend-riscv64-linux")
+            .await
+            .is_err());
+        // network failures are errors, not updates
+        assert!(find_update(&StaticFetch::default(), &current, ARTIFACT)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_download() {
+        let current = Version::new(0, 1, 1);
+        let fetch = fetch("v0.2.0", &FileHash::sha256("old build").to_string());
+        let update = find_update(&fetch, &current, ARTIFACT)
+            .await
+            .unwrap()
+            .unwrap();
+        let error = download(&fetch, &update).await.unwrap_err();
+        assert!(error.starts_with("Checksum mismatch"), "{}", error);
+        assert_eq!(checksum_of("abc  other\n", ARTIFACT), None);
+    }
+
+    #[test]
+    fn test_check_throttle() {
+        let stamp = std::env::temp_dir()
+            .join(format!("localsend-update-{}", std::process::id()))
+            .join("update-check");
+        let now = SystemTime::now();
+        assert!(check_due(&stamp, now));
+        record_check(&stamp, now).unwrap();
+        assert!(!check_due(&stamp, now + Duration::from_secs(60)));
+        assert!(check_due(&stamp, now + CHECK_INTERVAL));
+        std::fs::remove_dir_all(stamp.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,383 @@
+_localsend() {
+    local cur prev node i
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    node="localsend"
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "$node:${COMP_WORDS[i]}" in
+            localsend:receive) node="localsend_receive" ;;
+            localsend:send) node="localsend_send" ;;
+            localsend:pull) node="localsend_pull" ;;
+            localsend:serve-text) node="localsend_serve_text" ;;
+            localsend:doctor) node="localsend_doctor" ;;
+            localsend:daemon) node="localsend_daemon" ;;
+            localsend:debug) node="localsend_debug" ;;
+            localsend:completions) node="localsend_completions" ;;
+            localsend_debug:announce) node="localsend_debug_announce" ;;
+        esac
+    done
+
+    case "$node:$prev" in
+        localsend:--alias)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--multiaddr)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--port)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--http-port)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--announce-port)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--advertise-ip)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--advertise-port)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--device-type)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--device-model)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--announce-limit)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--scan-settle-ms)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--discovery)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--config)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--progress)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend:--theme)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--dest)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--on-conflict)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--archive)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--replace-char)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--name-case)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--session-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--status-file)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--queue)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--queue-wait)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--on-receive)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--on-receive-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--completion-marker-max-age)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--completion-fifo)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--max-concurrent-uploads)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--limit-rate)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--preview-dir)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--preview-max-size)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--dedup-action)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--audit-log)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--max-depth)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--max-dirs)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--max-files)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_receive:--wait-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--from-file)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--batch)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--exclude)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--symlinks)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--mime)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--to)
+            local IFS=$'\n'; COMPREPLY=($(compgen -W "$(localsend __complete-targets 2>/dev/null)" -- "$cur"))
+            return ;;
+        localsend_send:--to-fingerprint)
+            local IFS=$'\n'; COMPREPLY=($(compgen -W "$(localsend __complete-targets --fingerprints 2>/dev/null)" -- "$cur"))
+            return ;;
+        localsend_send:--to-ip)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--to-host)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--only-type)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--alias-contains)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--chunk-size)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--alias-once)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--note)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--sort)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--pace)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--control-socket)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--merge-window)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--dest)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--on-conflict)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--archive)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--replace-char)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--name-case)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--session-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--status-file)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--queue)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--queue-wait)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--on-receive)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--on-receive-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--completion-marker-max-age)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--completion-fifo)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--max-concurrent-uploads)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--limit-rate)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--preview-dir)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--preview-max-size)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--dedup-action)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--audit-log)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--max-depth)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--max-dirs)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--max-files)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_send:--wait-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_pull:--dest)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_pull:--on-conflict)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_doctor:--peer)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--dest)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--on-conflict)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--archive)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--replace-char)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--name-case)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--session-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--status-file)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--queue)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--queue-wait)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--on-receive)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--on-receive-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--completion-marker-max-age)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--completion-fifo)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--max-concurrent-uploads)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--limit-rate)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--preview-dir)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--preview-max-size)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--dedup-action)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--audit-log)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--max-depth)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--max-dirs)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--max-files)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--wait-timeout)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+        localsend_daemon:--control-socket)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return ;;
+    esac
+
+    local flags subcommands
+    case "$node" in
+        localsend)
+            flags="--alias --multiaddr --port --http-port --announce-port --advertise-ip --advertise-port --device-type --device-model --announce-limit --scan-settle-ms --discovery --stealth --config --probe-static --no-nerd --progress --theme --verbose -v --trace-http --help -h"
+            subcommands="receive send pull serve-text doctor daemon debug completions" ;;
+        localsend_receive)
+            flags="--dest --quick-save --auto-accept-texts --no-dest-prompt --create-dest --on-conflict --append-any-type --archive --archive-texts --portable-names --replace-char --keep-dangerous-names --name-case --session-timeout --status-file --allow-extend --queue --queue-wait --on-receive --on-receive-timeout --completion-marker --completion-marker-max-age --completion-fifo --max-concurrent-uploads --limit-rate --preview-dir --preview-max-size --dedup --dedup-action --audit --audit-log --max-depth --max-dirs --max-files --strict --cleanup --once --wait-timeout --verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_send)
+            flags="--from-file --batch --fail-fast --yes -y --include-hidden --respect-gitignore --exclude --symlinks --mime --no-sniff --to --to-fingerprint --to-ip --to-host --prefer-ipv6 --only-type --alias-contains --parallel-targets --retry-busy --chunk-size --alias-once --note --sort --no-precheck --insecure --pace --daemon --control-socket --bidirectional --merge-window --dest --quick-save --auto-accept-texts --no-dest-prompt --create-dest --on-conflict --append-any-type --archive --archive-texts --portable-names --replace-char --keep-dangerous-names --name-case --session-timeout --status-file --allow-extend --queue --queue-wait --on-receive --on-receive-timeout --completion-marker --completion-marker-max-age --completion-fifo --max-concurrent-uploads --limit-rate --preview-dir --preview-max-size --dedup --dedup-action --audit --audit-log --max-depth --max-dirs --max-files --strict --cleanup --once --wait-timeout --verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_pull)
+            flags="--dest --on-conflict --verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_serve_text)
+            flags="--no-qr --verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_doctor)
+            flags="--peer --json --verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_daemon)
+            flags="--dest --quick-save --auto-accept-texts --no-dest-prompt --create-dest --on-conflict --append-any-type --archive --archive-texts --portable-names --replace-char --keep-dangerous-names --name-case --session-timeout --status-file --allow-extend --queue --queue-wait --on-receive --on-receive-timeout --completion-marker --completion-marker-max-age --completion-fifo --max-concurrent-uploads --limit-rate --preview-dir --preview-max-size --dedup --dedup-action --audit --audit-log --max-depth --max-dirs --max-files --strict --cleanup --once --wait-timeout --control-socket --verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_debug)
+            flags="--verbose -v --trace-http --help -h"
+            subcommands="announce" ;;
+        localsend_debug_announce)
+            flags="--verbose -v --trace-http --help -h"
+            subcommands="" ;;
+        localsend_completions)
+            flags="--verbose -v --trace-http --help -h"
+            subcommands="" ;;
+    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$flags" -- "$cur"))
+    elif [[ -n "$subcommands" ]]; then
+        COMPREPLY=($(compgen -W "$subcommands" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}
+
+complete -F _localsend -o bashdefault -o default localsend
@@ -0,0 +1,207 @@
+complete -c localsend -n '__fish_use_subcommand' -f -a 'receive' -d 'Run as receive server'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'send' -d 'Run as send client'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'pull' -d 'Download files offered by a device'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'serve-text' -d 'Offer a text to any device until stopped'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'doctor' -d 'Check the network for common problems'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'daemon' -d 'Run in the background, taking commands from a local control socket'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'debug' -d 'Inspect what is sent to other devices'
+complete -c localsend -n '__fish_use_subcommand' -f -a 'completions' -d 'Print a shell completion script, e.g. `localsend completions bash >> ~/.bashrc`'
+complete -c localsend -n '__fish_use_subcommand' -l alias -r -d 'Alias of localsend, use hostname by default'
+complete -c localsend -n '__fish_use_subcommand' -l multiaddr -r -d 'Multicast address of localsend'
+complete -c localsend -n '__fish_use_subcommand' -l port -r -d 'Port of localsend'
+complete -c localsend -n '__fish_use_subcommand' -l http-port -r -d 'Port of localsend http server'
+complete -c localsend -n '__fish_use_subcommand' -l announce-port -r -d 'UDP port to send announcements to, same as --port by default'
+complete -c localsend -n '__fish_use_subcommand' -l advertise-ip -r -d 'IP address advertised to other devices, e.g. the host address of a container'
+complete -c localsend -n '__fish_use_subcommand' -l advertise-port -r -d 'Http port advertised to other devices, same as --http-port by default'
+complete -c localsend -n '__fish_use_subcommand' -l device-type -r -d 'Device type shown to other devices: mobile, desktop, web, headless, server'
+complete -c localsend -n '__fish_use_subcommand' -l device-model -r -d 'Device model shown to other devices, the operating system by default'
+complete -c localsend -n '__fish_use_subcommand' -l announce-limit -r -d 'Keep announcements within this many bytes, the device model and then the alias are cut to fit'
+complete -c localsend -n '__fish_use_subcommand' -l scan-settle-ms -r -d 'End scans once no new device answered for this long, 0 always listens 2 seconds'
+complete -c localsend -n '__fish_use_subcommand' -l discovery -r -d 'Where to find devices: static for the [[devices]] of the config file only, multicast for announcing ones only, or both'
+complete -c localsend -n '__fish_use_subcommand' -l stealth -d 'Send without ever announcing this device or answering others, only devices announcing themselves are found. --to-host and static devices are reached directly'
+complete -c localsend -n '__fish_use_subcommand' -l config -r -d 'Config file with the static [[devices]], the config.toml in the config directory by default'
+complete -c localsend -n '__fish_use_subcommand' -l probe-static -d 'Connect to every static device at startup, those not answering are shown as unreachable'
+complete -c localsend -n '__fish_use_subcommand' -l no-nerd -d 'Do not use nerd fonts'
+complete -c localsend -n '__fish_use_subcommand' -l progress -r -d 'How to show transfer progress: auto, full, compact or none'
+complete -c localsend -n '__fish_use_subcommand' -l theme -r -d 'Colors and icons: default, high-contrast, mono or the path of a JSON theme, the theme.json in the config directory by default'
+complete -c localsend -n '__fish_use_subcommand' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_use_subcommand' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_use_subcommand' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l dest -r -d 'File save destination path, may contain {alias}, {fingerprint}, {date}, {time} and {sessionId}'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l quick-save -d 'Quickly save all files without asking'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l auto-accept-texts -d 'Accept text messages right away, e.g. clipboard texts, still asking about files'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l no-dest-prompt -d 'Save accepted files to --dest without asking where to save them'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l create-dest -d 'Create --dest when it does not exist instead of refusing to start'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l on-conflict -r -d 'What to do when a file already exists: overwrite, rename or append. Append adds complete bodies to the end of texts and files of unknown type, others are renamed'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l append-any-type -d 'Append files of any type with --on-conflict append, e.g. two images'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l archive -r -d 'Save all received files into a single .tar or .zip archive'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l archive-texts -d 'Also save text messages into the archive instead of printing them'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l portable-names -d 'Only save names Windows accepts, e.g. when saving to a FAT or exFAT drive'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l replace-char -r -d 'Character replacing the ones the destination does not accept in file names'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l keep-dangerous-names -d 'Save files that may run when opened, e.g. .exe or .lnk on Windows, under the name offered instead of appending .received'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l name-case -r -d 'Whether names differing only in case collide, "sensitive" or "insensitive", detected on the destination by default'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l session-timeout -r -d 'Drop a session when the sender stops uploading for this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l status-file -r -d 'Keep a JSON file describing the current transfer up to date, e.g. for status bars'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l allow-extend -d 'Let a sender add files to its running session, like some official app flows do'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l queue -r -d 'Let up to this many senders wait while a session runs instead of turning them away, they are received in the order they arrived'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l queue-wait -r -d 'Turn a waiting sender away after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l on-receive -r -d 'Run this shell command for every saved file, with LS_FILE_PATH, LS_FILE_NAME, LS_FILE_TYPE, LS_SENDER_ALIAS and LS_SESSION_ID set'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l on-receive-timeout -r -d 'Kill the --on-receive command after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l completion-marker -d 'Write <name>.localsend-complete with size, digest, sender and time next to every saved file once it is complete, for tools watching the destination'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l completion-marker-max-age -r -d 'Remove markers older than this on startup, e.g. 12h or 7d'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l completion-fifo -r -d 'Write one JSON line per saved file to this named pipe, dropped while nothing reads it'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l max-concurrent-uploads -r -d 'Let only this many uploads write at the same time, the others wait'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l limit-rate -r -d 'Receive at most this many bytes per second, e.g. 500K or 10M, for all files of a session together; senders are slowed down to match. `send` uploads at most this rate as well, whatever --pace measures'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l preview-dir -r -d 'Receive files up to --preview-max-size into this directory without asking, then keep or discard them once they can be opened'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l preview-max-size -r -d 'Largest file received for a preview, e.g. 500K or 5M'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l dedup -d 'Do not receive files again that were received before with the same digest, files sent without a digest are always received'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l dedup-action -r -d 'What to do with a file received before: skip, link or copy it to the offered name'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l audit -d 'Receive as usual but save nothing, every file is counted, hashed and logged, e.g. to observe senders before trusting --quick-save or to measure throughput'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l audit-log -r -d 'Append what --audit received to this file, one JSON object per file'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l max-depth -r -d 'Refuse files nested deeper than this many directories'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l max-dirs -r -d 'Refuse files creating more than this many directories in a session'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l max-files -r -d 'Refuse files beyond this many in a session'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l strict -d 'Refuse the whole offer when a file breaks --max-depth, --max-dirs or --max-files'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l cleanup -d 'Remove what receivers that crashed left half written, then exit. Receivers also clean up after themselves when they start on the same port again'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l once -d 'Receive a single session, then exit with a code telling how it went: 0 received, 1 failed or cancelled, 3 declined, 4 nothing offered within --wait-timeout'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l wait-timeout -r -d 'Give up waiting for an offer with --once after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from receive' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l from-file -r -d 'Send the files listed in a file, one path per line, `-` reads stdin. A tab separates a path from the name the receiver sees'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l batch -r -d 'Run the sends of a TOML file one after another, each `[[job]]` has `files` and one of `to`, `to-fingerprint` and `to-ip`. Paths are relative to the file'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l fail-fast -d 'Stop the batch at the first job that fails, the others run anyway by default'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l yes -s y -d 'Send inputs that look like paths but do not exist as texts without asking'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l include-hidden -d 'Do not skip .git, .svn, .DS_Store and Thumbs.db in directories'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l respect-gitignore -d 'Skip files ignored by .gitignore files in directories'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l exclude -r -d 'Skip files in directories matching the glob, can be repeated'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l symlinks -r -d 'What to do with symlinks in directories: follow, skip, error'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l mime -r -d 'Upload files matching the glob with a content type, e.g. \'*.jsonl=application/x-ndjson\', can be repeated, the first match wins'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l no-sniff -d 'Do not read files without a known extension to tell text from binary uploads'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l to -x -a '(localsend __complete-targets 2>/dev/null)' -d 'Alias of a device to send to, can be repeated, select interactively by default. Case-insensitive, a trailing * matches any rest'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l to-fingerprint -x -a '(localsend __complete-targets --fingerprints 2>/dev/null)' -d 'Fingerprint of a device to send to, can be repeated'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l to-ip -r -d 'Ip of a device to send to, can be repeated'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l to-host -r -d 'Host name of a device to send to, with an optional port, e.g. mylaptop.local:53317; can be repeated. The device is asked for its info instead of being discovered'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l prefer-ipv6 -d 'Try the IPv6 addresses of --to-host names before the IPv4 ones'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l only-type -r -d 'Only list and match devices of this type, can be repeated'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l alias-contains -r -d 'Only list and match devices whose alias contains this text, case-insensitive'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l parallel-targets -d 'Send to all devices at the same time instead of one after another'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l retry-busy -d 'Retry once after a short delay when a device is busy with another transfer'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l chunk-size -r -d 'Offer at most this many files per session, larger sends are split into sessions to the same device one after another'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l alias-once -r -d 'Alias shown to the receivers of this send only, the configured alias stays unchanged'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l note -r -d 'Note shown by localsend-rs receivers before accepting, other receivers get it as the text file _localsend_note.txt'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l sort -r -d 'Order of the devices to choose from: alias, recent (last sent to first) or usage (most transfers first). Devices never sent to come last'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l no-precheck -d 'Do not check that devices answer before sending, e.g. behind filters dropping the probe'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l insecure -d 'Accept any certificate of devices reached over HTTPS instead of only the one matching their fingerprint, for debugging'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l pace -r -d 'Pace uploads: off, or adaptive to back off once they make the latency to the receiver grow, leaving room for other traffic. --limit-rate caps either'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l daemon -d 'Hand the input to a running `localsend daemon` and return once it is queued'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l control-socket -r -d 'Control socket of the daemon'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l bidirectional -d 'Also receive while sending, offers are answered once the current prompt closes unless --quick-save accepts them right away'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l merge-window -r -d 'Merge the input of other invocations started within this time, e.g. "2s", into one send: the first one sends, the others hand it their input and exit. For file managers starting one process per selected file'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l dest -r -d 'File save destination path, may contain {alias}, {fingerprint}, {date}, {time} and {sessionId}'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l quick-save -d 'Quickly save all files without asking'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l auto-accept-texts -d 'Accept text messages right away, e.g. clipboard texts, still asking about files'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l no-dest-prompt -d 'Save accepted files to --dest without asking where to save them'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l create-dest -d 'Create --dest when it does not exist instead of refusing to start'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l on-conflict -r -d 'What to do when a file already exists: overwrite, rename or append. Append adds complete bodies to the end of texts and files of unknown type, others are renamed'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l append-any-type -d 'Append files of any type with --on-conflict append, e.g. two images'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l archive -r -d 'Save all received files into a single .tar or .zip archive'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l archive-texts -d 'Also save text messages into the archive instead of printing them'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l portable-names -d 'Only save names Windows accepts, e.g. when saving to a FAT or exFAT drive'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l replace-char -r -d 'Character replacing the ones the destination does not accept in file names'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l keep-dangerous-names -d 'Save files that may run when opened, e.g. .exe or .lnk on Windows, under the name offered instead of appending .received'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l name-case -r -d 'Whether names differing only in case collide, "sensitive" or "insensitive", detected on the destination by default'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l session-timeout -r -d 'Drop a session when the sender stops uploading for this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l status-file -r -d 'Keep a JSON file describing the current transfer up to date, e.g. for status bars'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l allow-extend -d 'Let a sender add files to its running session, like some official app flows do'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l queue -r -d 'Let up to this many senders wait while a session runs instead of turning them away, they are received in the order they arrived'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l queue-wait -r -d 'Turn a waiting sender away after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l on-receive -r -d 'Run this shell command for every saved file, with LS_FILE_PATH, LS_FILE_NAME, LS_FILE_TYPE, LS_SENDER_ALIAS and LS_SESSION_ID set'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l on-receive-timeout -r -d 'Kill the --on-receive command after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l completion-marker -d 'Write <name>.localsend-complete with size, digest, sender and time next to every saved file once it is complete, for tools watching the destination'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l completion-marker-max-age -r -d 'Remove markers older than this on startup, e.g. 12h or 7d'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l completion-fifo -r -d 'Write one JSON line per saved file to this named pipe, dropped while nothing reads it'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l max-concurrent-uploads -r -d 'Let only this many uploads write at the same time, the others wait'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l limit-rate -r -d 'Receive at most this many bytes per second, e.g. 500K or 10M, for all files of a session together; senders are slowed down to match. `send` uploads at most this rate as well, whatever --pace measures'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l preview-dir -r -d 'Receive files up to --preview-max-size into this directory without asking, then keep or discard them once they can be opened'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l preview-max-size -r -d 'Largest file received for a preview, e.g. 500K or 5M'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l dedup -d 'Do not receive files again that were received before with the same digest, files sent without a digest are always received'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l dedup-action -r -d 'What to do with a file received before: skip, link or copy it to the offered name'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l audit -d 'Receive as usual but save nothing, every file is counted, hashed and logged, e.g. to observe senders before trusting --quick-save or to measure throughput'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l audit-log -r -d 'Append what --audit received to this file, one JSON object per file'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l max-depth -r -d 'Refuse files nested deeper than this many directories'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l max-dirs -r -d 'Refuse files creating more than this many directories in a session'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l max-files -r -d 'Refuse files beyond this many in a session'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l strict -d 'Refuse the whole offer when a file breaks --max-depth, --max-dirs or --max-files'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l cleanup -d 'Remove what receivers that crashed left half written, then exit. Receivers also clean up after themselves when they start on the same port again'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l once -d 'Receive a single session, then exit with a code telling how it went: 0 received, 1 failed or cancelled, 3 declined, 4 nothing offered within --wait-timeout'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l wait-timeout -r -d 'Give up waiting for an offer with --once after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from send' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from pull' -l dest -r -d 'File save destination path'
+complete -c localsend -n '__fish_seen_subcommand_from pull' -l on-conflict -r -d 'What to do when a file already exists: overwrite, rename'
+complete -c localsend -n '__fish_seen_subcommand_from pull' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from pull' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from pull' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from serve-text' -l no-qr -d 'Do not show a QR code of the link for browsers'
+complete -c localsend -n '__fish_seen_subcommand_from serve-text' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from serve-text' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from serve-text' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from doctor' -l peer -r -d 'Address of a device that can not be reached, e.g. 192.168.1.20 or 192.168.1.20:53318'
+complete -c localsend -n '__fish_seen_subcommand_from doctor' -l json -d 'Print the results as JSON, e.g. for bug reports'
+complete -c localsend -n '__fish_seen_subcommand_from doctor' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from doctor' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from doctor' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l dest -r -d 'File save destination path, may contain {alias}, {fingerprint}, {date}, {time} and {sessionId}'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l quick-save -d 'Quickly save all files without asking'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l auto-accept-texts -d 'Accept text messages right away, e.g. clipboard texts, still asking about files'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l no-dest-prompt -d 'Save accepted files to --dest without asking where to save them'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l create-dest -d 'Create --dest when it does not exist instead of refusing to start'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l on-conflict -r -d 'What to do when a file already exists: overwrite, rename or append. Append adds complete bodies to the end of texts and files of unknown type, others are renamed'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l append-any-type -d 'Append files of any type with --on-conflict append, e.g. two images'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l archive -r -d 'Save all received files into a single .tar or .zip archive'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l archive-texts -d 'Also save text messages into the archive instead of printing them'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l portable-names -d 'Only save names Windows accepts, e.g. when saving to a FAT or exFAT drive'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l replace-char -r -d 'Character replacing the ones the destination does not accept in file names'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l keep-dangerous-names -d 'Save files that may run when opened, e.g. .exe or .lnk on Windows, under the name offered instead of appending .received'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l name-case -r -d 'Whether names differing only in case collide, "sensitive" or "insensitive", detected on the destination by default'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l session-timeout -r -d 'Drop a session when the sender stops uploading for this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l status-file -r -d 'Keep a JSON file describing the current transfer up to date, e.g. for status bars'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l allow-extend -d 'Let a sender add files to its running session, like some official app flows do'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l queue -r -d 'Let up to this many senders wait while a session runs instead of turning them away, they are received in the order they arrived'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l queue-wait -r -d 'Turn a waiting sender away after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l on-receive -r -d 'Run this shell command for every saved file, with LS_FILE_PATH, LS_FILE_NAME, LS_FILE_TYPE, LS_SENDER_ALIAS and LS_SESSION_ID set'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l on-receive-timeout -r -d 'Kill the --on-receive command after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l completion-marker -d 'Write <name>.localsend-complete with size, digest, sender and time next to every saved file once it is complete, for tools watching the destination'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l completion-marker-max-age -r -d 'Remove markers older than this on startup, e.g. 12h or 7d'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l completion-fifo -r -d 'Write one JSON line per saved file to this named pipe, dropped while nothing reads it'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l max-concurrent-uploads -r -d 'Let only this many uploads write at the same time, the others wait'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l limit-rate -r -d 'Receive at most this many bytes per second, e.g. 500K or 10M, for all files of a session together; senders are slowed down to match. `send` uploads at most this rate as well, whatever --pace measures'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l preview-dir -r -d 'Receive files up to --preview-max-size into this directory without asking, then keep or discard them once they can be opened'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l preview-max-size -r -d 'Largest file received for a preview, e.g. 500K or 5M'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l dedup -d 'Do not receive files again that were received before with the same digest, files sent without a digest are always received'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l dedup-action -r -d 'What to do with a file received before: skip, link or copy it to the offered name'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l audit -d 'Receive as usual but save nothing, every file is counted, hashed and logged, e.g. to observe senders before trusting --quick-save or to measure throughput'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l audit-log -r -d 'Append what --audit received to this file, one JSON object per file'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l max-depth -r -d 'Refuse files nested deeper than this many directories'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l max-dirs -r -d 'Refuse files creating more than this many directories in a session'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l max-files -r -d 'Refuse files beyond this many in a session'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l strict -d 'Refuse the whole offer when a file breaks --max-depth, --max-dirs or --max-files'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l cleanup -d 'Remove what receivers that crashed left half written, then exit. Receivers also clean up after themselves when they start on the same port again'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l once -d 'Receive a single session, then exit with a code telling how it went: 0 received, 1 failed or cancelled, 3 declined, 4 nothing offered within --wait-timeout'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l wait-timeout -r -d 'Give up waiting for an offer with --once after this many seconds'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l control-socket -r -d 'Socket to take commands from, a named pipe name on Windows'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from daemon' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from debug' -f -a 'announce' -d 'Print the announcement broadcast to other devices'
+complete -c localsend -n '__fish_seen_subcommand_from debug' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from debug' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from debug' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from announce' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from announce' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from announce' -l help -s h -d 'Print help'
+complete -c localsend -n '__fish_seen_subcommand_from completions' -l verbose -s v -d 'Log debug messages, e.g. the errors behind hints'
+complete -c localsend -n '__fish_seen_subcommand_from completions' -l trace-http -d 'Print the HTTP requests and responses exchanged with other devices to stderr, tokens and session ids are masked unless --trace-http=full; turns off automatic progress'
+complete -c localsend -n '__fish_seen_subcommand_from completions' -l help -s h -d 'Print help'
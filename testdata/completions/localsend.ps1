@@ -0,0 +1,149 @@
+Register-ArgumentCompleter -Native -CommandName 'localsend' -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements |
+        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |
+        Select-Object -Skip 1 | ForEach-Object { $_.ToString() })
+    $node = 'localsend'
+    foreach ($word in $words) {
+        switch ("${node}:$word") {
+            'localsend:receive' { $node = 'localsend_receive' }
+            'localsend:send' { $node = 'localsend_send' }
+            'localsend:pull' { $node = 'localsend_pull' }
+            'localsend:serve-text' { $node = 'localsend_serve_text' }
+            'localsend:doctor' { $node = 'localsend_doctor' }
+            'localsend:daemon' { $node = 'localsend_daemon' }
+            'localsend:debug' { $node = 'localsend_debug' }
+            'localsend:completions' { $node = 'localsend_completions' }
+            'localsend_debug:announce' { $node = 'localsend_debug_announce' }
+        }
+    }
+    $prev = if ($words.Count) { $words[-1] } else { '' }
+    $candidates = $null
+    switch ("${node}:$prev") {
+        'localsend:--alias' { return }
+        'localsend:--multiaddr' { return }
+        'localsend:--port' { return }
+        'localsend:--http-port' { return }
+        'localsend:--announce-port' { return }
+        'localsend:--advertise-ip' { return }
+        'localsend:--advertise-port' { return }
+        'localsend:--device-type' { return }
+        'localsend:--device-model' { return }
+        'localsend:--announce-limit' { return }
+        'localsend:--scan-settle-ms' { return }
+        'localsend:--discovery' { return }
+        'localsend:--config' { return }
+        'localsend:--progress' { return }
+        'localsend:--theme' { return }
+        'localsend_receive:--dest' { return }
+        'localsend_receive:--on-conflict' { return }
+        'localsend_receive:--archive' { return }
+        'localsend_receive:--replace-char' { return }
+        'localsend_receive:--name-case' { return }
+        'localsend_receive:--session-timeout' { return }
+        'localsend_receive:--status-file' { return }
+        'localsend_receive:--queue' { return }
+        'localsend_receive:--queue-wait' { return }
+        'localsend_receive:--on-receive' { return }
+        'localsend_receive:--on-receive-timeout' { return }
+        'localsend_receive:--completion-marker-max-age' { return }
+        'localsend_receive:--completion-fifo' { return }
+        'localsend_receive:--max-concurrent-uploads' { return }
+        'localsend_receive:--limit-rate' { return }
+        'localsend_receive:--preview-dir' { return }
+        'localsend_receive:--preview-max-size' { return }
+        'localsend_receive:--dedup-action' { return }
+        'localsend_receive:--audit-log' { return }
+        'localsend_receive:--max-depth' { return }
+        'localsend_receive:--max-dirs' { return }
+        'localsend_receive:--max-files' { return }
+        'localsend_receive:--wait-timeout' { return }
+        'localsend_send:--from-file' { return }
+        'localsend_send:--batch' { return }
+        'localsend_send:--exclude' { return }
+        'localsend_send:--symlinks' { return }
+        'localsend_send:--mime' { return }
+        'localsend_send:--to' { $candidates = @(localsend __complete-targets 2>$null) }
+        'localsend_send:--to-fingerprint' { $candidates = @(localsend __complete-targets --fingerprints 2>$null) }
+        'localsend_send:--to-ip' { return }
+        'localsend_send:--to-host' { return }
+        'localsend_send:--only-type' { return }
+        'localsend_send:--alias-contains' { return }
+        'localsend_send:--chunk-size' { return }
+        'localsend_send:--alias-once' { return }
+        'localsend_send:--note' { return }
+        'localsend_send:--sort' { return }
+        'localsend_send:--pace' { return }
+        'localsend_send:--control-socket' { return }
+        'localsend_send:--merge-window' { return }
+        'localsend_send:--dest' { return }
+        'localsend_send:--on-conflict' { return }
+        'localsend_send:--archive' { return }
+        'localsend_send:--replace-char' { return }
+        'localsend_send:--name-case' { return }
+        'localsend_send:--session-timeout' { return }
+        'localsend_send:--status-file' { return }
+        'localsend_send:--queue' { return }
+        'localsend_send:--queue-wait' { return }
+        'localsend_send:--on-receive' { return }
+        'localsend_send:--on-receive-timeout' { return }
+        'localsend_send:--completion-marker-max-age' { return }
+        'localsend_send:--completion-fifo' { return }
+        'localsend_send:--max-concurrent-uploads' { return }
+        'localsend_send:--limit-rate' { return }
+        'localsend_send:--preview-dir' { return }
+        'localsend_send:--preview-max-size' { return }
+        'localsend_send:--dedup-action' { return }
+        'localsend_send:--audit-log' { return }
+        'localsend_send:--max-depth' { return }
+        'localsend_send:--max-dirs' { return }
+        'localsend_send:--max-files' { return }
+        'localsend_send:--wait-timeout' { return }
+        'localsend_pull:--dest' { return }
+        'localsend_pull:--on-conflict' { return }
+        'localsend_doctor:--peer' { return }
+        'localsend_daemon:--dest' { return }
+        'localsend_daemon:--on-conflict' { return }
+        'localsend_daemon:--archive' { return }
+        'localsend_daemon:--replace-char' { return }
+        'localsend_daemon:--name-case' { return }
+        'localsend_daemon:--session-timeout' { return }
+        'localsend_daemon:--status-file' { return }
+        'localsend_daemon:--queue' { return }
+        'localsend_daemon:--queue-wait' { return }
+        'localsend_daemon:--on-receive' { return }
+        'localsend_daemon:--on-receive-timeout' { return }
+        'localsend_daemon:--completion-marker-max-age' { return }
+        'localsend_daemon:--completion-fifo' { return }
+        'localsend_daemon:--max-concurrent-uploads' { return }
+        'localsend_daemon:--limit-rate' { return }
+        'localsend_daemon:--preview-dir' { return }
+        'localsend_daemon:--preview-max-size' { return }
+        'localsend_daemon:--dedup-action' { return }
+        'localsend_daemon:--audit-log' { return }
+        'localsend_daemon:--max-depth' { return }
+        'localsend_daemon:--max-dirs' { return }
+        'localsend_daemon:--max-files' { return }
+        'localsend_daemon:--wait-timeout' { return }
+        'localsend_daemon:--control-socket' { return }
+    }
+    if ($null -eq $candidates) {
+        switch ($node) {
+            'localsend' { $flags = @('--alias', '--multiaddr', '--port', '--http-port', '--announce-port', '--advertise-ip', '--advertise-port', '--device-type', '--device-model', '--announce-limit', '--scan-settle-ms', '--discovery', '--stealth', '--config', '--probe-static', '--no-nerd', '--progress', '--theme', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @('receive', 'send', 'pull', 'serve-text', 'doctor', 'daemon', 'debug', 'completions'); $default = $subcommands }
+            'localsend_receive' { $flags = @('--dest', '--quick-save', '--auto-accept-texts', '--no-dest-prompt', '--create-dest', '--on-conflict', '--append-any-type', '--archive', '--archive-texts', '--portable-names', '--replace-char', '--keep-dangerous-names', '--name-case', '--session-timeout', '--status-file', '--allow-extend', '--queue', '--queue-wait', '--on-receive', '--on-receive-timeout', '--completion-marker', '--completion-marker-max-age', '--completion-fifo', '--max-concurrent-uploads', '--limit-rate', '--preview-dir', '--preview-max-size', '--dedup', '--dedup-action', '--audit', '--audit-log', '--max-depth', '--max-dirs', '--max-files', '--strict', '--cleanup', '--once', '--wait-timeout', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_send' { $flags = @('--from-file', '--batch', '--fail-fast', '--yes', '-y', '--include-hidden', '--respect-gitignore', '--exclude', '--symlinks', '--mime', '--no-sniff', '--to', '--to-fingerprint', '--to-ip', '--to-host', '--prefer-ipv6', '--only-type', '--alias-contains', '--parallel-targets', '--retry-busy', '--chunk-size', '--alias-once', '--note', '--sort', '--no-precheck', '--insecure', '--pace', '--daemon', '--control-socket', '--bidirectional', '--merge-window', '--dest', '--quick-save', '--auto-accept-texts', '--no-dest-prompt', '--create-dest', '--on-conflict', '--append-any-type', '--archive', '--archive-texts', '--portable-names', '--replace-char', '--keep-dangerous-names', '--name-case', '--session-timeout', '--status-file', '--allow-extend', '--queue', '--queue-wait', '--on-receive', '--on-receive-timeout', '--completion-marker', '--completion-marker-max-age', '--completion-fifo', '--max-concurrent-uploads', '--limit-rate', '--preview-dir', '--preview-max-size', '--dedup', '--dedup-action', '--audit', '--audit-log', '--max-depth', '--max-dirs', '--max-files', '--strict', '--cleanup', '--once', '--wait-timeout', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_pull' { $flags = @('--dest', '--on-conflict', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_serve_text' { $flags = @('--no-qr', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_doctor' { $flags = @('--peer', '--json', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_daemon' { $flags = @('--dest', '--quick-save', '--auto-accept-texts', '--no-dest-prompt', '--create-dest', '--on-conflict', '--append-any-type', '--archive', '--archive-texts', '--portable-names', '--replace-char', '--keep-dangerous-names', '--name-case', '--session-timeout', '--status-file', '--allow-extend', '--queue', '--queue-wait', '--on-receive', '--on-receive-timeout', '--completion-marker', '--completion-marker-max-age', '--completion-fifo', '--max-concurrent-uploads', '--limit-rate', '--preview-dir', '--preview-max-size', '--dedup', '--dedup-action', '--audit', '--audit-log', '--max-depth', '--max-dirs', '--max-files', '--strict', '--cleanup', '--once', '--wait-timeout', '--control-socket', '--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_debug' { $flags = @('--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @('announce'); $default = $subcommands }
+            'localsend_debug_announce' { $flags = @('--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+            'localsend_completions' { $flags = @('--verbose', '-v', '--trace-http', '--help', '-h'); $subcommands = @(); $default = $flags }
+        }
+        $candidates = if ($wordToComplete -like '-*') { $flags } else { $default }
+    }
+    $candidates | Where-Object { $_ -like "$wordToComplete*" } | ForEach-Object {
+        $text = if ($_ -match '\s') { "'$_'" } else { $_ }
+        [System.Management.Automation.CompletionResult]::new($text, $_, 'ParameterValue', $_)
+    }
+}